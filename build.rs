@@ -11,7 +11,6 @@ use std::env;
 struct ShaderData {
     src: String,
     src_path: PathBuf,
-    spv_path: PathBuf,
     kind: shaderc::ShaderKind,
 }
 
@@ -30,34 +29,56 @@ impl ShaderData {
         };
 
         let src = read_to_string(src_path.clone())?;
-        let spv_path = src_path.with_extension(format!("{}.spv", extension));
 
         Ok(Self {
             src,
             src_path,
-            spv_path,
             kind,
         })
     }
 }
 
-fn main() -> Result<()> {
-    // Collect all shaders recursively within /src/
-    let mut shader_paths = Vec::new();
-    shader_paths.extend(glob("./src/**/*.vert")?);
-    shader_paths.extend(glob("./src/**/*.frag")?);
-    shader_paths.extend(glob("./src/**/*.comp")?);
+/// Shaders also compiled with every combination of these defines, into files named with the
+/// suffixes of the defines, e.g. `shader_normal_map_vertex_color.vert.spv`. Which one a mesh is
+/// drawn with is picked from `ShaderFeatures` in permutation.rs, which must list the same.
+/// Skinned meshes cast their shadows with `shadow_skinned.vert.spv`, see shadow.rs.
+const PERMUTATIONS: &[(&str, &[(&str, &str)])] = &[
+    (
+        "shader",
+        &[
+            ("_normal_map", "HAS_NORMAL_MAP"),
+            ("_vertex_color", "HAS_VERTEX_COLOR"),
+            ("_skinned", "SKINNED"),
+            ("_pbr", "PBR"),
+            ("_uber", "UBER"),
+        ],
+    ),
+    ("shadow", &[("_skinned", "SKINNED")]),
+];
 
-    // This could be parallelized
-    let shaders = shader_paths
-        .into_par_iter()
-        .map(|glob_result| ShaderData::load(glob_result?))
-        .collect::<Vec<Result<_>>>()
-        .into_iter()
-        .collect::<Result<Vec<_>>>()?;
+/// File name suffixes and defines of the shader's permutations, the plain one first.
+fn permutations(src_path: &Path) -> Vec<(String, Vec<&'static str>)> {
+    let stem = src_path.file_stem().and_then(|stem| stem.to_str());
+    let features = PERMUTATIONS
+        .iter()
+        .find(|(name, _)| Some(*name) == stem)
+        .map(|(_, features)| *features)
+        .unwrap_or(&[]);
+    (0..1usize << features.len())
+        .map(|mask| {
+            let chosen = features
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & 1 << i != 0)
+                .map(|(_, feature)| feature);
+            let suffix = chosen.clone().map(|(suffix, _)| *suffix).collect();
+            (suffix, chosen.map(|(_, define)| *define).collect())
+        })
+        .collect()
+}
 
-    let mut compiler = shaderc::Compiler::new().context("Unable to create shader compiler")?;
-    let mut options = shaderc::CompileOptions::new().unwrap();
+fn compile_options(defines: &[&str]) -> Result<shaderc::CompileOptions<'static>> {
+    let mut options = shaderc::CompileOptions::new().context("Unable to create compile options")?;
     options.set_include_callback(
         |name, include_type, source_name, _depth| match include_type {
             shaderc::IncludeType::Relative => {
@@ -76,6 +97,28 @@ fn main() -> Result<()> {
             }
         },
     );
+    for define in defines {
+        options.add_macro_definition(define, None);
+    }
+    Ok(options)
+}
+
+fn main() -> Result<()> {
+    // Collect all shaders recursively within /src/
+    let mut shader_paths = Vec::new();
+    shader_paths.extend(glob("./src/**/*.vert")?);
+    shader_paths.extend(glob("./src/**/*.frag")?);
+    shader_paths.extend(glob("./src/**/*.comp")?);
+
+    // This could be parallelized
+    let shaders = shader_paths
+        .into_par_iter()
+        .map(|glob_result| ShaderData::load(glob_result?))
+        .collect::<Vec<Result<_>>>()
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut compiler = shaderc::Compiler::new().context("Unable to create shader compiler")?;
 
     // This can't be parallelized. The [shaderc::Compiler] is not
     // thread safe. Also, it creates a lot of resources. You could
@@ -85,18 +128,27 @@ fn main() -> Result<()> {
     for shader in shaders {
         // This tells cargo to rerun this script if something in /src/ changes.
         println!("cargo:rerun-if-changed={:?}", shader.src_path);
-        let compiled = compiler.compile_into_spirv(
-            &shader.src,
-            shader.kind,
-            &shader.src_path.to_str().unwrap(),
-            "main",
-            Some(&options),
-        )?;
-        write(&shader.spv_path, compiled.as_binary_u8())?;
-        write(
-            Path::new(&env::var("OUT_DIR").unwrap()).join(shader.spv_path.file_name().unwrap()),
-            compiled.as_binary_u8(),
-        )?;
+        for (suffix, defines) in permutations(&shader.src_path) {
+            let options = compile_options(&defines)?;
+            let compiled = compiler.compile_into_spirv(
+                &shader.src,
+                shader.kind,
+                &shader.src_path.to_str().unwrap(),
+                "main",
+                Some(&options),
+            )?;
+            let spv_path = shader.src_path.with_file_name(format!(
+                "{}{}.{}.spv",
+                shader.src_path.file_stem().unwrap().to_string_lossy(),
+                suffix,
+                shader.src_path.extension().unwrap().to_string_lossy(),
+            ));
+            write(&spv_path, compiled.as_binary_u8())?;
+            write(
+                Path::new(&env::var("OUT_DIR").unwrap()).join(spv_path.file_name().unwrap()),
+                compiled.as_binary_u8(),
+            )?;
+        }
     }
 
     println!("cargo:rerun-if-changed=res/*");
@@ -106,7 +158,18 @@ fn main() -> Result<()> {
     copy_options.overwrite = true;
     let mut paths_to_copy = Vec::new();
     paths_to_copy.push("res/");
-    copy_items(&paths_to_copy, out_dir, &copy_options)?;
+    copy_items(&paths_to_copy, &out_dir, &copy_options)?;
+
+    // Android can't list asset subdirectories, so the app unpacks the files listed here.
+    let mut res_files = Vec::new();
+    for entry in glob("res/**/*")? {
+        let path = entry?;
+        if path.is_file() {
+            let relative = path.strip_prefix("res")?.to_string_lossy().replace('\\', "/");
+            res_files.push(relative);
+        }
+    }
+    write(Path::new(&out_dir).join("res_files.txt"), res_files.join("\n"))?;
     
     Ok(())
 }
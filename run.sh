@@ -2,14 +2,31 @@
 
 set -e
 
-echo "Compiling..."
-cargo build --target wasm32-unknown-unknown --release
+mkdir -p target/wasm/modeling
 
+# web/index.html loads the WebGPU build where the browser supports it and WebGL2 otherwise.
+echo "Compiling for WebGPU..."
+RUSTFLAGS=--cfg=web_sys_unstable_apis cargo build --target wasm32-unknown-unknown --release --bin modeling
 echo "Generating bindings..."
-mkdir -p target/wasm/modeling
-wasm-bindgen --target web --out-dir target/wasm/modeling target/wasm32-unknown-unknown/release/modeling.wasm
-cp ../wgpu/wasm-resources/index.template.html target/wasm/modeling/index.html
-sed -i "s/{{example}}/modeling/g" target/wasm/modeling/index.html
+wasm-bindgen --target web --out-dir target/wasm/modeling --out-name modeling_webgpu target/wasm32-unknown-unknown/release/modeling.wasm
+
+echo "Compiling for WebGL2..."
+cargo build --target wasm32-unknown-unknown --release --bin modeling --features webgl
+echo "Generating bindings..."
+wasm-bindgen --target web --out-dir target/wasm/modeling --out-name modeling_webgl target/wasm32-unknown-unknown/release/modeling.wasm
+
+# The library builds export the ModelingViewer API for embedding without the standalone page's
+# main, see src/embed.rs.
+echo "Compiling the embeddable viewer..."
+RUSTFLAGS=--cfg=web_sys_unstable_apis cargo build --target wasm32-unknown-unknown --release --lib
+wasm-bindgen --target web --out-dir target/wasm/modeling --out-name modeling_embed_webgpu target/wasm32-unknown-unknown/release/modeling.wasm
+cargo build --target wasm32-unknown-unknown --release --lib --features webgl
+wasm-bindgen --target web --out-dir target/wasm/modeling --out-name modeling_embed_webgl target/wasm32-unknown-unknown/release/modeling.wasm
+
+cp web/index.html target/wasm/modeling/index.html
+cp web/embed.html target/wasm/modeling/embed.html
+# Served next to the page so models can be loaded by URL.
+cp -r res target/wasm/modeling/
 
 # Find a serving tool to host the example
 SERVE_CMD=""
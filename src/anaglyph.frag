@@ -0,0 +1,17 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0) uniform texture2D t_left;
+layout(set=0, binding=1) uniform texture2D t_right;
+layout(set=0, binding=2) uniform sampler s_source;
+
+// Half color red-cyan anaglyph: the left eye only as brightness in red, which rivals less
+// with the right eye's colors than its own red would.
+void main() {
+    vec3 left = texture(sampler2D(t_left, s_source), v_tex_coords).rgb;
+    vec4 right = texture(sampler2D(t_right, s_source), v_tex_coords);
+    float luma = dot(left, vec3(0.299, 0.587, 0.114));
+    f_color = vec4(luma, right.g, right.b, right.a);
+}
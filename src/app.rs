@@ -0,0 +1,87 @@
+use instant::Instant;
+use winit::{event_loop::EventLoop, window::Window};
+
+use crate::{bench, gui, project, recording, render_config::RenderConfig, state};
+
+/// Runs the viewer in `window` until it is closed.
+pub async fn run(
+    event_loop: EventLoop<gui::Event>,
+    window: Window,
+    surface_format: Option<wgpu::TextureFormat>,
+    render_config: RenderConfig,
+    session: Option<recording::Session>,
+    benchmark: Option<bench::Benchmark>,
+    project: Option<project::Project>,
+) {
+    let mut state = state::State::new(&window, surface_format, render_config, &event_loop).await;
+    state.session = session;
+    if let Some(project) = project {
+        state.open_project(project);
+    }
+    if let Some(benchmark) = benchmark {
+        state.start_benchmark(benchmark);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    let canvas_fit = {
+        use winit::platform::web::WindowExtWebSys;
+        crate::web::CanvasFit::new(event_loop.create_proxy(), &window.canvas())
+    };
+
+    let start_time = Instant::now();
+    let mut previous_frame_time = None;
+    let mut last_update_inst = Instant::now();
+    event_loop.run(move |event, _, control_flow| {
+        // Kept alive as long as the event loop runs.
+        #[cfg(target_arch = "wasm32")]
+        let _ = &canvas_fit;
+        state.gui.handle_event(&event);
+        state.handle_event(
+            &event,
+            control_flow,
+            &window,
+            start_time,
+            &mut last_update_inst,
+            &mut previous_frame_time,
+        );
+    });
+}
+
+/// Entry point on Android and iOS, where the viewer is built as a library started by the
+/// platform instead of `main.rs`.
+#[cfg(any(target_os = "android", target_os = "ios"))]
+#[cfg_attr(target_os = "android", ndk_glue::main(backtrace = "on"))]
+pub fn main() {
+    env_logger::init();
+    let event_loop: EventLoop<gui::Event> = EventLoop::with_user_event();
+    let window = winit::window::WindowBuilder::new()
+        .build(&event_loop)
+        .unwrap();
+
+    // The surface can only be created once Android has handed over the native window.
+    #[cfg(target_os = "android")]
+    while ndk_glue::native_window().is_none() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    #[cfg(target_os = "android")]
+    if let Err(e) = crate::assets::unpack() {
+        log::error!("Failed to unpack assets: {:?}", e);
+    }
+
+    futures::executor::block_on(run(
+        event_loop,
+        window,
+        None,
+        RenderConfig::default(),
+        None,
+        None,
+        None,
+    ));
+}
+
+/// Called from the Xcode project's `main`.
+#[cfg(target_os = "ios")]
+#[no_mangle]
+pub extern "C" fn start_app() {
+    main();
+}
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+/// Directory the files of `res/` are read from at run time.
+///
+/// The build copies them next to the build output, which only exists on the machine that built
+/// the viewer. iOS bundles them with the app, and Android packs them into the APK, from where
+/// `unpack` copies them into the app's data directory.
+pub fn res_dir() -> PathBuf {
+    #[cfg(target_os = "android")]
+    {
+        PathBuf::from(ndk_glue::native_activity().internal_data_path()).join("res")
+    }
+    #[cfg(target_os = "ios")]
+    {
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|bundle| bundle.join("res")))
+            .unwrap_or_else(|| PathBuf::from("res"))
+    }
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    {
+        std::path::Path::new(env!("OUT_DIR")).join("res")
+    }
+}
+
+/// Copies the APK assets into `res_dir` so they can be opened as files.
+///
+/// The asset manager can't list subdirectories, so the files are taken from the listing the
+/// build writes.
+#[cfg(target_os = "android")]
+pub fn unpack() -> anyhow::Result<()> {
+    use anyhow::Context;
+    use std::io::Read;
+
+    let files = include_str!(concat!(env!("OUT_DIR"), "/res_files.txt"));
+    let assets = ndk_glue::native_activity().asset_manager();
+    let dir = res_dir();
+    for file in files.lines().filter(|line| !line.is_empty()) {
+        let path = dir.join(file);
+        if path.exists() {
+            continue;
+        }
+        let name = std::ffi::CString::new(file)?;
+        let mut asset = assets
+            .open(&name)
+            .with_context(|| format!("Missing asset {}", file))?;
+        let mut data = Vec::new();
+        asset.read_to_end(&mut data)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, data)?;
+    }
+    Ok(())
+}
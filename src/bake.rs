@@ -0,0 +1,352 @@
+use std::path::Path;
+
+use anyhow::*;
+use cgmath::{EuclideanSpace, InnerSpace, Point2, Point3, Vector3};
+use image::{GrayImage, ImageBuffer, Luma, Pixel, Rgba, RgbaImage};
+
+use crate::{
+    bvh::Bvh,
+    model::{self, Mesh, ModelVertex},
+    picking::Ray,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct BakeSettings {
+    /// Width and height of the baked maps in pixels.
+    pub size: u32,
+    /// How far from the low poly surface, in both directions, the high poly surface is searched.
+    pub max_distance: f32,
+    /// Rays per texel for ambient occlusion. Zero skips the occlusion map.
+    pub ao_samples: u32,
+    pub ao_distance: f32,
+    /// Texels filled around UV islands to hide seams when the maps are filtered.
+    pub padding: u32,
+}
+
+impl Default for BakeSettings {
+    fn default() -> Self {
+        Self {
+            size: 1024,
+            max_distance: 0.1,
+            ao_samples: 16,
+            ao_distance: 1.0,
+            padding: 4,
+        }
+    }
+}
+
+/// Triangles with everything needed for baking, independent of the GPU.
+#[derive(Debug, Clone, Default)]
+pub struct BakeMesh {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl BakeMesh {
+    pub fn from_meshes<'a, I: IntoIterator<Item = &'a Mesh>>(meshes: I) -> Self {
+        let mut bake_mesh = Self::default();
+        for mesh in meshes {
+            bake_mesh.append(&mesh.vertices, &mesh.indices);
+        }
+        bake_mesh
+    }
+
+    /// Loads every object of an OBJ file into one mesh without touching the GPU.
+    pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let (obj_models, _) = tobj::load_obj(
+            path.as_ref(),
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+        )
+        .with_context(|| format!("Bake: cannot load {:?}", path.as_ref()))?;
+
+        let mut bake_mesh = Self::default();
+        for m in obj_models {
+            let mesh = &m.mesh;
+            let mut vertices = (0..mesh.positions.len() / 3)
+                .map(|i| ModelVertex {
+                    position: [
+                        mesh.positions[i * 3],
+                        mesh.positions[i * 3 + 1],
+                        mesh.positions[i * 3 + 2],
+                    ],
+                    tex_coords: if mesh.texcoords.is_empty() {
+                        [0.0; 2]
+                    } else {
+                        [mesh.texcoords[i * 2], 1.0 - mesh.texcoords[i * 2 + 1]]
+                    },
+                    normal: if mesh.normals.is_empty() {
+                        [0.0; 3]
+                    } else {
+                        [
+                            mesh.normals[i * 3],
+                            mesh.normals[i * 3 + 1],
+                            mesh.normals[i * 3 + 2],
+                        ]
+                    },
+                    tangent: [0.0; 3],
+                    bitangent: [0.0; 3],
+                })
+                .collect::<Vec<_>>();
+            if mesh.normals.is_empty() {
+                model::compute_normals(&mut vertices, &mesh.indices);
+            }
+            model::compute_tangents(&mut vertices, &mesh.indices);
+            bake_mesh.append(&vertices, &mesh.indices);
+        }
+        Ok(bake_mesh)
+    }
+
+    fn append(&mut self, vertices: &[ModelVertex], indices: &[u32]) {
+        let offset = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(vertices);
+        self.indices.extend(indices.iter().map(|i| i + offset));
+    }
+
+    fn triangle(&self, index: usize) -> [&ModelVertex; 3] {
+        let c = &self.indices[index * 3..index * 3 + 3];
+        [
+            &self.vertices[c[0] as usize],
+            &self.vertices[c[1] as usize],
+            &self.vertices[c[2] as usize],
+        ]
+    }
+
+    fn bvh(&self) -> Bvh {
+        let positions = self
+            .vertices
+            .iter()
+            .map(|v| Point3::from(v.position))
+            .collect::<Vec<_>>();
+        Bvh::from_indexed(&positions, &self.indices)
+    }
+}
+
+fn interpolate(values: [[f32; 3]; 3], w: [f32; 3]) -> Vector3<f32> {
+    Vector3::from(values[0]) * w[0]
+        + Vector3::from(values[1]) * w[1]
+        + Vector3::from(values[2]) * w[2]
+}
+
+/// Barycentric weights of `p` in the 2D triangle, or `None` if it is degenerate.
+pub(crate) fn barycentric(
+    p: Point2<f32>,
+    a: Point2<f32>,
+    b: Point2<f32>,
+    c: Point2<f32>,
+) -> Option<[f32; 3]> {
+    let v0 = b - a;
+    let v1 = c - a;
+    let v2 = p - a;
+    let det = v0.x * v1.y - v1.x * v0.y;
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+    let v = (v2.x * v1.y - v1.x * v2.y) / det;
+    let w = (v0.x * v2.y - v2.x * v0.y) / det;
+    Some([1. - v - w, v, w])
+}
+
+/// Low discrepancy points on the unit square, so every texel uses the same sample pattern.
+pub(crate) fn hammersley(i: u32, n: u32) -> (f32, f32) {
+    (i as f32 / n as f32, i.reverse_bits() as f32 / 4_294_967_296.0)
+}
+
+pub(crate) fn orthonormal_basis(n: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let helper = if n.x.abs() > 0.9 {
+        Vector3::unit_y()
+    } else {
+        Vector3::unit_x()
+    };
+    let t = n.cross(helper).normalize();
+    (t, n.cross(t))
+}
+
+#[derive(Debug)]
+pub struct BakedMaps {
+    /// Tangent space normals of the high poly mesh, encoded as `n * 0.5 + 0.5`.
+    pub normal: RgbaImage,
+    /// One where the surface is fully open, darker where it is occluded.
+    pub ambient_occlusion: GrayImage,
+    /// 128 on flat areas, brighter on convex and darker on concave areas.
+    pub curvature: GrayImage,
+}
+
+impl BakedMaps {
+    /// Writes `{prefix}_normal.png`, `{prefix}_ao.png` and `{prefix}_curvature.png` into the directory.
+    pub fn save<P: AsRef<Path>>(&self, dir: P, prefix: &str) -> Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        self.normal.save(dir.join(format!("{}_normal.png", prefix)))?;
+        self.ambient_occlusion
+            .save(dir.join(format!("{}_ao.png", prefix)))?;
+        self.curvature
+            .save(dir.join(format!("{}_curvature.png", prefix)))?;
+        Ok(())
+    }
+}
+
+/// Projects the surface of `high` onto the UV layout of `low`.
+///
+/// For every texel covered by a low poly triangle a ray is cast against the high poly mesh
+/// along the interpolated normal, starting `max_distance` outside the surface.
+pub fn bake(low: &BakeMesh, high: &BakeMesh, settings: &BakeSettings) -> BakedMaps {
+    let size = settings.size.max(1);
+    let bvh = high.bvh();
+    let scale = bvh.bounds().size().magnitude().max(f32::EPSILON);
+
+    let flat_normal = Rgba([128, 128, 255, 255]);
+    let mut normal = RgbaImage::from_pixel(size, size, flat_normal);
+    let mut ambient_occlusion = GrayImage::from_pixel(size, size, Luma([255]));
+    let mut covered = vec![false; (size * size) as usize];
+    let mut tangent_normals = vec![Vector3::new(0., 0., 1.); (size * size) as usize];
+
+    for triangle in 0..low.indices.len() / 3 {
+        let corners = low.triangle(triangle);
+        let uv = corners.map(|v| Point2::from(v.tex_coords) * size as f32);
+        let min = |f: fn(&Point2<f32>) -> f32| uv.iter().map(f).fold(f32::INFINITY, f32::min);
+        let max = |f: fn(&Point2<f32>) -> f32| uv.iter().map(f).fold(f32::NEG_INFINITY, f32::max);
+        let (min_x, min_y) = (min(|p| p.x).max(0.) as u32, min(|p| p.y).max(0.) as u32);
+        let max_x = (max(|p| p.x).ceil().max(0.) as u32).min(size);
+        let max_y = (max(|p| p.y).ceil().max(0.) as u32).min(size);
+
+        // The tangent frame is constant over a triangle.
+        let tangent = Vector3::from(corners[0].tangent);
+        let bitangent = Vector3::from(corners[0].bitangent);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let p = Point2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let w = match barycentric(p, uv[0], uv[1], uv[2]) {
+                    Some(w) if w.iter().all(|&w| w >= -1e-4) => w,
+                    _ => continue,
+                };
+                let texel = (y * size + x) as usize;
+                covered[texel] = true;
+
+                let position = Point3::from_vec(interpolate(corners.map(|v| v.position), w));
+                let n = interpolate(corners.map(|v| v.normal), w).normalize();
+                let t = (tangent - n * n.dot(tangent)).normalize();
+                let b = {
+                    let b = n.cross(t);
+                    if b.dot(bitangent) < 0. {
+                        -b
+                    } else {
+                        b
+                    }
+                };
+
+                let ray = Ray {
+                    origin: position + n * settings.max_distance,
+                    direction: -n,
+                };
+                let hit = match bvh.intersect(&ray, settings.max_distance * 2.) {
+                    Some(hit) => hit,
+                    None => continue,
+                };
+                let [ha, hb, hc] = high.triangle(hit.triangle);
+                let hw = [1. - hit.u - hit.v, hit.u, hit.v];
+                let high_normal = interpolate([ha.normal, hb.normal, hc.normal], hw).normalize();
+
+                let tangent_normal =
+                    Vector3::new(high_normal.dot(t), high_normal.dot(b), high_normal.dot(n));
+                tangent_normals[texel] = tangent_normal;
+                let encode = |c: f32| ((c * 0.5 + 0.5).max(0.).min(1.) * 255.).round() as u8;
+                normal.put_pixel(
+                    x,
+                    y,
+                    Rgba([
+                        encode(tangent_normal.x),
+                        encode(tangent_normal.y),
+                        encode(tangent_normal.z),
+                        255,
+                    ]),
+                );
+
+                if settings.ao_samples > 0 {
+                    let hit_point = ray.at(hit.distance) + high_normal * (scale * 1e-4);
+                    let (ht, hbt) = orthonormal_basis(high_normal);
+                    let occluded = (0..settings.ao_samples)
+                        .filter(|&i| {
+                            // Cosine weighted hemisphere around the high poly normal.
+                            let (u1, u2) = hammersley(i, settings.ao_samples);
+                            let r = u1.sqrt();
+                            let phi = 2. * std::f32::consts::PI * u2;
+                            let direction = ht * (r * phi.cos())
+                                + hbt * (r * phi.sin())
+                                + high_normal * (1. - u1).max(0.).sqrt();
+                            bvh.occluded(
+                                &Ray {
+                                    origin: hit_point,
+                                    direction,
+                                },
+                                settings.ao_distance,
+                            )
+                        })
+                        .count();
+                    let open = 1. - occluded as f32 / settings.ao_samples as f32;
+                    ambient_occlusion.put_pixel(x, y, Luma([(open * 255.).round() as u8]));
+                }
+            }
+        }
+    }
+
+    let mut curvature = GrayImage::from_pixel(size, size, Luma([128]));
+    for y in 1..size.saturating_sub(1) {
+        for x in 1..size.saturating_sub(1) {
+            let at = |x: u32, y: u32| (y * size + x) as usize;
+            let neighbours = [at(x - 1, y), at(x + 1, y), at(x, y - 1), at(x, y + 1)];
+            if !covered[at(x, y)] || neighbours.iter().any(|&i| !covered[i]) {
+                continue;
+            }
+            // Divergence of the normal field: positive where normals spread apart.
+            let dx = tangent_normals[neighbours[1]].x - tangent_normals[neighbours[0]].x;
+            let dy = tangent_normals[neighbours[3]].y - tangent_normals[neighbours[2]].y;
+            let value = 0.5 + (dx + dy) * size as f32 / 64.;
+            let value = (value.max(0.).min(1.) * 255.).round() as u8;
+            curvature.put_pixel(x, y, Luma([value]));
+        }
+    }
+
+    dilate(&mut normal, &covered, size, settings.padding);
+    dilate(&mut ambient_occlusion, &covered, size, settings.padding);
+    dilate(&mut curvature, &covered, size, settings.padding);
+
+    BakedMaps {
+        normal,
+        ambient_occlusion,
+        curvature,
+    }
+}
+
+/// Grows the covered area by copying edge texels outward, one texel per pass.
+fn dilate<P>(image: &mut ImageBuffer<P, Vec<u8>>, covered: &[bool], size: u32, passes: u32)
+where
+    P: Pixel<Subpixel = u8> + 'static,
+{
+    let mut covered = covered.to_vec();
+    for _ in 0..passes {
+        let mut next = covered.clone();
+        for y in 0..size {
+            for x in 0..size {
+                if covered[(y * size + x) as usize] {
+                    continue;
+                }
+                let neighbour = [(-1i32, 0i32), (1, 0), (0, -1), (0, 1)]
+                    .iter()
+                    .map(|(dx, dy)| (x as i32 + dx, y as i32 + dy))
+                    .filter(|&(nx, ny)| nx >= 0 && ny >= 0 && nx < size as i32 && ny < size as i32)
+                    .find(|&(nx, ny)| covered[(ny as u32 * size + nx as u32) as usize]);
+                if let Some((nx, ny)) = neighbour {
+                    let pixel = *image.get_pixel(nx as u32, ny as u32);
+                    image.put_pixel(x, y, pixel);
+                    next[(y * size + x) as usize] = true;
+                }
+            }
+        }
+        covered = next;
+    }
+}
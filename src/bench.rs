@@ -0,0 +1,157 @@
+use std::{fs::File, io::Write, path::PathBuf};
+
+use anyhow::*;
+use cgmath::InnerSpace;
+use instant::Instant;
+use serde::Serialize;
+
+use crate::{camera::Camera, scene::SceneStats};
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FrameSample {
+    /// Seconds since the benchmark started.
+    pub time: f32,
+    pub frame_time_ms: f32,
+    pub draw_calls: u32,
+    pub triangles: u64,
+    pub vram_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub duration: f32,
+    pub frames: usize,
+    pub mean_frame_ms: f32,
+    pub min_frame_ms: f32,
+    pub max_frame_ms: f32,
+    pub p50_frame_ms: f32,
+    pub p95_frame_ms: f32,
+    pub p99_frame_ms: f32,
+    pub mean_fps: f32,
+    pub samples: Vec<FrameSample>,
+}
+
+/// Flies the camera on a fixed orbit around the scene while collecting per-frame statistics.
+pub struct Benchmark {
+    pub duration: f32,
+    pub output: PathBuf,
+    start: Option<Instant>,
+    last_frame: Option<Instant>,
+    origin: Option<(cgmath::Point3<f32>, f32)>,
+    samples: Vec<FrameSample>,
+}
+
+impl Benchmark {
+    pub fn new<P: Into<PathBuf>>(duration: f32, output: P) -> Self {
+        Self {
+            duration,
+            output: output.into(),
+            start: None,
+            last_frame: None,
+            origin: None,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn elapsed(&self) -> f32 {
+        self.start
+            .map(|start| start.elapsed().as_secs_f32())
+            .unwrap_or(0.)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed() >= self.duration
+    }
+
+    /// Places the camera on the path. The orbit is derived from the first pose so every run is identical.
+    pub fn update_camera(&mut self, camera: &mut Camera) {
+        let (target, radius) = *self
+            .origin
+            .get_or_insert_with(|| (camera.target, (camera.eye - camera.target).magnitude()));
+        let t = self.elapsed() / self.duration;
+        let angle = std::f32::consts::PI * 2. * t;
+        let height = radius * (0.3 + 0.2 * f32::sin(angle * 2.));
+        camera.target = target;
+        camera.eye = target
+            + cgmath::Vector3::new(radius * f32::cos(angle), height, radius * f32::sin(angle));
+        camera.up = cgmath::Vector3::unit_y();
+    }
+
+    pub fn record_frame(&mut self, stats: SceneStats) {
+        let now = Instant::now();
+        let start = *self.start.get_or_insert(now);
+        if let Some(last_frame) = self.last_frame {
+            self.samples.push(FrameSample {
+                time: (now - start).as_secs_f32(),
+                frame_time_ms: (now - last_frame).as_secs_f32() * 1000.,
+                draw_calls: stats.draw_calls,
+                triangles: stats.triangles,
+                vram_bytes: stats.vram_bytes,
+            });
+        }
+        self.last_frame = Some(now);
+    }
+
+    pub fn report(&self) -> Report {
+        let mut frame_times = self
+            .samples
+            .iter()
+            .map(|s| s.frame_time_ms)
+            .collect::<Vec<_>>();
+        frame_times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let percentile = |p: f32| {
+            if frame_times.is_empty() {
+                0.
+            } else {
+                let i = ((frame_times.len() - 1) as f32 * p).round() as usize;
+                frame_times[i]
+            }
+        };
+        let total: f32 = frame_times.iter().sum();
+        let mean = total / frame_times.len().max(1) as f32;
+        Report {
+            duration: self.elapsed(),
+            frames: self.samples.len(),
+            mean_frame_ms: mean,
+            min_frame_ms: frame_times.first().copied().unwrap_or(0.),
+            max_frame_ms: frame_times.last().copied().unwrap_or(0.),
+            p50_frame_ms: percentile(0.5),
+            p95_frame_ms: percentile(0.95),
+            p99_frame_ms: percentile(0.99),
+            mean_fps: if mean > 0. { 1000. / mean } else { 0. },
+            samples: self.samples.clone(),
+        }
+    }
+
+    /// Writes the report as CSV when the output ends with `.csv`, otherwise as JSON.
+    pub fn write_report(&self) -> Result<()> {
+        let report = self.report();
+        let mut file = File::create(&self.output)
+            .with_context(|| format!("Cannot create benchmark report {:?}", self.output))?;
+        let is_csv = self
+            .output
+            .extension()
+            .map(|ext| ext.eq_ignore_ascii_case("csv"))
+            .unwrap_or(false);
+        if is_csv {
+            writeln!(file, "time,frame_time_ms,draw_calls,triangles,vram_bytes")?;
+            for s in report.samples.iter() {
+                writeln!(
+                    file,
+                    "{},{},{},{},{}",
+                    s.time, s.frame_time_ms, s.draw_calls, s.triangles, s.vram_bytes
+                )?;
+            }
+        } else {
+            serde_json::to_writer_pretty(file, &report)?;
+        }
+        log::info!(
+            "Benchmark: {} frames, mean {:.2} ms, p95 {:.2} ms, written to {:?}",
+            report.frames,
+            report.mean_frame_ms,
+            report.p95_frame_ms,
+            self.output
+        );
+        Ok(())
+    }
+}
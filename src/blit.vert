@@ -0,0 +1,10 @@
+#version 450
+
+layout(location=0) out vec2 v_tex_coords;
+
+// One triangle covering the screen.
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    v_tex_coords = uv;
+    gl_Position = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0) uniform texture2D t_source;
+layout(set=0, binding=1) uniform sampler s_source;
+
+// The target doesn't encode on write, so the linear color is encoded here.
+vec3 srgb_from_linear(vec3 linear) {
+    bvec3 cutoff = lessThan(linear, vec3(0.0031308));
+    vec3 lower = linear * 12.92;
+    vec3 higher = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(higher, lower, vec3(cutoff));
+}
+
+void main() {
+    vec4 color = texture(sampler2D(t_source, s_source), v_tex_coords);
+    f_color = vec4(srgb_from_linear(color.rgb), color.a);
+}
@@ -0,0 +1,170 @@
+//! Project bundles, `.modpack` files: a zip archive of the project with the models it names and
+//! every file they load, the material libraries and textures of OBJ files and the buffers and
+//! images of glTF files, so a scene opens on another machine without broken paths. Each model
+//! keeps its files where they were next to it, under a folder of its own. Opening a bundle
+//! unpacks it into the temporary directory and loads the models from there.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+use itertools::Itertools;
+use zip::{write::FileOptions, ZipArchive, ZipWriter};
+
+use crate::{
+    gltf_import::{self, uri_to_name},
+    loading::sibling,
+    project::Project,
+    scene::Scene,
+};
+
+pub const EXTENSION: &str = "modpack";
+/// The project in the archive, its models named by their path in it.
+const PROJECT: &str = "project.ron";
+
+/// MTL statements naming a texture file, with the rest of the line as tobj reads them.
+const TEXTURE_STATEMENTS: [&str; 10] = [
+    "map_Ka", "map_Kd", "map_Ks", "map_Bump", "map_bump", "map_Ns", "map_ns", "map_NS", "bump",
+    "map_d",
+];
+
+/// The files the model at `source` loads besides itself. The ones it names that don't exist are
+/// left out, as loading does without them too.
+fn dependencies(source: &str) -> Result<Vec<String>> {
+    let bytes = std::fs::read(source).with_context(|| format!("Can't open {}", source))?;
+    let mut files = Vec::new();
+    if gltf_import::is_gltf(source) {
+        let gltf =
+            gltf::Gltf::from_slice(&bytes).with_context(|| format!("Can't parse {}", source))?;
+        let buffers = gltf.buffers().filter_map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Uri(uri) => Some(uri),
+            gltf::buffer::Source::Bin => None,
+        });
+        let images = gltf.images().filter_map(|image| match image.source() {
+            gltf::image::Source::Uri { uri, .. } => Some(uri),
+            gltf::image::Source::View { .. } => None,
+        });
+        files.extend(
+            buffers
+                .chain(images)
+                .filter(|uri| !uri.starts_with("data:"))
+                .map(|uri| sibling(source, &uri_to_name(uri))),
+        );
+    } else {
+        for line in String::from_utf8_lossy(&bytes).lines() {
+            let mut words = line.split_whitespace();
+            if words.next() != Some("mtllib") {
+                continue;
+            }
+            for library in words.map(|name| sibling(source, name)) {
+                let text = match std::fs::read_to_string(&library) {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                };
+                for line in text.lines().map(str::trim) {
+                    let statement = line.split_whitespace().next().unwrap_or_default();
+                    if TEXTURE_STATEMENTS.contains(&statement) {
+                        files.push(sibling(source, line[statement.len()..].trim()));
+                    }
+                }
+                files.push(library);
+            }
+        }
+    }
+    Ok(files.into_iter().unique().filter(|file| Path::new(file).is_file()).collect())
+}
+
+/// The deepest folder holding all of the files.
+fn common_dir(files: &[PathBuf]) -> PathBuf {
+    let mut dir = files
+        .first()
+        .and_then(|file| file.parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    while !files.iter().all(|file| file.starts_with(&dir)) {
+        if !dir.pop() {
+            break;
+        }
+    }
+    dir
+}
+
+/// Writes the scene's project with its models and their files into a bundle at `path`,
+/// returning how many files went in. Models made in the app, which have no file, are left out.
+pub fn export<P: AsRef<Path>>(scene: &Scene, path: P) -> Result<usize> {
+    profile_function!();
+    let mut project = Project::from_scene(scene);
+    let mut zip = ZipWriter::new(File::create(path.as_ref())?);
+    let options = FileOptions::default();
+    let mut written = 0;
+    // Models loaded from the same file share its copy.
+    let mut entries: HashMap<String, String> = HashMap::new();
+    for (i, source) in project.models.iter_mut().enumerate() {
+        if source.is_empty() {
+            continue;
+        }
+        if let Some(entry) = entries.get(source) {
+            *source = entry.clone();
+            continue;
+        }
+        let mut files = vec![source.clone()];
+        files.extend(dependencies(source)?);
+        let files = files
+            .iter()
+            .map(|file| std::fs::canonicalize(file).with_context(|| format!("No {}", file)))
+            .collect::<Result<Vec<_>>>()?;
+        let dir = common_dir(&files);
+        let mut model_entry = None;
+        for file in files.iter() {
+            let relative = file.strip_prefix(&dir).unwrap_or(file);
+            let name = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .join("/");
+            let entry = format!("models/{}/{}", i, name);
+            zip.start_file(entry.as_str(), options)?;
+            std::io::copy(&mut File::open(file)?, &mut zip)?;
+            written += 1;
+            model_entry.get_or_insert(entry);
+        }
+        let entry = model_entry.unwrap_or_default();
+        entries.insert(source.clone(), entry.clone());
+        *source = entry;
+    }
+    zip.start_file(PROJECT, options)?;
+    let text = ron::ser::to_string_pretty(&project, ron::ser::PrettyConfig::default())?;
+    zip.write_all(text.as_bytes())?;
+    zip.finish()?;
+    Ok(written)
+}
+
+/// Unpacks the bundle at `path` into a folder of its own in the temporary directory, replacing
+/// what an earlier bundle of the same name left there, and reads its project, whose models are
+/// named relative to it.
+pub fn extract<P: AsRef<Path>>(path: P) -> Result<Project> {
+    profile_function!();
+    let path = path.as_ref();
+    let name = path.file_stem().context("The bundle has no file name")?;
+    let dir = std::env::temp_dir().join("modeling-bundles").join(name);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    let file = File::open(path).with_context(|| format!("Bundle: {:?} not found", path))?;
+    ZipArchive::new(file)
+        .and_then(|mut archive| archive.extract(&dir))
+        .with_context(|| format!("Bundle: {:?} is broken", path))?;
+    Project::load(dir.join(PROJECT))
+}
+
+impl Scene {
+    /// Opens the bundle at `path`, loading its models and then grouping them like its project.
+    pub fn open_bundle<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let project = extract(path)?;
+        self.load_project(project);
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+use cgmath::Point3;
+
+use crate::{bounds::Aabb, picking::Ray};
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bounds: Aabb,
+    /// For leaves the first triangle in `order`, otherwise the index of the right child.
+    /// The left child always directly follows its parent.
+    start: u32,
+    /// Number of triangles, zero for inner nodes.
+    count: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BvhHit {
+    pub distance: f32,
+    /// Index of the triangle in the list the hierarchy was built from.
+    pub triangle: usize,
+    /// Barycentric weights of the second and third corner.
+    pub u: f32,
+    pub v: f32,
+}
+
+/// What `Bvh::traverse` does after a hit.
+enum Visit {
+    /// Only look for hits closer than it from now on.
+    Closer,
+    /// Go on as if it missed.
+    Skip,
+    /// Stop looking.
+    Stop,
+}
+
+/// Bounding volume hierarchy over triangles for fast ray queries.
+#[derive(Debug)]
+pub struct Bvh {
+    triangles: Vec<[Point3<f32>; 3]>,
+    order: Vec<u32>,
+    nodes: Vec<Node>,
+}
+
+impl Bvh {
+    const LEAF_SIZE: usize = 4;
+
+    pub fn new(triangles: Vec<[Point3<f32>; 3]>) -> Self {
+        let mut bvh = Self {
+            order: (0..triangles.len() as u32).collect(),
+            triangles,
+            nodes: Vec::new(),
+        };
+        if !bvh.triangles.is_empty() {
+            let centroids = bvh
+                .triangles
+                .iter()
+                .map(|[a, b, c]| {
+                    Point3::new(
+                        (a.x + b.x + c.x) / 3.,
+                        (a.y + b.y + c.y) / 3.,
+                        (a.z + b.z + c.z) / 3.,
+                    )
+                })
+                .collect::<Vec<_>>();
+            bvh.build(0, bvh.triangles.len(), &centroids);
+        }
+        bvh
+    }
+
+    /// Builds a hierarchy over the triangles of an indexed mesh.
+    pub fn from_indexed(positions: &[Point3<f32>], indices: &[u32]) -> Self {
+        Self::new(
+            indices
+                .chunks_exact(3)
+                .map(|c| {
+                    [
+                        positions[c[0] as usize],
+                        positions[c[1] as usize],
+                        positions[c[2] as usize],
+                    ]
+                })
+                .collect(),
+        )
+    }
+
+    pub fn triangle(&self, index: usize) -> &[Point3<f32>; 3] {
+        &self.triangles[index]
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map(|node| node.bounds).unwrap_or(Aabb::EMPTY)
+    }
+
+    fn build(&mut self, start: usize, end: usize, centroids: &[Point3<f32>]) -> usize {
+        let bounds = self.order[start..end].iter().fold(Aabb::EMPTY, |aabb, &i| {
+            let [a, b, c] = self.triangles[i as usize];
+            aabb.extend(a).extend(b).extend(c)
+        });
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds,
+            start: start as u32,
+            count: (end - start) as u32,
+        });
+        if end - start <= Self::LEAF_SIZE {
+            return index;
+        }
+
+        // Split at the median centroid along the longest axis.
+        let centroid_bounds = self.order[start..end]
+            .iter()
+            .fold(Aabb::EMPTY, |aabb, &i| aabb.extend(centroids[i as usize]));
+        let size = centroid_bounds.size();
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        };
+        let mid = (start + end) / 2;
+        self.order[start..end].select_nth_unstable_by(mid - start, |&a, &b| {
+            centroids[a as usize][axis]
+                .partial_cmp(&centroids[b as usize][axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        self.build(start, mid, centroids);
+        let right = self.build(mid, end, centroids);
+        self.nodes[index].start = right as u32;
+        self.nodes[index].count = 0;
+        index
+    }
+
+    /// Finds the closest triangle hit by the ray within `max_distance`.
+    pub fn intersect(&self, ray: &Ray, max_distance: f32) -> Option<BvhHit> {
+        self.intersect_where(ray, max_distance, |_, _| true)
+    }
+
+    /// Like `intersect`, skipping the hits `accept` turns down given the triangle and the
+    /// distance, so the ones behind them can still be found.
+    pub fn intersect_where<F>(&self, ray: &Ray, max_distance: f32, mut accept: F) -> Option<BvhHit>
+    where
+        F: FnMut(usize, f32) -> bool,
+    {
+        let mut closest: Option<BvhHit> = None;
+        self.traverse(ray, max_distance, |triangle, (t, u, v)| {
+            if closest.map(|hit| t >= hit.distance).unwrap_or(false) || !accept(triangle, t) {
+                return Visit::Skip;
+            }
+            closest = Some(BvhHit {
+                distance: t,
+                triangle,
+                u,
+                v,
+            });
+            Visit::Closer
+        });
+        closest
+    }
+
+    /// Whether anything is hit within `max_distance`, cheaper than `intersect`.
+    pub fn occluded(&self, ray: &Ray, max_distance: f32) -> bool {
+        let mut hit = false;
+        self.traverse(ray, max_distance, |_, _| {
+            hit = true;
+            Visit::Stop
+        });
+        hit
+    }
+
+    /// Calls `on_hit` for triangles hit closer than the closest one so far.
+    fn traverse<F>(&self, ray: &Ray, max_distance: f32, mut on_hit: F)
+    where
+        F: FnMut(usize, (f32, f32, f32)) -> Visit,
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut limit = max_distance;
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            match node.bounds.intersect_ray(ray.origin, ray.direction) {
+                Some(t) if t <= limit => {}
+                _ => continue,
+            }
+            if node.count == 0 {
+                stack.push(node.start as usize);
+                stack.push(index + 1);
+                continue;
+            }
+            let start = node.start as usize;
+            for &triangle in self.order[start..start + node.count as usize].iter() {
+                let [a, b, c] = self.triangles[triangle as usize];
+                if let Some(hit) = ray.intersect_triangle_barycentric(a, b, c) {
+                    if hit.0 <= limit {
+                        match on_hit(triangle as usize, hit) {
+                            Visit::Closer => limit = hit.0,
+                            Visit::Skip => {}
+                            Visit::Stop => return,
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::*;
+
+    fn square(z: f32) -> Vec<[Point3<f32>; 3]> {
+        let p = |x, y| Point3::new(x, y, z);
+        vec![
+            [p(-1., -1.), p(1., -1.), p(1., 1.)],
+            [p(-1., -1.), p(1., 1.), p(-1., 1.)],
+        ]
+    }
+
+    #[test]
+    fn skipped_hits_let_the_ones_behind_through() {
+        let mut triangles = square(1.);
+        triangles.extend(square(2.));
+        let bvh = Bvh::new(triangles);
+        let ray = Ray {
+            origin: Point3::new(0.2, 0.1, 0.),
+            direction: Vector3::new(0., 0., 1.),
+        };
+
+        let front = bvh.intersect(&ray, f32::INFINITY).unwrap();
+        assert_eq!(front.distance, 1.);
+        let back = bvh
+            .intersect_where(&ray, f32::INFINITY, |_, t| t > 1.5)
+            .unwrap();
+        assert_eq!(back.distance, 2.);
+        assert!(back.triangle >= 2);
+        assert!(bvh.intersect_where(&ray, 1.5, |_, t| t > 1.5).is_none());
+    }
+}
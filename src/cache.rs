@@ -0,0 +1,172 @@
+//! Downloads kept in IndexedDB by the web build, so models aren't fetched again on every visit.
+//!
+//! Entries are stored under their URL together with the ETag they were served with, and only
+//! reused after the server answered a conditional request for that ETag with 304. Files served
+//! without an ETag can't be checked that way and aren't cached.
+
+use anyhow::*;
+use js_sys::{Object, Promise, Reflect, Uint8Array};
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbRequest, IdbTransactionMode};
+
+use crate::web::{fetch_with_progress, js_error, Fetched};
+
+const DATABASE: &str = "modeling-assets";
+/// Raw bytes of models and material libraries.
+const FILES: &str = "files";
+/// Textures as decoded RGBA pixels, saving the decode as well.
+const IMAGES: &str = "images";
+
+/// Resolves with the result of the request once it succeeded.
+async fn settle(request: &IdbRequest) -> Result<JsValue> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        request.set_onsuccess(Some(&resolve));
+        request.set_onerror(Some(&reject));
+    });
+    JsFuture::from(promise).await.map_err(js_error)?;
+    request.result().map_err(js_error)
+}
+
+fn get(entry: &JsValue, field: &str) -> Option<JsValue> {
+    Reflect::get(entry, &field.into())
+        .ok()
+        .filter(|value| !value.is_undefined())
+}
+
+pub struct AssetCache {
+    db: IdbDatabase,
+}
+
+impl AssetCache {
+    /// Fails where IndexedDB isn't available, e.g. in some private windows.
+    pub async fn open() -> Result<Self> {
+        let factory = web_sys::window()
+            .context("No window")?
+            .indexed_db()
+            .map_err(js_error)?
+            .context("No IndexedDB")?;
+        let request = factory.open_with_u32(DATABASE, 1).map_err(js_error)?;
+        let upgrade = Closure::wrap(Box::new(|event: web_sys::Event| {
+            let db = event
+                .target()
+                .and_then(|target| target.dyn_into::<IdbRequest>().ok())
+                .and_then(|request| request.result().ok())
+                .and_then(|db| db.dyn_into::<IdbDatabase>().ok());
+            if let Some(db) = db {
+                db.create_object_store(FILES).ok();
+                db.create_object_store(IMAGES).ok();
+            }
+        }) as Box<dyn FnMut(web_sys::Event)>);
+        request.set_onupgradeneeded(Some(upgrade.as_ref().unchecked_ref()));
+        let db = settle(&request).await?;
+        request.set_onupgradeneeded(None);
+        Ok(Self {
+            db: db.dyn_into().map_err(js_error)?,
+        })
+    }
+
+    async fn entry(&self, store: &str, url: &str) -> Result<Option<JsValue>> {
+        let transaction = self.db.transaction_with_str(store).map_err(js_error)?;
+        let request = transaction
+            .object_store(store)
+            .and_then(|store| store.get(&url.into()))
+            .map_err(js_error)?;
+        let entry = settle(&request).await?;
+        Ok(Some(entry).filter(|entry| entry.is_object()))
+    }
+
+    async fn put(&self, store: &str, url: &str, entry: &Object) -> Result<()> {
+        let transaction = self
+            .db
+            .transaction_with_str_and_mode(store, IdbTransactionMode::Readwrite)
+            .map_err(js_error)?;
+        let request = transaction
+            .object_store(store)
+            .and_then(|store| store.put_with_key(entry, &url.into()))
+            .map_err(js_error)?;
+        settle(&request).await?;
+        Ok(())
+    }
+
+    /// Stores `fields` under `url` with the ETag it was served with. Failing to store, e.g.
+    /// because the quota is used up, only costs a download next time.
+    async fn store(&self, store: &str, url: &str, etag: &str, fields: &[(&str, JsValue)]) {
+        let entry = Object::new();
+        let set = |field: &str, value: &JsValue| Reflect::set(&entry, &field.into(), value);
+        let stored = fields
+            .iter()
+            .chain(&[("etag", etag.into())])
+            .try_for_each(|(field, value)| set(field, value).map(drop));
+        let stored = match stored {
+            Ok(()) => self.put(store, url, &entry).await,
+            Err(e) => Err(js_error(e)),
+        };
+        if let Err(e) = stored {
+            log::warn!("Caching {} failed: {:?}", url, e);
+        }
+    }
+
+    /// The file at `url`, from the cache if it's still current.
+    pub async fn fetch(&self, url: &str, progress: &dyn Fn(u64, Option<u64>)) -> Result<Vec<u8>> {
+        let cached = self.entry(FILES, url).await.unwrap_or(None);
+        let etag = cached
+            .as_ref()
+            .and_then(|entry| get(entry, "etag")?.as_string());
+        match fetch_with_progress(url, etag.as_deref(), progress).await? {
+            Fetched::NotModified => {
+                let data = cached
+                    .and_then(|entry| get(&entry, "data"))
+                    .context("Cache entry without data")?;
+                Ok(data.unchecked_into::<Uint8Array>().to_vec())
+            }
+            Fetched::Downloaded { data, etag } => {
+                if let Some(etag) = etag {
+                    let fields = [("data", Uint8Array::from(&data[..]).into())];
+                    self.store(FILES, url, &etag, &fields).await;
+                }
+                Ok(data)
+            }
+        }
+    }
+
+    /// The image at `url` decoded, from the cache if it's still current.
+    pub async fn fetch_image(
+        &self,
+        url: &str,
+        progress: &dyn Fn(u64, Option<u64>),
+    ) -> Result<image::DynamicImage> {
+        let cached = self.entry(IMAGES, url).await.unwrap_or(None);
+        let etag = cached
+            .as_ref()
+            .and_then(|entry| get(entry, "etag")?.as_string());
+        match fetch_with_progress(url, etag.as_deref(), progress).await? {
+            Fetched::NotModified => {
+                let entry = cached.context("No cached image")?;
+                let size = |field| get(&entry, field)?.as_f64().map(|size| size as u32);
+                let image = match (size("width"), size("height"), get(&entry, "pixels")) {
+                    (Some(width), Some(height), Some(pixels)) => {
+                        let pixels = pixels.unchecked_into::<Uint8Array>().to_vec();
+                        image::RgbaImage::from_raw(width, height, pixels)
+                    }
+                    _ => None,
+                }
+                .context("Malformed cached image")?;
+                Ok(image::DynamicImage::ImageRgba8(image))
+            }
+            Fetched::Downloaded { data, etag } => {
+                let image = image::load_from_memory(&data)?;
+                if let Some(etag) = etag {
+                    let rgba = image.to_rgba8();
+                    let fields = [
+                        ("width", rgba.width().into()),
+                        ("height", rgba.height().into()),
+                        ("pixels", Uint8Array::from(rgba.as_raw().as_slice()).into()),
+                    ];
+                    self.store(IMAGES, url, &etag, &fields).await;
+                }
+                Ok(image)
+            }
+        }
+    }
+}
@@ -2,6 +2,8 @@ use winit::{dpi::PhysicalSize, event::*};
 
 use cgmath::InnerSpace;
 
+use crate::camera_rig::{CameraRig, CameraScheme};
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -86,12 +88,33 @@ pub struct Camera {
     pub target: cgmath::Point3<f32>,
     pub up: cgmath::Vector3<f32>,
     pub projection: cgmath::PerspectiveFov<f32>,
+    /// Views without perspective, as large as the perspective view is at the target. Toggled
+    /// with numpad 5.
+    pub orthographic: bool,
 }
 
 impl Camera {
     pub fn calc_matrix(&self) -> cgmath::Matrix4<f32> {
         cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up)
     }
+
+    /// Half the width and height of the orthographic view. Zooming in shrinks it, as it moves
+    /// the eye closer to the target.
+    pub fn ortho_half_size(&self) -> (f32, f32) {
+        let distance = (self.target - self.eye).magnitude();
+        let half_height = distance * f32::tan(self.projection.fovy.0 / 2.);
+        (half_height * self.projection.aspect, half_height)
+    }
+
+    /// The perspective or orthographic projection, corrected to wgpu's depth range.
+    pub fn projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        if !self.orthographic {
+            return self.projection.calc_matrix();
+        }
+        let (width, height) = self.ortho_half_size();
+        let (near, far) = (self.projection.near, self.projection.far);
+        OPENGL_TO_WGPU_MATRIX * cgmath::ortho(-width, width, -height, height, near, far)
+    }
     pub fn new(size: PhysicalSize<u32>) -> Self {
         let projection = cgmath::PerspectiveFov::new(size.width, size.height, cgmath::Deg(45.0), 0.1, 100000.0);
 
@@ -100,38 +123,141 @@ impl Camera {
             target: (0.0, 0.0, 0.0).into(),
             up: cgmath::Vector3::unit_y(),
             projection,
+            orthographic: false,
         }
     }
 }
 
-pub struct CameraController {
-    speed: f32,
-    is_up_pressed: bool,
-    is_down_pressed: bool,
-    is_forward_pressed: bool,
-    is_backward_pressed: bool,
-    is_move_left_pressed: bool,
-    is_move_right_pressed: bool,
-    is_move_up_pressed: bool,
-    is_move_down_pressed: bool,
-    is_left_pressed: bool,
-    is_right_pressed: bool,
-    is_middle_pressed: bool,
+#[derive(Debug, Clone, Copy)]
+pub struct CameraPose {
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+    pub up: cgmath::Vector3<f32>,
+}
+
+impl CameraPose {
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            eye: camera.eye,
+            target: camera.target,
+            up: camera.up,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.eye = self.eye;
+        camera.target = self.target;
+        camera.up = self.up;
+    }
+
+    /// Moves part of the way toward `goal`. Pan moves the target, orbit turns the
+    /// view direction around it and zoom changes the distance, each with its own rate.
+    pub fn damp_toward(&self, goal: &CameraPose, smoothing: &Smoothing, dt: f32) -> Self {
+        let pan = Smoothing::factor(smoothing.pan, dt);
+        let orbit = Smoothing::factor(smoothing.orbit, dt);
+        let zoom = Smoothing::factor(smoothing.zoom, dt);
+
+        let target = self.target + (goal.target - self.target) * pan;
+
+        let offset = self.eye - self.target;
+        let goal_offset = goal.eye - goal.target;
+        let distance = offset.magnitude() + (goal_offset.magnitude() - offset.magnitude()) * zoom;
+        let direction = nlerp(offset.normalize(), goal_offset.normalize(), orbit);
+        let up = nlerp(self.up, goal.up, orbit);
+
+        Self {
+            eye: target + direction * distance,
+            target,
+            up,
+        }
+    }
+}
+
+/// Normalized linear interpolation, falling back to `b` when the directions cancel out.
+fn nlerp(a: cgmath::Vector3<f32>, b: cgmath::Vector3<f32>, t: f32) -> cgmath::Vector3<f32> {
+    let v = a + (b - a) * t;
+    if v.magnitude2() < 1e-8 || !v.x.is_finite() {
+        b
+    } else {
+        v.normalize()
+    }
+}
+
+/// Exponential damping rates in 1/s. Higher is snappier, 0 follows the input immediately.
+#[derive(Debug, Clone, Copy)]
+pub struct Smoothing {
+    pub orbit: f32,
+    pub pan: f32,
+    pub zoom: f32,
+}
+
+impl Smoothing {
+    pub const NONE: Smoothing = Smoothing {
+        orbit: 0.,
+        pan: 0.,
+        zoom: 0.,
+    };
+
+    fn factor(rate: f32, dt: f32) -> f32 {
+        if rate <= 0. {
+            1.
+        } else {
+            1. - f32::exp(-rate * dt)
+        }
+    }
+}
+
+impl Default for Smoothing {
+    fn default() -> Self {
+        Self {
+            orbit: 15.,
+            pan: 15.,
+            zoom: 10.,
+        }
+    }
+}
+
+/// What the keys, mouse buttons, wheel and touches have held since the last update, for a
+/// `CameraRig` to move the camera by.
+pub struct CameraInput {
+    pub is_up_pressed: bool,
+    pub is_down_pressed: bool,
+    pub is_forward_pressed: bool,
+    pub is_backward_pressed: bool,
+    pub is_move_left_pressed: bool,
+    pub is_move_right_pressed: bool,
+    pub is_move_up_pressed: bool,
+    pub is_move_down_pressed: bool,
+    pub is_left_pressed: bool,
+    pub is_right_pressed: bool,
+    pub is_middle_pressed: bool,
+    pub is_shift_pressed: bool,
+    pub is_camera_front_pressed: bool,
+    pub is_camera_right_pressed: bool,
+    pub is_camera_top_pressed: bool,
+    /// Numpad 5 was pressed since the last update, switching between perspective and
+    /// orthographic.
+    pub is_projection_toggled: bool,
     is_wheel_scrolled: bool,
-    is_shift_pressed: bool,
-    is_camera_front_pressed: bool,
-    is_camera_right_pressed: bool,
-    is_camera_top_pressed: bool,
     scroll: f32,
+    /// Point the next scroll zooms toward, set by the owner when zooming to the cursor.
+    pub zoom_anchor: Option<cgmath::Point3<f32>>,
     cursor_position_before: (f64, f64),
     cursor_position_current: (f64, f64),
+    /// Fingers on the screen by touch id. One finger orbits, two pinch to zoom.
+    touches: Vec<(u64, (f64, f64))>,
+    /// Distance between the two fingers at the last touch event.
+    pinch_distance: f64,
     pub size: PhysicalSize<u32>,
+    /// Physical pixels per logical one on the window's monitor. Positions are kept in physical
+    /// pixels, as picking takes them, and movements measured in logical ones, so dragging turns
+    /// the view as far on any monitor.
+    pub scale_factor: f64,
 }
 
-impl CameraController {
-    pub fn new(speed: f32, size: PhysicalSize<u32>) -> Self {
+impl CameraInput {
+    pub fn new(size: PhysicalSize<u32>, scale_factor: f64) -> Self {
         Self {
-            speed,
             is_up_pressed: false,
             is_down_pressed: false,
             is_move_left_pressed: false,
@@ -148,13 +274,19 @@ impl CameraController {
             is_camera_front_pressed: false,
             is_camera_right_pressed: false,
             is_camera_top_pressed: false,
+            is_projection_toggled: false,
             scroll: 0.,
+            zoom_anchor: None,
             cursor_position_before: (0., 0.),
             cursor_position_current: (0., 0.),
+            touches: Vec::new(),
+            pinch_distance: 0.,
             size,
+            scale_factor,
         }
     }
 
+    /// Keeps track of the event, returning whether it was for the camera.
     pub fn process_events(&mut self, event: &WindowEvent, size: PhysicalSize<u32>) -> bool {
         self.size = size;
         match event {
@@ -206,6 +338,10 @@ impl CameraController {
                         self.is_down_pressed = is_pressed;
                         true
                     }
+                    VirtualKeyCode::Numpad5 => {
+                        self.is_projection_toggled |= is_pressed;
+                        true
+                    }
                     VirtualKeyCode::Numpad4 => {
                         self.is_left_pressed = is_pressed;
                         true
@@ -251,162 +387,161 @@ impl CameraController {
                     _ => false,
                 }
             }
+            WindowEvent::Touch(touch) => {
+                self.process_touch(touch);
+                true
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.rescale(*scale_factor);
+                false
+            }
             _ => false,
         }
     }
 
-    pub fn update_camera(&mut self, camera: &mut Camera) {
-        let forward = camera.target - camera.eye;
-        let forward_norm = forward.normalize();
-        let forward_mag = forward.magnitude();
-
-        //if self.is_wheel_scrolled && self.scroll >= 0. && forward_mag > self.scroll {
-        if self.is_wheel_scrolled && self.scroll >= 0. {
-            camera.eye += forward / 10. * self.scroll;
-            self.is_wheel_scrolled = false;
-            self.scroll = 0.;
+    /// Moves the positions kept to where they are in physical pixels at the new scale factor,
+    /// since the cursor only reports its own once it moves again, and a drag would jump.
+    fn rescale(&mut self, scale_factor: f64) {
+        let ratio = scale_factor / self.scale_factor;
+        let positions = std::iter::once(&mut self.cursor_position_before)
+            .chain(std::iter::once(&mut self.cursor_position_current))
+            .chain(self.touches.iter_mut().map(|(_, position)| position));
+        for position in positions {
+            *position = (position.0 * ratio, position.1 * ratio);
         }
+        self.pinch_distance *= ratio;
+        self.scale_factor = scale_factor;
+    }
 
-        if self.is_wheel_scrolled && self.scroll < 0. {
-            //camera.eye += forward_norm * self.scroll;
-            camera.eye += forward / 10. * self.scroll;
-            self.is_wheel_scrolled = false;
-            self.scroll = 0.;
+    /// Logical pixels two fingers have to move apart for one scroll line of zoom.
+    const PINCH_PIXELS_PER_LINE: f64 = 40.;
+
+    /// Orbits while one finger drags, the way the middle mouse button does, and zooms while two
+    /// fingers pinch.
+    fn process_touch(&mut self, touch: &Touch) {
+        let location = (touch.location.x, touch.location.y);
+        match touch.phase {
+            TouchPhase::Started => self.touches.push((touch.id, location)),
+            TouchPhase::Moved => {
+                if let Some(finger) = self.touches.iter_mut().find(|(id, _)| *id == touch.id) {
+                    finger.1 = location;
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touches.retain(|(id, _)| *id != touch.id)
+            }
         }
 
-        if self.is_right_pressed {
-            let forward = camera.target - camera.eye;
-            let rotate =
-                quartanion_matrix(rotate_quartanion(-0.05, cgmath::Vector3::new(0., 1., 0.)));
-            camera.eye = camera.target - rotate * forward;
-            camera.up = rotate * camera.up;
-            camera.up = camera.up.normalize();
+        match self.touches[..] {
+            [(_, position)] => {
+                if !self.is_middle_pressed {
+                    // Starts the drag where the finger is instead of where the last one lifted.
+                    self.cursor_position_before = position;
+                }
+                self.cursor_position_current = position;
+                self.is_middle_pressed = true;
+            }
+            [(_, a), (_, b), ..] => {
+                self.is_middle_pressed = false;
+                let distance = ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+                if touch.phase == TouchPhase::Moved {
+                    let pinched = (distance - self.pinch_distance) / self.scale_factor;
+                    self.scroll += (pinched / Self::PINCH_PIXELS_PER_LINE) as f32;
+                    self.is_wheel_scrolled = true;
+                }
+                self.pinch_distance = distance;
+            }
+            [] => self.is_middle_pressed = false,
         }
+    }
 
-        if self.is_left_pressed {
-            let forward = camera.target - camera.eye;
-            let rotate =
-                quartanion_matrix(rotate_quartanion(0.05, cgmath::Vector3::new(0., 1., 0.)));
-            camera.eye = camera.target - rotate * forward;
-            camera.up = rotate * camera.up;
-            camera.up = camera.up.normalize();
+    /// Lines scrolled since the last update, if any, which no later call returns.
+    pub fn take_scroll(&mut self) -> Option<f32> {
+        if !std::mem::take(&mut self.is_wheel_scrolled) {
+            return None;
         }
+        Some(std::mem::take(&mut self.scroll))
+    }
 
-        if self.is_up_pressed {
-            const SENSITIVITY: f32 = 0.05;
-            let forward = camera.target - camera.eye;
-            let right = forward.normalize().cross(camera.up);
-            let v = rotate_quartanion(SENSITIVITY as f32, right);
-            let rotate = quartanion_matrix(v);
-            camera.eye = camera.target - rotate * forward;
-            camera.up = rotate * camera.up;
-            camera.up = camera.up.normalize();
-        }
+    /// Logical pixels the cursor, or the finger orbiting, moved since the last update.
+    pub fn cursor_delta(&self) -> (f64, f64) {
+        (
+            (self.cursor_position_current.0 - self.cursor_position_before.0) / self.scale_factor,
+            (self.cursor_position_current.1 - self.cursor_position_before.1) / self.scale_factor,
+        )
+    }
 
-        if self.is_down_pressed {
-            const SENSITIVITY: f32 = 0.05;
-            let forward = camera.target - camera.eye;
-            let right = forward.normalize().cross(camera.up);
-            let v = rotate_quartanion(-SENSITIVITY as f32, right);
-            let rotate = quartanion_matrix(v);
-            camera.eye = camera.target - rotate * forward;
-            camera.up = rotate * camera.up;
-            camera.up = camera.up.normalize();
-        }
+    /// The cursor position in physical pixels.
+    pub fn cursor_position(&self) -> (f64, f64) {
+        self.cursor_position_current
+    }
+}
 
-        if self.is_camera_front_pressed {
-            let forward = camera.target - camera.eye;
-            let forward_mag = forward.magnitude();
-            camera.eye = cgmath::Point3::new(0., 0., -forward_mag);
-            camera.target = cgmath::Point3::new(0., 0., 0.);
-            camera.up = cgmath::Vector3::new(0., 1., 0.);
-        }
+/// Moves the viewport's camera by the input, the way its rig does, easing it there.
+pub struct CameraController {
+    speed: f32,
+    pub smoothing: Smoothing,
+    /// Pose the input has asked for; the camera eases toward it.
+    goal: Option<CameraPose>,
+    /// Zoom toward the point under the cursor instead of the camera target.
+    pub zoom_to_cursor: bool,
+    pub input: CameraInput,
+    rig: Box<dyn CameraRig>,
+}
 
-        if self.is_camera_right_pressed {
-            let forward = camera.target - camera.eye;
-            let forward_mag = forward.magnitude();
-            camera.eye = cgmath::Point3::new(-forward_mag, 0., 0.);
-            camera.target = cgmath::Point3::new(0., 0., 0.);
-            camera.up = cgmath::Vector3::new(0., 2., 0.);
+impl CameraController {
+    pub fn new(speed: f32, size: PhysicalSize<u32>, scale_factor: f64) -> Self {
+        Self {
+            speed,
+            smoothing: Smoothing::default(),
+            goal: None,
+            zoom_to_cursor: true,
+            input: CameraInput::new(size, scale_factor),
+            rig: CameraScheme::default().rig(),
         }
+    }
 
-        if self.is_camera_top_pressed {
-            let forward = camera.target - camera.eye;
-            let forward_mag = forward.magnitude();
-            camera.eye = cgmath::Point3::new(0., forward_mag, 0.);
-            camera.target = cgmath::Point3::new(0., 0., 0.);
-            camera.up = cgmath::Vector3::new(0., 0., 1.);
-        }
+    pub fn process_events(&mut self, event: &WindowEvent, size: PhysicalSize<u32>) -> bool {
+        self.input.process_events(event, size)
+    }
 
-        if self.is_forward_pressed {
-            const SENSITIVITY: f32 = 0.003;
-            let mag = forward.magnitude();
-            camera.eye += forward.normalize() *  mag * SENSITIVITY;
-            camera.target += forward.normalize() *  mag * SENSITIVITY;
-        }
-        if self.is_backward_pressed {
-            const SENSITIVITY: f32 = 0.003;
-            let mag = forward.magnitude();
-            camera.eye += -forward.normalize() *  mag * SENSITIVITY;
-            camera.target += -forward.normalize() *  mag * SENSITIVITY;
-        }
-        if self.is_move_left_pressed {
-            const SENSITIVITY: f32 = 0.003;
-            let right = forward.normalize().cross(camera.up);
-            let mag = forward.magnitude();
-            camera.eye += -right *  mag * SENSITIVITY;
-            camera.target += -right *  mag * SENSITIVITY;
-        }
-        if self.is_move_right_pressed {
-            const SENSITIVITY: f32 = 0.003;
-            let right = forward.normalize().cross(camera.up);
-            let mag = forward.magnitude();
-            camera.eye += right *  mag * SENSITIVITY;
-            camera.target += right *  mag * SENSITIVITY;
-        }
-        if self.is_move_up_pressed {
-            const SENSITIVITY: f32 = 0.003;
-            let mag = forward.magnitude();
-            camera.eye += camera.up * mag * SENSITIVITY;
-            camera.target += camera.up * mag * SENSITIVITY;
+    /// Navigates with another rig from the next update on.
+    pub fn set_rig(&mut self, rig: Box<dyn CameraRig>) {
+        self.rig = rig;
+        self.reset();
+    }
+
+    pub fn set_scheme(&mut self, scheme: CameraScheme) {
+        self.set_rig(scheme.rig());
+    }
+
+    pub fn rig_name(&self) -> &str {
+        self.rig.name()
+    }
+
+    /// Moves the camera by the input held during the last `dt` seconds.
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        if std::mem::take(&mut self.input.is_projection_toggled) {
+            camera.orthographic = !camera.orthographic;
         }
-        if self.is_move_down_pressed {
-            const SENSITIVITY: f32 = 0.003;
-            let mag = forward.magnitude();
-            camera.eye += -camera.up * mag * SENSITIVITY;
-            camera.target += -camera.up * mag * SENSITIVITY;
+        let current = CameraPose::from_camera(camera);
+        if let Some(goal) = self.goal {
+            goal.apply(camera);
         }
+        self.rig.apply_input(&mut self.input, camera, dt);
+        self.input.cursor_position_before = self.input.cursor_position_current;
+        let goal = CameraPose::from_camera(camera);
+        self.goal = Some(goal);
+        current.damp_toward(&goal, &self.smoothing, dt).apply(camera);
+    }
 
-        if self.is_middle_pressed {
-            let cursor_diff = (
-                self.cursor_position_current.0 - self.cursor_position_before.0,
-                self.cursor_position_current.1 - self.cursor_position_before.1,
-            );
-            const SENSITIVITY: f32 = 0.003;
-            if self.is_shift_pressed {
-                let right = forward.normalize().cross(camera.up);
-                let mag = forward.magnitude();
-                camera.eye += -right * 2. * mag * cursor_diff.0 as f32 / self.size.width as f32 * f32::tan(camera.projection.fovy.0);
-                camera.eye += camera.up * 2. * mag * cursor_diff.1 as f32 / self.size.height as f32* f32::tan(camera.projection.fovy.0);
-                camera.target += -right * 2. * mag * cursor_diff.0 as f32 / self.size.width as f32* f32::tan(camera.projection.fovy.0);
-                camera.target += camera.up * 2. * mag * cursor_diff.1 as f32 / self.size.height as f32* f32::tan(camera.projection.fovy.0);
-            } else {
-                let forward = camera.target - camera.eye;
-                let right = forward.normalize().cross(camera.up);
-                let a = rotate_quartanion(-SENSITIVITY * cursor_diff.1 as f32, right);
-                let b = rotate_quartanion(
-                    SENSITIVITY * cursor_diff.0 as f32,
-                    cgmath::Vector3::new(0., 1., 0.),
-                );
-                let v = mult_quartanion(a, b);
-                let rotate = quartanion_matrix(v);
-                camera.eye = camera.target - rotate * forward;
-                camera.up = rotate * camera.up;
-                camera.up = camera.up.normalize();
-            }
-        }
+    pub fn cursor_position(&self) -> (f64, f64) {
+        self.input.cursor_position()
+    }
 
-        self.cursor_position_before = self.cursor_position_current;
+    /// Forgets the pending goal, e.g. after the camera was moved from outside the controller.
+    pub fn reset(&mut self) {
+        self.goal = None;
     }
 }
 
@@ -0,0 +1,334 @@
+//! How the input moves the viewport's camera. The `CameraController` keeps track of the keys,
+//! mouse and touches and eases the camera toward where its rig puts it, so another way of
+//! navigating is another `CameraRig`, switched to with `CameraController::set_rig`.
+//!
+//! The rigs here are the Blender-like orbit, a first-person fly camera and a turntable orbiting
+//! with the world's y axis kept up.
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use crate::camera::{mult_quartanion, quartanion_matrix, rotate_quartanion, Camera, CameraInput};
+
+/// Moves the camera by the input held during the last update.
+pub trait CameraRig {
+    fn name(&self) -> &str;
+
+    /// Moves the camera by the input held during the last `dt` seconds, taking the scroll.
+    fn apply_input(&mut self, input: &mut CameraInput, camera: &mut Camera, dt: f32);
+}
+
+/// The rigs to choose from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraScheme {
+    Orbit,
+    Fly,
+    Turntable,
+}
+
+impl CameraScheme {
+    pub const ALL: [CameraScheme; 3] = [
+        CameraScheme::Orbit,
+        CameraScheme::Fly,
+        CameraScheme::Turntable,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CameraScheme::Orbit => "Orbit",
+            CameraScheme::Fly => "Fly",
+            CameraScheme::Turntable => "Turntable",
+        }
+    }
+
+    pub fn rig(self) -> Box<dyn CameraRig> {
+        match self {
+            CameraScheme::Orbit => Box::new(OrbitRig),
+            CameraScheme::Fly => Box::new(FlyRig::default()),
+            CameraScheme::Turntable => Box::new(TurntableRig),
+        }
+    }
+}
+
+impl Default for CameraScheme {
+    fn default() -> Self {
+        CameraScheme::Orbit
+    }
+}
+
+/// Rotation speed of the numpad orbit keys in radians per second.
+const ROTATION_SPEED: f32 = 3.0;
+/// Radians the view turns per logical pixel the cursor moves.
+const SENSITIVITY: f32 = 0.003;
+/// How close to straight up or down the fly and turntable cameras look, in radians.
+const MAX_PITCH: f32 = 1.5;
+
+/// Moves the eye toward the target by the scroll, or along the ray to the zoom anchor when
+/// there is one, which keeps the point under the cursor in place.
+fn zoom(input: &mut CameraInput, camera: &mut Camera) {
+    let scroll = match input.take_scroll() {
+        Some(scroll) => scroll,
+        None => return,
+    };
+    match input.zoom_anchor.take() {
+        Some(anchor) => {
+            let step = (anchor - camera.eye) * (scroll / 10.).min(0.9);
+            camera.eye += step;
+            camera.target += step;
+        }
+        None => camera.eye += (camera.target - camera.eye) / 10. * scroll,
+    }
+}
+
+/// Moves the eye and target across the view by the cursor's movement.
+fn pan(input: &CameraInput, camera: &mut Camera) {
+    let (dx, dy) = input.cursor_delta();
+    let forward = camera.target - camera.eye;
+    let right = forward.normalize().cross(camera.up).normalize();
+    let up = right.cross(forward.normalize());
+    let scale = 2. * forward.magnitude() * f32::tan(camera.projection.fovy.0);
+    let size = input.size.to_logical::<f32>(input.scale_factor);
+    let step = -right * scale * dx as f32 / size.width + up * scale * dy as f32 / size.height;
+    camera.eye += step;
+    camera.target += step;
+}
+
+/// `forward` turned by `yaw` around the y axis and `pitch` up, the pitch left out where it
+/// would tip the direction over the top. A direction straight up or down is first tilted
+/// toward the side `hint` points to.
+fn turn_level(forward: Vector3<f32>, hint: Vector3<f32>, yaw: f32, pitch: f32) -> Vector3<f32> {
+    let mut horizontal = Vector3::new(forward.x, 0., forward.z);
+    if horizontal.magnitude2() < 1e-8 {
+        // Looking down, the top of the view points the way the camera faces.
+        horizontal = Vector3::new(hint.x, 0., hint.z) * -forward.y.signum();
+        if horizontal.magnitude2() < 1e-8 {
+            horizontal = Vector3::unit_z();
+        }
+    }
+    let elevation = forward.y.atan2(horizontal.magnitude()) + pitch;
+    let elevation = elevation.max(-MAX_PITCH).min(MAX_PITCH);
+    let yaw = quartanion_matrix(rotate_quartanion(yaw, Vector3::unit_y()));
+    let horizontal = yaw * horizontal.normalize();
+    horizontal * elevation.cos() + Vector3::unit_y() * elevation.sin()
+}
+
+/// The yaw and pitch the numpad orbit keys ask for, in radians.
+fn key_turn(input: &CameraInput, dt: f32) -> (f32, f32) {
+    let mut yaw = 0.;
+    let mut pitch = 0.;
+    if input.is_left_pressed {
+        yaw += ROTATION_SPEED * dt;
+    }
+    if input.is_right_pressed {
+        yaw -= ROTATION_SPEED * dt;
+    }
+    if input.is_up_pressed {
+        pitch -= ROTATION_SPEED * dt;
+    }
+    if input.is_down_pressed {
+        pitch += ROTATION_SPEED * dt;
+    }
+    (yaw, pitch)
+}
+
+/// Orbits the target freely with the middle mouse button or the numpad, pans with Shift held,
+/// zooms with the wheel and flies with WASD, Q and E. Numpad 1, 3 and 7 look from the front,
+/// the right and the top.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OrbitRig;
+
+impl OrbitRig {
+    /// Movement speed of the fly keys, as a fraction of the distance to the target per second.
+    const MOVE_SPEED: f32 = 0.18;
+
+    /// Turns the eye and up direction around the target by the rotation.
+    fn rotate(camera: &mut Camera, rotation: cgmath::Vector4<f32>) {
+        let forward = camera.target - camera.eye;
+        let rotate = quartanion_matrix(rotation);
+        camera.eye = camera.target - rotate * forward;
+        camera.up = (rotate * camera.up).normalize();
+    }
+}
+
+impl CameraRig for OrbitRig {
+    fn name(&self) -> &str {
+        CameraScheme::Orbit.name()
+    }
+
+    fn apply_input(&mut self, input: &mut CameraInput, camera: &mut Camera, dt: f32) {
+        let forward = camera.target - camera.eye;
+        zoom(input, camera);
+
+        let angle = ROTATION_SPEED * dt;
+        if input.is_right_pressed {
+            Self::rotate(camera, rotate_quartanion(-angle, Vector3::unit_y()));
+        }
+        if input.is_left_pressed {
+            Self::rotate(camera, rotate_quartanion(angle, Vector3::unit_y()));
+        }
+        if input.is_up_pressed {
+            let right = (camera.target - camera.eye).normalize().cross(camera.up);
+            Self::rotate(camera, rotate_quartanion(angle, right));
+        }
+        if input.is_down_pressed {
+            let right = (camera.target - camera.eye).normalize().cross(camera.up);
+            Self::rotate(camera, rotate_quartanion(-angle, right));
+        }
+
+        let distance = (camera.target - camera.eye).magnitude();
+        let views = [
+            (
+                input.is_camera_front_pressed,
+                (0., 0., -distance),
+                Vector3::unit_y(),
+            ),
+            (
+                input.is_camera_right_pressed,
+                (-distance, 0., 0.),
+                Vector3::unit_y(),
+            ),
+            (
+                input.is_camera_top_pressed,
+                (0., distance, 0.),
+                Vector3::unit_z(),
+            ),
+        ];
+        for &(pressed, eye, up) in views.iter() {
+            if pressed {
+                camera.eye = eye.into();
+                camera.target = Point3::new(0., 0., 0.);
+                camera.up = up;
+            }
+        }
+
+        let step = Self::MOVE_SPEED * dt * forward.magnitude();
+        let right = forward.normalize().cross(camera.up);
+        let moves = [
+            (input.is_forward_pressed, forward.normalize()),
+            (input.is_backward_pressed, -forward.normalize()),
+            (input.is_move_left_pressed, -right),
+            (input.is_move_right_pressed, right),
+            (input.is_move_up_pressed, camera.up),
+            (input.is_move_down_pressed, -camera.up),
+        ];
+        for &(pressed, direction) in moves.iter() {
+            if pressed {
+                camera.eye += direction * step;
+                camera.target += direction * step;
+            }
+        }
+
+        if input.is_middle_pressed {
+            if input.is_shift_pressed {
+                pan(input, camera);
+            } else {
+                let (dx, dy) = input.cursor_delta();
+                let forward = camera.target - camera.eye;
+                let right = forward.normalize().cross(camera.up);
+                let a = rotate_quartanion(-SENSITIVITY * dy as f32, right);
+                let b = rotate_quartanion(SENSITIVITY * dx as f32, Vector3::unit_y());
+                Self::rotate(camera, mult_quartanion(a, b));
+            }
+        }
+    }
+}
+
+/// Looks around from the eye with the middle mouse button or the numpad and moves with WASD,
+/// Q and E like a first-person game, faster with Shift held. The wheel changes the speed.
+#[derive(Debug, Clone, Copy)]
+pub struct FlyRig {
+    /// Units moved per second.
+    pub speed: f32,
+}
+
+impl Default for FlyRig {
+    fn default() -> Self {
+        Self { speed: 2. }
+    }
+}
+
+impl FlyRig {
+    const FAST: f32 = 4.;
+    /// Speed change per scroll line.
+    const SPEED_STEP: f32 = 1.2;
+}
+
+impl CameraRig for FlyRig {
+    fn name(&self) -> &str {
+        CameraScheme::Fly.name()
+    }
+
+    fn apply_input(&mut self, input: &mut CameraInput, camera: &mut Camera, dt: f32) {
+        if let Some(scroll) = input.take_scroll() {
+            self.speed = (self.speed * Self::SPEED_STEP.powf(scroll))
+                .max(0.01)
+                .min(1000.);
+        }
+        input.zoom_anchor = None;
+
+        // The target stays as far ahead, for when the camera orbits again.
+        let offset = camera.target - camera.eye;
+        let distance = offset.magnitude().max(1e-3);
+        // The keys turn the view the way they point, the mouse drags it like in a game.
+        let (yaw, pitch) = key_turn(input, dt);
+        let (mut yaw, mut pitch) = (-yaw, -pitch);
+        if input.is_middle_pressed {
+            let (dx, dy) = input.cursor_delta();
+            yaw += SENSITIVITY * dx as f32;
+            pitch -= SENSITIVITY * dy as f32;
+        }
+        let forward = turn_level(offset.normalize(), camera.up, yaw, pitch);
+        let up = Vector3::unit_y();
+        let right = forward.cross(up).normalize();
+
+        let mut movement = Vector3::new(0., 0., 0.);
+        let moves = [
+            (input.is_forward_pressed, forward),
+            (input.is_backward_pressed, -forward),
+            (input.is_move_left_pressed, -right),
+            (input.is_move_right_pressed, right),
+            (input.is_move_up_pressed, up),
+            (input.is_move_down_pressed, -up),
+        ];
+        for &(pressed, direction) in moves.iter() {
+            if pressed {
+                movement += direction;
+            }
+        }
+        let speed = if input.is_shift_pressed {
+            self.speed * Self::FAST
+        } else {
+            self.speed
+        };
+        camera.eye += movement * speed * dt;
+        camera.target = camera.eye + forward * distance;
+        camera.up = up;
+    }
+}
+
+/// Orbits the target with the y axis kept up and the view kept from tipping over the top,
+/// with the middle mouse button or the numpad. Pans with Shift held and zooms with the wheel.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TurntableRig;
+
+impl CameraRig for TurntableRig {
+    fn name(&self) -> &str {
+        CameraScheme::Turntable.name()
+    }
+
+    fn apply_input(&mut self, input: &mut CameraInput, camera: &mut Camera, dt: f32) {
+        zoom(input, camera);
+        let (mut yaw, mut pitch) = key_turn(input, dt);
+        if input.is_middle_pressed && !input.is_shift_pressed {
+            let (dx, dy) = input.cursor_delta();
+            yaw += SENSITIVITY * dx as f32;
+            pitch += SENSITIVITY * dy as f32;
+        }
+        let offset = camera.target - camera.eye;
+        let forward = turn_level(offset.normalize(), camera.up, yaw, pitch);
+        camera.eye = camera.target - forward * offset.magnitude();
+        camera.up = Vector3::unit_y();
+        if input.is_middle_pressed && input.is_shift_pressed {
+            pan(input, camera);
+        }
+    }
+}
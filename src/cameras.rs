@@ -0,0 +1,178 @@
+//! Cameras placed in the scene besides the viewport's own, named and saved with the project.
+//! The viewport can look through one of them, which moves it along while navigating, and
+//! frames can be rendered through any of them. The others are drawn as frustums.
+
+use cgmath::{InnerSpace, Point3, Rad};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gizmos::{GizmoObject, Gizmos},
+    headless::CameraDesc,
+    scene::Scene,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneCamera {
+    pub name: String,
+    pub view: CameraDesc,
+}
+
+#[derive(Debug, Clone)]
+pub struct SceneCameras {
+    pub cameras: Vec<SceneCamera>,
+    /// The camera the viewport looks through, by index.
+    pub looking_through: Option<usize>,
+    /// The viewport's own view, put back when it stops looking through a camera.
+    viewport: Option<CameraDesc>,
+    /// Length of the drawn frustums.
+    pub gizmo_size: f32,
+}
+
+impl Default for SceneCameras {
+    fn default() -> Self {
+        Self {
+            cameras: Vec::new(),
+            looking_through: None,
+            viewport: None,
+            gizmo_size: 1.,
+        }
+    }
+}
+
+impl SceneCameras {
+    const COLOR: [f32; 3] = [0.8, 0.8, 0.8];
+
+    /// A name no camera has yet.
+    pub fn next_name(&self) -> String {
+        (1..)
+            .map(|i| format!("Camera {}", i))
+            .find(|name| self.cameras.iter().all(|camera| camera.name != *name))
+            .unwrap()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&SceneCamera> {
+        self.cameras.iter().find(|camera| camera.name == name)
+    }
+}
+
+impl Scene {
+    /// Adds a camera seeing what the viewport sees.
+    pub fn add_camera(&mut self) -> usize {
+        let name = self.cameras.next_name();
+        self.cameras.cameras.push(SceneCamera {
+            name,
+            view: CameraDesc::from_camera(&self.camera),
+        });
+        self.cameras.cameras.len() - 1
+    }
+
+    pub fn remove_camera(&mut self, index: usize) {
+        if self.cameras.looking_through == Some(index) {
+            self.look_through(None);
+        }
+        self.cameras.cameras.remove(index);
+        if let Some(through) = &mut self.cameras.looking_through {
+            if *through > index {
+                *through -= 1;
+            }
+        }
+        let selected = std::mem::take(&mut self.gizmos.selected);
+        self.gizmos.selected = selected
+            .into_iter()
+            .filter_map(|object| match object {
+                GizmoObject::Camera(i) if i == index => None,
+                GizmoObject::Camera(i) if i > index => Some(GizmoObject::Camera(i - 1)),
+                object => Some(object),
+            })
+            .collect();
+    }
+
+    /// Makes the viewport look through the camera, or with `None` go back to its own view.
+    pub fn look_through(&mut self, index: Option<usize>) {
+        let view = match index.and_then(|i| self.cameras.cameras.get(i)) {
+            Some(camera) => camera.view.clone(),
+            None => {
+                self.cameras.looking_through = None;
+                if let Some(viewport) = self.cameras.viewport.take() {
+                    viewport.apply(&mut self.camera);
+                }
+                return;
+            }
+        };
+        if self.cameras.looking_through.is_none() {
+            self.cameras.viewport = Some(CameraDesc::from_camera(&self.camera));
+        }
+        self.cameras.looking_through = index;
+        view.apply(&mut self.camera);
+    }
+
+    /// Points the renderer through the camera for a frame, until `restore_view` is called
+    /// with what this returns. The frame has none of the lines drawn over the viewport, the
+    /// next update draws them again.
+    pub fn view_through(&mut self, queue: &wgpu::Queue, index: usize) -> Option<CameraDesc> {
+        let view = self.cameras.cameras.get(index)?.view.clone();
+        Some(self.view_from(queue, &view))
+    }
+
+    /// Points the renderer through the view for a frame, like `view_through`.
+    pub fn view_from(&mut self, queue: &wgpu::Queue, view: &CameraDesc) -> CameraDesc {
+        let previous = CameraDesc::from_camera(&self.camera);
+        view.apply(&mut self.camera);
+        self.renderer.debug_lines.clear();
+        self.renderer.update(queue, &self.camera);
+        self.lights.update_clusters(queue, &self.camera);
+        previous
+    }
+
+    pub fn restore_view(&mut self, queue: &wgpu::Queue, view: CameraDesc) {
+        view.apply(&mut self.camera);
+        self.renderer.update(queue, &self.camera);
+        self.lights.update_clusters(queue, &self.camera);
+    }
+
+    /// Keeps the camera looked through where the viewport has been moved to.
+    pub(crate) fn follow_viewport(&mut self) {
+        if let Some(i) = self.cameras.looking_through {
+            self.cameras.cameras[i].view = CameraDesc::from_camera(&self.camera);
+        }
+    }
+
+    /// Draws the cameras not looked through as pyramids from their eye to a rectangle of the
+    /// viewport's shape, with a triangle on top of it pointing up.
+    pub(crate) fn draw_cameras(&mut self) {
+        let aspect = self.camera.projection.aspect;
+        let length = self.cameras.gizmo_size;
+        let lines = &mut self.renderer.debug_lines;
+        for (i, camera) in self.cameras.cameras.iter().enumerate() {
+            if self.cameras.looking_through == Some(i) {
+                continue;
+            }
+            let color = if self.gizmos.selected.contains(&GizmoObject::Camera(i)) {
+                Gizmos::SELECTED_COLOR
+            } else {
+                SceneCameras::COLOR
+            };
+            let view = &camera.view;
+            let eye = Point3::from(view.eye);
+            let forward = (Point3::from(view.target) - eye).normalize();
+            let right = forward.cross(view.up.into()).normalize();
+            let up = right.cross(forward);
+            let half_height = length * (Rad::from(cgmath::Deg(view.fovy)).0 / 2.).tan();
+            let (x, y) = (right * half_height * aspect, up * half_height);
+            let center = eye + forward * length;
+            let corners = [
+                center - x - y,
+                center + x - y,
+                center + x + y,
+                center - x + y,
+            ];
+            for (j, &corner) in corners.iter().enumerate() {
+                lines.line(eye, corner, color);
+                lines.line(corner, corners[(j + 1) % 4], color);
+            }
+            let top = center + y * 1.5;
+            lines.line(corners[2] - x * 0.5, top, color);
+            lines.line(top, corners[3] + x * 0.5, color);
+        }
+    }
+}
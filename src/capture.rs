@@ -0,0 +1,163 @@
+use anyhow::*;
+use std::{num::NonZeroU32, path::PathBuf};
+
+use crate::gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked};
+
+/// A file name for a frame captured now, e.g. `screenshot-20211120-153012.png`.
+pub fn screenshot_name() -> PathBuf {
+    let time = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    PathBuf::from(format!("screenshot-{}.png", time))
+}
+
+/// An offscreen color target which can be copied back to the CPU.
+#[derive(Debug)]
+pub struct Capture {
+    pub texture: Tracked<wgpu::Texture>,
+    pub view: wgpu::TextureView,
+    pub size: wgpu::Extent3d,
+    pub format: wgpu::TextureFormat,
+    readback: Readback,
+}
+
+impl Capture {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        let readback = Readback::new(device, width, height, format);
+        let size = readback.size;
+        let texture = device.create_tracked_texture(GpuCategory::Targets, &wgpu::TextureDescriptor {
+            label: Some("capture_texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            size,
+            format,
+            readback,
+        }
+    }
+
+    pub fn copy_to_buffer(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.readback.copy_from(encoder, &self.texture);
+    }
+
+    /// Maps the buffer filled by `copy_to_buffer` and converts it to an RGBA image.
+    /// The copy has to be submitted before calling this.
+    pub async fn read(&self, device: &wgpu::Device) -> Result<image::RgbaImage> {
+        self.readback.read(device).await
+    }
+}
+
+/// A buffer a color texture of 8 bits per channel is copied into, to be read on the CPU.
+#[derive(Debug)]
+pub struct Readback {
+    pub size: wgpu::Extent3d,
+    pub format: wgpu::TextureFormat,
+    buffer: Tracked<wgpu::Buffer>,
+    padded_bytes_per_row: u32,
+}
+
+impl Readback {
+    const BYTES_PER_PIXEL: u32 = 4;
+
+    /// Whether textures of the format can be read back as RGBA images.
+    pub fn supports(format: wgpu::TextureFormat) -> bool {
+        matches!(
+            format,
+            wgpu::TextureFormat::Rgba8Unorm
+                | wgpu::TextureFormat::Rgba8UnormSrgb
+                | wgpu::TextureFormat::Bgra8Unorm
+                | wgpu::TextureFormat::Bgra8UnormSrgb
+        )
+    }
+
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        // Rows copied into a buffer have to be aligned to 256 bytes.
+        let unpadded_bytes_per_row = width * Self::BYTES_PER_PIXEL;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (unpadded_bytes_per_row + align - 1) / align * align;
+        let buffer = device.create_tracked_buffer(GpuCategory::Other, &wgpu::BufferDescriptor {
+            label: Some("capture_buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            size,
+            format,
+            buffer,
+            padded_bytes_per_row,
+        }
+    }
+
+    /// Copies the texture, which must be of the size and format, into the buffer.
+    pub fn copy_from(&self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(self.padded_bytes_per_row),
+                    rows_per_image: NonZeroU32::new(self.size.height),
+                },
+            },
+            self.size,
+        );
+    }
+
+    /// Maps the buffer filled by `copy_from` and converts it to an RGBA image.
+    /// The copy has to be submitted before calling this.
+    pub async fn read(&self, device: &wgpu::Device) -> Result<image::RgbaImage> {
+        let mapping = self.buffer.slice(..).map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        mapping.await.context("Failed to map capture buffer")?;
+        self.mapped_image()
+    }
+
+    /// Like `read`, for the web, where the browser maps the buffer without being polled.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn into_image(self) -> Result<image::RgbaImage> {
+        let mapping = self.buffer.slice(..).map_async(wgpu::MapMode::Read);
+        mapping.await.context("Failed to map capture buffer")?;
+        self.mapped_image()
+    }
+
+    fn mapped_image(&self) -> Result<image::RgbaImage> {
+        let slice = self.buffer.slice(..);
+        let width = self.size.width;
+        let height = self.size.height;
+        let unpadded_bytes_per_row = (width * Self::BYTES_PER_PIXEL) as usize;
+        let mut pixels = Vec::with_capacity(unpadded_bytes_per_row * height as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(self.padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row]);
+            }
+        }
+        self.buffer.unmap();
+
+        if let wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb = self.format {
+            pixels.chunks_mut(4).for_each(|p| p.swap(0, 2));
+        }
+
+        image::RgbaImage::from_raw(width, height, pixels).context("Capture size mismatch")
+    }
+}
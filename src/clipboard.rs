@@ -0,0 +1,237 @@
+//! Copying models through the system clipboard as text, so they can be pasted into the same
+//! or another running instance.
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    loading::{LoadedMaterial, LoadedMesh, LoadedModel, Pixels},
+    model::{compute_tangents, Model, ModelVertex},
+    outliner::ObjectFlags,
+    scene::Scene,
+    vertex_layout::VertexData,
+};
+
+/// First line of copied models, telling them apart from any other text on the clipboard.
+const HEADER: &str = "modeling clipboard 1";
+
+/// Models with more vertices than this are copied as a reference to their file, which would
+/// make for megabytes of text otherwise.
+const EMBED_LIMIT: usize = 200_000;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CopiedMesh {
+    pub name: String,
+    pub material: String,
+    pub positions: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+    /// Extra vertex attributes, missing in text copied by older versions.
+    #[serde(default)]
+    pub attributes: Option<VertexData>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CopiedGeometry {
+    /// The meshes as they are in the scene, edits included.
+    Embedded(Vec<CopiedMesh>),
+    /// The path or URL the model was loaded from, loaded again when pasted.
+    Source(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CopiedModel {
+    /// Where the model was loaded from, to find its materials when pasted into the same
+    /// instance.
+    pub source: Option<String>,
+    /// Name of the model's layer, pasted onto the layer of the same name if there is one.
+    pub layer: String,
+    pub flags: ObjectFlags,
+    pub geometry: CopiedGeometry,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Clipboard {
+    pub models: Vec<CopiedModel>,
+}
+
+impl Clipboard {
+    /// The selected models of the scene.
+    pub fn copy(scene: &Scene) -> Self {
+        let models = scene
+            .outliner
+            .selected
+            .iter()
+            .filter_map(|&model| Some((model, scene.models.get(model)?)))
+            .map(|(model, meshes)| {
+                let source = scene.sources.get(&model).cloned();
+                let vertices: usize = meshes.meshes().iter().map(|m| m.vertices.len()).sum();
+                let geometry = match &source {
+                    Some(source) if vertices > EMBED_LIMIT => {
+                        CopiedGeometry::Source(source.clone())
+                    }
+                    _ => CopiedGeometry::Embedded(Self::copy_meshes(meshes)),
+                };
+                let layer = scene.outliner.layer_of(model);
+                CopiedModel {
+                    source,
+                    layer: scene.outliner.layers[layer].name.clone(),
+                    flags: scene.outliner.flags(model),
+                    geometry,
+                }
+            })
+            .collect();
+        Self { models }
+    }
+
+    fn copy_meshes(model: &Model) -> Vec<CopiedMesh> {
+        model
+            .meshes()
+            .iter()
+            .map(|mesh| CopiedMesh {
+                name: mesh.name.clone(),
+                material: mesh.material.name.clone(),
+                positions: mesh.vertices.iter().map(|v| v.position).collect(),
+                tex_coords: mesh.vertices.iter().map(|v| v.tex_coords).collect(),
+                normals: mesh.vertices.iter().map(|v| v.normal).collect(),
+                indices: mesh.indices.clone(),
+                attributes: mesh.extra.as_ref().map(|extra| extra.data.clone()),
+            })
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+
+    pub fn to_text(&self) -> Result<String> {
+        let ron = ron::ser::to_string(self)?;
+        Ok(format!("{}\n{}", HEADER, ron))
+    }
+
+    /// Reads copied models back, failing on any other text.
+    pub fn from_text(text: &str) -> Result<Self> {
+        let ron = text
+            .trim_start()
+            .strip_prefix(HEADER)
+            .context("The clipboard holds no models")?;
+        ron::de::from_str(ron).context("The models on the clipboard are broken")
+    }
+
+    /// Whether `text` looks like copied models, to leave other pasted text alone.
+    pub fn is_models(text: &str) -> bool {
+        text.trim_start().starts_with(HEADER)
+    }
+}
+
+impl CopiedMesh {
+    fn to_loaded(&self, material: usize) -> Result<LoadedMesh> {
+        let count = self.positions.len();
+        if self.tex_coords.len() != count || self.normals.len() != count {
+            bail!("Mesh {} has attributes of different lengths", self.name);
+        }
+        if self.attributes.as_ref().map_or(false, |a| a.len() != count) {
+            bail!("Mesh {} has extra attributes for other vertices", self.name);
+        }
+        if self.indices.iter().any(|&i| i as usize >= count) {
+            bail!("Mesh {} has indices past its vertices", self.name);
+        }
+        let mut vertices = (0..count)
+            .map(|i| ModelVertex {
+                position: self.positions[i],
+                tex_coords: self.tex_coords[i],
+                normal: self.normals[i],
+                tangent: [0.0; 3],
+                bitangent: [0.0; 3],
+            })
+            .collect::<Vec<_>>();
+        compute_tangents(&mut vertices, &self.indices);
+        Ok(LoadedMesh {
+            name: self.name.clone(),
+            vertices,
+            indices: self.indices.clone(),
+            material: Some(material),
+            attributes: self.attributes.clone(),
+        })
+    }
+}
+
+impl Scene {
+    /// Adds the copied models to the scene and selects them. Embedded models keep the
+    /// materials they had when pasted into the instance they were copied from, elsewhere they
+    /// are drawn in a plain color. Models copied as a reference are loaded like any other and
+    /// show up once decoded.
+    pub fn paste(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        clipboard: Clipboard,
+    ) -> Result<()> {
+        self.outliner.selected.clear();
+        for copied in clipboard.models {
+            let meshes = match &copied.geometry {
+                CopiedGeometry::Embedded(meshes) => meshes,
+                CopiedGeometry::Source(source) => {
+                    self.start_load(source.clone());
+                    continue;
+                }
+            };
+            let mut materials: Vec<String> = Vec::new();
+            let mut loaded_meshes = Vec::new();
+            for mesh in meshes.iter() {
+                let material = match materials.iter().position(|m| *m == mesh.material) {
+                    Some(i) => i,
+                    None => {
+                        materials.push(mesh.material.clone());
+                        materials.len() - 1
+                    }
+                };
+                loaded_meshes.push(mesh.to_loaded(material)?);
+            }
+            let loaded = LoadedModel {
+                // Not the original source, whose materials would be replaced by plain ones.
+                source: "clipboard".to_string(),
+                meshes: loaded_meshes,
+                materials: materials
+                    .iter()
+                    .map(|name| LoadedMaterial {
+                        name: name.clone(),
+                        diffuse: Pixels::Color([0xcc, 0xcc, 0xcc, 0xff]),
+                        normal: Pixels::Color([0x80, 0x80, 0xff, 0]),
+                        specular: Pixels::Color([0, 0, 0, 0xff]),
+                        occlusion: Pixels::Color([0xff; 4]),
+                        emissive: Pixels::Color([0, 0, 0, 0xff]),
+                        pbr: false,
+                    })
+                    .collect(),
+                nodes: Vec::new(),
+                animations: Vec::new(),
+                skins: Vec::new(),
+                warnings: Vec::new(),
+            };
+            let mut model = self.upload_model(device, queue, config, loaded);
+            if let Some(source) = &copied.source {
+                let known = self.materials.read().unwrap();
+                for (mesh, copied) in model.meshes_mut().iter_mut().zip(meshes.iter()) {
+                    if let Some(material) = known.by_name(&format!("{}:{}", source, copied.material)) {
+                        mesh.material = material.clone();
+                    }
+                }
+            }
+
+            let index = self.add_model(model).0;
+            let layer = self
+                .outliner
+                .layers
+                .iter()
+                .position(|layer| layer.name == copied.layer)
+                .unwrap_or(0);
+            self.outliner.set_layer(index, layer);
+            self.outliner.set_flags(index, copied.flags);
+            self.outliner.selected.insert(index);
+        }
+        Ok(())
+    }
+}
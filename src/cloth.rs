@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3, Zero};
+
+use crate::{
+    debug_lines::DebugLines,
+    model::{Mesh, ModelVertex},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClothSettings {
+    pub gravity: Vector3<f32>,
+    /// Fraction of the velocity lost every step.
+    pub damping: f32,
+    /// Constraint solver iterations per step. More iterations make the cloth less stretchy.
+    pub iterations: u32,
+    /// How much of the constraint error is corrected per iteration, from 0 to 1.
+    pub stiffness: f32,
+    /// Particles never go below this height.
+    pub floor: Option<f32>,
+}
+
+impl Default for ClothSettings {
+    fn default() -> Self {
+        Self {
+            gravity: Vector3::new(0.0, -9.81, 0.0),
+            damping: 0.01,
+            iterations: 8,
+            stiffness: 1.0,
+            floor: None,
+        }
+    }
+}
+
+/// Position based cloth simulation driving the vertices of one mesh.
+///
+/// Vertices sharing a position are welded into a single particle so the cloth doesn't tear
+/// along UV seams, and every triangle edge becomes a distance constraint.
+#[derive(Debug)]
+pub struct Cloth {
+    pub model: usize,
+    pub mesh: usize,
+    pub settings: ClothSettings,
+    pub running: bool,
+    rest: Vec<ModelVertex>,
+    indices: Vec<u32>,
+    /// Particle each vertex follows.
+    vertex_particle: Vec<usize>,
+    rest_positions: Vec<Vector3<f32>>,
+    positions: Vec<Vector3<f32>>,
+    previous: Vec<Vector3<f32>>,
+    /// Zero for pinned particles.
+    inverse_mass: Vec<f32>,
+    constraints: Vec<(usize, usize, f32)>,
+    dirty: bool,
+    detached: bool,
+}
+
+impl Cloth {
+    const PIN_COLOR: [f32; 3] = [1.0, 0.2, 0.2];
+
+    pub fn new(model: usize, mesh_index: usize, mesh: &Mesh) -> Self {
+        let mut welded = HashMap::new();
+        let mut rest_positions = Vec::new();
+        let vertex_particle = mesh
+            .vertices
+            .iter()
+            .map(|v| {
+                let key = v.position.map(f32::to_bits);
+                *welded.entry(key).or_insert_with(|| {
+                    rest_positions.push(Vector3::from(v.position));
+                    rest_positions.len() - 1
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut edges = HashSet::new();
+        for triangle in mesh.indices.chunks_exact(3) {
+            for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+                let a = vertex_particle[triangle[a] as usize];
+                let b = vertex_particle[triangle[b] as usize];
+                if a != b {
+                    edges.insert((a.min(b), a.max(b)));
+                }
+            }
+        }
+        let constraints = edges
+            .into_iter()
+            .map(|(a, b)| (a, b, (rest_positions[b] - rest_positions[a]).magnitude()))
+            .collect();
+
+        Self {
+            model,
+            mesh: mesh_index,
+            settings: ClothSettings::default(),
+            running: false,
+            rest: mesh.vertices.clone(),
+            indices: mesh.indices.clone(),
+            vertex_particle,
+            positions: rest_positions.clone(),
+            previous: rest_positions.clone(),
+            inverse_mass: vec![1.0; rest_positions.len()],
+            rest_positions,
+            constraints,
+            dirty: false,
+            detached: false,
+        }
+    }
+
+    pub fn num_particles(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn pin(&mut self, particle: usize) {
+        self.inverse_mass[particle] = 0.0;
+    }
+
+    pub fn unpin_all(&mut self) {
+        self.inverse_mass.iter_mut().for_each(|w| *w = 1.0);
+    }
+
+    /// Pins every particle whose rest position satisfies the predicate.
+    pub fn pin_where<F: Fn(Point3<f32>) -> bool>(&mut self, predicate: F) {
+        for i in 0..self.rest_positions.len() {
+            if predicate(Point3::from_vec(self.rest_positions[i])) {
+                self.pin(i);
+            }
+        }
+    }
+
+    /// Pins the highest particles, like a curtain hanging from a rod.
+    pub fn pin_top(&mut self, tolerance: f32) {
+        let top = self
+            .rest_positions
+            .iter()
+            .map(|p| p.y)
+            .fold(f32::NEG_INFINITY, f32::max);
+        self.pin_where(|p| p.y >= top - tolerance);
+    }
+
+    pub fn reset(&mut self) {
+        self.positions.copy_from_slice(&self.rest_positions);
+        self.previous.copy_from_slice(&self.rest_positions);
+        self.dirty = true;
+    }
+
+    /// Resets the mesh and marks the cloth for removal once the reset is uploaded.
+    pub fn detach(&mut self) {
+        self.running = false;
+        self.reset();
+        self.detached = true;
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        if !self.running || dt <= 0. {
+            return;
+        }
+        let settings = self.settings;
+
+        for i in 0..self.positions.len() {
+            if self.inverse_mass[i] == 0. {
+                continue;
+            }
+            let velocity = (self.positions[i] - self.previous[i]) * (1. - settings.damping);
+            self.previous[i] = self.positions[i];
+            self.positions[i] += velocity + settings.gravity * dt * dt;
+        }
+
+        for _ in 0..settings.iterations {
+            for &(a, b, rest_length) in self.constraints.iter() {
+                let (wa, wb) = (self.inverse_mass[a], self.inverse_mass[b]);
+                let w = wa + wb;
+                let delta = self.positions[b] - self.positions[a];
+                let length = delta.magnitude();
+                if w == 0. || length < f32::EPSILON {
+                    continue;
+                }
+                let correction = delta * ((length - rest_length) / (length * w)) * settings.stiffness;
+                self.positions[a] += correction * wa;
+                self.positions[b] -= correction * wb;
+            }
+            if let Some(floor) = settings.floor {
+                for p in self.positions.iter_mut() {
+                    p.y = p.y.max(floor);
+                }
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Builds the mesh vertices for the current particle positions with recomputed normals.
+    pub fn vertices(&self) -> Vec<ModelVertex> {
+        let mut normals = vec![Vector3::zero(); self.positions.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.vertex_particle[triangle[i] as usize]);
+            // Area weighted, since the cross product isn't normalized.
+            let normal =
+                (self.positions[b] - self.positions[a]).cross(self.positions[c] - self.positions[a]);
+            normals[a] += normal;
+            normals[b] += normal;
+            normals[c] += normal;
+        }
+        self.rest
+            .iter()
+            .zip(self.vertex_particle.iter())
+            .map(|(v, &p)| {
+                let normal = if normals[p].magnitude2() > 0. {
+                    normals[p].normalize()
+                } else {
+                    Vector3::from(v.normal)
+                };
+                ModelVertex {
+                    position: self.positions[p].into(),
+                    normal: normal.into(),
+                    ..*v
+                }
+            })
+            .collect()
+    }
+
+    /// Uploads the simulated shape if it changed since the last call.
+    pub fn sync(&mut self, mesh: &mut Mesh, queue: &wgpu::Queue) {
+        if self.dirty {
+            mesh.upload_vertices(queue, &self.vertices());
+            self.dirty = false;
+        }
+    }
+
+    pub fn draw_pins(&self, lines: &mut DebugLines, size: f32) {
+        if self.detached {
+            return;
+        }
+        for (p, _) in self
+            .positions
+            .iter()
+            .zip(self.inverse_mass.iter())
+            .filter(|(_, &w)| w == 0.)
+        {
+            let p = Point3::from_vec(*p);
+            for axis in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+                lines.line(p - axis * size, p + axis * size, Self::PIN_COLOR);
+            }
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! Clustered shading for the lights after the first. The camera's view frustum is cut into a
+//! grid of clusters, deeper the farther they are from the eye, and a compute pass lists the
+//! lights reaching each of them, directional ones reaching all. The forward shader only shades
+//! a fragment with the lights of its cluster, so dozens of lights cost about as much as a few.
+//!
+//! Without storage textures, as on WebGL2, there is no compute pass and only the first light
+//! shades the scene.
+
+use cgmath::{Matrix4, SquareMatrix};
+
+use crate::{
+    camera::Camera,
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    light::LightRaw,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ClusterUniform {
+    view: [[f32; 4]; 4],
+    view_proj: [[f32; 4]; 4],
+    inverse_projection: [[f32; 4]; 4],
+    /// Clusters along x, y and z, then the number of lights.
+    grid: [u32; 4],
+    /// The near and far planes the clusters span.
+    depth: [f32; 4],
+}
+
+// Plain numbers without padding between them.
+unsafe impl bytemuck::Zeroable for ClusterUniform {}
+unsafe impl bytemuck::Pod for ClusterUniform {}
+
+/// The compute pass filling the clusters.
+#[derive(Debug)]
+struct ClusterCulling {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+/// The lights as a texture, four texels each: position and range, color, direction and kind,
+/// then the spot cone, and the lights of every cluster as a row of another: their number, then
+/// their indices. The compute pass and the forward shader read the lights from the storage
+/// buffer of `Lights` instead, and only the uber shader, made for WebGL2 which has no storage
+/// buffers, reads the copy in the texture. On WebGL2 the texture stays empty, as nothing lists
+/// lights in the clusters.
+#[derive(Debug)]
+pub struct ClusteredLights {
+    light_texture: Tracked<wgpu::Texture>,
+    light_view: wgpu::TextureView,
+    cluster_view: Tracked<wgpu::TextureView>,
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    culling: Option<ClusterCulling>,
+}
+
+impl ClusteredLights {
+    /// Clusters across, up and into the frustum.
+    pub const GRID: [u32; 3] = [16, 8, 16];
+    pub const MAX_LIGHTS: usize = 256;
+    /// Lights shading one cluster, lights past it are left out.
+    pub const MAX_PER_CLUSTER: u32 = 31;
+    const WORKGROUP_SIZE: u32 = 64;
+    const LIGHT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+    const CLUSTER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+    /// Binding of the storage buffer of `Lights` in the lights' bind group.
+    pub const STORAGE_BINDING: u32 = 9;
+
+    fn cluster_count() -> u32 {
+        Self::GRID.iter().product()
+    }
+
+    pub fn new(device: &wgpu::Device, light_storage: &wgpu::Buffer) -> Self {
+        let light_texture = device.create_tracked_texture(
+            GpuCategory::Lights,
+            &wgpu::TextureDescriptor {
+                label: Some("Point Light Texture"),
+                size: wgpu::Extent3d {
+                    width: Self::MAX_LIGHTS as u32 * 4,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::LIGHT_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            },
+        );
+        let light_view = light_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let cull = device.limits().max_storage_textures_per_shader_stage > 0;
+        let usage = if cull {
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING
+        } else {
+            wgpu::TextureUsages::TEXTURE_BINDING
+        };
+        let cluster_texture = device.create_tracked_texture(
+            GpuCategory::Lights,
+            &wgpu::TextureDescriptor {
+                label: Some("Cluster Texture"),
+                size: wgpu::Extent3d {
+                    width: Self::MAX_PER_CLUSTER + 1,
+                    height: Self::cluster_count(),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::CLUSTER_FORMAT,
+                usage,
+            },
+        );
+        let cluster_view = cluster_texture.into_view(&wgpu::TextureViewDescriptor::default());
+        let uniform_buffer = device.create_tracked_buffer(
+            GpuCategory::Lights,
+            &wgpu::BufferDescriptor {
+                label: Some("Cluster Uniform Buffer"),
+                size: std::mem::size_of::<ClusterUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+        let culling = if cull {
+            Some(Self::create_culling(
+                device,
+                light_storage,
+                &cluster_view,
+                &uniform_buffer,
+            ))
+        } else {
+            None
+        };
+        Self {
+            light_texture,
+            light_view,
+            cluster_view,
+            uniform_buffer,
+            culling,
+        }
+    }
+
+    fn create_culling(
+        device: &wgpu::Device,
+        light_storage: &wgpu::Buffer,
+        cluster_view: &wgpu::TextureView,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> ClusterCulling {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("cluster_culling_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: Self::CLUSTER_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("cluster_culling_bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_storage.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(cluster_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let module = device.create_shader_module(&wgpu::include_spirv!("clusters.comp.spv"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cluster Culling Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cluster Culling Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "main",
+        });
+        ClusterCulling {
+            pipeline,
+            bind_group,
+        }
+    }
+
+    /// Whether the device runs the compute pass, without which only the first light shades.
+    pub fn culls(&self) -> bool {
+        self.culling.is_some()
+    }
+
+    /// Entries of the lights' bind group layout reading the clusters, after the light itself.
+    pub fn layout_entries() -> [wgpu::BindGroupLayoutEntry; 3] {
+        let texture = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type,
+            },
+            count: None,
+        };
+        [
+            texture(1, wgpu::TextureSampleType::Float { filterable: false }),
+            texture(2, wgpu::TextureSampleType::Uint),
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ]
+    }
+
+    /// The resources for `layout_entries`.
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 3] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&self.light_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&self.cluster_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+        ]
+    }
+
+    /// Entry of the lights' bind group layout for the storage buffer of `Lights`, after the
+    /// environment's. `None` on devices without storage buffers, such as WebGL2.
+    pub fn storage_layout_entry(device: &wgpu::Device) -> Option<wgpu::BindGroupLayoutEntry> {
+        if device.limits().max_storage_buffers_per_shader_stage == 0 {
+            return None;
+        }
+        Some(wgpu::BindGroupLayoutEntry {
+            binding: Self::STORAGE_BINDING,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        })
+    }
+
+    /// Uploads the lights and fits the clusters into the camera's view.
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera, lights: &[LightRaw]) {
+        profile_function!();
+        let count = match self.culling {
+            Some(_) => lights.len().min(Self::MAX_LIGHTS),
+            None => 0,
+        };
+        let texels = lights[..count]
+            .iter()
+            .flat_map(|light| [light.position, light.color, light.direction, light.spot])
+            .collect::<Vec<_>>();
+        if count > 0 {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.light_texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(&texels),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(count as u32 * 4 * 16),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width: count as u32 * 4,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        let view = camera.calc_matrix();
+        let projection = camera.projection_matrix();
+        let inverse_projection = projection.invert().unwrap_or_else(Matrix4::identity);
+        let [x, y, z] = Self::GRID;
+        let uniform = ClusterUniform {
+            view: view.into(),
+            view_proj: (projection * view).into(),
+            inverse_projection: inverse_projection.into(),
+            grid: [x, y, z, count as u32],
+            depth: [camera.projection.near, camera.projection.far, 0., 0.],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Lists the lights of every cluster, before the forward pass reads them.
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder) {
+        let culling = match &self.culling {
+            Some(culling) => culling,
+            None => return,
+        };
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cluster Culling Pass"),
+        });
+        pass.set_pipeline(&culling.pipeline);
+        pass.set_bind_group(0, &culling.bind_group, &[]);
+        let groups = (Self::cluster_count() + Self::WORKGROUP_SIZE - 1) / Self::WORKGROUP_SIZE;
+        pass.dispatch(groups, 1, 1);
+    }
+}
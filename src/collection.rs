@@ -2,6 +2,8 @@ use std::{collections::HashMap, path::Path, sync::{Arc, RwLock}};
 
 use anyhow::*;
 
+use crate::loading::LoadedModel;
+
 #[derive(Debug, Clone, Copy)]
 pub struct ModelVertex {
     position: [f32; 3],
@@ -12,6 +14,7 @@ pub struct ModelVertex {
 }
 
 type Models = Arc<RwLock<HashMap<String, Arc<Model>>>>;
+#[derive(Debug)]
 pub struct Collection {
     pub models: Models,
 }
@@ -159,6 +162,31 @@ impl ObjModel {
 
         Ok(Self { meshes, is_dirty: true })
     }
+
+    /// The meshes of a model loaded into the scene, so its file is only read once.
+    pub fn from_loaded(loaded: &LoadedModel) -> Self {
+        let meshes = loaded
+            .meshes
+            .iter()
+            .map(|mesh| Mesh {
+                name: mesh.name.clone(),
+                vertices: mesh
+                    .vertices
+                    .iter()
+                    .map(|vertex| ModelVertex {
+                        position: vertex.position,
+                        tex_coords: vertex.tex_coords,
+                        normal: vertex.normal,
+                        tangent: vertex.tangent,
+                        bitangent: vertex.bitangent,
+                    })
+                    .collect(),
+                indices: mesh.indices.clone(),
+                num_elements: mesh.indices.len() as u32,
+            })
+            .collect();
+        Self { meshes, is_dirty: true }
+    }
     
     pub fn update_buffers(&self) {
         if self.is_dirty {
@@ -0,0 +1,83 @@
+//! A/B comparison of two looks of the scene in one viewport, for look-dev decisions: left of a
+//! divider the meshes are drawn as the viewport's display says, right of it in another display
+//! mode and with a material swapped for another. Both sides are forward passes over the same
+//! target, each clipped to its side by a scissor rectangle.
+
+use std::sync::Arc;
+
+use crate::{
+    display::{Display, DisplayMode},
+    model::Material,
+    scene::Scene,
+};
+
+#[derive(Debug, Clone)]
+pub struct Compare {
+    pub enabled: bool,
+    /// Where the divider is, from 0 at the left edge of the view to 1 at the right.
+    pub split: f32,
+    /// How the right side colors the meshes.
+    pub mode: DisplayMode,
+    /// Registry names of a material and the one drawn instead of it on the right side.
+    pub swap: Option<(String, String)>,
+}
+
+impl Default for Compare {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            split: 0.5,
+            mode: DisplayMode::Shaded,
+            swap: None,
+        }
+    }
+}
+
+impl Compare {
+    /// The display colors of the right side, the left one's flat color kept.
+    pub fn display(&self, left: &Display) -> Display {
+        Display {
+            mode: self.mode,
+            ..left.clone()
+        }
+    }
+
+    /// Pixel column of the divider in a target `width` wide, leaving both sides at least one.
+    pub fn divider(&self, width: u32) -> u32 {
+        let x = (self.split.max(0.).min(1.) * width as f32).round() as u32;
+        x.max(1).min(width.saturating_sub(1))
+    }
+}
+
+/// One side of the comparison, drawn within its scissor rectangle only.
+#[derive(Debug)]
+pub struct ComparePass<'a> {
+    /// x, y, width and height in pixels of the target.
+    pub scissor: [u32; 4],
+    /// Whether the meshes are drawn with the flags of the right side, see `ShadowMap::update`.
+    pub compared: bool,
+    /// Meshes with the first material are drawn with the second one.
+    pub swap: Option<(&'a Arc<Material>, &'a Arc<Material>)>,
+}
+
+impl<'a> ComparePass<'a> {
+    /// The material the mesh is drawn with on this side.
+    pub fn material<'b>(&self, material: &'b Arc<Material>) -> &'b Material
+    where
+        'a: 'b,
+    {
+        match self.swap {
+            Some((from, to)) if Arc::ptr_eq(material, from) => to,
+            _ => material,
+        }
+    }
+}
+
+impl Scene {
+    /// The materials of the swap of the right side, while both are still registered.
+    pub(crate) fn compare_swap(&self) -> Option<(Arc<Material>, Arc<Material>)> {
+        let (from, to) = self.compare.swap.as_ref()?;
+        let materials = self.materials.read().unwrap();
+        Some((materials.by_name(from)?.clone(), materials.by_name(to)?.clone()))
+    }
+}
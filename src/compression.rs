@@ -0,0 +1,108 @@
+//! Optional compression of the files kept between sessions, projects and the mesh cache, which
+//! get large for scenes with a lot of geometry. They are written as an LZ4 or a zstd frame, or
+//! as they are, and read back by the magic number the frame starts with, whatever the setting
+//! is now. Compressing needs the `compression` feature and a native build; without it files
+//! are written as they are, and compressed ones can't be read.
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+/// How files are compressed when written, trading speed for size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compression {
+    Off,
+    /// LZ4, quick to write and read, about half the size of the data.
+    Speed,
+    /// zstd at a high level, often half the size of LZ4 but several times slower to write.
+    Size,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Off
+    }
+}
+
+impl Compression {
+    pub const ALL: [Compression; 3] = [Compression::Off, Compression::Speed, Compression::Size];
+
+    /// Whether this build can compress and decompress files.
+    pub const AVAILABLE: bool = cfg!(all(feature = "compression", not(target_arch = "wasm32")));
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Off => "Off",
+            Compression::Speed => "Speed (LZ4)",
+            Compression::Size => "Size (zstd)",
+        }
+    }
+
+    /// The compression of a file from its first bytes.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&LZ4_MAGIC) {
+            Compression::Speed
+        } else if bytes.starts_with(&ZSTD_MAGIC) {
+            Compression::Size
+        } else {
+            Compression::Off
+        }
+    }
+}
+
+/// The magic numbers of an LZ4 and a zstd frame, little endian.
+const LZ4_MAGIC: [u8; 4] = [0x04, 0x22, 0x4d, 0x18];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Past it zstd gets much slower for little gain.
+#[cfg(all(feature = "compression", not(target_arch = "wasm32")))]
+const ZSTD_LEVEL: i32 = 15;
+
+/// Compresses `bytes` as chosen, in a frame `decompress` recognizes.
+#[cfg(all(feature = "compression", not(target_arch = "wasm32")))]
+pub fn compress(bytes: Vec<u8>, compression: Compression) -> Result<Vec<u8>> {
+    use std::io::Write;
+
+    Ok(match compression {
+        Compression::Off => bytes,
+        Compression::Speed => {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+            encoder.write_all(&bytes)?;
+            encoder.finish()?
+        }
+        Compression::Size => zstd::encode_all(bytes.as_slice(), ZSTD_LEVEL)?,
+    })
+}
+
+/// The file's bytes uncompressed, as they are when they don't start with a known frame.
+#[cfg(all(feature = "compression", not(target_arch = "wasm32")))]
+pub fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    use std::io::Read;
+
+    Ok(match Compression::detect(&bytes) {
+        Compression::Off => bytes,
+        Compression::Speed => {
+            let mut decoded = Vec::new();
+            lz4_flex::frame::FrameDecoder::new(bytes.as_slice())
+                .read_to_end(&mut decoded)
+                .context("Broken LZ4 frame")?;
+            decoded
+        }
+        Compression::Size => zstd::decode_all(bytes.as_slice()).context("Broken zstd frame")?,
+    })
+}
+
+#[cfg(not(all(feature = "compression", not(target_arch = "wasm32"))))]
+pub fn compress(bytes: Vec<u8>, _compression: Compression) -> Result<Vec<u8>> {
+    Ok(bytes)
+}
+
+#[cfg(not(all(feature = "compression", not(target_arch = "wasm32"))))]
+pub fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    match Compression::detect(&bytes) {
+        Compression::Off => Ok(bytes),
+        compression => bail!(
+            "Compressed with {}, built without the compression feature",
+            compression.name()
+        ),
+    }
+}
@@ -0,0 +1,267 @@
+//! Constraints driving nodes of the scene graph at every update, before the node transforms
+//! are applied: aiming at a target, running along a path, copying another node and sticking to
+//! a point on a mesh. A turntable is a closed path around a model with a look-at constraint on
+//! its center, a camera rail a path alone. A node's constraints apply in order, so a later one
+//! wins where they overlap.
+
+use cgmath::{
+    EuclideanSpace, InnerSpace, Matrix3, Matrix4, Point3, Quaternion, SquareMatrix, Transform,
+    Vector3,
+};
+
+use crate::{picking::Hit, scene::Scene, scene_graph::SceneGraph};
+
+/// What a look-at constraint aims at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// The origin of the node of the index.
+    Node(usize),
+    /// A point in scene space.
+    Point(Point3<f32>),
+}
+
+/// Points in scene space run through at a steady speed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FollowPath {
+    pub points: Vec<Point3<f32>>,
+    /// Whether the path goes on from the last point to the first. An open path is run there
+    /// and back instead.
+    pub closed: bool,
+    /// Seconds a lap takes.
+    pub duration: f32,
+    /// Seconds into the lap.
+    pub time: f32,
+}
+
+impl FollowPath {
+    pub fn new(points: Vec<Point3<f32>>, closed: bool, duration: f32) -> Self {
+        Self {
+            points,
+            closed,
+            duration,
+            time: 0.,
+        }
+    }
+
+    /// A closed path of `segments` points circling `center` level with `start`, from it.
+    pub fn circle(center: Point3<f32>, start: Point3<f32>, segments: u32, duration: f32) -> Self {
+        let offset = start - center;
+        let radius = Vector3::new(offset.x, 0., offset.z).magnitude();
+        let first = offset.z.atan2(offset.x);
+        let points = (0..segments.max(3))
+            .map(|i| {
+                let angle = first + i as f32 / segments.max(3) as f32 * std::f32::consts::TAU;
+                center + Vector3::new(radius * angle.cos(), offset.y, radius * angle.sin())
+            })
+            .collect();
+        Self::new(points, true, duration)
+    }
+
+    /// The point the fraction of a lap along the path.
+    pub fn at(&self, fraction: f32) -> Option<Point3<f32>> {
+        let first = *self.points.first()?;
+        let last = *self.points.last()?;
+        let mut segments = self
+            .points
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect::<Vec<_>>();
+        let mut fraction = fraction.rem_euclid(1.);
+        if self.closed {
+            segments.push((last, first));
+        } else {
+            fraction = 1. - (fraction * 2. - 1.).abs();
+        }
+        let length = segments
+            .iter()
+            .map(|(a, b)| (b - a).magnitude())
+            .sum::<f32>();
+        let mut left = fraction * length;
+        for &(a, b) in segments.iter() {
+            let segment = (b - a).magnitude();
+            if left <= segment && segment > 0. {
+                return Some(a + (b - a) * (left / segment));
+            }
+            left -= segment;
+        }
+        Some(if self.closed { first } else { last })
+    }
+
+    /// Moves `dt` seconds on, returning where that is.
+    fn advance(&mut self, dt: f32) -> Option<Point3<f32>> {
+        if self.duration <= 0. {
+            return self.at(0.);
+        }
+        self.time = (self.time + dt).rem_euclid(self.duration);
+        self.at(self.time / self.duration)
+    }
+}
+
+/// A point on a triangle of a mesh, which follows the mesh as it moves and deforms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceAnchor {
+    pub model: usize,
+    pub mesh: usize,
+    /// Index of the first of the triangle's three indices.
+    pub triangle: usize,
+    /// Barycentric weights of the triangle's corners.
+    pub weights: [f32; 3],
+    /// Whether the node's y axis follows the surface normal.
+    pub align: bool,
+}
+
+impl SurfaceAnchor {
+    pub fn from_hit(hit: &Hit) -> Self {
+        Self {
+            model: hit.model,
+            mesh: hit.mesh,
+            triangle: hit.triangle,
+            weights: hit.weights,
+            align: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    /// Turns the node so its -z axis points at the target, keeping its y axis up.
+    LookAt(Target),
+    /// Moves the node along the path.
+    FollowPath(FollowPath),
+    /// Gives the node the local transform of the node of the index.
+    CopyTransform(usize),
+    /// Puts the node on a point of another model's surface. Anchoring a node to the meshes it
+    /// places would move them away endlessly.
+    Surface(SurfaceAnchor),
+}
+
+impl Constraint {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Constraint::LookAt(_) => "Look at",
+            Constraint::FollowPath(_) => "Follow path",
+            Constraint::CopyTransform(_) => "Copy transform",
+            Constraint::Surface(_) => "Surface",
+        }
+    }
+
+    /// The constraint after the nodes were renumbered by `index`, which gives `None` for the
+    /// removed ones. A constraint on a removed node goes with it.
+    pub(crate) fn renumber(&self, index: impl Fn(usize) -> Option<usize>) -> Option<Self> {
+        match self {
+            Constraint::LookAt(Target::Node(node)) => {
+                Some(Constraint::LookAt(Target::Node(index(*node)?)))
+            }
+            Constraint::CopyTransform(node) => Some(Constraint::CopyTransform(index(*node)?)),
+            constraint => Some(constraint.clone()),
+        }
+    }
+
+    fn apply(
+        &mut self,
+        graph: &mut SceneGraph,
+        node: usize,
+        dt: f32,
+        surface: &dyn Fn(&SurfaceAnchor) -> Option<(Point3<f32>, Vector3<f32>)>,
+    ) {
+        // From scene space into the space the node's local transform is in.
+        let to_parent = graph.nodes[node]
+            .parent
+            .and_then(|parent| graph.world(parent).invert())
+            .unwrap_or_else(Matrix4::identity);
+        match self {
+            Constraint::LookAt(target) => {
+                let target = match *target {
+                    Target::Node(other) if other < graph.nodes.len() => {
+                        graph.world(other).transform_point(Point3::origin())
+                    }
+                    Target::Point(point) => point,
+                    Target::Node(_) => return,
+                };
+                let local = &mut graph.nodes[node].local;
+                let forward =
+                    to_parent.transform_point(target) - Point3::from_vec(local.translation);
+                let up = to_parent.transform_vector(Vector3::unit_y());
+                if let Some(rotation) = facing(forward, up) {
+                    local.rotation = rotation;
+                }
+            }
+            Constraint::FollowPath(path) => {
+                if let Some(point) = path.advance(dt) {
+                    graph.nodes[node].local.translation = to_parent.transform_point(point).to_vec();
+                }
+            }
+            Constraint::CopyTransform(source) => {
+                if let Some(local) = graph.nodes.get(*source).map(|source| source.local) {
+                    graph.nodes[node].local = local;
+                }
+            }
+            Constraint::Surface(anchor) => {
+                let (point, normal) = match surface(anchor) {
+                    Some(surface) => surface,
+                    None => return,
+                };
+                let local = &mut graph.nodes[node].local;
+                local.translation = to_parent.transform_point(point).to_vec();
+                let normal = to_parent.transform_vector(normal);
+                if anchor.align && normal.magnitude2() > 1e-12 {
+                    local.rotation =
+                        Quaternion::from_arc(Vector3::unit_y(), normal.normalize(), None);
+                }
+            }
+        }
+    }
+}
+
+/// The rotation turning -z to `forward` and y as close to `up` as it goes, if they differ.
+fn facing(forward: Vector3<f32>, up: Vector3<f32>) -> Option<Quaternion<f32>> {
+    let right = forward.cross(up);
+    if forward.magnitude2() < 1e-12 || right.magnitude2() < 1e-12 {
+        return None;
+    }
+    let (forward, right) = (forward.normalize(), right.normalize());
+    Some(Matrix3::from_cols(right, right.cross(forward), -forward).into())
+}
+
+impl SceneGraph {
+    /// Drives the constrained nodes `dt` seconds on, with `surface` finding where an anchor is
+    /// and the surface normal there.
+    pub(crate) fn evaluate_constraints(
+        &mut self,
+        dt: f32,
+        surface: &dyn Fn(&SurfaceAnchor) -> Option<(Point3<f32>, Vector3<f32>)>,
+    ) {
+        for node in 0..self.nodes.len() {
+            let mut constraints = std::mem::take(&mut self.nodes[node].constraints);
+            for constraint in constraints.iter_mut() {
+                constraint.apply(self, node, dt, surface);
+            }
+            self.nodes[node].constraints = constraints;
+        }
+    }
+}
+
+impl Scene {
+    /// Drives the constrained nodes `dt` seconds on, anchors following their meshes where
+    /// they are drawn.
+    pub(crate) fn evaluate_constraints(&mut self, dt: f32) {
+        profile_function!();
+        let matrices = self.mesh_matrices();
+        let models = &self.models;
+        let surface = |anchor: &SurfaceAnchor| {
+            let mesh = models.get(anchor.model)?.meshes().get(anchor.mesh)?;
+            let matrix = matrices[anchor.model][anchor.mesh];
+            let corners = mesh.indices.get(anchor.triangle..anchor.triangle + 3)?;
+            let mut point = Vector3::new(0., 0., 0.);
+            let mut normal = Vector3::new(0., 0., 0.);
+            for (&index, &weight) in corners.iter().zip(anchor.weights.iter()) {
+                let vertex = mesh.vertices.get(index as usize)?;
+                point += Vector3::from(vertex.position) * weight;
+                normal += Vector3::from(vertex.normal) * weight;
+            }
+            let point = matrix.transform_point(Point3::from_vec(point));
+            Some((point, matrix.transform_vector(normal)))
+        };
+        self.graph.evaluate_constraints(dt, &surface);
+    }
+}
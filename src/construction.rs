@@ -0,0 +1,241 @@
+//! Construction planes, named planes made from three picked points or a face of a model and
+//! drawn as translucent grids. The active one is a drawing plane: the 3D cursor and new
+//! primitives go on it, snapped to its grid, and the knife can cut along it.
+
+use anyhow::*;
+use cgmath::{InnerSpace, Matrix3, Matrix4, Point3, Vector3};
+
+use crate::{mesh::topology::Plane, picking::Ray, scene::Scene};
+
+#[derive(Debug, Clone)]
+pub struct ConstructionPlane {
+    pub name: String,
+    /// Where the grid is centered.
+    pub origin: Point3<f32>,
+    /// Unit length.
+    pub normal: Vector3<f32>,
+    /// The direction of the grid lines across the plane, unit length.
+    pub axis: Vector3<f32>,
+    pub visible: bool,
+}
+
+impl ConstructionPlane {
+    /// The plane through the three points, facing the side they turn counterclockwise on,
+    /// with its grid along the first two.
+    pub fn from_points(name: String, [a, b, c]: [Point3<f32>; 3]) -> Result<Self> {
+        let normal = (b - a).cross(c - a);
+        if normal.magnitude2() < 1e-12 {
+            bail!("The points of {} are in a line", name);
+        }
+        Ok(Self {
+            name,
+            origin: a,
+            normal: normal.normalize(),
+            axis: (b - a).normalize(),
+            visible: true,
+        })
+    }
+
+    /// The other direction of the grid.
+    pub fn cross_axis(&self) -> Vector3<f32> {
+        self.normal.cross(self.axis)
+    }
+
+    pub fn plane(&self) -> Plane {
+        Plane::new(self.origin, self.normal)
+    }
+
+    /// Where the ray meets the plane, from either side.
+    pub fn intersect(&self, ray: &Ray) -> Option<Point3<f32>> {
+        let facing = ray.direction.dot(self.normal);
+        if facing.abs() < 1e-6 {
+            return None;
+        }
+        let distance = (self.origin - ray.origin).dot(self.normal) / facing;
+        Some(ray.at(distance)).filter(|_| distance > 0.)
+    }
+
+    /// The closest crossing of grid lines `spacing` apart.
+    pub fn snap(&self, point: Point3<f32>, spacing: f32) -> Point3<f32> {
+        let offset = point - self.origin;
+        let round = |axis: Vector3<f32>| axis * (offset.dot(axis) / spacing).round() * spacing;
+        self.origin + round(self.axis) + round(self.cross_axis())
+    }
+
+    /// Turns what is upright in the scene to stand on the plane.
+    pub fn rotation(&self) -> Matrix4<f32> {
+        let z = self.axis.cross(self.normal);
+        Matrix3::from_cols(self.axis, self.normal, z).into()
+    }
+}
+
+/// What clicks in the viewport pick while a plane is being made.
+#[derive(Debug, Clone)]
+pub enum PlanePick {
+    /// Surface points, the plane is made once there are three.
+    Points(Vec<Point3<f32>>),
+    /// The face under the click.
+    Face,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConstructionPlanes {
+    pub planes: Vec<ConstructionPlane>,
+    /// The plane drawn on, by index.
+    pub active: Option<usize>,
+    /// Distance between grid lines.
+    pub spacing: f32,
+    /// Grid lines on each side of the origin.
+    pub extent: u32,
+    /// Whether points on the active plane snap to its grid.
+    pub snap: bool,
+    pub picking: Option<PlanePick>,
+}
+
+impl Default for ConstructionPlanes {
+    fn default() -> Self {
+        Self {
+            planes: Vec::new(),
+            active: None,
+            spacing: 1.,
+            extent: 10,
+            snap: true,
+            picking: None,
+        }
+    }
+}
+
+impl ConstructionPlanes {
+    const COLOR: [f32; 3] = [0.3, 0.8, 1.0];
+    const ACTIVE_COLOR: [f32; 3] = [1.0, 0.8, 0.3];
+    const ALPHA: f32 = 0.3;
+    const PICK_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+
+    pub fn active(&self) -> Option<&ConstructionPlane> {
+        self.planes.get(self.active?)
+    }
+
+    /// The point on the active plane under the ray, on its grid when snapping.
+    pub fn point(&self, ray: &Ray) -> Option<Point3<f32>> {
+        let plane = self.active()?;
+        let point = plane.intersect(ray)?;
+        Some(match self.snap && self.spacing > 0. {
+            true => plane.snap(point, self.spacing),
+            false => point,
+        })
+    }
+
+    /// Adds the plane and draws on it from now on.
+    pub fn add(&mut self, plane: ConstructionPlane) {
+        self.planes.push(plane);
+        self.active = Some(self.planes.len() - 1);
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        self.planes.remove(index);
+        self.active = match self.active {
+            Some(active) if active == index => None,
+            Some(active) if active > index => Some(active - 1),
+            active => active,
+        };
+    }
+
+    /// A name no plane has yet.
+    pub fn next_name(&self) -> String {
+        (1..)
+            .map(|i| format!("Plane {}", i))
+            .find(|name| self.planes.iter().all(|plane| plane.name != *name))
+            .unwrap()
+    }
+}
+
+impl Scene {
+    /// Picks a point or face for the plane being made, if there is one. Returns whether the
+    /// click was taken for it.
+    pub fn pick_construction_plane(&mut self, ray: &Ray) -> bool {
+        let pick = match self.construction.picking.take() {
+            Some(pick) => pick,
+            None => return false,
+        };
+        let hit = self.raycast(ray);
+        let name = self.construction.next_name();
+        let made = match (pick, hit) {
+            (PlanePick::Points(mut points), Some(hit)) => {
+                points.push(hit.point);
+                match points[..] {
+                    [a, b, c] => Some(ConstructionPlane::from_points(name, [a, b, c])),
+                    _ => {
+                        self.construction.picking = Some(PlanePick::Points(points));
+                        None
+                    }
+                }
+            }
+            (PlanePick::Face, Some(hit)) => {
+                let mesh = &self.models[hit.model].meshes()[hit.mesh];
+                let corners = [0, 1, 2].map(|i| {
+                    Point3::from(mesh.vertices[mesh.indices[hit.triangle + i] as usize].position)
+                });
+                Some(
+                    ConstructionPlane::from_points(name, corners).map(|plane| ConstructionPlane {
+                        origin: hit.point,
+                        ..plane
+                    }),
+                )
+            }
+            // Missing the models keeps waiting for a click on them.
+            (pick, None) => {
+                self.construction.picking = Some(pick);
+                None
+            }
+        };
+        match made {
+            Some(Ok(plane)) => self.construction.add(plane),
+            Some(Err(e)) => log::warn!("No construction plane made: {:?}", e),
+            None => {}
+        }
+        true
+    }
+
+    /// Draws the visible planes as grids and the points picked for a new one.
+    pub(crate) fn draw_construction_planes(&mut self) {
+        let construction = &self.construction;
+        let lines = &mut self.renderer.debug_lines;
+        for (i, plane) in construction.planes.iter().enumerate() {
+            if !plane.visible {
+                continue;
+            }
+            let color = if construction.active == Some(i) {
+                ConstructionPlanes::ACTIVE_COLOR
+            } else {
+                ConstructionPlanes::COLOR
+            };
+            let extent = construction.extent as i32;
+            let half = construction.spacing * extent as f32;
+            for (along, across) in [
+                (plane.axis, plane.cross_axis()),
+                (plane.cross_axis(), plane.axis),
+            ] {
+                for line in -extent..=extent {
+                    let center = plane.origin + across * (line as f32 * construction.spacing);
+                    let (a, b) = (center - along * half, center + along * half);
+                    // The lines through the origin stand out.
+                    let alpha = if line == 0 {
+                        1.
+                    } else {
+                        ConstructionPlanes::ALPHA
+                    };
+                    lines.translucent_line(a, b, color, alpha);
+                }
+            }
+        }
+        if let Some(PlanePick::Points(points)) = &construction.picking {
+            let size = construction.spacing * 0.1;
+            for &point in points {
+                for axis in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+                    let (a, b) = (point - axis * size, point + axis * size);
+                    lines.line(a, b, ConstructionPlanes::PICK_COLOR);
+                }
+            }
+        }
+    }
+}
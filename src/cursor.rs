@@ -0,0 +1,202 @@
+//! The 3D cursor, a point in the scene placed with shift and the right mouse button on the
+//! surface under the pointer, or on the work plane where there is none. New primitives are
+//! added there, the selection can turn around it and be snapped to it.
+
+use anyhow::*;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Vector3};
+
+use crate::{
+    events::SceneEvent,
+    outliner::GroupTransform,
+    picking::{Hit, Ray},
+    primitive::Primitive,
+    registry::{MaterialTextures, NodeId},
+    scene::Scene,
+};
+
+/// The plane the cursor is put on when there is no surface under the pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkPlane {
+    /// Level, facing up.
+    Ground,
+    /// Upright, facing along z.
+    Front,
+    /// Upright, facing along x.
+    Side,
+}
+
+impl WorkPlane {
+    pub const ALL: [WorkPlane; 3] = [WorkPlane::Ground, WorkPlane::Front, WorkPlane::Side];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorkPlane::Ground => "Ground (XZ)",
+            WorkPlane::Front => "Front (XY)",
+            WorkPlane::Side => "Side (YZ)",
+        }
+    }
+
+    pub fn normal(&self) -> Vector3<f32> {
+        match self {
+            WorkPlane::Ground => Vector3::unit_y(),
+            WorkPlane::Front => Vector3::unit_z(),
+            WorkPlane::Side => Vector3::unit_x(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cursor3D {
+    pub position: Point3<f32>,
+    pub plane: WorkPlane,
+    /// How far the work plane is from the scene origin along its normal.
+    pub plane_offset: f32,
+    /// Width of the primitives added at the cursor.
+    pub primitive_size: f32,
+    /// The surface the cursor was last placed on.
+    surface: Option<Hit>,
+}
+
+impl Default for Cursor3D {
+    fn default() -> Self {
+        Self {
+            position: Point3::new(0., 0., 0.),
+            plane: WorkPlane::Ground,
+            plane_offset: 0.,
+            primitive_size: 1.,
+            surface: None,
+        }
+    }
+}
+
+impl Cursor3D {
+    const COLOR: [f32; 3] = [1.0, 0.3, 0.3];
+    const MATERIAL: &'static str = "Primitive";
+
+    /// Where the ray meets the work plane, `None` running alongside it or away from it.
+    pub fn on_plane(&self, ray: &Ray) -> Option<Point3<f32>> {
+        let normal = self.plane.normal();
+        let facing = ray.direction.dot(normal);
+        if facing.abs() < 1e-6 {
+            return None;
+        }
+        let distance = (self.plane_offset - ray.origin.to_vec().dot(normal)) / facing;
+        Some(ray.at(distance)).filter(|_| distance > 0.)
+    }
+}
+
+impl Scene {
+    /// Puts the cursor on the active construction plane if there is one, or else on the
+    /// surface under the ray or the work plane. Returns whether it hit any.
+    pub fn place_cursor(&mut self, ray: &Ray) -> bool {
+        self.cursor.surface = None;
+        let point = match self.construction.point(ray) {
+            Some(point) => Some(point),
+            None => match self.raycast(ray) {
+                Some(hit) => {
+                    self.cursor.surface = Some(hit);
+                    Some(hit.point)
+                }
+                None => self.cursor.on_plane(ray),
+            },
+        };
+        if let Some(point) = point {
+            self.cursor.position = point;
+        }
+        point.is_some()
+    }
+
+    /// The surface the cursor is on, if it was placed on one and stayed there.
+    pub fn cursor_surface(&self) -> Option<Hit> {
+        self.cursor
+            .surface
+            .filter(|hit| (hit.point - self.cursor.position).magnitude2() < 1e-12)
+    }
+
+    /// Puts the cursor at the pivot of the selection.
+    pub fn cursor_to_selected(&mut self) {
+        if let Some(pivot) = self.selection_pivot() {
+            self.cursor.position = pivot;
+        }
+    }
+
+    /// Moves the selected models so their pivot lands on the cursor.
+    pub fn selection_to_cursor(&mut self) {
+        let pivot = match self.selection_pivot() {
+            Some(pivot) => pivot,
+            None => return,
+        };
+        let offset = self.cursor.position - pivot;
+        let models = self.selected_models();
+        for &model in models.iter() {
+            self.bake_transform(model, Matrix4::from_translation(offset));
+        }
+        for object in self.selected_gizmos() {
+            self.transform_gizmo(object, Matrix4::from_translation(offset));
+        }
+        self.events.push(SceneEvent::TransformEdited(
+            models.into_iter().map(NodeId).collect(),
+        ));
+    }
+
+    /// Adds the primitive at the cursor, selected and with its origin there, in a plain
+    /// material shared by all primitives. It stands on the active construction plane, if any.
+    pub fn add_primitive(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        primitive: Primitive,
+    ) -> Result<NodeId> {
+        let existing = self.materials.read().unwrap().handle(Cursor3D::MATERIAL);
+        let material = match existing {
+            Some(material) => material,
+            None => self.create_material(
+                device,
+                queue,
+                config,
+                Cursor3D::MATERIAL,
+                MaterialTextures::default(),
+            )?,
+        };
+        let mesh = self.add_mesh(primitive.name(), primitive.mesh(self.cursor.primitive_size));
+        let position = self.cursor.position;
+        let transform = GroupTransform {
+            translation: position.into(),
+            ..Default::default()
+        };
+        let node = self.spawn(device, mesh, material, transform)?;
+        self.set_origin(node.0, position);
+        if let Some(plane) = self.construction.active() {
+            let pivot = position - Point3::new(0., 0., 0.);
+            let matrix = Matrix4::from_translation(pivot)
+                * plane.rotation()
+                * Matrix4::from_translation(-pivot);
+            self.bake_transform(node.0, matrix);
+        }
+        self.outliner.selected.clear();
+        self.gizmos.selected.clear();
+        self.outliner.selected.insert(node.0);
+        Ok(node)
+    }
+
+    /// Draws the cursor as a cross inside a level diamond.
+    pub(crate) fn draw_cursor(&mut self) {
+        let position = self.cursor.position;
+        let size = (self.camera.eye - position).magnitude() * 0.02;
+        let lines = &mut self.renderer.debug_lines;
+        for axis in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()].iter() {
+            lines.line(
+                position - axis * size,
+                position + axis * size,
+                Cursor3D::COLOR,
+            );
+        }
+        // The diamond tells it apart from the origins of models.
+        let (x, z) = (Vector3::unit_x() * size, Vector3::unit_z() * size);
+        let corners = [position + x, position + z, position - x, position - z];
+        for i in 0..4 {
+            lines.line(corners[i], corners[(i + 1) % 4], Cursor3D::COLOR);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use crate::{
+    bounds::Aabb,
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    model::Vertex,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    /// Linear RGB and opacity.
+    pub color: [f32; 4],
+}
+
+impl Vertex for LineVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Lines rebuilt every update and drawn on top of the scene, for visualizing helpers such as colliders.
+#[derive(Debug)]
+pub struct DebugLines {
+    vertices: Vec<LineVertex>,
+    num_vertices: u32,
+    buffer: Tracked<wgpu::Buffer>,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl DebugLines {
+    /// Lines beyond this many vertices are dropped.
+    pub const MAX_VERTICES: usize = 1 << 16;
+
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        uniforms_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let buffer = device.create_tracked_buffer(GpuCategory::Other, &wgpu::BufferDescriptor {
+            label: Some("Debug Line Buffer"),
+            size: (Self::MAX_VERTICES * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!("debug_line.vert.spv"));
+        let fs_module = device.create_shader_module(&wgpu::include_spirv!("debug_line.frag.spv"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Line Pipeline Layout"),
+            bind_group_layouts: &[uniforms_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Line Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &vs_module,
+                entry_point: "main",
+                buffers: &[LineVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_module,
+                entry_point: "main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+        });
+
+        Self {
+            vertices: Vec::new(),
+            num_vertices: 0,
+            buffer,
+            pipeline,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn line(&mut self, a: Point3<f32>, b: Point3<f32>, color: [f32; 3]) {
+        self.translucent_line(a, b, color, 1.);
+    }
+
+    /// A line blended over the scene with `alpha` opacity, for helpers that shouldn't hide it.
+    pub fn translucent_line(
+        &mut self,
+        a: Point3<f32>,
+        b: Point3<f32>,
+        color: [f32; 3],
+        alpha: f32,
+    ) {
+        let color = [color[0], color[1], color[2], alpha];
+        self.vertices.push(LineVertex {
+            position: a.into(),
+            color,
+        });
+        self.vertices.push(LineVertex {
+            position: b.into(),
+            color,
+        });
+    }
+
+    /// Draws the twelve edges of a box given its corners, indexed by the bits (x, y, z) of the corner.
+    pub fn cuboid(&mut self, corners: &[Point3<f32>; 8], color: [f32; 3]) {
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    self.line(corners[i], corners[i | axis], color);
+                }
+            }
+        }
+    }
+
+    /// A circle around `center` in the plane facing `normal`, as a polygon of `segments` sides.
+    pub fn circle(
+        &mut self,
+        center: Point3<f32>,
+        normal: Vector3<f32>,
+        radius: f32,
+        segments: u32,
+        color: [f32; 3],
+    ) {
+        let normal = normal.normalize();
+        let helper = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+        let u = normal.cross(helper).normalize() * radius;
+        let v = normal.cross(u);
+        let point = |i: u32| {
+            let (sin, cos) = (i as f32 / segments as f32 * std::f32::consts::TAU).sin_cos();
+            center + u * cos + v * sin
+        };
+        for i in 0..segments {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 3]) {
+        if aabb.is_empty() {
+            return;
+        }
+        let (min, max) = (aabb.min, aabb.max);
+        let mut corners = [min; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            *corner = Point3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+        }
+        self.cuboid(&corners, color);
+    }
+
+    pub fn upload(&mut self, queue: &wgpu::Queue) {
+        let len = self.vertices.len().min(Self::MAX_VERTICES) & !1;
+        if len > 0 {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.vertices[..len]));
+        }
+        self.num_vertices = len as u32;
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, uniforms: &'a wgpu::BindGroup) {
+        if self.num_vertices == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, uniforms, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.draw(0..self.num_vertices, 0..1);
+    }
+}
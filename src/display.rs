@@ -0,0 +1,80 @@
+use std::hash::{Hash, Hasher};
+
+/// How the viewport colors the meshes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisplayMode {
+    /// Textured and lit as the materials say.
+    Shaded,
+    /// A color per mesh, to see how a file was split into objects.
+    Object,
+    /// A color per material.
+    Material,
+    /// The same color everywhere.
+    Flat,
+}
+
+impl DisplayMode {
+    pub const ALL: [DisplayMode; 4] = [
+        DisplayMode::Shaded,
+        DisplayMode::Object,
+        DisplayMode::Material,
+        DisplayMode::Flat,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisplayMode::Shaded => "Shaded",
+            DisplayMode::Object => "Object",
+            DisplayMode::Material => "Material",
+            DisplayMode::Flat => "Flat",
+        }
+    }
+}
+
+/// Viewport display colors, drawn lit but without the textures.
+#[derive(Debug, Clone)]
+pub struct Display {
+    pub mode: DisplayMode,
+    pub flat_color: [f32; 3],
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self {
+            mode: DisplayMode::Shaded,
+            flat_color: [0.8, 0.8, 0.8],
+        }
+    }
+}
+
+impl Display {
+    /// The color replacing the textures of a model's mesh, none while shaded.
+    pub fn color(&self, model: usize, mesh: usize, material: &str) -> Option<[f32; 3]> {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        match self.mode {
+            DisplayMode::Shaded => return None,
+            DisplayMode::Flat => return Some(self.flat_color),
+            DisplayMode::Object => (model, mesh).hash(&mut hasher),
+            DisplayMode::Material => material.hash(&mut hasher),
+        }
+        Some(Self::hashed_color(hasher.finish()))
+    }
+
+    /// A saturated color that stays the same for the same key.
+    fn hashed_color(key: u64) -> [f32; 3] {
+        let hue = (key % 360) as f32 / 60.;
+        let (saturation, value) = (0.65, 0.9);
+        let chroma = value * saturation;
+        let x = chroma * (1. - (hue % 2. - 1.).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (chroma, x, 0.),
+            1 => (x, chroma, 0.),
+            2 => (0., chroma, x),
+            3 => (0., x, chroma),
+            4 => (x, 0., chroma),
+            _ => (chroma, 0., x),
+        };
+        let m = value - chroma;
+        [r + m, g + m, b + m]
+    }
+}
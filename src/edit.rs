@@ -0,0 +1,1193 @@
+use std::collections::{BTreeSet, HashMap};
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3, Zero};
+
+use crate::{
+    debug_lines::DebugLines,
+    mesh::{
+        selection::{face_center, Element, SelectMode, Selection},
+        topology::{EdgeRing, HalfEdgeMesh, Plane},
+    },
+    model::{Mesh, Model, ModelVertex, ObjModel},
+    picking::{to_screen, Ray},
+    scene::Scene,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditTool {
+    /// Selects the clicked vertex, edge or face, depending on the select mode.
+    Select,
+    /// Drags the selection, or the clicked element without one, on a plane facing the camera.
+    Move,
+    /// Selects the edge loop through the clicked edge.
+    SelectLoop,
+    /// Selects what is inside a rectangle dragged on the screen.
+    BoxSelect,
+    /// Selects what is inside a shape drawn on the screen.
+    LassoSelect,
+    /// Cuts a new loop across the ring of quads of the clicked edge, sliding it while dragging.
+    LoopCut,
+    /// Cuts along a line clicked point by point on the screen, through the whole mesh.
+    Knife,
+    /// Cuts the mesh in two with the plane of a line dragged across the screen.
+    PlaneCut,
+}
+
+impl EditTool {
+    pub const ALL: [EditTool; 8] = [
+        EditTool::Select,
+        EditTool::Move,
+        EditTool::SelectLoop,
+        EditTool::BoxSelect,
+        EditTool::LassoSelect,
+        EditTool::LoopCut,
+        EditTool::Knife,
+        EditTool::PlaneCut,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EditTool::Select => "Select",
+            EditTool::Move => "Move",
+            EditTool::SelectLoop => "Select loop",
+            EditTool::BoxSelect => "Box select",
+            EditTool::LassoSelect => "Lasso select",
+            EditTool::LoopCut => "Loop cut",
+            EditTool::Knife => "Knife",
+            EditTool::PlaneCut => "Plane cut",
+        }
+    }
+}
+
+impl Default for EditTool {
+    fn default() -> Self {
+        EditTool::Select
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BevelSettings {
+    pub width: f32,
+    pub segments: u32,
+}
+
+impl Default for BevelSettings {
+    fn default() -> Self {
+        Self {
+            width: 0.05,
+            segments: 1,
+        }
+    }
+}
+
+/// How the pull of a moved selection fades with the distance from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Falloff {
+    Smooth,
+    Sphere,
+    Root,
+    Linear,
+    Sharp,
+    Constant,
+}
+
+impl Falloff {
+    pub const ALL: [Falloff; 6] = [
+        Falloff::Smooth,
+        Falloff::Sphere,
+        Falloff::Root,
+        Falloff::Linear,
+        Falloff::Sharp,
+        Falloff::Constant,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Falloff::Smooth => "Smooth",
+            Falloff::Sphere => "Sphere",
+            Falloff::Root => "Root",
+            Falloff::Linear => "Linear",
+            Falloff::Sharp => "Sharp",
+            Falloff::Constant => "Constant",
+        }
+    }
+
+    /// From 1 at the selection to 0 at `radius` away from it.
+    pub fn weight(&self, distance: f32, radius: f32) -> f32 {
+        let t = (distance / radius).max(0.).min(1.);
+        let x = 1. - t;
+        match self {
+            Falloff::Smooth => x * x * (3. - 2. * x),
+            Falloff::Sphere => (1. - t * t).sqrt(),
+            Falloff::Root => x.sqrt(),
+            Falloff::Linear => x,
+            Falloff::Sharp => x * x,
+            Falloff::Constant if t < 1. => 1.,
+            Falloff::Constant => 0.,
+        }
+    }
+}
+
+/// Proportional editing, moving the vertices near the selection along with it.
+#[derive(Debug, Clone, Copy)]
+pub struct ProportionalSettings {
+    pub enabled: bool,
+    pub radius: f32,
+    pub falloff: Falloff,
+}
+
+impl Default for ProportionalSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            radius: 0.5,
+            falloff: Falloff::Smooth,
+        }
+    }
+}
+
+/// Operations on the whole selection, asked for by the GUI and applied on the next update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditOperation {
+    Bevel,
+    /// Cuts along the knife line.
+    Knife,
+    /// Cuts through the whole target along the active construction plane.
+    ConstructionPlaneCut,
+    Undo,
+}
+
+fn draw_edges(mesh: &HalfEdgeMesh, edges: &[[u32; 2]], lines: &mut DebugLines, color: [f32; 3]) {
+    for &[a, b] in edges {
+        lines.line(mesh.position(a), mesh.position(b), color);
+    }
+}
+
+fn draw_cut(mesh: &HalfEdgeMesh, ring: &EdgeRing, factor: f32, lines: &mut DebugLines) {
+    const COLOR: [f32; 3] = [1.0, 0.8, 0.1];
+    let points = ring
+        .edges
+        .iter()
+        .map(|&[a, b]| {
+            let (a, b) = (mesh.position(a), mesh.position(b));
+            a + (b - a) * factor
+        })
+        .collect::<Vec<_>>();
+    for pair in points.windows(2) {
+        lines.line(pair[0], pair[1], COLOR);
+    }
+    if ring.closed && points.len() > 2 {
+        lines.line(points[points.len() - 1], points[0], COLOR);
+    }
+}
+
+/// The plane through the camera and the screen-space line between the rays, and the two planes
+/// facing inwards that bound the part of it between them.
+fn cut_planes(from: &Ray, to: &Ray) -> Option<(Plane, [Plane; 2])> {
+    let normal = from.direction.cross(to.direction);
+    if normal.magnitude2() < 1e-12 {
+        return None;
+    }
+    let normal = normal.normalize();
+    let sides = [
+        Plane::new(from.origin, normal.cross(from.direction)),
+        Plane::new(to.origin, to.direction.cross(normal)),
+    ];
+    Some((Plane::new(from.origin, normal), sides))
+}
+
+/// Whether the point is inside the polygon, by the even-odd rule.
+fn inside_polygon(point: [f32; 2], polygon: &[[f32; 2]]) -> bool {
+    let mut inside = false;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        if (a[1] > point[1]) != (b[1] > point[1])
+            && point[0] < a[0] + (b[0] - a[0]) * (point[1] - a[1]) / (b[1] - a[1])
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Position along the segment from `a` to `b`, from 0 to 1, closest to the ray.
+fn closest_on_segment(a: Point3<f32>, b: Point3<f32>, ray: &Ray) -> f32 {
+    let d = b - a;
+    let w = a - ray.origin;
+    let (dd, de) = (d.dot(d), d.dot(ray.direction));
+    let denominator = dd - de * de;
+    if denominator.abs() < 1e-12 {
+        return 0.5;
+    }
+    let t = (de * w.dot(ray.direction) - w.dot(d)) / denominator;
+    t.max(0.).min(1.)
+}
+
+#[derive(Debug)]
+struct EditTarget {
+    model: usize,
+    mesh: usize,
+    topology: HalfEdgeMesh,
+    /// Face of every triangle in the uploaded mesh, for picking.
+    triangle_face: Vec<u32>,
+    /// Polygon mesh vertex of every vertex in the uploaded mesh, for moving them in place.
+    buffer_vertex: Vec<u32>,
+    /// Size of the vertex markers, relative to the mesh.
+    marker_size: f32,
+}
+
+/// The polygon mesh of a target from before an operation.
+#[derive(Debug)]
+struct UndoEntry {
+    name: &'static str,
+    model: usize,
+    mesh: usize,
+    topology: HalfEdgeMesh,
+    /// Whether the operation only moved vertices, so undo can move them back without
+    /// renumbering the uploaded mesh's vertices.
+    in_place: bool,
+}
+
+/// A point of a line drawn on the screen, with where it is drawn in the scene.
+#[derive(Debug, Clone, Copy)]
+struct LinePoint {
+    ray: Ray,
+    screen: [f32; 2],
+    point: Point3<f32>,
+}
+
+/// The selection being dragged.
+#[derive(Debug)]
+struct Move {
+    plane: Plane,
+    start: Point3<f32>,
+    offset: Vector3<f32>,
+    /// The polygon mesh from before the move, which every drag starts over from.
+    original: HalfEdgeMesh,
+    selected: BTreeSet<u32>,
+    /// Moved vertices and how far they follow the selection.
+    weights: Vec<(u32, f32)>,
+}
+
+/// The moved vertices, the selected ones fully and, with proportional editing, the ones within
+/// the radius of the closest selected vertex by the falloff.
+fn move_weights(
+    mesh: &HalfEdgeMesh,
+    selected: &BTreeSet<u32>,
+    settings: &ProportionalSettings,
+) -> Vec<(u32, f32)> {
+    let mut weights = selected.iter().map(|&v| (v, 1.)).collect::<Vec<_>>();
+    if !settings.enabled {
+        return weights;
+    }
+    let positions = selected
+        .iter()
+        .map(|&v| mesh.position(v))
+        .collect::<Vec<_>>();
+    for v in 0..mesh.num_vertices() as u32 {
+        if selected.contains(&v) || mesh.vertex(v).half_edge.is_none() {
+            continue;
+        }
+        let p = mesh.position(v);
+        let distance = positions
+            .iter()
+            .map(|&s| (p - s).magnitude())
+            .fold(f32::INFINITY, f32::min);
+        let weight = settings.falloff.weight(distance, settings.radius);
+        if weight > 0. {
+            weights.push((v, weight));
+        }
+    }
+    weights
+}
+
+/// Where the ray crosses the plane, if it isn't parallel to it.
+fn intersect_plane(ray: &Ray, plane: &Plane) -> Option<Point3<f32>> {
+    let denominator = ray.direction.dot(plane.normal);
+    if denominator.abs() < 1e-6 {
+        return None;
+    }
+    Some(ray.at(-plane.distance(ray.origin) / denominator))
+}
+
+fn draw_circle(
+    center: Point3<f32>,
+    normal: Vector3<f32>,
+    radius: f32,
+    lines: &mut DebugLines,
+    color: [f32; 3],
+) {
+    const SEGMENTS: usize = 48;
+    let helper = if normal.x.abs() < 0.9 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+    let u = normal.cross(helper).normalize() * radius;
+    let v = normal.cross(u);
+    let point = |i: usize| {
+        let angle = i as f32 / SEGMENTS as f32 * std::f32::consts::TAU;
+        center + u * angle.cos() + v * angle.sin()
+    };
+    for i in 0..SEGMENTS {
+        lines.line(point(i), point(i + 1), color);
+    }
+}
+
+#[derive(Debug)]
+struct Slide {
+    ring: EdgeRing,
+    /// The ring edge that was grabbed, following the cursor.
+    edge: usize,
+    factor: f32,
+}
+
+/// Polygon editing of the mesh under the cursor.
+#[derive(Debug, Default)]
+pub struct EditMode {
+    pub enabled: bool,
+    pub tool: EditTool,
+    pub bevel: BevelSettings,
+    pub proportional: ProportionalSettings,
+    /// Whether cuts split the mesh into separate models where it falls apart.
+    pub separate_pieces: bool,
+    target: Option<EditTarget>,
+    hovered: Option<u32>,
+    /// The vertex of the hovered face closest to the cursor.
+    hovered_vertex: Option<u32>,
+    selection: Selection,
+    /// Outline of the box or lasso being dragged.
+    region: Vec<LinePoint>,
+    /// Whether the region adds to the selection instead of replacing it.
+    extend_region: bool,
+    slide: Option<Slide>,
+    moving: Option<Move>,
+    knife: Vec<LinePoint>,
+    /// Start and end of the plane cut line being dragged.
+    plane_line: Option<[LinePoint; 2]>,
+    pending: Option<EditOperation>,
+    history: Vec<UndoEntry>,
+}
+
+impl EditMode {
+    const HOVER_COLOR: [f32; 3] = [0.4, 0.8, 1.0];
+    const SELECTED_COLOR: [f32; 3] = [1.0, 0.5, 0.1];
+    const KNIFE_COLOR: [f32; 3] = [1.0, 0.2, 0.2];
+    const MAX_UNDO: usize = 32;
+
+    /// Drops the polygon mesh being edited, e.g. after something else changed the mesh.
+    pub fn release_target(&mut self) {
+        self.target = None;
+        self.hovered = None;
+        self.hovered_vertex = None;
+        self.selection.clear();
+        self.region.clear();
+        self.slide = None;
+        self.moving = None;
+        self.knife.clear();
+        self.plane_line = None;
+    }
+
+    /// The model whose mesh is being edited.
+    pub fn target_model(&self) -> Option<usize> {
+        self.target.as_ref().map(|target| target.model)
+    }
+
+    /// Number of points on the knife line.
+    pub fn knife_len(&self) -> usize {
+        self.knife.len()
+    }
+
+    pub fn clear_knife(&mut self) {
+        self.knife.clear();
+    }
+
+    pub fn selection(&self) -> &Selection {
+        &self.selection
+    }
+
+    /// The target's model and mesh with the uploaded vertices of the selected polygon
+    /// vertices, if anything is selected.
+    pub fn selected_vertices(&self) -> Option<(usize, usize, Vec<usize>)> {
+        let target = self.target.as_ref()?;
+        let selected = self.selection.vertices(&target.topology);
+        if selected.is_empty() {
+            return None;
+        }
+        let vertices = target
+            .buffer_vertex
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| selected.contains(v))
+            .map(|(i, _)| i)
+            .collect();
+        Some((target.model, target.mesh, vertices))
+    }
+
+    /// Gives the corners of the target's polygon mesh the normals of the uploaded `vertices`
+    /// about to change, so later edits keep them. Call before the vertices are changed.
+    pub fn set_normals(
+        &mut self,
+        model: usize,
+        mesh: usize,
+        vertices: &[ModelVertex],
+        normals: &[(usize, [f32; 3])],
+    ) {
+        let target = match &mut self.target {
+            Some(target) if target.model == model && target.mesh == mesh => target,
+            _ => return,
+        };
+        // Corners are told apart by the same attributes `to_buffers` splits vertices by.
+        let key = |v: u32, tex_coords: [f32; 2], normal: [f32; 3]| {
+            (v, tex_coords.map(f32::to_bits), normal.map(f32::to_bits))
+        };
+        let changed = normals
+            .iter()
+            .map(|&(i, normal)| {
+                let vertex = &vertices[i];
+                let v = target.buffer_vertex[i];
+                (key(v, vertex.tex_coords, vertex.normal), normal)
+            })
+            .collect::<HashMap<_, _>>();
+        let topology = &mut target.topology;
+        let half_edges = topology
+            .faces()
+            .flat_map(|f| topology.face_half_edges(f))
+            .collect::<Vec<_>>();
+        for h in half_edges {
+            let half_edge = topology.half_edge(h);
+            let corner = half_edge.corner;
+            if let Some(&normal) =
+                changed.get(&key(half_edge.origin, corner.tex_coords, corner.normal.into()))
+            {
+                topology.set_corner_normal(h, normal.into());
+            }
+        }
+    }
+
+    /// Changes the selection with the target's polygon mesh, if there is one.
+    fn change_selection(&mut self, change: impl FnOnce(&mut Selection, &HalfEdgeMesh)) {
+        if let Some(target) = &self.target {
+            change(&mut self.selection, &target.topology);
+        }
+    }
+
+    pub fn set_select_mode(&mut self, mode: SelectMode) {
+        // Without a target the selection is empty, so any mesh will do.
+        let empty = HalfEdgeMesh::default();
+        let mesh = self.target.as_ref().map(|t| &t.topology).unwrap_or(&empty);
+        self.selection.set_mode(mode, mesh);
+    }
+
+    pub fn select_linked(&mut self) {
+        self.change_selection(Selection::select_linked);
+    }
+
+    pub fn invert_selection(&mut self) {
+        self.change_selection(Selection::invert);
+    }
+
+    pub fn grow_selection(&mut self) {
+        self.change_selection(Selection::grow);
+    }
+
+    pub fn shrink_selection(&mut self) {
+        self.change_selection(Selection::shrink);
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection.clear();
+    }
+
+    /// Applies the operation on the next update.
+    pub fn request(&mut self, operation: EditOperation) {
+        self.pending = Some(operation);
+    }
+
+    /// Name of the operation undo would revert.
+    pub fn undo_name(&self) -> Option<&'static str> {
+        self.history.last().map(|entry| entry.name)
+    }
+
+    /// Remembers the target's polygon mesh before it gets changed by `name`.
+    fn push_undo(&mut self, name: &'static str, in_place: bool) {
+        if let Some(target) = &self.target {
+            if self.history.len() == Self::MAX_UNDO {
+                self.history.remove(0);
+            }
+            self.history.push(UndoEntry {
+                name,
+                model: target.model,
+                mesh: target.mesh,
+                topology: target.topology.clone(),
+                in_place,
+            });
+        }
+    }
+
+    pub fn is_sliding(&self) -> bool {
+        self.slide.is_some()
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.moving.is_some()
+    }
+
+    /// Grows the proportional radius by a tenth per scrolled line, shrinking it when scrolling
+    /// back, and pulls the vertices now in or out of reach along with the selection.
+    pub fn scale_proportional_radius(&mut self, lines: f32) {
+        self.proportional.radius = (self.proportional.radius * 1.1f32.powf(lines))
+            .max(0.001)
+            .min(100.);
+        if let Some(moving) = &mut self.moving {
+            moving.weights = move_weights(&moving.original, &moving.selected, &self.proportional);
+        }
+        self.apply_move();
+    }
+
+    /// Moves the dragged vertices by the offset, starting over from the mesh before the move.
+    fn apply_move(&mut self) {
+        let (moving, target) = match (&mut self.moving, &mut self.target) {
+            (Some(moving), Some(target)) => (moving, target),
+            _ => return,
+        };
+        let mut topology = moving.original.clone();
+        for &(v, weight) in moving.weights.iter() {
+            topology.set_position(v, moving.original.position(v) + moving.offset * weight);
+        }
+        topology.update_normals(&moving.weights.iter().map(|&(v, _)| v).collect());
+        target.topology = topology;
+    }
+
+    pub fn draw(&self, lines: &mut DebugLines) {
+        let target = match (&self.target, self.enabled) {
+            (Some(target), true) => target,
+            _ => return,
+        };
+        let mesh = &target.topology;
+        for element in self.selection.elements() {
+            draw_element(target, element, lines, Self::SELECTED_COLOR);
+        }
+        let knife = self.knife.iter().chain(self.plane_line.iter().flatten());
+        for pair in knife.collect::<Vec<_>>().windows(2) {
+            lines.line(pair[0].point, pair[1].point, Self::KNIFE_COLOR);
+        }
+        for (i, a) in self.region.iter().enumerate() {
+            let b = &self.region[(i + 1) % self.region.len()];
+            lines.line(a.point, b.point, Self::HOVER_COLOR);
+        }
+        if let Some(slide) = &self.slide {
+            draw_cut(mesh, &slide.ring, slide.factor, lines);
+            return;
+        }
+        if let Some(moving) = &self.moving {
+            if self.proportional.enabled {
+                let center = moving.start + moving.offset;
+                let (normal, radius) = (moving.plane.normal, self.proportional.radius);
+                draw_circle(center, normal, radius, lines, Self::HOVER_COLOR);
+            }
+            return;
+        }
+        if let Some(h) = self.hovered {
+            match self.tool {
+                EditTool::Select | EditTool::Move => {
+                    if let Some(element) = self.hovered_element(target, h) {
+                        draw_element(target, &element, lines, Self::HOVER_COLOR);
+                    }
+                }
+                EditTool::SelectLoop => {
+                    draw_edges(mesh, &mesh.edge_loop(h), lines, Self::HOVER_COLOR)
+                }
+                EditTool::LoopCut => draw_cut(mesh, &mesh.edge_ring(h), 0.5, lines),
+                EditTool::BoxSelect
+                | EditTool::LassoSelect
+                | EditTool::Knife
+                | EditTool::PlaneCut => {}
+            }
+        }
+    }
+
+    /// The element the select tool would pick for the hovered half-edge.
+    fn hovered_element(&self, target: &EditTarget, h: u32) -> Option<Element> {
+        let mesh = &target.topology;
+        match self.selection.mode() {
+            SelectMode::Vertex => self.hovered_vertex.map(Element::Vertex),
+            SelectMode::Edge => Some(Element::edge(mesh.origin(h), mesh.dest(h))),
+            SelectMode::Face => Some(Element::Face(mesh.face_of(h))),
+        }
+    }
+}
+
+/// The polygon mesh vertex at the position of every vertex, as vertices are welded by position.
+fn buffer_vertices(mesh: &HalfEdgeMesh, vertices: &[ModelVertex]) -> Vec<u32> {
+    let mut welded = HashMap::new();
+    for v in (0..mesh.num_vertices() as u32).filter(|&v| mesh.vertex(v).half_edge.is_some()) {
+        let position: [f32; 3] = mesh.position(v).into();
+        welded.entry(position.map(f32::to_bits)).or_insert(v);
+    }
+    vertices
+        .iter()
+        .map(|vertex| welded[&vertex.position.map(f32::to_bits)])
+        .collect()
+}
+
+fn marker_size(mesh: &Mesh) -> f32 {
+    mesh.bounds.size().magnitude() * 0.005
+}
+
+fn draw_element(target: &EditTarget, element: &Element, lines: &mut DebugLines, color: [f32; 3]) {
+    let mesh = &target.topology;
+    match *element {
+        Element::Vertex(v) => {
+            let p = mesh.position(v);
+            let size = target.marker_size;
+            for axis in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+                lines.line(p - axis * size, p + axis * size, color);
+            }
+        }
+        Element::Edge(edge) => draw_edges(mesh, &[edge], lines, color),
+        Element::Face(f) => {
+            let edges = mesh
+                .face_half_edges(f)
+                .into_iter()
+                .map(|h| [mesh.origin(h), mesh.dest(h)])
+                .collect::<Vec<_>>();
+            draw_edges(mesh, &edges, lines, color);
+            // A smaller copy of the outline tells faces apart from edges.
+            let center = face_center(mesh, f);
+            let inset = |v: u32| center + (mesh.position(v) - center) * 0.8;
+            for [a, b] in edges {
+                lines.line(inset(a), inset(b), color);
+            }
+        }
+    }
+}
+
+impl Scene {
+    /// Finds the edge under the ray, or follows the cursor with whatever is being dragged.
+    pub fn hover_edit(&mut self, ray: &Ray) {
+        if self.edit.plane_line.is_some() {
+            let end = self.line_point(ray, true);
+            if let (Some(line), Some(end)) = (&mut self.edit.plane_line, end) {
+                line[1] = end;
+            }
+            return;
+        }
+        if !self.edit.region.is_empty() {
+            self.drag_region(ray);
+            return;
+        }
+        if let Some(slide) = &mut self.edit.slide {
+            if let Some(target) = &self.edit.target {
+                let [a, b] = slide.ring.edges[slide.edge].map(|v| target.topology.position(v));
+                slide.factor = closest_on_segment(a, b, ray).max(0.02).min(0.98);
+            }
+            return;
+        }
+        if let Some(moving) = &mut self.edit.moving {
+            if let Some(point) = intersect_plane(ray, &moving.plane) {
+                moving.offset = point - moving.start;
+                self.edit.apply_move();
+                self.write_edit_positions();
+            }
+            return;
+        }
+        let hit = match self.raycast(ray) {
+            Some(hit) => hit,
+            None => {
+                self.edit.hovered = None;
+                self.edit.hovered_vertex = None;
+                return;
+            }
+        };
+        let is_target = self
+            .edit
+            .target
+            .as_ref()
+            .map(|t| t.model == hit.model && t.mesh == hit.mesh)
+            .unwrap_or(false);
+        if !is_target {
+            let mesh = &self.models[hit.model].meshes()[hit.mesh];
+            let (topology, triangle_face) =
+                HalfEdgeMesh::from_buffers(&mesh.vertices, &mesh.indices);
+            self.edit.release_target();
+            self.edit.target = Some(EditTarget {
+                model: hit.model,
+                mesh: hit.mesh,
+                buffer_vertex: buffer_vertices(&topology, &mesh.vertices),
+                topology,
+                triangle_face,
+                marker_size: marker_size(mesh),
+            });
+        }
+        let target = self.edit.target.as_ref().unwrap();
+        let mesh = &target.topology;
+        let face = target.triangle_face[hit.triangle / 3];
+        let distance = |v: &u32| (mesh.position(*v) - hit.point).magnitude2();
+        self.edit.hovered = Some(mesh.closest_half_edge(face, hit.point));
+        self.edit.hovered_vertex = mesh
+            .face_vertices(face)
+            .into_iter()
+            .min_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap());
+    }
+
+    /// The point of a line drawn on the screen for the ray, on the surface under it if asked
+    /// and there is one, or else on the active construction plane or at the depth of the
+    /// target.
+    fn line_point(&self, ray: &Ray, on_surface: bool) -> Option<LinePoint> {
+        let target = self.edit.target.as_ref()?;
+        let bounds = &self.models[target.model].meshes()[target.mesh].bounds;
+        let depth = (bounds.center() - ray.origin).magnitude();
+        let point = match self.raycast(ray).filter(|_| on_surface) {
+            Some(hit) => hit.point,
+            None => match self.construction.point(ray).filter(|_| on_surface) {
+                Some(point) => point,
+                None => ray.at(depth),
+            },
+        };
+        let screen = to_screen(&self.camera, ray.at(depth))?;
+        Some(LinePoint {
+            ray: *ray,
+            screen,
+            point,
+        })
+    }
+
+    /// Follows the cursor with the box or lasso.
+    fn drag_region(&mut self, ray: &Ray) {
+        let end = match self.line_point(ray, false) {
+            Some(end) => end,
+            None => return,
+        };
+        match self.edit.tool {
+            EditTool::BoxSelect => {
+                let start = self.edit.region[0].screen;
+                let corners = [
+                    start,
+                    [end.screen[0], start[1]],
+                    end.screen,
+                    [start[0], end.screen[1]],
+                ];
+                let region = corners
+                    .iter()
+                    .filter_map(|&corner| {
+                        self.line_point(&Ray::from_screen(&self.camera, corner), false)
+                    })
+                    .collect::<Vec<_>>();
+                if region.len() == corners.len() {
+                    self.edit.region = region;
+                }
+            }
+            _ => {
+                const SPACING: f32 = 0.01;
+                let last = self.edit.region[self.edit.region.len() - 1].screen;
+                let (dx, dy) = (end.screen[0] - last[0], end.screen[1] - last[1]);
+                if dx * dx + dy * dy > SPACING * SPACING {
+                    self.edit.region.push(end);
+                }
+            }
+        }
+    }
+
+    /// Applies the current tool at the ray. `extend` adds to the selection instead of
+    /// replacing it. Returns whether there was something to act on.
+    pub fn begin_edit_action(&mut self, ray: &Ray, extend: bool) -> bool {
+        self.hover_edit(ray);
+        // The cutting and region tools work on the last mesh hovered, as lines start off the
+        // mesh.
+        match self.edit.tool {
+            EditTool::Knife => {
+                let point = self.line_point(ray, true);
+                self.edit.knife.extend(point);
+                return point.is_some();
+            }
+            EditTool::PlaneCut => {
+                self.edit.plane_line = self.line_point(ray, true).map(|point| [point; 2]);
+                return self.edit.plane_line.is_some();
+            }
+            EditTool::BoxSelect | EditTool::LassoSelect => {
+                self.edit.region = self.line_point(ray, false).into_iter().collect();
+                self.edit.extend_region = extend;
+                return !self.edit.region.is_empty();
+            }
+            _ => {}
+        }
+        let (target, h) = match (&self.edit.target, self.edit.hovered) {
+            (Some(target), Some(h)) => (target, h),
+            _ => return false,
+        };
+        match self.edit.tool {
+            EditTool::Select => {
+                if let Some(element) = self.edit.hovered_element(target, h) {
+                    let selection = &mut self.edit.selection;
+                    if !extend {
+                        selection.clear();
+                    }
+                    selection.toggle(element);
+                }
+            }
+            EditTool::Move => {
+                if self.edit.selection.is_empty() {
+                    if let Some(element) = self.edit.hovered_element(target, h) {
+                        self.edit.selection.toggle(element);
+                    }
+                }
+                return self.begin_move(ray);
+            }
+            EditTool::SelectLoop => {
+                if !extend {
+                    self.edit.selection.clear();
+                }
+                let edges = target.topology.edge_loop(h);
+                self.edit.selection.select_edges(&target.topology, &edges);
+            }
+            EditTool::LoopCut => {
+                let ring = target.topology.edge_ring(h);
+                let edge = [target.topology.origin(h), target.topology.dest(h)];
+                let edge = ring.edges.iter().position(|&e| e == edge).unwrap_or(0);
+                self.edit.slide = Some(Slide {
+                    ring,
+                    edge,
+                    factor: 0.5,
+                });
+            }
+            EditTool::BoxSelect | EditTool::LassoSelect | EditTool::Knife | EditTool::PlaneCut => {}
+        }
+        true
+    }
+
+    /// Starts dragging the selected vertices on the plane through their center facing the
+    /// camera. Cloth and modifiers of the model stay baked into the mesh, as they would fight
+    /// the move. Shape keys are kept, as the vertices move in place.
+    fn begin_move(&mut self, ray: &Ray) -> bool {
+        let target = match &self.edit.target {
+            Some(target) => target,
+            None => return false,
+        };
+        let selected = self.edit.selection.vertices(&target.topology);
+        if selected.is_empty() {
+            return false;
+        }
+        let center = selected.iter().fold(Point3::origin(), |sum, &v| {
+            sum + target.topology.position(v).to_vec()
+        }) / selected.len() as f32;
+        let plane = Plane::new(center, -ray.direction);
+        let start = match intersect_plane(ray, &plane) {
+            Some(start) => start,
+            None => return false,
+        };
+        let weights = move_weights(&target.topology, &selected, &self.edit.proportional);
+        let model = target.model;
+        self.edit.moving = Some(Move {
+            plane,
+            start,
+            offset: Vector3::zero(),
+            original: target.topology.clone(),
+            selected,
+            weights,
+        });
+        self.shape_changed(model);
+        true
+    }
+
+    /// Finishes a loop cut being slid, a selection being moved, a plane cut line or a box or
+    /// lasso being dragged. Returns whether there was one.
+    pub fn end_edit_action(&mut self, device: &wgpu::Device) -> bool {
+        if let Some(moving) = self.edit.moving.take() {
+            let target = match &mut self.edit.target {
+                Some(target) if moving.offset != Vector3::zero() => target,
+                _ => return true,
+            };
+            // The undo entry has to hold the mesh from before the move.
+            let moved = std::mem::replace(&mut target.topology, moving.original);
+            let model = target.model;
+            self.edit.push_undo("Move", true);
+            if let Some(target) = &mut self.edit.target {
+                target.topology = moved;
+            }
+            self.shape_changed(model);
+            return true;
+        }
+        if let Some([from, to]) = self.edit.plane_line.take() {
+            self.cut_target(device, &[[from.ray, to.ray]], false);
+            return true;
+        }
+        if !self.edit.region.is_empty() {
+            self.select_region();
+            return true;
+        }
+        let slide = match self.edit.slide.take() {
+            Some(slide) => slide,
+            None => return false,
+        };
+        self.edit.push_undo("Loop cut", false);
+        let target = match &mut self.edit.target {
+            Some(target) => target,
+            None => return false,
+        };
+        if let Err(e) = target.topology.loop_cut(&slide.ring, slide.factor) {
+            log::warn!("Loop cut failed: {:?}", e);
+        }
+        self.upload_edit_target(device);
+        true
+    }
+
+    /// Selects what shows inside the box or lasso. Hidden elements are selected too.
+    fn select_region(&mut self) {
+        let region = std::mem::take(&mut self.edit.region);
+        let target = match &self.edit.target {
+            Some(target) => target,
+            None => return,
+        };
+        let polygon = region.iter().map(|p| p.screen).collect::<Vec<_>>();
+        let selection = &mut self.edit.selection;
+        if !self.edit.extend_region {
+            selection.clear();
+        }
+        if polygon.len() < 3 {
+            return;
+        }
+        let camera = &self.camera;
+        selection.select_inside(&target.topology, |p| {
+            to_screen(camera, p)
+                .map(|screen| inside_polygon(screen, &polygon))
+                .unwrap_or(false)
+        });
+    }
+
+    /// Applies the operation the GUI asked for, if any.
+    pub fn apply_edit_operation(&mut self, device: &wgpu::Device) {
+        profile_function!();
+        match self.edit.pending.take() {
+            Some(EditOperation::Bevel) => self.bevel_selection(device),
+            Some(EditOperation::Knife) => {
+                let knife = std::mem::take(&mut self.edit.knife);
+                let lines = knife
+                    .windows(2)
+                    .map(|pair| [pair[0].ray, pair[1].ray])
+                    .collect::<Vec<_>>();
+                self.cut_target(device, &lines, true);
+            }
+            Some(EditOperation::ConstructionPlaneCut) => self.cut_along_construction_plane(device),
+            Some(EditOperation::Undo) => self.undo_edit(device),
+            None => {}
+        }
+    }
+
+    fn bevel_selection(&mut self, device: &wgpu::Device) {
+        let settings = self.edit.bevel;
+        let target = match &self.edit.target {
+            Some(target) => target,
+            None => return,
+        };
+        let edges = self.edit.selection.edges(&target.topology);
+        if edges.is_empty() {
+            return;
+        }
+        let mut topology = target.topology.clone();
+        match topology.bevel_edges(&edges, settings.width, settings.segments) {
+            Ok(()) => {
+                self.edit.push_undo("Bevel", false);
+                if let Some(target) = &mut self.edit.target {
+                    target.topology = topology;
+                }
+                self.upload_edit_target(device);
+            }
+            Err(e) => log::warn!("Bevel failed: {:?}", e),
+        }
+    }
+
+    /// Cuts the target along the planes of the screen-space lines. `bounded` limits every cut
+    /// to the part of the mesh between the line's ends, otherwise the planes cut through all of
+    /// it.
+    fn cut_target(&mut self, device: &wgpu::Device, lines: &[[Ray; 2]], bounded: bool) {
+        let target = match &self.edit.target {
+            Some(target) => target,
+            None => return,
+        };
+        let mut topology = target.topology.clone();
+        let mut seams = Vec::new();
+        for [from, to] in lines {
+            if let Some((plane, sides)) = cut_planes(from, to) {
+                let keep = |p: Point3<f32>| !bounded || sides.iter().all(|s| s.distance(p) >= 0.);
+                seams.extend(topology.cut(&plane, keep));
+            }
+        }
+        let name = if bounded { "Knife" } else { "Plane cut" };
+        self.finish_cut(device, topology, seams, name);
+    }
+
+    /// Cuts the target through along the active construction plane.
+    fn cut_along_construction_plane(&mut self, device: &wgpu::Device) {
+        let (target, plane) = match (&self.edit.target, self.construction.active()) {
+            (Some(target), Some(plane)) => (target, plane.plane()),
+            _ => return,
+        };
+        let mut topology = target.topology.clone();
+        let seams = topology.cut(&plane, |_| true);
+        self.finish_cut(device, topology, seams, "Construction plane cut");
+    }
+
+    /// Takes the cut topology as the target's, split into models along the seams if asked.
+    fn finish_cut(
+        &mut self,
+        device: &wgpu::Device,
+        mut topology: HalfEdgeMesh,
+        seams: Vec<[u32; 2]>,
+        name: &'static str,
+    ) {
+        let target = match &self.edit.target {
+            Some(target) => target,
+            None => return,
+        };
+        if seams.is_empty() {
+            return;
+        }
+        let pieces = if self.edit.separate_pieces {
+            topology.pieces(&seams)
+        } else {
+            Vec::new()
+        };
+        let (model, mesh) = (target.model, target.mesh);
+        if pieces.len() > 1 {
+            // Undo can't take the new models away again, so the model's history goes.
+            self.edit.history.retain(|entry| entry.model != model);
+            let source = &self.models[model].meshes()[mesh];
+            let (name, material) = (source.name.clone(), source.material.clone());
+            for (i, faces) in pieces[1..].iter().enumerate() {
+                let buffers = topology.extract(faces).to_buffers();
+                let piece = Mesh::new(
+                    device,
+                    format!("{} piece {}", name, i + 1),
+                    buffers.vertices,
+                    buffers.indices,
+                    material.clone(),
+                );
+                self.add_model(Model::OBJ(ObjModel {
+                    meshes: vec![piece],
+                }));
+            }
+            topology = topology.extract(&pieces[0]);
+        } else {
+            self.edit.push_undo(name, false);
+        }
+        if let Some(target) = &mut self.edit.target {
+            target.topology = topology;
+        }
+        self.upload_edit_target(device);
+    }
+
+    /// Puts the mesh of the last edit operation back the way it was before it.
+    fn undo_edit(&mut self, device: &wgpu::Device) {
+        let entry = match self.edit.history.pop() {
+            Some(entry) => entry,
+            None => return,
+        };
+        let exists = self
+            .models
+            .get(entry.model)
+            .map(|model| entry.mesh < model.meshes().len())
+            .unwrap_or(false);
+        if !exists {
+            return;
+        }
+        if entry.in_place {
+            if let Some(target) = &mut self.edit.target {
+                let same_vertices = target.topology.num_vertices() == entry.topology.num_vertices();
+                if target.model == entry.model && target.mesh == entry.mesh && same_vertices {
+                    target.topology = entry.topology;
+                    self.write_edit_positions();
+                    self.shape_changed(entry.model);
+                    return;
+                }
+            }
+        }
+        self.edit.release_target();
+        self.edit.target = Some(EditTarget {
+            model: entry.model,
+            mesh: entry.mesh,
+            topology: entry.topology,
+            triangle_face: Vec::new(),
+            buffer_vertex: Vec::new(),
+            marker_size: 0.,
+        });
+        self.upload_edit_target(device);
+    }
+
+    /// Copies the positions of the target's polygon mesh into its uploaded mesh in place, so
+    /// the vertex order stays, e.g. for shape keys. Normals around the moved vertices are
+    /// recomputed from the triangles, so hard edges stay where the mesh has split vertices.
+    fn write_edit_positions(&mut self) {
+        let target = match &self.edit.target {
+            Some(target) => target,
+            None => return,
+        };
+        let mesh = &mut self.models[target.model].meshes_mut()[target.mesh];
+        let mut moved = vec![false; mesh.vertices.len()];
+        for (i, &v) in target.buffer_vertex.iter().enumerate() {
+            let position: [f32; 3] = target.topology.position(v).into();
+            if mesh.vertices[i].position != position {
+                mesh.vertices[i].position = position;
+                moved[i] = true;
+            }
+        }
+        let mut normals = HashMap::new();
+        for triangle in mesh.indices.chunks_exact(3) {
+            if triangle.iter().any(|&i| moved[i as usize]) {
+                for &i in triangle {
+                    normals.insert(i, Vector3::zero());
+                }
+            }
+        }
+        if normals.is_empty() {
+            return;
+        }
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] =
+                [0, 1, 2].map(|k| Vector3::from(mesh.vertices[triangle[k] as usize].position));
+            let normal = (b - a).cross(c - a);
+            for i in triangle {
+                if let Some(sum) = normals.get_mut(i) {
+                    *sum += normal;
+                }
+            }
+        }
+        let (mut first, mut last) = (usize::MAX, 0);
+        for (&i, normal) in normals.iter() {
+            if normal.magnitude2() > 0. {
+                mesh.vertices[i as usize].normal = normal.normalize().into();
+            }
+            first = first.min(i as usize);
+            last = last.max(i as usize + 1);
+        }
+        mesh.mark_vertices_dirty(first..last);
+    }
+
+    /// Replaces the target's mesh with its polygon mesh after an edit.
+    fn upload_edit_target(&mut self, device: &wgpu::Device) {
+        let model = match &self.edit.target {
+            Some(target) => target.model,
+            None => return,
+        };
+        self.upload_edit_mesh(device);
+        self.edit.hovered = None;
+        self.edit.hovered_vertex = None;
+        self.edit.selection.clear();
+        self.topology_changed(model);
+    }
+
+    /// Replaces the target's mesh with its polygon mesh, keeping the selection. The vertices
+    /// must not have been renumbered.
+    fn upload_edit_mesh(&mut self, device: &wgpu::Device) {
+        let target = match &mut self.edit.target {
+            Some(target) => target,
+            None => return,
+        };
+        let buffers = target.topology.to_buffers();
+        target.triangle_face = buffers.triangle_face;
+        target.buffer_vertex = buffer_vertices(&target.topology, &buffers.vertices);
+        let mesh = &mut self.models[target.model].meshes_mut()[target.mesh];
+        mesh.replace_geometry(device, buffers.vertices, buffers.indices);
+        target.marker_size = marker_size(mesh);
+    }
+}
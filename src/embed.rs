@@ -0,0 +1,99 @@
+//! JavaScript API for embedding the viewer into other pages, e.g. as a product viewer.
+//!
+//! ```js
+//! import init, { ModelingViewer } from "./modeling_embed_webgl.js";
+//! await init();
+//! const viewer = ModelingViewer.attach(document.getElementById("viewer"));
+//! viewer.loadUrl("models/chair.obj");
+//! viewer.setCamera([3, 2, -4], [0, 0.5, 0]);
+//! viewer.onSelect((models) => console.log("selected", models));
+//! ```
+
+use wasm_bindgen::prelude::*;
+use winit::{
+    event_loop::{EventLoop, EventLoopProxy},
+    platform::web::WindowBuilderExtWebSys,
+    window::WindowBuilder,
+};
+
+use crate::{app::run, camera_rig::CameraScheme, gui::Event, render_config::RenderConfig};
+
+/// A viewer drawing into a canvas of the page.
+///
+/// winit runs one event loop per page, so only one viewer can be attached.
+#[wasm_bindgen]
+pub struct ModelingViewer {
+    proxy: EventLoopProxy<Event>,
+}
+
+#[wasm_bindgen]
+impl ModelingViewer {
+    /// Starts the viewer in `canvas`, which has to be in the document already. The canvas is
+    /// kept as large as its parent element.
+    pub fn attach(canvas: web_sys::HtmlCanvasElement) -> Result<ModelingViewer, JsValue> {
+        std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+        // Fails when the page already set up a logger.
+        console_log::init_with_level(log::Level::Warn).ok();
+        if !cfg!(feature = "webgl") && !crate::web::webgpu_available() {
+            return Err("This browser has no WebGPU, load the WebGL2 build instead".into());
+        }
+        if canvas.parent_element().is_none() {
+            return Err("The canvas has to be added to the document first".into());
+        }
+
+        let event_loop = EventLoop::with_user_event();
+        let window = WindowBuilder::new()
+            .with_canvas(Some(canvas))
+            .build(&event_loop)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let proxy = event_loop.create_proxy();
+        wasm_bindgen_futures::spawn_local(async move {
+            run(event_loop, window, None, RenderConfig::default(), None, None, None).await;
+        });
+        Ok(Self { proxy })
+    }
+
+    /// Adds the OBJ model at `url` to the scene, with the materials and textures next to it.
+    #[wasm_bindgen(js_name = loadUrl)]
+    pub fn load_url(&self, url: String) -> Result<(), JsValue> {
+        self.send(Event::LoadModel(url))
+    }
+
+    /// Looks from `eye` at `target`, both `[x, y, z]`.
+    #[wasm_bindgen(js_name = setCamera)]
+    pub fn set_camera(&self, eye: &[f32], target: &[f32]) -> Result<(), JsValue> {
+        let point = |p: &[f32]| match *p {
+            [x, y, z] => Ok(cgmath::Point3::new(x, y, z)),
+            _ => Err(JsValue::from_str("Expected [x, y, z]")),
+        };
+        self.send(Event::SetCamera {
+            eye: point(eye)?,
+            target: point(target)?,
+        })
+    }
+
+    /// Navigates with the `"orbit"`, `"fly"` or `"turntable"` controls.
+    #[wasm_bindgen(js_name = setControls)]
+    pub fn set_controls(&self, controls: &str) -> Result<(), JsValue> {
+        let scheme = CameraScheme::ALL
+            .iter()
+            .find(|scheme| scheme.name().eq_ignore_ascii_case(controls))
+            .ok_or_else(|| JsValue::from_str("Expected orbit, fly or turntable"))?;
+        self.send(Event::SetCameraScheme(*scheme))
+    }
+
+    /// Calls `callback` with an array of the selected model indices whenever the selection
+    /// changes.
+    #[wasm_bindgen(js_name = onSelect)]
+    pub fn on_select(&self, callback: js_sys::Function) -> Result<(), JsValue> {
+        self.send(Event::OnSelect(callback))
+    }
+}
+
+impl ModelingViewer {
+    fn send(&self, event: Event) -> Result<(), JsValue> {
+        self.proxy
+            .send_event(event)
+            .map_err(|_| JsValue::from_str("The viewer has stopped"))
+    }
+}
@@ -0,0 +1,63 @@
+#ifndef ENVIRONMENT_GLSL
+#define ENVIRONMENT_GLSL
+// Shared by the compute passes baking the environment lighting, see environment.rs.
+
+const float PI = 3.14159265359;
+
+// The direction through the texel of a cube face, faces in the order +x, -x, +y, -y, +z, -z.
+vec3 cube_direction(uvec3 id, int size) {
+    vec2 uv = (vec2(id.xy) + 0.5) / float(size) * 2.0 - 1.0;
+    vec3 direction;
+    switch (int(id.z)) {
+        case 0: direction = vec3(1.0, -uv.y, -uv.x); break;
+        case 1: direction = vec3(-1.0, -uv.y, uv.x); break;
+        case 2: direction = vec3(uv.x, 1.0, uv.y); break;
+        case 3: direction = vec3(uv.x, -1.0, -uv.y); break;
+        case 4: direction = vec3(uv.x, -uv.y, 1.0); break;
+        default: direction = vec3(-uv.x, -uv.y, -1.0); break;
+    }
+    return normalize(direction);
+}
+
+// The texel of the equirectangular map in the direction, on one of its mip levels.
+vec3 equirect(texture2D map, vec3 direction, int lod) {
+    lod = clamp(lod, 0, textureQueryLevels(map) - 1);
+    ivec2 size = textureSize(map, lod);
+    vec2 uv = vec2(atan(direction.z, direction.x) / (2.0 * PI) + 0.5,
+                   acos(clamp(direction.y, -1.0, 1.0)) / PI);
+    ivec2 texel = clamp(ivec2(uv * vec2(size)), ivec2(0), size - 1);
+    return texelFetch(map, texel, lod).rgb;
+}
+
+// The mip level of the map whose texels cover about the solid angle.
+int equirect_lod(texture2D map, float solid_angle) {
+    ivec2 size = textureSize(map, 0);
+    float texel = 4.0 * PI / float(size.x * size.y);
+    return int(max(0.5 * log2(solid_angle / texel) + 1.0, 0.0));
+}
+
+// The n-th of count points spread evenly over the unit square.
+vec2 hammersley(uint i, uint count) {
+    uint bits = bitfieldReverse(i);
+    return vec2(float(i) / float(count), float(bits) * 2.3283064365386963e-10);
+}
+
+// A half vector around the normal, as likely as the GGX distribution of the roughness has it.
+vec3 importance_sample_ggx(vec2 xi, vec3 normal, float roughness) {
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    vec3 h = vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+    return normalize(tangent * h.x + bitangent * h.y + normal * h.z);
+}
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float d = n_dot_h * n_dot_h * (a * a - 1.0) + 1.0;
+    return a * a / (PI * d * d);
+}
+#endif
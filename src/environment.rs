@@ -0,0 +1,547 @@
+//! Image based lighting from an environment map, an equirectangular image loaded like a model.
+//! Compute passes bake it into a cube of the diffuse light arriving from every direction, a
+//! cube of its reflections, blurrier on each mip level for rougher surfaces, and a table of how
+//! much of the reflection a surface keeps by view angle and roughness. The model shader lights
+//! with them instead of a constant ambient, so glTF materials in shadow aren't black.
+//!
+//! The cubes keep their size whatever the image, so a new environment is only baked into them
+//! again and the bind groups reading them stay. Without storage textures, as on WebGL2, nothing
+//! is baked and the ambient stays a tenth of the first light's color.
+
+use std::sync::{mpsc, Mutex};
+
+use anyhow::*;
+
+use crate::{
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    loading::{Fetcher, LoadStatus},
+    scene::Scene,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct EnvironmentUniform {
+    /// Whether the environment lights the scene, its intensity, then the last mip level of the
+    /// reflections.
+    params: [f32; 4],
+}
+
+/// An equirectangular image in linear colors with its mip levels, halving until a side is one.
+#[derive(Debug)]
+pub struct Equirect {
+    pub source: String,
+    /// Width and height of the first level.
+    pub size: (u32, u32),
+    pub levels: Vec<Vec<[f32; 4]>>,
+}
+
+impl Equirect {
+    /// Images wider are scaled down, the cubes are much smaller anyway.
+    const MAX_WIDTH: u32 = 2048;
+
+    /// Decodes a Radiance HDR image, or an 8-bit one taken to be sRGB.
+    pub fn decode(source: String, data: &[u8]) -> Result<Self> {
+        let (width, height, pixels) = if data.starts_with(b"#?") {
+            let decoder = image::codecs::hdr::HdrDecoder::new(std::io::Cursor::new(data))?;
+            let metadata = decoder.metadata();
+            let pixels = decoder
+                .read_image_hdr()?
+                .into_iter()
+                .map(|image::Rgb([r, g, b])| [r, g, b, 1.])
+                .collect::<Vec<_>>();
+            (metadata.width, metadata.height, pixels)
+        } else {
+            let image = image::load_from_memory(data)?.to_rgba8();
+            let linear = |c: u8| (c as f32 / 255.).powf(2.2);
+            let pixels = image
+                .pixels()
+                .map(|image::Rgba([r, g, b, _])| [linear(*r), linear(*g), linear(*b), 1.])
+                .collect();
+            (image.width(), image.height(), pixels)
+        };
+        ensure!(width > 0 && height > 0, "{} is empty", source);
+        let (mut pixels, mut size) = (pixels, (width, height));
+        while size.0 > Self::MAX_WIDTH {
+            pixels = downsample(&pixels, size);
+            size = half(size);
+        }
+        let first = size;
+        let mut levels = vec![pixels];
+        while size.0 > 1 && size.1 > 1 {
+            levels.push(downsample(levels.last().unwrap(), size));
+            size = half(size);
+        }
+        Ok(Self {
+            source,
+            size: first,
+            levels,
+        })
+    }
+}
+
+fn half((width, height): (u32, u32)) -> (u32, u32) {
+    ((width / 2).max(1), (height / 2).max(1))
+}
+
+/// The image at half the size, every texel the average of the four it covers.
+fn downsample(pixels: &[[f32; 4]], (width, height): (u32, u32)) -> Vec<[f32; 4]> {
+    let (w, h) = half((width, height));
+    let at = |x: u32, y: u32| pixels[(y.min(height - 1) * width + x.min(width - 1)) as usize];
+    let mut level = Vec::with_capacity((w * h) as usize);
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = [0.; 4];
+            for [dx, dy] in [[0, 0], [1, 0], [0, 1], [1, 1]] {
+                let texel = at(x * 2 + dx, y * 2 + dy);
+                for (sum, c) in sum.iter_mut().zip(texel) {
+                    *sum += c / 4.;
+                }
+            }
+            level.push(sum);
+        }
+    }
+    level
+}
+
+/// Download and decode of an environment map, on a worker thread natively.
+#[derive(Debug)]
+struct EnvironmentJob {
+    receiver: mpsc::Receiver<Result<Equirect>>,
+}
+
+impl EnvironmentJob {
+    fn spawn(source: String) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let work = async move {
+            let status = Mutex::new(LoadStatus::new(source.clone()));
+            let fetcher = Fetcher::new(&status).await;
+            let equirect = match fetcher.file(&source).await {
+                Ok(data) => Equirect::decode(source, &data),
+                Err(e) => Err(e),
+            };
+            sender.send(equirect).ok();
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(move || futures::executor::block_on(work));
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(work);
+        Self { receiver }
+    }
+}
+
+/// The compute passes baking the environment.
+#[derive(Debug)]
+struct EnvironmentBaking {
+    layout: wgpu::BindGroupLayout,
+    irradiance: wgpu::ComputePipeline,
+    prefilter: wgpu::ComputePipeline,
+    lut: wgpu::ComputePipeline,
+    lut_bind_group: wgpu::BindGroup,
+    /// The table doesn't depend on the environment, it is baked with the first one.
+    lut_baked: bool,
+}
+
+#[derive(Debug)]
+pub struct Environment {
+    /// Whether a loaded environment lights the scene.
+    pub enabled: bool,
+    pub intensity: f32,
+    /// Where the environment baked into the cubes was loaded from.
+    pub source: Option<String>,
+    irradiance: Tracked<wgpu::Texture>,
+    irradiance_view: wgpu::TextureView,
+    prefiltered: Tracked<wgpu::Texture>,
+    prefiltered_view: wgpu::TextureView,
+    lut_view: Tracked<wgpu::TextureView>,
+    sampler: wgpu::Sampler,
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    baking: Option<EnvironmentBaking>,
+    job: Option<EnvironmentJob>,
+}
+
+impl Environment {
+    pub const IRRADIANCE_SIZE: u32 = 32;
+    /// Size of the first mip level of the reflections, must match prefilter.comp.
+    pub const PREFILTERED_SIZE: u32 = 128;
+    /// Mip levels of the reflections, from roughness 0 to 1. Must match prefilter.comp.
+    pub const PREFILTERED_MIPS: u32 = 5;
+    pub const LUT_SIZE: u32 = 256;
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const EQUIRECT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+    const WORKGROUP_SIZE: u32 = 8;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let bake = device.limits().max_storage_textures_per_shader_stage > 0;
+        let usage = if bake {
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING
+        } else {
+            wgpu::TextureUsages::TEXTURE_BINDING
+        };
+        let texture = |label, size, mip_level_count, depth_or_array_layers| {
+            device.create_tracked_texture(
+                GpuCategory::Lights,
+                &wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: size,
+                        height: size,
+                        depth_or_array_layers,
+                    },
+                    mip_level_count,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: Self::FORMAT,
+                    usage,
+                },
+            )
+        };
+        let cube = wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        };
+        let irradiance = texture("Irradiance Texture", Self::IRRADIANCE_SIZE, 1, 6);
+        let irradiance_view = irradiance.create_view(&cube);
+        let prefiltered = texture(
+            "Prefiltered Environment Texture",
+            Self::PREFILTERED_SIZE,
+            Self::PREFILTERED_MIPS,
+            6,
+        );
+        let prefiltered_view = prefiltered.create_view(&cube);
+        let lut_view = texture("BRDF LUT Texture", Self::LUT_SIZE, 1, 1)
+            .into_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_tracked_buffer_init(
+            GpuCategory::Lights,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Environment Uniform Buffer"),
+                contents: bytemuck::bytes_of(&EnvironmentUniform { params: [0.; 4] }),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+        let baking = if bake {
+            Some(Self::create_baking(device, &lut_view))
+        } else {
+            None
+        };
+        Self {
+            enabled: true,
+            intensity: 1.,
+            source: None,
+            irradiance,
+            irradiance_view,
+            prefiltered,
+            prefiltered_view,
+            lut_view,
+            sampler,
+            uniform_buffer,
+            baking,
+            job: None,
+        }
+    }
+
+    fn create_baking(device: &wgpu::Device, lut_view: &wgpu::TextureView) -> EnvironmentBaking {
+        let storage = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: Self::FORMAT,
+                view_dimension,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("environment_baking_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                storage(1, wgpu::TextureViewDimension::D2Array),
+            ],
+        });
+        let lut_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("brdf_lut_bind_group_layout"),
+            entries: &[storage(0, wgpu::TextureViewDimension::D2)],
+        });
+        let lut_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("brdf_lut_bind_group"),
+            layout: &lut_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(lut_view),
+            }],
+        });
+        let pipeline = |label, layout, module: wgpu::ShaderModuleDescriptor| {
+            let module = device.create_shader_module(&module);
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: "main",
+            })
+        };
+        EnvironmentBaking {
+            irradiance: pipeline(
+                "Irradiance Pipeline",
+                &layout,
+                wgpu::include_spirv!("irradiance.comp.spv"),
+            ),
+            prefilter: pipeline(
+                "Prefilter Pipeline",
+                &layout,
+                wgpu::include_spirv!("prefilter.comp.spv"),
+            ),
+            lut: pipeline(
+                "BRDF LUT Pipeline",
+                &lut_layout,
+                wgpu::include_spirv!("brdf_lut.comp.spv"),
+            ),
+            layout,
+            lut_bind_group,
+            lut_baked: false,
+        }
+    }
+
+    /// Whether the device runs the compute passes, without which no environment lights.
+    pub fn bakes(&self) -> bool {
+        self.baking.is_some()
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.job.is_some()
+    }
+
+    /// Starts loading the environment map at the URL or path, baked once it is decoded.
+    pub fn load(&mut self, source: String) {
+        self.job = Some(EnvironmentJob::spawn(source));
+    }
+
+    /// Entries of the lights' bind group layout reading the environment, after the clusters.
+    pub fn layout_entries() -> [wgpu::BindGroupLayoutEntry; 5] {
+        let texture = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        [
+            texture(4, wgpu::TextureViewDimension::Cube),
+            texture(5, wgpu::TextureViewDimension::Cube),
+            texture(6, wgpu::TextureViewDimension::D2),
+            wgpu::BindGroupLayoutEntry {
+                binding: 7,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler {
+                    filtering: true,
+                    comparison: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 8,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ]
+    }
+
+    /// The resources for `layout_entries`.
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 5] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&self.irradiance_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&self.prefiltered_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::TextureView(&self.lut_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+        ]
+    }
+
+    /// Uploads whether and how brightly the environment lights.
+    pub fn update(&self, queue: &wgpu::Queue) {
+        let lights = self.enabled && self.source.is_some() && self.bakes();
+        let uniform = EnvironmentUniform {
+            params: [
+                lights as u32 as f32,
+                self.intensity,
+                (Self::PREFILTERED_MIPS - 1) as f32,
+                0.,
+            ],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Bakes the environment map into the cubes, and the table the first time.
+    pub fn bake(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, equirect: &Equirect) {
+        profile_function!();
+        let baking = match &mut self.baking {
+            Some(baking) => baking,
+            None => return,
+        };
+        let (width, height) = equirect.size;
+        let texture = device.create_tracked_texture(
+            GpuCategory::Lights,
+            &wgpu::TextureDescriptor {
+                label: Some("Equirect Texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: equirect.levels.len() as u32,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::EQUIRECT_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            },
+        );
+        for (level, pixels) in equirect.levels.iter().enumerate() {
+            let (w, h) = ((width >> level).max(1), (height >> level).max(1));
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(pixels),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(w * 16),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width: w,
+                    height: h,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        let equirect_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Each pass writes one mip level of a cube, its faces as layers.
+        let target = |texture: &wgpu::Texture, mip| {
+            let view = texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                base_mip_level: mip,
+                mip_level_count: std::num::NonZeroU32::new(1),
+                ..Default::default()
+            });
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("environment_baking_bind_group"),
+                layout: &baking.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&equirect_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                ],
+            })
+        };
+        let irradiance = target(&self.irradiance, 0);
+        let cube = &self.prefiltered;
+        let prefiltered = (0..Self::PREFILTERED_MIPS)
+            .map(|mip| target(cube, mip))
+            .collect::<Vec<_>>();
+
+        let groups = |size: u32| (size + Self::WORKGROUP_SIZE - 1) / Self::WORKGROUP_SIZE;
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Baking Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Environment Baking Pass"),
+            });
+            pass.set_pipeline(&baking.irradiance);
+            pass.set_bind_group(0, &irradiance, &[]);
+            pass.dispatch(groups(Self::IRRADIANCE_SIZE), groups(Self::IRRADIANCE_SIZE), 6);
+            pass.set_pipeline(&baking.prefilter);
+            for (mip, bind_group) in prefiltered.iter().enumerate() {
+                let size = Self::PREFILTERED_SIZE >> mip;
+                pass.set_bind_group(0, bind_group, &[]);
+                pass.dispatch(groups(size), groups(size), 6);
+            }
+            if !baking.lut_baked {
+                pass.set_pipeline(&baking.lut);
+                pass.set_bind_group(0, &baking.lut_bind_group, &[]);
+                pass.dispatch(groups(Self::LUT_SIZE), groups(Self::LUT_SIZE), 1);
+                baking.lut_baked = true;
+            }
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        self.source = Some(equirect.source.clone());
+    }
+}
+
+impl Scene {
+    /// Bakes the environment map once it is loaded, and uploads its settings.
+    pub fn poll_environment(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let environment = &mut self.lights.environment;
+        let received = match &environment.job {
+            Some(job) => job.receiver.try_recv(),
+            None => Err(mpsc::TryRecvError::Empty),
+        };
+        match received {
+            Ok(Ok(equirect)) => {
+                environment.job = None;
+                environment.bake(device, queue, &equirect);
+            }
+            Ok(Err(e)) => {
+                environment.job = None;
+                log::warn!("Loading the environment failed: {:?}", e);
+            }
+            Err(mpsc::TryRecvError::Disconnected) => environment.job = None,
+            Err(mpsc::TryRecvError::Empty) => {}
+        }
+        environment.update(queue);
+    }
+}
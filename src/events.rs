@@ -0,0 +1,80 @@
+//! What happens in the scene, for programs embedding the viewer. The scene queues events while
+//! it is locked for the update, and subscribers are called after the lock is let go, so they
+//! can read the scene themselves.
+//!
+//! Subscribers are added by sending `gui::Event::Subscribe` through the event loop proxy, e.g.
+//! before handing the event loop to `app::run`.
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
+
+use crate::{registry::NodeId, scene::SceneStats};
+
+#[derive(Debug, Clone)]
+pub enum SceneEvent {
+    /// A model finished loading, added or in place of the one loaded from the same source.
+    ModelLoaded { model: NodeId, source: String },
+    /// The models selected now, whenever that changes.
+    SelectionChanged(Vec<NodeId>),
+    /// Models moved by the transform of their group.
+    TransformEdited(Vec<NodeId>),
+    /// A frame was presented, with what it drew.
+    FrameRendered { frame: u64, stats: SceneStats },
+}
+
+/// Called with every event. Shared so the event loop can hand it over by reference.
+pub type Subscriber = Arc<Mutex<dyn FnMut(&SceneEvent) + Send>>;
+
+pub fn subscriber(callback: impl FnMut(&SceneEvent) + Send + 'static) -> Subscriber {
+    Arc::new(Mutex::new(callback))
+}
+
+/// Events of the scene waiting for the subscribers.
+#[derive(Debug, Default)]
+pub struct EventQueue {
+    events: Vec<SceneEvent>,
+    /// The selection subscribers last heard of.
+    selected: BTreeSet<usize>,
+}
+
+impl EventQueue {
+    pub fn push(&mut self, event: SceneEvent) {
+        self.events.push(event);
+    }
+
+    /// The events since the last call, with one for the selection if it changed meanwhile.
+    pub fn take(&mut self, selected: &BTreeSet<usize>) -> Vec<SceneEvent> {
+        if *selected != self.selected {
+            self.selected = selected.clone();
+            let models = selected.iter().map(|&model| NodeId(model)).collect();
+            self.events.push(SceneEvent::SelectionChanged(models));
+        }
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[derive(Default)]
+pub struct Subscribers {
+    subscribers: Vec<Subscriber>,
+}
+
+impl Subscribers {
+    pub fn add(&mut self, subscriber: Subscriber) {
+        self.subscribers.push(subscriber);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    pub fn notify(&self, events: &[SceneEvent]) {
+        for subscriber in self.subscribers.iter() {
+            let mut callback = subscriber.lock().unwrap();
+            for event in events {
+                (*callback)(event);
+            }
+        }
+    }
+}
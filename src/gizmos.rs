@@ -0,0 +1,186 @@
+//! Wireframe gizmos for the lights, besides the frustums of the scene cameras. Both are picked
+//! and boxed along with the models and moved by the selection's arrows and transforms.
+//!
+//! Lights are drawn as points, directional ones with parallel arrows along their direction like
+//! sunlight and spot lights with the cone they shine in.
+
+use std::collections::BTreeSet;
+
+use cgmath::{InnerSpace, Matrix4, Point3, Transform, Vector3};
+
+use crate::{light::LightKind, picking::Ray, scene::Scene};
+
+/// A light or scene camera, by index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GizmoObject {
+    Light(usize),
+    Camera(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Gizmos {
+    pub show_lights: bool,
+    pub selected: BTreeSet<GizmoObject>,
+    /// Lights moved since the last update, written to the GPU then.
+    lights_moved: bool,
+}
+
+impl Default for Gizmos {
+    fn default() -> Self {
+        Self {
+            show_lights: true,
+            selected: BTreeSet::new(),
+            lights_moved: false,
+        }
+    }
+}
+
+impl Gizmos {
+    const LIGHT_COLOR: [f32; 3] = [1.0, 0.9, 0.4];
+    pub(crate) const SELECTED_COLOR: [f32; 3] = [1.0, 0.6, 0.1];
+    /// How far off a gizmo a click may be, as the tangent of the angle from the eye.
+    const PICK_ANGLE: f32 = 0.02;
+    const CIRCLE_SEGMENTS: u32 = 24;
+}
+
+/// Two unit directions square to the unit `direction` and each other.
+fn perpendicular(direction: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let side = direction.cross(Vector3::unit_y());
+    let side = if side.magnitude2() < 1e-6 {
+        Vector3::unit_x()
+    } else {
+        side.normalize()
+    };
+    (side, direction.cross(side))
+}
+
+impl Scene {
+    pub fn gizmo_position(&self, object: GizmoObject) -> Option<Point3<f32>> {
+        match object {
+            GizmoObject::Light(i) => self.lights.lights.get(i).map(|light| light.light.position),
+            GizmoObject::Camera(i) => self
+                .cameras
+                .cameras
+                .get(i)
+                .map(|camera| camera.view.eye.into()),
+        }
+    }
+
+    /// The selected gizmos whose object still exists.
+    pub(crate) fn selected_gizmos(&self) -> Vec<GizmoObject> {
+        self.gizmos
+            .selected
+            .iter()
+            .copied()
+            .filter(|&object| self.gizmo_position(object).is_some())
+            .collect()
+    }
+
+    /// The gizmos drawn, which are the ones that can be picked.
+    pub(crate) fn shown_gizmos(&self) -> Vec<GizmoObject> {
+        let lights = (0..self.lights.lights.len())
+            .filter(|_| self.gizmos.show_lights)
+            .map(GizmoObject::Light);
+        let cameras = (0..self.cameras.cameras.len())
+            .filter(|&i| self.cameras.looking_through != Some(i))
+            .map(GizmoObject::Camera);
+        lights.chain(cameras).collect()
+    }
+
+    /// The gizmo closest to the ray within reach, with its distance along it.
+    pub(crate) fn pick_gizmo(&self, ray: &Ray) -> Option<(GizmoObject, f32)> {
+        self.shown_gizmos()
+            .into_iter()
+            .filter_map(|object| {
+                let position = self.gizmo_position(object)?;
+                let depth = (position - ray.origin).dot(ray.direction);
+                let miss = (ray.at(depth) - position).magnitude();
+                Some((object, depth)).filter(|_| depth > 0. && miss <= depth * Gizmos::PICK_ANGLE)
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+    }
+
+    /// Moves the light or camera by the matrix, turning a camera's view along.
+    pub(crate) fn transform_gizmo(&mut self, object: GizmoObject, matrix: Matrix4<f32>) {
+        match object {
+            GizmoObject::Light(i) => {
+                if let Some(light) = self.lights.lights.get_mut(i) {
+                    light.light.position = matrix.transform_point(light.light.position);
+                    self.gizmos.lights_moved = true;
+                }
+            }
+            GizmoObject::Camera(i) => {
+                if let Some(camera) = self.cameras.cameras.get_mut(i) {
+                    let view = &mut camera.view;
+                    view.eye = matrix.transform_point(view.eye.into()).into();
+                    view.target = matrix.transform_point(view.target.into()).into();
+                    view.up = matrix.transform_vector(view.up.into()).normalize().into();
+                }
+            }
+        }
+    }
+
+    /// Uploads the lights at the next update, after they were edited.
+    pub fn lights_edited(&mut self) {
+        self.gizmos.lights_moved = true;
+    }
+
+    /// Uploads the lights moved by their gizmos.
+    pub(crate) fn write_moved_lights(&mut self, queue: &wgpu::Queue) {
+        if std::mem::take(&mut self.gizmos.lights_moved) {
+            for light in self.lights.lights.iter() {
+                light.write(queue);
+            }
+        }
+    }
+
+    pub(crate) fn draw_light_gizmos(&mut self) {
+        if !self.gizmos.show_lights {
+            return;
+        }
+        let lines = &mut self.renderer.debug_lines;
+        for (i, object) in self.lights.lights.iter().enumerate() {
+            let light = &object.light;
+            let color = if self.gizmos.selected.contains(&GizmoObject::Light(i)) {
+                Gizmos::SELECTED_COLOR
+            } else {
+                Gizmos::LIGHT_COLOR
+            };
+            let position = light.position;
+            let size = (self.camera.eye - position).magnitude() * 0.02;
+            for axis in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+                lines.circle(position, axis, size, Gizmos::CIRCLE_SEGMENTS, color);
+            }
+            let direction = light.direction();
+            let (side, other) = perpendicular(direction);
+            match light.kind {
+                LightKind::Directional => {
+                    for offset in [Vector3::new(0., 0., 0.), side, -side, other, -other] {
+                        let start = position + offset * size * 2.;
+                        let tip = start + direction * size * 8.;
+                        lines.line(start, tip, color);
+                        lines.line(tip, tip - direction * size * 2. + side * size, color);
+                        lines.line(tip, tip - direction * size * 2. - side * size, color);
+                    }
+                }
+                LightKind::Spot => {
+                    // The outer cone up to the light's range.
+                    let spread = light.outer_cone.0.min(1.5).tan();
+                    let far = light.range;
+                    lines.circle(
+                        position + direction * far,
+                        direction,
+                        far * spread,
+                        Gizmos::CIRCLE_SEGMENTS,
+                        color,
+                    );
+                    for edge in [side, -side, other, -other] {
+                        let edge = direction + edge * spread;
+                        lines.line(position, position + edge * far, color);
+                    }
+                }
+                LightKind::Point => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,669 @@
+//! Reads glTF 2.0 models, `.gltf` with their buffers and images next to them or embedded as
+//! data URIs and binary `.glb`, into the same decoded model the OBJ loader makes. The meshes
+//! are moved to where their nodes put them, and the nodes go into the scene graph. Skinned
+//! meshes stay where they were bound to their joints instead, which move them once drawn.
+//!
+//! The materials are drawn with the PBR permutation of the model shader, the metallic-roughness
+//! texture in the specular slot with the factors multiplied in.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::*;
+use cgmath::{Matrix4, Quaternion, SquareMatrix, Vector3};
+use gltf::animation::{util::ReadOutputs, Interpolation};
+
+use crate::{
+    loading::{
+        set_stage, sibling, yield_now, Fetcher, LoadStage, LoadStatus, LoadedMaterial, LoadedMesh,
+        LoadedModel, Pixels,
+    },
+    model::{compute_normals, compute_tangents, transform_vertices, ModelVertex},
+    poses::{AnimationClip, Channel, Keyframes},
+    scene_graph::{LoadedNode, NodeTransform},
+    skin::Skin,
+    vertex_layout::{VertexAttribute, VertexData, VertexLayout},
+};
+
+/// Whether the source is a glTF model, by its extension.
+pub fn is_gltf(source: &str) -> bool {
+    let path = source
+        .split(|c| c == '?' || c == '#')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    path.ends_with(".gltf") || path.ends_with(".glb")
+}
+
+/// Relative URIs are percent encoded, file names aren't.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn uri_to_name(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// URLs keep their encoding on the web.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn uri_to_name(uri: &str) -> String {
+    uri.to_string()
+}
+
+/// The payload of a base64 data URI, `None` for other URIs.
+fn data_uri(uri: &str) -> Option<Result<Vec<u8>>> {
+    let data = uri.strip_prefix("data:")?;
+    Some(match data.split_once(";base64,") {
+        Some((_, encoded)) => base64::decode(encoded).map_err(Error::from),
+        None => Err(anyhow!("Only base64 data URIs are supported")),
+    })
+}
+
+/// What the textures of the materials point at, each decoded once however many use it.
+struct Images<'a, 'f> {
+    fetcher: &'a Fetcher<'f>,
+    source: &'a str,
+    buffers: &'a [Vec<u8>],
+    decoded: HashMap<usize, Option<image::DynamicImage>>,
+}
+
+impl Images<'_, '_> {
+    async fn decode(&self, image: &gltf::Image<'_>) -> Result<image::DynamicImage> {
+        match image.source() {
+            gltf::image::Source::View { view, .. } => {
+                let bytes = self.buffers[view.buffer().index()]
+                    .get(view.offset()..view.offset() + view.length())
+                    .context("The image is past the end of its buffer")?;
+                Ok(image::load_from_memory(bytes)?)
+            }
+            gltf::image::Source::Uri { uri, .. } => match data_uri(uri) {
+                Some(bytes) => Ok(image::load_from_memory(&bytes?)?),
+                None => {
+                    self.fetcher
+                        .image(&sibling(self.source, &uri_to_name(uri)))
+                        .await
+                }
+            },
+        }
+    }
+
+    /// The image of the texture, `None` if it can't be loaded.
+    async fn get(
+        &mut self,
+        texture: gltf::Texture<'_>,
+        tex_coord: u32,
+        warnings: &mut Vec<String>,
+    ) -> Option<image::DynamicImage> {
+        if tex_coord != 0 {
+            warnings.push(format!(
+                "Texture {} uses texture coordinates {}, only the first are read",
+                texture.index(),
+                tex_coord
+            ));
+        }
+        let image = texture.source();
+        if !self.decoded.contains_key(&image.index()) {
+            let decoded = match self.decode(&image).await {
+                Ok(decoded) => Some(decoded),
+                Err(e) => {
+                    log::warn!("Image {} not loaded: {:?}", image.index(), e);
+                    warnings.push(format!("Image {} not loaded: {:#}", image.index(), e));
+                    None
+                }
+            };
+            self.decoded.insert(image.index(), decoded);
+        }
+        self.decoded[&image.index()].clone()
+    }
+}
+
+async fn load_material(
+    material: gltf::Material<'_>,
+    images: &mut Images<'_, '_>,
+    warnings: &mut Vec<String>,
+) -> LoadedMaterial {
+    let pbr = material.pbr_metallic_roughness();
+    // Colors of one pixel textures are linear, like the factors.
+    let color = |c: f32| (c.max(0.).min(1.) * 255.) as u8;
+    let factor = pbr.base_color_factor();
+    let base_color = match pbr.base_color_texture() {
+        Some(info) => Some(images.get(info.texture(), info.tex_coord(), warnings).await),
+        None => None,
+    };
+    let diffuse = match base_color {
+        Some(Some(image)) if factor == [1.; 4] => Pixels::Image(image),
+        Some(Some(image)) => {
+            // The image is sRGB encoded.
+            let factor = factor.map(|c| c.powf(1. / 2.2));
+            let mut pixels = image.to_rgba8();
+            for pixel in pixels.pixels_mut() {
+                for (channel, factor) in pixel.0.iter_mut().zip(factor.iter()) {
+                    *channel = (*channel as f32 * factor) as u8;
+                }
+            }
+            Pixels::Image(image::DynamicImage::ImageRgba8(pixels))
+        }
+        Some(None) => Pixels::Missing,
+        None => Pixels::Color(factor.map(color)),
+    };
+
+    let normal = match material.normal_texture() {
+        Some(normal) => Some(
+            images
+                .get(normal.texture(), normal.tex_coord(), warnings)
+                .await,
+        ),
+        None => None,
+    };
+    let normal = match normal {
+        Some(Some(image)) => Pixels::Image(image),
+        Some(None) => Pixels::Missing,
+        None => Pixels::Color([0x80, 0x80, 0xff, 0]),
+    };
+
+    let (metallic, roughness) = (pbr.metallic_factor(), pbr.roughness_factor());
+    let metallic_roughness = match pbr.metallic_roughness_texture() {
+        Some(info) => Some(images.get(info.texture(), info.tex_coord(), warnings).await),
+        None => None,
+    };
+    let specular = match metallic_roughness {
+        // Roughness in green, metalness in blue.
+        Some(Some(image)) if [metallic, roughness] == [1.; 2] => Pixels::Image(image),
+        Some(Some(image)) => {
+            let mut pixels = image.to_rgba8();
+            for pixel in pixels.pixels_mut() {
+                let [r, g, b, a] = pixel.0;
+                pixel.0 = [r, (g as f32 * roughness) as u8, (b as f32 * metallic) as u8, a];
+            }
+            Pixels::Image(image::DynamicImage::ImageRgba8(pixels))
+        }
+        Some(None) => Pixels::Missing,
+        None => Pixels::Color([0, color(roughness), color(metallic), 0xff]),
+    };
+
+    let occlusion = match material.occlusion_texture() {
+        Some(occlusion) => Some(
+            images
+                .get(occlusion.texture(), occlusion.tex_coord(), warnings)
+                .await,
+        ),
+        None => None,
+    };
+    let occlusion = match occlusion {
+        Some(Some(image)) => Pixels::Image(image),
+        Some(None) => Pixels::Missing,
+        None => Pixels::Color([0xff; 4]),
+    };
+
+    let [r, g, b] = material.emissive_factor();
+    let emissive = match material.emissive_texture() {
+        Some(info) => Some(images.get(info.texture(), info.tex_coord(), warnings).await),
+        None => None,
+    };
+    let emissive = match emissive {
+        Some(Some(image)) if [r, g, b] == [1.; 3] => Pixels::Image(image),
+        Some(Some(image)) => {
+            // The image is sRGB encoded.
+            let factor = [r, g, b].map(|c| c.powf(1. / 2.2));
+            let mut pixels = image.to_rgba8();
+            for pixel in pixels.pixels_mut() {
+                for (channel, factor) in pixel.0.iter_mut().zip(factor.iter()) {
+                    *channel = (*channel as f32 * factor) as u8;
+                }
+            }
+            Pixels::Image(image::DynamicImage::ImageRgba8(pixels))
+        }
+        Some(None) => Pixels::Missing,
+        None => Pixels::Color([color(r), color(g), color(b), 0xff]),
+    };
+
+    LoadedMaterial {
+        name: material
+            .name()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("material {}", material.index().unwrap_or_default())),
+        diffuse,
+        normal,
+        specular,
+        occlusion,
+        emissive,
+        pbr: true,
+    }
+}
+
+/// `node` and the nodes under it, parents first, with the index of their parent among the
+/// found nodes and where they are in the scene.
+fn collect_nodes<'a>(
+    node: gltf::Node<'a>,
+    parent: Option<(usize, Matrix4<f32>)>,
+    found: &mut Vec<(gltf::Node<'a>, Option<usize>, Matrix4<f32>)>,
+) {
+    let local = Matrix4::from(node.transform().matrix());
+    let matrix = parent.map_or(local, |(_, matrix)| matrix * local);
+    let index = found.len();
+    found.push((node.clone(), parent.map(|(i, _)| i), matrix));
+    for child in node.children() {
+        collect_nodes(child, Some((index, matrix)), found);
+    }
+}
+
+fn node_transform(node: &gltf::Node) -> NodeTransform {
+    let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+    NodeTransform {
+        translation: translation.into(),
+        rotation: Quaternion::new(w, x, y, z),
+        scale: scale.into(),
+    }
+}
+
+/// The animations of the nodes found, morph target weights left out. `found` gives the index
+/// among the found nodes of a node of the document.
+fn read_animations(
+    document: &gltf::Document,
+    buffers: &[Vec<u8>],
+    found: &HashMap<usize, usize>,
+) -> Vec<AnimationClip> {
+    profile_function!();
+    document
+        .animations()
+        .map(|animation| {
+            let channels = animation
+                .channels()
+                .filter_map(|channel| {
+                    // Loaded nodes come after the root node the model gets in the scene graph.
+                    let node = found.get(&channel.target().node().index())? + 1;
+                    let interpolation = channel.sampler().interpolation();
+                    let reader =
+                        channel.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+                    let times = reader.read_inputs()?.collect::<Vec<_>>();
+                    // Cubic splines keep an in and out tangent around every value.
+                    let values = |values: Vec<[f32; 3]>| -> Vec<Vector3<f32>> {
+                        match interpolation {
+                            Interpolation::CubicSpline => values
+                                .into_iter()
+                                .skip(1)
+                                .step_by(3)
+                                .map(Vector3::from)
+                                .collect(),
+                            _ => values.into_iter().map(Vector3::from).collect(),
+                        }
+                    };
+                    let keyframes = match reader.read_outputs()? {
+                        ReadOutputs::Translations(t) => Keyframes::Translation(values(t.collect())),
+                        ReadOutputs::Scales(s) => Keyframes::Scale(values(s.collect())),
+                        ReadOutputs::Rotations(r) => {
+                            let rotations =
+                                r.into_f32().map(|[x, y, z, w]| Quaternion::new(w, x, y, z));
+                            Keyframes::Rotation(match interpolation {
+                                Interpolation::CubicSpline => {
+                                    rotations.skip(1).step_by(3).collect()
+                                }
+                                _ => rotations.collect(),
+                            })
+                        }
+                        ReadOutputs::MorphTargetWeights(_) => return None,
+                    };
+                    Some(Channel {
+                        node,
+                        times,
+                        keyframes,
+                        step: interpolation == Interpolation::Step,
+                    })
+                })
+                .collect();
+            AnimationClip {
+                name: animation
+                    .name()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("animation {}", animation.index())),
+                model: 0,
+                channels,
+            }
+        })
+        .collect()
+}
+
+/// The joints of the skin by their place among the model's nodes, with their inverse bind
+/// matrices. `None` if a joint isn't one of the nodes found.
+fn read_skin(
+    skin: &gltf::Skin,
+    buffers: &[Vec<u8>],
+    found: &HashMap<usize, usize>,
+) -> Option<Skin> {
+    let joints = skin
+        .joints()
+        .map(|joint| found.get(&joint.index()).map(|i| i + 1))
+        .collect::<Option<Vec<_>>>()?;
+    let reader = skin.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+    // Without inverse bind matrices, the mesh was bound where the joints are in the scene.
+    let mut inverse_binds = reader
+        .read_inverse_bind_matrices()
+        .map(|matrices| matrices.map(Matrix4::from).collect::<Vec<_>>())
+        .unwrap_or_default();
+    inverse_binds.resize(joints.len(), Matrix4::identity());
+    Some(Skin {
+        model: 0,
+        mesh: 0,
+        joints,
+        inverse_binds,
+    })
+}
+
+/// The triangles of the primitive, moved to where its node is unless the node's skin of
+/// `joint_count` joints moves them. `None` with a warning for what can't be drawn.
+fn read_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[Vec<u8>],
+    name: String,
+    matrix: Matrix4<f32>,
+    joint_count: Option<usize>,
+    warnings: &mut Vec<String>,
+) -> Result<Option<LoadedMesh>> {
+    profile_function!();
+    if primitive.mode() != gltf::mesh::Mode::Triangles {
+        warnings.push(format!(
+            "Mesh {} is drawn as {:?}, only triangles are supported",
+            name,
+            primitive.mode()
+        ));
+        return Ok(None);
+    }
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+    let positions = match reader.read_positions() {
+        Some(positions) => positions.collect::<Vec<_>>(),
+        None => {
+            warnings.push(format!("Mesh {} has no positions", name));
+            return Ok(None);
+        }
+    };
+    let normals = reader.read_normals().map(|n| n.collect::<Vec<_>>());
+    let tex_coords = reader
+        .read_tex_coords(0)
+        .map(|t| t.into_f32().collect::<Vec<_>>());
+    let tangents = reader.read_tangents().map(|t| t.collect::<Vec<_>>());
+    let mut indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+        warnings.push(format!(
+            "Mesh {} has index {} past its {} vertices",
+            name,
+            index,
+            positions.len()
+        ));
+        return Ok(None);
+    }
+    indices.truncate(indices.len() / 3 * 3);
+    if tex_coords.is_none() {
+        warnings.push(format!("Mesh {} has no texture coordinates", name));
+    }
+
+    let mut vertices = positions
+        .iter()
+        .enumerate()
+        .map(|(i, &position)| ModelVertex {
+            position,
+            // Already with the origin at the top left, unlike OBJ.
+            tex_coords: tex_coords
+                .as_ref()
+                .and_then(|t| t.get(i).copied())
+                .unwrap_or([0.; 2]),
+            normal: normals
+                .as_ref()
+                .and_then(|n| n.get(i).copied())
+                .unwrap_or([0.; 3]),
+            tangent: [0.; 3],
+            bitangent: [0.; 3],
+        })
+        .collect::<Vec<_>>();
+    if normals.is_none() {
+        compute_normals(&mut vertices, &indices);
+    }
+    match tangents.filter(|t| t.len() == vertices.len()) {
+        Some(tangents) => {
+            for (vertex, [x, y, z, w]) in vertices.iter_mut().zip(tangents) {
+                let normal = Vector3::from(vertex.normal);
+                let tangent = Vector3::new(x, y, z);
+                vertex.tangent = tangent.into();
+                // glTF points the bitangent up the texture, `compute_tangents` down it.
+                vertex.bitangent = (-normal.cross(tangent) * w).into();
+            }
+        }
+        None => compute_tangents(&mut vertices, &indices),
+    }
+    let skin = match (joint_count, reader.read_joints(0), reader.read_weights(0)) {
+        (Some(count), Some(joints), Some(weights)) => {
+            let joints = joints.into_u16().collect::<Vec<_>>();
+            match joints
+                .iter()
+                .flatten()
+                .find(|&&joint| joint as usize >= count)
+            {
+                Some(joint) => {
+                    warnings.push(format!(
+                        "Mesh {} has joint {} past the {} of its skin",
+                        name, joint, count
+                    ));
+                    None
+                }
+                None => Some((joints, weights.into_f32().collect::<Vec<_>>())),
+            }
+        }
+        _ => None,
+    };
+    // The joints move skinned vertices from where they were bound.
+    let matrix = match skin {
+        Some(_) => Matrix4::identity(),
+        None => matrix,
+    };
+    if matrix != Matrix4::identity() {
+        transform_vertices(&mut vertices, matrix);
+        // Mirroring turns the triangles inside out.
+        if matrix.determinant() < 0. {
+            for triangle in indices.chunks_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+
+    let colors = reader.read_colors(0);
+    let attributes = if colors.is_some() || skin.is_some() {
+        let mut layout = VertexLayout::builder();
+        if colors.is_some() {
+            layout = layout.color();
+        }
+        if skin.is_some() {
+            layout = layout.skin();
+        }
+        let mut data = VertexData::new(layout.build()?, vertices.len());
+        let colors = colors.into_iter().flat_map(|colors| colors.into_rgb_f32());
+        for (i, color) in colors.enumerate().take(vertices.len()) {
+            data.set(i, &VertexAttribute::Color, &color);
+        }
+        if let Some((joints, weights)) = skin {
+            let bound = joints.into_iter().zip(weights).take(vertices.len());
+            for (i, (joints, weights)) in bound.enumerate() {
+                data.set(i, &VertexAttribute::Joints, &joints.map(f32::from));
+                data.set(i, &VertexAttribute::Weights, &weights);
+            }
+        }
+        Some(data)
+    } else {
+        None
+    };
+
+    Ok(Some(LoadedMesh {
+        name,
+        vertices,
+        indices,
+        material: primitive.material().index(),
+        attributes,
+    }))
+}
+
+/// Reads the glTF model at `source` with its buffers and images, reporting the progress to
+/// `status`. Fails when the document or its buffers can't be read, missing images only warn.
+pub(crate) async fn load(
+    fetcher: &Fetcher<'_>,
+    source: &str,
+    status: &Mutex<LoadStatus>,
+) -> Result<LoadedModel> {
+    let bytes = fetcher.file(source).await?;
+    set_stage(status, source, LoadStage::Decoding { done: 0, count: 0 });
+    yield_now().await;
+    let gltf::Gltf { document, mut blob } =
+        gltf::Gltf::from_slice(&bytes).with_context(|| format!("Can't parse {}", source))?;
+    let mut warnings = document
+        .extensions_required()
+        .map(|extension| format!("Extension {} isn't supported", extension))
+        .collect::<Vec<_>>();
+
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => blob.take().context("The binary chunk is missing")?,
+            gltf::buffer::Source::Uri(uri) => match data_uri(uri) {
+                Some(data) => data?,
+                None => fetcher.file(&sibling(source, &uri_to_name(uri))).await?,
+            },
+        };
+        if data.len() < buffer.length() {
+            bail!(
+                "Buffer {} has {} of its {} bytes",
+                buffer.index(),
+                data.len(),
+                buffer.length()
+            );
+        }
+        buffers.push(data);
+    }
+
+    // The nodes of the scene place the meshes, files without one just list them.
+    let mut nodes = Vec::new();
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next());
+    if let Some(scene) = &scene {
+        for node in scene.nodes() {
+            collect_nodes(node, None, &mut nodes);
+        }
+    }
+    let mut loaded_nodes = nodes
+        .iter()
+        .map(|(node, parent, _)| LoadedNode {
+            name: node
+                .name()
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("node {}", node.index())),
+            parent: *parent,
+            local: node_transform(node),
+            meshes: Vec::new(),
+        })
+        .collect::<Vec<_>>();
+    let found = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, (node, _, _))| (node.index(), i))
+        .collect::<HashMap<_, _>>();
+    let animations = read_animations(&document, &buffers, &found);
+    let meshes = match scene {
+        Some(_) => nodes
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, (node, _, matrix))| {
+                let name = node.name().map(str::to_string);
+                let skin = node
+                    .skin()
+                    .and_then(|skin| read_skin(&skin, &buffers, &found));
+                node.mesh().map(|mesh| (mesh, name, matrix, Some(i), skin))
+            })
+            .collect::<Vec<_>>(),
+        None => document
+            .meshes()
+            .map(|mesh| (mesh, None, Matrix4::identity(), None, None))
+            .collect(),
+    };
+
+    let count = document.materials().len() + meshes.len();
+    let mut done = 0;
+    let mut images = Images {
+        fetcher,
+        source,
+        buffers: &buffers,
+        decoded: HashMap::new(),
+    };
+    let mut materials = Vec::new();
+    for material in document.materials() {
+        materials.push(load_material(material, &mut images, &mut warnings).await);
+        done += 1;
+        set_stage(status, source, LoadStage::Decoding { done, count });
+        yield_now().await;
+    }
+
+    let mut loaded_meshes = Vec::new();
+    let mut skins = Vec::new();
+    for (mesh, node_name, matrix, node, skin) in meshes {
+        let name = node_name
+            .or_else(|| mesh.name().map(str::to_string))
+            .unwrap_or_else(|| format!("mesh {}", mesh.index()));
+        let primitives = mesh.primitives().len();
+        for primitive in mesh.primitives() {
+            let name = match primitives {
+                1 => name.clone(),
+                _ => format!("{}.{}", name, primitive.index()),
+            };
+            let joint_count = skin.as_ref().map(|skin| skin.joints.len());
+            let read = read_primitive(
+                &primitive,
+                &buffers,
+                name,
+                matrix,
+                joint_count,
+                &mut warnings,
+            )?;
+            if let (Some(node), Some(_)) = (node, &read) {
+                loaded_nodes[node].meshes.push(loaded_meshes.len());
+            }
+            let skinned = read
+                .as_ref()
+                .and_then(|mesh| mesh.attributes.as_ref())
+                .map_or(false, |data| data.layout.has(&VertexAttribute::Joints));
+            if let Some(skin) = skin.as_ref().filter(|_| skinned) {
+                skins.push(Skin {
+                    mesh: loaded_meshes.len(),
+                    ..skin.clone()
+                });
+            }
+            loaded_meshes.extend(read);
+        }
+        done += 1;
+        set_stage(status, source, LoadStage::Decoding { done, count });
+        yield_now().await;
+    }
+
+    Ok(LoadedModel {
+        source: source.to_string(),
+        meshes: loaded_meshes,
+        materials,
+        nodes: loaded_nodes,
+        animations,
+        skins,
+        warnings,
+    })
+}
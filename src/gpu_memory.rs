@@ -0,0 +1,273 @@
+//! A registry of the GPU buffers and textures, with their label, category and size. They are
+//! created through `DeviceMemoryExt` and come wrapped in `Tracked`, which keeps them in the
+//! registry until dropped. The Memory panel shows the totals per category and the largest
+//! ones, and the buffers of a model that was replaced are checked to be gone an update later,
+//! any still there being reported as leaked.
+//!
+//! egui's own buffers and textures aren't tracked.
+
+use std::{
+    collections::BTreeMap,
+    ops::Deref,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use wgpu::util::DeviceExt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GpuCategory {
+    /// Vertex, index and shape key buffers.
+    Meshes,
+    /// Material textures.
+    Textures,
+    /// Render targets, depth buffers and the shadow map.
+    Targets,
+    Lights,
+    Uniforms,
+    /// Debug lines and read-back buffers.
+    Other,
+}
+
+impl GpuCategory {
+    pub const ALL: [GpuCategory; 6] = [
+        GpuCategory::Meshes,
+        GpuCategory::Textures,
+        GpuCategory::Targets,
+        GpuCategory::Lights,
+        GpuCategory::Uniforms,
+        GpuCategory::Other,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GpuCategory::Meshes => "Meshes",
+            GpuCategory::Textures => "Textures",
+            GpuCategory::Targets => "Targets",
+            GpuCategory::Lights => "Lights",
+            GpuCategory::Uniforms => "Uniforms",
+            GpuCategory::Other => "Other",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GpuEntry {
+    /// Increasing in the order they were created.
+    pub id: u64,
+    pub label: String,
+    pub category: GpuCategory,
+    pub bytes: u64,
+}
+
+struct Registry {
+    next_id: u64,
+    entries: BTreeMap<u64, GpuEntry>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    next_id: 0,
+    entries: BTreeMap::new(),
+});
+
+fn registry() -> MutexGuard<'static, Registry> {
+    // The registry stays consistent if a thread panicked holding it.
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Everything in the registry, in the order it was created.
+pub fn entries() -> Vec<GpuEntry> {
+    registry().entries.values().cloned().collect()
+}
+
+/// The number of resources and their bytes per category, in the order of `GpuCategory::ALL`.
+pub fn totals() -> Vec<(GpuCategory, usize, u64)> {
+    let registry = registry();
+    GpuCategory::ALL
+        .iter()
+        .map(|&category| {
+            let entries = registry
+                .entries
+                .values()
+                .filter(|entry| entry.category == category);
+            let (count, bytes) = entries.fold((0, 0), |(count, bytes), entry| {
+                (count + 1, bytes + entry.bytes)
+            });
+            (category, count, bytes)
+        })
+        .collect()
+}
+
+pub fn total_bytes() -> u64 {
+    registry().entries.values().map(|entry| entry.bytes).sum()
+}
+
+/// Bytes of a texture with all its mip levels.
+pub fn texture_bytes(desc: &wgpu::TextureDescriptor) -> u64 {
+    let info = desc.format.describe();
+    let (block_width, block_height) = info.block_dimensions;
+    let (mut width, mut height) = (desc.size.width, desc.size.height);
+    let mut bytes = 0;
+    for _ in 0..desc.mip_level_count {
+        let blocks = ((width + block_width as u32 - 1) / block_width as u32) as u64
+            * ((height + block_height as u32 - 1) / block_height as u32) as u64;
+        bytes += blocks * info.block_size as u64;
+        width = (width / 2).max(1);
+        height = (height / 2).max(1);
+    }
+    bytes * desc.size.depth_or_array_layers as u64 * desc.sample_count as u64
+}
+
+/// A place in the registry, left when dropped.
+#[derive(Debug)]
+struct Allocation(u64);
+
+impl Allocation {
+    fn new(label: Option<&str>, category: GpuCategory, bytes: u64) -> Self {
+        let mut registry = registry();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        let label = label.unwrap_or("unlabeled").to_string();
+        registry.entries.insert(
+            id,
+            GpuEntry {
+                id,
+                label,
+                category,
+                bytes,
+            },
+        );
+        Self(id)
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        registry().entries.remove(&self.0);
+    }
+}
+
+/// A buffer or texture, kept in the registry while it lives.
+#[derive(Debug)]
+pub struct Tracked<T> {
+    resource: T,
+    allocation: Allocation,
+}
+
+impl<T> Tracked<T> {
+    pub fn id(&self) -> u64 {
+        self.allocation.0
+    }
+}
+
+impl Tracked<wgpu::Texture> {
+    /// A view of the texture, which keeps its place in the registry as it keeps the texture's
+    /// memory in use.
+    pub fn into_view(self, desc: &wgpu::TextureViewDescriptor) -> Tracked<wgpu::TextureView> {
+        Tracked {
+            resource: self.resource.create_view(desc),
+            allocation: self.allocation,
+        }
+    }
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.resource
+    }
+}
+
+/// Creates buffers and textures in the registry.
+pub trait DeviceMemoryExt {
+    fn create_tracked_buffer(
+        &self,
+        category: GpuCategory,
+        desc: &wgpu::BufferDescriptor,
+    ) -> Tracked<wgpu::Buffer>;
+
+    fn create_tracked_buffer_init(
+        &self,
+        category: GpuCategory,
+        desc: &wgpu::util::BufferInitDescriptor,
+    ) -> Tracked<wgpu::Buffer>;
+
+    fn create_tracked_texture(
+        &self,
+        category: GpuCategory,
+        desc: &wgpu::TextureDescriptor,
+    ) -> Tracked<wgpu::Texture>;
+}
+
+impl DeviceMemoryExt for wgpu::Device {
+    fn create_tracked_buffer(
+        &self,
+        category: GpuCategory,
+        desc: &wgpu::BufferDescriptor,
+    ) -> Tracked<wgpu::Buffer> {
+        Tracked {
+            resource: self.create_buffer(desc),
+            allocation: Allocation::new(desc.label, category, desc.size),
+        }
+    }
+
+    fn create_tracked_buffer_init(
+        &self,
+        category: GpuCategory,
+        desc: &wgpu::util::BufferInitDescriptor,
+    ) -> Tracked<wgpu::Buffer> {
+        // Buffers are padded to a multiple of four bytes.
+        let bytes = (desc.contents.len() as u64 + 3) / 4 * 4;
+        Tracked {
+            resource: self.create_buffer_init(desc),
+            allocation: Allocation::new(desc.label, category, bytes),
+        }
+    }
+
+    fn create_tracked_texture(
+        &self,
+        category: GpuCategory,
+        desc: &wgpu::TextureDescriptor,
+    ) -> Tracked<wgpu::Texture> {
+        Tracked {
+            resource: self.create_texture(desc),
+            allocation: Allocation::new(desc.label, category, texture_bytes(desc)),
+        }
+    }
+}
+
+/// Resources expected to be freed, checked at the next update.
+#[derive(Debug, Default)]
+pub struct LeakCheck {
+    pending: Vec<(String, Vec<u64>)>,
+    /// What was let go with the resources still alive after it.
+    pub leaks: Vec<(String, Vec<GpuEntry>)>,
+}
+
+impl LeakCheck {
+    /// Expects the resources of the ids to be gone by the next `check`, `what` telling whose
+    /// they were.
+    pub fn expect_freed(&mut self, what: String, ids: Vec<u64>) {
+        if !ids.is_empty() {
+            self.pending.push((what, ids));
+        }
+    }
+
+    /// Reports the expected resources still alive as leaked.
+    pub fn check(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let registry = registry();
+        for (what, ids) in std::mem::take(&mut self.pending) {
+            let alive = ids
+                .iter()
+                .filter_map(|id| registry.entries.get(id).cloned())
+                .collect::<Vec<_>>();
+            if !alive.is_empty() {
+                log::warn!("{} GPU resources of {} leaked", alive.len(), what);
+                self.leaks.push((what, alive));
+            }
+        }
+    }
+}
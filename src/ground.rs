@@ -0,0 +1,289 @@
+//! A top-down capture of the scene for standing it on the ground: the height of the highest
+//! surface over every texel of the ground under the scene, and an occlusion map darkening the
+//! ground under and around what stands on it, like buildings. Both are rasterized on the CPU
+//! from the visible meshes. The occlusion is laid on a ground plane added under the scene, or
+//! saved with the heights for a terrain material made elsewhere.
+
+use std::path::Path;
+
+use anyhow::*;
+use cgmath::{Point2, Point3, Transform};
+use image::{GrayImage, ImageBuffer, Luma, Rgba, RgbaImage};
+
+use crate::{
+    bake::barycentric,
+    bounds::Aabb,
+    outliner::GroupTransform,
+    primitive::Primitive,
+    registry::{MaterialTextures, NodeId},
+    scene::Scene,
+    texture::Texture,
+};
+
+#[derive(Debug, Clone)]
+pub struct GroundOcclusion {
+    /// Texels along each side of the capture.
+    pub resolution: u32,
+    /// How far around what stands on the ground it is darkened, in scene units.
+    pub radius: f32,
+    /// How dark the ground gets under something, from 0 for not at all to 1 for black.
+    pub strength: f32,
+    /// Ground left around the scene's bounds, in scene units.
+    pub margin: f32,
+    /// The ground plane added with the last capture, taken away by the next one.
+    pub plane: Option<NodeId>,
+}
+
+impl Default for GroundOcclusion {
+    fn default() -> Self {
+        Self {
+            resolution: 256,
+            radius: 2.0,
+            strength: 0.8,
+            margin: 2.0,
+            plane: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GroundCapture {
+    /// Center of the captured square, x and z.
+    pub center: [f32; 2],
+    /// Side of the square in scene units.
+    pub size: f32,
+    /// Height of the ground, the bottom of the scene's bounds.
+    pub ground: f32,
+    pub resolution: u32,
+    /// Height of the highest surface over each texel, or the ground's where there is none. Rows
+    /// go from -z to +z, as the plane primitive's texture coordinates do.
+    pub heights: Vec<f32>,
+    /// One where the ground is open, darker under and around what stands on it.
+    pub occlusion: Vec<f32>,
+}
+
+impl GroundCapture {
+    pub fn occlusion_image(&self) -> GrayImage {
+        let n = self.resolution;
+        GrayImage::from_fn(n, n, |x, y| {
+            let occlusion = self.occlusion[(y * n + x) as usize];
+            Luma([(occlusion * 255.).round() as u8])
+        })
+    }
+
+    /// Heights from black on the ground to white at the top of the scene.
+    pub fn height_image(&self) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+        let n = self.resolution;
+        let top = self.heights.iter().cloned().fold(self.ground, f32::max);
+        let range = (top - self.ground).max(f32::EPSILON);
+        ImageBuffer::from_fn(n, n, |x, y| {
+            let height = (self.heights[(y * n + x) as usize] - self.ground) / range;
+            Luma([(height * 65535.).round() as u16])
+        })
+    }
+
+    /// Writes `{prefix}_occlusion.png` and `{prefix}_height.png` into the directory.
+    pub fn save<P: AsRef<Path>>(&self, dir: P, prefix: &str) -> Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        self.occlusion_image()
+            .save(dir.join(format!("{}_occlusion.png", prefix)))?;
+        self.height_image()
+            .save(dir.join(format!("{}_height.png", prefix)))?;
+        Ok(())
+    }
+}
+
+/// Captures the world space triangles from above, or `None` without any.
+pub fn capture(
+    triangles: &[[Point3<f32>; 3]],
+    settings: &GroundOcclusion,
+) -> Option<GroundCapture> {
+    if triangles.is_empty() {
+        return None;
+    }
+    let bounds = Aabb::from_points(triangles.iter().flatten().cloned());
+    let (min, max) = (bounds.min, bounds.max);
+    let n = settings.resolution.max(1);
+    let ground = min.y;
+    let center = [(min.x + max.x) / 2., (min.z + max.z) / 2.];
+    let size = ((max.x - min.x).max(max.z - min.z) + 2. * settings.margin.max(0.)).max(1e-3);
+    let texel = size / n as f32;
+    let to_texel = |p: Point3<f32>| {
+        Point2::new(
+            (p.x - center[0]) / texel + n as f32 / 2.,
+            (p.z - center[1]) / texel + n as f32 / 2.,
+        )
+    };
+    let mut heights = vec![ground; (n * n) as usize];
+    let mut raise = |x: f32, y: f32, height: f32| {
+        if x >= 0. && y >= 0. && x < n as f32 && y < n as f32 {
+            let h = &mut heights[y as usize * n as usize + x as usize];
+            *h = h.max(height);
+        }
+    };
+    for triangle in triangles {
+        let texels = triangle.map(to_texel);
+        let [a, b, c] = texels;
+        let lo = (a.x.min(b.x).min(c.x).floor().max(0.), a.y.min(b.y).min(c.y).floor().max(0.));
+        let hi = (
+            a.x.max(b.x).max(c.x).ceil().min(n as f32),
+            a.y.max(b.y).max(c.y).ceil().min(n as f32),
+        );
+        for y in lo.1 as u32..hi.1.max(lo.1) as u32 {
+            for x in lo.0 as u32..hi.0.max(lo.0) as u32 {
+                let p = Point2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let w = match barycentric(p, a, b, c) {
+                    Some(w) if w.iter().all(|&w| w >= 0.) => w,
+                    _ => continue,
+                };
+                let height = w[0] * triangle[0].y + w[1] * triangle[1].y + w[2] * triangle[2].y;
+                raise(p.x, p.y, height);
+            }
+        }
+        // Walls seen from above are lines covering no texel centers, their edges are walked.
+        for i in 0..3 {
+            let (from, to) = (i, (i + 1) % 3);
+            let length = (texels[to].x - texels[from].x).hypot(texels[to].y - texels[from].y);
+            let steps = (length * 2.).ceil() as u32 + 1;
+            for step in 0..=steps {
+                let t = step as f32 / steps as f32;
+                let x = texels[from].x + (texels[to].x - texels[from].x) * t;
+                let y = texels[from].y + (texels[to].y - texels[from].y) * t;
+                raise(x, y, triangle[from].y + (triangle[to].y - triangle[from].y) * t);
+            }
+        }
+    }
+    // How much each texel occludes the ground around it: nothing for the ground itself, fully
+    // for anything standing at least the radius tall.
+    let radius = settings.radius.max(texel);
+    let cover = heights
+        .iter()
+        .map(|&height| {
+            let above = height - ground;
+            if above < texel / 2. {
+                0.
+            } else {
+                (above / radius).min(1.)
+            }
+        })
+        .collect::<Vec<_>>();
+    // Two box blurs of half the radius fall off about like a tent of the whole radius.
+    let half = ((radius / texel) / 2.).ceil().max(1.) as usize;
+    let blurred = blur(&blur(&cover, n as usize, half), n as usize, half);
+    let strength = settings.strength.max(0.).min(1.);
+    let occlusion = cover
+        .iter()
+        .zip(blurred.iter())
+        .map(|(&cover, &blurred)| 1. - strength * cover.max((blurred * 2.).min(1.)))
+        .collect();
+    Some(GroundCapture {
+        center,
+        size,
+        ground,
+        resolution: n,
+        heights,
+        occlusion,
+    })
+}
+
+/// Averages every value of the square grid with the ones up to `radius` away along the rows,
+/// then the columns. Outside the grid counts as open ground.
+fn blur(values: &[f32], n: usize, radius: usize) -> Vec<f32> {
+    let width = (2 * radius + 1) as f32;
+    let pass = |values: &[f32], at: &dyn Fn(usize, usize) -> usize| {
+        let mut out = vec![0.; values.len()];
+        for line in 0..n {
+            let mut sum: f32 = (0..=radius.min(n - 1)).map(|i| values[at(line, i)]).sum();
+            for i in 0..n {
+                out[at(line, i)] = sum / width;
+                if i + radius + 1 < n {
+                    sum += values[at(line, i + radius + 1)];
+                }
+                if i >= radius {
+                    sum -= values[at(line, i - radius)];
+                }
+            }
+        }
+        out
+    };
+    let rows = pass(values, &|line, i| line * n + i);
+    pass(&rows, &|line, i| i * n + line)
+}
+
+impl Scene {
+    /// Registry name of the ground plane's mesh, material and texture.
+    const GROUND: &'static str = "Ground";
+
+    /// The triangles of the visible models where they are in the scene.
+    pub(crate) fn world_triangles(&self) -> Vec<[Point3<f32>; 3]> {
+        let matrices = self.mesh_matrices();
+        let mut triangles = Vec::new();
+        for (i, model) in self.models.iter().enumerate() {
+            if !self.outliner.is_visible(i) {
+                continue;
+            }
+            for (mesh, matrix) in model.meshes().iter().zip(matrices[i].iter()) {
+                let position = |index: &u32| {
+                    let position = mesh.vertices[*index as usize].position;
+                    matrix.transform_point(Point3::from(position))
+                };
+                for triangle in mesh.indices.chunks_exact(3) {
+                    triangles.push([
+                        position(&triangle[0]),
+                        position(&triangle[1]),
+                        position(&triangle[2]),
+                    ]);
+                }
+            }
+        }
+        triangles
+    }
+
+    /// Captures the visible models from above, or `None` when there are none.
+    pub fn capture_ground(&self) -> Option<GroundCapture> {
+        profile_function!();
+        capture(&self.world_triangles(), &self.ground)
+    }
+
+    /// Lays a plane under the scene, its color darkened by the occlusion captured from above.
+    /// It takes the place of the plane of the last capture, which is left out of this one.
+    pub fn add_ground(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Result<NodeId> {
+        if let Some(plane) = self.ground.plane.take() {
+            let is_plane = self.models.get(plane.0).map_or(false, |model| {
+                model.meshes().iter().all(|mesh| mesh.name == Self::GROUND)
+            });
+            if is_plane {
+                self.remove_model(plane);
+            }
+        }
+        let capture = self.capture_ground().context("There is nothing to stand on the ground")?;
+        let n = capture.resolution;
+        let color = RgbaImage::from_fn(n, n, |x, y| {
+            let shade = (capture.occlusion[(y * n + x) as usize] * 204.).round() as u8;
+            Rgba([shade, shade, shade, 0xff])
+        });
+        let texture = Texture::from_rgba(device, queue, &color, Some(Self::GROUND), false);
+        let texture = self.add_texture(Self::GROUND, texture);
+        let textures = MaterialTextures {
+            diffuse: Some(texture),
+            ..Default::default()
+        };
+        let material = self.create_material(device, queue, config, Self::GROUND, textures)?;
+        let mesh = self.add_mesh(Self::GROUND, Primitive::Plane.mesh(capture.size));
+        // A little under the scene, so a floor of its own doesn't fight with it.
+        let below = capture.ground - capture.size * 1e-4;
+        let transform = GroupTransform {
+            translation: [capture.center[0], below, capture.center[1]],
+            ..Default::default()
+        };
+        let node = self.spawn(device, mesh, material, transform)?;
+        self.ground.plane = Some(node);
+        Ok(node)
+    }
+}
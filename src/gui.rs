@@ -11,6 +11,49 @@ use instant::Instant;
 use anyhow::*;
 pub enum Event {
     RequestRedraw,
+    ToggleFullscreen,
+    /// The page around the canvas changed size, on the web.
+    CanvasResized(winit::dpi::LogicalSize<f64>),
+    /// Adds the OBJ or glTF model at the URL or path to the scene.
+    LoadModel(String),
+    /// Loads the OBJ or glTF model at the URL or path again, replacing the one loaded from it before.
+    ReloadModel(String),
+    /// Adds the models copied as text, from this or another instance.
+    PasteModels(String),
+    /// Adds the primitive at the 3D cursor.
+    AddPrimitive(crate::primitive::Primitive),
+    /// Adds a point light at the 3D cursor.
+    AddLight,
+    SetCamera {
+        eye: cgmath::Point3<f32>,
+        target: cgmath::Point3<f32>,
+    },
+    /// Navigates the viewport the way the scheme does.
+    SetCameraScheme(crate::camera_rig::CameraScheme),
+    /// Looks through the scene camera of the index, or with `None` from the viewport's own view.
+    LookThrough(Option<usize>),
+    /// Renders a frame through the scene camera of the index into the image file.
+    RenderCamera {
+        camera: usize,
+        path: std::path::PathBuf,
+    },
+    /// Exports the review notes into the folder, screenshots with the report.
+    ExportReview(std::path::PathBuf),
+    /// Lights the scene with the equirectangular environment map at the URL or path.
+    LoadEnvironment(String),
+    /// Captures the scene from above and lays a ground plane darkened by it under the scene.
+    AddGround,
+    /// Saves the next frame with the GUI as a PNG named after the time, or downloads it on the
+    /// web. Also on F12.
+    CaptureFrame,
+    /// Opens the project bundle at the path, loading its models.
+    #[cfg(not(target_arch = "wasm32"))]
+    OpenBundle(std::path::PathBuf),
+    /// Calls the subscriber with every scene event from now on.
+    Subscribe(crate::events::Subscriber),
+    /// Calls the function with the indices of the selected models whenever they change.
+    #[cfg(target_arch = "wasm32")]
+    OnSelect(js_sys::Function),
 }
 
 use winit::{
@@ -21,8 +64,16 @@ use winit::{
 };
 
 use crate::{
+    clipboard::Clipboard,
     collection::{self, Collection},
+    loading::LoadStage,
+    normals::NormalSettings,
+    poses::PoseSettings,
     scene::Scene,
+    shadow::ShadowMap,
+    remesh::RemeshSettings,
+    scan::CleanupSettings,
+    smoothing::SmoothSettings,
 };
 
 /// This is the repaint signal type that egui needs for requesting a repaint from another thread.
@@ -41,6 +92,13 @@ impl epi::RepaintSignal for ExampleRepaintSignal {
 pub struct Gui {
     platform: Platform,
     render_pass: RenderPass,
+    /// egui asked for another frame after the last one, e.g. for an animation.
+    needs_repaint: bool,
+    /// The finger acting as the mouse, see `handle_touch`.
+    touch_id: Option<u64>,
+    /// The scale factor the platform lays the GUI out at, from the same events it got, so the
+    /// layout and the pixels it's drawn to agree while the window moves between monitors.
+    scale_factor: f64,
     repaint_signal: std::sync::Arc<ExampleRepaintSignal>,
     app: Box<dyn epi::App>,
     // app: egui_demo_lib::WrapApp,
@@ -78,11 +136,14 @@ impl Gui {
 
         // Display the demo application that ships with egui.
         // let demo_app = egui_demo_lib::WrapApp::default();
-        let demo_app = MyApp::new(scene, collection);
+        let demo_app = MyApp::new(scene, collection, event_loop.create_proxy());
 
         Gui {
             platform,
             render_pass: egui_rpass,
+            needs_repaint: true,
+            touch_id: None,
+            scale_factor: window.scale_factor(),
             repaint_signal,
             app: Box::new(demo_app),
         }
@@ -100,6 +161,7 @@ impl Gui {
         width: u32,
         height: u32,
     ) -> Result<()> {
+        profile_function!();
         self.platform
             .update_time(start_time.elapsed().as_secs_f64());
 
@@ -112,7 +174,7 @@ impl Gui {
             info: epi::IntegrationInfo {
                 web_info: None,
                 cpu_usage: *previous_frame_time,
-                native_pixels_per_point: Some(window.scale_factor() as _),
+                native_pixels_per_point: Some(self.scale_factor as _),
                 prefer_dark_mode: None,
                 name: "modeling",
             },
@@ -124,11 +186,18 @@ impl Gui {
 
         // Draw the demo application.
         //use eself_demo_lib::WrapApp::*;
-        self.app.update(&self.platform.context(), &mut iframe);
+        {
+            profile_scope!("GUI layout");
+            self.app.update(&self.platform.context(), &mut iframe);
+        }
 
         // End the UI frame. We could now handle the output and draw the UI with the backend.
-        let (_output, paint_commands) = self.platform.end_frame(Some(window));
-        let paint_jobs = self.platform.context().tessellate(paint_commands);
+        let (output, paint_commands) = self.platform.end_frame(Some(window));
+        self.needs_repaint = output.needs_repaint;
+        let paint_jobs = {
+            profile_scope!("GUI tessellation");
+            self.platform.context().tessellate(paint_commands)
+        };
 
         let frame_time = (Instant::now() - eself_start).as_secs_f64() as f32;
         *previous_frame_time = Some(frame_time);
@@ -137,7 +206,7 @@ impl Gui {
         let screen_descriptor = ScreenDescriptor {
             physical_width: width,
             physical_height: height,
-            scale_factor: window.scale_factor() as f32,
+            scale_factor: self.scale_factor as f32,
         };
         self.render_pass
             .update_texture(device, queue, &self.platform.context().texture());
@@ -151,29 +220,2450 @@ impl Gui {
         Ok(())
     }
 
+    /// Lets egui draw the shadow map's debug copy once it exists.
+    pub fn register_shadow_debug(&mut self, device: &wgpu::Device, shadows: &mut ShadowMap) {
+        if shadows.debug_texture_id.is_some() {
+            return;
+        }
+        if let Some(texture) = shadows.debug_texture() {
+            let id = self.render_pass.egui_texture_from_wgpu_texture(
+                device,
+                texture,
+                wgpu::FilterMode::Nearest,
+            );
+            shadows.debug_texture_id = Some(id);
+        }
+    }
+
     pub fn handle_event<T>(&mut self, event: &winit::event::Event<T>) {
+        match event {
+            WindowEvent {
+                window_id,
+                event: winit::event::WindowEvent::Touch(touch),
+            } => self.handle_touch(*window_id, touch),
+            WindowEvent {
+                event: winit::event::WindowEvent::ScaleFactorChanged { scale_factor, .. },
+                ..
+            } => {
+                self.scale_factor = *scale_factor;
+                // Laid out again at the new size right away, even when idle.
+                self.needs_repaint = true;
+            }
+            _ => {}
+        }
         self.platform.handle_event(event);
     }
+
+    /// The platform integration only knows the mouse, so the first finger on the screen is fed
+    /// to it as the cursor with the left button held.
+    #[allow(deprecated)]
+    fn handle_touch(&mut self, window_id: winit::window::WindowId, touch: &winit::event::Touch) {
+        use winit::event::{ElementState, MouseButton, TouchPhase, WindowEvent};
+
+        let device_id = touch.device_id;
+        let modifiers = Default::default();
+        let mut events = vec![WindowEvent::CursorMoved {
+            device_id,
+            position: touch.location,
+            modifiers,
+        }];
+        match touch.phase {
+            TouchPhase::Started if self.touch_id.is_none() => {
+                self.touch_id = Some(touch.id);
+                events.push(WindowEvent::MouseInput {
+                    device_id,
+                    state: ElementState::Pressed,
+                    button: MouseButton::Left,
+                    modifiers,
+                });
+            }
+            TouchPhase::Moved if self.touch_id == Some(touch.id) => {}
+            TouchPhase::Ended | TouchPhase::Cancelled if self.touch_id == Some(touch.id) => {
+                self.touch_id = None;
+                events.push(WindowEvent::MouseInput {
+                    device_id,
+                    state: ElementState::Released,
+                    button: MouseButton::Left,
+                    modifiers,
+                });
+                // Nothing stays hovered once the finger is gone.
+                events.push(WindowEvent::CursorLeft { device_id });
+            }
+            _ => return,
+        }
+        for event in events {
+            let event: winit::event::Event<()> =
+                winit::event::Event::WindowEvent { window_id, event };
+            self.platform.handle_event(&event);
+        }
+    }
+
+    /// Whether the pointer is over a window or dragging one, so the scene shouldn't react to it.
+    pub fn wants_pointer_input(&self) -> bool {
+        self.platform.context().wants_pointer_input()
+    }
+
+    /// Whether the last frame asked for another one.
+    pub fn needs_repaint(&self) -> bool {
+        self.needs_repaint
+    }
+
+    /// Whether a text field has focus, so key presses are typed rather than shortcuts.
+    pub fn wants_keyboard_input(&self) -> bool {
+        self.platform.context().wants_keyboard_input()
+    }
+}
+
+/// Asks for a model file in the system's dialog and loads it into the scene, and into the
+/// collection too once loaded if it's an OBJ file. The scene isn't locked while the dialog is
+/// open.
+#[cfg(all(feature = "file-dialog", not(target_arch = "wasm32")))]
+fn open_model(scene: &RwLock<Scene>, collection: &Arc<RwLock<Collection>>) {
+    let path = match rfd::FileDialog::new()
+        .add_filter("Models", &["obj", "gltf", "glb"])
+        .pick_file()
+    {
+        Some(path) => path,
+        None => return,
+    };
+    let source = path.to_string_lossy().into_owned();
+    scene
+        .write()
+        .unwrap()
+        .start_collected_load(source, collection.clone());
+}
+
+/// The node's transform, parent and constraints, with its children nested below. Nodes can be
+/// anchored to the `surface` the 3D cursor is on.
+fn node_ui(
+    ui: &mut egui::Ui,
+    graph: &mut crate::scene_graph::SceneGraph,
+    node: usize,
+    surface: Option<crate::constraints::SurfaceAnchor>,
+) {
+    use cgmath::{Deg, Euler, Quaternion};
+
+    let name = graph.nodes[node].name.clone();
+    egui::CollapsingHeader::new(&name)
+        .id_source(("scene graph node", node))
+        .show(ui, |ui| {
+            let local = &mut graph.nodes[node].local;
+            ui.horizontal(|ui| {
+                ui.label("Translation");
+                for axis in 0..3 {
+                    ui.add(egui::DragValue::new(&mut local.translation[axis]).speed(0.1));
+                }
+            });
+            let euler = Euler::from(local.rotation);
+            let mut degrees = [euler.x, euler.y, euler.z].map(|angle| Deg::from(angle).0);
+            ui.horizontal(|ui| {
+                ui.label("Rotation");
+                let mut changed = false;
+                for angle in degrees.iter_mut() {
+                    changed |= ui.add(egui::DragValue::new(angle).suffix("°")).changed();
+                }
+                if changed {
+                    let [x, y, z] = degrees.map(Deg);
+                    local.rotation = Quaternion::from(Euler::new(x, y, z));
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Scale");
+                for axis in 0..3 {
+                    ui.add(egui::DragValue::new(&mut local.scale[axis]).speed(0.01));
+                }
+            });
+            let parent = graph.nodes[node].parent;
+            let parent_name = parent.map_or("None", |p| graph.nodes[p].name.as_str());
+            let mut chosen = parent;
+            egui::ComboBox::from_id_source(("scene graph parent", node))
+                .selected_text(format!("parent: {}", parent_name))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut chosen, None, "None");
+                    for other in (0..graph.nodes.len()).filter(|&o| !graph.is_ancestor(node, o)) {
+                        ui.selectable_value(&mut chosen, Some(other), &graph.nodes[other].name);
+                    }
+                });
+            if chosen != parent {
+                graph.set_parent(node, chosen);
+            }
+            constraints_ui(ui, graph, node, surface);
+            for child in graph.nodes[node].children.clone() {
+                node_ui(ui, graph, child, surface);
+            }
+        });
+}
+
+/// The node's constraints, and buttons adding more.
+fn constraints_ui(
+    ui: &mut egui::Ui,
+    graph: &mut crate::scene_graph::SceneGraph,
+    node: usize,
+    surface: Option<crate::constraints::SurfaceAnchor>,
+) {
+    use crate::constraints::{Constraint, FollowPath, Target};
+    use cgmath::{EuclideanSpace, Point3, Transform};
+
+    let names = graph.nodes.iter().map(|node| node.name.clone()).collect::<Vec<_>>();
+    let others = (0..names.len()).filter(|&other| other != node).collect::<Vec<_>>();
+    let mut removed = None;
+    for (i, constraint) in graph.nodes[node].constraints.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.label(constraint.name());
+            match constraint {
+                Constraint::LookAt(target) => {
+                    let selected = match *target {
+                        Target::Node(other) => names.get(other).map_or("", String::as_str),
+                        Target::Point(_) => "origin",
+                    };
+                    egui::ComboBox::from_id_source(("look at", node, i))
+                        .selected_text(selected)
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(target, Target::Point(Point3::origin()), "origin");
+                            for &other in others.iter() {
+                                ui.selectable_value(target, Target::Node(other), &names[other]);
+                            }
+                        });
+                }
+                Constraint::FollowPath(path) => {
+                    ui.add(
+                        egui::DragValue::new(&mut path.duration)
+                            .clamp_range(0.1..=600.0)
+                            .speed(0.1)
+                            .suffix(" s a lap"),
+                    );
+                    ui.checkbox(&mut path.closed, "Closed");
+                }
+                Constraint::CopyTransform(source) => {
+                    egui::ComboBox::from_id_source(("copy transform", node, i))
+                        .selected_text(names.get(*source).map_or("", String::as_str))
+                        .show_ui(ui, |ui| {
+                            for &other in others.iter() {
+                                ui.selectable_value(source, other, &names[other]);
+                            }
+                        });
+                }
+                Constraint::Surface(anchor) => {
+                    ui.label(format!("model {}, mesh {}", anchor.model, anchor.mesh));
+                    ui.checkbox(&mut anchor.align, "Align to the normal");
+                }
+            }
+            if ui.button("Remove").clicked() {
+                removed = Some(i);
+            }
+        });
+    }
+    let start = graph.world(node).transform_point(Point3::origin());
+    let model = graph.nodes[node].model;
+    let surface = surface.filter(|anchor| Some(anchor.model) != model);
+    let constraints = &mut graph.nodes[node].constraints;
+    if let Some(i) = removed {
+        constraints.remove(i);
+    }
+    ui.horizontal(|ui| {
+        ui.label("Add");
+        if ui.button("Look at").clicked() {
+            constraints.push(Constraint::LookAt(Target::Point(Point3::origin())));
+        }
+        if ui.button("Circle the origin").clicked() {
+            let path = FollowPath::circle(Point3::origin(), start, 64, 10.);
+            constraints.push(Constraint::FollowPath(path));
+            constraints.push(Constraint::LookAt(Target::Point(Point3::origin())));
+        }
+        if let Some(&other) = others.first() {
+            if ui.button("Copy transform").clicked() {
+                constraints.push(Constraint::CopyTransform(other));
+            }
+        }
+        let attach = ui
+            .add_enabled(surface.is_some(), egui::Button::new("Surface"))
+            .on_disabled_hover_text("Place the 3D cursor on another model first");
+        if let Some(anchor) = surface.filter(|_| attach.clicked()) {
+            constraints.push(Constraint::Surface(anchor));
+        }
+    });
 }
 
 struct MyApp {
     scene: Arc<RwLock<Scene>>,
     collection: Arc<RwLock<Collection>>,
     counter: u32,
+    /// Lattice control point edited in the modifier panel.
+    lattice_point: [usize; 3],
+    smooth: SmoothSettings,
+    normals: NormalSettings,
+    remesh: RemeshSettings,
+    cleanup: CleanupSettings,
+    poses: PoseSettings,
+    /// Where the outliner saves and loads the project.
+    project_path: String,
+    /// Where the outliner exports and opens project bundles.
+    #[cfg(not(target_arch = "wasm32"))]
+    bundle_path: String,
+    /// Image file frames rendered through scene cameras are saved to.
+    render_path: String,
+    /// Text of the next review note.
+    note: String,
+    /// Folder the review is exported to.
+    review_path: String,
+    /// Environment map lighting the scene, a Radiance HDR or 8-bit equirectangular image.
+    environment_source: String,
+    /// Folder the ground capture's occlusion and height maps are saved to.
+    ground_path: String,
+    /// OBJ model added to the scene from the outliner, a URL on the web.
+    model_source: String,
+    /// The models copied last, pasted by the outliner's button. Without the `clipboard`
+    /// feature the system clipboard can't be read, so this is the only way to paste them.
+    copied: String,
+    /// How the viewport is navigated, kept by the event loop's camera controller.
+    camera_scheme: crate::camera_rig::CameraScheme,
+    /// Whether the Profiler window is open.
+    #[cfg(feature = "profiling")]
+    profiler: bool,
+    /// Asks the event loop for what needs the window, like fullscreen.
+    proxy: winit::event_loop::EventLoopProxy<Event>,
 }
 
 impl MyApp {
-    fn new(scene: Arc<RwLock<Scene>>, collection: Arc<RwLock<Collection>>) -> Self {
+    fn new(
+        scene: Arc<RwLock<Scene>>,
+        collection: Arc<RwLock<Collection>>,
+        proxy: winit::event_loop::EventLoopProxy<Event>,
+    ) -> Self {
         Self {
             scene,
             counter: 0,
             collection,
+            lattice_point: [0; 3],
+            smooth: SmoothSettings::default(),
+            normals: NormalSettings::default(),
+            remesh: RemeshSettings::default(),
+            cleanup: CleanupSettings::default(),
+            poses: PoseSettings::default(),
+            project_path: "project.ron".to_string(),
+            #[cfg(not(target_arch = "wasm32"))]
+            bundle_path: "project.modpack".to_string(),
+            render_path: "render.png".to_string(),
+            note: String::new(),
+            review_path: "review".to_string(),
+            environment_source: "environment.hdr".to_string(),
+            ground_path: "ground".to_string(),
+            model_source: if cfg!(target_arch = "wasm32") {
+                "res/cube.obj".to_string()
+            } else {
+                crate::assets::res_dir().join("cube.obj").to_string_lossy().into_owned()
+            },
+            copied: String::new(),
+            camera_scheme: Default::default(),
+            #[cfg(feature = "profiling")]
+            profiler: false,
+            proxy,
+        }
+    }
+}
+
+/// A count shortened to thousands or millions, e.g. 1.2M.
+fn short_count(count: u64) -> String {
+    match count {
+        0..=9_999 => count.to_string(),
+        10_000..=999_999 => format!("{:.1}k", count as f64 / 1e3),
+        _ => format!("{:.1}M", count as f64 / 1e6),
+    }
+}
+
+/// Puts the selected models on the clipboard as text, and keeps them in `copied`.
+fn copy_selected(scene: &Scene, ctx: &egui::CtxRef, copied: &mut String) {
+    let clipboard = Clipboard::copy(scene);
+    if clipboard.is_empty() {
+        return;
+    }
+    match clipboard.to_text() {
+        Ok(text) => {
+            ctx.output().copied_text = text.clone();
+            *copied = text;
+        }
+        Err(e) => log::warn!("Copying failed: {:?}", e),
+    }
+}
+
+impl MyApp {
+    /// Ctrl+C copies the selected models and Ctrl+V pastes copied ones, unless a text field has
+    /// the keyboard.
+    fn clipboard_shortcuts(&mut self, ctx: &egui::CtxRef) {
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+        let mut copy = false;
+        let mut pasted = None;
+        for event in ctx.input().events.iter() {
+            match event {
+                egui::Event::Copy => copy = true,
+                // The platform turns Ctrl+V into the clipboard's text.
+                egui::Event::Text(text) if Clipboard::is_models(text) => {
+                    pasted = Some(text.clone())
+                }
+                _ => {}
+            }
+        }
+        if copy {
+            copy_selected(&self.scene.read().unwrap(), ctx, &mut self.copied);
+        }
+        if let Some(text) = pasted {
+            self.proxy.send_event(Event::PasteModels(text)).ok();
+        }
+    }
+
+    /// Frame rate, what a frame draws and the resolution scale, in the top right corner.
+    fn stats_overlay(&mut self, ctx: &egui::CtxRef) {
+        let scene = self.scene.read().unwrap();
+        let stats = scene.stats();
+        egui::Window::new("Stats")
+            .anchor(egui::Align2::RIGHT_TOP, [-10., 10.])
+            .resizable(false)
+            .collapsible(true)
+            .show(ctx, |ui| {
+                ui.label(format!("{:.0} fps", scene.resolution.fps()));
+                ui.label(format!("{} draw calls", stats.draw_calls));
+                ui.label(format!("{} triangles", stats.triangles));
+                ui.label(format!("{:.1} MiB VRAM", stats.vram_bytes as f64 / (1 << 20) as f64));
+                ui.label(format!(
+                    "Render scale {:.0}%{}",
+                    scene.resolution.scale() * 100.,
+                    if scene.resolution.full_effects() { "" } else { ", no shadows" }
+                ));
+                for (what, count, limit) in scene.budget.exceeded(&stats) {
+                    let (count, limit) = (short_count(count), short_count(limit));
+                    let text = format!("Over budget: {} / {} {}", count, limit, what);
+                    ui.colored_label(egui::Color32::RED, text);
+                }
+            });
+    }
+
+    /// Progress of the models being loaded, in the middle of the screen.
+    fn loading_overlay(&mut self, ctx: &egui::CtxRef) {
+        let mut scene = self.scene.write().unwrap();
+        if scene.load_jobs.is_empty() {
+            return;
+        }
+        let mut dismissed = None;
+        egui::Window::new("Loading")
+            .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                for (i, job) in scene.load_jobs.iter().enumerate() {
+                    let status = job.status();
+                    ui.label(&status.file);
+                    let text = match status.stage {
+                        LoadStage::Downloading { received, total } => {
+                            let mib = |bytes: u64| bytes as f64 / (1 << 20) as f64;
+                            match total {
+                                Some(total) => {
+                                    format!("{:.1} / {:.1} MiB", mib(received), mib(total))
+                                }
+                                None => format!("{:.1} MiB", mib(received)),
+                            }
+                        }
+                        LoadStage::Decoding { done, count } if count > 0 => {
+                            format!("Decoding {} / {}", done, count)
+                        }
+                        LoadStage::Decoding { .. } => "Parsing".to_string(),
+                        LoadStage::Failed(error) => {
+                            ui.colored_label(egui::Color32::RED, error);
+                            if ui.button("Dismiss").clicked() {
+                                dismissed = Some(i);
+                            }
+                            continue;
+                        }
+                    };
+                    let fraction = status.fraction();
+                    ui.add(
+                        egui::ProgressBar::new(fraction.unwrap_or(0.))
+                            .text(text)
+                            .animate(fraction.is_none()),
+                    );
+                }
+            });
+        if let Some(job) = dismissed {
+            scene.dismiss_load(job);
+        }
+    }
+
+    /// Steps and progress of the scan cleanups running, in the middle of the screen.
+    fn cleanup_overlay(&mut self, ctx: &egui::CtxRef) {
+        let mut scene = self.scene.write().unwrap();
+        if scene.cleanup_jobs.is_empty() {
+            return;
+        }
+        let mut cancelled = None;
+        egui::Window::new("Scan cleanup")
+            .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                for job in scene.cleanup_jobs.iter() {
+                    ui.label(format!("Model {}", job.model));
+                    let text = match job.step() {
+                        Some(step) => {
+                            let index = job.steps.iter().position(|s| *s == step).unwrap_or(0);
+                            format!("{} ({} / {})", step.name(), index + 1, job.steps.len())
+                        }
+                        None => "Finishing".to_string(),
+                    };
+                    ui.add(egui::ProgressBar::new(job.progress()).text(text));
+                    if ui.button("Cancel").clicked() {
+                        cancelled = Some(job.model);
+                    }
+                }
+            });
+        if let Some(model) = cancelled {
+            scene.cancel_cleanup(model);
+        }
+    }
+
+    /// The files of the project being opened that are missing, and where to look for them.
+    fn remap_overlay(&mut self, ctx: &egui::CtxRef) {
+        let mut scene = self.scene.write().unwrap();
+        let remap = match scene.remap.as_mut() {
+            Some(remap) => remap,
+            None => return,
+        };
+        let (mut open, mut cancel) = (false, false);
+        egui::Window::new("Missing files")
+            .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label("The project names files that aren't where it says. Look for them in");
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut remap.base);
+                    if ui
+                        .button("Use folder")
+                        .on_hover_text("Where their paths end up below the folder")
+                        .clicked()
+                    {
+                        remap.rebase();
+                    }
+                    if ui
+                        .button("Search folder")
+                        .on_hover_text("By name anywhere below the folder")
+                        .clicked()
+                    {
+                        remap.search();
+                    }
+                });
+                egui::ScrollArea::vertical().max_height(200.).show(ui, |ui| {
+                    for (file, found) in remap.files.iter() {
+                        ui.label(file);
+                        match found {
+                            Some(found) => ui.colored_label(egui::Color32::GREEN, found),
+                            None => ui.colored_label(egui::Color32::RED, "Missing"),
+                        };
+                    }
+                });
+                let missing = remap.files.len() - remap.found();
+                if missing > 0 {
+                    ui.label(format!("{} files still missing are left out", missing));
+                }
+                ui.horizontal(|ui| {
+                    open = ui.button("Open").clicked();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            });
+        if open {
+            scene.finish_remap();
+        } else if cancel {
+            scene.remap = None;
+        }
+    }
+
+    fn render_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Render").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Fullscreen (F11)").clicked() {
+                    self.proxy.send_event(Event::ToggleFullscreen).ok();
+                }
+                if ui.button("Screenshot (F12)").clicked() {
+                    self.proxy.send_event(Event::CaptureFrame).ok();
+                }
+            });
+            #[cfg(feature = "profiling")]
+            ui.checkbox(&mut self.profiler, "Profiler");
+            let mut scene = self.scene.write().unwrap();
+            let resolution = &mut scene.resolution;
+            let mut percent = resolution.render_scale * 100.;
+            let slider = egui::Slider::new(&mut percent, 50.0..=200.0)
+                .suffix("%")
+                .text("render scale");
+            if ui.add(slider).changed() {
+                resolution.render_scale = percent / 100.;
+            }
+            ui.checkbox(&mut resolution.enabled, "Dynamic resolution");
+            ui.add_enabled(
+                resolution.enabled,
+                egui::Slider::new(&mut resolution.target_fps, 20.0..=60.0).text("target fps"),
+            );
+            ui.add_enabled(
+                resolution.enabled,
+                egui::Slider::new(&mut resolution.min_scale, 0.25..=1.0).text("minimum scale"),
+            );
+            ui.horizontal(|ui| {
+                use crate::stereo::StereoMode;
+
+                let stereo = &mut scene.stereo;
+                ui.checkbox(&mut stereo.enabled, "Stereo");
+                ui.radio_value(&mut stereo.mode, StereoMode::SideBySide, "Side by side");
+                ui.radio_value(&mut stereo.mode, StereoMode::Anaglyph, "Anaglyph");
+            });
+            ui.add_enabled(
+                scene.stereo.enabled,
+                egui::Slider::new(&mut scene.stereo.eye_separation, 0.001..=10.0)
+                    .logarithmic(true)
+                    .text("eye separation"),
+            );
+            ui.horizontal(|ui| {
+                use crate::display::DisplayMode;
+
+                let display = &mut scene.display;
+                egui::ComboBox::from_label("display")
+                    .selected_text(display.mode.name())
+                    .show_ui(ui, |ui| {
+                        for &mode in DisplayMode::ALL.iter() {
+                            ui.selectable_value(&mut display.mode, mode, mode.name());
+                        }
+                    });
+                if display.mode == DisplayMode::Flat {
+                    ui.color_edit_button_rgb(&mut display.flat_color);
+                }
+            });
+            ui.horizontal(|ui| {
+                use crate::xray::XRayMode;
+
+                let xray = &mut scene.xray;
+                egui::ComboBox::from_label("x-ray")
+                    .selected_text(xray.mode.name())
+                    .show_ui(ui, |ui| {
+                        for &mode in XRayMode::ALL.iter() {
+                            ui.selectable_value(&mut xray.mode, mode, mode.name());
+                        }
+                    });
+                if xray.mode != XRayMode::Off {
+                    ui.color_edit_button_rgb(&mut xray.color);
+                    ui.add(egui::Slider::new(&mut xray.opacity, 0.05..=1.0).text("opacity"));
+                }
+            });
+            ui.horizontal(|ui| {
+                use crate::wireframe::WireframeMode;
+
+                let wireframe = &mut scene.wireframe;
+                egui::ComboBox::from_label("wireframe")
+                    .selected_text(wireframe.mode.name())
+                    .show_ui(ui, |ui| {
+                        for &mode in WireframeMode::ALL.iter() {
+                            ui.selectable_value(&mut wireframe.mode, mode, mode.name());
+                        }
+                    });
+                if wireframe.mode != WireframeMode::Off {
+                    ui.color_edit_button_rgb(&mut wireframe.color);
+                }
+            });
+            ui.checkbox(&mut scene.reduced_power, "Reduced power when idle");
+            ui.horizontal(|ui| {
+                let impostors = &mut scene.impostors;
+                ui.checkbox(&mut impostors.enabled, "Impostors")
+                    .on_hover_text("Draws far models as pictures of them, baked from 8 sides");
+                ui.add_enabled(
+                    impostors.enabled,
+                    egui::DragValue::new(&mut impostors.distance)
+                        .clamp_range(0.0..=f32::MAX)
+                        .prefix("beyond "),
+                );
+                ui.add_enabled(
+                    impostors.enabled,
+                    egui::DragValue::new(&mut impostors.resolution)
+                        .clamp_range(16..=1024)
+                        .suffix(" px"),
+                );
+            });
+
+            let config = &mut scene.renderer.config;
+            ui.horizontal(|ui| {
+                ui.color_edit_button_rgba_unmultiplied(&mut config.clear_color);
+                ui.label("background");
+            });
+            ui.horizontal(|ui| {
+                let mut capped = config.frame_cap.is_some();
+                if ui.checkbox(&mut capped, "Frame cap").changed() {
+                    config.frame_cap = if capped { Some(60) } else { None };
+                }
+                if let Some(fps) = &mut config.frame_cap {
+                    ui.add(egui::Slider::new(fps, 10..=240).suffix(" fps"));
+                }
+            });
+            ui.checkbox(&mut config.cull_back_faces, "Cull back faces")
+                .on_hover_text("Applies to models loaded from now on");
+            ui.label(format!("Depth buffer: {}", config.depth_format.name()));
+        });
+    }
+
+    fn compare_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::display::DisplayMode;
+
+        egui::CollapsingHeader::new("Compare").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let materials = scene.materials.read().unwrap();
+            let names = materials.iter().map(|(name, _)| name.to_string()).collect::<Vec<_>>();
+            drop(materials);
+            let compare = &mut scene.compare;
+            ui.checkbox(&mut compare.enabled, "Split view");
+            ui.label("Left of the divider as the display says, right of it as set here.");
+            ui.add(egui::Slider::new(&mut compare.split, 0.0..=1.0).text("divider"));
+            egui::ComboBox::from_label("right display")
+                .selected_text(compare.mode.name())
+                .show_ui(ui, |ui| {
+                    for &mode in DisplayMode::ALL.iter() {
+                        ui.selectable_value(&mut compare.mode, mode, mode.name());
+                    }
+                });
+            let mut swapping = compare.swap.is_some();
+            if ui.checkbox(&mut swapping, "Swap a material on the right").changed() {
+                compare.swap = match names.first() {
+                    Some(name) if swapping => Some((name.clone(), name.clone())),
+                    _ => None,
+                };
+            }
+            if let Some((from, to)) = &mut compare.swap {
+                for (label, name) in [("material", from), ("drawn as", to)] {
+                    egui::ComboBox::from_label(label)
+                        .selected_text(name.as_str())
+                        .show_ui(ui, |ui| {
+                            for option in names.iter() {
+                                ui.selectable_value(name, option.clone(), option);
+                            }
+                        });
+                }
+            }
+        });
+    }
+
+    fn lights_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::light::LightKind;
+
+        egui::CollapsingHeader::new("Lights").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            if !scene.lights.clusters.culls() {
+                ui.label("Only the first light shades on this device.");
+            }
+            let mut edited = false;
+            let mut removed = None;
+            for (i, object) in scene.lights.lights.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    if i == 0 {
+                        ui.label("Light 1, casting shadows");
+                        ui.checkbox(&mut object.orbit, "Orbit");
+                    } else {
+                        ui.label(format!("Light {}", i + 1));
+                        if ui.button("Remove").clicked() {
+                            removed = Some(i);
+                        }
+                    }
+                });
+                let light = &mut object.light;
+                let kind = light.kind;
+                egui::ComboBox::from_id_source(("light kind", i))
+                    .selected_text(kind.name())
+                    .show_ui(ui, |ui| {
+                        for &kind in LightKind::ALL.iter() {
+                            ui.selectable_value(&mut light.kind, kind, kind.name());
+                        }
+                    });
+                edited |= light.kind != kind;
+                ui.horizontal(|ui| {
+                    let position = &mut light.position;
+                    for value in [&mut position.x, &mut position.y, &mut position.z] {
+                        edited |= ui.add(egui::DragValue::new(value).speed(0.1)).changed();
+                    }
+                    ui.label("position");
+                });
+                if light.kind != LightKind::Point {
+                    ui.horizontal(|ui| {
+                        let target = &mut light.target;
+                        for value in [&mut target.x, &mut target.y, &mut target.z] {
+                            edited |= ui.add(egui::DragValue::new(value).speed(0.1)).changed();
+                        }
+                        ui.label("aimed at");
+                    });
+                }
+                ui.horizontal(|ui| {
+                    let mut color: [f32; 3] = light.color.into();
+                    if ui.color_edit_button_rgb(&mut color).changed() {
+                        light.color = color.into();
+                        edited = true;
+                    }
+                    let intensity = egui::Slider::new(&mut light.intensity, 0.0..=10.0)
+                        .text("intensity");
+                    edited |= ui.add(intensity).changed();
+                });
+                if light.kind != LightKind::Directional {
+                    let range = egui::Slider::new(&mut light.range, 0.1..=1000.0)
+                        .logarithmic(true)
+                        .text("range");
+                    edited |= ui.add(range).changed();
+                }
+                if light.kind == LightKind::Spot {
+                    let cones = [
+                        (&mut light.outer_cone, "outer cone"),
+                        (&mut light.inner_cone, "inner cone"),
+                    ];
+                    for (cone, text) in cones {
+                        let mut degrees = cgmath::Deg::from(*cone).0;
+                        let slider = egui::Slider::new(&mut degrees, 0.0..=85.0)
+                            .suffix("°")
+                            .text(text);
+                        if ui.add(slider).changed() {
+                            *cone = cgmath::Deg(degrees).into();
+                            edited = true;
+                        }
+                    }
+                }
+            }
+            if let Some(i) = removed {
+                scene.remove_light(i);
+            }
+            if edited {
+                scene.lights_edited();
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Add at the 3D cursor").clicked() {
+                    self.proxy.send_event(Event::AddLight).ok();
+                }
+                ui.checkbox(&mut scene.gizmos.show_lights, "Gizmos");
+            });
+            ui.separator();
+            let environment = &mut scene.lights.environment;
+            if !environment.bakes() {
+                ui.label("Environment maps can't light on this device.");
+                return;
+            }
+            let (proxy, source) = (&self.proxy, &mut self.environment_source);
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(source);
+                let load = egui::Button::new("Load environment");
+                if ui.add_enabled(!environment.is_loading(), load).clicked() {
+                    proxy.send_event(Event::LoadEnvironment(source.clone())).ok();
+                }
+            });
+            match &environment.source {
+                Some(source) => ui.label(format!("Environment: {}", source)),
+                None => ui.label("No environment, a tenth of the first light is ambient."),
+            };
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut environment.enabled, "Environment lighting");
+                ui.add(
+                    egui::Slider::new(&mut environment.intensity, 0.0..=4.0).text("intensity"),
+                );
+            });
+        });
+    }
+
+    fn shadows_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Shadows").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            // Only the first light casts shadows.
+            for (i, object) in scene.lights.lights.iter_mut().enumerate().take(1) {
+                let settings = &mut object.shadow;
+                ui.label(format!("Light {}", i + 1));
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source(("shadow map", i))
+                        .selected_text(settings.size.to_string())
+                        .show_ui(ui, |ui| {
+                            for &size in [512, 1024, 2048, 4096, 8192].iter() {
+                                ui.selectable_value(&mut settings.size, size, size.to_string());
+                            }
+                        });
+                    ui.label("map size");
+                });
+                ui.add(
+                    egui::Slider::new(&mut settings.bias, 0.0..=0.01)
+                        .logarithmic(true)
+                        .text("depth bias"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut settings.normal_bias, 0.0..=4.0)
+                        .suffix(" texels")
+                        .text("normal bias"),
+                );
+                let side = settings.pcf_radius * 2 + 1;
+                ui.add(
+                    egui::Slider::new(&mut settings.pcf_radius, 0..=3)
+                        .text(format!("filter radius ({0}x{0} PCF)", side)),
+                );
+                ui.checkbox(&mut settings.stabilize, "Stable")
+                    .on_hover_text("Snaps the map to whole texels so shadows don't shimmer");
+            }
+            let shadows = &mut scene.renderer.shadows;
+            ui.checkbox(&mut shadows.debug_view, "Show shadow map");
+            if let Some(texture) = shadows.debug_texture_id.filter(|_| shadows.debug_view) {
+                ui.image(texture, [256., 256.]);
+            }
+        });
+    }
+
+    fn ground_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Ground").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let (proxy, ground_path) = (&self.proxy, &mut self.ground_path);
+            ui.label("Captures the scene from above to darken the ground under and around it.");
+            let ground = &mut scene.ground;
+            ui.add(egui::Slider::new(&mut ground.resolution, 16..=1024).text("resolution"));
+            ui.add(
+                egui::Slider::new(&mut ground.radius, 0.1..=50.0)
+                    .logarithmic(true)
+                    .text("radius"),
+            );
+            ui.add(egui::Slider::new(&mut ground.strength, 0.0..=1.0).text("strength"));
+            ui.add(egui::Slider::new(&mut ground.margin, 0.0..=50.0).text("margin"));
+            if ui.button("Add ground plane").clicked() {
+                proxy.send_event(Event::AddGround).ok();
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            ui.horizontal(|ui| {
+                ui.label("Save maps to");
+                ui.text_edit_singleline(ground_path);
+                if ui.button("Save").clicked() {
+                    let saved = scene
+                        .capture_ground()
+                        .context("There is nothing to capture")
+                        .and_then(|capture| capture.save(ground_path.as_str(), "ground"));
+                    if let Err(e) = saved {
+                        log::warn!("Saving the ground maps failed: {:?}", e);
+                    }
+                }
+            });
+        });
+    }
+
+    fn camera_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::{camera_rig::CameraScheme, lens::Lens};
+
+        egui::CollapsingHeader::new("Camera").show(ui, |ui| {
+            let (proxy, camera_scheme) = (&self.proxy, &mut self.camera_scheme);
+            egui::ComboBox::from_label("controls")
+                .selected_text(camera_scheme.name())
+                .show_ui(ui, |ui| {
+                    for &scheme in CameraScheme::ALL.iter() {
+                        if ui.selectable_value(camera_scheme, scheme, scheme.name()).clicked() {
+                            proxy.send_event(Event::SetCameraScheme(scheme)).ok();
+                        }
+                    }
+                });
+            let mut scene = self.scene.write().unwrap();
+            ui.checkbox(&mut scene.camera.orthographic, "Orthographic (numpad 5)");
+            let lens = &mut scene.lens;
+            ui.checkbox(&mut lens.enabled, "Physical lens");
+            ui.add_enabled_ui(lens.enabled, |ui| {
+                ui.add(
+                    egui::Slider::new(&mut lens.focal_length, 8.0..=300.0)
+                        .logarithmic(true)
+                        .suffix(" mm")
+                        .text("focal length"),
+                );
+                let sensor = Lens::SENSORS
+                    .iter()
+                    .find(|(_, w, h)| (*w, *h) == (lens.sensor_width, lens.sensor_height))
+                    .map_or("Custom", |(name, ..)| name);
+                egui::ComboBox::from_label("sensor")
+                    .selected_text(sensor)
+                    .show_ui(ui, |ui| {
+                        for &(name, width, height) in Lens::SENSORS.iter() {
+                            let selected = sensor == name;
+                            if ui.selectable_label(selected, name).clicked() {
+                                lens.sensor_width = width;
+                                lens.sensor_height = height;
+                            }
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    let range = 1.0..=100.0;
+                    ui.add(egui::DragValue::new(&mut lens.sensor_width).clamp_range(range.clone()));
+                    ui.label("x");
+                    ui.add(egui::DragValue::new(&mut lens.sensor_height).clamp_range(range));
+                    ui.label("mm");
+                });
+                ui.label(format!(
+                    "{:.1}° horizontal field of view",
+                    cgmath::Deg::from(lens.horizontal_fov()).0
+                ));
+            });
+            let guides = &mut scene.guides;
+            ui.checkbox(&mut guides.film_gate, "Film gate");
+            ui.checkbox(&mut guides.safe_areas, "Safe areas");
+            ui.checkbox(&mut guides.thirds, "Rule of thirds");
+            ui.checkbox(&mut guides.center, "Center");
+        });
+    }
+
+    fn sun_ui(&mut self, ui: &mut egui::Ui) {
+        use chrono::Datelike;
+
+        egui::CollapsingHeader::new("Sun study").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let sun = &mut scene.sun;
+            ui.checkbox(&mut sun.enabled, "Light follows the sun");
+            ui.add_enabled_ui(sun.enabled, |ui| {
+                ui.add(egui::Slider::new(&mut sun.latitude, -90.0..=90.0).text("latitude"));
+                ui.add(egui::Slider::new(&mut sun.longitude, -180.0..=180.0).text("longitude"));
+                ui.add(egui::Slider::new(&mut sun.utc_offset, -12.0..=14.0).text("UTC offset"));
+                ui.add(egui::Slider::new(&mut sun.north, -180.0..=180.0).text("north"));
+                let mut day = sun.date.ordinal();
+                let days = chrono::NaiveDate::from_ymd(sun.date.year(), 12, 31).ordinal();
+                let date = sun.date.format("%b %-d").to_string();
+                if ui.add(egui::Slider::new(&mut day, 1..=days).text(date)).changed() {
+                    sun.date = sun.date.with_ordinal(day).unwrap_or(sun.date);
+                }
+                ui.horizontal(|ui| {
+                    let label = if sun.playing { "Pause" } else { "Play" };
+                    if ui.button(label).clicked() {
+                        sun.playing = !sun.playing;
+                    }
+                    let (hours, minutes) = (sun.time as u32, (sun.time.fract() * 60.) as u32);
+                    let clock = format!("{:02}:{:02}", hours, minutes);
+                    ui.add(egui::Slider::new(&mut sun.time, 0.0..=24.0).text(clock));
+                });
+                ui.add(
+                    egui::Slider::new(&mut sun.speed, 0.1..=12.0)
+                        .logarithmic(true)
+                        .text("hours per second"),
+                );
+                let elevation = sun.elevation().0;
+                if elevation < 0. {
+                    ui.label("The sun is below the horizon");
+                } else {
+                    ui.label(format!("Sun {:.1}° above the horizon", elevation));
+                }
+            });
+        });
+    }
+
+    fn selection_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::selection::{GizmoMode, PivotMode};
+
+        egui::CollapsingHeader::new("Selection").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            ui.label(format!(
+                "{} selected. Click models, lights and cameras or drag a box around them, shift \
+                 adds to the selection.",
+                scene.outliner.selected.len() + scene.gizmos.selected.len()
+            ));
+            let selection = &mut scene.selection;
+            egui::ComboBox::from_label("pivot")
+                .selected_text(selection.pivot.name())
+                .show_ui(ui, |ui| {
+                    for &mode in PivotMode::ALL.iter() {
+                        ui.selectable_value(&mut selection.pivot, mode, mode.name());
+                    }
+                });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut selection.gizmo, "Gizmo");
+                ui.add_enabled_ui(selection.gizmo, |ui| {
+                    for &mode in GizmoMode::ALL.iter() {
+                        ui.selectable_value(&mut selection.gizmo_mode, mode, mode.name());
+                    }
+                });
+            });
+            let has_selection = scene.has_selection();
+            ui.horizontal(|ui| {
+                ui.label("Origin to");
+                if ui.add_enabled(has_selection, egui::Button::new("Bounds center")).clicked() {
+                    scene.center_selected_origins();
+                }
+                let picking = &mut scene.selection.picking_origin;
+                ui.add_enabled(has_selection, egui::SelectableLabel::new(*picking, "Picked point"))
+                    .clicked()
+                    .then(|| *picking = !*picking);
+                if ui.add_enabled(has_selection, egui::Button::new("3D cursor")).clicked() {
+                    let cursor = scene.cursor.position;
+                    scene.set_selected_origins(cursor);
+                }
+            });
+            let selection = &mut scene.selection;
+            let transform = &mut selection.transform;
+            ui.horizontal(|ui| {
+                ui.label("Translation");
+                for t in transform.translation.iter_mut() {
+                    ui.add(egui::DragValue::new(t).speed(0.1));
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Rotation");
+                for r in transform.rotation.iter_mut() {
+                    ui.add(egui::DragValue::new(r).suffix("°"));
+                }
+            });
+            ui.add(
+                egui::Slider::new(&mut transform.scale, 0.01..=100.0)
+                    .logarithmic(true)
+                    .text("scale"),
+            );
+            if ui
+                .add_enabled(has_selection, egui::Button::new("Apply to selected"))
+                .clicked()
+            {
+                let transform = std::mem::take(&mut scene.selection.transform);
+                scene.transform_selected(&transform);
+            }
+        });
+    }
+
+    fn cursor_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::{cursor::WorkPlane, primitive::Primitive};
+
+        egui::CollapsingHeader::new("3D cursor").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            ui.label("Shift and right click puts it on a surface, or else on the work plane.");
+            let cursor = &mut scene.cursor;
+            ui.horizontal(|ui| {
+                ui.label("Position");
+                for axis in 0..3 {
+                    ui.add(egui::DragValue::new(&mut cursor.position[axis]).speed(0.1));
+                }
+            });
+            egui::ComboBox::from_label("work plane")
+                .selected_text(cursor.plane.name())
+                .show_ui(ui, |ui| {
+                    for &plane in WorkPlane::ALL.iter() {
+                        ui.selectable_value(&mut cursor.plane, plane, plane.name());
+                    }
+                });
+            ui.add(egui::DragValue::new(&mut cursor.plane_offset).speed(0.1).prefix("offset: "));
+            let has_selection = scene.has_selection();
+            ui.horizontal(|ui| {
+                let to_selected = egui::Button::new("Cursor to selected");
+                if ui.add_enabled(has_selection, to_selected).clicked() {
+                    scene.cursor_to_selected();
+                }
+                let to_cursor = egui::Button::new("Selection to cursor");
+                if ui.add_enabled(has_selection, to_cursor).clicked() {
+                    scene.selection_to_cursor();
+                }
+                if ui.button("Reset").clicked() {
+                    scene.cursor.position = cgmath::Point3::new(0., 0., 0.);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Add");
+                for &primitive in Primitive::ALL.iter() {
+                    if ui.button(primitive.name()).clicked() {
+                        self.proxy.send_event(Event::AddPrimitive(primitive)).ok();
+                    }
+                }
+            });
+            ui.add(
+                egui::Slider::new(&mut scene.cursor.primitive_size, 0.01..=100.0)
+                    .logarithmic(true)
+                    .text("size"),
+            );
+        });
+    }
+
+    fn construction_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::construction::PlanePick;
+
+        egui::CollapsingHeader::new("Construction planes").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let construction = &mut scene.construction;
+            ui.label("The active plane takes the 3D cursor, new primitives and knife points.");
+            let mut removed = None;
+            let active = &mut construction.active;
+            for (i, plane) in construction.planes.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.radio_value(active, Some(i), "");
+                    ui.checkbox(&mut plane.visible, "");
+                    ui.text_edit_singleline(&mut plane.name);
+                    if ui.button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = removed {
+                construction.remove(i);
+            }
+            ui.radio_value(&mut construction.active, None, "None");
+            ui.horizontal(|ui| {
+                match &construction.picking {
+                    Some(PlanePick::Points(points)) => {
+                        ui.label(format!("Click point {} of 3", points.len() + 1));
+                    }
+                    Some(PlanePick::Face) => {
+                        ui.label("Click a face");
+                    }
+                    None => {}
+                }
+                if construction.picking.is_some() {
+                    if ui.button("Cancel").clicked() {
+                        construction.picking = None;
+                    }
+                } else {
+                    if ui.button("From 3 points").clicked() {
+                        construction.picking = Some(PlanePick::Points(Vec::new()));
+                    }
+                    if ui.button("From face").clicked() {
+                        construction.picking = Some(PlanePick::Face);
+                    }
+                }
+            });
+            ui.add(
+                egui::Slider::new(&mut construction.spacing, 0.01..=10.0)
+                    .logarithmic(true)
+                    .text("grid spacing"),
+            );
+            ui.add(egui::Slider::new(&mut construction.extent, 1..=50).text("grid lines"));
+            ui.checkbox(&mut construction.snap, "Snap to the grid");
+        });
+    }
+
+    fn cameras_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Cameras").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let (proxy, render_path) = (&self.proxy, &mut self.render_path);
+            ui.label("Navigating while looking through a camera moves it.");
+            let looking_through = scene.cameras.looking_through;
+            let mut removed = None;
+            for (i, camera) in scene.cameras.cameras.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    let through = looking_through == Some(i);
+                    if ui.radio(through, "").clicked() && !through {
+                        proxy.send_event(Event::LookThrough(Some(i))).ok();
+                    }
+                    ui.text_edit_singleline(&mut camera.name);
+                    ui.add(
+                        egui::DragValue::new(&mut camera.view.fovy)
+                            .clamp_range(1.0..=170.0)
+                            .suffix("°"),
+                    );
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if ui.button("Render").clicked() {
+                        let path = render_path.clone().into();
+                        proxy.send_event(Event::RenderCamera { camera: i, path }).ok();
+                    }
+                    if ui.button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = removed {
+                scene.remove_camera(i);
+            }
+            if ui.radio(looking_through.is_none(), "Viewport").clicked()
+                && looking_through.is_some()
+            {
+                proxy.send_event(Event::LookThrough(None)).ok();
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Add from the view").clicked() {
+                    scene.add_camera();
+                }
+                ui.add(
+                    egui::DragValue::new(&mut scene.cameras.gizmo_size)
+                        .clamp_range(0.01..=100.0)
+                        .speed(0.05)
+                        .prefix("gizmo size: "),
+                );
+            });
+            #[cfg(not(target_arch = "wasm32"))]
+            ui.horizontal(|ui| {
+                ui.label("Render to");
+                ui.text_edit_singleline(render_path);
+            });
+        });
+    }
+
+    fn review_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Review").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let (proxy, draft, review_path) = (&self.proxy, &mut self.note, &mut self.review_path);
+            ui.label("Notes are pinned at the 3D cursor, about the selected models.");
+            ui.text_edit_multiline(draft);
+            if ui.button("Add note at the 3D cursor").clicked() && !draft.is_empty() {
+                scene.add_note(std::mem::take(draft));
+            }
+            let mut removed = None;
+            for (i, note) in scene.review.notes.iter_mut().enumerate() {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}.", i + 1));
+                    ui.text_edit_singleline(&mut note.text);
+                });
+                ui.horizontal(|ui| {
+                    let models = note.models.iter().map(|object| object.name.as_str());
+                    ui.label(models.collect::<Vec<_>>().join(", "));
+                    if ui.button("Go to").clicked() {
+                        let (eye, target) = (note.view.eye.into(), note.view.target.into());
+                        proxy.send_event(Event::SetCamera { eye, target }).ok();
+                    }
+                    if ui.button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = removed {
+                scene.review.notes.remove(i);
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            ui.horizontal(|ui| {
+                ui.label("Export to");
+                ui.text_edit_singleline(review_path);
+                let notes = !scene.review.notes.is_empty();
+                if ui.add_enabled(notes, egui::Button::new("Export")).clicked() {
+                    let path = review_path.clone().into();
+                    proxy.send_event(Event::ExportReview(path)).ok();
+                }
+            });
+        });
+    }
+
+    fn graph_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::constraints::SurfaceAnchor;
+
+        egui::CollapsingHeader::new("Scene graph").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            ui.label("Moving a node moves its meshes and the nodes below it.");
+            let surface = scene.cursor_surface().map(|hit| SurfaceAnchor::from_hit(&hit));
+            for root in scene.graph.roots() {
+                node_ui(ui, &mut scene.graph, root, surface);
+            }
+        });
+    }
+
+    fn poses_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Poses").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let settings = &mut self.poses;
+            let model_name = |scene: &Scene, model: usize| {
+                let root = scene.graph.model_nodes(model).first().copied();
+                root.map_or_else(|| format!("model {}", model), |root| {
+                    scene.graph.nodes[root].name.clone()
+                })
+            };
+
+            let clips = scene
+                .poses
+                .clips
+                .iter()
+                .map(|clip| (clip.model, clip.name.clone(), clip.duration()))
+                .collect::<Vec<_>>();
+            if clips.is_empty() {
+                ui.label("No animations loaded. Poses can still be stored from the scene graph.");
+            } else {
+                settings.clip = settings.clip.min(clips.len() - 1);
+                let (model, _, duration) = clips[settings.clip];
+                let clip_name = |(model, name, _): &(usize, String, f32)| {
+                    format!("{}: {}", model_name(&scene, *model), name)
+                };
+                egui::ComboBox::from_label("animation")
+                    .selected_text(clip_name(&clips[settings.clip]))
+                    .show_ui(ui, |ui| {
+                        for (i, clip) in clips.iter().enumerate() {
+                            ui.selectable_value(&mut settings.clip, i, clip_name(clip));
+                        }
+                    });
+                let slider = egui::Slider::new(&mut settings.time, 0.0..=duration).suffix(" s");
+                if ui.add(slider).changed() {
+                    scene.scrub(settings.clip, settings.time);
+                }
+                ui.horizontal(|ui| {
+                    if settings.name.is_empty() {
+                        settings.name = scene.poses.next_name();
+                    }
+                    ui.text_edit_singleline(&mut settings.name);
+                    if ui.button("Store pose").clicked() {
+                        scene.store_pose(model, std::mem::take(&mut settings.name));
+                    }
+                });
+            }
+            ui.separator();
+
+            let mut applied = None;
+            let mut removed = None;
+            let poses = (0..scene.poses.poses.len())
+                .map(|i| {
+                    let pose = &scene.poses.poses[i];
+                    (pose.model, format!("{} ({})", pose.name, model_name(&scene, pose.model)))
+                })
+                .collect::<Vec<_>>();
+            for (i, pose) in scene.poses.poses.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut pose.name);
+                    if ui.button("Apply").clicked() {
+                        applied = Some(i);
+                    }
+                    if ui.button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = applied {
+                scene.apply_pose(i);
+            }
+            if let Some(i) = removed {
+                scene.poses.poses.remove(i);
+            }
+            if poses.is_empty() {
+                return;
+            }
+
+            ui.label("Blend between two poses of a model.");
+            let (from, to, blend) = &mut settings.blend;
+            *from = (*from).min(poses.len() - 1);
+            let model = poses[*from].0;
+            if poses.get(*to).map(|(m, _)| *m) != Some(model) {
+                *to = *from;
+            }
+            let mut changed = false;
+            for (label, chosen) in [("from", &mut *from), ("to", &mut *to)] {
+                egui::ComboBox::from_label(label)
+                    .selected_text(&poses[*chosen].1)
+                    .show_ui(ui, |ui| {
+                        for (i, (_, name)) in poses.iter().enumerate() {
+                            if label == "from" || poses[i].0 == model {
+                                changed |= ui.selectable_value(chosen, i, name).changed();
+                            }
+                        }
+                    });
+            }
+            changed |= ui
+                .add(egui::Slider::new(blend, 0.0..=1.0).text("blend"))
+                .changed();
+            if changed {
+                scene.blend_poses(*from, *to, *blend);
+            }
+        });
+    }
+
+    fn section_ui(&mut self, ui: &mut egui::Ui) {
+        use cgmath::InnerSpace;
+
+        egui::CollapsingHeader::new("Section box").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let bounds = scene.visible_bounds();
+            let section = &mut scene.section;
+            if ui.checkbox(&mut section.enabled, "Clip to the section box").changed()
+                && section.enabled
+            {
+                section.fit(&bounds);
+            }
+            ui.add_enabled_ui(section.enabled, |ui| {
+                if ui.button("Fit to visible models").clicked() {
+                    section.fit(&bounds);
+                }
+                for (axis, name) in ["x", "y", "z"].iter().enumerate() {
+                    let (min, max) = (section.bounds.min[axis], section.bounds.max[axis]);
+                    let speed = (section.bounds.size().magnitude() * 0.005).max(1e-3);
+                    ui.horizontal(|ui| {
+                        ui.label(*name);
+                        ui.add(
+                            egui::DragValue::new(&mut section.bounds.min[axis])
+                                .clamp_range(f32::MIN..=max)
+                                .speed(speed),
+                        );
+                        ui.add(
+                            egui::DragValue::new(&mut section.bounds.max[axis])
+                                .clamp_range(min..=f32::MAX)
+                                .speed(speed),
+                        );
+                    });
+                }
+            });
+        });
+    }
+
+    /// The film gate and composition guides over the view, below the windows.
+    fn guides_overlay(&mut self, ctx: &egui::CtxRef) {
+        use crate::lens::Guides;
+        use egui::{pos2, vec2, Color32, Rect, Stroke};
+
+        let scene = self.scene.read().unwrap();
+        let guides = &scene.guides;
+        if !(guides.film_gate || guides.safe_areas || guides.thirds || guides.center) {
+            return;
+        }
+        let screen = ctx.input().screen_rect();
+        let [x, y, width, height] = scene.lens.gate_rect(screen.width(), screen.height());
+        let gate = Rect::from_min_size(screen.min + vec2(x, y), vec2(width, height));
+        let painter = ctx.layer_painter(egui::LayerId::new(
+            egui::Order::Background,
+            egui::Id::new("guides"),
+        ));
+        let stroke = Stroke::new(1., Color32::from_white_alpha(128));
+
+        if guides.film_gate {
+            let mask = Color32::from_black_alpha(160);
+            let bars = [
+                Rect::from_min_max(screen.min, pos2(gate.min.x, screen.max.y)),
+                Rect::from_min_max(pos2(gate.max.x, screen.min.y), screen.max),
+                Rect::from_min_max(pos2(gate.min.x, screen.min.y), pos2(gate.max.x, gate.min.y)),
+                Rect::from_min_max(pos2(gate.min.x, gate.max.y), pos2(gate.max.x, screen.max.y)),
+            ];
+            for bar in bars.iter().filter(|bar| bar.is_positive()) {
+                painter.rect_filled(*bar, 0., mask);
+            }
+            painter.rect_stroke(gate, 0., stroke);
+        }
+        if guides.safe_areas {
+            for &scale in [Guides::ACTION_SAFE, Guides::TITLE_SAFE].iter() {
+                let area = Rect::from_center_size(gate.center(), gate.size() * scale);
+                painter.rect_stroke(area, 0., Stroke::new(1., Color32::from_white_alpha(64)));
+            }
+        }
+        if guides.thirds {
+            for &t in [1. / 3., 2. / 3.].iter() {
+                let x = gate.min.x + gate.width() * t;
+                let y = gate.min.y + gate.height() * t;
+                painter.line_segment([pos2(x, gate.min.y), pos2(x, gate.max.y)], stroke);
+                painter.line_segment([pos2(gate.min.x, y), pos2(gate.max.x, y)], stroke);
+            }
         }
+        if guides.center {
+            let center = gate.center();
+            let arm = gate.width().min(gate.height()) * 0.03;
+            painter.line_segment([center - vec2(arm, 0.), center + vec2(arm, 0.)], stroke);
+            painter.line_segment([center - vec2(0., arm), center + vec2(0., arm)], stroke);
+        }
+    }
+
+    /// The divider of the comparison view with a handle dragging it, below the windows.
+    fn compare_overlay(&mut self, ctx: &egui::CtxRef) {
+        use egui::{pos2, vec2, Align2, Color32, Sense, Stroke, TextStyle};
+
+        let mut scene = self.scene.write().unwrap();
+        if !scene.compare.enabled {
+            return;
+        }
+        let screen = ctx.input().screen_rect();
+        let x = screen.min.x + screen.width() * scene.compare.split;
+        let painter = ctx.layer_painter(egui::LayerId::new(
+            egui::Order::Background,
+            egui::Id::new("compare"),
+        ));
+        let color = Color32::from_white_alpha(200);
+        let divider = [pos2(x, screen.min.y), pos2(x, screen.max.y)];
+        painter.line_segment(divider, Stroke::new(2., color));
+        let top = screen.min.y + 8.;
+        painter.text(pos2(x - 8., top), Align2::RIGHT_TOP, "A", TextStyle::Heading, color);
+        painter.text(pos2(x + 8., top), Align2::LEFT_TOP, "B", TextStyle::Heading, color);
+        egui::Area::new("compare_handle")
+            .fixed_pos(pos2(x - 6., screen.center().y - 20.))
+            .show(ctx, |ui| {
+                let (rect, response) = ui.allocate_exact_size(vec2(12., 40.), Sense::drag());
+                ui.painter().rect_filled(rect, 4., color);
+                if response.dragged() {
+                    let dragged = x + response.drag_delta().x - screen.min.x;
+                    scene.compare.split = (dragged / screen.width()).max(0.).min(1.);
+                }
+            });
+    }
+
+    fn outliner_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::{compression::Compression, project::Project};
+
+        #[cfg(all(feature = "file-dialog", not(target_arch = "wasm32")))]
+        let mut open_clicked = false;
+        egui::CollapsingHeader::new("Outliner").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let scene = &mut *scene;
+            let project_path = &mut self.project_path;
+            #[cfg(not(target_arch = "wasm32"))]
+            let bundle_path = &mut self.bundle_path;
+            let model_source = &mut self.model_source;
+            let (copied, proxy) = (&mut self.copied, &self.proxy);
+            let stats = scene.stats();
+            ui.label(format!(
+                "{} triangles, {} vertices, {} draw calls",
+                short_count(stats.triangles),
+                short_count(stats.vertices),
+                stats.draw_calls
+            ));
+            for (what, count, limit) in scene.budget.exceeded(&stats) {
+                let (count, limit) = (short_count(count), short_count(limit));
+                let text = format!("{} {} is over the budget of {}", count, what, limit);
+                ui.colored_label(egui::Color32::RED, text);
+            }
+            ui.collapsing("Budget", |ui| {
+                let budget = &mut scene.budget;
+                ui.checkbox(&mut budget.enabled, "Warn when over budget");
+                for (value, what) in [
+                    (&mut budget.triangles, "triangles"),
+                    (&mut budget.vertices, "vertices"),
+                ]
+                .iter_mut()
+                {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(*value).speed(1000).clamp_range(0..=u64::MAX));
+                        ui.label(*what);
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut budget.draw_calls).clamp_range(0..=u32::MAX));
+                    ui.label("draw calls");
+                });
+            });
+            ui.separator();
+
+            let filter = &mut scene.outliner.filter;
+            ui.horizontal(|ui| {
+                ui.label("Name");
+                ui.text_edit_singleline(&mut filter.name);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Material");
+                ui.text_edit_singleline(&mut filter.material);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Triangles");
+                ui.add(egui::DragValue::new(&mut filter.min_triangles).speed(10));
+                let mut limited = filter.max_triangles.is_some();
+                ui.checkbox(&mut limited, "to");
+                let mut max = filter.max_triangles.unwrap_or(filter.min_triangles.max(1000));
+                ui.add_enabled(
+                    limited,
+                    egui::DragValue::new(&mut max)
+                        .clamp_range(filter.min_triangles..=u64::MAX)
+                        .speed(10),
+                );
+                filter.max_triangles = Some(max).filter(|_| limited);
+            });
+            let filtering = filter.is_active();
+            let matching = scene.matching_meshes();
+            if filtering {
+                let mut select_matching = false;
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} meshes match", matching.len()));
+                    select_matching = ui.button("Select all matching").clicked();
+                    if ui.button("Clear filter").clicked() {
+                        scene.outliner.filter = Default::default();
+                    }
+                });
+                if select_matching {
+                    scene.select_matching(false);
+                }
+            }
+
+            let model_stats = (0..scene.models.len())
+                .map(|model| scene.model_stats(model))
+                .collect::<Vec<_>>();
+            let models = &scene.models;
+            let outliner = &mut scene.outliner;
+            let mut removed = None;
+            for model in 0..models.len() {
+                // Removed models are left empty.
+                if models[model].meshes().is_empty() {
+                    continue;
+                }
+                let meshes = matching
+                    .iter()
+                    .filter(|(m, _)| *m == model)
+                    .map(|&(_, mesh)| mesh)
+                    .collect::<Vec<_>>();
+                if filtering && meshes.is_empty() {
+                    continue;
+                }
+                ui.horizontal(|ui| {
+                    let mut selected = outliner.selected.contains(&model);
+                    if ui.checkbox(&mut selected, format!("Model {}", model)).changed() {
+                        outliner.toggle_selected(model);
+                    }
+                    let mut layer = outliner.layer_of(model);
+                    egui::ComboBox::from_id_source(("model layer", model))
+                        .selected_text(&outliner.layers[layer].name)
+                        .show_ui(ui, |ui| {
+                            for (i, l) in outliner.layers.iter().enumerate() {
+                                ui.selectable_value(&mut layer, i, &l.name);
+                            }
+                        });
+                    if layer != outliner.layer_of(model) {
+                        outliner.set_layer(model, layer);
+                    }
+                    let mut flags = outliner.flags(model);
+                    let cast = ui.checkbox(&mut flags.cast_shadow, "Casts shadow");
+                    let receive = ui.checkbox(&mut flags.receive_shadow, "Receives shadow");
+                    let lit = ui
+                        .checkbox(&mut flags.lit, "Lit")
+                        .on_hover_text("Off draws the model's colors unshaded, for baked lighting");
+                    if cast.changed() || receive.changed() || lit.changed() {
+                        outliner.set_flags(model, flags);
+                    }
+                    if let Some(group) = outliner.group_of(model) {
+                        ui.label(format!("in {}", outliner.groups[group].name));
+                    }
+                    if ui.button("Remove").clicked() {
+                        removed = Some(model);
+                    }
+                });
+                let stats = &model_stats[model];
+                let header = format!(
+                    "{} triangles, {} vertices in {} meshes",
+                    short_count(stats.triangles),
+                    short_count(stats.vertices),
+                    stats.draw_calls
+                );
+                egui::CollapsingHeader::new(header)
+                    .id_source(("model meshes", model))
+                    .show(ui, |ui| {
+                        for &mesh in meshes.iter() {
+                            let mesh = &models[model].meshes()[mesh];
+                            ui.label(format!(
+                                "{}: {} triangles, {} vertices",
+                                mesh.name,
+                                short_count(mesh.num_elements as u64 / 3),
+                                short_count(mesh.num_vertices as u64)
+                            ));
+                        }
+                    });
+            }
+            if let Some(model) = removed {
+                scene.remove_model(crate::registry::NodeId(model));
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Group selected").clicked() {
+                    let name = format!("Group {}", scene.outliner.groups.len() + 1);
+                    scene.group_selected(name);
+                }
+                if ui.button("Purge unused").clicked() {
+                    scene.purge_unused();
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Copy selected").clicked() {
+                    copy_selected(scene, ui.ctx(), copied);
+                }
+                let paste = egui::Button::new("Paste");
+                if ui.add_enabled(!copied.is_empty(), paste).clicked() {
+                    proxy.send_event(Event::PasteModels(copied.clone())).ok();
+                }
+            });
+
+            let outliner = &mut scene.outliner;
+            let mut ungrouped = None;
+            let mut select = None;
+            for (i, group) in outliner.groups.iter_mut().enumerate() {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut group.name);
+                    ui.checkbox(&mut group.visible, "Visible");
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Select").clicked() {
+                        select = Some(i);
+                    }
+                    if ui.button("Ungroup").clicked() {
+                        ungrouped = Some(i);
+                    }
+                });
+                let transform = &mut group.transform;
+                ui.horizontal(|ui| {
+                    ui.label("Translation");
+                    for t in transform.translation.iter_mut() {
+                        ui.add(egui::DragValue::new(t).speed(0.1));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Rotation");
+                    for r in transform.rotation.iter_mut() {
+                        ui.add(egui::DragValue::new(r).suffix("°"));
+                    }
+                });
+                ui.add(
+                    egui::Slider::new(&mut transform.scale, 0.01..=100.0)
+                        .logarithmic(true)
+                        .text("scale"),
+                );
+            }
+            if let Some(i) = select {
+                outliner.select_group(i, false);
+            }
+            if let Some(i) = ungrouped {
+                outliner.remove_group(i);
+            }
+
+            ui.separator();
+            ui.label("Layers (number keys toggle, with shift solo)");
+            for layer in outliner.layers.iter_mut() {
+                ui.horizontal(|ui| {
+                    ui.label(&layer.name);
+                    ui.checkbox(&mut layer.visible, "Visible");
+                    ui.checkbox(&mut layer.selectable, "Selectable");
+                    ui.checkbox(&mut layer.cast_shadows, "Shadows");
+                });
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(project_path);
+                if ui.button("Save").clicked() {
+                    match Project::from_scene(scene).save(&project_path, scene.compression) {
+                        Ok(()) => log::info!("Saved the project to {}", project_path),
+                        Err(e) => log::warn!("Saving the project failed: {:?}", e),
+                    }
+                }
+                if ui.button("Load").clicked() {
+                    match Project::load(&project_path) {
+                        Ok(project) => scene.load_project(project),
+                        Err(e) => log::warn!("Loading the project failed: {:?}", e),
+                    }
+                }
+            });
+            if Compression::AVAILABLE {
+                egui::ComboBox::from_label("compression")
+                    .selected_text(scene.compression.name())
+                    .show_ui(ui, |ui| {
+                        for &compression in Compression::ALL.iter() {
+                            let name = compression.name();
+                            ui.selectable_value(&mut scene.compression, compression, name);
+                        }
+                    })
+                    .response
+                    .on_hover_text("Of saved projects and the mesh cache, read back either way");
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(bundle_path);
+                if ui.button("Export bundle").clicked() {
+                    match crate::bundle::export(scene, &bundle_path) {
+                        Ok(files) => log::info!("Bundled {} files into {}", files, bundle_path),
+                        Err(e) => log::warn!("Exporting the bundle failed: {:?}", e),
+                    }
+                }
+                if ui.button("Open bundle").clicked() {
+                    if let Err(e) = scene.open_bundle(&bundle_path) {
+                        log::warn!("Opening the bundle failed: {:?}", e);
+                    }
+                }
+            })
+            .response
+            .on_hover_text("The project with its models and their files in one .modpack archive");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(model_source);
+                if ui.button("Add model").clicked() {
+                    scene.start_load(model_source.clone());
+                }
+                #[cfg(all(feature = "file-dialog", not(target_arch = "wasm32")))]
+                if ui.button("Open model...").clicked() {
+                    open_clicked = true;
+                }
+            });
+            ui.checkbox(&mut scene.texture_stream.enabled, "Stream textures")
+                .on_hover_text("Adds models before their textures, which come in afterwards");
+        });
+        #[cfg(all(feature = "file-dialog", not(target_arch = "wasm32")))]
+        if open_clicked {
+            open_model(&self.scene, &self.collection);
+        }
+    }
+
+    fn sculpt_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::sculpt::Brush;
+
+        egui::CollapsingHeader::new("Sculpt").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let sculpt = &mut scene.sculpt;
+            let toggled = ui.checkbox(&mut sculpt.enabled, "Sculpt mode (left drag)").changed();
+            if toggled && !sculpt.enabled {
+                sculpt.release_target();
+            }
+            ui.horizontal(|ui| {
+                for brush in Brush::ALL {
+                    ui.radio_value(&mut sculpt.settings.brush, brush, brush.name());
+                }
+            });
+            ui.add(
+                egui::Slider::new(&mut sculpt.settings.radius, 0.01..=10.0)
+                    .logarithmic(true)
+                    .text("radius"),
+            );
+            ui.add(egui::Slider::new(&mut sculpt.settings.strength, 0.0..=1.0).text("strength"));
+            ui.checkbox(&mut sculpt.settings.invert, "Invert");
+            ui.checkbox(&mut sculpt.settings.taubin, "Keep volume when smoothing");
+            ui.horizontal(|ui| {
+                ui.label("Mirror");
+                ui.checkbox(&mut sculpt.settings.mirror[0], "X");
+                ui.checkbox(&mut sculpt.settings.mirror[1], "Y");
+                ui.checkbox(&mut sculpt.settings.mirror[2], "Z");
+            });
+        });
+    }
+
+    fn edit_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::{
+            edit::{EditOperation, EditTool, Falloff},
+            mesh::selection::SelectMode,
+        };
+
+        egui::CollapsingHeader::new("Edit").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let has_plane = scene.construction.active().is_some();
+            let edit = &mut scene.edit;
+            if ui.checkbox(&mut edit.enabled, "Edit mode (left click)").changed() {
+                edit.release_target();
+            }
+            ui.horizontal_wrapped(|ui| {
+                for tool in EditTool::ALL {
+                    ui.radio_value(&mut edit.tool, tool, tool.name());
+                }
+            });
+            match edit.tool {
+                EditTool::Move => {
+                    ui.label("Drag the selection, scroll meanwhile to resize the radius.");
+                }
+                EditTool::LoopCut => {
+                    ui.label("Drag across an edge to slide the cut, release to apply.");
+                }
+                EditTool::Knife => {
+                    ui.label("Click points along the cut, then cut.");
+                    ui.horizontal(|ui| {
+                        let ready = edit.knife_len() >= 2;
+                        if ui.add_enabled(ready, egui::Button::new("Cut")).clicked() {
+                            edit.request(EditOperation::Knife);
+                        }
+                        if ui.button("Clear").clicked() {
+                            edit.clear_knife();
+                        }
+                    });
+                    let along_plane = egui::Button::new("Cut along the construction plane");
+                    if ui.add_enabled(has_plane, along_plane).clicked() {
+                        edit.request(EditOperation::ConstructionPlaneCut);
+                    }
+                }
+                EditTool::PlaneCut => {
+                    ui.label("Drag a line across the mesh, release to cut.");
+                }
+                _ => {}
+            }
+            if matches!(edit.tool, EditTool::Knife | EditTool::PlaneCut) {
+                ui.checkbox(&mut edit.separate_pieces, "Separate pieces into new models");
+            }
+            ui.horizontal(|ui| {
+                let mut mode = edit.selection().mode();
+                for m in SelectMode::ALL {
+                    ui.radio_value(&mut mode, m, m.name());
+                }
+                edit.set_select_mode(mode);
+            });
+            ui.label(format!(
+                "{} selected, shift adds to the selection",
+                edit.selection().len()
+            ));
+            ui.horizontal(|ui| {
+                if ui.button("Linked").clicked() {
+                    edit.select_linked();
+                }
+                if ui.button("Invert").clicked() {
+                    edit.invert_selection();
+                }
+                if ui.button("Grow").clicked() {
+                    edit.grow_selection();
+                }
+                if ui.button("Shrink").clicked() {
+                    edit.shrink_selection();
+                }
+                if ui.button("Clear").clicked() {
+                    edit.clear_selection();
+                }
+            });
+            ui.checkbox(&mut edit.proportional.enabled, "Proportional editing");
+            ui.add_enabled(
+                edit.proportional.enabled,
+                egui::Slider::new(&mut edit.proportional.radius, 0.001..=100.0)
+                    .logarithmic(true)
+                    .text("proportional radius"),
+            );
+            ui.add_enabled_ui(edit.proportional.enabled, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    for falloff in Falloff::ALL {
+                        ui.radio_value(&mut edit.proportional.falloff, falloff, falloff.name());
+                    }
+                });
+            });
+            ui.add(egui::Slider::new(&mut edit.bevel.width, 0.001..=0.5).text("bevel width"));
+            ui.add(egui::Slider::new(&mut edit.bevel.segments, 1..=8).text("bevel segments"));
+            let has_selection = !edit.selection().is_empty();
+            if ui.add_enabled(has_selection, egui::Button::new("Bevel")).clicked() {
+                edit.request(EditOperation::Bevel);
+            }
+            let undo = edit.undo_name();
+            let label = format!("Undo {}", undo.unwrap_or_default());
+            if ui.add_enabled(undo.is_some(), egui::Button::new(label)).clicked() {
+                edit.request(EditOperation::Undo);
+            }
+        });
+    }
+
+    fn smoothing_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Smoothing").show(ui, |ui| {
+            let settings = &mut self.smooth;
+            ui.add(egui::Slider::new(&mut settings.iterations, 1..=100).text("iterations"));
+            ui.add(egui::Slider::new(&mut settings.lambda, 0.0..=1.0).text("lambda"));
+            ui.checkbox(&mut settings.taubin, "Taubin (keep volume)");
+            if settings.taubin {
+                ui.add(egui::Slider::new(&mut settings.mu, -1.0..=0.0).text("mu"));
+            }
+            let mut scene = self.scene.write().unwrap();
+            for model in 0..scene.models.len() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Model {}", model));
+                    if ui.button("Smooth").clicked() {
+                        scene.smooth_model(model, settings);
+                    }
+                });
+            }
+        });
+    }
+
+    fn normals_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::normals::NormalOperation;
+
+        egui::CollapsingHeader::new("Normals").show(ui, |ui| {
+            let settings = &mut self.normals;
+            let mut scene = self.scene.write().unwrap();
+            let vertices: usize = scene
+                .normal_tool_vertices()
+                .iter()
+                .map(|(_, _, vertices)| vertices.len())
+                .sum();
+            ui.label(format!(
+                "{} vertices (the edit mode selection, or else the selected models)",
+                vertices
+            ));
+            egui::ComboBox::from_label("operation")
+                .selected_text(settings.operation.name())
+                .show_ui(ui, |ui| {
+                    for &operation in NormalOperation::ALL.iter() {
+                        ui.selectable_value(&mut settings.operation, operation, operation.name());
+                    }
+                });
+            match settings.operation {
+                NormalOperation::Toward | NormalOperation::Away => {
+                    ui.horizontal(|ui| {
+                        ui.label("Target");
+                        for t in settings.target.iter_mut() {
+                            ui.add(egui::DragValue::new(t).speed(0.1));
+                        }
+                        if ui.button("Camera target").clicked() {
+                            settings.target = scene.camera.target.into();
+                        }
+                    });
+                }
+                NormalOperation::CopyFrom => {
+                    let last = scene.models.len().saturating_sub(1);
+                    ui.horizontal(|ui| {
+                        ui.label("From model");
+                        ui.add(egui::DragValue::new(&mut settings.source).clamp_range(0..=last));
+                    });
+                    ui.add(
+                        egui::Slider::new(&mut settings.max_distance, 0.001..=100.0)
+                            .logarithmic(true)
+                            .text("search distance"),
+                    );
+                }
+                NormalOperation::Flatten => {}
+            }
+            ui.add(egui::Slider::new(&mut settings.strength, 0.0..=1.0).text("strength"));
+            if ui.add_enabled(vertices > 0, egui::Button::new("Apply")).clicked() {
+                match scene.edit_normals(settings) {
+                    Ok(count) => log::info!("Changed {} normals", count),
+                    Err(e) => log::warn!("Editing the normals failed: {:?}", e),
+                }
+            }
+        });
+    }
+
+    fn remesh_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Remesh").show(ui, |ui| {
+            let settings = &mut self.remesh;
+            ui.add(
+                egui::Slider::new(&mut settings.voxel_size, 0.001..=1.0)
+                    .logarithmic(true)
+                    .text("voxel size"),
+            );
+            let mut scene = self.scene.write().unwrap();
+            for model in 0..scene.models.len() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Model {}", model));
+                    match scene.remesh_job(model).map(|job| job.progress()) {
+                        Some(progress) => {
+                            ui.add(egui::ProgressBar::new(progress).show_percentage());
+                            if ui.button("Cancel").clicked() {
+                                scene.cancel_remesh(model);
+                            }
+                        }
+                        None => {
+                            if ui.button("Remesh").clicked() {
+                                scene.start_remesh(model, *settings);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    fn cleanup_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Scan cleanup").show(ui, |ui| {
+            let settings = &mut self.cleanup;
+            ui.checkbox(&mut settings.weld, "Weld");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut settings.decimate, "Decimate to");
+                ui.add(
+                    egui::DragValue::new(&mut settings.target_triangles)
+                        .speed(100.)
+                        .clamp_range(1..=10_000_000)
+                        .suffix(" triangles"),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut settings.remesh, "Remesh");
+                ui.add(
+                    egui::Slider::new(&mut settings.remesh_settings.voxel_size, 0.001..=1.0)
+                        .logarithmic(true)
+                        .text("voxel size"),
+                );
+            });
+            ui.checkbox(&mut settings.normals, "Recompute normals");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut settings.occlusion, "Bake AO");
+                ui.add(egui::Slider::new(&mut settings.ao_samples, 1..=64).text("rays"));
+                ui.add(
+                    egui::Slider::new(&mut settings.ao_distance, 0.01..=10.0)
+                        .logarithmic(true)
+                        .text("distance"),
+                );
+            });
+            let mut scene = self.scene.write().unwrap();
+            for model in 0..scene.models.len() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Model {}", model));
+                    let running = scene.cleanup_jobs.iter().any(|job| job.model == model);
+                    let button = egui::Button::new("Clean up");
+                    if ui.add_enabled(!running && !settings.steps().is_empty(), button).clicked() {
+                        scene.start_cleanup(model, *settings);
+                    }
+                });
+            }
+        });
+    }
+
+    fn modifier_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::modifier::{Axis, Deform, Lattice};
+
+        egui::CollapsingHeader::new("Modifiers").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let lattice_point = &mut self.lattice_point;
+            for model in 0..scene.models.len() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Model {}", model));
+                    let added = if ui.button("Bend").clicked() {
+                        Some(Deform::Bend {
+                            axis: Axis::Y,
+                            angle: 0.5,
+                        })
+                    } else if ui.button("Twist").clicked() {
+                        Some(Deform::Twist {
+                            axis: Axis::Y,
+                            angle: 0.5,
+                        })
+                    } else if ui.button("Taper").clicked() {
+                        Some(Deform::Taper {
+                            axis: Axis::Y,
+                            factor: 0.5,
+                        })
+                    } else if ui.button("Lattice").clicked() {
+                        Some(Deform::Lattice(Lattice::new([3, 3, 3])))
+                    } else {
+                        None
+                    };
+                    if let Some(deform) = added {
+                        scene.modifier_stack_mut(model).push(deform);
+                    }
+                });
+            }
+
+            for stack in scene.modifiers.iter_mut() {
+                ui.separator();
+                ui.label(format!("Model {}", stack.model));
+                ui.checkbox(&mut stack.show_lattice, "Show lattice");
+                let mut changed = false;
+                let mut removed = None;
+                let mut raised = None;
+                for (i, modifier) in stack.modifiers.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        let name = modifier.deform.name();
+                        changed |= ui.checkbox(&mut modifier.enabled, name).changed();
+                        if i > 0 && ui.small_button("^").clicked() {
+                            raised = Some(i);
+                        }
+                        if ui.small_button("x").clicked() {
+                            removed = Some(i);
+                        }
+                    });
+                    let mut axis_ui = |ui: &mut egui::Ui, axis: &mut Axis| {
+                        ui.horizontal(|ui| {
+                            for a in Axis::ALL {
+                                changed |= ui.radio_value(axis, a, a.name()).changed();
+                            }
+                        });
+                    };
+                    match &mut modifier.deform {
+                        Deform::Bend { axis, angle } | Deform::Twist { axis, angle } => {
+                            axis_ui(ui, axis);
+                            changed |= ui
+                                .add(egui::Slider::new(angle, -6.3..=6.3).text("angle"))
+                                .changed();
+                        }
+                        Deform::Taper { axis, factor } => {
+                            axis_ui(ui, axis);
+                            changed |= ui
+                                .add(egui::Slider::new(factor, -1.0..=2.0).text("factor"))
+                                .changed();
+                        }
+                        Deform::Lattice(lattice) => {
+                            ui.horizontal(|ui| {
+                                ui.label("point");
+                                for a in 0..3 {
+                                    let max = lattice.resolution[a] - 1;
+                                    lattice_point[a] = lattice_point[a].min(max);
+                                    ui.add(
+                                        egui::DragValue::new(&mut lattice_point[a])
+                                            .clamp_range(0..=max),
+                                    );
+                                }
+                            });
+                            let [i, j, k] = *lattice_point;
+                            let index = lattice.index(i, j, k);
+                            let offset = &mut lattice.offsets[index];
+                            ui.horizontal(|ui| {
+                                ui.label("offset");
+                                for a in 0..3 {
+                                    changed |= ui
+                                        .add(egui::DragValue::new(&mut offset[a]).speed(0.01))
+                                        .changed();
+                                }
+                            });
+                        }
+                    }
+                }
+                if let Some(i) = raised {
+                    stack.modifiers.swap(i - 1, i);
+                    changed = true;
+                }
+                if let Some(i) = removed {
+                    stack.remove(i);
+                }
+                if changed {
+                    stack.mark_dirty();
+                }
+            }
+        });
+    }
+
+    fn shape_keys_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Shape keys").show(ui, |ui| {
+            ui.label("Add shape keys, edit the model, then store its shape as a key.");
+            let mut scene = self.scene.write().unwrap();
+            let scene = &mut *scene;
+            for model in 0..scene.models.len() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Model {}", model));
+                    match scene.shape_keys.iter_mut().find(|keys| keys.model == model) {
+                        Some(keys) => {
+                            if ui.button("Store shape").clicked() {
+                                let name = format!("Key {}", keys.keys.len() + 1);
+                                if let Err(e) = keys.store(name, &scene.models[model]) {
+                                    log::warn!("Storing the shape key failed: {:?}", e);
+                                }
+                            }
+                        }
+                        None => {
+                            if ui.button("Add shape keys").clicked() {
+                                scene.shape_keys_mut(model);
+                            }
+                        }
+                    }
+                });
+            }
+
+            let models = &scene.models;
+            let mut dropped = None;
+            for (i, keys) in scene.shape_keys.iter_mut().enumerate() {
+                ui.separator();
+                ui.label(format!("Model {}", keys.model));
+                let mut changed = ui.checkbox(&mut keys.gpu, "Blend on the GPU").changed();
+                let mut removed = None;
+                for (k, key) in keys.keys.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        changed |= ui
+                            .add(egui::Slider::new(&mut key.weight, 0.0..=1.0).text(&key.name))
+                            .changed();
+                        if ui.small_button("x").clicked() {
+                            removed = Some(k);
+                        }
+                    });
+                }
+                if let Some(k) = removed {
+                    keys.remove(k);
+                }
+                if changed {
+                    keys.mark_dirty();
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Show basis").clicked() {
+                        keys.reset();
+                    }
+                    if ui.button("Export glTF").clicked() {
+                        let path = format!("model_{}_shape_keys.glb", keys.model);
+                        match keys.export_gltf(&models[keys.model], &path) {
+                            Ok(()) => log::info!("Exported shape keys to {}", path),
+                            Err(e) => log::warn!("Exporting shape keys failed: {:?}", e),
+                        }
+                    }
+                    if ui.button("Remove").clicked() {
+                        dropped = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = dropped {
+                scene.shape_keys.remove(i);
+            }
+        });
+    }
+
+    fn cloth_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Cloth").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let running = scene.cloths.iter().any(|cloth| cloth.running);
+            ui.horizontal(|ui| {
+                if ui.button(if running { "Pause" } else { "Play" }).clicked() {
+                    scene.cloths.iter_mut().for_each(|cloth| cloth.running = !running);
+                }
+                if ui.button("Reset").clicked() {
+                    scene.cloths.iter_mut().for_each(|cloth| cloth.reset());
+                }
+            });
+            for i in 0..scene.models.len() {
+                let attached = scene.cloths.iter().any(|cloth| cloth.model == i);
+                ui.horizontal(|ui| {
+                    ui.label(format!("Model {}", i));
+                    if attached {
+                        if ui.button("Detach").clicked() {
+                            scene.detach_cloth(i);
+                        }
+                    } else if ui.button("Attach cloth").clicked() {
+                        scene.attach_cloth(i);
+                    }
+                });
+            }
+            for cloth in scene.cloths.iter_mut() {
+                ui.label(format!(
+                    "Model {} mesh {}: {} particles",
+                    cloth.model,
+                    cloth.mesh,
+                    cloth.num_particles()
+                ));
+                ui.add(egui::Slider::new(&mut cloth.settings.stiffness, 0.0..=1.0).text("stiffness"));
+                ui.add(egui::Slider::new(&mut cloth.settings.iterations, 1..=32).text("iterations"));
+                ui.add(egui::Slider::new(&mut cloth.settings.damping, 0.0..=0.2).text("damping"));
+            }
+        });
+    }
+}
+
+#[cfg(feature = "physics")]
+impl MyApp {
+    fn physics_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::physics::BodyKind;
+
+        egui::CollapsingHeader::new("Physics").show(ui, |ui| {
+            let mut scene = self.scene.write().unwrap();
+            let scene = &mut *scene;
+            ui.horizontal(|ui| {
+                let label = if scene.physics.running { "Pause" } else { "Play" };
+                if ui.button(label).clicked() {
+                    scene.physics.running = !scene.physics.running;
+                }
+                if ui.button("Reset").clicked() {
+                    scene.physics.request_reset();
+                }
+            });
+            ui.checkbox(&mut scene.physics.show_colliders, "Show colliders");
+            for (i, model) in scene.models.iter().enumerate() {
+                let current = scene.physics.body_kind(i);
+                let mut kind = current;
+                ui.horizontal(|ui| {
+                    ui.label(format!("Model {}", i));
+                    ui.radio_value(&mut kind, None, "None");
+                    ui.radio_value(&mut kind, Some(BodyKind::Static), "Static");
+                    ui.radio_value(&mut kind, Some(BodyKind::Dynamic), "Dynamic");
+                });
+                if kind != current {
+                    scene.physics.set_body(i, model, kind);
+                }
+            }
+        });
+    }
+
+    /// GPU memory per category, the largest buffers and textures, and what was leaked.
+    fn memory_ui(&mut self, ui: &mut egui::Ui) {
+        use crate::gpu_memory;
+
+        egui::CollapsingHeader::new("Memory").show(ui, |ui| {
+            let mib = |bytes: u64| bytes as f64 / (1 << 20) as f64;
+            ui.label(format!("{:.1} MiB in total", mib(gpu_memory::total_bytes())));
+            for (category, count, bytes) in gpu_memory::totals() {
+                let name = category.name();
+                ui.label(format!("{}: {} resources, {:.1} MiB", name, count, mib(bytes)));
+            }
+            egui::CollapsingHeader::new("Resources").show(ui, |ui| {
+                let mut entries = gpu_memory::entries();
+                entries.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+                egui::ScrollArea::vertical().max_height(200.).show(ui, |ui| {
+                    for entry in entries.iter() {
+                        ui.label(format!(
+                            "{} ({}): {:.1} KiB",
+                            entry.label,
+                            entry.category.name(),
+                            entry.bytes as f64 / 1024.
+                        ));
+                    }
+                });
+            });
+            let mut scene = self.scene.write().unwrap();
+            if scene.memory.leaks.is_empty() {
+                ui.label("No leaks found");
+                return;
+            }
+            for (what, entries) in scene.memory.leaks.iter() {
+                let text = format!("{} buffers of {} still alive", entries.len(), what);
+                ui.colored_label(egui::Color32::RED, text);
+                for entry in entries.iter() {
+                    ui.label(format!("  {}", entry.label));
+                }
+            }
+            if ui.button("Clear leaks").clicked() {
+                scene.memory.leaks.clear();
+            }
+        });
     }
 }
 
 impl epi::App for MyApp {
     fn update(&mut self, ctx: &egui::CtxRef, frame: &mut Frame<'_>) {
+        self.clipboard_shortcuts(ctx);
+        self.stats_overlay(ctx);
+        self.loading_overlay(ctx);
+        self.cleanup_overlay(ctx);
+        self.remap_overlay(ctx);
+        self.guides_overlay(ctx);
+        self.compare_overlay(ctx);
+        #[cfg(feature = "profiling")]
+        crate::profiling::window(ctx, &mut self.profiler);
         egui::Window::new("wrap_app_top_bar")
             .min_width(50.0)
             .show(ctx, |ui| {
@@ -234,6 +2724,34 @@ impl epi::App for MyApp {
                     for material in self.scene.read().unwrap().materials.read().unwrap().iter() {
                         ui.label(material.0);
                     }
+                    self.render_ui(ui);
+                    self.compare_ui(ui);
+                    self.lights_ui(ui);
+                    self.shadows_ui(ui);
+                    self.ground_ui(ui);
+                    self.camera_ui(ui);
+                    self.sun_ui(ui);
+                    self.section_ui(ui);
+                    self.selection_ui(ui);
+                    self.cursor_ui(ui);
+                    self.construction_ui(ui);
+                    self.cameras_ui(ui);
+                    self.review_ui(ui);
+                    self.graph_ui(ui);
+                    self.poses_ui(ui);
+                    self.outliner_ui(ui);
+                    self.sculpt_ui(ui);
+                    self.edit_ui(ui);
+                    self.smoothing_ui(ui);
+                    self.normals_ui(ui);
+                    self.remesh_ui(ui);
+                    self.cleanup_ui(ui);
+                    self.modifier_ui(ui);
+                    self.shape_keys_ui(ui);
+                    self.cloth_ui(ui);
+                    #[cfg(feature = "physics")]
+                    self.physics_ui(ui);
+                    self.memory_ui(ui);
                 });
             });
     }
@@ -0,0 +1,498 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::*;
+use cgmath::{Deg, InnerSpace, Point3, Quaternion, Rotation3, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bounds::Aabb,
+    camera::Camera,
+    cameras::SceneCamera,
+    capture::Capture,
+    compression::Compression,
+    gltf_import,
+    light::{Light, LightKind},
+    loading::{self, LoadStatus},
+    model,
+    outliner::GroupTransform,
+    registry::NodeId,
+    render_config::RenderConfig,
+    scene::Scene,
+    scene_graph::NodeTransform,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraDesc {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    pub up: [f32; 3],
+    /// Vertical field of view in degrees.
+    pub fovy: f32,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        Self {
+            eye: [3.0, 4.0, -6.0],
+            target: [0.0, 0.0, 0.0],
+            up: [0.0, 1.0, 0.0],
+            fovy: 45.0,
+        }
+    }
+}
+
+impl CameraDesc {
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            eye: camera.eye.into(),
+            target: camera.target.into(),
+            up: camera.up.into(),
+            fovy: cgmath::Deg::from(camera.projection.fovy).0,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.eye = self.eye.into();
+        camera.target = self.target.into();
+        camera.up = self.up.into();
+        camera.projection.fovy = cgmath::Deg(self.fovy).into();
+    }
+
+    /// The view moved to look at the center of `bounds` from the same direction, far enough
+    /// for the whole of them to fit.
+    pub fn framing(&self, bounds: &Aabb) -> Self {
+        if bounds.is_empty() {
+            return self.clone();
+        }
+        let target = bounds.center();
+        let radius = (bounds.size().magnitude() / 2.).max(1e-3);
+        let direction = Point3::from(self.eye) - Point3::from(self.target);
+        let direction = if direction.magnitude2() > 0. {
+            direction.normalize()
+        } else {
+            Vector3::unit_z()
+        };
+        let distance = radius / (self.fovy / 2.).to_radians().sin();
+        Self {
+            eye: (target + direction * distance).into(),
+            target: target.into(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Parses an image size written as `WIDTHxHEIGHT`, e.g. `1280x720`.
+pub fn parse_size(text: &str) -> Result<(u32, u32)> {
+    let (width, height) = text
+        .split_once(|c| c == 'x' || c == 'X')
+        .with_context(|| format!("{:?} isn't WIDTHxHEIGHT", text))?;
+    let size = (width.trim().parse::<u32>()?, height.trim().parse::<u32>()?);
+    ensure!(size.0 > 0 && size.1 > 0, "{:?} is empty", text);
+    Ok(size)
+}
+
+/// An OBJ or glTF model of a scene description, placed by its transform.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelDesc {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub transform: GroupTransform,
+}
+
+impl ModelDesc {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            transform: GroupTransform::default(),
+        }
+    }
+
+    /// The transform as the model's root node takes it.
+    pub fn node_transform(&self) -> NodeTransform {
+        let [x, y, z] = self.transform.rotation;
+        let scale = self.transform.scale;
+        NodeTransform {
+            translation: self.transform.translation.into(),
+            rotation: Quaternion::from_angle_z(Deg(z))
+                * Quaternion::from_angle_y(Deg(y))
+                * Quaternion::from_angle_x(Deg(x)),
+            scale: Vector3::new(scale, scale, scale),
+        }
+    }
+}
+
+/// A light of a scene description.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightDesc {
+    pub kind: LightKind,
+    pub position: [f32; 3],
+    /// The point directional and spot lights aim at.
+    pub target: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// How far point and spot lights reach.
+    pub range: f32,
+    /// The cone of a spot light in degrees, from its axis to the edge.
+    pub cone: f32,
+}
+
+impl Default for LightDesc {
+    fn default() -> Self {
+        Self {
+            kind: LightKind::Directional,
+            position: [200.0, 200.0, 2.0],
+            target: [0.0, 0.0, 0.0],
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            range: 20.0,
+            cone: 22.5,
+        }
+    }
+}
+
+impl LightDesc {
+    pub fn to_light(&self) -> Light {
+        let mut light = Light::new(
+            self.position.into(),
+            self.color.into(),
+            Deg(self.cone * 2.),
+            1.0..self.range.max(1.0),
+        );
+        light.kind = self.kind;
+        light.target = self.target.into();
+        light.intensity = self.intensity;
+        light
+    }
+}
+
+/// Everything needed to render a scene without a window. Left out fields keep their default,
+/// so a scene file may only list its models.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneDesc {
+    pub models: Vec<ModelDesc>,
+    /// The first light casts the shadows. Without any, the scene keeps its default light.
+    pub lights: Vec<LightDesc>,
+    pub camera: CameraDesc,
+    pub size: (u32, u32),
+    pub render: RenderConfig,
+    /// Cameras of the scene, e.g. from a project.
+    pub cameras: Vec<SceneCamera>,
+    /// Renders through the scene camera of this name instead of `camera`.
+    pub look_through: Option<String>,
+    /// Moves `camera` to frame all of the models, looking from the same direction.
+    pub frame_models: bool,
+}
+
+impl Default for SceneDesc {
+    fn default() -> Self {
+        Self {
+            models: Vec::new(),
+            lights: Vec::new(),
+            camera: CameraDesc::default(),
+            size: (256, 256),
+            render: RenderConfig::default(),
+            cameras: Vec::new(),
+            look_through: None,
+            frame_models: false,
+        }
+    }
+}
+
+impl SceneDesc {
+    /// Reads a RON scene file. Model paths are relative to the file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("Scene: {:?} not found", path))?;
+        let mut desc: Self = ron::de::from_reader(BufReader::new(file))
+            .with_context(|| format!("Scene: {:?} is broken", path))?;
+        if let Some(dir) = path.parent() {
+            for model in desc.models.iter_mut() {
+                model.path = dir.join(&model.path);
+            }
+        }
+        Ok(desc)
+    }
+}
+
+/// A device created without a surface, e.g. for tests.
+pub struct Headless {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+}
+
+impl Headless {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    pub async fn new() -> Result<Self> {
+        let backend = wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all);
+        let instance = wgpu::Instance::new(backend);
+        let adapter = wgpu::util::initialize_adapter_from_env_or_default(&instance, backend, None)
+            .await
+            .context("No suitable GPU adapters found on the system!")?;
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
+                    limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+                },
+                None,
+            )
+            .await?;
+        Ok(Self { device, queue })
+    }
+
+    /// The configuration a window surface of the same size would have.
+    pub fn config(width: u32, height: u32) -> wgpu::SurfaceConfiguration {
+        wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: Self::FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+        }
+    }
+
+    pub async fn render(&self, desc: &SceneDesc) -> Result<image::RgbaImage> {
+        let (width, height) = desc.size;
+        let config = Self::config(width, height);
+        let scene = Arc::new(RwLock::new(Scene::with_render_config(
+            &self.device,
+            &self.queue,
+            &config,
+            desc.render.clone(),
+        )));
+
+        for placed in desc.models.iter() {
+            let node = self
+                .load_model(&scene, &config, &placed.path)
+                .await
+                .with_context(|| format!("Can't load {:?}", placed.path))?;
+            scene
+                .write()
+                .unwrap()
+                .set_model_transform(node, placed.node_transform());
+        }
+
+        {
+            let mut scene = scene.write().unwrap();
+            if let Some((first, others)) = desc.lights.split_first() {
+                let light = &mut scene.lights.lights[0];
+                light.light = first.to_light();
+                light.orbit = false;
+                for other in others {
+                    scene.lights.add(&self.device, other.to_light());
+                }
+            }
+            scene.cameras.cameras = desc.cameras.clone();
+            let view = match &desc.look_through {
+                Some(name) => match scene.cameras.find(name) {
+                    Some(camera) => camera.view.clone(),
+                    None => bail!("No camera named {:?} in the scene", name),
+                },
+                None if desc.frame_models => {
+                    // The transforms reach the nodes with an update.
+                    scene.update(&self.queue, 0.);
+                    desc.camera.framing(&Aabb::from_points(
+                        scene.world_triangles().into_iter().flatten(),
+                    ))
+                }
+                None => desc.camera.clone(),
+            };
+            view.apply(&mut scene.camera);
+            scene.update(&self.queue, 0.);
+            scene.update_shadows(&self.device, &self.queue);
+            scene.update_wireframe(&self.device, &self.queue);
+        }
+
+        let capture = Capture::new(&self.device, width, height, config.format);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Headless Encoder"),
+            });
+        scene.read().unwrap().draw(&mut encoder, &capture.view);
+        capture.copy_to_buffer(&mut encoder);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        capture.read(&self.device).await
+    }
+
+    /// Adds the OBJ model at `path` the way scene files always did, or the glTF one the way
+    /// the window loads it.
+    async fn load_model(
+        &self,
+        scene: &Arc<RwLock<Scene>>,
+        config: &wgpu::SurfaceConfiguration,
+        path: &Path,
+    ) -> Result<NodeId> {
+        let (device, queue) = (&self.device, &self.queue);
+        let source = path.to_string_lossy();
+        if !gltf_import::is_gltf(&source) {
+            let model = model::ObjModel::load(device, queue, path, config, scene.clone()).await?;
+            return Ok(scene.write().unwrap().add_model(model::Model::OBJ(model)));
+        }
+        let status = std::sync::Mutex::new(LoadStatus::new(source.to_string()));
+        let mut loaded = loading::load(&source, &status, false, Compression::Off).await?;
+        let nodes = std::mem::take(&mut loaded.nodes);
+        let skins = std::mem::take(&mut loaded.skins);
+        let mut scene = scene.write().unwrap();
+        let model = scene.upload_model(device, queue, config, loaded);
+        let node = scene.add_model(model);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        scene.add_loaded_nodes(node.0, name.into_owned(), nodes);
+        scene.set_skins(node.0, skins);
+        Ok(node)
+    }
+}
+
+/// Renders the described scene with a headless adapter, blocking until the image is read back.
+pub fn render_scene_to_image(desc: &SceneDesc) -> Result<image::RgbaImage> {
+    futures::executor::block_on(async {
+        let headless = Headless::new().await?;
+        headless.render(desc).await
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageDiff {
+    /// Number of pixels whose largest channel difference exceeds the tolerance.
+    pub mismatched: usize,
+    pub total: usize,
+    /// Largest channel difference found in the whole image.
+    pub max_difference: u8,
+}
+
+impl ImageDiff {
+    pub fn mismatched_ratio(&self) -> f32 {
+        self.mismatched as f32 / self.total.max(1) as f32
+    }
+
+    pub fn is_within(&self, max_mismatched_ratio: f32) -> bool {
+        self.mismatched_ratio() <= max_mismatched_ratio
+    }
+}
+
+fn pixel_difference(a: &image::Rgba<u8>, b: &image::Rgba<u8>) -> u8 {
+    a.0.iter()
+        .zip(b.0.iter())
+        .map(|(a, b)| (*a as i16 - *b as i16).abs() as u8)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Compares two images channel by channel. Pixels differing by at most `tolerance` count as equal.
+pub fn compare_images(
+    actual: &image::RgbaImage,
+    expected: &image::RgbaImage,
+    tolerance: u8,
+) -> Result<ImageDiff> {
+    ensure!(
+        actual.dimensions() == expected.dimensions(),
+        "Image sizes differ: {:?} != {:?}",
+        actual.dimensions(),
+        expected.dimensions()
+    );
+    let mut diff = ImageDiff {
+        mismatched: 0,
+        total: (actual.width() * actual.height()) as usize,
+        max_difference: 0,
+    };
+    for (a, b) in actual.pixels().zip(expected.pixels()) {
+        let d = pixel_difference(a, b);
+        diff.max_difference = diff.max_difference.max(d);
+        if d > tolerance {
+            diff.mismatched += 1;
+        }
+    }
+    Ok(diff)
+}
+
+/// Builds an image highlighting pixels outside the tolerance in red, useful when a comparison fails.
+pub fn diff_image(
+    actual: &image::RgbaImage,
+    expected: &image::RgbaImage,
+    tolerance: u8,
+) -> image::RgbaImage {
+    image::RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let a = actual.get_pixel(x, y);
+        let in_bounds = x < expected.width() && y < expected.height();
+        if in_bounds && pixel_difference(a, expected.get_pixel(x, y)) <= tolerance {
+            // Dimmed grayscale keeps the matching parts recognizable.
+            let l = ((a[0] as u16 + a[1] as u16 + a[2] as u16) / 6) as u8;
+            image::Rgba([l, l, l, 255])
+        } else {
+            image::Rgba([255, 0, 0, 255])
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(width: u32, height: u32, value: u8) -> image::RgbaImage {
+        image::RgbaImage::from_pixel(width, height, image::Rgba([value, value, value, 255]))
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = filled(4, 3, 100);
+        let diff = compare_images(&image, &image, 0).unwrap();
+        assert_eq!(diff.mismatched, 0);
+        assert_eq!(diff.total, 12);
+        assert_eq!(diff.max_difference, 0);
+        assert!(diff.is_within(0.));
+    }
+
+    #[test]
+    fn one_pixel_differs() {
+        let expected = filled(4, 4, 100);
+        let mut actual = expected.clone();
+        actual.put_pixel(2, 1, image::Rgba([100, 160, 100, 255]));
+        let diff = compare_images(&actual, &expected, 0).unwrap();
+        assert_eq!(diff.mismatched, 1);
+        assert_eq!(diff.max_difference, 60);
+        assert_eq!(diff.mismatched_ratio(), 1. / 16.);
+        assert!(!diff.is_within(0.05));
+        assert!(diff.is_within(0.1));
+
+        let marked = diff_image(&actual, &expected, 0);
+        assert_eq!(*marked.get_pixel(2, 1), image::Rgba([255, 0, 0, 255]));
+        assert_eq!(*marked.get_pixel(0, 0), image::Rgba([50, 50, 50, 255]));
+    }
+
+    #[test]
+    fn tolerance_is_inclusive() {
+        let expected = filled(2, 2, 100);
+        let actual = filled(2, 2, 108);
+        assert_eq!(compare_images(&actual, &expected, 8).unwrap().mismatched, 0);
+        assert_eq!(compare_images(&actual, &expected, 7).unwrap().mismatched, 4);
+        assert_eq!(compare_images(&expected, &actual, 7).unwrap().max_difference, 8);
+    }
+
+    #[test]
+    fn sizes_must_match() {
+        assert!(compare_images(&filled(4, 4, 0), &filled(4, 5, 0), 0).is_err());
+        // Pixels outside the expected image count as different.
+        let marked = diff_image(&filled(3, 1, 0), &filled(2, 1, 0), 0);
+        assert_eq!(*marked.get_pixel(2, 0), image::Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1280x720").unwrap(), (1280, 720));
+        assert_eq!(parse_size("64X32").unwrap(), (64, 32));
+        assert_eq!(parse_size(" 8 x 8 ").unwrap(), (8, 8));
+        for bad in ["", "1280", "1280x", "x720", "0x720", "1280x0", "-1x2", "12.5x3", "axb"] {
+            assert!(parse_size(bad).is_err(), "{:?} parsed", bad);
+        }
+    }
+}
@@ -0,0 +1,434 @@
+//! Impostors: models far from the camera drawn as one quad showing a picture of them instead
+//! of their meshes, cutting the draw cost of large scenes. The pictures are baked offscreen
+//! from `ImpostorPass::ANGLES` directions around the model into an atlas, and the quad, turned
+//! towards the eye, shows the one baked from the direction closest to the eye's. A model is
+//! baked again when its materials, meshes, placement or display colors change, at most one per
+//! frame; its meshes are drawn until its impostor is there. The lighting is the one at the time
+//! of the bake.
+
+use std::{collections::HashMap, sync::Arc};
+
+use cgmath::{InnerSpace, Matrix4, Point3, Rad, Transform, Vector3};
+
+use crate::{
+    bounds::Aabb,
+    camera::Camera,
+    display::DisplayMode,
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    model::DrawModel,
+    renderer::Uniforms,
+    scene::Scene,
+    texture,
+};
+
+#[derive(Debug, Clone)]
+pub struct Impostors {
+    pub enabled: bool,
+    /// Models further than this from the eye, to their bounds, are drawn as impostors.
+    pub distance: f32,
+    /// Width and height in pixels of the picture from each direction.
+    pub resolution: u32,
+}
+
+impl Default for Impostors {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            distance: 50.,
+            resolution: 128,
+        }
+    }
+}
+
+/// What an impostor was baked from, baked again when it changes.
+#[derive(Debug, Clone, PartialEq)]
+struct BakeKey {
+    materials: Vec<usize>,
+    buffers: Vec<u64>,
+    matrices: Vec<[[f32; 4]; 4]>,
+    display: (DisplayMode, [f32; 3]),
+    lit: bool,
+    resolution: u32,
+}
+
+#[derive(Debug)]
+struct Impostor {
+    key: BakeKey,
+    _atlas: Tracked<wgpu::TextureView>,
+    _buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+/// The baked impostors, by model, and the pipeline drawing them.
+#[derive(Debug)]
+pub struct ImpostorPass {
+    impostors: HashMap<usize, Impostor>,
+    /// Models drawn as impostors in the next frames.
+    shown: Vec<usize>,
+    /// Cameras of the directions pictures are baked from.
+    views: Vec<Uniforms>,
+    format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ImpostorPass {
+    /// Directions around the model pictures are baked from, evenly spread about the vertical.
+    pub const ANGLES: u32 = 8;
+
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        uniforms_bind_group_layout: &wgpu::BindGroupLayout,
+        camera: &Camera,
+    ) -> Self {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Impostor Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("impostor_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!("impostor.vert.spv"));
+        let fs_module = device.create_shader_module(&wgpu::include_spirv!("impostor.frag.spv"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Impostor Pipeline Layout"),
+            bind_group_layouts: &[uniforms_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Impostor Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &vs_module,
+                entry_point: "main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_module,
+                entry_point: "main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+        });
+
+        Self {
+            impostors: HashMap::new(),
+            shown: Vec::new(),
+            views: (0..Self::ANGLES).map(|_| Uniforms::new(device, camera)).collect(),
+            format,
+            depth_format,
+            sampler,
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    /// Whether the model is drawn as its impostor.
+    pub fn shows(&self, model: usize) -> bool {
+        self.shown.contains(&model)
+    }
+
+    /// Forgets every impostor, freeing their atlases.
+    pub fn clear(&mut self) {
+        self.impostors.clear();
+        self.shown.clear();
+    }
+
+    /// Draws the impostors shown into a pass whose depth holds the scene.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        uniforms: &'a wgpu::BindGroup,
+    ) {
+        if self.shown.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, uniforms, &[]);
+        for impostor in self.shown.iter().filter_map(|model| self.impostors.get(model)) {
+            render_pass.set_bind_group(1, &impostor.bind_group, &[]);
+            render_pass.draw(0..6, 0..1);
+        }
+    }
+
+    /// The camera looking at the sphere from the direction of the angle, seeing all of it.
+    fn view(center: Point3<f32>, radius: f32, angle: u32) -> Camera {
+        let yaw = angle as f32 / Self::ANGLES as f32 * std::f32::consts::TAU;
+        let direction = Vector3::new(yaw.sin(), 0., yaw.cos());
+        // Orthographic views are as large as a perspective one at the target, so at two radii
+        // away a field of view with a tangent of a half shows a radius to each side.
+        let fovy = Rad(2. * 0.5f32.atan());
+        Camera {
+            eye: center + direction * radius * 2.,
+            target: center,
+            up: Vector3::unit_y(),
+            projection: cgmath::PerspectiveFov {
+                fovy,
+                aspect: 1.,
+                near: radius * 0.5,
+                far: radius * 4.,
+            },
+            orthographic: true,
+        }
+    }
+}
+
+/// Bounds of the meshes moved by their model matrices.
+fn world_bounds(meshes: &[crate::model::Mesh], matrices: &[Matrix4<f32>]) -> Aabb {
+    let mut bounds = Aabb::EMPTY;
+    for (mesh, matrix) in meshes.iter().zip(matrices.iter()) {
+        if mesh.bounds.is_empty() {
+            continue;
+        }
+        let (min, max) = (mesh.bounds.min, mesh.bounds.max);
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+            bounds = bounds.extend(matrix.transform_point(corner));
+        }
+    }
+    bounds
+}
+
+impl Scene {
+    /// Picks the models drawn as impostors for the next frames and bakes the first of them
+    /// without an impostor, or with one baked from something else. Call after
+    /// `update_shadows`, the bake draws with the object flags it uploads.
+    pub fn update_impostors(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        profile_function!();
+        if !self.impostors.enabled {
+            self.renderer.impostors.clear();
+            return;
+        }
+        let matrices = self.mesh_matrices();
+        let mut far = Vec::new();
+        for (i, model) in self.models.iter().enumerate() {
+            // Instanced models are all over the place, their bounds don't say where.
+            if !self.outliner.is_visible(i) || self.renderer.instancing.models.contains_key(&i) {
+                continue;
+            }
+            let bounds = world_bounds(model.meshes(), &matrices[i]);
+            if bounds.is_empty() {
+                continue;
+            }
+            let (center, radius) = (bounds.center(), bounds.size().magnitude() / 2.);
+            if (self.camera.eye - center).magnitude() - radius < self.impostors.distance {
+                continue;
+            }
+            let key = BakeKey {
+                materials: model
+                    .meshes()
+                    .iter()
+                    .map(|mesh| Arc::as_ptr(&mesh.material) as usize)
+                    .collect(),
+                buffers: model.buffer_ids(),
+                matrices: matrices[i].iter().map(|&matrix| matrix.into()).collect(),
+                display: (self.display.mode, self.display.flat_color),
+                lit: self.outliner.is_lit(i),
+                resolution: self.impostors.resolution,
+            };
+            far.push((i, center, radius.max(1e-4), key));
+        }
+        let mut shown = Vec::new();
+        let mut baked = false;
+        for (i, center, radius, key) in far {
+            let current = self.renderer.impostors.impostors.get(&i);
+            if current.map_or(false, |impostor| impostor.key == key) {
+                shown.push(i);
+            } else if !baked {
+                self.bake_impostor(device, queue, i, center, radius, key);
+                baked = true;
+                shown.push(i);
+            }
+        }
+        let count = self.models.len();
+        let impostors = &mut self.renderer.impostors;
+        impostors.impostors.retain(|&model, _| model < count);
+        impostors.shown = shown;
+    }
+
+    /// Renders the model from every direction into an atlas, a square tile per direction.
+    fn bake_impostor(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        index: usize,
+        center: Point3<f32>,
+        radius: f32,
+        key: BakeKey,
+    ) {
+        profile_function!();
+        let renderer = &mut self.renderer;
+        let pass = &mut renderer.impostors;
+        let tile = key.resolution.max(1);
+        let size = (ImpostorPass::ANGLES * tile, tile);
+        let atlas = device
+            .create_tracked_texture(GpuCategory::Targets, &wgpu::TextureDescriptor {
+                label: Some("Impostor Atlas"),
+                size: wgpu::Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: pass.format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+            })
+            .into_view(&wgpu::TextureViewDescriptor::default());
+        let depth = texture::Texture::create_depth_texture(
+            device,
+            &wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                format: pass.format,
+                width: size.0,
+                height: size.1,
+                present_mode: wgpu::PresentMode::Fifo,
+            },
+            pass.depth_format,
+            "Impostor Depth",
+        );
+        for (angle, view) in pass.views.iter_mut().enumerate() {
+            view.update(queue, &ImpostorPass::view(center, radius, angle as u32));
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Impostor Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Impostor Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &atlas,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            let model = &self.models[index];
+            let shadows = &renderer.shadows;
+            let instances = renderer.instancing.of(index);
+            for (angle, view) in pass.views.iter().enumerate() {
+                let x = (angle as u32 * tile) as f32;
+                render_pass.set_viewport(x, 0., tile as f32, tile as f32, 0., 1.);
+                for (i, mesh) in model.meshes().iter().enumerate() {
+                    let offset = shadows.object_offset(index, i);
+                    render_pass.set_bind_group(3, &shadows.bind_group, &[offset]);
+                    render_pass.draw_mesh_instanced(
+                        mesh,
+                        &Some(&mesh.material),
+                        instances,
+                        &view.bind_group,
+                        &self.lights.lights[0].bind_group,
+                    );
+                }
+            }
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let [x, y, z]: [f32; 3] = center.into();
+        let buffer = device.create_tracked_buffer_init(
+            GpuCategory::Uniforms,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Impostor Buffer"),
+                contents: bytemuck::cast_slice(&[
+                    [x, y, z, radius],
+                    [ImpostorPass::ANGLES as f32, 0., 0., 0.],
+                ]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            },
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("impostor_bind_group"),
+            layout: &pass.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&pass.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        });
+        pass.impostors.insert(
+            index,
+            Impostor {
+                key,
+                _atlas: atlas,
+                _buffer: buffer,
+                bind_group,
+            },
+        );
+    }
+}
@@ -0,0 +1,44 @@
+#version 450
+
+layout(location=0) out vec2 v_tex_coords;
+
+layout(set=0, binding=0)
+uniform Uniforms {
+    vec3 u_view_position;
+    mat4 u_view_proj;
+    vec4 u_section_min;
+    vec4 u_section_max;
+};
+
+layout(set=1, binding=2)
+uniform Impostor {
+    // xyz: center of the model's bounds, w: their radius
+    vec4 u_sphere;
+    // x: directions baked into the atlas
+    vec4 u_angles;
+};
+
+const float PI = 3.14159265;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+// A quad at the model turned towards the eye about the vertical, showing the picture baked
+// from the direction closest to the eye's, see impostor.rs.
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+    vec2 to_eye = u_view_position.xz - u_sphere.xz;
+    to_eye = length(to_eye) > 1e-5 ? normalize(to_eye) : vec2(0.0, 1.0);
+
+    float count = u_angles.x;
+    float angle = atan(to_eye.x, to_eye.y);
+    float tile = mod(round(angle / (2.0 * PI / count)), count);
+    vec2 uv = vec2(corner.x + 1.0, 1.0 - corner.y) / 2.0;
+    v_tex_coords = vec2((tile + uv.x) / count, uv.y);
+
+    vec3 right = vec3(to_eye.y, 0.0, -to_eye.x);
+    vec3 position = u_sphere.xyz + (right * corner.x + vec3(0.0, corner.y, 0.0)) * u_sphere.w;
+    gl_Position = u_view_proj * vec4(position, 1.0);
+}
@@ -0,0 +1,259 @@
+//! `modeling info`, describing a model file without opening a window or touching the GPU.
+
+use std::{collections::HashMap, fmt::Write, sync::Mutex};
+
+use anyhow::*;
+use cgmath::{InnerSpace, Point3, Vector3};
+use image::GenericImageView;
+use serde::Serialize;
+
+use crate::{
+    bounds::Aabb,
+    compression::Compression,
+    loading::{self, LoadStatus, LoadedMesh, Pixels},
+    weld::Welded,
+};
+
+#[derive(Debug, Serialize)]
+pub struct TextureInfo {
+    pub kind: &'static str,
+    /// `None` when the material has a plain color instead.
+    pub size: Option<[u32; 2]>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MaterialInfo {
+    pub name: String,
+    pub textures: Vec<TextureInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MeshInfo {
+    pub name: String,
+    pub vertices: usize,
+    pub triangles: usize,
+    pub material: Option<String>,
+    pub bounds: [[f32; 3]; 2],
+    /// Edges with a triangle on one side only, where the surface isn't closed.
+    pub open_edges: usize,
+    /// Vertex attributes the file had beyond positions, texture coordinates and normals.
+    pub attributes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelInfo {
+    pub source: String,
+    pub vertices: usize,
+    pub triangles: usize,
+    pub bounds: [[f32; 3]; 2],
+    pub meshes: Vec<MeshInfo>,
+    pub materials: Vec<MaterialInfo>,
+    /// What the loader had to work around and what the mesh checks found.
+    pub warnings: Vec<String>,
+}
+
+/// Problems of a mesh's geometry, with the number of its open edges. Vertices at the same
+/// position count as one, so UV seams aren't taken for holes.
+fn check_mesh(mesh: &LoadedMesh, warnings: &mut Vec<String>) -> usize {
+    let name = &mesh.name;
+    if mesh.indices.is_empty() {
+        warnings.push(format!("Mesh {} has no triangles", name));
+        return 0;
+    }
+    if mesh.indices.len() % 3 != 0 {
+        warnings.push(format!(
+            "Mesh {} has {} indices, not whole triangles",
+            name,
+            mesh.indices.len()
+        ));
+    }
+    let out_of_range = mesh
+        .indices
+        .iter()
+        .filter(|&&i| i as usize >= mesh.vertices.len())
+        .count();
+    if out_of_range > 0 {
+        // Nothing else can be checked without knowing which vertices are meant.
+        warnings.push(format!(
+            "Mesh {} has {} indices past its vertices",
+            name, out_of_range
+        ));
+        return 0;
+    }
+    let non_finite = mesh
+        .vertices
+        .iter()
+        .filter(|v| !v.position.iter().all(|c| c.is_finite()))
+        .count();
+    if non_finite > 0 {
+        warnings.push(format!(
+            "Mesh {} has {} vertices at infinity or NaN",
+            name, non_finite
+        ));
+    }
+
+    let welded = Welded::new(&mesh.vertices, &mesh.indices);
+    let mut degenerate = 0;
+    let mut edges = HashMap::new();
+    for triangle in welded.triangles.iter() {
+        let [a, b, c] = triangle.map(|i| welded.positions[i as usize]);
+        if (b - a).cross(c - a).magnitude2() == 0. {
+            degenerate += 1;
+            continue;
+        }
+        for i in 0..3 {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+    if degenerate > 0 {
+        warnings.push(format!(
+            "Mesh {} has {} triangles without area",
+            name, degenerate
+        ));
+    }
+    let non_manifold = edges.values().filter(|&&count| count > 2).count();
+    if non_manifold > 0 {
+        warnings.push(format!(
+            "Mesh {} has {} edges shared by more than two triangles",
+            name, non_manifold
+        ));
+    }
+    edges.values().filter(|&&count| count == 1).count()
+}
+
+fn bounds_of(bounds: &Aabb) -> [[f32; 3]; 2] {
+    if bounds.is_empty() {
+        [[0.; 3]; 2]
+    } else {
+        [bounds.min.into(), bounds.max.into()]
+    }
+}
+
+impl ModelInfo {
+    /// Loads the model at `path` with the viewer's loader and checks its meshes.
+    pub fn load(path: &str) -> Result<Self> {
+        let status = Mutex::new(LoadStatus::new(path.to_string()));
+        let load = loading::load(path, &status, false, Compression::Off);
+        let model = futures::executor::block_on(load)?;
+        let mut warnings = model.warnings;
+
+        let materials = model
+            .materials
+            .iter()
+            .map(|material| {
+                let texture = |kind, pixels: &Pixels| TextureInfo {
+                    kind,
+                    size: match pixels {
+                        Pixels::Image(image) => Some([image.width(), image.height()]),
+                        Pixels::Color(_) | Pixels::Missing | Pixels::Streamed { .. } => None,
+                    },
+                };
+                MaterialInfo {
+                    name: material.name.clone(),
+                    textures: vec![
+                        texture("diffuse", &material.diffuse),
+                        texture("normal", &material.normal),
+                        texture("specular", &material.specular),
+                        texture("occlusion", &material.occlusion),
+                        texture("emissive", &material.emissive),
+                    ],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut total = Aabb::EMPTY;
+        let meshes = model
+            .meshes
+            .iter()
+            .map(|mesh| {
+                let bounds =
+                    Aabb::from_points(mesh.vertices.iter().map(|v| Point3::from(v.position)));
+                total = total.union(&bounds);
+                MeshInfo {
+                    name: mesh.name.clone(),
+                    vertices: mesh.vertices.len(),
+                    triangles: mesh.indices.len() / 3,
+                    material: mesh.material.map(|i| materials[i].name.clone()),
+                    bounds: bounds_of(&bounds),
+                    open_edges: check_mesh(mesh, &mut warnings),
+                    attributes: mesh
+                        .attributes
+                        .iter()
+                        .flat_map(|data| data.layout.attributes())
+                        .map(|attribute| attribute.name().to_string())
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        if meshes.is_empty() {
+            warnings.push("The model has no meshes".to_string());
+        }
+
+        Ok(Self {
+            source: model.source,
+            vertices: meshes.iter().map(|mesh| mesh.vertices).sum(),
+            triangles: meshes.iter().map(|mesh| mesh.triangles).sum(),
+            bounds: bounds_of(&total),
+            meshes,
+            materials,
+            warnings,
+        })
+    }
+
+    /// A report for reading in a terminal.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let [min, max] = self.bounds;
+        let size = Vector3::from(max) - Vector3::from(min);
+        // Writing to a String can't fail.
+        let _ = writeln!(text, "{}", self.source);
+        let _ = writeln!(
+            text,
+            "  {} meshes, {} materials, {} triangles, {} vertices",
+            self.meshes.len(),
+            self.materials.len(),
+            self.triangles,
+            self.vertices
+        );
+        let _ = writeln!(
+            text,
+            "  bounds {:?} to {:?}, size {} x {} x {}",
+            min, max, size.x, size.y, size.z
+        );
+        let _ = writeln!(text, "Meshes");
+        for mesh in self.meshes.iter() {
+            let _ = writeln!(
+                text,
+                "  {}: {} triangles, {} vertices, {} open edges, material {}",
+                mesh.name,
+                mesh.triangles,
+                mesh.vertices,
+                mesh.open_edges,
+                mesh.material.as_deref().unwrap_or("none")
+            );
+            if !mesh.attributes.is_empty() {
+                let _ = writeln!(text, "    attributes {}", mesh.attributes.join(", "));
+            }
+        }
+        let _ = writeln!(text, "Materials");
+        for material in self.materials.iter() {
+            let textures = material
+                .textures
+                .iter()
+                .map(|texture| match texture.size {
+                    Some([width, height]) => format!("{} {}x{}", texture.kind, width, height),
+                    None => format!("{} color", texture.kind),
+                })
+                .collect::<Vec<_>>();
+            let _ = writeln!(text, "  {}: {}", material.name, textures.join(", "));
+        }
+        if !self.warnings.is_empty() {
+            let _ = writeln!(text, "Warnings");
+            for warning in self.warnings.iter() {
+                let _ = writeln!(text, "  {}", warning);
+            }
+        }
+        text
+    }
+}
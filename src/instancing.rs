@@ -0,0 +1,171 @@
+//! Drawing a model many times in one draw call per mesh, each copy with its own transform read
+//! from an instance vertex buffer, e.g. for trees or crowds. Every model pipeline reads the
+//! instance buffer, so models drawn once are drawn with a single instance in place.
+//!
+//! Instances are drawn by the forward pass only: they don't cast shadows and can't be picked.
+
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{One, Quaternion, Vector3};
+
+use crate::{
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    model::Vertex,
+    scene::Scene,
+};
+
+/// Shader location of the instance's position, followed by its rotation and scale. The extra
+/// vertex attributes of vertex_layout.rs stop before it.
+pub const FIRST_LOCATION: u32 = 13;
+
+/// Where one copy of a model is drawn, on top of the model's own transform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instance {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            position: Vector3::new(0., 0., 0.),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1., 1., 1.),
+        }
+    }
+}
+
+impl Instance {
+    pub fn to_raw(&self) -> InstanceRaw {
+        let rotation = self.rotation;
+        InstanceRaw {
+            position: self.position.into(),
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+            scale: self.scale.into(),
+        }
+    }
+}
+
+/// An instance as the shader reads it, the rotation as a quaternion with w last.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct InstanceRaw {
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl Vertex for InstanceRaw {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: FIRST_LOCATION,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: FIRST_LOCATION + 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 7]>() as wgpu::BufferAddress,
+                    shader_location: FIRST_LOCATION + 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+/// The instances of a model uploaded to a vertex buffer.
+#[derive(Debug)]
+pub struct InstanceBuffer {
+    pub buffer: Tracked<wgpu::Buffer>,
+    pub count: u32,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device, label: &str, instances: &[Instance]) -> Self {
+        let raw = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let buffer = device.create_tracked_buffer_init(
+            GpuCategory::Meshes,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(&raw),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+        Self {
+            buffer,
+            count: instances.len() as u32,
+        }
+    }
+
+    /// The instance models not drawn instanced are drawn with.
+    pub fn single(device: &wgpu::Device) -> Self {
+        Self::new(device, "Single Instance Buffer", &[Instance::default()])
+    }
+
+    /// Writes the instances over the uploaded ones, which must be as many.
+    pub fn update(&self, queue: &wgpu::Queue, instances: &[Instance]) {
+        let raw = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&raw));
+    }
+}
+
+/// The instance buffers of the models drawn instanced, by model index.
+#[derive(Debug)]
+pub struct Instancing {
+    single: InstanceBuffer,
+    pub models: HashMap<usize, InstanceBuffer>,
+}
+
+impl Instancing {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            single: InstanceBuffer::single(device),
+            models: HashMap::new(),
+        }
+    }
+
+    /// The instances the model is drawn with.
+    pub fn of(&self, model: usize) -> &InstanceBuffer {
+        self.models.get(&model).unwrap_or(&self.single)
+    }
+}
+
+impl Scene {
+    /// Draws the model once at each of the instances, in one draw call per mesh. The buffer is
+    /// written over when there are as many as before, and made anew otherwise. No instances
+    /// hide the model.
+    pub fn set_instances(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        model: usize,
+        instances: &[Instance],
+    ) {
+        let models = &mut self.renderer.instancing.models;
+        match models.get(&model) {
+            Some(buffer) if buffer.count == instances.len() as u32 && !instances.is_empty() => {
+                buffer.update(queue, instances)
+            }
+            _ => {
+                let label = format!("Model {} Instance Buffer", model);
+                models.insert(model, InstanceBuffer::new(device, &label, instances));
+            }
+        }
+    }
+
+    /// Draws the model once again, at its own transform.
+    pub fn clear_instances(&mut self, model: usize) {
+        self.renderer.instancing.models.remove(&model);
+    }
+}
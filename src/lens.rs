@@ -0,0 +1,85 @@
+/// Camera sensor and lens, setting the field of view like a real camera would.
+///
+/// The film gate, the part of the image the sensor records, is fitted into the window. Where
+/// the window is wider or taller than the sensor the view extends past the gate, which the
+/// overlay marks.
+#[derive(Debug, Clone)]
+pub struct Lens {
+    /// The camera keeps its own field of view while false.
+    pub enabled: bool,
+    pub focal_length: f32,
+    /// Millimeters.
+    pub sensor_width: f32,
+    pub sensor_height: f32,
+}
+
+impl Default for Lens {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            focal_length: 50.,
+            sensor_width: 36.,
+            sensor_height: 24.,
+        }
+    }
+}
+
+impl Lens {
+    /// Common sensor sizes in millimeters.
+    pub const SENSORS: [(&'static str, f32, f32); 5] = [
+        ("Full frame", 36., 24.),
+        ("APS-C", 23.6, 15.6),
+        ("Super 35", 24.89, 18.66),
+        ("Micro Four Thirds", 17.3, 13.),
+        ("1 inch", 13.2, 8.8),
+    ];
+
+    pub fn sensor_aspect(&self) -> f32 {
+        self.sensor_width / self.sensor_height.max(1e-3)
+    }
+
+    /// Horizontal field of view across the film gate.
+    pub fn horizontal_fov(&self) -> cgmath::Rad<f32> {
+        cgmath::Rad(2. * (self.sensor_width / (2. * self.focal_length.max(1e-3))).atan())
+    }
+
+    /// Vertical field of view of a window with the given aspect ratio, the gate fitted inside.
+    pub fn vertical_fov(&self, aspect: f32) -> cgmath::Rad<f32> {
+        let half_width = self.sensor_width / (2. * self.focal_length.max(1e-3));
+        // The window's height relative to the gate's width.
+        let height = (1. / aspect.max(1e-3)).max(1. / self.sensor_aspect());
+        cgmath::Rad(2. * (half_width * height).atan())
+    }
+
+    /// The film gate in a window of the given size, as `[x, y, width, height]`.
+    pub fn gate_rect(&self, width: f32, height: f32) -> [f32; 4] {
+        let sensor = self.sensor_aspect();
+        let (gate_width, gate_height) = if width / height.max(1e-3) > sensor {
+            (height * sensor, height)
+        } else {
+            (width, width / sensor)
+        };
+        [
+            (width - gate_width) / 2.,
+            (height - gate_height) / 2.,
+            gate_width,
+            gate_height,
+        ]
+    }
+}
+
+/// Composition guides drawn over the view.
+#[derive(Debug, Clone, Default)]
+pub struct Guides {
+    /// Darkens what lies outside the film gate.
+    pub film_gate: bool,
+    /// Action safe (93%) and title safe (90%) areas of the gate.
+    pub safe_areas: bool,
+    pub thirds: bool,
+    pub center: bool,
+}
+
+impl Guides {
+    pub const ACTION_SAFE: f32 = 0.93;
+    pub const TITLE_SAFE: f32 = 0.9;
+}
@@ -1,10 +1,95 @@
+// First, so its macros can be used in the modules below.
+#[macro_use]
+pub mod profiling;
+
+pub mod app;
+pub mod assets;
+pub mod bake;
+pub mod bench;
+pub mod bounds;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bundle;
+pub mod bvh;
+#[cfg(target_arch = "wasm32")]
+pub mod cache;
 pub mod camera;
+pub mod camera_rig;
+pub mod cameras;
+pub mod capture;
+pub mod clipboard;
+pub mod cloth;
+pub mod clusters;
+pub mod compare;
+pub mod compression;
+pub mod constraints;
+pub mod construction;
+pub mod cursor;
+pub mod debug_lines;
+pub mod display;
+pub mod edit;
+#[cfg(target_arch = "wasm32")]
+pub mod embed;
+pub mod environment;
+pub mod events;
+pub mod gizmos;
+pub mod gltf_import;
+pub mod ground;
+pub mod gpu_memory;
 pub mod gui;
+pub mod headless;
+pub mod impostor;
+pub mod info;
+pub mod instancing;
+pub mod lens;
 pub mod light;
+pub mod loading;
+pub mod mesh;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod mesh_cache;
 pub mod model;
+pub mod modifier;
+pub mod normals;
+pub mod origin;
+pub mod outliner;
+pub mod permutation;
+#[cfg(feature = "physics")]
+pub mod physics;
+pub mod picking;
+pub mod placeholder;
+pub mod poses;
+pub mod primitive;
+pub mod project;
+pub mod recording;
+pub mod registry;
+pub mod remap;
+pub mod remesh;
+pub mod render_config;
 pub mod renderer;
+pub mod resolution;
+pub mod review;
+pub mod scan;
 pub mod scene;
+pub mod scene_graph;
+pub mod sculpt;
+pub mod section;
+pub mod selection;
 pub mod shader;
+pub mod shadow;
+pub mod shape_keys;
+pub mod skin;
+pub mod smoothing;
 pub mod state;
+pub mod stereo;
+pub mod streaming;
+pub mod sun;
+pub mod surface;
 pub mod texture;
+pub mod vertex_layout;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod watch;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
+pub mod weld;
+pub mod wireframe;
+pub mod xray;
 pub mod collection;
\ No newline at end of file
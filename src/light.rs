@@ -1,63 +1,160 @@
-use std::{mem, num::NonZeroU32, ops::Range};
+use std::{mem, ops::Range};
 
 use bytemuck::{Pod, Zeroable};
-use wgpu::util::DeviceExt;
+
+use crate::{
+    camera::Camera,
+    clusters::ClusteredLights,
+    environment::Environment,
+    gizmos::GizmoObject,
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    scene::Scene,
+    shadow::ShadowSettings,
+};
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightRaw {
     pub projection: [[f32; 4]; 4],
+    /// The range in w.
     pub position: [f32; 4],
+    /// Scaled by the intensity.
     pub color: [f32; 4],
+    /// Where the light points, with the kind in w as in `LightKind::index`.
+    pub direction: [f32; 4],
+    /// Cosines of the inner and outer cone angles of a spot light.
+    pub spot: [f32; 4],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LightKind {
+    /// Parallel rays along the direction, from far away like the sun.
+    Directional,
+    /// Light in every direction, fading out with the distance.
+    Point,
+    /// A point light shining in a cone around the direction, fading out toward its edge.
+    Spot,
+}
+
+impl LightKind {
+    pub const ALL: [LightKind; 3] = [LightKind::Directional, LightKind::Point, LightKind::Spot];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LightKind::Directional => "Directional",
+            LightKind::Point => "Point",
+            LightKind::Spot => "Spot",
+        }
+    }
+
+    /// How the shaders tell the kinds apart.
+    pub fn index(&self) -> f32 {
+        match self {
+            LightKind::Directional => 0.,
+            LightKind::Point => 1.,
+            LightKind::Spot => 2.,
+        }
+    }
 }
 
 #[repr(C)]
 #[derive(Debug)]
 pub struct Light {
+    pub kind: LightKind,
     pub position: cgmath::Point3<f32>,
+    /// The point directional and spot lights aim at.
+    pub target: cgmath::Point3<f32>,
     pub color: cgmath::Vector3<f32>,
     pub fov: cgmath::Rad<f32>,
     pub depth: Range<f32>,
-    pub shadow_view: Option<wgpu::TextureView>,
+    /// How far point and spot lights reach, fading out smoothly up to it.
+    pub range: f32,
+    /// The cone a spot light shines in at full strength, within the outer one.
+    pub inner_cone: cgmath::Rad<f32>,
+    /// The cone a spot light shines in, from its axis to the edge.
+    pub outer_cone: cgmath::Rad<f32>,
+    /// Scales the color.
+    pub intensity: f32,
 }
 
 impl Light {
+    /// Unit vector from the light toward its target.
+    pub fn direction(&self) -> cgmath::Vector3<f32> {
+        use cgmath::InnerSpace;
+
+        let direction = self.target - self.position;
+        if direction.magnitude2() < 1e-12 {
+            -cgmath::Vector3::unit_y()
+        } else {
+            direction.normalize()
+        }
+    }
+
     pub fn to_raw(&self) -> LightRaw {
         use crate::camera::PerspectiveFovExt;
-        use cgmath::{Deg, EuclideanSpace, Matrix4, PerspectiveFov, Point3, Vector3};
-
-        let view_matrix = Matrix4::look_at_rh(self.position, Point3::origin(), Vector3::unit_z());
-        let projection = PerspectiveFov {
-            fovy: self.fov,
-            aspect: 1.0,
-            near: self.depth.start,
-            far: self.depth.end,
+        use cgmath::{Matrix4, PerspectiveFov, Vector3};
+
+        let direction = self.direction();
+        let view_matrix =
+            Matrix4::look_at_rh(self.position, self.position + direction, Vector3::unit_z());
+        let (near, far) = (self.depth.start, self.depth.end);
+        let projection = match self.kind {
+            LightKind::Directional => {
+                let half = far * (self.fov.0 / 2.).tan();
+                cgmath::ortho(-half, half, -half, half, near, far)
+            }
+            LightKind::Point => PerspectiveFov {
+                fovy: self.fov,
+                aspect: 1.0,
+                near,
+                far,
+            }
+            .calc_matrix(),
+            LightKind::Spot => PerspectiveFov {
+                fovy: self.outer_cone * 2.,
+                aspect: 1.0,
+                near,
+                far,
+            }
+            .calc_matrix(),
         };
-        let view_proj = projection.calc_matrix() * view_matrix;
+        let view_proj = projection * view_matrix;
+        let color = self.color * self.intensity;
         LightRaw {
             projection: *view_proj.as_ref(),
-            position: [self.position.x, self.position.y, self.position.z, 1.0],
-            color: [
-                self.color.x as f32,
-                self.color.y as f32,
-                self.color.z as f32,
-                1.0,
+            position: [self.position.x, self.position.y, self.position.z, self.range],
+            color: [color.x, color.y, color.z, 1.0],
+            direction: [direction.x, direction.y, direction.z, self.kind.index()],
+            spot: [
+                self.inner_cone.0.min(self.outer_cone.0).cos(),
+                self.outer_cone.0.cos(),
+                0.,
+                0.,
             ],
         }
     }
 
+    /// A point light aiming at the origin as a spot light.
     pub fn new<F: Into<cgmath::Rad<f32>>>(
         position: cgmath::Point3<f32>,
         color: cgmath::Vector3<f32>,
         fov: F,
         depth: Range<f32>,
     ) -> Self {
+        use cgmath::EuclideanSpace;
+
+        let fov = fov.into();
         Self {
+            kind: LightKind::Point,
             position,
+            target: cgmath::Point3::origin(),
             color,
-            fov: fov.into(),
+            fov,
+            range: depth.end,
+            inner_cone: fov / 3.,
+            outer_cone: fov / 2.,
+            intensity: 1.0,
             depth,
-            shadow_view: None,
         }
     }
 }
@@ -65,21 +162,39 @@ impl Light {
 #[derive(Debug)]
 pub struct LightObject {
     pub light: Light,
-    pub buffer: wgpu::Buffer,
+    /// Whether the light circles around the origin.
+    pub orbit: bool,
+    pub shadow: ShadowSettings,
+    pub buffer: Tracked<wgpu::Buffer>,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
 }
 
 impl LightObject {
-    pub fn new(device: &wgpu::Device, light: Light) -> Self {
-        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Light VB"),
-            contents: bytemuck::cast_slice(&[light.to_raw()]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+    /// The light's bind group also holds the clusters, the environment and, where the device
+    /// has storage buffers, `light_storage` with the point lights.
+    pub fn new(
+        device: &wgpu::Device,
+        light: Light,
+        clusters: &ClusteredLights,
+        environment: &Environment,
+        light_storage: &wgpu::Buffer,
+    ) -> Self {
+        let buffer = device.create_tracked_buffer_init(
+            GpuCategory::Lights,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Light VB"),
+                contents: bytemuck::cast_slice(&[light.to_raw()]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
 
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
+        let [lights, clustered, cluster_uniform] = ClusteredLights::layout_entries();
+        let [irradiance, prefiltered, lut, sampler, environment_uniform] =
+            Environment::layout_entries();
+        let storage = ClusteredLights::storage_layout_entry(device);
+        let mut layout_entries = vec![
+            wgpu::BindGroupLayoutEntry {
                 binding: 0,
                 visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
@@ -88,28 +203,66 @@ impl LightObject {
                     min_binding_size: None,
                 },
                 count: None,
-            }],
+            },
+            lights,
+            clustered,
+            cluster_uniform,
+            irradiance,
+            prefiltered,
+            lut,
+            sampler,
+            environment_uniform,
+        ];
+        layout_entries.extend(storage);
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &layout_entries,
             label: None,
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
+        let [lights, clustered, cluster_uniform] = clusters.bind_group_entries();
+        let [irradiance, prefiltered, lut, sampler, environment_uniform] =
+            environment.bind_group_entries();
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
                 binding: 0,
                 resource: buffer.as_entire_binding(),
-            }],
+            },
+            lights,
+            clustered,
+            cluster_uniform,
+            irradiance,
+            prefiltered,
+            lut,
+            sampler,
+            environment_uniform,
+        ];
+        entries.extend(storage.map(|_| wgpu::BindGroupEntry {
+            binding: ClusteredLights::STORAGE_BINDING,
+            resource: light_storage.as_entire_binding(),
+        }));
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &entries,
             label: None,
         });
 
         Self {
             light,
+            orbit: true,
+            shadow: ShadowSettings::default(),
             buffer,
             bind_group,
             bind_group_layout,
         }
     }
-    pub fn update(&mut self, queue: &wgpu::Queue) {
+    /// Degrees per second the light circles around the origin.
+    const ORBIT_SPEED: f32 = 12.0;
+
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32) {
         use cgmath::EuclideanSpace;
+        if !self.orbit {
+            return;
+        }
         let old_position: cgmath::Vector3<f32> = self.light.position.to_vec();
         let rot: cgmath::Quaternion<f32> = cgmath::Rotation3::from_axis_angle(
             cgmath::Vector3 {
@@ -117,12 +270,16 @@ impl LightObject {
                 y: 1.0,
                 z: 0.0,
             },
-            cgmath::Deg(0.2),
+            cgmath::Deg(Self::ORBIT_SPEED * dt),
         );
 
         let pos: cgmath::Vector3<f32> = rot * old_position;
         self.light.position = cgmath::Point3::new(0., 0., 0.) + pos;
+        self.write(queue);
+    }
 
+    /// Uploads the light after it was changed.
+    pub fn write(&self, queue: &wgpu::Queue) {
         queue.write_buffer(
             &self.buffer,
             0,
@@ -133,78 +290,100 @@ impl LightObject {
 
 #[derive(Debug)]
 pub struct Lights {
+    /// The first light casts the shadows, the others are point lights.
     pub lights: Vec<LightObject>,
-    pub shadow_texture: wgpu::Texture,
-    pub shadow_view: wgpu::TextureView,
-    pub light_storage_buf: wgpu::Buffer,
+    /// The point lights, read by the compute pass filling the clusters and by the forward
+    /// shader. The uber shader reads the copy in `clusters`' texture instead, see
+    /// `ClusteredLights`.
+    pub light_storage_buf: Tracked<wgpu::Buffer>,
+    pub clusters: ClusteredLights,
+    /// The environment map lighting the scene besides the lights.
+    pub environment: Environment,
 }
 
 impl Lights {
-    pub const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
-    pub const SHADOW_SIZE: wgpu::Extent3d = wgpu::Extent3d {
-        width: 1,
-        height: 1,
-        depth_or_array_layers: 1,
-    };
-
-    pub fn new(device: &wgpu::Device, lights: Vec<LightObject>) -> Self {
-        let shadow_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
-            size: Self::SHADOW_SIZE,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: Self::SHADOW_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT // 3.
-                | wgpu::TextureUsages::TEXTURE_BINDING,
-        });
+    pub fn new(device: &wgpu::Device, light: Light) -> Self {
+        let light_storage_size =
+            (ClusteredLights::MAX_LIGHTS * mem::size_of::<LightRaw>()) as wgpu::BufferAddress;
+        let light_storage_buf = device.create_tracked_buffer(
+            GpuCategory::Lights,
+            &wgpu::BufferDescriptor {
+                label: Some("Point Light Storage Buffer"),
+                size: light_storage_size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+        let clusters = ClusteredLights::new(device, &light_storage_buf);
+        let environment = Environment::new(device);
+        let light = LightObject::new(device, light, &clusters, &environment, &light_storage_buf);
 
-        let shadow_view = shadow_texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let shadow_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            compare: Some(wgpu::CompareFunction::LessEqual), // 5.
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
-            ..Default::default()
-        });
+        Self {
+            lights: vec![light],
+            light_storage_buf,
+            clusters,
+            environment,
+        }
+    }
 
-        let mut lights = lights;
-        lights.iter_mut().enumerate().for_each(|(i, lo)| {
-            lo.light.shadow_view = Some(shadow_texture.create_view(&wgpu::TextureViewDescriptor {
-                label: Some("shadow"),
-                format: None,
-                dimension: Some(wgpu::TextureViewDimension::D2),
-                aspect: wgpu::TextureAspect::All,
-                base_mip_level: 0,
-                mip_level_count: None,
-                base_array_layer: i as u32,
-                array_layer_count: NonZeroU32::new(1),
-            }))
-        });
+    /// Adds a point light staying where it is, returning its index.
+    pub fn add(&mut self, device: &wgpu::Device, light: Light) -> usize {
+        let mut object = LightObject::new(
+            device,
+            light,
+            &self.clusters,
+            &self.environment,
+            &self.light_storage_buf,
+        );
+        object.orbit = false;
+        self.lights.push(object);
+        self.lights.len() - 1
+    }
 
-        const MAX_LIGHTS: usize = 2;
-        let light_uniform_size =
-            (MAX_LIGHTS * mem::size_of::<LightRaw>()) as wgpu::BufferAddress;
-        let light_storage_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: light_uniform_size,
-            usage: wgpu::BufferUsages::STORAGE
-                | wgpu::BufferUsages::COPY_SRC
-                | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+    /// Uploads the lights after the first for the clusters of the camera's view.
+    pub fn update_clusters(&self, queue: &wgpu::Queue, camera: &Camera) {
+        let lights = self.lights[1..]
+            .iter()
+            .take(ClusteredLights::MAX_LIGHTS)
+            .map(|object| object.light.to_raw())
+            .collect::<Vec<_>>();
+        if self.clusters.culls() && !lights.is_empty() {
+            queue.write_buffer(&self.light_storage_buf, 0, bytemuck::cast_slice(&lights));
+        }
+        self.clusters.update(queue, camera, &lights);
+    }
+}
 
+impl Scene {
+    /// Adds a white point light at the 3D cursor reaching 10 units, returning its index.
+    /// Nothing is added past `ClusteredLights::MAX_LIGHTS` point lights.
+    pub fn add_light(&mut self, device: &wgpu::Device) -> Option<usize> {
+        if self.lights.lights.len() > ClusteredLights::MAX_LIGHTS {
+            return None;
+        }
+        let light = Light::new(
+            self.cursor.position,
+            cgmath::Vector3::new(1., 1., 1.),
+            cgmath::Deg(45.),
+            0.1..10.0,
+        );
+        Some(self.lights.add(device, light))
+    }
 
-        Self {
-            lights,
-            shadow_texture,
-            shadow_view,
-            light_storage_buf,
+    /// Removes a point light. The first light casts the shadows and stays.
+    pub fn remove_light(&mut self, index: usize) {
+        if index == 0 || index >= self.lights.lights.len() {
+            return;
         }
+        self.lights.lights.remove(index);
+        let selected = std::mem::take(&mut self.gizmos.selected);
+        self.gizmos.selected = selected
+            .into_iter()
+            .filter_map(|object| match object {
+                GizmoObject::Light(i) if i == index => None,
+                GizmoObject::Light(i) if i > index => Some(GizmoObject::Light(i - 1)),
+                object => Some(object),
+            })
+            .collect();
     }
 }
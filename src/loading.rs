@@ -0,0 +1,787 @@
+use std::{
+    collections::HashMap,
+    sync::{mpsc, Arc, Mutex, RwLock},
+};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collection::{self, Collection},
+    compression::Compression,
+    events::SceneEvent,
+    gltf_import,
+    model::{compute_tangents, GltfModel, Material, Mesh, Model, ModelVertex, ObjModel},
+    scene::Scene,
+    shader, texture,
+    permutation::ShaderFeatures,
+    streaming::{self, StreamedTexture},
+    poses::AnimationClip,
+    registry::NodeId,
+    scene_graph::LoadedNode,
+    skin::Skin,
+    vertex_layout::{VertexAttribute, VertexData, VertexLayout},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::mesh_cache;
+
+/// What a load is busy with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadStage {
+    /// `total` is only known when the server sends a Content-Length.
+    Downloading {
+        received: u64,
+        total: Option<u64>,
+    },
+    /// Textures and meshes decoded so far, `count` is 0 while the OBJ is parsed.
+    Decoding {
+        done: usize,
+        count: usize,
+    },
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadStatus {
+    /// The file being downloaded or decoded.
+    pub file: String,
+    pub stage: LoadStage,
+}
+
+impl LoadStatus {
+    /// Starting to download `file`.
+    pub fn new(file: String) -> Self {
+        Self {
+            file,
+            stage: LoadStage::Downloading {
+                received: 0,
+                total: None,
+            },
+        }
+    }
+
+    /// From 0 to 1, or `None` when it's not known how much is left.
+    pub fn fraction(&self) -> Option<f32> {
+        match self.stage {
+            LoadStage::Downloading {
+                received,
+                total: Some(total),
+            } if total > 0 => Some((received as f32 / total as f32).min(1.)),
+            LoadStage::Decoding { done, count } if count > 0 => Some(done as f32 / count as f32),
+            _ => None,
+        }
+    }
+}
+
+/// Pixels of a material texture, a single color when the material has no texture for it.
+#[derive(Debug)]
+pub enum Pixels {
+    Image(image::DynamicImage),
+    Color([u8; 4]),
+    /// The texture couldn't be loaded, drawn with the magenta placeholder.
+    Missing,
+    /// The texture at `url`, streamed in after the model is added and drawn in `color` until
+    /// then.
+    Streamed { url: String, color: [u8; 4] },
+}
+
+#[derive(Debug)]
+pub struct LoadedMaterial {
+    pub name: String,
+    pub diffuse: Pixels,
+    pub normal: Pixels,
+    /// The specular color, or with `pbr` roughness in green and metalness in blue.
+    pub specular: Pixels,
+    /// Ambient occlusion in the red channel.
+    pub occlusion: Pixels,
+    pub emissive: Pixels,
+    /// Whether the material is lit as metallic-roughness, see `ShaderFeatures::pbr`.
+    pub pbr: bool,
+}
+
+#[derive(Debug)]
+pub struct LoadedMesh {
+    pub name: String,
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    /// Index into the materials of the model.
+    pub material: Option<usize>,
+    /// Attributes beyond the ones of `ModelVertex`, one per vertex.
+    pub attributes: Option<VertexData>,
+}
+
+/// A material of an OBJ's material library, before its textures are loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ObjMaterial {
+    pub name: String,
+    pub diffuse_texture: String,
+    pub diffuse: [f32; 3],
+    pub normal_texture: String,
+    pub specular_texture: String,
+    pub specular: [f32; 3],
+    pub ambient_texture: String,
+}
+
+impl From<tobj::Material> for ObjMaterial {
+    fn from(material: tobj::Material) -> Self {
+        Self {
+            name: material.name,
+            diffuse_texture: material.diffuse_texture,
+            diffuse: material.diffuse,
+            normal_texture: material.normal_texture,
+            specular_texture: material.specular_texture,
+            specular: material.specular,
+            ambient_texture: material.ambient_texture,
+        }
+    }
+}
+
+/// The geometry and material definitions of an OBJ, what `mesh_cache` keeps of it.
+#[derive(Debug)]
+pub(crate) struct ParsedObj {
+    pub materials: Vec<ObjMaterial>,
+    pub meshes: Vec<LoadedMesh>,
+    pub warnings: Vec<String>,
+}
+
+/// A model decoded on the CPU, waiting to be uploaded.
+#[derive(Debug)]
+pub struct LoadedModel {
+    pub source: String,
+    pub meshes: Vec<LoadedMesh>,
+    pub materials: Vec<LoadedMaterial>,
+    /// The hierarchy placing the meshes, empty for formats without one.
+    pub nodes: Vec<LoadedNode>,
+    /// Animations of the nodes.
+    pub animations: Vec<AnimationClip>,
+    /// The joints bending the skinned meshes.
+    pub skins: Vec<Skin>,
+    /// What was missing or broken, and loaded as well as possible anyway.
+    pub warnings: Vec<String>,
+}
+
+/// Download and decode of an OBJ or glTF model with its materials and textures.
+///
+/// On the web the files are fetched with the page's event loop still running and the decoding
+/// yields to it between textures and meshes, so the GUI keeps drawing. Natively the source is a
+/// file path read on a worker thread.
+#[derive(Debug)]
+pub struct LoadJob {
+    pub source: String,
+    /// Whether the model takes the place of the one loaded from the same source before.
+    pub replace: bool,
+    /// Where an OBJ model is added to as well once it's loaded.
+    pub collection: Option<Arc<RwLock<Collection>>>,
+    status: Arc<Mutex<LoadStatus>>,
+    receiver: mpsc::Receiver<LoadedModel>,
+}
+
+impl LoadJob {
+    /// With `textures`, the OBJ material textures are sent there once the model is done, see
+    /// `streaming`.
+    pub fn spawn(
+        source: String,
+        textures: Option<mpsc::Sender<StreamedTexture>>,
+        cache: Compression,
+    ) -> Self {
+        let status = Arc::new(Mutex::new(LoadStatus::new(source.clone())));
+        let (sender, receiver) = mpsc::channel();
+        let work = {
+            let status = status.clone();
+            let source = source.clone();
+            async move {
+                match load(&source, &status, textures.is_some(), cache).await {
+                    Ok(model) => {
+                        let streamed = streaming::streamed_textures(&model);
+                        // Fails if the job was dropped in the meantime, when nobody wants it.
+                        if sender.send(model).is_ok() && !streamed.is_empty() {
+                            if let Some(textures) = textures {
+                                let fetcher = Fetcher::new(&status).await;
+                                streaming::stream(&fetcher, streamed, textures).await;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("Loading {} failed: {:?}", source, e);
+                        status.lock().unwrap().stage = LoadStage::Failed(format!("{:#}", e));
+                    }
+                }
+            }
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(move || futures::executor::block_on(work));
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(work);
+
+        Self {
+            source,
+            replace: false,
+            collection: None,
+            status,
+            receiver,
+        }
+    }
+
+    pub fn status(&self) -> LoadStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self.status.lock().unwrap().stage, LoadStage::Failed(_))
+    }
+}
+
+pub(crate) fn set_stage(status: &Mutex<LoadStatus>, file: &str, stage: LoadStage) {
+    let mut status = status.lock().unwrap();
+    status.file = file.to_string();
+    status.stage = stage;
+}
+
+/// `name` next to `base`, which is a URL or a path.
+pub(crate) fn sibling(base: &str, name: &str) -> String {
+    match base.rfind(|c| c == '/' || c == '\\') {
+        Some(i) => format!("{}{}", &base[..=i], name),
+        None => name.to_string(),
+    }
+}
+
+/// Downloads the files of a model, or reads them natively, reporting the progress.
+pub(crate) struct Fetcher<'a> {
+    status: &'a Mutex<LoadStatus>,
+    /// Where the web build keeps downloads between visits, if the browser allows it.
+    #[cfg(target_arch = "wasm32")]
+    cache: Option<crate::cache::AssetCache>,
+}
+
+impl<'a> Fetcher<'a> {
+    pub(crate) async fn new(status: &'a Mutex<LoadStatus>) -> Fetcher<'a> {
+        Self {
+            status,
+            #[cfg(target_arch = "wasm32")]
+            cache: crate::cache::AssetCache::open()
+                .await
+                .map_err(|e| log::warn!("Downloads won't be cached: {:?}", e))
+                .ok(),
+        }
+    }
+
+    fn report(&self, file: &str, received: u64, total: Option<u64>) {
+        set_stage(
+            self.status,
+            file,
+            LoadStage::Downloading { received, total },
+        );
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) async fn file(&self, url: &str) -> Result<Vec<u8>> {
+        use crate::web::{fetch_with_progress, Fetched};
+
+        let report = |received, total| self.report(url, received, total);
+        match &self.cache {
+            Some(cache) => cache.fetch(url, &report).await,
+            None => match fetch_with_progress(url, None, &report).await? {
+                Fetched::Downloaded { data, .. } => Ok(data),
+                Fetched::NotModified => bail!("{} not modified without asking", url),
+            },
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) async fn image(&self, url: &str) -> Result<image::DynamicImage> {
+        match &self.cache {
+            Some(cache) => {
+                let report = |received, total| self.report(url, received, total);
+                cache.fetch_image(url, &report).await
+            }
+            None => Ok(image::load_from_memory(&self.file(url).await?)?),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) async fn file(&self, path: &str) -> Result<Vec<u8>> {
+        use std::io::Read;
+
+        let mut file = std::fs::File::open(path).with_context(|| format!("Can't open {}", path))?;
+        let total = file.metadata().ok().map(|metadata| metadata.len());
+        let mut data = Vec::new();
+        let mut chunk = vec![0; 1 << 16];
+        loop {
+            let read = file.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            data.extend_from_slice(&chunk[..read]);
+            self.report(path, data.len() as u64, total);
+        }
+        Ok(data)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) async fn image(&self, path: &str) -> Result<image::DynamicImage> {
+        Ok(image::load_from_memory(&self.file(path).await?)?)
+    }
+}
+
+/// Lets the page handle events between pieces of decoding.
+pub(crate) async fn yield_now() {
+    #[cfg(target_arch = "wasm32")]
+    crate::web::yield_now().await;
+}
+
+/// A texture of the material next to the model, `fallback` when there is none, or missing when it
+/// can't be loaded. Streamed textures are only looked for later.
+async fn load_texture(
+    fetcher: &Fetcher<'_>,
+    source: &str,
+    name: &str,
+    fallback: [f32; 3],
+    stream: bool,
+    warnings: &mut Vec<String>,
+) -> Pixels {
+    let [r, g, b] = fallback.map(|c| (c * 255.) as u8);
+    if name.is_empty() {
+        return Pixels::Color([r, g, b, 0xff]);
+    }
+    let url = sibling(source, name);
+    if stream {
+        let color = [r, g, b, 0xff];
+        return Pixels::Streamed { url, color };
+    }
+    match fetcher.image(&url).await {
+        Ok(image) => Pixels::Image(image),
+        Err(e) => {
+            log::warn!("Texture {} not loaded: {:?}", url, e);
+            warnings.push(format!("Texture {} not loaded: {:#}", url, e));
+            Pixels::Missing
+        }
+    }
+}
+
+/// Reads the OBJ or glTF model at `source` with its materials and textures, reporting the
+/// progress to `status`. Only fails when the model itself can't be read. With `stream` the OBJ
+/// material textures are left to stream in. Natively OBJ files are cached compressed as `cache`
+/// says, see `mesh_cache`.
+pub async fn load(
+    source: &str,
+    status: &Mutex<LoadStatus>,
+    stream: bool,
+    cache: Compression,
+) -> Result<LoadedModel> {
+    let fetcher = Fetcher::new(status).await;
+    if gltf_import::is_gltf(source) {
+        return gltf_import::load(&fetcher, source, status).await;
+    }
+    let ParsedObj {
+        materials: obj_materials,
+        meshes,
+        mut warnings,
+    } = parse_obj(&fetcher, source, status, cache).await?;
+
+    let count = obj_materials.len();
+    let mut done = 0;
+    let mut materials = Vec::new();
+    for mat in obj_materials {
+        let w = &mut warnings;
+        let (f, s) = (&fetcher, stream);
+        let diffuse = load_texture(f, source, &mat.diffuse_texture, mat.diffuse, s, w).await;
+        let normal = if mat.normal_texture.is_empty() {
+            Pixels::Color([0x80, 0x80, 0xff, 0])
+        } else {
+            load_texture(f, source, &mat.normal_texture, [0.5, 0.5, 1.], s, w).await
+        };
+        let specular = load_texture(f, source, &mat.specular_texture, mat.specular, s, w).await;
+        // OBJ has no occlusion map, the ambient map is the closest.
+        let occlusion = load_texture(f, source, &mat.ambient_texture, [1.; 3], s, w).await;
+        materials.push(LoadedMaterial {
+            name: mat.name,
+            diffuse,
+            normal,
+            specular,
+            occlusion,
+            emissive: Pixels::Color([0, 0, 0, 0xff]),
+            pbr: false,
+        });
+        done += 1;
+        set_stage(status, source, LoadStage::Decoding { done, count });
+        yield_now().await;
+    }
+
+    Ok(LoadedModel {
+        source: source.to_string(),
+        meshes,
+        materials,
+        nodes: Vec::new(),
+        animations: Vec::new(),
+        skins: Vec::new(),
+        warnings,
+    })
+}
+
+/// Parses the OBJ at `source` and the material libraries it names, or natively reads it from
+/// the mesh cache while that's up to date, caching it otherwise.
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+async fn parse_obj(
+    fetcher: &Fetcher<'_>,
+    source: &str,
+    status: &Mutex<LoadStatus>,
+    cache: Compression,
+) -> Result<ParsedObj> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(parsed) = mesh_cache::read(source) {
+        return Ok(parsed);
+    }
+    let obj = fetcher.file(source).await?;
+    let mut warnings = Vec::new();
+
+    // The parser can't wait for downloads, so the material libraries are fetched up front.
+    let mut libraries = HashMap::new();
+    // All of them, found or not, so the cache notices one turning up.
+    let mut library_files = Vec::new();
+    for line in String::from_utf8_lossy(&obj).lines() {
+        let mut words = line.split_whitespace();
+        if let (Some("mtllib"), Some(name)) = (words.next(), words.next()) {
+            library_files.push(sibling(source, name));
+            match fetcher.file(&sibling(source, name)).await {
+                Ok(bytes) => {
+                    libraries.insert(name.to_string(), bytes);
+                }
+                Err(e) => {
+                    log::warn!("Material library {} not loaded: {:?}", name, e);
+                    warnings.push(format!("Material library {} not loaded: {:#}", name, e));
+                }
+            }
+        }
+    }
+
+    set_stage(status, source, LoadStage::Decoding { done: 0, count: 0 });
+    yield_now().await;
+    let (obj_models, obj_materials) = {
+        profile_scope!("parse OBJ");
+        tobj::load_obj_buf(
+            &mut obj.as_slice(),
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+            |path| match libraries.get(path.to_string_lossy().as_ref()) {
+                Some(bytes) => tobj::load_mtl_buf(&mut bytes.as_slice()),
+                None => Err(tobj::LoadError::OpenFileFailed),
+            },
+        )
+        .with_context(|| format!("Can't parse {}", source))?
+    };
+    let materials = obj_materials
+        .unwrap_or_default()
+        .into_iter()
+        .map(ObjMaterial::from)
+        .collect::<Vec<_>>();
+
+    let count = obj_models.len();
+    let mut done = 0;
+    let mut meshes = Vec::new();
+    for m in obj_models {
+        let mesh = m.mesh;
+        if !mesh.positions.is_empty() {
+            if mesh.texcoords.is_empty() {
+                warnings.push(format!("Mesh {} has no texture coordinates", m.name));
+            }
+            if mesh.normals.is_empty() {
+                warnings.push(format!("Mesh {} has no normals", m.name));
+            }
+        }
+        if let Some(id) = mesh.material_id.filter(|&id| id >= materials.len()) {
+            warnings.push(format!("Mesh {} uses the missing material {}", m.name, id));
+        }
+        let mut vertices = (0..mesh.positions.len() / 3)
+            .map(|i| ModelVertex {
+                position: [
+                    mesh.positions[i * 3],
+                    mesh.positions[i * 3 + 1],
+                    mesh.positions[i * 3 + 2],
+                ],
+                tex_coords: match mesh.texcoords.get(i * 2..i * 2 + 2) {
+                    Some(uv) => [uv[0], 1.0 - uv[1]],
+                    None => [0.0; 2],
+                },
+                normal: match mesh.normals.get(i * 3..i * 3 + 3) {
+                    Some(n) => [n[0], n[1], n[2]],
+                    None => [0.0, 1.0, 0.0],
+                },
+                tangent: [0.0; 3],
+                bitangent: [0.0; 3],
+            })
+            .collect::<Vec<_>>();
+        compute_tangents(&mut vertices, &mesh.indices);
+        // Colors written after the positions, `v x y z r g b`.
+        let attributes = if mesh.vertex_color.len() == mesh.positions.len() {
+            let layout = VertexLayout::builder().color().build()?;
+            let mut data = VertexData::new(layout, vertices.len());
+            for (i, color) in mesh.vertex_color.chunks_exact(3).enumerate() {
+                data.set(i, &VertexAttribute::Color, color);
+            }
+            Some(data)
+        } else {
+            None
+        };
+        meshes.push(LoadedMesh {
+            name: m.name,
+            vertices,
+            indices: mesh.indices,
+            material: mesh.material_id.filter(|&id| id < materials.len()),
+            attributes,
+        });
+        done += 1;
+        set_stage(status, source, LoadStage::Decoding { done, count });
+        yield_now().await;
+    }
+
+    let parsed = ParsedObj {
+        materials,
+        meshes,
+        warnings,
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Err(e) = mesh_cache::write(source, &library_files, &parsed, cache) {
+        log::warn!("{} not cached: {:?}", source, e);
+    }
+    Ok(parsed)
+}
+
+impl Scene {
+    /// Starts loading an OBJ or glTF model from a URL on the web or a path natively, added to the
+    /// scene once it's decoded.
+    pub fn start_load(&mut self, source: String) {
+        let textures = self.texture_stream.sender();
+        self.load_jobs
+            .push(LoadJob::spawn(source, textures, self.compression));
+    }
+
+    /// Like `start_load`, adding the model to `collection` as well if it's an OBJ.
+    pub fn start_collected_load(&mut self, source: String, collection: Arc<RwLock<Collection>>) {
+        let textures = self.texture_stream.sender();
+        let mut job = LoadJob::spawn(source, textures, self.compression);
+        job.collection = Some(collection);
+        self.load_jobs.push(job);
+    }
+
+    /// Loads the model again, e.g. after its file changed, replacing the one loaded from
+    /// `source` before. A reload of it still running is dropped.
+    pub fn start_reload(&mut self, source: String) {
+        self.load_jobs
+            .retain(|job| !(job.replace && job.source == source));
+        let textures = self.texture_stream.sender();
+        let mut job = LoadJob::spawn(source, textures, self.compression);
+        job.replace = true;
+        self.load_jobs.push(job);
+    }
+
+    /// Forgets a failed load.
+    pub fn dismiss_load(&mut self, job: usize) {
+        self.load_jobs.remove(job);
+    }
+
+    /// Whether a load is still downloading or decoding.
+    pub fn is_loading(&self) -> bool {
+        self.load_jobs.iter().any(|job| !job.is_failed())
+    }
+
+    /// Uploads the models of finished loads and adds them to the scene. Failed loads stay until
+    /// dismissed so their error can be shown.
+    pub fn poll_loads(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+    ) {
+        profile_function!();
+        let mut finished = Vec::new();
+        self.load_jobs.retain(|job| match job.receiver.try_recv() {
+            Ok(model) => {
+                finished.push((job.replace, job.collection.clone(), model));
+                false
+            }
+            Err(mpsc::TryRecvError::Empty) => true,
+            Err(mpsc::TryRecvError::Disconnected) => job.is_failed(),
+        });
+
+        for (replace, collection, mut loaded) in finished {
+            let source = loaded.source.clone();
+            if let Some(collection) = collection.filter(|_| !gltf_import::is_gltf(&source)) {
+                let model = collection::ObjModel::from_loaded(&loaded);
+                let model = Arc::new(collection::Model::OBJ(model));
+                collection.write().unwrap().add_model(model, &source);
+            }
+            let nodes = std::mem::take(&mut loaded.nodes);
+            let animations = std::mem::take(&mut loaded.animations);
+            let skins = std::mem::take(&mut loaded.skins);
+            self.texture_stream.pending += streaming::streamed_textures(&loaded).len();
+            let model = self.upload_model(device, queue, config, loaded);
+            let previous = self
+                .sources
+                .iter()
+                .find(|(_, previous)| **previous == source)
+                .map(|(&i, _)| i)
+                .filter(|_| replace);
+            let node = match previous {
+                Some(i) => {
+                    self.replace_model(i, model);
+                    NodeId(i)
+                }
+                None => {
+                    self.sources.insert(self.models.len(), source.clone());
+                    self.add_model(model)
+                }
+            };
+            let name = source.rsplit(|c| c == '/' || c == '\\').next().unwrap_or(&source);
+            self.add_loaded_nodes(node.0, name.to_string(), nodes);
+            self.poses.set_clips(node.0, animations);
+            self.set_skins(node.0, skins);
+            self.events.push(SceneEvent::ModelLoaded {
+                model: node,
+                source,
+            });
+        }
+        self.apply_pending_project();
+    }
+
+    /// Puts `model` in the place of model `index`, keeping its place in the outliner.
+    pub(crate) fn replace_model(&mut self, index: usize, model: Model) {
+        self.cancel_remesh(index);
+        self.cancel_cleanup(index);
+        if self.edit.target_model() == Some(index) {
+            self.edit.release_target();
+        }
+        let ids = self.models[index].buffer_ids();
+        self.memory
+            .expect_freed(format!("the model replaced at {}", index), ids);
+        self.models[index] = model;
+        self.topology_changed(index);
+    }
+
+    /// The permutation of the model shader with `features`, made for meshes with the extra
+    /// attributes of `layout` if given. Compiled once and shared by everything needing the same.
+    pub(crate) fn model_shader(
+        &self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        features: ShaderFeatures,
+        layout: Option<&VertexLayout>,
+    ) -> Arc<shader::Shader> {
+        let features = features.compiled(self.shader_strategy);
+        let filename = std::path::Path::new(env!("OUT_DIR")).join("shader");
+        let mut shader_key = format!("{}{}", filename.to_string_lossy(), features.key());
+        if let Some(layout) = layout {
+            shader_key = format!("{}#{}", shader_key, layout.key());
+        }
+        self.shaders
+            .write()
+            .unwrap()
+            .entry(shader_key)
+            .or_insert_with(|| {
+                Arc::new(shader::Shader::with_features(
+                    "obj vertex shader",
+                    filename,
+                    device,
+                    &self.renderer.texture_bind_group_layout,
+                    &self.lights.lights[0].bind_group_layout,
+                    &self.renderer.uniforms.bind_group_layout,
+                    &self.renderer.shadows.bind_group_layout,
+                    &config.format,
+                    self.renderer.config.depth_format.format(),
+                    features,
+                    layout,
+                ))
+            })
+            .clone()
+    }
+
+    pub(crate) fn upload_model(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        loaded: LoadedModel,
+    ) -> Model {
+        let upload = |pixels: &Pixels, label: &str, is_normal_map: bool| match pixels {
+            Pixels::Image(image) => {
+                texture::Texture::from_image(device, queue, image, Some(label), is_normal_map)
+                    .map(Arc::new)
+                    .unwrap_or_else(|_| self.placeholders.missing.clone())
+            }
+            Pixels::Color(color) => Arc::new(texture::Texture::one_pixel(
+                device,
+                queue,
+                color,
+                Some(label),
+                true,
+            )),
+            Pixels::Missing => self.placeholders.missing.clone(),
+            Pixels::Streamed { color, .. } => Arc::new(texture::Texture::one_pixel(
+                device,
+                queue,
+                color,
+                Some(label),
+                true,
+            )),
+        };
+        let material = |i: usize, loaded: &LoadedMaterial| {
+            let features = ShaderFeatures {
+                normal_map: matches!(loaded.normal, Pixels::Image(_) | Pixels::Streamed { .. }),
+                double_sided: !self.renderer.config.cull_back_faces,
+                pbr: loaded.pbr,
+                ..Default::default()
+            };
+            Arc::new(Material::new(
+                device,
+                &loaded.name,
+                upload(&loaded.diffuse, "diffuse texture", false),
+                upload(&loaded.normal, "normal texture", true),
+                // Metalness and roughness are linear, like normals.
+                upload(&loaded.specular, "specular texture", loaded.pbr),
+                upload(&loaded.occlusion, "occlusion texture", false),
+                upload(&loaded.emissive, "emissive texture", false),
+                features,
+                i as u32,
+                &self.renderer.texture_bind_group_layout,
+                self.model_shader(device, config, features, None),
+            ))
+        };
+
+        let mut materials = Vec::new();
+        for (i, loaded_material) in loaded.materials.iter().enumerate() {
+            let material = material(i, loaded_material);
+            self.materials.write().unwrap().insert(
+                format!("{}:{}", loaded.source, loaded_material.name),
+                material.clone(),
+            );
+            materials.push(material);
+        }
+        let fallback = self.default_material(device, config);
+
+        let meshes = loaded
+            .meshes
+            .into_iter()
+            .map(|mesh| {
+                let material = match mesh.material {
+                    Some(i) => materials[i].clone(),
+                    None => fallback.clone(),
+                };
+                let mut uploaded =
+                    Mesh::new(device, mesh.name, mesh.vertices, mesh.indices, material);
+                if let Some(data) = mesh.attributes {
+                    let features = uploaded.material.features.with_layout(&data.layout);
+                    let shader = self.model_shader(device, config, features, Some(&data.layout));
+                    uploaded.set_extra(device, data, shader);
+                }
+                uploaded
+            })
+            .collect();
+        if gltf_import::is_gltf(&loaded.source) {
+            Model::GLTF(GltfModel { meshes, materials })
+        } else {
+            Model::OBJ(ObjModel { meshes })
+        }
+    }
+}
@@ -1,7 +1,15 @@
-use modeling::{gui, state};
+use modeling::{
+    app::run,
+    bake, bench, gui, headless, info, project, recording,
+    render_config::{self, DepthFormat, RenderConfig},
+    state, surface,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use modeling::{bundle, watch};
 use winit::{
+    dpi::PhysicalSize,
     event_loop::EventLoop,
-    window::{Window, WindowBuilder},
+    window::WindowBuilder,
 };
 
 use instant::Instant;
@@ -13,31 +21,230 @@ use structopt::StructOpt;
 #[derive(StructOpt, Debug)]
 #[structopt(name = "basic")]
 struct Opt {
+    /// OBJ or glTF model to load, .gltf or .glb
     #[structopt(name = "FILE", parse(from_os_str))]
     files: Option<PathBuf>,
+
+    /// Record input events to a session file
+    #[structopt(long, parse(from_os_str))]
+    record: Option<PathBuf>,
+
+    /// Replay a session file recorded with --record
+    #[structopt(long, parse(from_os_str), conflicts_with = "record")]
+    replay: Option<PathBuf>,
+
+    /// Save every frame rendered during --replay as PNG into this directory
+    #[structopt(long, parse(from_os_str), requires = "replay")]
+    dump_frames: Option<PathBuf>,
+
+    /// Fly a fixed camera path for the given number of seconds and write a performance report
+    #[structopt(long, conflicts_with = "replay")]
+    bench: Option<f32>,
+
+    /// Benchmark report path, written as CSV if it ends with .csv and as JSON otherwise
+    #[structopt(long, parse(from_os_str), default_value = "bench.json")]
+    bench_output: PathBuf,
+
+    /// Open a project file saved from the outliner, restoring its groups, or a .modpack bundle
+    /// exported from it, loading its models too
+    #[structopt(long, parse(from_os_str))]
+    project: Option<PathBuf>,
+
+    /// Render FILE once to this image without opening a window and exit, framed from the
+    /// default camera's direction with the renderer settings below; the format is picked by the
+    /// extension
+    #[structopt(long, parse(from_os_str), requires = "FILE")]
+    render_to: Option<PathBuf>,
+
+    /// Width and height of the --render-to image, as WIDTHxHEIGHT
+    #[structopt(long, parse(try_from_str = headless::parse_size), default_value = "1280x720")]
+    size: (u32, u32),
+
+    /// Load the OBJ and glTF models in this folder, and load them again whenever they change
+    #[structopt(long, parse(from_os_str))]
+    watch: Option<PathBuf>,
+
+    /// Configure the window surface with this format instead of the adapter's preferred one,
+    /// e.g. bgra8unorm to check the sRGB encoding fallback
+    #[structopt(long, parse(try_from_str = surface::parse_format))]
+    surface_format: Option<wgpu::TextureFormat>,
+
+    /// Read the renderer settings from this RON file, the options below override it
+    #[structopt(long, parse(from_os_str))]
+    render_config: Option<PathBuf>,
+
+    /// Color the scene is drawn over as r,g,b or r,g,b,a
+    #[structopt(long, parse(try_from_str = render_config::parse_color))]
+    clear_color: Option<[f32; 4]>,
+
+    /// Format of the depth buffer: depth32float, depth24plus or depth24plus-stencil8
+    #[structopt(long, parse(try_from_str = DepthFormat::parse))]
+    depth_format: Option<DepthFormat>,
+
+    /// Frames per second at most, 0 for no cap
+    #[structopt(long)]
+    frame_cap: Option<u32>,
+
+    /// Width and height of the shadow map
+    #[structopt(long)]
+    shadow_map_size: Option<u32>,
+
+    /// Skip drawing the back faces of models
+    #[structopt(long)]
+    cull_back_faces: bool,
+
+    /// Bake normal, ambient occlusion and curvature maps onto the UVs of this low poly OBJ and exit
+    #[structopt(long, parse(from_os_str), requires = "bake-high")]
+    bake_low: Option<PathBuf>,
+
+    /// High poly OBJ providing the surface detail for --bake-low
+    #[structopt(long, parse(from_os_str), requires = "bake-low")]
+    bake_high: Option<PathBuf>,
+
+    /// Directory the baked maps are written to
+    #[structopt(long, parse(from_os_str), default_value = "bake")]
+    bake_output: PathBuf,
+
+    /// Width and height of the baked maps
+    #[structopt(long, default_value = "1024")]
+    bake_size: u32,
+
+    /// How far apart the low and high poly surfaces may be
+    #[structopt(long, default_value = "0.1")]
+    bake_distance: f32,
+
+    /// Ambient occlusion rays per texel, 0 to skip
+    #[structopt(long, default_value = "16")]
+    bake_ao_samples: u32,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-async fn run(
-    event_loop: EventLoop<gui::Event>,
-    window: Window,
-    swapchain_format: wgpu::TextureFormat,
-) {
-    let mut state = state::State::new(&window, swapchain_format, &event_loop).await;
-
-    let start_time = Instant::now();
-    let mut previous_frame_time = None;
-    let mut last_update_inst = Instant::now();
-    event_loop.run(move |event, _, control_flow| {
-        state.gui.handle_event(&event);
-        state.handle_event(
-            &event,
-            control_flow,
-            &window,
-            start_time,
-            &mut last_update_inst,
-            &mut previous_frame_time,
-        );
-    });
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Print the meshes, materials, textures and bounds of a model and what's wrong with it,
+    /// without opening a window
+    Info {
+        #[structopt(name = "MODEL")]
+        file: String,
+
+        /// Print JSON instead of text
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Render the models, lights and camera of a RON scene file to an image, without opening a
+    /// window
+    Render {
+        #[structopt(name = "SCENE", parse(from_os_str))]
+        scene: PathBuf,
+
+        /// Image the render is written to, its format picked by the extension
+        #[structopt(short, long, parse(from_os_str), default_value = "render.png")]
+        output: PathBuf,
+    },
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn run_info(file: &str, json: bool) -> anyhow::Result<()> {
+    let info = info::ModelInfo::load(file)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        print!("{}", info.to_text());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn run_render(scene: &std::path::Path, output: &std::path::Path) -> anyhow::Result<()> {
+    let desc = headless::SceneDesc::load(scene)?;
+    let start = Instant::now();
+    let image = headless::render_scene_to_image(&desc)?;
+    image.save(output)?;
+    log::info!(
+        "Rendered {:?} in {:.2}s, written to {:?}",
+        scene,
+        start.elapsed().as_secs_f32(),
+        output
+    );
+    Ok(())
+}
+
+/// Renders FILE as `--render-to` asks.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_render_to(
+    opt: &Opt,
+    model: &std::path::Path,
+    output: &std::path::Path,
+) -> anyhow::Result<()> {
+    let desc = headless::SceneDesc {
+        models: vec![headless::ModelDesc::new(model)],
+        size: opt.size,
+        render: render_config(opt)?,
+        frame_models: true,
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let image = headless::render_scene_to_image(&desc)?;
+    image.save(output)?;
+    log::info!(
+        "Rendered {:?} in {:.2}s, written to {:?}",
+        model,
+        start.elapsed().as_secs_f32(),
+        output
+    );
+    Ok(())
+}
+
+/// The render config file if given, with the command line options applied on top.
+#[cfg(not(target_arch = "wasm32"))]
+fn render_config(opt: &Opt) -> anyhow::Result<RenderConfig> {
+    let mut config = match &opt.render_config {
+        Some(path) => RenderConfig::load(path)?,
+        None => RenderConfig::default(),
+    };
+    if let Some(color) = opt.clear_color {
+        config.clear_color = color;
+    }
+    if let Some(format) = opt.depth_format {
+        config.depth_format = format;
+    }
+    if let Some(fps) = opt.frame_cap {
+        config.frame_cap = Some(fps).filter(|&fps| fps > 0);
+    }
+    if let Some(size) = opt.shadow_map_size {
+        config.shadow_map_size = size;
+    }
+    config.cull_back_faces |= opt.cull_back_faces;
+    Ok(config)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn run_bake(opt: &Opt, low: &std::path::Path, high: &std::path::Path) -> anyhow::Result<()> {
+    let settings = bake::BakeSettings {
+        size: opt.bake_size,
+        max_distance: opt.bake_distance,
+        ao_samples: opt.bake_ao_samples,
+        ..Default::default()
+    };
+    let low_mesh = bake::BakeMesh::load_obj(low)?;
+    let high_mesh = bake::BakeMesh::load_obj(high)?;
+    let start = Instant::now();
+    let maps = bake::bake(&low_mesh, &high_mesh, &settings);
+    let prefix = low
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "baked".to_string());
+    maps.save(&opt.bake_output, &prefix)?;
+    log::info!(
+        "Baked {:?} onto {:?} in {:.2}s, written to {:?}",
+        high,
+        low,
+        start.elapsed().as_secs_f32(),
+        opt.bake_output
+    );
+    Ok(())
 }
 
 fn main() {
@@ -45,18 +252,117 @@ fn main() {
     let opt = Opt::from_args();
     #[cfg(not(target_arch = "wasm32"))]
     env_logger::init();
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(command) = &opt.command {
+        let result = match command {
+            Command::Info { file, json } => run_info(file, *json),
+            Command::Render { scene, output } => run_render(scene, output),
+        };
+        if let Err(e) = result {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let (Some(output), Some(model)) = (&opt.render_to, &opt.files) {
+        if let Err(e) = run_render_to(&opt, model, output) {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let (Some(low), Some(high)) = (&opt.bake_low, &opt.bake_high) {
+        if let Err(e) = run_bake(&opt, low, high) {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    let render_config = match render_config(&opt) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+    };
     let event_loop: EventLoop<gui::Event> = EventLoop::with_user_event();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
     #[cfg(not(target_arch = "wasm32"))]
     {
         //wgpu_subscriber::initialize_default_subscriber(None);
-        // Temporarily avoid srgb formats for the swapchain on the web
+
+        let session = if let Some(path) = opt.replay {
+            let player = match recording::Player::load(path, opt.dump_frames) {
+                Ok(player) => player,
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    std::process::exit(1);
+                }
+            };
+            // Replays have to run at the recorded size to be deterministic.
+            let (width, height) = player.recording.size;
+            window.set_inner_size(PhysicalSize::new(width, height));
+            Some(recording::Session::Replay(player))
+        } else {
+            opt.record.map(|path| {
+                recording::Session::Record(recording::Recorder::new(
+                    path,
+                    window.inner_size(),
+                    state::State::TIMESTEP,
+                ))
+            })
+        };
+
+        let bench_output = opt.bench_output;
+        let benchmark = opt
+            .bench
+            .map(|seconds| bench::Benchmark::new(seconds, bench_output));
+        let bundle_extension = Some(std::ffi::OsStr::new(bundle::EXTENSION));
+        let project = match opt.project {
+            Some(path) if path.extension() == bundle_extension => {
+                event_loop
+                    .create_proxy()
+                    .send_event(gui::Event::OpenBundle(path))
+                    .ok();
+                None
+            }
+            Some(path) => match project::Project::load(path) {
+                Ok(project) => Some(project),
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    std::process::exit(1);
+                }
+            },
+            None => None,
+        };
+        if let Some(path) = opt.files {
+            let source = path.to_string_lossy().into_owned();
+            event_loop
+                .create_proxy()
+                .send_event(gui::Event::LoadModel(source))
+                .ok();
+        }
+        if let Some(dir) = opt.watch {
+            watch::FolderWatch::new(dir).spawn(event_loop.create_proxy());
+        }
+        let surface_format = opt.surface_format;
 
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
-            //run(event_loop, window, wgpu::TextureFormat::Bgra8UnormSrgb).await;
-            run(event_loop, window, wgpu::TextureFormat::Bgra8UnormSrgb).await;
+            run(
+                event_loop,
+                window,
+                surface_format,
+                render_config,
+                session,
+                benchmark,
+                project,
+            )
+            .await;
         })
     }
     #[cfg(target_arch = "wasm32")]
@@ -69,6 +375,10 @@ fn main() {
             .unwrap_or(log::Level::Error);
         console_log::init_with_level(level).expect("could not initialize logger");
         std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+        // index.html picks the build, this only explains a mismatch.
+        if !cfg!(feature = "webgl") && !modeling::web::webgpu_available() {
+            log::error!("This browser has no WebGPU, load the WebGL2 build instead");
+        }
         // On wasm, append the canvas to the document body
         web_sys::window()
             .and_then(|win| win.document())
@@ -80,7 +390,7 @@ fn main() {
             .expect("couldn't append canvas to document body");
         use wasm_bindgen::{prelude::*, JsCast};
         wasm_bindgen_futures::spawn_local(async move {
-            run(event_loop, window, wgpu::TextureFormat::Bgra8UnormSrgb).await;
+            run(event_loop, window, None, RenderConfig::default(), None, None, None).await;
         });
     }
 }
@@ -0,0 +1,5 @@
+//! Mesh representations for editing, converted from and to the flat vertex and index buffers
+//! the renderer draws.
+
+pub mod selection;
+pub mod topology;
@@ -0,0 +1,322 @@
+use std::collections::BTreeSet;
+
+use cgmath::{EuclideanSpace, Point3};
+
+use super::topology::HalfEdgeMesh;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectMode {
+    Vertex,
+    Edge,
+    Face,
+}
+
+impl SelectMode {
+    pub const ALL: [SelectMode; 3] = [SelectMode::Vertex, SelectMode::Edge, SelectMode::Face];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SelectMode::Vertex => "Vertex",
+            SelectMode::Edge => "Edge",
+            SelectMode::Face => "Face",
+        }
+    }
+}
+
+impl Default for SelectMode {
+    fn default() -> Self {
+        SelectMode::Edge
+    }
+}
+
+/// A vertex, an edge as the vertex pair with the smaller index first, or a face.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Element {
+    Vertex(u32),
+    Edge([u32; 2]),
+    Face(u32),
+}
+
+impl Element {
+    pub fn edge(a: u32, b: u32) -> Self {
+        Element::Edge([a.min(b), a.max(b)])
+    }
+}
+
+/// Selected elements of a polygon mesh, all of the kind the mode selects.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    mode: SelectMode,
+    elements: BTreeSet<Element>,
+}
+
+impl Selection {
+    pub fn mode(&self) -> SelectMode {
+        self.mode
+    }
+
+    /// Switches the kind of element selected, keeping what the old selection covers.
+    pub fn set_mode(&mut self, mode: SelectMode, mesh: &HalfEdgeMesh) {
+        if mode == self.mode {
+            return;
+        }
+        let vertices = self.vertices(mesh);
+        self.elements = match mode {
+            SelectMode::Vertex => vertices.into_iter().map(Element::Vertex).collect(),
+            SelectMode::Edge => match self.mode {
+                SelectMode::Face => self.edges(mesh).into_iter().map(Element::Edge).collect(),
+                _ => edges_within(mesh, &vertices),
+            },
+            SelectMode::Face => faces_within(mesh, &vertices),
+        };
+        self.mode = mode;
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.elements.clear();
+    }
+
+    pub fn contains(&self, element: &Element) -> bool {
+        self.elements.contains(element)
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> + '_ {
+        self.elements.iter()
+    }
+
+    /// Selects the element, or deselects it when already selected. Elements of another kind
+    /// than the mode selects are ignored.
+    pub fn toggle(&mut self, element: Element) {
+        if self.accepts(&element) && !self.elements.remove(&element) {
+            self.elements.insert(element);
+        }
+    }
+
+    /// Selects the edges, or what they cover in vertex and face mode.
+    pub fn select_edges(&mut self, mesh: &HalfEdgeMesh, edges: &[[u32; 2]]) {
+        let edges = edges.iter().map(|&[a, b]| Element::edge(a, b));
+        match self.mode {
+            SelectMode::Edge => self.elements.extend(edges),
+            SelectMode::Vertex | SelectMode::Face => {
+                let mut vertices = self.vertices(mesh);
+                for edge in edges {
+                    if let Element::Edge(pair) = edge {
+                        vertices.extend(pair);
+                    }
+                }
+                self.elements.extend(match self.mode {
+                    SelectMode::Vertex => vertices.into_iter().map(Element::Vertex).collect(),
+                    _ => faces_within(mesh, &vertices),
+                });
+            }
+        }
+    }
+
+    /// Selects the elements `inside` says are in the region, vertices by their position, edges
+    /// by both ends and faces by their center.
+    pub fn select_inside(&mut self, mesh: &HalfEdgeMesh, inside: impl Fn(Point3<f32>) -> bool) {
+        let elements = all_elements(mesh, self.mode)
+            .into_iter()
+            .filter(|element| match *element {
+                Element::Vertex(v) => inside(mesh.position(v)),
+                Element::Edge([a, b]) => inside(mesh.position(a)) && inside(mesh.position(b)),
+                Element::Face(f) => inside(face_center(mesh, f)),
+            })
+            .collect::<Vec<_>>();
+        self.elements.extend(elements);
+    }
+
+    /// Extends the selection to everything connected to it.
+    pub fn select_linked(&mut self, mesh: &HalfEdgeMesh) {
+        let mut vertices = self.vertices(mesh);
+        let mut stack = vertices.iter().copied().collect::<Vec<_>>();
+        while let Some(v) = stack.pop() {
+            for n in neighbours(mesh, v) {
+                if vertices.insert(n) {
+                    stack.push(n);
+                }
+            }
+        }
+        self.elements = self.from_vertices(mesh, &vertices);
+    }
+
+    pub fn invert(&mut self, mesh: &HalfEdgeMesh) {
+        self.elements = all_elements(mesh, self.mode)
+            .into_iter()
+            .filter(|element| !self.elements.contains(element))
+            .collect();
+    }
+
+    /// Adds the elements sharing a vertex with the selection.
+    pub fn grow(&mut self, mesh: &HalfEdgeMesh) {
+        let vertices = self.vertices(mesh);
+        let grown = all_elements(mesh, self.mode)
+            .into_iter()
+            .filter(|element| match self.mode {
+                SelectMode::Vertex => element_vertices(mesh, element)
+                    .into_iter()
+                    .any(|v| neighbours(mesh, v).iter().any(|n| vertices.contains(n))),
+                _ => element_vertices(mesh, element)
+                    .iter()
+                    .any(|v| vertices.contains(v)),
+            })
+            .collect::<Vec<_>>();
+        self.elements.extend(grown);
+    }
+
+    /// Removes the elements at the border of the selection, the ones sharing a vertex with an
+    /// unselected element.
+    pub fn shrink(&mut self, mesh: &HalfEdgeMesh) {
+        let mut border = BTreeSet::new();
+        for element in all_elements(mesh, self.mode) {
+            if !self.elements.contains(&element) {
+                border.extend(element_vertices(mesh, &element));
+            }
+        }
+        self.elements.retain(|element| match *element {
+            Element::Vertex(v) => !neighbours(mesh, v).iter().any(|n| border.contains(n)),
+            _ => !element_vertices(mesh, element)
+                .iter()
+                .any(|v| border.contains(v)),
+        });
+    }
+
+    /// Every vertex the selection covers.
+    pub fn vertices(&self, mesh: &HalfEdgeMesh) -> BTreeSet<u32> {
+        self.elements
+            .iter()
+            .flat_map(|element| element_vertices(mesh, element))
+            .collect()
+    }
+
+    /// Every edge the selection covers, as vertex pairs.
+    pub fn edges(&self, mesh: &HalfEdgeMesh) -> Vec<[u32; 2]> {
+        let edges = match self.mode {
+            SelectMode::Vertex => edges_within(mesh, &self.vertices(mesh)),
+            SelectMode::Edge => self.elements.clone(),
+            SelectMode::Face => self
+                .elements
+                .iter()
+                .flat_map(|element| match *element {
+                    Element::Face(f) => mesh
+                        .face_half_edges(f)
+                        .into_iter()
+                        .map(|h| Element::edge(mesh.origin(h), mesh.dest(h)))
+                        .collect(),
+                    _ => Vec::new(),
+                })
+                .collect(),
+        };
+        edges
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::Edge(pair) => Some(pair),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Every face the selection covers.
+    pub fn faces(&self, mesh: &HalfEdgeMesh) -> Vec<u32> {
+        let faces = match self.mode {
+            SelectMode::Face => self.elements.clone(),
+            _ => faces_within(mesh, &self.vertices(mesh)),
+        };
+        faces
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::Face(f) => Some(f),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn accepts(&self, element: &Element) -> bool {
+        matches!(
+            (self.mode, element),
+            (SelectMode::Vertex, Element::Vertex(_))
+                | (SelectMode::Edge, Element::Edge(_))
+                | (SelectMode::Face, Element::Face(_))
+        )
+    }
+
+    /// The elements of the mode's kind made up of the vertices.
+    fn from_vertices(&self, mesh: &HalfEdgeMesh, vertices: &BTreeSet<u32>) -> BTreeSet<Element> {
+        match self.mode {
+            SelectMode::Vertex => vertices.iter().copied().map(Element::Vertex).collect(),
+            SelectMode::Edge => edges_within(mesh, vertices),
+            SelectMode::Face => faces_within(mesh, vertices),
+        }
+    }
+}
+
+/// Average of the face's vertices.
+pub fn face_center(mesh: &HalfEdgeMesh, f: u32) -> Point3<f32> {
+    let vertices = mesh.face_vertices(f);
+    let sum = vertices
+        .iter()
+        .fold(Point3::origin(), |sum, &v| sum + mesh.position(v).to_vec());
+    sum / vertices.len() as f32
+}
+
+fn element_vertices(mesh: &HalfEdgeMesh, element: &Element) -> Vec<u32> {
+    match *element {
+        Element::Vertex(v) => vec![v],
+        Element::Edge(pair) => pair.to_vec(),
+        Element::Face(f) => mesh.face_vertices(f),
+    }
+}
+
+/// Vertices sharing an edge with `v`.
+fn neighbours(mesh: &HalfEdgeMesh, v: u32) -> Vec<u32> {
+    let mut neighbours = Vec::new();
+    for h in mesh.outgoing(v) {
+        neighbours.push(mesh.dest(h));
+        // Neighbours only linked by an incoming boundary edge aren't the end of any outgoing one.
+        if mesh.twin(mesh.prev(h)).is_none() {
+            neighbours.push(mesh.origin(mesh.prev(h)));
+        }
+    }
+    neighbours
+}
+
+fn all_elements(mesh: &HalfEdgeMesh, mode: SelectMode) -> Vec<Element> {
+    match mode {
+        SelectMode::Vertex => {
+            let vertices = mesh
+                .faces()
+                .flat_map(|f| mesh.face_vertices(f))
+                .collect::<BTreeSet<_>>();
+            vertices.into_iter().map(Element::Vertex).collect()
+        }
+        SelectMode::Edge => mesh
+            .edges()
+            .into_iter()
+            .map(|[a, b]| Element::edge(a, b))
+            .collect(),
+        SelectMode::Face => mesh.faces().map(Element::Face).collect(),
+    }
+}
+
+fn edges_within(mesh: &HalfEdgeMesh, vertices: &BTreeSet<u32>) -> BTreeSet<Element> {
+    mesh.edges()
+        .into_iter()
+        .filter(|edge| edge.iter().all(|v| vertices.contains(v)))
+        .map(|[a, b]| Element::edge(a, b))
+        .collect()
+}
+
+fn faces_within(mesh: &HalfEdgeMesh, vertices: &BTreeSet<u32>) -> BTreeSet<Element> {
+    mesh.faces()
+        .filter(|&f| mesh.face_vertices(f).iter().all(|v| vertices.contains(v)))
+        .map(Element::Face)
+        .collect()
+}
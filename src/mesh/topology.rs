@@ -0,0 +1,1487 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use anyhow::*;
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3, Zero};
+
+use crate::model::{compute_tangents, ModelVertex};
+
+/// Triangles are joined into quads when the angle between their normals is below this cosine,
+/// about 40 degrees.
+const JOIN_ANGLE_COS: f32 = 0.766;
+
+/// Attributes a face gives one of its vertices, so seams survive editing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Corner {
+    pub tex_coords: [f32; 2],
+    pub normal: Vector3<f32>,
+}
+
+impl Corner {
+    pub fn lerp(&self, other: &Corner, t: f32) -> Corner {
+        let normal = self.normal + (other.normal - self.normal) * t;
+        Corner {
+            tex_coords: [0, 1]
+                .map(|i| self.tex_coords[i] + (other.tex_coords[i] - self.tex_coords[i]) * t),
+            normal: if normal.magnitude2() > 0. {
+                normal.normalize()
+            } else {
+                self.normal
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Vertex {
+    pub position: Point3<f32>,
+    /// Any half-edge leaving the vertex, `None` while no face uses it.
+    pub half_edge: Option<u32>,
+}
+
+/// One side of an edge, running counter-clockwise around its face.
+#[derive(Debug, Clone, Copy)]
+pub struct HalfEdge {
+    pub origin: u32,
+    /// The opposite side in the neighbouring face, `None` on the boundary.
+    pub twin: Option<u32>,
+    pub next: u32,
+    pub prev: u32,
+    pub face: u32,
+    /// Attributes of the face at `origin`.
+    pub corner: Corner,
+    pub removed: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Face {
+    pub half_edge: u32,
+    pub removed: bool,
+}
+
+/// Triangulated faces ready for upload.
+#[derive(Debug, Default)]
+pub struct MeshBuffers {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    /// Face every triangle belongs to.
+    pub triangle_face: Vec<u32>,
+}
+
+/// The points where `normal · p == offset`.
+#[derive(Debug, Clone, Copy)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub offset: f32,
+}
+
+impl Plane {
+    pub fn new(point: Point3<f32>, normal: Vector3<f32>) -> Self {
+        Self {
+            normal,
+            offset: normal.dot(point.to_vec()),
+        }
+    }
+
+    /// Signed distance, in units of the normal's length.
+    pub fn distance(&self, p: Point3<f32>) -> f32 {
+        self.normal.dot(p.to_vec()) - self.offset
+    }
+}
+
+/// Edges opposite each other across a strip of quads, the edges a loop cut splits.
+#[derive(Debug, Clone)]
+pub struct EdgeRing {
+    /// Vertex pairs, all pointing the same way across the strip.
+    pub edges: Vec<[u32; 2]>,
+    /// Quads between consecutive edges.
+    pub faces: Vec<u32>,
+    pub closed: bool,
+}
+
+/// Turns the triangles into polygons, joining pairs across the longest edge of both into
+/// quads as that is how quads get triangulated.
+fn join_triangles(
+    positions: &[Point3<f32>],
+    triangles: &[[(u32, Corner); 3]],
+) -> (Vec<Vec<(u32, Corner)>>, Vec<u32>) {
+    let edge = |t: &[(u32, Corner); 3], i: usize| (t[i].0, t[(i + 1) % 3].0);
+    let mut edge_triangle = HashMap::new();
+    for (t, triangle) in triangles.iter().enumerate() {
+        for i in 0..3 {
+            edge_triangle.insert(edge(triangle, i), (t, i));
+        }
+    }
+    let longest = |t: &[(u32, Corner); 3]| {
+        (0..3)
+            .max_by(|&i, &j| {
+                let length = |i: usize| {
+                    let (a, b) = edge(t, i);
+                    (positions[a as usize] - positions[b as usize]).magnitude2()
+                };
+                length(i).total_cmp(&length(j))
+            })
+            .unwrap()
+    };
+    let normal = |t: &[(u32, Corner); 3]| {
+        let [a, b, c] = t.map(|(v, _)| positions[v as usize]);
+        (b - a).cross(c - a)
+    };
+
+    let mut polygons = Vec::new();
+    let mut face_of = vec![u32::MAX; triangles.len()];
+    for (t, triangle) in triangles.iter().enumerate() {
+        if face_of[t] != u32::MAX {
+            continue;
+        }
+        let i = longest(triangle);
+        let (a, b) = edge(triangle, i);
+        let other = match edge_triangle.get(&(b, a)) {
+            Some(&(u, j)) if face_of[u] == u32::MAX && u != t && longest(&triangles[u]) == j => {
+                Some((u, j))
+            }
+            _ => None,
+        };
+        let joined = other.filter(|&(u, _)| {
+            let (n, m) = (normal(triangle), normal(&triangles[u]));
+            n.magnitude2() > 0.
+                && m.magnitude2() > 0.
+                && n.normalize().dot(m.normalize()) > JOIN_ANGLE_COS
+        });
+        let face = polygons.len() as u32;
+        face_of[t] = face;
+        match joined {
+            Some((u, j)) => {
+                face_of[u] = face;
+                polygons.push(vec![
+                    triangle[i],
+                    triangles[u][(j + 2) % 3],
+                    triangle[(i + 1) % 3],
+                    triangle[(i + 2) % 3],
+                ]);
+            }
+            None => polygons.push(triangle.to_vec()),
+        }
+    }
+    (polygons, face_of)
+}
+
+/// Normal of a polygon of vertex indices into `positions`, twice its area long.
+fn polygon_area_normal(positions: &[Point3<f32>], polygon: &[(u32, Corner)]) -> Vector3<f32> {
+    let point = |i: usize| positions[polygon[i].0 as usize];
+    (1..polygon.len().saturating_sub(1))
+        .map(|i| (point(i) - point(0)).cross(point(i + 1) - point(0)))
+        .fold(Vector3::zero(), |sum, n| sum + n)
+}
+
+fn polygon_normal(positions: &[Point3<f32>], polygon: &[(u32, Corner)]) -> Vector3<f32> {
+    let normal = polygon_area_normal(positions, polygon);
+    if normal.magnitude2() > 0. {
+        normal.normalize()
+    } else {
+        normal
+    }
+}
+
+/// Whether the polygon has next to no area for its size, e.g. all its points on a line.
+fn is_sliver(positions: &[Point3<f32>], polygon: &[(u32, Corner)]) -> bool {
+    let n = polygon.len();
+    let point = |i: usize| positions[polygon[i % n].0 as usize];
+    let perimeter = (0..n)
+        .map(|i| (point(i + 1) - point(i)).magnitude())
+        .sum::<f32>();
+    polygon_area_normal(positions, polygon).magnitude() * 0.5 < perimeter * perimeter * 1e-4
+}
+
+/// Folds a sliver into the polygon across its longest edge, so points left on a straight
+/// line don't get a face of their own.
+fn merge_sliver(
+    polygons: &mut [Vec<(u32, Corner)>],
+    positions: &[Point3<f32>],
+    sliver: &[(u32, Corner)],
+) {
+    let n = sliver.len();
+    let length = |i: usize| {
+        (positions[sliver[(i + 1) % n].0 as usize] - positions[sliver[i].0 as usize]).magnitude2()
+    };
+    let i = (0..n)
+        .max_by(|&i, &j| length(i).total_cmp(&length(j)))
+        .unwrap();
+    let (a, b) = (sliver[i].0, sliver[(i + 1) % n].0);
+    for polygon in polygons.iter_mut() {
+        let m = polygon.len();
+        if let Some(j) = (0..m).find(|&j| polygon[j].0 == b && polygon[(j + 1) % m].0 == a) {
+            let normal = polygon[j].1.lerp(&polygon[(j + 1) % m].1, 0.5).normal;
+            let path = (2..n).map(|k| {
+                let (vertex, corner) = sliver[(i + k) % n];
+                (
+                    vertex,
+                    Corner {
+                        tex_coords: corner.tex_coords,
+                        normal,
+                    },
+                )
+            });
+            polygon.splice(j + 1..j + 1, path);
+            return;
+        }
+    }
+}
+
+/// Polygon mesh with half-edge connectivity, so edits can walk and change the surface locally.
+///
+/// Removed faces and half-edges stay in place, flagged, so indices held during an edit stay
+/// valid. They are dropped when converting back to buffers.
+#[derive(Debug, Clone, Default)]
+pub struct HalfEdgeMesh {
+    vertices: Vec<Vertex>,
+    half_edges: Vec<HalfEdge>,
+    faces: Vec<Face>,
+}
+
+impl HalfEdgeMesh {
+    /// Builds the mesh from a triangle list. Vertices sharing a position are welded and pairs of
+    /// triangles forming a flat quad are joined again. Also returns the face of every triangle.
+    pub fn from_buffers(vertices: &[ModelVertex], indices: &[u32]) -> (Self, Vec<u32>) {
+        let mut positions = Vec::new();
+        let mut welded = HashMap::new();
+        let corners = vertices
+            .iter()
+            .map(|v| {
+                let key = v.position.map(f32::to_bits);
+                let vertex = *welded.entry(key).or_insert_with(|| {
+                    positions.push(Point3::from(v.position));
+                    positions.len() as u32 - 1
+                });
+                (
+                    vertex,
+                    Corner {
+                        tex_coords: v.tex_coords,
+                        normal: v.normal.into(),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let triangles = indices
+            .chunks_exact(3)
+            .map(|c| [0, 1, 2].map(|i| corners[c[i] as usize]))
+            .collect::<Vec<_>>();
+
+        let (polygons, triangle_face) = join_triangles(&positions, &triangles);
+        (Self::from_polygons(positions, &polygons), triangle_face)
+    }
+
+    /// Builds the mesh from polygons of vertex indices into `positions`, each with its corner.
+    pub fn from_polygons(positions: Vec<Point3<f32>>, polygons: &[Vec<(u32, Corner)>]) -> Self {
+        let mut mesh = Self::default();
+        for position in positions {
+            mesh.add_vertex(position);
+        }
+        let mut directed = HashMap::new();
+        for polygon in polygons.iter() {
+            let face = mesh.faces.len() as u32;
+            let start = mesh.half_edges.len() as u32;
+            let len = polygon.len() as u32;
+            for (i, &(vertex, corner)) in polygon.iter().enumerate() {
+                let i = i as u32;
+                let h = start + i;
+                mesh.half_edges.push(HalfEdge {
+                    origin: vertex,
+                    twin: None,
+                    next: start + (i + 1) % len,
+                    prev: start + (i + len - 1) % len,
+                    face,
+                    corner,
+                    removed: false,
+                });
+                mesh.vertices[vertex as usize].half_edge.get_or_insert(h);
+                let dest = polygon[((i + 1) % len) as usize].0;
+                directed.insert((vertex, dest), h);
+            }
+            mesh.faces.push(Face {
+                half_edge: start,
+                removed: false,
+            });
+        }
+        // Edges used twice in the same direction are non-manifold and left without twins, so
+        // twins always point back at each other.
+        for h in 0..mesh.half_edges.len() as u32 {
+            let (a, b) = (mesh.origin(h), mesh.dest(h));
+            if directed.get(&(a, b)) == Some(&h) {
+                mesh.half_edges[h as usize].twin = directed
+                    .get(&(b, a))
+                    .copied()
+                    .filter(|&t| directed.get(&(mesh.origin(t), mesh.dest(t))) == Some(&t));
+            }
+        }
+        mesh
+    }
+
+    /// Triangulates every face as a fan, so faces should be convex.
+    pub fn to_buffers(&self) -> MeshBuffers {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut triangle_face = Vec::new();
+        let mut unique = HashMap::new();
+        for f in self.faces() {
+            let mut index = |h: u32| {
+                let half_edge = &self.half_edges[h as usize];
+                let normal: [f32; 3] = half_edge.corner.normal.into();
+                let key = (
+                    half_edge.origin,
+                    half_edge.corner.tex_coords.map(f32::to_bits),
+                    normal.map(f32::to_bits),
+                );
+                *unique.entry(key).or_insert_with(|| {
+                    vertices.push(ModelVertex {
+                        position: self.position(half_edge.origin).into(),
+                        tex_coords: half_edge.corner.tex_coords,
+                        normal,
+                        tangent: [0.; 3],
+                        bitangent: [0.; 3],
+                    });
+                    vertices.len() as u32 - 1
+                })
+            };
+            let half_edges = self.face_half_edges(f);
+            let first = index(half_edges[0]);
+            for pair in half_edges[1..].windows(2) {
+                let triangle = [first, index(pair[0]), index(pair[1])];
+                indices.extend_from_slice(&triangle);
+                triangle_face.push(f);
+            }
+        }
+        compute_tangents(&mut vertices, &indices);
+        MeshBuffers {
+            vertices,
+            indices,
+            triangle_face,
+        }
+    }
+
+    pub fn add_vertex(&mut self, position: Point3<f32>) -> u32 {
+        self.vertices.push(Vertex {
+            position,
+            half_edge: None,
+        });
+        self.vertices.len() as u32 - 1
+    }
+
+    pub fn num_vertices(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn vertex(&self, v: u32) -> &Vertex {
+        &self.vertices[v as usize]
+    }
+
+    pub fn half_edge(&self, h: u32) -> &HalfEdge {
+        &self.half_edges[h as usize]
+    }
+
+    pub fn face(&self, f: u32) -> &Face {
+        &self.faces[f as usize]
+    }
+
+    pub fn position(&self, v: u32) -> Point3<f32> {
+        self.vertices[v as usize].position
+    }
+
+    pub fn set_position(&mut self, v: u32, position: Point3<f32>) {
+        self.vertices[v as usize].position = position;
+    }
+
+    pub fn set_corner_normal(&mut self, h: u32, normal: Vector3<f32>) {
+        self.half_edges[h as usize].corner.normal = normal;
+    }
+
+    /// Faces that haven't been removed.
+    pub fn faces(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.faces.len() as u32).filter(move |&f| !self.faces[f as usize].removed)
+    }
+
+    pub fn origin(&self, h: u32) -> u32 {
+        self.half_edges[h as usize].origin
+    }
+
+    pub fn dest(&self, h: u32) -> u32 {
+        self.origin(self.next(h))
+    }
+
+    pub fn next(&self, h: u32) -> u32 {
+        self.half_edges[h as usize].next
+    }
+
+    pub fn prev(&self, h: u32) -> u32 {
+        self.half_edges[h as usize].prev
+    }
+
+    pub fn twin(&self, h: u32) -> Option<u32> {
+        self.half_edges[h as usize].twin
+    }
+
+    pub fn face_of(&self, h: u32) -> u32 {
+        self.half_edges[h as usize].face
+    }
+
+    pub fn edge_points(&self, h: u32) -> [Point3<f32>; 2] {
+        [self.position(self.origin(h)), self.position(self.dest(h))]
+    }
+
+    pub fn face_half_edges(&self, f: u32) -> Vec<u32> {
+        let start = self.faces[f as usize].half_edge;
+        let mut half_edges = vec![start];
+        let mut h = self.next(start);
+        while h != start {
+            half_edges.push(h);
+            h = self.next(h);
+        }
+        half_edges
+    }
+
+    pub fn face_vertices(&self, f: u32) -> Vec<u32> {
+        self.face_half_edges(f)
+            .into_iter()
+            .map(|h| self.origin(h))
+            .collect()
+    }
+
+    pub fn face_len(&self, f: u32) -> usize {
+        self.face_half_edges(f).len()
+    }
+
+    /// Area weighted normal, also right for non-planar polygons.
+    pub fn face_normal(&self, f: u32) -> Vector3<f32> {
+        let positions = self
+            .face_vertices(f)
+            .into_iter()
+            .map(|v| self.position(v))
+            .collect::<Vec<_>>();
+        let normal = (1..positions.len().saturating_sub(1))
+            .map(|i| (positions[i] - positions[0]).cross(positions[i + 1] - positions[0]))
+            .fold(Vector3::zero(), |sum, n| sum + n);
+        if normal.magnitude2() > 0. {
+            normal.normalize()
+        } else {
+            normal
+        }
+    }
+
+    /// Recomputes the corner normals of the faces around the vertices after they moved. A corner
+    /// only shares its new normal with the corners it shared the old one with, so hard edges
+    /// stay hard.
+    pub fn update_normals(&mut self, vertices: &BTreeSet<u32>) {
+        let faces = vertices
+            .iter()
+            .flat_map(|&v| self.outgoing(v))
+            .map(|h| self.face_of(h))
+            .collect::<BTreeSet<_>>();
+        let affected = faces
+            .iter()
+            .flat_map(|&f| self.face_vertices(f))
+            .collect::<BTreeSet<_>>();
+        let mut normals = Vec::new();
+        for v in affected {
+            let around = self.outgoing(v);
+            for &h in around.iter() {
+                let old = self.half_edges[h as usize].corner.normal;
+                let sum = around
+                    .iter()
+                    .filter(|&&g| self.half_edges[g as usize].corner.normal == old)
+                    .fold(Vector3::zero(), |sum, &g| {
+                        sum + self.face_normal(self.face_of(g))
+                    });
+                if sum.magnitude2() > 0. {
+                    normals.push((h, sum.normalize()));
+                }
+            }
+        }
+        for (h, normal) in normals {
+            self.half_edges[h as usize].corner.normal = normal;
+        }
+    }
+
+    /// Every edge once, as vertex pairs.
+    pub fn edges(&self) -> Vec<[u32; 2]> {
+        self.faces()
+            .flat_map(|f| self.face_half_edges(f))
+            .filter(|&h| self.twin(h).map(|t| h < t).unwrap_or(true))
+            .map(|h| [self.origin(h), self.dest(h)])
+            .collect()
+    }
+
+    /// Half-edges leaving the vertex, in order around it. Starts at the boundary if there is one.
+    pub fn outgoing(&self, v: u32) -> Vec<u32> {
+        let start = match self.vertices[v as usize].half_edge {
+            Some(h) => h,
+            None => return Vec::new(),
+        };
+        // Rewind to the boundary, if any. The walks are bounded in case the surface is
+        // non-manifold around the vertex.
+        let limit = self.half_edges.len();
+        let mut first = start;
+        for _ in 0..limit {
+            match self.twin(first) {
+                Some(t) if self.next(t) != start => first = self.next(t),
+                _ => break,
+            }
+        }
+        let mut around = vec![first];
+        let mut h = first;
+        while let Some(t) = self.twin(self.prev(h)) {
+            if t == first || around.len() >= limit {
+                break;
+            }
+            around.push(t);
+            h = t;
+        }
+        around
+    }
+
+    pub fn is_boundary_vertex(&self, v: u32) -> bool {
+        self.outgoing(v)
+            .iter()
+            .any(|&h| self.twin(h).is_none() || self.twin(self.prev(h)).is_none())
+    }
+
+    /// The half-edge from `a` to `b`, if there is a face on that side.
+    pub fn find_half_edge(&self, a: u32, b: u32) -> Option<u32> {
+        self.outgoing(a).into_iter().find(|&h| self.dest(h) == b)
+    }
+
+    /// The half-edge of the face closest to `point`.
+    pub fn closest_half_edge(&self, f: u32, point: Point3<f32>) -> u32 {
+        let distance = |h: u32| {
+            let [a, b] = self.edge_points(h);
+            let ab = b - a;
+            let t = ((point - a).dot(ab) / ab.magnitude2().max(1e-12))
+                .max(0.)
+                .min(1.);
+            (a + ab * t - point).magnitude2()
+        };
+        self.face_half_edges(f)
+            .into_iter()
+            .min_by(|&g, &h| distance(g).total_cmp(&distance(h)))
+            .unwrap()
+    }
+
+    /// The ring of edges opposite each other across quads, through the edge of `h`.
+    pub fn edge_ring(&self, h: u32) -> EdgeRing {
+        let mut visited = HashSet::new();
+        let mut edges = vec![[self.origin(h), self.dest(h)]];
+        let mut faces = Vec::new();
+        let mut closed = false;
+        let mut current = h;
+        while self.face_len(self.face_of(current)) == 4 && visited.insert(self.face_of(current)) {
+            faces.push(self.face_of(current));
+            let opposite = self.next(self.next(current));
+            match self.twin(opposite) {
+                Some(t) if t == h => {
+                    closed = true;
+                    break;
+                }
+                Some(t) => {
+                    edges.push([self.origin(t), self.dest(t)]);
+                    current = t;
+                }
+                None => {
+                    edges.push([self.dest(opposite), self.origin(opposite)]);
+                    break;
+                }
+            }
+        }
+
+        if !closed {
+            let mut back_edges = Vec::new();
+            let mut back_faces = Vec::new();
+            let mut current = self.twin(h);
+            while let Some(c) = current {
+                if self.face_len(self.face_of(c)) != 4 || !visited.insert(self.face_of(c)) {
+                    break;
+                }
+                back_faces.push(self.face_of(c));
+                let opposite = self.next(self.next(c));
+                back_edges.push([self.origin(opposite), self.dest(opposite)]);
+                current = self.twin(opposite);
+            }
+            back_edges.reverse();
+            back_faces.reverse();
+            back_edges.extend(edges);
+            back_faces.extend(faces);
+            edges = back_edges;
+            faces = back_faces;
+        }
+        EdgeRing {
+            edges,
+            faces,
+            closed,
+        }
+    }
+
+    /// Continues an edge loop through the end of `h`, which only works at interior vertices
+    /// with four edges.
+    fn loop_step(&self, h: u32) -> Option<u32> {
+        let around = self.outgoing(self.dest(h));
+        let back = self.twin(h)?;
+        let i = around.iter().position(|&o| o == back)?;
+        if around.len() == 4 && !self.is_boundary_vertex(self.dest(h)) {
+            Some(around[(i + 2) % 4])
+        } else {
+            None
+        }
+    }
+
+    /// Edges of the loop through `h`, followed across four-edge vertices both ways.
+    pub fn edge_loop(&self, h: u32) -> Vec<[u32; 2]> {
+        let mut edges = vec![[self.origin(h), self.dest(h)]];
+        let mut visited = HashSet::new();
+        visited.insert(h);
+        let mut current = h;
+        while let Some(next) = self.loop_step(current) {
+            if !visited.insert(next) {
+                return edges;
+            }
+            edges.push([self.origin(next), self.dest(next)]);
+            current = next;
+        }
+        if let Some(twin) = self.twin(h) {
+            let mut current = twin;
+            while let Some(next) = self.loop_step(current) {
+                if !visited.insert(next) {
+                    break;
+                }
+                edges.push([self.origin(next), self.dest(next)]);
+                current = next;
+            }
+        }
+        edges
+    }
+
+    /// Inserts a vertex at `t` along the edge of `h`, into the faces on both sides. Returns the
+    /// new vertex; `h` then ends there.
+    pub fn split_edge(&mut self, h: u32, t: f32) -> u32 {
+        let [a, b] = self.edge_points(h);
+        let vertex = self.add_vertex(a + (b - a) * t);
+        let twin = self.twin(h);
+        let after_h = self.insert_after(h, vertex, t);
+        self.vertices[vertex as usize].half_edge = Some(after_h);
+        if let Some(twin) = twin {
+            let after_twin = self.insert_after(twin, vertex, 1. - t);
+            self.half_edges[h as usize].twin = Some(after_twin);
+            self.half_edges[after_twin as usize].twin = Some(h);
+            self.half_edges[twin as usize].twin = Some(after_h);
+            self.half_edges[after_h as usize].twin = Some(twin);
+        }
+        vertex
+    }
+
+    /// Adds a half-edge from `vertex` after `h` in its face, with the corner blended at `t`.
+    fn insert_after(&mut self, h: u32, vertex: u32, t: f32) -> u32 {
+        let next = self.next(h);
+        let corner = self.half_edges[h as usize]
+            .corner
+            .lerp(&self.half_edges[next as usize].corner, t);
+        let new = self.half_edges.len() as u32;
+        self.half_edges.push(HalfEdge {
+            origin: vertex,
+            twin: None,
+            next,
+            prev: h,
+            face: self.face_of(h),
+            corner,
+            removed: false,
+        });
+        self.half_edges[h as usize].next = new;
+        self.half_edges[next as usize].prev = new;
+        new
+    }
+
+    /// Splits the face of `a` and `b` by an edge between their origins. The part from `a` up
+    /// to `b` becomes a new face. Returns the new half-edge left in the old face, running from
+    /// `a`'s origin to `b`'s.
+    pub fn split_face(&mut self, a: u32, b: u32) -> Result<u32> {
+        let face = self.face_of(a);
+        ensure!(self.face_of(b) == face, "half-edges are in different faces");
+        ensure!(
+            a != b && self.next(a) != b && self.next(b) != a,
+            "split would leave a two sided face"
+        );
+        let (before_a, before_b) = (self.prev(a), self.prev(b));
+        let new_face = self.faces.len() as u32;
+        let x = self.half_edges.len() as u32;
+        let y = x + 1;
+        let corner_a = self.half_edges[a as usize].corner;
+        let corner_b = self.half_edges[b as usize].corner;
+        // `x` closes the new face from `b`'s origin back to `a`'s.
+        self.half_edges.push(HalfEdge {
+            origin: self.origin(b),
+            twin: Some(y),
+            next: a,
+            prev: before_b,
+            face: new_face,
+            corner: corner_b,
+            removed: false,
+        });
+        self.half_edges.push(HalfEdge {
+            origin: self.origin(a),
+            twin: Some(x),
+            next: b,
+            prev: before_a,
+            face,
+            corner: corner_a,
+            removed: false,
+        });
+        self.half_edges[before_b as usize].next = x;
+        self.half_edges[a as usize].prev = x;
+        self.half_edges[before_a as usize].next = y;
+        self.half_edges[b as usize].prev = y;
+        self.faces.push(Face {
+            half_edge: a,
+            removed: false,
+        });
+        self.faces[face as usize].half_edge = b;
+        let mut h = a;
+        while h != x {
+            self.half_edges[h as usize].face = new_face;
+            h = self.next(h);
+        }
+        self.half_edges[x as usize].face = new_face;
+        Ok(y)
+    }
+
+    /// Removes the edge of `h`, merging the faces on both sides into the face of `h`.
+    pub fn dissolve_edge(&mut self, h: u32) -> Result<u32> {
+        let twin = self
+            .twin(h)
+            .ok_or_else(|| anyhow!("can't dissolve a boundary edge"))?;
+        let (face, other) = (self.face_of(h), self.face_of(twin));
+        ensure!(face != other, "both sides of the edge are in the same face");
+        ensure!(
+            self.next(h) != twin && self.next(twin) != h,
+            "dissolving would leave a dangling edge"
+        );
+        let (before_h, after_h) = (self.prev(h), self.next(h));
+        let (before_twin, after_twin) = (self.prev(twin), self.next(twin));
+        self.half_edges[before_h as usize].next = after_twin;
+        self.half_edges[after_twin as usize].prev = before_h;
+        self.half_edges[before_twin as usize].next = after_h;
+        self.half_edges[after_h as usize].prev = before_twin;
+        let mut g = after_twin;
+        while g != after_h {
+            self.half_edges[g as usize].face = face;
+            g = self.next(g);
+        }
+        self.faces[face as usize].half_edge = after_h;
+        self.faces[other as usize].removed = true;
+        self.half_edges[h as usize].removed = true;
+        self.half_edges[twin as usize].removed = true;
+        let (a, b) = (self.origin(h), self.origin(twin));
+        self.vertices[a as usize].half_edge = Some(after_twin);
+        self.vertices[b as usize].half_edge = Some(after_h);
+        Ok(face)
+    }
+
+    /// Moves a copy of the face by `offset`, joining it to the rest of the mesh with a quad
+    /// along every edge. Returns the new side faces.
+    pub fn extrude_face(&mut self, f: u32, offset: Vector3<f32>) -> Vec<u32> {
+        let top = self.face_half_edges(f);
+        let n = top.len();
+        let bottom = top.iter().map(|&h| self.origin(h)).collect::<Vec<_>>();
+        let raised = bottom
+            .iter()
+            .map(|&v| self.add_vertex(self.position(v) + offset))
+            .collect::<Vec<_>>();
+
+        // Four half-edges per side, base, up, top and down, all added in one go.
+        let first = self.half_edges.len() as u32;
+        let side = |i: usize, k: u32| first + 4 * i as u32 + k;
+        let mut sides = Vec::with_capacity(n);
+        for i in 0..n {
+            let j = (i + 1) % n;
+            let face = self.faces.len() as u32;
+            sides.push(face);
+            self.faces.push(Face {
+                half_edge: side(i, 0),
+                removed: false,
+            });
+            let corner = self.half_edges[top[i] as usize].corner;
+            let next_corner = self.half_edges[top[j] as usize].corner;
+            let outer_twin = self.twin(top[i]);
+            let ends = [
+                (bottom[i], corner, outer_twin),
+                (bottom[j], next_corner, Some(side(j, 3))),
+                (raised[j], next_corner, Some(top[i])),
+                (raised[i], corner, Some(side((i + n - 1) % n, 1))),
+            ];
+            for (k, &(origin, corner, twin)) in ends.iter().enumerate() {
+                let k = k as u32;
+                self.half_edges.push(HalfEdge {
+                    origin,
+                    twin,
+                    next: side(i, (k + 1) % 4),
+                    prev: side(i, (k + 3) % 4),
+                    face,
+                    corner,
+                    removed: false,
+                });
+            }
+            if let Some(outer) = outer_twin {
+                self.half_edges[outer as usize].twin = Some(side(i, 0));
+            }
+            self.vertices[bottom[i] as usize].half_edge = Some(side(i, 0));
+        }
+        for (i, &h) in top.iter().enumerate() {
+            self.half_edges[h as usize].origin = raised[i];
+            self.half_edges[h as usize].twin = Some(side(i, 2));
+            self.vertices[raised[i] as usize].half_edge = Some(h);
+        }
+        for &face in sides.iter() {
+            let normal = self.face_normal(face);
+            for h in self.face_half_edges(face) {
+                self.half_edges[h as usize].corner.normal = normal;
+            }
+        }
+        sides
+    }
+
+    /// Splits every quad of the ring in two, through new vertices at `factor` along its
+    /// edges. Returns the new vertices.
+    pub fn loop_cut(&mut self, ring: &EdgeRing, factor: f32) -> Result<Vec<u32>> {
+        let mut cuts = Vec::with_capacity(ring.edges.len());
+        for &[a, b] in ring.edges.iter() {
+            let vertex = match self.find_half_edge(a, b) {
+                Some(h) => self.split_edge(h, factor),
+                None => {
+                    let h = self
+                        .find_half_edge(b, a)
+                        .ok_or_else(|| anyhow!("edge {}-{} is gone", a, b))?;
+                    self.split_edge(h, 1. - factor)
+                }
+            };
+            cuts.push(vertex);
+        }
+        for (i, &face) in ring.faces.iter().enumerate() {
+            let (from, to) = (cuts[i], cuts[(i + 1) % cuts.len()]);
+            let leaving = |v: u32| {
+                self.face_half_edges(face)
+                    .into_iter()
+                    .find(|&h| self.origin(h) == v)
+            };
+            if let (Some(a), Some(b)) = (leaving(from), leaving(to)) {
+                self.split_face(a, b)?;
+            }
+        }
+        Ok(cuts)
+    }
+
+    /// Cuts the faces along the plane wherever `keep` allows, inserting vertices where edges
+    /// cross it and splitting faces between them. Returns the edges along the cut as vertex
+    /// pairs.
+    pub fn cut(&mut self, plane: &Plane, keep: impl Fn(Point3<f32>) -> bool) -> Vec<[u32; 2]> {
+        let used = (0..self.vertices.len() as u32)
+            .filter(|&v| self.vertices[v as usize].half_edge.is_some())
+            .collect::<Vec<_>>();
+        let extent = used
+            .iter()
+            .map(|&v| self.position(v).to_vec().magnitude())
+            .fold(1., f32::max);
+        let epsilon = extent * 1e-5;
+        let mut on_plane = used
+            .into_iter()
+            .filter(|&v| {
+                let p = self.position(v);
+                plane.distance(p).abs() < epsilon && keep(p)
+            })
+            .collect::<HashSet<_>>();
+
+        let edges = self
+            .faces()
+            .flat_map(|f| self.face_half_edges(f))
+            .filter(|&h| self.twin(h).map(|t| h < t).unwrap_or(true))
+            .collect::<Vec<_>>();
+        for h in edges {
+            let [a, b] = self.edge_points(h);
+            let (da, db) = (plane.distance(a), plane.distance(b));
+            if da.abs() < epsilon || db.abs() < epsilon || (da > 0.) == (db > 0.) {
+                continue;
+            }
+            let t = da / (da - db);
+            if keep(a + (b - a) * t) {
+                on_plane.insert(self.split_edge(h, t));
+            }
+        }
+
+        for f in self.faces().collect::<Vec<_>>() {
+            let crossing = self
+                .face_half_edges(f)
+                .into_iter()
+                .filter(|&h| on_plane.contains(&self.origin(h)))
+                .collect::<Vec<_>>();
+            // Faces are convex, so the plane crosses them at most twice. Faces that already
+            // have an edge there fail to split and are left alone.
+            if let [a, b] = crossing[..] {
+                self.split_face(a, b).ok();
+            }
+        }
+        // The cut also runs along edges that were in the plane already.
+        self.edges()
+            .into_iter()
+            .filter(|edge| edge.iter().all(|v| on_plane.contains(v)))
+            .collect()
+    }
+
+    /// Groups the faces into pieces connected across edges other than `seams`, given as vertex
+    /// pairs.
+    pub fn pieces(&self, seams: &[[u32; 2]]) -> Vec<Vec<u32>> {
+        let key = |a: u32, b: u32| (a.min(b), a.max(b));
+        let seams = seams
+            .iter()
+            .map(|&[a, b]| key(a, b))
+            .collect::<HashSet<_>>();
+        let mut piece_of = vec![usize::MAX; self.faces.len()];
+        let mut pieces = Vec::new();
+        for f in self.faces() {
+            if piece_of[f as usize] != usize::MAX {
+                continue;
+            }
+            let piece = pieces.len();
+            piece_of[f as usize] = piece;
+            let mut faces = Vec::new();
+            let mut stack = vec![f];
+            while let Some(g) = stack.pop() {
+                faces.push(g);
+                for h in self.face_half_edges(g) {
+                    if seams.contains(&key(self.origin(h), self.dest(h))) {
+                        continue;
+                    }
+                    if let Some(t) = self.twin(h) {
+                        let neighbour = self.face_of(t) as usize;
+                        if piece_of[neighbour] == usize::MAX {
+                            piece_of[neighbour] = piece;
+                            stack.push(neighbour as u32);
+                        }
+                    }
+                }
+            }
+            pieces.push(faces);
+        }
+        pieces
+    }
+
+    /// A new mesh of copies of the faces, with only the vertices they use.
+    pub fn extract(&self, faces: &[u32]) -> Self {
+        let mut vertex_of = HashMap::new();
+        let mut positions = Vec::new();
+        let mut polygons = Vec::with_capacity(faces.len());
+        for &f in faces {
+            let mut polygon = Vec::new();
+            for h in self.face_half_edges(f) {
+                let v = self.origin(h);
+                let vertex = *vertex_of.entry(v).or_insert_with(|| {
+                    positions.push(self.position(v));
+                    positions.len() as u32 - 1
+                });
+                polygon.push((vertex, self.half_edges[h as usize].corner));
+            }
+            polygons.push(polygon);
+        }
+        Self::from_polygons(positions, &polygons)
+    }
+
+    /// Bevels the edges, given as vertex pairs, cutting `width` into the faces on both sides and
+    /// rounding them over with `segments` faces. Gaps left where bevels end or meet are capped
+    /// with a face.
+    pub fn bevel_edges(&mut self, edges: &[[u32; 2]], width: f32, segments: u32) -> Result<()> {
+        ensure!(width > 0., "bevel width must be positive");
+        let segments = segments.max(1);
+        let key = |a: u32, b: u32| (a.min(b), a.max(b));
+        let selected = edges
+            .iter()
+            .map(|&[a, b]| key(a, b))
+            .collect::<BTreeSet<_>>();
+        ensure!(!selected.is_empty(), "no edges to bevel");
+        let mut strips = Vec::with_capacity(selected.len());
+        for &(a, b) in selected.iter() {
+            let h = self
+                .find_half_edge(a, b)
+                .or_else(|| self.find_half_edge(b, a))
+                .ok_or_else(|| anyhow!("edge {}-{} is gone", a, b))?;
+            let t = self
+                .twin(h)
+                .ok_or_else(|| anyhow!("can't bevel boundary edge {}-{}", a, b))?;
+            strips.push((h, t));
+        }
+        let is_selected = |a: u32, b: u32| selected.contains(&key(a, b));
+        let beveled = selected
+            .iter()
+            .flat_map(|&(a, b)| [a, b])
+            .collect::<HashSet<_>>();
+        let corners = self
+            .faces()
+            .flat_map(|f| self.face_half_edges(f))
+            .filter(|&h| beveled.contains(&self.origin(h)))
+            .collect::<Vec<_>>();
+        let open = self
+            .faces()
+            .flat_map(|f| self.face_half_edges(f))
+            .filter(|&h| self.twin(h).is_none())
+            .map(|h| (self.origin(h), self.dest(h)))
+            .collect::<HashSet<_>>();
+        let mut positions = self.vertices.iter().map(|v| v.position).collect::<Vec<_>>();
+
+        // Where the sides of the bevels cross the unselected edges next to them, keyed by the
+        // beveled vertex and the other end of the edge.
+        let mut slides = HashMap::new();
+        for &h in corners.iter() {
+            let (u, v, w) = (self.origin(self.prev(h)), self.origin(h), self.dest(h));
+            let (from_u, to_w) = (is_selected(u, v), is_selected(v, w));
+            if from_u == to_w {
+                continue;
+            }
+            let (along, beside) = if to_w { (u, w) } else { (w, u) };
+            slides.entry((v, along)).or_insert_with(|| {
+                let (p, q) = (self.position(v), self.position(along));
+                let distance = self.offset_distance(v, along, beside, width);
+                positions.push(p + (q - p).normalize() * distance);
+                positions.len() as u32 - 1
+            });
+        }
+
+        // What every corner at a beveled vertex turns into, in order around its face.
+        let mut replaced = HashMap::new();
+        for &h in corners.iter() {
+            let (p, n) = (self.prev(h), self.next(h));
+            let (u, v, w) = (self.origin(p), self.origin(h), self.origin(n));
+            let slid = |vertex: u32, toward: u32| {
+                (
+                    vertex,
+                    self.corner_towards(h, toward, positions[vertex as usize]),
+                )
+            };
+            let replacement = match (is_selected(u, v), is_selected(v, w)) {
+                (true, true) => {
+                    let (point, corner) = self.inset_corner(h, width);
+                    positions.push(point);
+                    vec![(positions.len() as u32 - 1, corner)]
+                }
+                (false, true) => vec![slid(slides[&(v, u)], p)],
+                (true, false) => vec![slid(slides[&(v, w)], n)],
+                (false, false) => {
+                    let (from_u, to_w) = (slides.get(&(v, u)), slides.get(&(v, w)));
+                    let mut replacement = Vec::new();
+                    if let Some(&s) = from_u {
+                        replacement.push(slid(s, p));
+                    }
+                    // The vertex stays where the face doesn't reach the bevel on both sides.
+                    if from_u.is_none() || to_w.is_none() {
+                        replacement.push((v, self.half_edges[h as usize].corner));
+                    }
+                    if let Some(&s) = to_w {
+                        replacement.push(slid(s, n));
+                    }
+                    replacement
+                }
+            };
+            replaced.insert(h, replacement);
+        }
+
+        let mut polygons = self
+            .faces()
+            .map(|f| {
+                self.face_half_edges(f)
+                    .into_iter()
+                    .flat_map(|h| match replaced.get(&h) {
+                        Some(replacement) => replacement.clone(),
+                        None => vec![(self.origin(h), self.half_edges[h as usize].corner)],
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // Profile points across the end of a bevel, shared with the bevel continuing there.
+        let mut profiles = HashMap::new();
+        let mut profile = |v: u32,
+                           start: (u32, Corner),
+                           end: (u32, Corner),
+                           normals: [Vector3<f32>; 2]| {
+            let control = self.position(v);
+            (0..=segments)
+                .map(|k| {
+                    let s = k as f32 / segments as f32;
+                    let vertex = if k == 0 {
+                        start.0
+                    } else if k == segments {
+                        end.0
+                    } else {
+                        let key = if start.0 < end.0 {
+                            (v, start.0, end.0, k)
+                        } else {
+                            (v, end.0, start.0, segments - k)
+                        };
+                        *profiles.entry(key).or_insert_with(|| {
+                            let (p, q) = (positions[start.0 as usize], positions[end.0 as usize]);
+                            positions
+                                .push(p + (control - p) * (2. * s * (1. - s)) + (q - p) * (s * s));
+                            positions.len() as u32 - 1
+                        })
+                    };
+                    let normal = normals[0] + (normals[1] - normals[0]) * s;
+                    let corner = Corner {
+                        tex_coords: start.1.lerp(&end.1, s).tex_coords,
+                        normal: if normal.magnitude2() > 0. {
+                            normal.normalize()
+                        } else {
+                            normals[0]
+                        },
+                    };
+                    (vertex, corner)
+                })
+                .collect::<Vec<_>>()
+        };
+        for &(h, t) in strips.iter() {
+            let side_start = |h: u32| *replaced[&h].last().unwrap();
+            let side_end = |h: u32| replaced[&self.next(h)][0];
+            let normals = [
+                self.face_normal(self.face_of(h)),
+                self.face_normal(self.face_of(t)),
+            ];
+            let at_a = profile(self.origin(h), side_start(h), side_end(t), normals);
+            let at_b = profile(self.dest(h), side_end(h), side_start(t), normals);
+            for k in 0..segments as usize {
+                polygons.push(vec![at_b[k], at_a[k], at_a[k + 1], at_b[k + 1]]);
+            }
+        }
+        // A single segment is a flat chamfer.
+        if segments == 1 {
+            let first = polygons.len() - strips.len();
+            for polygon in polygons[first..].iter_mut() {
+                let normal = polygon_normal(&positions, polygon);
+                for (_, corner) in polygon.iter_mut() {
+                    corner.normal = normal;
+                }
+            }
+        }
+
+        // Cap the holes the bevel opened, which are the boundaries the mesh didn't have.
+        let beveled_mesh = Self::from_polygons(positions.clone(), &polygons);
+        let mut gaps = BTreeMap::new();
+        for h in 0..beveled_mesh.half_edges.len() as u32 {
+            if beveled_mesh.twin(h).is_none() {
+                gaps.insert(beveled_mesh.dest(h), h);
+            }
+        }
+        let mut caps = Vec::new();
+        let mut merged = false;
+        while let Some(&start) = gaps.keys().next() {
+            let mut cap = Vec::new();
+            let mut vertex = start;
+            let mut closed = false;
+            let mut was_open = false;
+            while let Some(h) = gaps.remove(&vertex) {
+                let corner = beveled_mesh.half_edges[beveled_mesh.next(h) as usize].corner;
+                cap.push((vertex, corner));
+                vertex = beveled_mesh.origin(h);
+                was_open |= open.contains(&(vertex, cap[cap.len() - 1].0));
+                if vertex == start {
+                    closed = true;
+                    break;
+                }
+            }
+            if !closed || was_open || cap.len() < 3 {
+                continue;
+            }
+            if is_sliver(&positions, &cap) {
+                merge_sliver(&mut polygons, &positions, &cap);
+                merged = true;
+            } else {
+                let normal = polygon_normal(&positions, &cap);
+                for (_, corner) in cap.iter_mut() {
+                    corner.normal = normal;
+                }
+                caps.push(cap);
+            }
+        }
+        *self = if caps.is_empty() && !merged {
+            beveled_mesh
+        } else {
+            polygons.extend(caps);
+            Self::from_polygons(positions, &polygons)
+        };
+        Ok(())
+    }
+
+    /// How far along the edge from `v` to `along` the bevel of the edge from `v` to `beside`
+    /// reaches, for it to be `width` wide. At most half the edge.
+    fn offset_distance(&self, v: u32, along: u32, beside: u32, width: f32) -> f32 {
+        let p = self.position(v);
+        let (d, e) = (self.position(along) - p, self.position(beside) - p);
+        let sin = d.normalize().cross(e.normalize()).magnitude().max(0.1);
+        (width / sin).min(d.magnitude() * 0.5)
+    }
+
+    /// The corner of `h` blended towards the corner of `toward` for a point on the edge
+    /// between their vertices.
+    fn corner_towards(&self, h: u32, toward: u32, point: Point3<f32>) -> Corner {
+        let a = self.position(self.origin(h));
+        let b = self.position(self.origin(toward));
+        let t = (point - a).magnitude() / (b - a).magnitude().max(1e-12);
+        self.half_edges[h as usize]
+            .corner
+            .lerp(&self.half_edges[toward as usize].corner, t)
+    }
+
+    /// Point inside the face of `h`, `width` away from both edges at its origin, with its
+    /// corner.
+    fn inset_corner(&self, h: u32, width: f32) -> (Point3<f32>, Corner) {
+        let (p, n) = (self.prev(h), self.next(h));
+        let v = self.position(self.origin(h));
+        let (d, e) = (
+            self.position(self.origin(p)) - v,
+            self.position(self.origin(n)) - v,
+        );
+        let sin = d.normalize().cross(e.normalize()).magnitude().max(0.1);
+        let offset = |x: Vector3<f32>| x.normalize() * (width / sin).min(x.magnitude() * 0.5);
+        let (a, b) = (offset(d), offset(e));
+        let corner = self.half_edges[h as usize].corner;
+        let (ca, cb) = (
+            self.corner_towards(h, p, v + a),
+            self.corner_towards(h, n, v + b),
+        );
+        let corner = Corner {
+            tex_coords: [0, 1].map(|i| ca.tex_coords[i] + cb.tex_coords[i] - corner.tex_coords[i]),
+            normal: corner.normal,
+        };
+        (v + a + b, corner)
+    }
+
+    /// Checks that all links are consistent.
+    pub fn validate(&self) -> Result<()> {
+        for (h, half_edge) in self.half_edges.iter().enumerate() {
+            if half_edge.removed {
+                continue;
+            }
+            let h = h as u32;
+            ensure!(
+                self.prev(self.next(h)) == h,
+                "next of {} doesn't lead back",
+                h
+            );
+            ensure!(
+                self.next(self.prev(h)) == h,
+                "prev of {} doesn't lead back",
+                h
+            );
+            ensure!(
+                self.face_of(self.next(h)) == half_edge.face,
+                "{} leaves its face",
+                h
+            );
+            ensure!(
+                !self.faces[half_edge.face as usize].removed,
+                "{} is in a removed face",
+                h
+            );
+            if let Some(t) = half_edge.twin {
+                ensure!(
+                    !self.half_edges[t as usize].removed,
+                    "twin of {} was removed",
+                    h
+                );
+                ensure!(self.twin(t) == Some(h), "twin of {} doesn't lead back", h);
+                ensure!(
+                    self.origin(t) == self.dest(h),
+                    "twin of {} starts elsewhere",
+                    h
+                );
+            }
+        }
+        for f in self.faces() {
+            let start = self.faces[f as usize].half_edge;
+            ensure!(self.face_of(start) == f, "face {} starts outside itself", f);
+            let mut len = 1;
+            let mut h = self.next(start);
+            while h != start {
+                len += 1;
+                ensure!(len <= self.half_edges.len(), "face {} doesn't close", f);
+                h = self.next(h);
+            }
+            ensure!(len >= 3, "face {} has {} sides", f, len);
+        }
+        for (v, vertex) in self.vertices.iter().enumerate() {
+            if let Some(h) = vertex.half_edge {
+                let half_edge = &self.half_edges[h as usize];
+                ensure!(
+                    !half_edge.removed && half_edge.origin == v as u32,
+                    "vertex {} points at a half-edge it doesn't start",
+                    v
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitive::Primitive;
+
+    fn vertex(position: [f32; 3]) -> ModelVertex {
+        ModelVertex {
+            position,
+            tex_coords: [0.; 2],
+            normal: [0., 1., 0.],
+            tangent: [0.; 3],
+            bitangent: [0.; 3],
+        }
+    }
+
+    fn cube() -> HalfEdgeMesh {
+        let cube = Primitive::Cube.mesh(2.);
+        HalfEdgeMesh::from_buffers(&cube.vertices, &cube.indices).0
+    }
+
+    fn used_vertices(mesh: &HalfEdgeMesh) -> usize {
+        (0..mesh.num_vertices() as u32)
+            .filter(|&v| mesh.vertex(v).half_edge.is_some())
+            .count()
+    }
+
+    /// Vertices, edges and faces of a closed surface without holes.
+    fn euler_characteristic(mesh: &HalfEdgeMesh) -> i64 {
+        used_vertices(mesh) as i64 - mesh.edges().len() as i64 + mesh.faces().count() as i64
+    }
+
+    fn area(vertices: &[ModelVertex], indices: &[u32]) -> f32 {
+        indices
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [0, 1, 2].map(|i| Point3::from(vertices[t[i] as usize].position));
+                (b - a).cross(c - a).magnitude() / 2.
+            })
+            .sum()
+    }
+
+    /// Builds the mesh from the triangles and back, checking the surface stayed the same.
+    fn round_trip(vertices: &[ModelVertex], indices: &[u32]) -> (HalfEdgeMesh, MeshBuffers) {
+        let (mesh, triangle_face) = HalfEdgeMesh::from_buffers(vertices, indices);
+        mesh.validate().unwrap();
+        assert_eq!(triangle_face.len(), indices.len() / 3);
+        let buffers = mesh.to_buffers();
+        assert_eq!(buffers.indices.len(), indices.len());
+        assert_eq!(buffers.triangle_face.len(), indices.len() / 3);
+        assert!((area(&buffers.vertices, &buffers.indices) - area(vertices, indices)).abs() < 1e-4);
+        for v in buffers.vertices.iter() {
+            assert!(vertices.iter().any(|w| w.position == v.position));
+        }
+        (mesh, buffers)
+    }
+
+    #[test]
+    fn closed_round_trip() {
+        let cube = Primitive::Cube.mesh(2.);
+        let (mesh, buffers) = round_trip(&cube.vertices, &cube.indices);
+        // Welded into the corners and joined into quads, the faces keep their own normals.
+        assert_eq!(used_vertices(&mesh), 8);
+        assert_eq!(mesh.faces().count(), 6);
+        assert!(mesh.faces().all(|f| mesh.face_len(f) == 4));
+        assert_eq!(mesh.edges().len(), 12);
+        assert_eq!(euler_characteristic(&mesh), 2);
+        assert!((0..mesh.num_vertices() as u32).all(|v| !mesh.is_boundary_vertex(v)));
+        assert_eq!(buffers.vertices.len(), 24);
+    }
+
+    #[test]
+    fn open_round_trip() {
+        let plane = Primitive::Plane.mesh(2.);
+        let (mesh, buffers) = round_trip(&plane.vertices, &plane.indices);
+        assert_eq!(mesh.faces().count(), 1);
+        assert_eq!(mesh.face_len(0), 4);
+        assert!((0..4).all(|v| mesh.is_boundary_vertex(v)));
+        assert!(mesh.face_half_edges(0).iter().all(|&h| mesh.twin(h).is_none()));
+        assert_eq!(buffers.vertices.len(), 4);
+    }
+
+    #[test]
+    fn non_manifold_round_trip() {
+        // Three fins around the edge from 0 to 1, two of them running it the same way.
+        let vertices = [
+            [0., 0., 0.],
+            [0., 1., 0.],
+            [1., 0., 0.],
+            [-0.5, 0., 0.87],
+            [-0.5, 0., -0.87],
+        ]
+        .map(vertex);
+        let indices = [0, 1, 2, 1, 0, 3, 0, 1, 4];
+        let (mesh, _) = round_trip(&vertices, &indices);
+        assert_eq!(mesh.faces().count(), 3);
+        let twins = (0..3)
+            .flat_map(|f| mesh.face_half_edges(f))
+            .filter(|&h| mesh.twin(h).is_some())
+            .count();
+        // Two of the fins pair up across the edge, the third is left with a boundary there.
+        assert_eq!(twins, 2);
+    }
+
+    #[test]
+    fn loop_cut_cube() {
+        let mut mesh = cube();
+        let h = mesh.face_half_edges(0)[0];
+        let ring = mesh.edge_ring(h);
+        assert!(ring.closed);
+        assert_eq!(ring.faces.len(), 4);
+        let cuts = mesh.loop_cut(&ring, 0.5).unwrap();
+        mesh.validate().unwrap();
+        assert_eq!(cuts.len(), 4);
+        assert_eq!(used_vertices(&mesh), 12);
+        assert_eq!(mesh.faces().count(), 10);
+        assert!(mesh.faces().all(|f| mesh.face_len(f) == 4));
+        assert_eq!(euler_characteristic(&mesh), 2);
+    }
+
+    #[test]
+    fn bevel_cube_edge() {
+        let mut mesh = cube();
+        let h = mesh.face_half_edges(0)[0];
+        let edge = [mesh.origin(h), mesh.dest(h)];
+        mesh.bevel_edges(&[edge], 0.2, 1).unwrap();
+        mesh.validate().unwrap();
+        assert_eq!(used_vertices(&mesh), 10);
+        assert_eq!(mesh.faces().count(), 7);
+        assert_eq!(euler_characteristic(&mesh), 2);
+    }
+
+    #[test]
+    fn bevel_cube_edge_segments() {
+        let mut mesh = cube();
+        let h = mesh.face_half_edges(0)[0];
+        let edge = [mesh.origin(h), mesh.dest(h)];
+        mesh.bevel_edges(&[edge], 0.2, 3).unwrap();
+        mesh.validate().unwrap();
+        assert_eq!(used_vertices(&mesh), 14);
+        // Three faces round the edge over, and the ends of the rounding are capped.
+        assert_eq!(mesh.faces().count(), 11);
+        assert_eq!(euler_characteristic(&mesh), 2);
+    }
+
+    #[test]
+    fn plane_cut_cube() {
+        let mut mesh = cube();
+        let seam = mesh.cut(&Plane::new(Point3::new(0., 0.25, 0.), Vector3::unit_y()), |_| true);
+        mesh.validate().unwrap();
+        assert_eq!(seam.len(), 4);
+        assert_eq!(used_vertices(&mesh), 12);
+        assert_eq!(mesh.faces().count(), 10);
+        assert_eq!(mesh.pieces(&seam).len(), 2);
+    }
+
+    #[test]
+    fn nan_positions_dont_panic() {
+        let mut vertices = Primitive::Cube.mesh(2.).vertices;
+        vertices[0].position[0] = f32::NAN;
+        let indices = Primitive::Cube.mesh(2.).indices;
+        let (mesh, _) = HalfEdgeMesh::from_buffers(&vertices, &indices);
+        mesh.closest_half_edge(0, Point3::new(0., 0., 0.));
+        mesh.to_buffers();
+    }
+}
@@ -0,0 +1,142 @@
+//! A binary cache of imported OBJ files, so big models load again without being parsed. After
+//! an import the meshes and the material definitions are written with bincode next to the
+//! file, as `<file>.meshcache`, and read instead of the OBJ for as long as the OBJ and its
+//! material libraries keep the size and modification time they had. The textures are still
+//! loaded from their files. The cache is compressed as chosen in the Outliner panel, see
+//! `compression`.
+//!
+//! Only native loads are cached; the web build keeps its downloads in IndexedDB, see `cache`.
+
+use std::{path::Path, time::SystemTime};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compression::{self, Compression},
+    loading::{LoadedMesh, ObjMaterial, ParsedObj},
+    model::ModelVertex,
+    vertex_layout::VertexData,
+};
+
+/// Bumped whenever what's cached changes, so caches of older builds are parsed again.
+const VERSION: u32 = 1;
+
+/// What a file looked like when it was cached.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Stamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Stamp {
+    fn of(path: &str) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedMesh {
+    name: String,
+    /// The `ModelVertex`es as their floats.
+    vertices: Vec<f32>,
+    indices: Vec<u32>,
+    material: Option<usize>,
+    attributes: Option<VertexData>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MeshCache {
+    version: u32,
+    /// The OBJ and the material libraries it names, with how they looked, `None` for the ones
+    /// that didn't exist.
+    files: Vec<(String, Option<Stamp>)>,
+    materials: Vec<ObjMaterial>,
+    meshes: Vec<CachedMesh>,
+    warnings: Vec<String>,
+}
+
+fn cache_path(source: &str) -> String {
+    format!("{}.meshcache", source)
+}
+
+/// The OBJ at `source` as cached, or `None` without a cache still matching its files.
+pub(crate) fn read(source: &str) -> Option<ParsedObj> {
+    profile_function!();
+    let path = cache_path(source);
+    if !Path::new(&path).exists() {
+        return None;
+    }
+    let cache = match std::fs::read(&path)
+        .map_err(Error::from)
+        .and_then(compression::decompress)
+        .and_then(|bytes| Ok(bincode::deserialize::<MeshCache>(&bytes)?))
+    {
+        Ok(cache) => cache,
+        Err(e) => {
+            log::warn!("Mesh cache {} not read: {:?}", path, e);
+            return None;
+        }
+    };
+    let fresh = cache.version == VERSION
+        && cache.files.first().map(|(file, _)| file.as_str()) == Some(source)
+        && cache.files.iter().all(|(file, stamp)| Stamp::of(file) == *stamp);
+    if !fresh {
+        return None;
+    }
+    let mut meshes = Vec::with_capacity(cache.meshes.len());
+    for mesh in cache.meshes {
+        let vertices = bytemuck::try_cast_slice::<f32, ModelVertex>(&mesh.vertices).ok()?;
+        meshes.push(LoadedMesh {
+            name: mesh.name,
+            vertices: vertices.to_vec(),
+            indices: mesh.indices,
+            material: mesh.material,
+            attributes: mesh.attributes,
+        });
+    }
+    Some(ParsedObj {
+        materials: cache.materials,
+        meshes,
+        warnings: cache.warnings,
+    })
+}
+
+/// Caches the OBJ at `source` as parsed from it and the material libraries at `libraries`.
+pub(crate) fn write(
+    source: &str,
+    libraries: &[String],
+    parsed: &ParsedObj,
+    compression: Compression,
+) -> Result<()> {
+    profile_function!();
+    let files = std::iter::once(source)
+        .chain(libraries.iter().map(String::as_str))
+        .map(|file| (file.to_string(), Stamp::of(file)))
+        .collect();
+    let meshes = parsed
+        .meshes
+        .iter()
+        .map(|mesh| CachedMesh {
+            name: mesh.name.clone(),
+            vertices: bytemuck::cast_slice(&mesh.vertices).to_vec(),
+            indices: mesh.indices.clone(),
+            material: mesh.material,
+            attributes: mesh.attributes.clone(),
+        })
+        .collect();
+    let cache = MeshCache {
+        version: VERSION,
+        files,
+        materials: parsed.materials.clone(),
+        meshes,
+        warnings: parsed.warnings.clone(),
+    };
+    let path = cache_path(source);
+    let bytes = compression::compress(bincode::serialize(&cache)?, compression)?;
+    std::fs::write(&path, bytes).with_context(|| format!("Can't write {}", path))
+}
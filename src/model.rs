@@ -1,13 +1,94 @@
+use crate::bounds::Aabb;
+use crate::bvh::Bvh;
 use crate::collection::Rungholt;
+use crate::gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked};
+use crate::instancing::InstanceBuffer;
+use crate::permutation::ShaderFeatures;
 use crate::scene::Scene;
 use crate::shader;
 use crate::texture;
+use crate::vertex_layout::VertexData;
 use anyhow::*;
 use std::ops::Range;
 use std::path::Path;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::RwLock;
-use wgpu::util::DeviceExt;
+
+/// Smooth normals, the area weighted sum of the faces around each vertex. Vertices without
+/// any faces keep theirs.
+pub fn compute_normals(vertices: &mut [ModelVertex], indices: &[u32]) {
+    use cgmath::InnerSpace;
+    let mut normals = vec![cgmath::Vector3::new(0.0f32, 0.0, 0.0); vertices.len()];
+    for c in indices.chunks_exact(3) {
+        let [a, b, d] = [0, 1, 2].map(|i| cgmath::Point3::from(vertices[c[i] as usize].position));
+        let normal = (b - a).cross(d - a);
+        for &i in c {
+            normals[i as usize] += normal;
+        }
+    }
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        if normal.magnitude2() > 0. {
+            vertex.normal = normal.normalize().into();
+        }
+    }
+}
+
+/// Fills in tangents and bitangents from the texture coordinates of each triangle.
+pub fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    for c in indices.chunks(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let p0: cgmath::Point3<_> = v0.position.into();
+        let p1: cgmath::Point3<_> = v1.position.into();
+        let p2: cgmath::Point3<_> = v2.position.into();
+
+        let w0: cgmath::Point2<_> = v0.tex_coords.into();
+        let w1: cgmath::Point2<_> = v1.tex_coords.into();
+        let w2: cgmath::Point2<_> = v2.tex_coords.into();
+
+        let dp1 = p1 - p0;
+        let dp2 = p2 - p0;
+
+        let dw1 = w1 - w0;
+        let dw2 = w2 - w0;
+
+        let r = 1.0 / (dw1.x * dw2.y - dw1.y * dw2.x);
+        let tangent = (dp1 * dw2.y - dp2 * dw1.y) * r;
+        let bitangent = (dp2 * dw1.x - dp1 * dw2.x) * r;
+
+        vertices[c[0] as usize].tangent = tangent.into();
+        vertices[c[1] as usize].tangent = tangent.into();
+        vertices[c[2] as usize].tangent = tangent.into();
+
+        vertices[c[0] as usize].bitangent = bitangent.into();
+        vertices[c[1] as usize].bitangent = bitangent.into();
+        vertices[c[2] as usize].bitangent = bitangent.into();
+    }
+}
+
+/// Moves the vertices by the matrix. Normals are transformed by its inverse transpose, tangents
+/// and bitangents like positions, so they keep following the surface.
+pub fn transform_vertices(vertices: &mut [ModelVertex], matrix: cgmath::Matrix4<f32>) {
+    use cgmath::{InnerSpace, Matrix, SquareMatrix, Transform};
+    let linear = cgmath::Matrix3::from_cols(
+        matrix.x.truncate(),
+        matrix.y.truncate(),
+        matrix.z.truncate(),
+    );
+    let normal_matrix = linear.invert().unwrap_or(linear).transpose();
+    for v in vertices.iter_mut() {
+        v.position = matrix.transform_point(v.position.into()).into();
+        let normal = normal_matrix * cgmath::Vector3::from(v.normal);
+        if normal.magnitude2() > 0. {
+            v.normal = normal.normalize().into();
+        }
+        v.tangent = (linear * cgmath::Vector3::from(v.tangent)).into();
+        v.bitangent = (linear * cgmath::Vector3::from(v.bitangent)).into();
+    }
+}
 
 pub trait Vertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
@@ -16,11 +97,11 @@ pub trait Vertex {
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelVertex {
-    position: [f32; 3],
-    tex_coords: [f32; 2],
-    normal: [f32; 3],
-    tangent: [f32; 3],
-    bitangent: [f32; 3],
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 3],
+    pub bitangent: [f32; 3],
 }
 
 impl Vertex for ModelVertex {
@@ -75,16 +156,37 @@ impl Model {
             Model::HOUSE(ref m) => &m.meshes,
         }
     }
+
+    pub fn meshes_mut(&mut self) -> &mut Vec<Mesh> {
+        match self {
+            Model::OBJ(ref mut m) => &mut m.meshes,
+            Model::GLTF(ref mut m) => &mut m.meshes,
+            Model::HOUSE(ref mut m) => &mut m.meshes,
+        }
+    }
+
+    /// The bounds of all meshes together.
+    pub fn bounds(&self) -> Aabb {
+        self.meshes()
+            .iter()
+            .fold(Aabb::EMPTY, |bounds, mesh| bounds.union(&mesh.bounds))
+    }
+
+    /// Ids of the meshes' buffers in the GPU memory registry.
+    pub fn buffer_ids(&self) -> Vec<u64> {
+        self.meshes().iter().flat_map(Mesh::buffer_ids).collect()
+    }
 }
 #[derive(Debug)]
 pub struct ObjModel {
     pub meshes: Vec<Mesh>,
 }
 
+/// A model read from a glTF file, see `gltf_import`.
 #[derive(Debug)]
 pub struct GltfModel {
     pub meshes: Vec<Mesh>,
-    pub materials: Vec<Material>,
+    pub materials: Vec<Arc<Material>>,
 }
 
 impl ObjModel {
@@ -110,13 +212,23 @@ impl ObjModel {
 
         let mut material_keys = Vec::new();
 
+        // Texture files that can't be loaded are drawn with the missing placeholder.
+        let load = |name: &str, is_normal_map| {
+            let path = containing_folder.join(name);
+            match texture::Texture::load(device, queue, &path, is_normal_map) {
+                Ok(texture) => Arc::new(texture),
+                Err(e) => {
+                    log::warn!("Texture {} not loaded: {:?}", path.display(), e);
+                    scene.placeholders.missing.clone()
+                }
+            }
+        };
+
         let mut materials = Vec::new();
         for (i, mat) in obj_materials.unwrap().into_iter().enumerate() {
             let diffuse_path = &mat.diffuse_texture;
             let diffuse_texture = if !diffuse_path.is_empty() {
-                texture::Texture::load(device, queue, containing_folder.join(diffuse_path), false)
-                    .with_context(|| format!("Diffuse texture: {} not found", diffuse_path))?
-                // .unwrap_or_else(|_| panic!("Diffuse texture: {} not found", diffuse_path))
+                load(diffuse_path, false)
             } else {
                 let mut diffuse_color = mat
                     .diffuse
@@ -124,33 +236,25 @@ impl ObjModel {
                     .map(|i| (i * 255.) as u8)
                     .collect::<Vec<u8>>();
                 diffuse_color.push(0xff);
-                texture::Texture::one_pixel(
+                Arc::new(texture::Texture::one_pixel(
                     device,
                     queue,
                     &diffuse_color,
                     Some("diffuse texture"),
                     true,
-                )
+                ))
             };
 
             let normal_path = &mat.normal_texture;
             let normal_texture = if !normal_path.is_empty() {
-                texture::Texture::load(device, queue, containing_folder.join(normal_path), true)
-                    .with_context(|| format!("Normal texture: {} not found", normal_path))?
+                load(normal_path, true)
             } else {
-                texture::Texture::one_pixel(
-                    device,
-                    queue,
-                    &[0x80, 0x80, 0xff, 0],
-                    Some("default normal texture"),
-                    true,
-                )
+                scene.placeholders.flat_normal.clone()
             };
 
             let specular_path = &mat.specular_texture;
             let specular_texture = if !specular_path.is_empty() {
-                texture::Texture::load(device, queue, containing_folder.join(specular_path), false)
-                    .with_context(|| format!("Diffuse texture: {} not found", specular_path))?
+                load(specular_path, false)
             } else {
                 let mut specular_color = mat
                     .specular
@@ -158,13 +262,21 @@ impl ObjModel {
                     .map(|i| (i * 255.) as u8)
                     .collect::<Vec<u8>>();
                 specular_color.push(0xff);
-                texture::Texture::one_pixel(
+                Arc::new(texture::Texture::one_pixel(
                     device,
                     queue,
                     &specular_color,
                     Some("specular texture"),
                     true,
-                )
+                ))
+            };
+
+            // OBJ has no occlusion map, the ambient map is the closest.
+            let occlusion_path = &mat.ambient_texture;
+            let occlusion_texture = if !occlusion_path.is_empty() {
+                load(occlusion_path, false)
+            } else {
+                scene.placeholders.white.clone()
             };
 
             let shader_key = std::path::Path::new(env!("OUT_DIR"))
@@ -184,7 +296,9 @@ impl ObjModel {
                         &scene.renderer.texture_bind_group_layout,
                         &scene.lights.lights[0].bind_group_layout,
                         &scene.renderer.uniforms.bind_group_layout,
+                        &scene.renderer.shadows.bind_group_layout,
                         &config.format,
+                        scene.renderer.config.depth_format.format(),
                     ))
                 })
                 .clone();
@@ -194,14 +308,16 @@ impl ObjModel {
                 .materials
                 .write()
                 .unwrap()
-                .entry(material_key.clone())
-                .or_insert_with(|| {
+                .get_or_insert_with(material_key.clone(), || {
                     Arc::new(Material::new(
                         device,
                         &mat.name,
                         diffuse_texture,
                         normal_texture,
                         specular_texture,
+                        occlusion_texture,
+                        scene.placeholders.black.clone(),
+                        ShaderFeatures::default(),
                         i as u32,
                         &scene.renderer.texture_bind_group_layout,
                         shader,
@@ -235,61 +351,44 @@ impl ObjModel {
 
             let indices = &m.mesh.indices;
 
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let p0: cgmath::Point3<_> = v0.position.into();
-                let p1: cgmath::Point3<_> = v1.position.into();
-                let p2: cgmath::Point3<_> = v2.position.into();
-
-                let w0: cgmath::Point2<_> = v0.tex_coords.into();
-                let w1: cgmath::Point2<_> = v1.tex_coords.into();
-                let w2: cgmath::Point2<_> = v2.tex_coords.into();
-
-                let dp1 = p1 - p0;
-                let dp2 = p2 - p0;
-
-                let dw1 = w1 - w0;
-                let dw2 = w2 - w0;
+            compute_tangents(&mut vertices, indices);
 
-                let r = 1.0 / (dw1.x * dw2.y - dw1.y * dw2.x);
-                let tangent = (dp1 * dw2.y - dp2 * dw1.y) * r;
-                let bitangent = (dp2 * dw1.x - dp1 * dw2.x) * r;
-
-                vertices[c[0] as usize].tangent = tangent.into();
-                vertices[c[1] as usize].tangent = tangent.into();
-                vertices[c[2] as usize].tangent = tangent.into();
-
-                vertices[c[0] as usize].bitangent = bitangent.into();
-                vertices[c[1] as usize].bitangent = bitangent.into();
-                vertices[c[2] as usize].bitangent = bitangent.into();
-            }
-
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", path.as_ref())),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", path.as_ref())),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
+            let vertex_buffer = device.create_tracked_buffer_init(
+                GpuCategory::Meshes,
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Vertex Buffer", path.as_ref())),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                },
+            );
+            let index_buffer = device.create_tracked_buffer_init(
+                GpuCategory::Meshes,
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Index Buffer", path.as_ref())),
+                    contents: bytemuck::cast_slice(&m.mesh.indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                },
+            );
 
             meshes.push(Mesh {
                 name: m.name,
                 vertex_buffer,
                 index_buffer,
                 num_elements: m.mesh.indices.len() as u32,
+                num_vertices: vertices.len() as u32,
                 material: scene
                     .materials
                     .read()
                     .unwrap()
-                    .get(&material_keys[m.mesh.material_id.unwrap()])
+                    .by_name(&material_keys[m.mesh.material_id.unwrap()])
                     .unwrap()
                     .clone(),
+                bounds: Aabb::from_points(vertices.iter().map(|v| v.position.into())),
+                vertices,
+                indices: m.mesh.indices,
+                dirty_vertices: None,
+                bvh: Mutex::new(None),
+                extra: None,
             });
         }
 
@@ -301,150 +400,19 @@ impl ObjModel {
     //}
 }
 
-//impl GltfModel {
-//    pub async fn load<P: AsRef<Path>>(
-//        device: &wgpu::Device,
-//        queue: &wgpu::Queue,
-//        layout: &wgpu::BindGroupLayout,
-//        path: P,
-//    ) -> Result<Self> {
-//        let (gltf, buffers, _) = tokio::task::block_in_place(|| gltf::import(path.as_ref()))?;
-//
-//        let materials = gltf
-//            .materials()
-//            .flat_map(|material| {
-//                //let materials = gltf.materials().par_bridge().map(|material| {
-//                if let Some(base_color_texture) =
-//                    material.pbr_metallic_roughness().base_color_texture()
-//                {
-//                    let diffuse_texture =
-//                        texture::Texture::load_gltf(device, queue, &base_color_texture, &buffers)
-//                            .unwrap();
-//
-//                    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-//                        layout,
-//                        entries: &[
-//                            wgpu::BindGroupEntry {
-//                                binding: 0,
-//                                resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-//                            },
-//                            wgpu::BindGroupEntry {
-//                                binding: 1,
-//                                resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-//                            },
-//                        ],
-//                        label: None,
-//                    });
-//
-//                    Some(Material {
-//                        name: material.name().unwrap().to_string(),
-//                        diffuse_texture,
-//                        bind_group,
-//                        id: material
-//                            .pbr_metallic_roughness()
-//                            .base_color_texture()
-//                            .unwrap()
-//                            .texture()
-//                            .index() as u32,
-//                    })
-//                } else {
-//                    None
-//                }
-//            })
-//            .collect();
-//
-//        let label_path = path.as_ref().to_str().map(|str| str.to_string());
-//
-//        //let meshes = gltf.meshes().map(|mesh| {
-//        let meshes = gltf
-//            .meshes()
-//            .par_bridge()
-//            .map(|mesh| {
-//                println!("Mesh #{}", mesh.index());
-//                //mesh.primitives().map(|primitive| {
-//                mesh.primitives()
-//                    .par_bridge()
-//                    .map(|primitive| {
-//                        println!("- Primitive #{}", primitive.index());
-//                        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-//                        let vertex_iter = reader.read_positions().unwrap();
-//
-//                        let tex_coord = primitive
-//                            .material()
-//                            .pbr_metallic_roughness()
-//                            .base_color_texture()
-//                            .unwrap()
-//                            .tex_coord();
-//                        let tex_coords_iter = match reader.read_tex_coords(tex_coord) {
-//                            Some(gltf::mesh::util::ReadTexCoords::F32(tex_coords_iter)) => {
-//                                tex_coords_iter
-//                            }
-//                            _ => panic!(),
-//                        };
-//
-//                        let normal_iter = reader.read_normals().unwrap();
-//                        let iter = izip!(vertex_iter, tex_coords_iter, normal_iter);
-//
-//                        // par_iter() は順序が維持されるが、par_bridge()は維持されない。
-//                        // par_iter()を使うためには、IntoParallelIteratorを実装する必要がある。
-//                        let vertices = iter
-//                            .map(|vertex| ModelVertex {
-//                                position: [vertex.0[0], vertex.0[1], vertex.0[2]],
-//                                tex_coords: [vertex.1[0], vertex.1[1]],
-//                                normal: [vertex.2[0], vertex.2[1], vertex.2[2]],
-//                            })
-//                            .collect::<Vec<_>>();
-//                        let vertex_buffer =
-//                            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-//                                label: Some(&format!("{:?} Vertex Buffer", label_path)),
-//                                contents: bytemuck::cast_slice(&vertices),
-//                                usage: wgpu::BufferUsage::VERTEX,
-//                            });
-//                        let indices =
-//                            if let Some(gltf::mesh::util::ReadIndices::U32(indices_iter)) =
-//                                reader.read_indices()
-//                            {
-//                                indices_iter.collect::<Vec<_>>()
-//                            } else {
-//                                Vec::new()
-//                            };
-//
-//                        let index_buffer =
-//                            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-//                                label: Some(&format!("{:?} Index Buffer", label_path)),
-//                                contents: bytemuck::cast_slice(&indices),
-//                                usage: wgpu::BufferUsage::INDEX,
-//                            });
-//
-//                        Mesh {
-//                            name: mesh.name().unwrap().to_string(),
-//                            vertex_buffer,
-//                            index_buffer,
-//                            num_elements: primitive.indices().unwrap().count() as u32,
-//                            material: primitive
-//                                .material()
-//                                .pbr_metallic_roughness()
-//                                .base_color_texture()
-//                                .unwrap()
-//                                .texture()
-//                                .index() as u32,
-//                        }
-//                    })
-//                    .collect::<Vec<_>>()
-//            })
-//            .flatten()
-//            .collect();
-//
-//        Ok(Self { meshes, materials })
-//    }
-//}
-
 #[derive(Debug)]
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: texture::Texture,
-    pub normal_texture: texture::Texture,
-    pub specular_texture: texture::Texture,
+    pub diffuse_texture: Arc<texture::Texture>,
+    pub normal_texture: Arc<texture::Texture>,
+    pub specular_texture: Arc<texture::Texture>,
+    /// Ambient occlusion in the red channel, darkening the ambient light.
+    pub occlusion_texture: Arc<texture::Texture>,
+    /// Light the surface gives off, added by the PBR shader only.
+    pub emissive_texture: Arc<texture::Texture>,
+    /// What the material needs from the shader, also in a uniform for the uber shader.
+    pub features: ShaderFeatures,
+    pub features_buffer: Tracked<wgpu::Buffer>,
     pub id: u32,
     pub bind_group: wgpu::BindGroup,
     pub shader: Arc<shader::Shader>,
@@ -454,13 +422,24 @@ impl Material {
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        diffuse_texture: texture::Texture,
-        normal_texture: texture::Texture,
-        specular_texture: texture::Texture,
+        diffuse_texture: Arc<texture::Texture>,
+        normal_texture: Arc<texture::Texture>,
+        specular_texture: Arc<texture::Texture>,
+        occlusion_texture: Arc<texture::Texture>,
+        emissive_texture: Arc<texture::Texture>,
+        features: ShaderFeatures,
         id: u32,
         layout: &wgpu::BindGroupLayout,
         shader: Arc<shader::Shader>,
     ) -> Self {
+        let features_buffer = device.create_tracked_buffer_init(
+            GpuCategory::Uniforms,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Features Buffer", name)),
+                contents: bytemuck::cast_slice(&features.flags()),
+                usage: wgpu::BufferUsages::UNIFORM,
+            },
+        );
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &layout,
             entries: &[
@@ -488,6 +467,26 @@ impl Material {
                     binding: 5,
                     resource: wgpu::BindingResource::Sampler(&specular_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&occlusion_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::Sampler(&occlusion_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: features_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::TextureView(&emissive_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::Sampler(&emissive_texture.sampler),
+                },
             ],
             label: None,
         });
@@ -497,6 +496,10 @@ impl Material {
             diffuse_texture,
             normal_texture,
             specular_texture,
+            occlusion_texture,
+            emissive_texture,
+            features,
+            features_buffer,
             bind_group,
             id,
             shader,
@@ -507,41 +510,222 @@ impl Material {
 #[derive(Debug)]
 pub struct Mesh {
     pub name: String,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
+    pub vertex_buffer: Tracked<wgpu::Buffer>,
+    pub index_buffer: Tracked<wgpu::Buffer>,
     pub num_elements: u32,
+    pub num_vertices: u32,
     pub material: Arc<Material>,
+    /// CPU copies of the uploaded geometry, used for picking.
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub bounds: Aabb,
+    /// Vertices edited without access to the queue, uploaded by `flush_vertices`.
+    dirty_vertices: Option<Range<usize>>,
+    /// The triangles of the CPU copy for picking, built for the first ray after the vertices
+    /// last changed.
+    bvh: Mutex<Option<Arc<Bvh>>>,
+    /// Attributes the file had beyond the ones of `ModelVertex`.
+    pub extra: Option<ExtraVertices>,
+}
+
+/// The second vertex buffer of a mesh with more attributes, drawn with the shader permutation
+/// reading them instead of the material's.
+#[derive(Debug)]
+pub struct ExtraVertices {
+    pub data: VertexData,
+    pub buffer: Tracked<wgpu::Buffer>,
+    pub shader: Arc<shader::Shader>,
+}
+
+impl Mesh {
+    /// Ids of the vertex and index buffers in the GPU memory registry.
+    pub fn buffer_ids(&self) -> Vec<u64> {
+        let mut ids = vec![self.vertex_buffer.id(), self.index_buffer.id()];
+        ids.extend(self.extra.iter().map(|extra| extra.buffer.id()));
+        ids
+    }
+
+    /// A mesh of the given geometry, e.g. a piece split off another mesh.
+    pub fn new(
+        device: &wgpu::Device,
+        name: String,
+        vertices: Vec<ModelVertex>,
+        indices: Vec<u32>,
+        material: Arc<Material>,
+    ) -> Self {
+        let vertex_buffer = device.create_tracked_buffer_init(
+            GpuCategory::Meshes,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Vertex Buffer", name)),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+        let index_buffer = device.create_tracked_buffer_init(
+            GpuCategory::Meshes,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Index Buffer", name)),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            },
+        );
+        Self {
+            name,
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            num_vertices: vertices.len() as u32,
+            material,
+            bounds: Aabb::from_points(vertices.iter().map(|v| v.position.into())),
+            vertices,
+            indices,
+            dirty_vertices: None,
+            bvh: Mutex::new(None),
+            extra: None,
+        }
+    }
+
+    /// Gives the vertices the extra attributes, one per vertex, drawn with `shader` which must
+    /// be made for their layout.
+    pub fn set_extra(
+        &mut self,
+        device: &wgpu::Device,
+        data: VertexData,
+        shader: Arc<shader::Shader>,
+    ) {
+        assert_eq!(data.len(), self.vertices.len());
+        let buffer = device.create_tracked_buffer_init(
+            GpuCategory::Meshes,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Extra Vertex Buffer", self.name)),
+                contents: bytemuck::cast_slice(data.values()),
+                usage: wgpu::BufferUsages::VERTEX,
+            },
+        );
+        self.extra = Some(ExtraVertices {
+            data,
+            buffer,
+            shader,
+        });
+    }
+
+    /// Replaces the vertex data on the GPU and the CPU copy, e.g. after a simulation step.
+    /// The vertex count must stay the same.
+    pub fn upload_vertices(&mut self, queue: &wgpu::Queue, vertices: &[ModelVertex]) {
+        assert_eq!(vertices.len(), self.vertices.len());
+        self.vertices.copy_from_slice(vertices);
+        self.bounds = Aabb::from_points(vertices.iter().map(|v| v.position.into()));
+        self.drop_bvh();
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(vertices));
+    }
+
+    /// Uploads only the given range of the CPU copy after it was edited in place.
+    pub fn upload_vertex_range(&mut self, queue: &wgpu::Queue, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        for v in self.vertices[range.clone()].iter() {
+            self.bounds = self.bounds.extend(v.position.into());
+        }
+        self.drop_bvh();
+        let offset = (range.start * std::mem::size_of::<ModelVertex>()) as wgpu::BufferAddress;
+        queue.write_buffer(
+            &self.vertex_buffer,
+            offset,
+            bytemuck::cast_slice(&self.vertices[range]),
+        );
+    }
+
+    /// Records that the range of the CPU copy was edited in place, to be uploaded later.
+    pub fn mark_vertices_dirty(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        self.drop_bvh();
+        self.dirty_vertices = Some(match self.dirty_vertices.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
+
+    pub fn flush_vertices(&mut self, queue: &wgpu::Queue) {
+        if let Some(range) = self.dirty_vertices.take() {
+            self.upload_vertex_range(queue, range);
+        }
+    }
+
+    /// Swaps in geometry with a different vertex or index count, e.g. after remeshing. The
+    /// extra attributes no longer match the vertices and are dropped.
+    pub fn replace_geometry(
+        &mut self,
+        device: &wgpu::Device,
+        vertices: Vec<ModelVertex>,
+        indices: Vec<u32>,
+    ) {
+        self.vertex_buffer = device.create_tracked_buffer_init(
+            GpuCategory::Meshes,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Vertex Buffer", self.name)),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+        self.index_buffer = device.create_tracked_buffer_init(
+            GpuCategory::Meshes,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Index Buffer", self.name)),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            },
+        );
+        self.num_elements = indices.len() as u32;
+        self.num_vertices = vertices.len() as u32;
+        self.bounds = Aabb::from_points(vertices.iter().map(|v| v.position.into()));
+        self.vertices = vertices;
+        self.indices = indices;
+        self.dirty_vertices = None;
+        self.drop_bvh();
+        self.extra = None;
+    }
+
+    /// The hierarchy of the mesh's triangles for picking, built again after the vertices
+    /// changed.
+    pub fn bvh(&self) -> Arc<Bvh> {
+        let mut bvh = self.bvh.lock().unwrap();
+        bvh.get_or_insert_with(|| {
+            let positions = self
+                .vertices
+                .iter()
+                .map(|v| v.position.into())
+                .collect::<Vec<_>>();
+            Arc::new(Bvh::from_indexed(&positions, &self.indices))
+        })
+        .clone()
+    }
+
+    fn drop_bvh(&mut self) {
+        *self.bvh.get_mut().unwrap() = None;
+    }
 }
 
+/// Draws with the model pipelines, which read every instance of the instance buffer. Models
+/// drawn once are drawn with `Instancing`'s single instance.
 pub trait DrawModel<'a, 'b>
 where
     'b: 'a,
 {
-    fn draw_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        material: &Option<&'b Material>,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    );
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
         material: &Option<&'b Material>,
-        instances: Range<u32>,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    );
-    fn draw_model(
-        &mut self,
-        model: &'b Model,
+        instances: &'b InstanceBuffer,
         uniforms: &'b wgpu::BindGroup,
         light: &'b wgpu::BindGroup,
     );
     fn draw_model_instanced(
         &mut self,
         model: &'b Model,
-        instances: Range<u32>,
+        instances: &'b InstanceBuffer,
         uniforms: &'b wgpu::BindGroup,
         light: &'b wgpu::BindGroup,
     );
@@ -550,25 +734,19 @@ impl<'a, 'b> DrawModel<'a, 'b> for wgpu::RenderPass<'a>
 where
     'b: 'a,
 {
-    fn draw_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        material: &Option<&'b Material>,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    ) {
-        self.draw_mesh_instanced(mesh, material, 0..1, uniforms, light);
-    }
-
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
         material: &Option<&'b Material>,
-        instances: Range<u32>,
+        instances: &'b InstanceBuffer,
         uniforms: &'b wgpu::BindGroup,
         light: &'b wgpu::BindGroup,
     ) {
+        if instances.count == 0 {
+            return;
+        }
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_vertex_buffer(1, instances.buffer.slice(..));
         self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         match material {
             Some(m) => {
@@ -579,34 +757,24 @@ where
                 todo!();
             }
         }
+        if let Some(extra) = &mesh.extra {
+            self.set_vertex_buffer(2, extra.buffer.slice(..));
+            self.set_pipeline(&extra.shader.render_pipeline);
+        }
         self.set_bind_group(1, &uniforms, &[]);
         self.set_bind_group(2, &light, &[]);
-        self.draw_indexed(0..mesh.num_elements, 0, instances);
-    }
-    fn draw_model(
-        &mut self,
-        model: &'b Model,
-        uniforms: &'b wgpu::BindGroup,
-        light: &'b wgpu::BindGroup,
-    ) {
-        self.draw_model_instanced(model, 0..1, uniforms, light);
+        self.draw_indexed(0..mesh.num_elements, 0, 0..instances.count);
     }
 
     fn draw_model_instanced(
         &mut self,
         model: &'b Model,
-        instances: Range<u32>,
+        instances: &'b InstanceBuffer,
         uniforms: &'b wgpu::BindGroup,
         light: &'b wgpu::BindGroup,
     ) {
         for mesh in model.meshes() {
-            self.draw_mesh_instanced(
-                mesh,
-                &Some(&mesh.material),
-                instances.clone(),
-                uniforms,
-                light,
-            );
+            self.draw_mesh_instanced(mesh, &Some(&mesh.material), instances, uniforms, light);
         }
     }
 }
@@ -789,6 +957,21 @@ impl House {
                 )
             };
 
+            // OBJ has no occlusion map, the ambient map is the closest.
+            let occlusion_path = &mat.ambient_texture;
+            let occlusion_texture = if !occlusion_path.is_empty() {
+                texture::Texture::load(device, queue, containing_folder.join(occlusion_path), false)
+                    .with_context(|| format!("Occlusion texture: {} not found", occlusion_path))?
+            } else {
+                texture::Texture::one_pixel(
+                    device,
+                    queue,
+                    &[0xff; 4],
+                    Some("default occlusion texture"),
+                    true,
+                )
+            };
+
             let shader_key = std::path::Path::new(env!("OUT_DIR"))
                 .join("shader")
                 .to_string_lossy()
@@ -806,7 +989,9 @@ impl House {
                         &scene.renderer.texture_bind_group_layout,
                         &scene.lights.lights[0].bind_group_layout,
                         &scene.renderer.uniforms.bind_group_layout,
+                        &scene.renderer.shadows.bind_group_layout,
                         &config.format,
+                        scene.renderer.config.depth_format.format(),
                     ))
                 })
                 .clone();
@@ -816,14 +1001,16 @@ impl House {
                 .materials
                 .write()
                 .unwrap()
-                .entry(material_key.clone())
-                .or_insert_with(|| {
+                .get_or_insert_with(material_key.clone(), || {
                     Arc::new(Material::new(
                         device,
                         &mat.name,
-                        diffuse_texture,
-                        normal_texture,
-                        specular_texture,
+                        Arc::new(diffuse_texture),
+                        Arc::new(normal_texture),
+                        Arc::new(specular_texture),
+                        Arc::new(occlusion_texture),
+                        scene.placeholders.black.clone(),
+                        ShaderFeatures::default(),
                         i as u32,
                         &scene.renderer.texture_bind_group_layout,
                         shader,
@@ -857,61 +1044,44 @@ impl House {
 
             let indices = &m.mesh.indices;
 
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let p0: cgmath::Point3<_> = v0.position.into();
-                let p1: cgmath::Point3<_> = v1.position.into();
-                let p2: cgmath::Point3<_> = v2.position.into();
-
-                let w0: cgmath::Point2<_> = v0.tex_coords.into();
-                let w1: cgmath::Point2<_> = v1.tex_coords.into();
-                let w2: cgmath::Point2<_> = v2.tex_coords.into();
-
-                let dp1 = p1 - p0;
-                let dp2 = p2 - p0;
-
-                let dw1 = w1 - w0;
-                let dw2 = w2 - w0;
-
-                let r = 1.0 / (dw1.x * dw2.y - dw1.y * dw2.x);
-                let tangent = (dp1 * dw2.y - dp2 * dw1.y) * r;
-                let bitangent = (dp2 * dw1.x - dp1 * dw2.x) * r;
+            compute_tangents(&mut vertices, indices);
 
-                vertices[c[0] as usize].tangent = tangent.into();
-                vertices[c[1] as usize].tangent = tangent.into();
-                vertices[c[2] as usize].tangent = tangent.into();
-
-                vertices[c[0] as usize].bitangent = bitangent.into();
-                vertices[c[1] as usize].bitangent = bitangent.into();
-                vertices[c[2] as usize].bitangent = bitangent.into();
-            }
-
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", path.as_ref())),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", path.as_ref())),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
+            let vertex_buffer = device.create_tracked_buffer_init(
+                GpuCategory::Meshes,
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Vertex Buffer", path.as_ref())),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                },
+            );
+            let index_buffer = device.create_tracked_buffer_init(
+                GpuCategory::Meshes,
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Index Buffer", path.as_ref())),
+                    contents: bytemuck::cast_slice(&m.mesh.indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                },
+            );
 
             meshes.push(Mesh {
                 name: m.name,
                 vertex_buffer,
                 index_buffer,
                 num_elements: m.mesh.indices.len() as u32,
+                num_vertices: vertices.len() as u32,
                 material: scene
                     .materials
                     .read()
                     .unwrap()
-                    .get(&material_keys[m.mesh.material_id.unwrap()])
+                    .by_name(&material_keys[m.mesh.material_id.unwrap()])
                     .unwrap()
                     .clone(),
+                bounds: Aabb::from_points(vertices.iter().map(|v| v.position.into())),
+                vertices,
+                indices: m.mesh.indices,
+                dirty_vertices: None,
+                bvh: Mutex::new(None),
+                extra: None,
             });
         }
 
@@ -0,0 +1,324 @@
+use cgmath::{InnerSpace, Matrix, Matrix3, Point3, SquareMatrix, Vector3, Zero};
+
+use crate::{
+    bounds::Aabb,
+    debug_lines::DebugLines,
+    model::{Model, ModelVertex},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    pub fn index(&self) -> usize {
+        match self {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Axis::X => "X",
+            Axis::Y => "Y",
+            Axis::Z => "Z",
+        }
+    }
+}
+
+/// Free form deformation: a grid of control points spanning the object, each moving the
+/// surface around it by its offset, blended with Bernstein polynomials.
+#[derive(Debug, Clone)]
+pub struct Lattice {
+    pub resolution: [usize; 3],
+    /// Displacement of every control point, `x` varying fastest.
+    pub offsets: Vec<Vector3<f32>>,
+}
+
+impl Lattice {
+    pub fn new(resolution: [usize; 3]) -> Self {
+        let resolution = resolution.map(|r| r.max(2));
+        Self {
+            resolution,
+            offsets: vec![Vector3::zero(); resolution[0] * resolution[1] * resolution[2]],
+        }
+    }
+
+    pub fn index(&self, i: usize, j: usize, k: usize) -> usize {
+        (k * self.resolution[1] + j) * self.resolution[0] + i
+    }
+
+    /// Rest position of a control point inside the bounds.
+    pub fn control_point(&self, bounds: &Aabb, i: usize, j: usize, k: usize) -> Point3<f32> {
+        let size = bounds.size();
+        let [rx, ry, rz] = self.resolution;
+        bounds.min
+            + Vector3::new(
+                size.x * i as f32 / (rx - 1) as f32,
+                size.y * j as f32 / (ry - 1) as f32,
+                size.z * k as f32 / (rz - 1) as f32,
+            )
+    }
+
+    fn deform(&self, bounds: &Aabb, p: Point3<f32>) -> Point3<f32> {
+        let size = bounds.size();
+        let local = [0, 1, 2].map(|a| {
+            if size[a] > 0. {
+                ((p[a] - bounds.min[a]) / size[a]).max(0.).min(1.)
+            } else {
+                0.
+            }
+        });
+        let weights = [0, 1, 2].map(|a| bernstein(self.resolution[a] - 1, local[a]));
+        let mut offset = Vector3::zero();
+        for k in 0..self.resolution[2] {
+            for j in 0..self.resolution[1] {
+                for i in 0..self.resolution[0] {
+                    let w = weights[0][i] * weights[1][j] * weights[2][k];
+                    offset += self.offsets[self.index(i, j, k)] * w;
+                }
+            }
+        }
+        p + offset
+    }
+
+    fn draw(&self, bounds: &Aabb, lines: &mut DebugLines) {
+        const COLOR: [f32; 3] = [0.8, 0.4, 1.0];
+        let [rx, ry, rz] = self.resolution;
+        let point = |i, j, k| self.control_point(bounds, i, j, k) + self.offsets[self.index(i, j, k)];
+        for k in 0..rz {
+            for j in 0..ry {
+                for i in 0..rx {
+                    if i + 1 < rx {
+                        lines.line(point(i, j, k), point(i + 1, j, k), COLOR);
+                    }
+                    if j + 1 < ry {
+                        lines.line(point(i, j, k), point(i, j + 1, k), COLOR);
+                    }
+                    if k + 1 < rz {
+                        lines.line(point(i, j, k), point(i, j, k + 1), COLOR);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// All Bernstein basis polynomials of the given degree evaluated at `t`.
+fn bernstein(degree: usize, t: f32) -> Vec<f32> {
+    let mut binomial = 1.0f32;
+    (0..=degree)
+        .map(|i| {
+            if i > 0 {
+                binomial = binomial * (degree + 1 - i) as f32 / i as f32;
+            }
+            binomial * t.powi(i as i32) * (1. - t).powi((degree - i) as i32)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub enum Deform {
+    /// Curls the object along `axis` toward the next axis by `angle` radians over its length.
+    Bend { axis: Axis, angle: f32 },
+    /// Rotates around `axis` by `angle` radians over the object's length.
+    Twist { axis: Axis, angle: f32 },
+    /// Scales across `axis`, growing linearly by `factor` from one end to the other.
+    Taper { axis: Axis, factor: f32 },
+    Lattice(Lattice),
+}
+
+impl Deform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Deform::Bend { .. } => "Bend",
+            Deform::Twist { .. } => "Twist",
+            Deform::Taper { .. } => "Taper",
+            Deform::Lattice(_) => "Lattice",
+        }
+    }
+
+    /// Moves a point. Parameters are relative to the bounds of the undeformed object.
+    pub fn deform(&self, bounds: &Aabb, p: Point3<f32>) -> Point3<f32> {
+        let center = bounds.center();
+        let size = bounds.size();
+        match self {
+            Deform::Bend { axis, angle } => {
+                let a = axis.index();
+                let b = (a + 1) % 3;
+                if angle.abs() < 1e-6 || size[a] <= 0. {
+                    return p;
+                }
+                let radius = size[a] / angle;
+                let phi = (p[a] - bounds.min[a]) / size[a] * angle;
+                let w = p[b] - center[b];
+                let mut q = p;
+                q[a] = bounds.min[a] + (radius - w) * phi.sin();
+                q[b] = center[b] + radius - (radius - w) * phi.cos();
+                q
+            }
+            Deform::Twist { axis, angle } => {
+                let a = axis.index();
+                let (b, c) = ((a + 1) % 3, (a + 2) % 3);
+                if size[a] <= 0. {
+                    return p;
+                }
+                let phi = (p[a] - center[a]) / size[a] * angle;
+                let (sin, cos) = phi.sin_cos();
+                let (u, v) = (p[b] - center[b], p[c] - center[c]);
+                let mut q = p;
+                q[b] = center[b] + u * cos - v * sin;
+                q[c] = center[c] + u * sin + v * cos;
+                q
+            }
+            Deform::Taper { axis, factor } => {
+                let a = axis.index();
+                if size[a] <= 0. {
+                    return p;
+                }
+                let scale = 1. + factor * (p[a] - bounds.min[a]) / size[a];
+                let mut q = p;
+                for i in (0..3).filter(|&i| i != a) {
+                    q[i] = center[i] + (p[i] - center[i]) * scale;
+                }
+                q
+            }
+            Deform::Lattice(lattice) => lattice.deform(bounds, p),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Modifier {
+    pub deform: Deform,
+    pub enabled: bool,
+}
+
+/// Modifiers of one model, applied in order to its original vertices.
+#[derive(Debug)]
+pub struct ModifierStack {
+    pub model: usize,
+    pub modifiers: Vec<Modifier>,
+    pub show_lattice: bool,
+    rest: Vec<Vec<ModelVertex>>,
+    rest_bounds: Aabb,
+    dirty: bool,
+}
+
+impl ModifierStack {
+    pub fn new(index: usize, model: &Model) -> Self {
+        let rest_bounds = model
+            .meshes()
+            .iter()
+            .fold(Aabb::EMPTY, |bounds, mesh| bounds.union(&mesh.bounds));
+        Self {
+            model: index,
+            modifiers: Vec::new(),
+            show_lattice: true,
+            rest: model.meshes().iter().map(|m| m.vertices.clone()).collect(),
+            rest_bounds,
+            dirty: false,
+        }
+    }
+
+    pub fn bounds(&self) -> &Aabb {
+        &self.rest_bounds
+    }
+
+    pub fn push(&mut self, deform: Deform) {
+        self.modifiers.push(Modifier {
+            deform,
+            enabled: true,
+        });
+        self.dirty = true;
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        self.modifiers.remove(index);
+        self.dirty = true;
+    }
+
+    /// Call after editing the modifiers so the model is deformed again on the next update.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn deform_point(&self, p: Point3<f32>) -> Point3<f32> {
+        self.modifiers
+            .iter()
+            .filter(|m| m.enabled)
+            .fold(p, |p, m| m.deform.deform(&self.rest_bounds, p))
+    }
+
+    /// Jacobian of the whole stack at `p`, by central differences.
+    fn jacobian(&self, p: Point3<f32>, epsilon: f32) -> Matrix3<f32> {
+        let column = |axis: Vector3<f32>| {
+            (self.deform_point(p + axis * epsilon) - self.deform_point(p - axis * epsilon))
+                / (2. * epsilon)
+        };
+        Matrix3::from_cols(
+            column(Vector3::unit_x()),
+            column(Vector3::unit_y()),
+            column(Vector3::unit_z()),
+        )
+    }
+
+    fn deform_vertex(&self, v: &ModelVertex, epsilon: f32) -> ModelVertex {
+        let p = Point3::from(v.position);
+        let jacobian = self.jacobian(p, epsilon);
+        // Normals transform with the inverse transpose to stay perpendicular to the surface.
+        let normal_matrix = jacobian
+            .invert()
+            .map(|m| m.transpose())
+            .unwrap_or_else(Matrix3::identity);
+        let transform = |m: &Matrix3<f32>, d: [f32; 3]| -> [f32; 3] {
+            let d = m * Vector3::from(d);
+            if d.magnitude2() > 0. {
+                d.normalize().into()
+            } else {
+                d.into()
+            }
+        };
+        ModelVertex {
+            position: self.deform_point(p).into(),
+            normal: transform(&normal_matrix, v.normal),
+            tangent: transform(&jacobian, v.tangent),
+            bitangent: transform(&jacobian, v.bitangent),
+            ..*v
+        }
+    }
+
+    /// Deforms the model's meshes if the stack changed since the last call.
+    pub fn apply(&mut self, model: &mut Model, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        let epsilon = (self.rest_bounds.size().magnitude() * 1e-4).max(1e-6);
+        for (mesh, rest) in model.meshes_mut().iter_mut().zip(self.rest.iter()) {
+            let vertices = rest
+                .iter()
+                .map(|v| self.deform_vertex(v, epsilon))
+                .collect::<Vec<_>>();
+            mesh.upload_vertices(queue, &vertices);
+        }
+    }
+
+    pub fn draw(&self, lines: &mut DebugLines) {
+        if !self.show_lattice {
+            return;
+        }
+        for modifier in self.modifiers.iter().filter(|m| m.enabled) {
+            if let Deform::Lattice(lattice) = &modifier.deform {
+                lattice.draw(&self.rest_bounds, lines);
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::*;
+use cgmath::{InnerSpace, Point3, Vector3, VectorSpace, Zero};
+
+use crate::{bvh::Bvh, picking::Ray, scene::Scene};
+
+/// What the normal tool does to the normals of the vertices it works on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalOperation {
+    /// Points them at the target point.
+    Toward,
+    /// Points them away from the target point, e.g. from the middle of a tree crown so its
+    /// leaf cards shade like one round volume.
+    Away,
+    /// Gives them all their average direction, so the surface shades flat.
+    Flatten,
+    /// Takes them from the closest surface of another model, e.g. a smooth proxy shape.
+    CopyFrom,
+}
+
+impl NormalOperation {
+    pub const ALL: [NormalOperation; 4] = [
+        NormalOperation::Toward,
+        NormalOperation::Away,
+        NormalOperation::Flatten,
+        NormalOperation::CopyFrom,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NormalOperation::Toward => "Point toward",
+            NormalOperation::Away => "Point away",
+            NormalOperation::Flatten => "Flatten",
+            NormalOperation::CopyFrom => "Copy from model",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NormalSettings {
+    pub operation: NormalOperation,
+    /// The point normals are turned toward or away from.
+    pub target: [f32; 3],
+    /// The model normals are copied from.
+    pub source: usize,
+    /// How far along the normals the source surface is searched, both ways.
+    pub max_distance: f32,
+    /// From 0 keeping the normals to 1 replacing them.
+    pub strength: f32,
+}
+
+impl Default for NormalSettings {
+    fn default() -> Self {
+        Self {
+            operation: NormalOperation::Away,
+            target: [0.; 3],
+            source: 0,
+            max_distance: 1.,
+            strength: 1.,
+        }
+    }
+}
+
+/// Triangles of a model with their vertex normals, to look up the normal of the surface at a
+/// point.
+struct NormalSource {
+    bvh: Bvh,
+    normals: Vec<[Vector3<f32>; 3]>,
+}
+
+impl NormalSource {
+    fn new(scene: &Scene, model: usize) -> Result<Self> {
+        let model = scene.models.get(model).context("There is no such model")?;
+        let mut triangles = Vec::new();
+        let mut normals = Vec::new();
+        for mesh in model.meshes() {
+            for c in mesh.indices.chunks_exact(3) {
+                let vertices = [0, 1, 2].map(|k| &mesh.vertices[c[k] as usize]);
+                triangles.push(vertices.map(|v| Point3::from(v.position)));
+                normals.push(vertices.map(|v| Vector3::from(v.normal)));
+            }
+        }
+        if triangles.is_empty() {
+            bail!("The model to copy the normals from has no triangles");
+        }
+        Ok(Self {
+            bvh: Bvh::new(triangles),
+            normals,
+        })
+    }
+
+    /// The normal of the closest surface along the line through `point` in `direction`.
+    fn normal(
+        &self,
+        point: Point3<f32>,
+        direction: Vector3<f32>,
+        max: f32,
+    ) -> Option<Vector3<f32>> {
+        let forward = Ray {
+            origin: point,
+            direction,
+        };
+        let backward = Ray {
+            origin: point,
+            direction: -direction,
+        };
+        let hit = match (
+            self.bvh.intersect(&forward, max),
+            self.bvh.intersect(&backward, max),
+        ) {
+            (Some(a), Some(b)) if b.distance < a.distance => b,
+            (Some(a), _) => a,
+            (None, b) => b?,
+        };
+        let [a, b, c] = self.normals[hit.triangle];
+        let normal = a * (1. - hit.u - hit.v) + b * hit.u + c * hit.v;
+        Some(normal).filter(|n| n.magnitude2() > 0.)
+    }
+}
+
+impl Scene {
+    /// The vertices the normal tool works on: the edit mode selection, or every vertex of the
+    /// selected models without one. As model, mesh and vertex indices.
+    pub fn normal_tool_vertices(&self) -> Vec<(usize, usize, Vec<usize>)> {
+        if let Some(selected) = self.edit.selected_vertices() {
+            return vec![selected];
+        }
+        self.outliner
+            .selected
+            .iter()
+            .filter_map(|&model| Some((model, self.models.get(model)?)))
+            .flat_map(|(model, m)| {
+                let meshes = m.meshes().iter().enumerate();
+                meshes.map(move |(mesh, m)| (model, mesh, (0..m.vertices.len()).collect()))
+            })
+            .collect()
+    }
+
+    /// Changes the normals of the vertices the tool works on. Shape keys and the polygon mesh
+    /// being edited keep the new normals, so they go into exports and survive later edits.
+    /// Returns the number of normals changed.
+    pub fn edit_normals(&mut self, settings: &NormalSettings) -> Result<usize> {
+        let mut targets = self.normal_tool_vertices();
+        let source = match settings.operation {
+            NormalOperation::CopyFrom => {
+                targets.retain(|(model, _, _)| *model != settings.source);
+                Some(NormalSource::new(self, settings.source)?)
+            }
+            _ => None,
+        };
+        let average = targets
+            .iter()
+            .flat_map(|(model, mesh, vertices)| {
+                let mesh = &self.models[*model].meshes()[*mesh];
+                vertices
+                    .iter()
+                    .map(move |&i| Vector3::from(mesh.vertices[i].normal))
+            })
+            .fold(Vector3::zero(), |sum, n| sum + n);
+        let target = Point3::from(settings.target);
+
+        let mut count = 0;
+        for (model, mesh, vertices) in targets {
+            let normals = {
+                let mesh = &self.models[model].meshes()[mesh];
+                vertices
+                    .into_iter()
+                    .filter_map(|i| {
+                        let vertex = &mesh.vertices[i];
+                        let (p, n) = (Point3::from(vertex.position), Vector3::from(vertex.normal));
+                        let wanted = match settings.operation {
+                            NormalOperation::Toward => target - p,
+                            NormalOperation::Away => p - target,
+                            NormalOperation::Flatten => average,
+                            NormalOperation::CopyFrom => {
+                                let source = source.as_ref()?;
+                                source.normal(p, n, settings.max_distance)?
+                            }
+                        };
+                        if wanted.magnitude2() == 0. {
+                            return None;
+                        }
+                        let normal = n.lerp(wanted.normalize(), settings.strength);
+                        let normal = Some(normal).filter(|n| n.magnitude2() > 0.)?;
+                        Some((i, normal.normalize().into()))
+                    })
+                    .collect::<Vec<(usize, [f32; 3])>>()
+            };
+            if normals.is_empty() {
+                continue;
+            }
+
+            let vertices = &self.models[model].meshes()[mesh].vertices;
+            self.edit.set_normals(model, mesh, vertices, &normals);
+            for keys in self
+                .shape_keys
+                .iter_mut()
+                .filter(|keys| keys.model == model)
+            {
+                keys.set_normals(mesh, &normals);
+            }
+            let mesh = &mut self.models[model].meshes_mut()[mesh];
+            let (mut first, mut last) = (usize::MAX, 0);
+            for &(i, normal) in normals.iter() {
+                mesh.vertices[i].normal = normal;
+                first = first.min(i);
+                last = last.max(i + 1);
+            }
+            mesh.mark_vertices_dirty(first..last);
+            count += normals.len();
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,36 @@
+//! Origins of models, the points they rotate and scale around on their own. Vertices are kept
+//! in scene space, so an origin is a point moving along with its model and setting it leaves
+//! the geometry where it is.
+
+use cgmath::Point3;
+
+use crate::scene::Scene;
+
+impl Scene {
+    /// The origin set for the model, or else the center of its bounds.
+    pub fn origin(&self, model: usize) -> Point3<f32> {
+        match self.origins.get(&model) {
+            Some(&origin) => origin,
+            None => self.models[model].bounds().center(),
+        }
+    }
+
+    pub fn set_origin(&mut self, model: usize, origin: Point3<f32>) {
+        self.origins.insert(model, origin);
+    }
+
+    /// Puts the origins of the selected models at `point`, e.g. a picked surface point.
+    pub fn set_selected_origins(&mut self, point: Point3<f32>) {
+        for model in self.selected_models() {
+            self.set_origin(model, point);
+        }
+    }
+
+    /// Puts the origin of each selected model at the center of its bounds.
+    pub fn center_selected_origins(&mut self) {
+        for model in self.selected_models() {
+            let center = self.models[model].bounds().center();
+            self.set_origin(model, center);
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use cgmath::{Deg, Matrix4, Point3, Vector3};
+use serde::{Deserialize, Serialize};
+
+/// Translation, rotation in degrees around X, then Y, then Z, and uniform scale.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GroupTransform {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 3],
+    pub scale: f32,
+}
+
+impl Default for GroupTransform {
+    fn default() -> Self {
+        Self {
+            translation: [0.; 3],
+            rotation: [0.; 3],
+            scale: 1.,
+        }
+    }
+}
+
+impl GroupTransform {
+    /// The transform as a matrix rotating and scaling around `pivot`.
+    pub fn matrix(&self, pivot: Point3<f32>) -> Matrix4<f32> {
+        let pivot = Vector3::new(pivot.x, pivot.y, pivot.z);
+        Matrix4::from_translation(pivot + Vector3::from(self.translation))
+            * Matrix4::from_angle_z(Deg(self.rotation[2]))
+            * Matrix4::from_angle_y(Deg(self.rotation[1]))
+            * Matrix4::from_angle_x(Deg(self.rotation[0]))
+            * Matrix4::from_scale(self.scale)
+            * Matrix4::from_translation(-pivot)
+    }
+}
+
+/// Named collection of models, shown, hidden, selected and transformed together.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    pub name: String,
+    pub models: Vec<usize>,
+    pub visible: bool,
+    pub transform: GroupTransform,
+    /// Center of the models when the group was formed, which the group rotates and scales
+    /// around.
+    pub pivot: [f32; 3],
+    /// The transform baked into the models' vertices. Models are loaded untransformed, so it
+    /// starts out as the identity.
+    #[serde(skip)]
+    pub applied: GroupTransform,
+}
+
+/// Every model is on exactly one layer, which decides how the renderer and the picker treat
+/// it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Layer {
+    pub name: String,
+    pub visible: bool,
+    /// Whether clicks pick the layer's models, e.g. to sculpt or edit them.
+    pub selectable: bool,
+    pub cast_shadows: bool,
+}
+
+impl Layer {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            visible: true,
+            selectable: true,
+            cast_shadows: true,
+        }
+    }
+}
+
+fn default_layers() -> Vec<Layer> {
+    (1..=Outliner::LAYER_COUNT)
+        .map(|i| Layer::new(format!("Layer {}", i)))
+        .collect()
+}
+
+fn default_lit() -> bool {
+    true
+}
+
+/// Per-model render settings, e.g. to keep a ground plane from casting shadows or a helper mesh
+/// from being shadowed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ObjectFlags {
+    pub cast_shadow: bool,
+    pub receive_shadow: bool,
+    /// Whether the lights shade the model, or it shows its textures' colors as they are, e.g.
+    /// for reference planes and models with the lighting baked into their textures.
+    #[serde(default = "default_lit")]
+    pub lit: bool,
+}
+
+impl Default for ObjectFlags {
+    fn default() -> Self {
+        Self {
+            cast_shadow: true,
+            receive_shadow: true,
+            lit: true,
+        }
+    }
+}
+
+/// Narrows the outliner down to the meshes matching all of the criteria, e.g. to find a part
+/// among the hundreds of objects of one OBJ.
+#[derive(Debug, Clone, Default)]
+pub struct OutlinerFilter {
+    /// Part of the mesh name, ignoring case.
+    pub name: String,
+    /// Part of the material name, ignoring case.
+    pub material: String,
+    pub min_triangles: u64,
+    /// No upper limit when `None`.
+    pub max_triangles: Option<u64>,
+}
+
+impl OutlinerFilter {
+    pub fn is_active(&self) -> bool {
+        !self.name.is_empty()
+            || !self.material.is_empty()
+            || self.min_triangles > 0
+            || self.max_triangles.is_some()
+    }
+
+    pub fn matches(&self, name: &str, material: &str, triangles: u64) -> bool {
+        let contains = |text: &str, part: &str| text.to_lowercase().contains(&part.to_lowercase());
+        contains(name, &self.name)
+            && contains(material, &self.material)
+            && triangles >= self.min_triangles
+            && self.max_triangles.map_or(true, |max| triangles <= max)
+    }
+}
+
+/// Organization of the scene's models into groups and layers, and which models are selected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Outliner {
+    pub groups: Vec<Group>,
+    #[serde(default = "default_layers")]
+    pub layers: Vec<Layer>,
+    /// Layer of every model not on the first one.
+    #[serde(default)]
+    pub model_layers: BTreeMap<usize, usize>,
+    /// Flags of every model not using the defaults.
+    #[serde(default)]
+    pub object_flags: BTreeMap<usize, ObjectFlags>,
+    #[serde(skip)]
+    pub selected: BTreeSet<usize>,
+    #[serde(skip)]
+    pub filter: OutlinerFilter,
+}
+
+impl Default for Outliner {
+    fn default() -> Self {
+        Self {
+            groups: Vec::new(),
+            layers: default_layers(),
+            model_layers: BTreeMap::new(),
+            object_flags: BTreeMap::new(),
+            selected: BTreeSet::new(),
+            filter: OutlinerFilter::default(),
+        }
+    }
+}
+
+impl Outliner {
+    /// Number of layers, each toggled by its number key.
+    pub const LAYER_COUNT: usize = 8;
+
+    /// Groups the models, taking them out of the groups they were in. Returns the new group's
+    /// index.
+    pub fn add_group(&mut self, name: String, models: Vec<usize>, pivot: Point3<f32>) -> usize {
+        for group in self.groups.iter_mut() {
+            group.models.retain(|m| !models.contains(m));
+        }
+        self.groups.push(Group {
+            name,
+            models,
+            visible: true,
+            transform: GroupTransform::default(),
+            pivot: pivot.into(),
+            applied: GroupTransform::default(),
+        });
+        self.groups.len() - 1
+    }
+
+    /// Dissolves the group, leaving its models ungrouped where they are.
+    pub fn remove_group(&mut self, group: usize) {
+        self.groups.remove(group);
+    }
+
+    pub fn group_of(&self, model: usize) -> Option<usize> {
+        self.groups.iter().position(|g| g.models.contains(&model))
+    }
+
+    pub fn layer_of(&self, model: usize) -> usize {
+        self.model_layers.get(&model).copied().unwrap_or(0)
+    }
+
+    pub fn set_layer(&mut self, model: usize, layer: usize) {
+        if layer == 0 {
+            self.model_layers.remove(&model);
+        } else {
+            self.model_layers.insert(model, layer);
+        }
+    }
+
+    /// Models in hidden groups or on hidden layers aren't drawn or picked.
+    pub fn is_visible(&self, model: usize) -> bool {
+        let group_visible = self
+            .group_of(model)
+            .map(|g| self.groups[g].visible)
+            .unwrap_or(true);
+        group_visible
+            && self
+                .layers
+                .get(self.layer_of(model))
+                .map(|layer| layer.visible)
+                .unwrap_or(true)
+    }
+
+    /// Whether clicks may hit the model.
+    pub fn is_pickable(&self, model: usize) -> bool {
+        self.is_visible(model)
+            && self
+                .layers
+                .get(self.layer_of(model))
+                .map(|layer| layer.selectable)
+                .unwrap_or(true)
+    }
+
+    pub fn flags(&self, model: usize) -> ObjectFlags {
+        self.object_flags.get(&model).copied().unwrap_or_default()
+    }
+
+    pub fn set_flags(&mut self, model: usize, flags: ObjectFlags) {
+        if flags == ObjectFlags::default() {
+            self.object_flags.remove(&model);
+        } else {
+            self.object_flags.insert(model, flags);
+        }
+    }
+
+    /// Whether the model is drawn into the shadow map.
+    pub fn casts_shadows(&self, model: usize) -> bool {
+        self.is_visible(model)
+            && self.flags(model).cast_shadow
+            && self
+                .layers
+                .get(self.layer_of(model))
+                .map(|layer| layer.cast_shadows)
+                .unwrap_or(true)
+    }
+
+    /// Whether the lighting shader darkens the model where the shadow map covers it.
+    pub fn receives_shadows(&self, model: usize) -> bool {
+        self.flags(model).receive_shadow
+    }
+
+    /// Whether the model is shaded, or drawn fullbright.
+    pub fn is_lit(&self, model: usize) -> bool {
+        self.flags(model).lit
+    }
+
+    /// Shows or hides the layer, or with `solo` shows only it.
+    pub fn toggle_layer(&mut self, layer: usize, solo: bool) {
+        if layer >= self.layers.len() {
+            return;
+        }
+        if solo {
+            for (i, l) in self.layers.iter_mut().enumerate() {
+                l.visible = i == layer;
+            }
+        } else {
+            self.layers[layer].visible = !self.layers[layer].visible;
+        }
+    }
+
+    /// Selects every model of the group, replacing the selection unless `extend` is set.
+    pub fn select_group(&mut self, group: usize, extend: bool) {
+        if !extend {
+            self.selected.clear();
+        }
+        self.selected
+            .extend(self.groups[group].models.iter().copied());
+    }
+
+    /// Selects the model, or deselects it when already selected.
+    pub fn toggle_selected(&mut self, model: usize) {
+        if !self.selected.remove(&model) {
+            self.selected.insert(model);
+        }
+    }
+
+    /// Drops groups' references to models past `count`, e.g. after loading a project made for
+    /// a scene with more models.
+    pub fn retain_models(&mut self, count: usize) {
+        for group in self.groups.iter_mut() {
+            group.models.retain(|&m| m < count);
+        }
+        self.model_layers.retain(|&m, _| m < count);
+        self.object_flags.retain(|&m, _| m < count);
+        self.selected.retain(|&m| m < count);
+    }
+
+    /// Moves the references to each model to the index `index` gives it, dropping the ones it
+    /// gives none, e.g. for a project whose models were loaded again in another order.
+    pub fn renumber_models(&mut self, index: impl Fn(usize) -> Option<usize>) {
+        for group in self.groups.iter_mut() {
+            group.models = group.models.iter().filter_map(|&m| index(m)).collect();
+        }
+        self.model_layers = self
+            .model_layers
+            .iter()
+            .filter_map(|(&m, &layer)| Some((index(m)?, layer)))
+            .collect();
+        self.object_flags = self
+            .object_flags
+            .iter()
+            .filter_map(|(&m, &flags)| Some((index(m)?, flags)))
+            .collect();
+        self.selected = self.selected.iter().filter_map(|&m| index(m)).collect();
+    }
+}
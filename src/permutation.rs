@@ -0,0 +1,156 @@
+//! Variants of the model shader compiled with defines for what a material and mesh have, so
+//! each is drawn with only the work it needs instead of one shader branching on everything.
+
+use std::path::{Path, PathBuf};
+
+use crate::vertex_layout::{VertexAttribute, VertexLayout};
+
+/// File name suffixes and defines of the features compiled into permutations, in the order the
+/// suffixes are joined. Must match `PERMUTATIONS` in build.rs.
+const DEFINES: [(&str, &str); 5] = [
+    ("_normal_map", "HAS_NORMAL_MAP"),
+    ("_vertex_color", "HAS_VERTEX_COLOR"),
+    ("_skinned", "SKINNED"),
+    ("_pbr", "PBR"),
+    ("_uber", "UBER"),
+];
+
+/// How the model shader is specialized for what materials need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderStrategy {
+    /// A compiled permutation and pipeline for every combination of features in use.
+    Permutations,
+    /// One shader branching on the material features in a uniform, so materials share their
+    /// pipeline. Only what changes the vertex inputs still makes separate ones.
+    Uber,
+}
+
+impl Default for ShaderStrategy {
+    fn default() -> Self {
+        ShaderStrategy::Permutations
+    }
+}
+
+impl ShaderStrategy {
+    /// The uber shader on WebGL, where every pipeline is a costly program link.
+    pub fn for_backend(backend: wgpu::Backend) -> Self {
+        match backend {
+            wgpu::Backend::Gl => ShaderStrategy::Uber,
+            _ => ShaderStrategy::Permutations,
+        }
+    }
+}
+
+/// What a material and mesh need from the model shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderFeatures {
+    /// Samples the normal map, off for materials without one.
+    pub normal_map: bool,
+    /// Multiplies the diffuse color with the colors in the mesh's second vertex buffer.
+    pub vertex_color: bool,
+    /// Reads joints and weights, moving the vertices by their joints' matrices, see skin.rs.
+    pub skinned: bool,
+    /// Draws back faces too. The same shader either way, only the pipeline differs.
+    pub double_sided: bool,
+    /// Lights with the metallic-roughness model of glTF, the specular map holding roughness in
+    /// green and metalness in blue, and adds the emissive map. Phong lighting otherwise.
+    pub pbr: bool,
+    /// Compiled as the uber shader, which reads the normal map, double sided and PBR flags
+    /// from the material instead.
+    pub uber: bool,
+}
+
+impl Default for ShaderFeatures {
+    /// What every model was drawn with before there were permutations.
+    fn default() -> Self {
+        Self {
+            normal_map: true,
+            vertex_color: false,
+            skinned: false,
+            double_sided: true,
+            pbr: false,
+            uber: false,
+        }
+    }
+}
+
+impl ShaderFeatures {
+    /// The features with the ones the extra vertex attributes of a mesh call for.
+    pub fn with_layout(self, layout: &VertexLayout) -> Self {
+        Self {
+            vertex_color: layout.has(&VertexAttribute::Color),
+            skinned: layout.has(&VertexAttribute::Joints) && layout.has(&VertexAttribute::Weights),
+            ..self
+        }
+    }
+
+    /// The features as compiled with `strategy`. The uber shader keeps only the ones changing
+    /// the vertex inputs, so those of the material don't make more pipelines.
+    pub fn compiled(self, strategy: ShaderStrategy) -> Self {
+        match strategy {
+            ShaderStrategy::Permutations => self,
+            ShaderStrategy::Uber => Self {
+                normal_map: false,
+                double_sided: false,
+                pbr: false,
+                uber: true,
+                ..self
+            },
+        }
+    }
+
+    fn defined(&self) -> [bool; 5] {
+        [
+            self.normal_map,
+            self.vertex_color,
+            self.skinned,
+            self.pbr,
+            self.uber,
+        ]
+    }
+
+    /// For the uniform the uber shader reads them from, x: normal map, y: double sided, z: PBR.
+    pub fn flags(&self) -> [f32; 4] {
+        [
+            self.normal_map as u32 as f32,
+            self.double_sided as u32 as f32,
+            self.pbr as u32 as f32,
+            0.,
+        ]
+    }
+
+    /// The compiled permutation of the shader at `base`, e.g. `shader_normal_map`.
+    pub fn shader_path(&self, base: &Path) -> PathBuf {
+        let mut name = base
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        for ((suffix, _), defined) in DEFINES.iter().zip(self.defined()) {
+            if defined {
+                name.push_str(suffix);
+            }
+        }
+        base.with_file_name(name)
+    }
+
+    /// Tells pipelines apart in the shader cache.
+    pub fn key(&self) -> String {
+        let mut key = self
+            .shader_path(Path::new(""))
+            .to_string_lossy()
+            .into_owned();
+        if self.double_sided {
+            key.push_str("_double_sided");
+        }
+        key
+    }
+
+    /// The uber shader discards back faces itself for single sided materials.
+    pub fn cull_mode(&self) -> Option<wgpu::Face> {
+        if self.double_sided || self.uber {
+            None
+        } else {
+            Some(wgpu::Face::Back)
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+
+use cgmath::Point3;
+use rapier3d::prelude::*;
+
+use crate::{
+    debug_lines::DebugLines,
+    model::{Model, ModelVertex},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyKind {
+    /// Collides but never moves, e.g. the ground.
+    Static,
+    Dynamic,
+}
+
+struct PhysicsBody {
+    kind: BodyKind,
+    handle: RigidBodyHandle,
+    /// Vertices of every mesh as they were when the body was created.
+    rest: Vec<Vec<ModelVertex>>,
+}
+
+/// Rigid body simulation for models that opted in with a collider.
+///
+/// Model vertices are already in world space, so every body starts at the origin with an
+/// identity rotation and its pose is applied to the rest vertices after each step.
+pub struct Physics {
+    pub running: bool,
+    pub show_colliders: bool,
+    pub gravity: Vector<Real>,
+    reset_requested: bool,
+    bodies: HashMap<usize, PhysicsBody>,
+    rigid_body_set: RigidBodySet,
+    collider_set: ColliderSet,
+    integration_parameters: IntegrationParameters,
+    physics_pipeline: PhysicsPipeline,
+    island_manager: IslandManager,
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+    impulse_joint_set: ImpulseJointSet,
+    multibody_joint_set: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+}
+
+impl std::fmt::Debug for Physics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Physics")
+            .field("running", &self.running)
+            .field("bodies", &self.bodies.len())
+            .finish()
+    }
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Physics {
+    const STATIC_COLOR: [f32; 3] = [0.2, 0.6, 1.0];
+    const DYNAMIC_COLOR: [f32; 3] = [1.0, 0.8, 0.1];
+    const SLEEPING_COLOR: [f32; 3] = [0.5, 0.5, 0.5];
+
+    pub fn new() -> Self {
+        Self {
+            running: false,
+            show_colliders: false,
+            gravity: vector![0.0, -9.81, 0.0],
+            reset_requested: false,
+            bodies: HashMap::new(),
+            rigid_body_set: RigidBodySet::new(),
+            collider_set: ColliderSet::new(),
+            integration_parameters: IntegrationParameters::default(),
+            physics_pipeline: PhysicsPipeline::new(),
+            island_manager: IslandManager::new(),
+            broad_phase: BroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            impulse_joint_set: ImpulseJointSet::new(),
+            multibody_joint_set: MultibodyJointSet::new(),
+            ccd_solver: CCDSolver::new(),
+        }
+    }
+
+    pub fn body_kind(&self, model: usize) -> Option<BodyKind> {
+        self.bodies.get(&model).map(|body| body.kind)
+    }
+
+    /// Attaches a body of the given kind to the model, or detaches it with `None`.
+    /// The model's current shape becomes the rest pose of the new body.
+    pub fn set_body(&mut self, index: usize, model: &Model, kind: Option<BodyKind>) {
+        if let Some(body) = self.bodies.remove(&index) {
+            self.rigid_body_set.remove(
+                body.handle,
+                &mut self.island_manager,
+                &mut self.collider_set,
+                &mut self.impulse_joint_set,
+                &mut self.multibody_joint_set,
+                true,
+            );
+        }
+        let kind = match kind {
+            Some(kind) => kind,
+            None => return,
+        };
+
+        let rigid_body = match kind {
+            BodyKind::Static => RigidBodyBuilder::fixed(),
+            BodyKind::Dynamic => RigidBodyBuilder::dynamic(),
+        }
+        .build();
+        let handle = self.rigid_body_set.insert(rigid_body);
+
+        for mesh in model.meshes() {
+            let points = mesh
+                .vertices
+                .iter()
+                .map(|v| point![v.position[0], v.position[1], v.position[2]])
+                .collect::<Vec<_>>();
+            let collider = match kind {
+                BodyKind::Static => {
+                    let triangles = mesh
+                        .indices
+                        .chunks_exact(3)
+                        .map(|t| [t[0], t[1], t[2]])
+                        .collect::<Vec<_>>();
+                    Some(ColliderBuilder::trimesh(points, triangles))
+                }
+                // Arbitrary meshes can't be simulated as dynamic trimeshes, so approximate them.
+                BodyKind::Dynamic => ColliderBuilder::convex_hull(&points).or_else(|| {
+                    let size = mesh.bounds.size() / 2.;
+                    let center = mesh.bounds.center();
+                    (!mesh.bounds.is_empty()).then(|| {
+                        ColliderBuilder::cuboid(size.x, size.y, size.z)
+                            .translation(vector![center.x, center.y, center.z])
+                    })
+                }),
+            };
+            match collider {
+                Some(collider) => {
+                    self.collider_set.insert_with_parent(
+                        collider.build(),
+                        handle,
+                        &mut self.rigid_body_set,
+                    );
+                }
+                None => log::warn!("Physics: mesh {} has no usable collider", mesh.name),
+            }
+        }
+
+        self.bodies.insert(
+            index,
+            PhysicsBody {
+                kind,
+                handle,
+                rest: model.meshes().iter().map(|m| m.vertices.clone()).collect(),
+            },
+        );
+    }
+
+    /// Puts every body back to its rest pose. Applied on the next update.
+    pub fn request_reset(&mut self) {
+        self.reset_requested = true;
+    }
+
+    /// Whether a step would move anything.
+    pub fn is_active(&self) -> bool {
+        if self.reset_requested {
+            return true;
+        }
+        self.running
+            && self.bodies.values().any(|body| {
+                self.rigid_body_set
+                    .get(body.handle)
+                    .map_or(false, |rigid_body| {
+                        !rigid_body.is_fixed() && !rigid_body.is_sleeping()
+                    })
+            })
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        if self.reset_requested {
+            self.reset_requested = false;
+            for body in self.bodies.values() {
+                if let Some(rigid_body) = self.rigid_body_set.get_mut(body.handle) {
+                    rigid_body.set_position(Isometry::identity(), true);
+                    rigid_body.set_linvel(Vector::zeros(), true);
+                    rigid_body.set_angvel(Vector::zeros(), true);
+                }
+            }
+            return;
+        }
+        if !self.running || dt <= 0. {
+            return;
+        }
+        self.integration_parameters.dt = dt;
+        self.physics_pipeline.step(
+            &self.gravity,
+            &self.integration_parameters,
+            &mut self.island_manager,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.rigid_body_set,
+            &mut self.collider_set,
+            &mut self.impulse_joint_set,
+            &mut self.multibody_joint_set,
+            &mut self.ccd_solver,
+            None,
+            &(),
+            &(),
+        );
+    }
+
+    /// Moves the vertices of dynamic models to the pose of their bodies.
+    pub fn sync(&self, models: &mut [Model], queue: &wgpu::Queue) {
+        for (index, body) in self.bodies.iter() {
+            if body.kind != BodyKind::Dynamic {
+                continue;
+            }
+            let (rigid_body, model) =
+                match (self.rigid_body_set.get(body.handle), models.get_mut(*index)) {
+                    (Some(rigid_body), Some(model)) => (rigid_body, model),
+                    _ => continue,
+                };
+            if rigid_body.is_sleeping() {
+                continue;
+            }
+            let pose = rigid_body.position();
+            for (mesh, rest) in model.meshes_mut().iter_mut().zip(body.rest.iter()) {
+                let vertices = rest
+                    .iter()
+                    .map(|v| {
+                        let rotate = |n: [f32; 3]| -> [f32; 3] {
+                            (pose.rotation * vector![n[0], n[1], n[2]]).into()
+                        };
+                        let position = pose * point![v.position[0], v.position[1], v.position[2]];
+                        ModelVertex {
+                            position: position.coords.into(),
+                            normal: rotate(v.normal),
+                            tangent: rotate(v.tangent),
+                            bitangent: rotate(v.bitangent),
+                            ..*v
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                mesh.upload_vertices(queue, &vertices);
+            }
+        }
+    }
+
+    /// Outlines the bounding box of every collider in its own orientation.
+    pub fn draw_colliders(&self, lines: &mut DebugLines) {
+        for (_, collider) in self.collider_set.iter() {
+            let color = match collider.parent().and_then(|h| self.rigid_body_set.get(h)) {
+                Some(body) if body.is_fixed() => Self::STATIC_COLOR,
+                Some(body) if body.is_sleeping() => Self::SLEEPING_COLOR,
+                _ => Self::DYNAMIC_COLOR,
+            };
+            let aabb = collider.shape().compute_local_aabb();
+            let pose = collider.position();
+            let mut corners = [Point3::new(0., 0., 0.); 8];
+            for (i, corner) in corners.iter_mut().enumerate() {
+                let local = point![
+                    if i & 1 == 0 { aabb.mins.x } else { aabb.maxs.x },
+                    if i & 2 == 0 { aabb.mins.y } else { aabb.maxs.y },
+                    if i & 4 == 0 { aabb.mins.z } else { aabb.maxs.z }
+                ];
+                let world = pose * local;
+                *corner = Point3::new(world.x, world.y, world.z);
+            }
+            lines.cuboid(&corners, color);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Point3, Vector3};
+use winit::dpi::PhysicalSize;
+
+use crate::{bvh::Bvh, camera::Camera, scene::Scene};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    /// The ray leaving the camera through the given cursor position in physical pixels.
+    pub fn from_cursor(camera: &Camera, cursor: (f64, f64), size: PhysicalSize<u32>) -> Self {
+        Self::from_screen(camera, cursor_to_screen(cursor, size))
+    }
+
+    /// The ray leaving the camera through the point on the screen, from -1 to 1 both ways with
+    /// y up.
+    pub fn from_screen(camera: &Camera, screen: [f32; 2]) -> Self {
+        let (forward, right, up) = camera_basis(camera);
+        if camera.orthographic {
+            // Parallel rays leaving the plane of the eye.
+            let (width, height) = camera.ortho_half_size();
+            return Self {
+                origin: camera.eye + right * screen[0] * width + up * screen[1] * height,
+                direction: forward,
+            };
+        }
+        let tan = f32::tan(camera.projection.fovy.0 / 2.);
+        let direction =
+            forward + right * screen[0] * tan * camera.projection.aspect + up * screen[1] * tan;
+
+        Self {
+            origin: camera.eye,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// Möller–Trumbore ray/triangle intersection, returning the distance along the ray.
+    pub fn intersect_triangle(&self, a: Point3<f32>, b: Point3<f32>, c: Point3<f32>) -> Option<f32> {
+        self.intersect_triangle_barycentric(a, b, c).map(|(t, _, _)| t)
+    }
+
+    /// Like `intersect_triangle`, also returning the barycentric weights `(u, v)` of `b` and `c`.
+    pub fn intersect_triangle_barycentric(
+        &self,
+        a: Point3<f32>,
+        b: Point3<f32>,
+        c: Point3<f32>,
+    ) -> Option<(f32, f32, f32)> {
+        const EPSILON: f32 = 1e-7;
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let h = self.direction.cross(edge2);
+        let det = edge1.dot(h);
+        if det.abs() < EPSILON {
+            return None;
+        }
+        let inv_det = 1. / det;
+        let s = self.origin - a;
+        let u = inv_det * s.dot(h);
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let v = inv_det * self.direction.dot(q);
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+        let t = inv_det * edge2.dot(q);
+        if t > EPSILON {
+            Some((t, u, v))
+        } else {
+            None
+        }
+    }
+}
+
+fn camera_basis(camera: &Camera) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+    let forward = (camera.target - camera.eye).normalize();
+    let right = forward.cross(camera.up).normalize();
+    let up = right.cross(forward);
+    (forward, right, up)
+}
+
+/// The cursor position in physical pixels on the screen, from -1 to 1 both ways with y up.
+pub fn cursor_to_screen(cursor: (f64, f64), size: PhysicalSize<u32>) -> [f32; 2] {
+    [
+        (2. * cursor.0 / size.width.max(1) as f64 - 1.) as f32,
+        (1. - 2. * cursor.1 / size.height.max(1) as f64) as f32,
+    ]
+}
+
+/// Where the point shows on the screen, the inverse of `Ray::from_screen`. `None` for points
+/// behind the camera.
+pub fn to_screen(camera: &Camera, point: Point3<f32>) -> Option<[f32; 2]> {
+    let (forward, right, up) = camera_basis(camera);
+    let d = point - camera.eye;
+    let depth = d.dot(forward);
+    if depth <= 0. {
+        return None;
+    }
+    if camera.orthographic {
+        let (width, height) = camera.ortho_half_size();
+        return Some([d.dot(right) / width, d.dot(up) / height]);
+    }
+    let tan = f32::tan(camera.projection.fovy.0 / 2.);
+    Some([
+        d.dot(right) / (depth * tan * camera.projection.aspect),
+        d.dot(up) / (depth * tan),
+    ])
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub distance: f32,
+    pub point: Point3<f32>,
+    pub model: usize,
+    pub mesh: usize,
+    /// Index of the first of the triangle's three indices.
+    pub triangle: usize,
+    /// Barycentric weights of the triangle's corners at the point.
+    pub weights: [f32; 3],
+}
+
+impl Scene {
+    /// Finds the closest surface hit by the ray, skinned meshes as their joints pose them.
+    pub fn raycast(&self, ray: &Ray) -> Option<Hit> {
+        let joints = self.joint_palette();
+        let mut closest: Option<Hit> = None;
+        for (model_index, model) in self.models.iter().enumerate() {
+            if !self.outliner.is_pickable(model_index) {
+                continue;
+            }
+            for (mesh_index, mesh) in model.meshes().iter().enumerate() {
+                // The bounds and the cached hierarchy are of the bind pose.
+                let bvh = match joints.posed_positions(model_index, mesh_index, mesh) {
+                    Some(positions) => Arc::new(Bvh::from_indexed(&positions, &mesh.indices)),
+                    None => {
+                        let enter = match mesh.bounds.intersect_ray(ray.origin, ray.direction) {
+                            Some(t) => t,
+                            None => continue,
+                        };
+                        if closest.map(|hit| hit.distance < enter).unwrap_or(false) {
+                            continue;
+                        }
+                        mesh.bvh()
+                    }
+                };
+                let max_distance = closest.map(|hit| hit.distance).unwrap_or(f32::INFINITY);
+                // Parts cut away by the section box can't be hit.
+                let visible = |_, t| self.section.contains(ray.at(t));
+                if let Some(hit) = bvh.intersect_where(ray, max_distance, visible) {
+                    if closest
+                        .map(|closest| hit.distance < closest.distance)
+                        .unwrap_or(true)
+                    {
+                        closest = Some(Hit {
+                            distance: hit.distance,
+                            point: ray.at(hit.distance),
+                            model: model_index,
+                            mesh: mesh_index,
+                            triangle: hit.triangle * 3,
+                            weights: [1. - hit.u - hit.v, hit.u, hit.v],
+                        });
+                    }
+                }
+            }
+        }
+        closest
+    }
+}
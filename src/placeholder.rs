@@ -0,0 +1,95 @@
+//! Well-known textures standing in for the ones a material doesn't have: a gray checker for
+//! meshes drawn before their textures are there, a magenta one for texture files that couldn't
+//! be loaded, so they show rather than failing the model, and flat normals, white and black for
+//! the maps a material has none of. Made once and shared by every material using them.
+
+use std::sync::Arc;
+
+use crate::{model::Material, permutation::ShaderFeatures, scene::Scene, texture::Texture};
+
+#[derive(Debug)]
+pub struct Placeholders {
+    pub checker: Arc<Texture>,
+    pub missing: Arc<Texture>,
+    pub flat_normal: Arc<Texture>,
+    pub white: Arc<Texture>,
+    pub black: Arc<Texture>,
+}
+
+impl Placeholders {
+    /// Registry name of the material meshes without one are drawn with.
+    pub const DEFAULT_MATERIAL: &'static str = "default";
+    /// Texels across the checkers, and across each of their squares.
+    const SIZE: u32 = 64;
+    const SQUARE: u32 = 8;
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let pixel = |color: [u8; 4], label| {
+            Arc::new(Texture::one_pixel(device, queue, &color, Some(label), true))
+        };
+        Self {
+            checker: Arc::new(Self::checker(
+                device,
+                queue,
+                "Checker Placeholder",
+                [[0xcc, 0xcc, 0xcc, 0xff], [0x99, 0x99, 0x99, 0xff]],
+            )),
+            missing: Arc::new(Self::checker(
+                device,
+                queue,
+                "Missing Placeholder",
+                [[0xff, 0, 0xff, 0xff], [0, 0, 0, 0xff]],
+            )),
+            flat_normal: pixel([0x80, 0x80, 0xff, 0], "Flat Normal Placeholder"),
+            white: pixel([0xff; 4], "White Placeholder"),
+            black: pixel([0, 0, 0, 0xff], "Black Placeholder"),
+        }
+    }
+
+    fn checker(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+        colors: [[u8; 4]; 2],
+    ) -> Texture {
+        let rgba = image::RgbaImage::from_fn(Self::SIZE, Self::SIZE, |x, y| {
+            image::Rgba(colors[((x / Self::SQUARE + y / Self::SQUARE) % 2) as usize])
+        });
+        Texture::from_rgba(device, queue, &rgba, Some(label), false)
+    }
+}
+
+impl Scene {
+    /// The material of meshes without one: the checker, flat normals, no specular, no
+    /// occlusion and no emission. Made on first use, and again after being purged.
+    pub fn default_material(
+        &self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Arc<Material> {
+        let mut materials = self.materials.write().unwrap();
+        let id = materials.len() as u32;
+        materials
+            .get_or_insert_with(Placeholders::DEFAULT_MATERIAL, || {
+                let features = ShaderFeatures {
+                    double_sided: !self.renderer.config.cull_back_faces,
+                    ..Default::default()
+                };
+                let placeholders = &self.placeholders;
+                Arc::new(Material::new(
+                    device,
+                    Placeholders::DEFAULT_MATERIAL,
+                    placeholders.checker.clone(),
+                    placeholders.flat_normal.clone(),
+                    placeholders.black.clone(),
+                    placeholders.white.clone(),
+                    placeholders.black.clone(),
+                    features,
+                    id,
+                    &self.renderer.texture_bind_group_layout,
+                    self.model_shader(device, config, features, None),
+                ))
+            })
+            .clone()
+    }
+}
@@ -0,0 +1,225 @@
+//! Poses of a model's nodes, for presentation shots of characters. The joints of a glTF
+//! character are nodes of the scene graph, so a pose is the local transform of each of the
+//! model's nodes. Poses are taken from wherever the nodes are, after scrubbing one of the
+//! model's animations to a frame or moving them by hand, stored with a name and applied again
+//! later, alone or blended between two. Skinned meshes follow their joints, see skin.rs.
+
+use cgmath::{InnerSpace, Quaternion, VectorSpace};
+
+use crate::{
+    scene::Scene,
+    scene_graph::{NodeTransform, SceneGraph},
+    skin::Skin,
+};
+
+#[derive(Debug, Clone)]
+pub enum Keyframes {
+    Translation(Vec<cgmath::Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<cgmath::Vector3<f32>>),
+}
+
+/// Keyframes of one property of a node.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    /// The node by its place among the model's nodes, the model's root node coming first.
+    pub node: usize,
+    /// Seconds of the keyframes, in order.
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+    /// Whether each keyframe holds until the next one instead of blending into it.
+    pub step: bool,
+}
+
+impl Channel {
+    /// The keyframes around the time and how far between them it is.
+    fn around(&self, time: f32) -> Option<(usize, usize, f32)> {
+        let last = self.times.len().checked_sub(1)?;
+        let next = self
+            .times
+            .iter()
+            .position(|&t| t > time)
+            .unwrap_or(last + 1);
+        if next == 0 {
+            return Some((0, 0, 0.));
+        }
+        if next > last {
+            return Some((last, last, 0.));
+        }
+        let (start, end) = (self.times[next - 1], self.times[next]);
+        let blend = if self.step || end <= start {
+            0.
+        } else {
+            (time - start) / (end - start)
+        };
+        Some((next - 1, next, blend))
+    }
+
+    /// Sets the property of `transform` to its value at the time.
+    fn sample(&self, time: f32, transform: &mut NodeTransform) {
+        let (a, b, blend) = match self.around(time) {
+            Some(around) => around,
+            None => return,
+        };
+        match &self.keyframes {
+            Keyframes::Translation(values) => {
+                if let (Some(&a), Some(&b)) = (values.get(a), values.get(b)) {
+                    transform.translation = a.lerp(b, blend);
+                }
+            }
+            Keyframes::Rotation(values) => {
+                if let (Some(&a), Some(&b)) = (values.get(a), values.get(b)) {
+                    transform.rotation = a.slerp(b, blend);
+                }
+            }
+            Keyframes::Scale(values) => {
+                if let (Some(&a), Some(&b)) = (values.get(a), values.get(b)) {
+                    transform.scale = a.lerp(b, blend);
+                }
+            }
+        }
+    }
+}
+
+/// An animation of a model, as read from its file.
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    /// Set once the model is in the scene.
+    pub model: usize,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    /// Seconds until the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .filter_map(|channel| channel.times.last())
+            .fold(0., |duration, &time| duration.max(time))
+    }
+}
+
+/// The local transforms of a model's nodes, in their order.
+#[derive(Debug, Clone)]
+pub struct Pose {
+    pub name: String,
+    pub model: usize,
+    pub transforms: Vec<NodeTransform>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PoseLibrary {
+    pub clips: Vec<AnimationClip>,
+    pub poses: Vec<Pose>,
+    /// The skinned meshes of the models, bent by the nodes the poses move.
+    pub skins: Vec<Skin>,
+}
+
+impl PoseLibrary {
+    /// Puts in the animations of a model just loaded, in place of those loaded for it before.
+    pub(crate) fn set_clips(&mut self, model: usize, clips: Vec<AnimationClip>) {
+        self.clips.retain(|clip| clip.model != model);
+        self.clips.extend(
+            clips
+                .into_iter()
+                .map(|clip| AnimationClip { model, ..clip }),
+        );
+    }
+
+    /// Names not given to a pose yet, like "Pose 2".
+    pub fn next_name(&self) -> String {
+        (1..)
+            .map(|i| format!("Pose {}", i))
+            .find(|name| self.poses.iter().all(|pose| pose.name != *name))
+            .unwrap()
+    }
+}
+
+/// What the pose panel has chosen.
+#[derive(Debug, Clone, Default)]
+pub struct PoseSettings {
+    /// The animation scrubbed, by index.
+    pub clip: usize,
+    /// Seconds into it.
+    pub time: f32,
+    /// Name of the next pose stored.
+    pub name: String,
+    /// The poses blended between, by index, and how far from the first to the second.
+    pub blend: (usize, usize, f32),
+}
+
+/// Interpolates between two transforms, by `blend` from `a` to `b`.
+fn blend_transforms(a: &NodeTransform, b: &NodeTransform, blend: f32) -> NodeTransform {
+    // The shorter way around.
+    let b_rotation = if a.rotation.dot(b.rotation) < 0. {
+        -b.rotation
+    } else {
+        b.rotation
+    };
+    NodeTransform {
+        translation: a.translation.lerp(b.translation, blend),
+        rotation: a.rotation.nlerp(b_rotation, blend).normalize(),
+        scale: a.scale.lerp(b.scale, blend),
+    }
+}
+
+impl SceneGraph {
+    /// The nodes placing the model, its root node first.
+    pub fn model_nodes(&self, model: usize) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|&i| self.nodes[i].model == Some(model))
+            .collect()
+    }
+}
+
+impl Scene {
+    /// Puts the model's nodes where the animation has them at the time, in seconds.
+    pub fn scrub(&mut self, clip: usize, time: f32) {
+        let clip = match self.poses.clips.get(clip) {
+            Some(clip) => clip,
+            None => return,
+        };
+        let nodes = self.graph.model_nodes(clip.model);
+        for channel in clip.channels.iter() {
+            if let Some(&node) = nodes.get(channel.node) {
+                channel.sample(time, &mut self.graph.nodes[node].local);
+            }
+        }
+    }
+
+    /// Stores where the model's nodes are as a pose, returning its index.
+    pub fn store_pose(&mut self, model: usize, name: String) -> usize {
+        let nodes = self.graph.model_nodes(model);
+        let transforms = nodes
+            .iter()
+            .map(|&node| self.graph.nodes[node].local)
+            .collect();
+        self.poses.poses.push(Pose {
+            name,
+            model,
+            transforms,
+        });
+        self.poses.poses.len() - 1
+    }
+
+    /// Puts the nodes of the pose's model where the pose has them, but for the root node.
+    pub fn apply_pose(&mut self, pose: usize) {
+        self.blend_poses(pose, pose, 0.);
+    }
+
+    /// Puts the nodes of the model between two of its poses, by `blend` from `a` to `b`. The
+    /// root node stays, so poses don't move the model around the scene. Nothing happens for
+    /// poses of different models.
+    pub fn blend_poses(&mut self, a: usize, b: usize, blend: f32) {
+        let (a, b) = match (self.poses.poses.get(a), self.poses.poses.get(b)) {
+            (Some(a), Some(b)) if a.model == b.model => (a, b),
+            _ => return,
+        };
+        let nodes = self.graph.model_nodes(a.model);
+        let transforms = a.transforms.iter().zip(b.transforms.iter());
+        for (&node, (a, b)) in nodes.iter().zip(transforms).skip(1) {
+            self.graph.nodes[node].local = blend_transforms(a, b, blend);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Simple shapes added to the scene at the 3D cursor, to block out a scene or to have something
+//! to try the tools on.
+
+use std::f32::consts::PI;
+
+use crate::{model::ModelVertex, registry::MeshData};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primitive {
+    Cube,
+    Plane,
+    Sphere,
+}
+
+fn vertex(position: [f32; 3], normal: [f32; 3], tex_coords: [f32; 2]) -> ModelVertex {
+    ModelVertex {
+        position,
+        tex_coords,
+        normal,
+        tangent: [0.; 3],
+        bitangent: [0.; 3],
+    }
+}
+
+impl Primitive {
+    pub const ALL: [Primitive; 3] = [Primitive::Cube, Primitive::Plane, Primitive::Sphere];
+
+    /// Rings and segments of the sphere.
+    const SPHERE_RINGS: u32 = 12;
+    const SPHERE_SEGMENTS: u32 = 24;
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Primitive::Cube => "Cube",
+            Primitive::Plane => "Plane",
+            Primitive::Sphere => "Sphere",
+        }
+    }
+
+    /// The shape around the scene origin, `size` across.
+    pub fn mesh(&self, size: f32) -> MeshData {
+        let half = size / 2.;
+        let (vertices, indices) = match self {
+            Primitive::Cube => Self::cube(half),
+            Primitive::Plane => Self::plane(half),
+            Primitive::Sphere => Self::sphere(half),
+        };
+        MeshData::new(vertices, indices).expect("Primitives are made of whole triangles")
+    }
+
+    fn plane(half: f32) -> (Vec<ModelVertex>, Vec<u32>) {
+        let up = [0., 1., 0.];
+        let vertices = vec![
+            vertex([-half, 0., half], up, [0., 1.]),
+            vertex([half, 0., half], up, [1., 1.]),
+            vertex([half, 0., -half], up, [1., 0.]),
+            vertex([-half, 0., -half], up, [0., 0.]),
+        ];
+        (vertices, vec![0, 1, 2, 0, 2, 3])
+    }
+
+    /// Four vertices of their own per face, so the edges stay sharp.
+    fn cube(half: f32) -> (Vec<ModelVertex>, Vec<u32>) {
+        let mut vertices = Vec::with_capacity(24);
+        let mut indices = Vec::with_capacity(36);
+        for axis in 0..3 {
+            for &sign in [1f32, -1.].iter() {
+                let mut normal = [0.; 3];
+                normal[axis] = sign;
+                // The two other axes, in the order keeping the face turned outwards.
+                let (u, v) = if sign > 0. {
+                    ((axis + 1) % 3, (axis + 2) % 3)
+                } else {
+                    ((axis + 2) % 3, (axis + 1) % 3)
+                };
+                let first = vertices.len() as u32;
+                for &(s, t) in [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)].iter() {
+                    let mut position = [0.; 3];
+                    position[axis] = sign * half;
+                    position[u] = s * half;
+                    position[v] = t * half;
+                    vertices.push(vertex(position, normal, [(s + 1.) / 2., (1. - t) / 2.]));
+                }
+                indices.extend([0, 1, 2, 0, 2, 3].iter().map(|i| first + i));
+            }
+        }
+        (vertices, indices)
+    }
+
+    /// A UV sphere, with a seam of doubled vertices so the texture wraps around once.
+    fn sphere(radius: f32) -> (Vec<ModelVertex>, Vec<u32>) {
+        let (rings, segments) = (Self::SPHERE_RINGS, Self::SPHERE_SEGMENTS);
+        let mut vertices = Vec::new();
+        for ring in 0..=rings {
+            let v = ring as f32 / rings as f32;
+            let (sin_polar, cos_polar) = (v * PI).sin_cos();
+            for segment in 0..=segments {
+                let u = segment as f32 / segments as f32;
+                let (sin_azimuth, cos_azimuth) = (u * 2. * PI).sin_cos();
+                let normal = [sin_polar * cos_azimuth, cos_polar, -sin_polar * sin_azimuth];
+                let position = normal.map(|n| n * radius);
+                vertices.push(vertex(position, normal, [u, v]));
+            }
+        }
+        let mut indices = Vec::new();
+        let row = segments + 1;
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let a = ring * row + segment;
+                let b = a + row;
+                // The triangles at the poles would have no area.
+                if ring != 0 {
+                    indices.extend([a, b, a + 1].iter());
+                }
+                if ring != rings - 1 {
+                    indices.extend([a + 1, b, b + 1].iter());
+                }
+            }
+        }
+        (vertices, indices)
+    }
+}
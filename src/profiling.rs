@@ -0,0 +1,37 @@
+//! CPU profiling with puffin (`profiling` feature). The update, loading and render code is
+//! timed in scopes, shown per frame as a flame graph in the Profiler window, so a slow frame
+//! can be put down to the update, waiting for the scene's lock or the GUI. Scopes are only
+//! recorded while the window is open, and compile to nothing without the feature.
+//!
+//! Scopes in async code end at the next `.await`, as the task may go on elsewhere.
+
+/// Times the rest of the enclosing block under the name.
+macro_rules! profile_scope {
+    ($name:expr) => {
+        #[cfg(feature = "profiling")]
+        puffin::profile_scope!($name);
+    };
+}
+
+/// Times the rest of the function under its name.
+macro_rules! profile_function {
+    () => {
+        #[cfg(feature = "profiling")]
+        puffin::profile_function!();
+    };
+}
+
+/// Starts the next frame of the profile, before each frame is rendered.
+pub fn new_frame() {
+    #[cfg(feature = "profiling")]
+    puffin::GlobalProfiler::lock().new_frame();
+}
+
+/// The Profiler window, recording scopes while it is open.
+#[cfg(feature = "profiling")]
+pub fn window(ctx: &egui::CtxRef, open: &mut bool) {
+    puffin::set_scopes_on(*open);
+    if *open {
+        *open = puffin_egui::profiler_window(ctx);
+    }
+}
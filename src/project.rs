@@ -0,0 +1,203 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cameras::SceneCamera,
+    compression::{self, Compression},
+    outliner::Outliner,
+    remap::{self, PathRemap},
+    review::Note,
+    scene::Scene,
+};
+
+/// The parts of a scene saved between sessions, written as RON, compressed or not.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Project {
+    pub outliner: Outliner,
+    #[serde(default)]
+    pub cameras: Vec<SceneCamera>,
+    #[serde(default)]
+    pub notes: Vec<Note>,
+    /// Where each model was loaded from, by index, empty for the ones made in the app. Saved
+    /// relative to the project file where there's a way from it.
+    #[serde(default)]
+    pub models: Vec<String>,
+}
+
+/// Whether the model source is a URL rather than a path.
+pub(crate) fn is_url(source: &str) -> bool {
+    source.contains("://")
+}
+
+/// The path as an absolute one, resolving links where it exists.
+fn absolute(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| {
+        std::env::current_dir()
+            .map(|dir| dir.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    })
+}
+
+/// `path` relative to the folder `base`, e.g. `../models/house.obj`, or `None` when they don't
+/// share a root, like paths on different drives.
+fn relative_path(path: &Path, base: &Path) -> Option<String> {
+    let (path, base) = (absolute(path), absolute(base));
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return None;
+    }
+    let ups = base.components().count() - common;
+    let parts = std::iter::repeat(Component::ParentDir)
+        .take(ups)
+        .chain(path.components().skip(common))
+        .map(|part| part.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    Some(parts.join("/"))
+}
+
+impl Project {
+    pub fn from_scene(scene: &Scene) -> Self {
+        Self {
+            outliner: scene.outliner.clone(),
+            cameras: scene.cameras.cameras.clone(),
+            notes: scene.review.notes.clone(),
+            models: (0..scene.models.len())
+                .map(|i| scene.sources.get(&i).cloned().unwrap_or_default())
+                .collect(),
+        }
+    }
+
+    /// Moves the references to each model to the index `index` gives it, dropping the ones it
+    /// gives none.
+    pub fn renumber_models(&mut self, index: impl Fn(usize) -> Option<usize>) {
+        self.outliner.renumber_models(&index);
+        for note in self.notes.iter_mut() {
+            let models = std::mem::take(&mut note.models);
+            note.models = models
+                .into_iter()
+                .filter_map(|mut object| {
+                    object.model = index(object.model)?;
+                    Some(object)
+                })
+                .collect();
+        }
+    }
+
+    /// Puts the project's groups, cameras and review notes into the scene.
+    pub fn apply(self, scene: &mut Scene) {
+        scene.set_outliner(self.outliner);
+        scene.look_through(None);
+        scene.cameras.cameras = self.cameras;
+        scene.review.notes = self.notes;
+    }
+
+    /// Reads a project, compressed or not, with its model paths resolved from its folder.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = std::fs::read(path.as_ref())
+            .with_context(|| format!("Project: {:?} not found", path.as_ref()))?;
+        let mut project: Self = compression::decompress(bytes)
+            .and_then(|bytes| Ok(ron::de::from_bytes(&bytes)?))
+            .with_context(|| format!("Project: {:?} is broken", path.as_ref()))?;
+        let dir = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+        for source in project.models.iter_mut() {
+            if !source.is_empty() && !is_url(source) && Path::new(source).is_relative() {
+                *source = dir.join(&*source).to_string_lossy().into_owned();
+            }
+        }
+        Ok(project)
+    }
+
+    /// Writes the project, its model paths relative to the folder it's written to.
+    pub fn save<P: AsRef<Path>>(&self, path: P, compression: Compression) -> Result<()> {
+        let dir = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+        let mut project = self.clone();
+        for source in project.models.iter_mut() {
+            if source.is_empty() || is_url(source) {
+                continue;
+            }
+            if let Some(relative) = relative_path(Path::new(source), dir) {
+                *source = relative;
+            }
+        }
+        let text = ron::ser::to_string_pretty(&project, ron::ser::PrettyConfig::default())?;
+        let bytes = compression::compress(text.into_bytes(), compression)?;
+        std::fs::write(path.as_ref(), bytes)?;
+        Ok(())
+    }
+}
+
+impl Scene {
+    /// Loads the models the project names that the scene doesn't have yet and puts the project
+    /// into the scene once they are in, renumbered to where they landed. When some of their
+    /// files are missing, the remap dialog asks where they went first.
+    pub fn load_project(&mut self, project: Project) {
+        let missing = remap::missing_files(&project);
+        if !missing.is_empty() {
+            log::warn!("{} files of the project are missing", missing.len());
+            self.remap = Some(PathRemap::new(project, missing));
+            return;
+        }
+        let mut loaded = HashMap::new();
+        for source in self.sources.values() {
+            *loaded.entry(source).or_insert(0) += 1;
+        }
+        let mut started = Vec::new();
+        for source in project.models.iter().filter(|source| !source.is_empty()) {
+            match loaded.get_mut(source) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => started.push(source.clone()),
+            }
+        }
+        for source in started {
+            self.start_load(source);
+        }
+        self.pending_project = Some(project);
+    }
+
+    /// Loads the project of the remap dialog with the files found in place of the missing ones,
+    /// leaving out the models still missing.
+    pub fn finish_remap(&mut self) {
+        if let Some(remap) = self.remap.take() {
+            self.load_project(remap.resolve());
+        }
+    }
+
+    /// Applies the project waiting for its models when no load is running any more. Models
+    /// that failed to load are left out of it.
+    pub(crate) fn apply_pending_project(&mut self) {
+        if self.pending_project.is_none() || self.is_loading() {
+            return;
+        }
+        let mut project = match self.pending_project.take() {
+            Some(project) => project,
+            None => return,
+        };
+        // Models from the same file are alike, so they take the loaded ones in turn.
+        let mut claimed = BTreeSet::new();
+        let index = project
+            .models
+            .iter()
+            .map(|source| {
+                let (&i, _) = self.sources.iter().find(|(i, loaded)| {
+                    !source.is_empty() && *loaded == source && !claimed.contains(*i)
+                })?;
+                claimed.insert(i);
+                Some(i)
+            })
+            .collect::<Vec<_>>();
+        // Projects saved before they listed their models keep the indices they have.
+        if !project.models.is_empty() {
+            project.renumber_models(|model| index.get(model).copied().flatten());
+        }
+        project.apply(self);
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+use instant::Instant;
+use serde::{Deserialize, Serialize};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{
+        DeviceId, ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta,
+        TouchPhase, VirtualKeyCode, WindowEvent,
+    },
+};
+
+/// The subset of window input the viewer reacts to, in a form that can be written to disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum InputEvent {
+    Key {
+        keycode: VirtualKeyCode,
+        state: ElementState,
+    },
+    MouseButton {
+        button: MouseButton,
+        state: ElementState,
+    },
+    MouseWheel {
+        lines: (f32, f32),
+    },
+    CursorMoved {
+        position: (f64, f64),
+    },
+}
+
+impl InputEvent {
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } => Some(InputEvent::Key {
+                keycode: *keycode,
+                state: *state,
+            }),
+            WindowEvent::MouseInput { state, button, .. } => Some(InputEvent::MouseButton {
+                button: *button,
+                state: *state,
+            }),
+            WindowEvent::MouseWheel {
+                delta: MouseScrollDelta::LineDelta(x, y),
+                ..
+            } => Some(InputEvent::MouseWheel { lines: (*x, *y) }),
+            WindowEvent::CursorMoved { position, .. } => Some(InputEvent::CursorMoved {
+                position: (position.x, position.y),
+            }),
+            _ => None,
+        }
+    }
+
+    #[allow(deprecated)]
+    pub fn to_window_event(&self) -> WindowEvent<'static> {
+        // Replayed events don't come from a real device.
+        let device_id = unsafe { DeviceId::dummy() };
+        let modifiers = ModifiersState::empty();
+        match *self {
+            InputEvent::Key { keycode, state } => WindowEvent::KeyboardInput {
+                device_id,
+                input: KeyboardInput {
+                    scancode: 0,
+                    state,
+                    virtual_keycode: Some(keycode),
+                    modifiers,
+                },
+                is_synthetic: true,
+            },
+            InputEvent::MouseButton { button, state } => WindowEvent::MouseInput {
+                device_id,
+                state,
+                button,
+                modifiers,
+            },
+            InputEvent::MouseWheel { lines } => WindowEvent::MouseWheel {
+                device_id,
+                delta: MouseScrollDelta::LineDelta(lines.0, lines.1),
+                phase: TouchPhase::Moved,
+                modifiers,
+            },
+            InputEvent::CursorMoved { position } => WindowEvent::CursorMoved {
+                device_id,
+                position: PhysicalPosition::new(position.0, position.1),
+                modifiers,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Number of updates that happened before the event arrived.
+    pub frame: u64,
+    /// Wall clock time since the recording started, in seconds.
+    pub time: f32,
+    pub event: InputEvent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub timestep: f32,
+    pub size: (u32, u32),
+    pub frames: u64,
+    pub events: Vec<RecordedEvent>,
+}
+
+impl Recording {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path.as_ref())
+            .with_context(|| format!("Recording: {:?} not found", path.as_ref()))?;
+        ron::de::from_reader(BufReader::new(file))
+            .with_context(|| format!("Recording: {:?} is broken", path.as_ref()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = File::create(path.as_ref())?;
+        ron::ser::to_writer_pretty(BufWriter::new(file), self, ron::ser::PrettyConfig::default())?;
+        Ok(())
+    }
+}
+
+pub struct Recorder {
+    path: PathBuf,
+    recording: Recording,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn new<P: Into<PathBuf>>(path: P, size: PhysicalSize<u32>, timestep: f32) -> Self {
+        Self {
+            path: path.into(),
+            recording: Recording {
+                timestep,
+                size: (size.width, size.height),
+                frames: 0,
+                events: Vec::new(),
+            },
+            start: Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, frame: u64, event: &WindowEvent) {
+        if let Some(event) = InputEvent::from_window_event(event) {
+            self.recording.events.push(RecordedEvent {
+                frame,
+                time: self.start.elapsed().as_secs_f32(),
+                event,
+            });
+        }
+    }
+
+    pub fn finish(&mut self, frames: u64) -> Result<()> {
+        self.recording.frames = frames;
+        self.recording.save(&self.path)?;
+        log::info!(
+            "Recorded {} events over {} frames to {:?}",
+            self.recording.events.len(),
+            frames,
+            self.path
+        );
+        Ok(())
+    }
+}
+
+pub struct Player {
+    pub recording: Recording,
+    pub dump_frames: Option<PathBuf>,
+    next: usize,
+}
+
+impl Player {
+    pub fn load<P: AsRef<Path>>(path: P, dump_frames: Option<PathBuf>) -> Result<Self> {
+        if let Some(dir) = &dump_frames {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(Self {
+            recording: Recording::load(path)?,
+            dump_frames,
+            next: 0,
+        })
+    }
+
+    /// Returns the events which were received before the given update.
+    pub fn events_for_frame(&mut self, frame: u64) -> Vec<WindowEvent<'static>> {
+        let mut events = Vec::new();
+        while let Some(recorded) = self.recording.events.get(self.next) {
+            if recorded.frame > frame {
+                break;
+            }
+            events.push(recorded.event.to_window_event());
+            self.next += 1;
+        }
+        events
+    }
+
+    pub fn is_finished(&self, frame: u64) -> bool {
+        frame >= self.recording.frames
+    }
+
+    pub fn frame_path(&self, frame: u64) -> Option<PathBuf> {
+        self.dump_frames
+            .as_ref()
+            .map(|dir| dir.join(format!("frame_{:05}.png", frame)))
+    }
+}
+
+pub enum Session {
+    Record(Recorder),
+    Replay(Player),
+}
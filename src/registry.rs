@@ -0,0 +1,442 @@
+//! Meshes, materials and textures kept by handle, so programs embedding the viewer can build
+//! scenes without reaching into the model list or knowing the keys loaders use.
+
+use std::{
+    collections::HashMap,
+    fmt, hash,
+    marker::PhantomData,
+    sync::{Arc, Weak},
+};
+
+use anyhow::*;
+use cgmath::Point3;
+
+use crate::{
+    model::{compute_tangents, transform_vertices, Material, Mesh, Model, ModelVertex, ObjModel},
+    outliner::GroupTransform,
+    permutation::ShaderFeatures,
+    scene::Scene,
+    shader::Shader,
+    texture::Texture,
+};
+
+/// Where a spawned model goes: translation, rotation in degrees and scale, as for groups.
+pub type Transform = GroupTransform;
+
+/// Refers to an asset in a `Registry`. Assets purged as unused leave their place empty, so a
+/// handle stays valid for the registry that gave it, giving nothing once its asset is purged.
+pub struct Handle<T> {
+    index: usize,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: usize) -> Self {
+        Self {
+            index,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> hash::Hash for Handle<T> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({})", self.index)
+    }
+}
+
+/// A spawned or loaded model, its index in `Scene::models`. Models are only ever added,
+/// replaced in place or left empty when removed, so the id keeps pointing at the same one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub usize);
+
+/// Named assets in the order they were added.
+#[derive(Debug)]
+pub struct Registry<T> {
+    /// `None` where an asset was purged.
+    assets: Vec<Option<(String, Arc<T>)>>,
+    names: HashMap<String, usize>,
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self {
+            assets: Vec::new(),
+            names: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Registry<T> {
+    /// Adds the asset, taking the place of one of the same name so its handle now gives the
+    /// new one.
+    pub fn insert(&mut self, name: impl Into<String>, asset: Arc<T>) -> Handle<T> {
+        let name = name.into();
+        match self.names.get(&name) {
+            Some(&index) => {
+                self.assets[index] = Some((name, asset));
+                Handle::new(index)
+            }
+            None => {
+                let index = self.assets.len();
+                self.names.insert(name.clone(), index);
+                self.assets.push(Some((name, asset)));
+                Handle::new(index)
+            }
+        }
+    }
+
+    /// The asset of that name, made with `make` if there is none yet.
+    pub fn get_or_insert_with(
+        &mut self,
+        name: impl Into<String>,
+        make: impl FnOnce() -> Arc<T>,
+    ) -> &Arc<T> {
+        let name = name.into();
+        let handle = match self.handle(&name) {
+            Some(handle) => handle,
+            None => self.insert(name, make()),
+        };
+        self.get(handle).unwrap()
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> Option<&Arc<T>> {
+        self.slot(handle.index).map(|(_, asset)| asset)
+    }
+
+    pub fn handle(&self, name: &str) -> Option<Handle<T>> {
+        self.names.get(name).map(|&index| Handle::new(index))
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&Arc<T>> {
+        self.handle(name).and_then(|handle| self.get(handle))
+    }
+
+    pub fn name(&self, handle: Handle<T>) -> Option<&str> {
+        self.slot(handle.index).map(|(name, _)| name.as_str())
+    }
+
+    fn slot(&self, index: usize) -> Option<&(String, Arc<T>)> {
+        self.assets.get(index).and_then(Option::as_ref)
+    }
+
+    /// The number of assets, leaving out purged ones.
+    pub fn len(&self) -> usize {
+        self.assets.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Names and assets in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<T>)> {
+        self.assets
+            .iter()
+            .flatten()
+            .map(|(name, asset)| (name.as_str(), asset))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Arc<T>> {
+        self.assets.iter().flatten().map(|(_, asset)| asset)
+    }
+
+    pub fn handles(&self) -> impl Iterator<Item = Handle<T>> + '_ {
+        (0..self.assets.len())
+            .filter(move |&index| self.assets[index].is_some())
+            .map(Handle::new)
+    }
+
+    /// Drops the assets nothing but the registry holds on to, returning how many. Their GPU
+    /// resources are freed with them.
+    pub fn purge_unused(&mut self) -> usize {
+        self.purge_unused_of(|_| true)
+    }
+
+    /// Like `purge_unused`, for the assets `of` picks only.
+    pub fn purge_unused_of(&mut self, mut of: impl FnMut(&Arc<T>) -> bool) -> usize {
+        let mut purged = 0;
+        for slot in self.assets.iter_mut() {
+            if matches!(slot, Some((_, asset)) if Arc::strong_count(asset) == 1 && of(asset)) {
+                let (name, _) = slot.take().unwrap();
+                self.names.remove(&name);
+                purged += 1;
+            }
+        }
+        purged
+    }
+}
+
+/// Geometry to spawn models from, kept on the CPU and uploaded for each model.
+#[derive(Debug, Clone)]
+pub struct MeshData {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    /// Triangles of the vertices, with the tangents worked out from their normals and
+    /// texture coordinates.
+    pub fn new(mut vertices: Vec<ModelVertex>, indices: Vec<u32>) -> Result<Self> {
+        if indices.len() % 3 != 0 {
+            bail!("Mesh indices don't make whole triangles");
+        }
+        if let Some(&index) = indices.iter().find(|&&i| i as usize >= vertices.len()) {
+            bail!(
+                "Mesh index {} is past the {} vertices",
+                index,
+                vertices.len()
+            );
+        }
+        compute_tangents(&mut vertices, &indices);
+        Ok(Self { vertices, indices })
+    }
+
+    /// The geometry of a mesh in the scene, to spawn copies of it.
+    pub fn from_mesh(mesh: &Mesh) -> Self {
+        Self {
+            vertices: mesh.vertices.clone(),
+            indices: mesh.indices.clone(),
+        }
+    }
+}
+
+/// The assets `Scene::purge_unused` dropped, by kind.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Purged {
+    pub materials: usize,
+    pub textures: usize,
+    pub shaders: usize,
+}
+
+/// The materials, textures and shaders of a model, without keeping them alive.
+#[derive(Default)]
+struct Used {
+    materials: Vec<Weak<Material>>,
+    textures: Vec<Weak<Texture>>,
+    shaders: Vec<Weak<Shader>>,
+}
+
+impl Used {
+    fn of(model: &Model) -> Self {
+        let mut used = Self::default();
+        for mesh in model.meshes() {
+            let material = &mesh.material;
+            used.materials.push(Arc::downgrade(material));
+            used.textures.extend(
+                [
+                    &material.diffuse_texture,
+                    &material.normal_texture,
+                    &material.specular_texture,
+                    &material.occlusion_texture,
+                    &material.emissive_texture,
+                ]
+                .iter()
+                .map(|texture| Arc::downgrade(texture)),
+            );
+            used.shaders.push(Arc::downgrade(&material.shader));
+            used.shaders
+                .extend(mesh.extra.iter().map(|extra| Arc::downgrade(&extra.shader)));
+        }
+        used
+    }
+
+    fn contains<T>(weaks: &[Weak<T>], asset: &Arc<T>) -> bool {
+        weaks.iter().any(|weak| weak.as_ptr() == Arc::as_ptr(asset))
+    }
+}
+
+/// Textures of a material made with `Scene::create_material`. Missing ones are plain: light
+/// grey, flat, not shiny, without occlusion and not glowing.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MaterialTextures {
+    pub diffuse: Option<Handle<Texture>>,
+    pub normal: Option<Handle<Texture>>,
+    pub specular: Option<Handle<Texture>>,
+    pub occlusion: Option<Handle<Texture>>,
+    pub emissive: Option<Handle<Texture>>,
+}
+
+impl Scene {
+    /// Adds a model, returning the id it keeps. It gets a root node in the scene graph.
+    pub fn add_model(&mut self, model: Model) -> NodeId {
+        let index = self.models.len();
+        let name = model
+            .meshes()
+            .first()
+            .map(|mesh| mesh.name.clone())
+            .unwrap_or_else(|| format!("Model {}", index));
+        self.models.push(model);
+        self.add_model_node(index, name);
+        NodeId(index)
+    }
+
+    pub fn add_mesh(&mut self, name: impl Into<String>, mesh: MeshData) -> Handle<MeshData> {
+        self.meshes.insert(name, Arc::new(mesh))
+    }
+
+    pub fn add_texture(&mut self, name: impl Into<String>, texture: Texture) -> Handle<Texture> {
+        self.textures.insert(name, Arc::new(texture))
+    }
+
+    /// Makes a material of the registered textures, drawn with the model shader.
+    pub fn create_material(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        name: &str,
+        textures: MaterialTextures,
+    ) -> Result<Handle<Material>> {
+        let texture = |handle: Option<Handle<Texture>>, label: &str| {
+            handle
+                .map(|handle| {
+                    self.textures
+                        .get(handle)
+                        .cloned()
+                        .with_context(|| format!("Material {}: there is no such {}", name, label))
+                })
+                .transpose()
+        };
+        let placeholders = &self.placeholders;
+        let diffuse = texture(textures.diffuse, "diffuse texture")?.unwrap_or_else(|| {
+            let color = [0xcc, 0xcc, 0xcc, 0xff];
+            Arc::new(Texture::one_pixel(device, queue, &color, Some("diffuse texture"), true))
+        });
+        let normal = texture(textures.normal, "normal texture")?
+            .unwrap_or_else(|| placeholders.flat_normal.clone());
+        let specular = texture(textures.specular, "specular texture")?
+            .unwrap_or_else(|| placeholders.black.clone());
+        let occlusion = texture(textures.occlusion, "occlusion texture")?
+            .unwrap_or_else(|| placeholders.white.clone());
+        let emissive = texture(textures.emissive, "emissive texture")?
+            .unwrap_or_else(|| placeholders.black.clone());
+        let features = ShaderFeatures {
+            normal_map: textures.normal.is_some(),
+            double_sided: !self.renderer.config.cull_back_faces,
+            ..Default::default()
+        };
+        let mut materials = self.materials.write().unwrap();
+        let material = Material::new(
+            device,
+            name,
+            diffuse,
+            normal,
+            specular,
+            occlusion,
+            emissive,
+            features,
+            materials.len() as u32,
+            &self.renderer.texture_bind_group_layout,
+            self.model_shader(device, config, features, None),
+        );
+        Ok(materials.insert(name, Arc::new(material)))
+    }
+
+    /// Adds a model of the mesh with the material, placed by `transform`.
+    pub fn spawn(
+        &mut self,
+        device: &wgpu::Device,
+        mesh: Handle<MeshData>,
+        material: Handle<Material>,
+        transform: Transform,
+    ) -> Result<NodeId> {
+        let name = self
+            .meshes
+            .name(mesh)
+            .context("There is no such mesh")?
+            .to_string();
+        let data = self
+            .meshes
+            .get(mesh)
+            .context("There is no such mesh")?
+            .clone();
+        let material = self
+            .materials
+            .read()
+            .unwrap()
+            .get(material)
+            .cloned()
+            .context("There is no such material")?;
+        let mut vertices = data.vertices.clone();
+        transform_vertices(&mut vertices, transform.matrix(Point3::new(0., 0., 0.)));
+        let mesh = Mesh::new(device, name, vertices, data.indices.clone(), material);
+        Ok(self.add_model(Model::OBJ(ObjModel { meshes: vec![mesh] })))
+    }
+
+    /// Takes the model out of the scene. An empty model keeps its place, so the ids of the
+    /// others stay. The materials, textures and shaders no other model uses are dropped with
+    /// their GPU resources.
+    pub fn remove_model(&mut self, id: NodeId) -> Purged {
+        let index = id.0;
+        let used = match self.models.get(index) {
+            Some(model) => Used::of(model),
+            None => return Purged::default(),
+        };
+        self.replace_model(index, Model::OBJ(ObjModel { meshes: Vec::new() }));
+        self.graph.remove_model(index);
+        self.outliner.selected.remove(&index);
+        self.renderer.instancing.models.remove(&index);
+        self.poses.set_clips(index, Vec::new());
+        self.set_skins(index, Vec::new());
+        self.poses.poses.retain(|pose| pose.model != index);
+        self.purge(
+            |material| Used::contains(&used.materials, material),
+            |texture| Used::contains(&used.textures, texture),
+            |shader| Used::contains(&used.shaders, shader),
+        )
+    }
+
+    /// Drops the materials, textures and shaders no model uses, e.g. materials made but never
+    /// spawned with.
+    pub fn purge_unused(&mut self) -> Purged {
+        self.purge(|_| true, |_| true, |_| true)
+    }
+
+    fn purge(
+        &mut self,
+        materials: impl FnMut(&Arc<Material>) -> bool,
+        textures: impl FnMut(&Arc<Texture>) -> bool,
+        mut shaders: impl FnMut(&Arc<Shader>) -> bool,
+    ) -> Purged {
+        // Materials first, as they hold on to their textures and shader.
+        let materials = self.materials.write().unwrap().purge_unused_of(materials);
+        let textures = self.textures.purge_unused_of(textures);
+        let mut cached = self.shaders.write().unwrap();
+        let count = cached.len();
+        cached.retain(|_, shader| Arc::strong_count(shader) > 1 || !shaders(shader));
+        let purged = Purged {
+            materials,
+            textures,
+            shaders: count - cached.len(),
+        };
+        log::info!("Purged {:?}", purged);
+        purged
+    }
+}
@@ -0,0 +1,145 @@
+//! Finding the files of a project that moved. Projects name their models relative to the
+//! project file, so a project moved together with its models opens anywhere; when one names a
+//! file that isn't there anymore, the remap dialog lists the missing files and looks for them
+//! under a folder picked instead: where their path ends up below it, or anywhere below it by
+//! name. The models still missing when the project is opened are left out of it.
+
+use std::{
+    collections::VecDeque,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::project::{self, Project};
+
+/// How many folders deep the search goes below the base folder.
+const SEARCH_DEPTH: usize = 8;
+
+/// The model files the project names that don't exist, each once. The web build loads models
+/// by URL, which can't be checked beforehand.
+pub fn missing_files(project: &Project) -> Vec<String> {
+    if cfg!(target_arch = "wasm32") {
+        return Vec::new();
+    }
+    let mut missing: Vec<String> = Vec::new();
+    for source in project.models.iter() {
+        let moved = !source.is_empty() && !project::is_url(source) && !Path::new(source).exists();
+        if moved && !missing.contains(source) {
+            missing.push(source.clone());
+        }
+    }
+    missing
+}
+
+/// The file names of `path` from the last one back, as far as they go.
+fn names(path: &str) -> Vec<&str> {
+    Path::new(path)
+        .components()
+        .rev()
+        .take_while(|component| matches!(component, Component::Normal(_)))
+        .filter_map(|component| component.as_os_str().to_str())
+        .collect()
+}
+
+/// A project whose files are being looked for.
+#[derive(Debug)]
+pub struct PathRemap {
+    pub project: Project,
+    /// The missing files with where they were found, if they were.
+    pub files: Vec<(String, Option<String>)>,
+    /// The folder to look in.
+    pub base: String,
+}
+
+impl PathRemap {
+    pub fn new(project: Project, missing: Vec<String>) -> Self {
+        let base = missing
+            .first()
+            .and_then(|file| Path::new(file).parent())
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self {
+            project,
+            files: missing.into_iter().map(|file| (file, None)).collect(),
+            base,
+        }
+    }
+
+    pub fn found(&self) -> usize {
+        self.files.iter().filter(|(_, found)| found.is_some()).count()
+    }
+
+    /// Looks for each file not found yet at the end of its path below the base folder, e.g.
+    /// `old/assets/house/house.obj` as `<base>/house/house.obj`, then as `<base>/house.obj`.
+    pub fn rebase(&mut self) -> usize {
+        let base = PathBuf::from(&self.base);
+        let mut found = 0;
+        for (file, place) in self.files.iter_mut().filter(|(_, place)| place.is_none()) {
+            let names = names(file);
+            *place = (1..=names.len()).rev().find_map(|count| {
+                let candidate = names[..count].iter().rev().fold(base.clone(), |path, name| {
+                    path.join(name)
+                });
+                candidate.is_file().then(|| candidate.to_string_lossy().into_owned())
+            });
+            found += place.is_some() as usize;
+        }
+        found
+    }
+
+    /// Looks for each file not found yet by its name anywhere below the base folder, taking the
+    /// shallowest of the same name.
+    pub fn search(&mut self) -> usize {
+        let mut wanted = self
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, place))| place.is_none())
+            .filter_map(|(i, (file, _))| Some((i, Path::new(file).file_name()?.to_owned())))
+            .collect::<Vec<_>>();
+        let mut found = 0;
+        let mut dirs = VecDeque::from(vec![(PathBuf::from(&self.base), 0)]);
+        while let Some((dir, depth)) = dirs.pop_front() {
+            if wanted.is_empty() {
+                break;
+            }
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let path = entry.path();
+                if path.is_dir() {
+                    if depth < SEARCH_DEPTH {
+                        dirs.push_back((path, depth + 1));
+                    }
+                    continue;
+                }
+                let name = entry.file_name();
+                let files = &mut self.files;
+                wanted.retain(|(i, wanted)| {
+                    if *wanted != name {
+                        return true;
+                    }
+                    files[*i].1 = Some(path.to_string_lossy().into_owned());
+                    found += 1;
+                    false
+                });
+            }
+        }
+        found
+    }
+
+    /// The project with its models moved to where they were found, the rest left out.
+    pub fn resolve(self) -> Project {
+        let mut project = self.project;
+        for (file, place) in self.files.iter() {
+            if place.is_none() {
+                log::warn!("Leaving out {}, it wasn't found", file);
+            }
+            for source in project.models.iter_mut().filter(|source| *source == file) {
+                *source = place.clone().unwrap_or_default();
+            }
+        }
+        project
+    }
+}
@@ -0,0 +1,380 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    mpsc, Arc,
+};
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3, Zero};
+
+use crate::{
+    bounds::Aabb,
+    bvh::Bvh,
+    model::{Model, ModelVertex},
+    picking::Ray,
+    scene::Scene,
+};
+
+/// Voxels along the longest axis of a mesh are capped at this, whatever the voxel size.
+const MAX_RESOLUTION: usize = 512;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RemeshSettings {
+    /// Edge length of the voxels, roughly the edge length of the result.
+    pub voxel_size: f32,
+}
+
+impl Default for RemeshSettings {
+    fn default() -> Self {
+        Self { voxel_size: 0.05 }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Remeshed {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+/// Coordinates along `axis` where the line from `origin` crosses the surface, in order.
+fn crossings(bvh: &Bvh, origin: Point3<f32>, axis: usize, length: f32, epsilon: f32) -> Vec<f32> {
+    let mut direction = Vector3::zero();
+    direction[axis] = 1.;
+    let mut found = Vec::new();
+    let mut travelled = 0.;
+    while let Some(hit) = bvh.intersect(
+        &Ray {
+            origin: origin + direction * travelled,
+            direction,
+        },
+        length - travelled,
+    ) {
+        travelled += hit.distance;
+        found.push(origin[axis] + travelled);
+        // Step past the hit so triangles sharing the hit edge or corner count once.
+        travelled += epsilon;
+    }
+    found
+}
+
+/// Rebuilds a mesh from its voxelized volume with surface nets, giving evenly sized faces
+/// regardless of the input topology. The mesh should be closed, as inside and outside are told
+/// apart by counting surface crossings.
+///
+/// `report` is called with the progress from 0 to 1 and returns whether to go on. Returns
+/// `None` when it stopped the remesh. Texture coordinates are not carried over.
+pub fn voxel_remesh(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    settings: &RemeshSettings,
+    report: &dyn Fn(f32) -> bool,
+) -> Option<Remeshed> {
+    let bounds = Aabb::from_points(vertices.iter().map(|v| v.position.into()));
+    if bounds.is_empty() || indices.is_empty() {
+        return Some(Remeshed::default());
+    }
+    let size = bounds.size();
+    let longest = size.x.max(size.y).max(size.z);
+    let h = settings
+        .voxel_size
+        .max(longest / MAX_RESOLUTION as f32)
+        .max(1e-6);
+    // Pad by two voxels so the surface never touches the border of the grid.
+    let min = bounds.min - Vector3::new(2. * h, 2. * h, 2. * h);
+    let n = [0, 1, 2].map(|a| (size[a] / h).ceil() as usize + 5);
+    let point = |g: [usize; 3]| min + Vector3::new(g[0] as f32, g[1] as f32, g[2] as f32) * h;
+    let index = |g: [usize; 3]| (g[2] * n[1] + g[1]) * n[0] + g[0];
+
+    // Surface crossings along every grid line of every axis, indexed by the other two
+    // coordinates of the line.
+    let positions = vertices
+        .iter()
+        .map(|v| Point3::from(v.position))
+        .collect::<Vec<_>>();
+    let bvh = Bvh::from_indexed(&positions, indices);
+    let mut lines: [Vec<Vec<f32>>; 3] = Default::default();
+    for a in 0..3 {
+        let (b, c) = ((a + 1) % 3, (a + 2) % 3);
+        for ic in 0..n[c] {
+            if !report((a as f32 + ic as f32 / n[c] as f32) / 3. * 0.7) {
+                return None;
+            }
+            for ib in 0..n[b] {
+                let mut origin = min;
+                origin[b] += ib as f32 * h;
+                origin[c] += ic as f32 * h;
+                let length = (n[a] - 1) as f32 * h;
+                lines[a].push(crossings(&bvh, origin, a, length, h * 1e-3));
+            }
+        }
+    }
+
+    let mut inside = vec![false; n[0] * n[1] * n[2]];
+    for k in 0..n[2] {
+        for j in 0..n[1] {
+            let xs = &lines[0][k * n[1] + j];
+            let mut count = 0;
+            for i in 0..n[0] {
+                let x = min.x + i as f32 * h;
+                while count < xs.len() && xs[count] < x {
+                    count += 1;
+                }
+                inside[index([i, j, k])] = count % 2 == 1;
+            }
+        }
+    }
+    let edge_crossing = |g: [usize; 3], a: usize| {
+        let (b, c) = ((a + 1) % 3, (a + 2) % 3);
+        let mut p = point(g);
+        let start = p[a];
+        p[a] = lines[a][g[c] * n[b] + g[b]]
+            .iter()
+            .copied()
+            .find(|&t| t >= start && t <= start + h)
+            .unwrap_or(start + h * 0.5);
+        p
+    };
+
+    // One vertex per cell the surface passes through, at the average of its edge crossings.
+    let cells = [n[0] - 1, n[1] - 1, n[2] - 1];
+    let cell_index = |g: [usize; 3]| (g[2] * cells[1] + g[1]) * cells[0] + g[0];
+    let mut cell_vertex = vec![u32::MAX; cells[0] * cells[1] * cells[2]];
+    let mut positions = Vec::new();
+    for k in 0..cells[2] {
+        if !report(0.7 + 0.2 * k as f32 / cells[2] as f32) {
+            return None;
+        }
+        for j in 0..cells[1] {
+            for i in 0..cells[0] {
+                let mut sum = Vector3::zero();
+                let mut count = 0;
+                for corner in 0..8 {
+                    let g = [
+                        i + (corner & 1),
+                        j + (corner >> 1 & 1),
+                        k + (corner >> 2 & 1),
+                    ];
+                    for a in (0..3).filter(|a| corner >> a & 1 == 0) {
+                        let mut end = g;
+                        end[a] += 1;
+                        if inside[index(g)] != inside[index(end)] {
+                            sum += edge_crossing(g, a).to_vec();
+                            count += 1;
+                        }
+                    }
+                }
+                if count > 0 {
+                    cell_vertex[cell_index([i, j, k])] = positions.len() as u32;
+                    positions.push(Point3::from_vec(sum / count as f32));
+                }
+            }
+        }
+    }
+
+    // A quad across every grid edge the surface crosses, joining the four cells around it.
+    let mut indices = Vec::new();
+    for k in 0..n[2] {
+        if !report(0.9 + 0.1 * k as f32 / n[2] as f32) {
+            return None;
+        }
+        for j in 0..n[1] {
+            for i in 0..n[0] {
+                let g = [i, j, k];
+                for a in 0..3 {
+                    let (b, c) = ((a + 1) % 3, (a + 2) % 3);
+                    if g[a] + 1 >= n[a]
+                        || g[b] == 0
+                        || g[c] == 0
+                        || g[b] >= cells[b]
+                        || g[c] >= cells[c]
+                    {
+                        continue;
+                    }
+                    let mut end = g;
+                    end[a] += 1;
+                    if inside[index(g)] == inside[index(end)] {
+                        continue;
+                    }
+                    // Counter-clockwise seen from the positive end of the edge.
+                    let quad = [(1, 1), (0, 1), (0, 0), (1, 0)].map(|(db, dc)| {
+                        let mut cell = g;
+                        cell[b] -= db;
+                        cell[c] -= dc;
+                        cell_vertex[cell_index(cell)]
+                    });
+                    let [q0, q1, q2, q3] = if inside[index(g)] {
+                        quad
+                    } else {
+                        [quad[3], quad[2], quad[1], quad[0]]
+                    };
+                    let p = |q: u32| positions[q as usize];
+                    if (p(q0) - p(q2)).magnitude2() <= (p(q1) - p(q3)).magnitude2() {
+                        indices.extend_from_slice(&[q0, q1, q2, q0, q2, q3]);
+                    } else {
+                        indices.extend_from_slice(&[q0, q1, q3, q1, q2, q3]);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut normals = vec![Vector3::zero(); positions.len()];
+    for t in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| positions[t[i] as usize]);
+        let normal = (b - a).cross(c - a);
+        for &i in t {
+            normals[i as usize] += normal;
+        }
+    }
+    let vertices = positions
+        .iter()
+        .zip(normals)
+        .map(|(p, n)| {
+            let normal = if n.magnitude2() > 0. {
+                n.normalize()
+            } else {
+                Vector3::unit_y()
+            };
+            // Without texture coordinates any frame around the normal will do.
+            let helper = if normal.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+            let tangent = helper.cross(normal).normalize();
+            ModelVertex {
+                position: (*p).into(),
+                tex_coords: [0., 0.],
+                normal: normal.into(),
+                tangent: tangent.into(),
+                bitangent: normal.cross(tangent).into(),
+            }
+        })
+        .collect();
+    Some(Remeshed { vertices, indices })
+}
+
+/// Progress of a job on a worker thread, also of scan cleanups.
+#[derive(Debug, Default)]
+pub(crate) struct Progress {
+    cancelled: AtomicBool,
+    /// Tenths of a percent.
+    permille: AtomicU32,
+}
+
+impl Progress {
+    /// Stores the progress, returning whether the job should go on.
+    pub(crate) fn report(&self, fraction: f32) -> bool {
+        let permille = (fraction.max(0.).min(1.) * 1000.) as u32;
+        self.permille.store(permille, Ordering::Relaxed);
+        !self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn fraction(&self) -> f32 {
+        self.permille.load(Ordering::Relaxed) as f32 / 1000.
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Remesh of a whole model running on a worker thread, as it takes seconds for fine voxels.
+#[derive(Debug)]
+pub struct RemeshJob {
+    pub model: usize,
+    progress: Arc<Progress>,
+    receiver: mpsc::Receiver<Vec<Remeshed>>,
+}
+
+impl RemeshJob {
+    /// Starts remeshing a copy of the model's current geometry.
+    pub fn spawn(index: usize, model: &Model, settings: RemeshSettings) -> Self {
+        let meshes = model
+            .meshes()
+            .iter()
+            .map(|m| (m.vertices.clone(), m.indices.clone()))
+            .collect::<Vec<_>>();
+        let progress = Arc::new(Progress::default());
+        let (sender, receiver) = mpsc::channel();
+        let worker_progress = progress.clone();
+        let work = move || {
+            let count = meshes.len().max(1) as f32;
+            let mut remeshed = Vec::new();
+            for (i, (vertices, indices)) in meshes.iter().enumerate() {
+                let report = |fraction: f32| worker_progress.report((i as f32 + fraction) / count);
+                match voxel_remesh(vertices, indices, &settings, &report) {
+                    Some(mesh) => remeshed.push(mesh),
+                    None => return,
+                }
+            }
+            // Fails if the job was dropped in the meantime, when nobody wants the result.
+            let _ = sender.send(remeshed);
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(work);
+        // There are no threads on the web, so it blocks there instead.
+        #[cfg(target_arch = "wasm32")]
+        work();
+
+        Self {
+            model: index,
+            progress,
+            receiver,
+        }
+    }
+
+    /// From 0 to 1.
+    pub fn progress(&self) -> f32 {
+        self.progress.fraction()
+    }
+
+    /// Asks the worker to stop at the next opportunity.
+    pub fn cancel(&self) {
+        self.progress.cancel();
+    }
+}
+
+impl Scene {
+    /// Starts remeshing every mesh of the model in the background, replacing any remesh of it
+    /// still running.
+    pub fn start_remesh(&mut self, model: usize, settings: RemeshSettings) {
+        self.cancel_remesh(model);
+        let job = RemeshJob::spawn(model, &self.models[model], settings);
+        self.remesh_jobs.push(job);
+    }
+
+    pub fn cancel_remesh(&mut self, model: usize) {
+        for job in self.remesh_jobs.iter().filter(|job| job.model == model) {
+            job.cancel();
+        }
+        self.remesh_jobs.retain(|job| job.model != model);
+    }
+
+    pub fn remesh_job(&self, model: usize) -> Option<&RemeshJob> {
+        self.remesh_jobs.iter().find(|job| job.model == model)
+    }
+
+    /// Swaps in the meshes of finished jobs. Takes the device as their buffers change size.
+    pub fn poll_remesh(&mut self, device: &wgpu::Device) {
+        profile_function!();
+        let mut finished = Vec::new();
+        self.remesh_jobs
+            .retain(|job| match job.receiver.try_recv() {
+                Ok(meshes) => {
+                    finished.push((job.model, meshes));
+                    false
+                }
+                Err(mpsc::TryRecvError::Empty) => true,
+                Err(mpsc::TryRecvError::Disconnected) => false,
+            });
+
+        for (model, meshes) in finished {
+            self.edit.release_target();
+            for (mesh, remeshed) in self.models[model].meshes_mut().iter_mut().zip(meshes) {
+                if !remeshed.indices.is_empty() {
+                    mesh.replace_geometry(device, remeshed.vertices, remeshed.indices);
+                }
+            }
+            self.topology_changed(model);
+        }
+    }
+}
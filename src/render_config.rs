@@ -0,0 +1,118 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::shadow::ShadowMap;
+
+/// Formats the depth buffer of the scene can have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DepthFormat {
+    Depth32Float,
+    Depth24Plus,
+    Depth24PlusStencil8,
+}
+
+impl DepthFormat {
+    pub const ALL: [DepthFormat; 3] = [
+        DepthFormat::Depth32Float,
+        DepthFormat::Depth24Plus,
+        DepthFormat::Depth24PlusStencil8,
+    ];
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        match self {
+            DepthFormat::Depth32Float => wgpu::TextureFormat::Depth32Float,
+            DepthFormat::Depth24Plus => wgpu::TextureFormat::Depth24Plus,
+            DepthFormat::Depth24PlusStencil8 => wgpu::TextureFormat::Depth24PlusStencil8,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DepthFormat::Depth32Float => "depth32float",
+            DepthFormat::Depth24Plus => "depth24plus",
+            DepthFormat::Depth24PlusStencil8 => "depth24plus-stencil8",
+        }
+    }
+
+    /// Reads a format by the name wgpu gives it, e.g. `depth24plus`.
+    pub fn parse(name: &str) -> Result<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == name.to_ascii_lowercase().replace('_', "-"))
+            .with_context(|| {
+                let names = Self::ALL.iter().map(|f| f.name()).collect::<Vec<_>>();
+                format!(
+                    "Unknown depth format {}, expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Reads a color from three or four comma separated numbers, e.g. `0.1,0.2,0.3`.
+pub fn parse_color(text: &str) -> Result<[f32; 4]> {
+    let values = text
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Color {:?} isn't made of numbers", text))?;
+    match values[..] {
+        [r, g, b] => Ok([r, g, b, 1.]),
+        [r, g, b, a] => Ok([r, g, b, a]),
+        _ => bail!("Color {:?} needs three or four values", text),
+    }
+}
+
+/// Settings of the renderer, read from a RON file and the command line and changed in the
+/// Render panel. The depth format and culling are only read when pipelines are made, so they
+/// apply from the start or to models loaded after the change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderConfig {
+    /// Linear RGBA the scene is drawn over.
+    pub clear_color: [f32; 4],
+    pub depth_format: DepthFormat,
+    /// Frames per second at most, `None` to draw as fast as the display allows.
+    pub frame_cap: Option<u32>,
+    /// Skips the back faces of models, which don't show on closed meshes.
+    pub cull_back_faces: bool,
+    /// Width and height of the light's shadow map when the scene is made.
+    pub shadow_map_size: u32,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            clear_color: [0.1, 0.2, 0.3, 1.0],
+            depth_format: DepthFormat::Depth32Float,
+            frame_cap: Some(60),
+            cull_back_faces: false,
+            shadow_map_size: ShadowMap::SIZE,
+        }
+    }
+}
+
+impl RenderConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path.as_ref())
+            .with_context(|| format!("Render config: {:?} not found", path.as_ref()))?;
+        ron::de::from_reader(BufReader::new(file))
+            .with_context(|| format!("Render config: {:?} is broken", path.as_ref()))
+    }
+
+    pub fn clear_color(&self) -> wgpu::Color {
+        let [r, g, b, a] = self.clear_color.map(|c| c as f64);
+        wgpu::Color { r, g, b, a }
+    }
+
+    /// Time between frames, `None` without a cap.
+    pub fn frame_time(&self) -> Option<std::time::Duration> {
+        self.frame_cap
+            .filter(|&fps| fps > 0)
+            .map(|fps| std::time::Duration::from_secs_f64(1.0 / fps as f64))
+    }
+}
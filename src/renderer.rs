@@ -2,15 +2,32 @@ use std::mem;
 
 use bytemuck::{Pod, Zeroable};
 use cgmath::SquareMatrix;
-use wgpu::util::DeviceExt;
 
-use crate::{camera::{self, Camera, Projection}, light::{Light, LightObject, LightRaw, Lights}, model::{self, Material, Model, Vertex}, texture};
+use crate::{
+    bounds::Aabb,
+    camera::{self, Camera, Projection},
+    compare::ComparePass,
+    debug_lines::DebugLines,
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    impostor::ImpostorPass,
+    instancing::Instancing,
+    light::{Light, LightObject, LightRaw, Lights},
+    model::{self, Material, Model, Vertex},
+    render_config::RenderConfig,
+    shadow::ShadowMap,
+    texture,
+    wireframe::WireframePass,
+    xray::XRayPass,
+};
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
 pub struct UniformsRaw {
     view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    /// Corners of the section box, with w of the minimum set while it clips.
+    section_min: [f32; 4],
+    section_max: [f32; 4],
 }
 
 impl UniformsRaw {
@@ -18,13 +35,25 @@ impl UniformsRaw {
         Self {
             view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
+            section_min: [0.0; 4],
+            section_max: [0.0; 4],
         }
     }
 
     fn update_view_proj(&mut self, camera: &Camera) {
-        use crate::camera::PerspectiveFovExt;
         self.view_position = camera.eye.to_homogeneous().into();
-        self.view_proj = (camera.projection.calc_matrix() * camera.calc_matrix()).into();
+        self.view_proj = (camera.projection_matrix() * camera.calc_matrix()).into();
+    }
+
+    fn set_section(&mut self, section: Option<&Aabb>) {
+        match section {
+            Some(section) => {
+                let (min, max) = (section.min, section.max);
+                self.section_min = [min.x, min.y, min.z, 1.0];
+                self.section_max = [max.x, max.y, max.z, 0.0];
+            }
+            None => self.section_min[3] = 0.0,
+        }
     }
 }
 
@@ -32,21 +61,24 @@ impl UniformsRaw {
 #[derive(Debug)]
 pub struct Uniforms {
     pub uniforms: UniformsRaw,
-    pub buffer: wgpu::Buffer,
+    pub buffer: Tracked<wgpu::Buffer>,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
 }
 
 impl Uniforms {
-    fn new(device: &wgpu::Device, camera: &Camera) -> Self {
+    pub(crate) fn new(device: &wgpu::Device, camera: &Camera) -> Self {
         let mut uniforms = UniformsRaw::new();
         uniforms.update_view_proj(camera);
 
-        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Uniform Buffer"),
-            contents: bytemuck::cast_slice(&[uniforms]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        let buffer = device.create_tracked_buffer_init(
+            GpuCategory::Uniforms,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Uniform Buffer"),
+                contents: bytemuck::cast_slice(&[uniforms]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[wgpu::BindGroupLayoutEntry {
@@ -77,7 +109,7 @@ impl Uniforms {
             bind_group_layout,
         }
     }
-    fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+    pub(crate) fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
         self.uniforms.update_view_proj(camera);
         queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
     }
@@ -86,8 +118,17 @@ impl Uniforms {
 #[derive(Debug)]
 pub struct Renderer {
     pub uniforms: Uniforms,
+    /// Camera of the right eye in stereo mode, where `uniforms` holds the left one.
+    pub right_eye: Uniforms,
     pub depth_texture: texture::Texture,
     pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    pub debug_lines: DebugLines,
+    pub shadows: ShadowMap,
+    pub xray: XRayPass,
+    pub wireframe: WireframePass,
+    pub impostors: ImpostorPass,
+    pub instancing: Instancing,
+    pub config: RenderConfig,
 }
 
 impl Renderer {
@@ -96,8 +137,10 @@ impl Renderer {
         config: &wgpu::SurfaceConfiguration,
         camera: &Camera,
         light: &LightObject,
+        render_config: RenderConfig,
     ) -> Self {
         let uniforms = Uniforms::new(device, camera);
+        let right_eye = Uniforms::new(device, camera);
 
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -159,22 +202,193 @@ impl Renderer {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler {
+                            comparison: false,
+                            filtering: true,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler {
+                            comparison: false,
+                            filtering: true,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             });
 
+        let depth_format = render_config.depth_format.format();
         let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+            texture::Texture::create_depth_texture(&device, &config, depth_format, "depth_texture");
+
+        let debug_lines = DebugLines::new(
+            device,
+            config.format,
+            depth_format,
+            &uniforms.bind_group_layout,
+        );
+        let shadows = ShadowMap::new(device, render_config.shadow_map_size);
+        let xray = XRayPass::new(
+            device,
+            config.format,
+            depth_format,
+            &uniforms.bind_group_layout,
+            &shadows.bind_group_layout,
+        );
+        let wireframe = WireframePass::new(
+            device,
+            config.format,
+            depth_format,
+            &uniforms.bind_group_layout,
+            &shadows.bind_group_layout,
+        );
+        let impostors = ImpostorPass::new(
+            device,
+            config.format,
+            depth_format,
+            &uniforms.bind_group_layout,
+            camera,
+        );
 
         Self {
             uniforms,
+            right_eye,
             depth_texture,
             texture_bind_group_layout,
+            debug_lines,
+            shadows,
+            xray,
+            wireframe,
+            impostors,
+            instancing: Instancing::new(device),
+            config: render_config,
         }
     }
 
     pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
         self.uniforms.update(queue, camera);
+        self.debug_lines.upload(queue);
+    }
+
+    /// Clips the models to the section box from the next `update` on.
+    pub fn set_section(&mut self, section: Option<&Aabb>) {
+        self.uniforms.uniforms.set_section(section);
+        self.right_eye.uniforms.set_section(section);
+    }
+
+    /// Like `update` with a camera per eye, for `draw_stereo`.
+    pub fn update_stereo(&mut self, queue: &wgpu::Queue, left: &Camera, right: &Camera) {
+        self.uniforms.update(queue, left);
+        self.right_eye.update(queue, right);
+        self.debug_lines.upload(queue);
+    }
+
+    /// Draws the models seen with `uniforms` into the viewport, the whole target if `None`.
+    /// The color is only cleared with `clear`, so several viewports can share a target.
+    fn forward_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        frame_view: &wgpu::TextureView,
+        models: &[(usize, &Model)],
+        lights: &Lights,
+        uniforms: &Uniforms,
+        viewport: Option<[f32; 4]>,
+        clear: bool,
+        side: Option<&ComparePass>,
+    ) {
+        profile_function!();
+        let load = if clear {
+            wgpu::LoadOp::Clear(self.config.clear_color())
+        } else {
+            wgpu::LoadOp::Load
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: frame_view,
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: true },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        if let Some([x, y, width, height]) = viewport {
+            render_pass.set_viewport(x, y, width, height, 0., 1.);
+        }
+        if let Some(side) = side {
+            let [x, y, width, height] = side.scissor;
+            render_pass.set_scissor_rect(x, y, width, height);
+        }
+
+        let surfaces = if self.wireframe.hides_surfaces() { &[][..] } else { models };
+        for &(index, model) in surfaces {
+            use model::DrawModel;
+            for (i, mesh) in model.meshes().iter().enumerate() {
+                let (offset, material) = match side {
+                    Some(side) if side.compared => {
+                        (self.shadows.compared_offset(index, i), side.material(&mesh.material))
+                    }
+                    _ => (self.shadows.object_offset(index, i), &*mesh.material),
+                };
+                render_pass.set_bind_group(3, &self.shadows.bind_group, &[offset]);
+                render_pass.draw_mesh_instanced(
+                    mesh,
+                    &Some(material),
+                    self.instancing.of(index),
+                    &uniforms.bind_group,
+                    &lights.lights[0].bind_group,
+                );
+            }
+        }
+        self.impostors.draw(&mut render_pass, &uniforms.bind_group);
+        self.xray
+            .draw(&mut render_pass, models, &uniforms.bind_group, &self.shadows);
+        self.wireframe
+            .draw(&mut render_pass, models, &uniforms.bind_group, &self.shadows);
+        self.debug_lines.draw(&mut render_pass, &uniforms.bind_group);
     }
 }
 
@@ -183,7 +397,43 @@ pub trait RendererExt {
         &self,
         encoder: &mut wgpu::CommandEncoder,
         frame_view: &wgpu::TextureView,
-        model: &Vec<Model>,
+        models: &[(usize, &Model)],
+        shadow_casters: &[(usize, &Model)],
+        light: &Lights,
+    );
+
+    /// Draws the two sides of the comparison view, each within its scissor rectangle.
+    fn draw_compare(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        frame_view: &wgpu::TextureView,
+        models: &[(usize, &Model)],
+        shadow_casters: &[(usize, &Model)],
+        light: &Lights,
+        left: &ComparePass,
+        right: &ComparePass,
+    );
+
+    /// Draws the left eye into the left half of the frame and the right eye into the right
+    /// half, with the cameras given to `update_stereo`.
+    fn draw_stereo(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        frame_view: &wgpu::TextureView,
+        models: &[(usize, &Model)],
+        shadow_casters: &[(usize, &Model)],
+        light: &Lights,
+    );
+
+    /// Draws each eye over the whole of its own target, with the cameras given to
+    /// `update_stereo`.
+    fn draw_eyes(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        left_view: &wgpu::TextureView,
+        right_view: &wgpu::TextureView,
+        models: &[(usize, &Model)],
+        shadow_casters: &[(usize, &Model)],
         light: &Lights,
     );
 }
@@ -193,101 +443,66 @@ impl RendererExt for Renderer {
         &self,
         encoder: &mut wgpu::CommandEncoder,
         frame_view: &wgpu::TextureView,
-        models: &Vec<Model>,
+        models: &[(usize, &Model)],
+        shadow_casters: &[(usize, &Model)],
         lights: &Lights,
     ) {
-//        let light_uniform_size =
-//            (2 * mem::size_of::<LightRaw>()) as wgpu::BufferAddress;
-//        let light_storage_buf = device.create_buffer(&wgpu::BufferDescriptor {
-//            label: None,
-//            size: light_uniform_size,
-//            usage: wgpu::BufferUsages::STORAGE
-//                | wgpu::BufferUsages::COPY_SRC
-//                | wgpu::BufferUsages::COPY_DST,
-//            mapped_at_creation: false,
-//        });
-//
-//        // shadow pass
-//        encoder.push_debug_group("shadow passes");
-//        for (i, light) in lights.lights.iter().enumerate() {
-//            encoder.push_debug_group(&format!(
-//                "shadow pass {} (light at position {:?})",
-//                i, light.light.position
-//            ));
-//
-//            // The light uniform buffer already has the projection,
-//            // let's just copy it over to the shadow uniform buffer.
-//            encoder.copy_buffer_to_buffer(
-//                &lights.light_storage_buf,
-//                (i * mem::size_of::<LightRaw>()) as wgpu::BufferAddress,
-//                &self.shadow_pass.uniform_buf,
-//                0,
-//                64,
-//            );
-//
-//            encoder.insert_debug_marker("render entities");
-//            {
-//                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-//                    label: None,
-//                    color_attachments: &[],
-//                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-//                        view: &light.light.shadow_view.unwrap(),
-//                        depth_ops: Some(wgpu::Operations {
-//                            load: wgpu::LoadOp::Clear(1.0),
-//                            store: true,
-//                        }),
-//                        stencil_ops: None,
-//                    }),
-//                });
-//                pass.set_pipeline(&self.shadow_pass.pipeline);
-//                pass.set_bind_group(0, &self.shadow_pass.bind_group, &[]);
-//
-//                for entity in &self.entities {
-//                    pass.set_bind_group(1, &self.entity_bind_group, &[entity.uniform_offset]);
-//                    pass.set_index_buffer(entity.index_buf.slice(..), entity.index_format);
-//                    pass.set_vertex_buffer(0, entity.vertex_buf.slice(..));
-//                    pass.draw_indexed(0..entity.index_count as u32, 0, 0..1);
-//                }
-//            }
-//
-//            encoder.pop_debug_group();
-//        }
-//        encoder.pop_debug_group();
-//
-//        // forward pass
-//        encoder.push_debug_group("forward rendering pass");
-//        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
-                color_attachments: &[wgpu::RenderPassColorAttachment {
-                    view: frame_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        // load: wgpu::LoadOp::Load,
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
+        self.shadows.draw(encoder, shadow_casters);
+        lights.clusters.cull(encoder);
+        self.forward_pass(encoder, frame_view, models, lights, &self.uniforms, None, true, None);
+    }
 
-            for model in models {
-                use model::DrawModel;
-                render_pass.draw_model(model, &self.uniforms.bind_group, &lights.lights[0].bind_group);
-            }
-       // }
-       // encoder.pop_debug_group();
+    fn draw_compare(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        frame_view: &wgpu::TextureView,
+        models: &[(usize, &Model)],
+        shadow_casters: &[(usize, &Model)],
+        lights: &Lights,
+        left: &ComparePass,
+        right: &ComparePass,
+    ) {
+        self.shadows.draw(encoder, shadow_casters);
+        lights.clusters.cull(encoder);
+        let uniforms = &self.uniforms;
+        self.forward_pass(encoder, frame_view, models, lights, uniforms, None, true, Some(left));
+        self.forward_pass(encoder, frame_view, models, lights, uniforms, None, false, Some(right));
+    }
+
+    fn draw_stereo(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        frame_view: &wgpu::TextureView,
+        models: &[(usize, &Model)],
+        shadow_casters: &[(usize, &Model)],
+        lights: &Lights,
+    ) {
+        self.shadows.draw(encoder, shadow_casters);
+        lights.clusters.cull(encoder);
+        let size = self.depth_texture.size;
+        let (width, height) = (size.width as f32, size.height as f32);
+        let half = (width / 2.).floor();
+        let left = [0., 0., half, height];
+        let right = [half, 0., width - half, height];
+        let uniforms = &self.uniforms;
+        self.forward_pass(encoder, frame_view, models, lights, uniforms, Some(left), true, None);
+        let right_eye = &self.right_eye;
+        self.forward_pass(encoder, frame_view, models, lights, right_eye, Some(right), false, None);
+    }
+
+    fn draw_eyes(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        left_view: &wgpu::TextureView,
+        right_view: &wgpu::TextureView,
+        models: &[(usize, &Model)],
+        shadow_casters: &[(usize, &Model)],
+        lights: &Lights,
+    ) {
+        self.shadows.draw(encoder, shadow_casters);
+        lights.clusters.cull(encoder);
+        self.forward_pass(encoder, left_view, models, lights, &self.uniforms, None, true, None);
+        let right_eye = &self.right_eye;
+        self.forward_pass(encoder, right_view, models, lights, right_eye, None, true, None);
     }
 }
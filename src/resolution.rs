@@ -0,0 +1,273 @@
+use crate::{
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    surface,
+};
+
+/// Holds the frame rate near a target by lowering the resolution the scene is rendered at, and
+/// below the lowest scale the effect quality, raising both again once frames are fast.
+///
+/// Frames are presented at most at the target rate, so frame times only tell when rendering
+/// can't keep up. The scaler steps up after frames kept up for a while and steps down again if
+/// that turns out to be too much.
+#[derive(Debug, Clone)]
+pub struct DynamicResolution {
+    /// Fraction of the window size the scene is rendered at, above 1 to supersample. The
+    /// dynamic scale lowers it further.
+    pub render_scale: f32,
+    pub enabled: bool,
+    pub target_fps: f32,
+    /// Lowest fraction of the window size the scene is rendered at.
+    pub min_scale: f32,
+    scale: f32,
+    /// Shadows are left out while false.
+    full_effects: bool,
+    /// Smoothed seconds between frames.
+    frame_time: f32,
+    /// Frames since the scale last changed.
+    settled: u32,
+}
+
+impl Default for DynamicResolution {
+    fn default() -> Self {
+        Self {
+            render_scale: 1.,
+            enabled: false,
+            target_fps: 60.,
+            min_scale: 0.5,
+            scale: 1.,
+            full_effects: true,
+            frame_time: 0.,
+            settled: 0,
+        }
+    }
+}
+
+impl DynamicResolution {
+    /// Scales move in steps this large so the render target isn't recreated every frame.
+    const STEP: f32 = 0.05;
+    /// Frames to measure after a change before the next one.
+    const SETTLE_FRAMES: u32 = 30;
+    /// Frames that have to keep up before trying a higher scale.
+    const UPSCALE_FRAMES: u32 = 120;
+    /// How much slower than the target frames may be before scaling down.
+    const SLACK: f32 = 1.1;
+
+    /// The render scale with the dynamic scale applied.
+    pub fn scale(&self) -> f32 {
+        self.render_scale * self.scale
+    }
+
+    pub fn full_effects(&self) -> bool {
+        self.full_effects
+    }
+
+    /// Smoothed frames per second.
+    pub fn fps(&self) -> f32 {
+        if self.frame_time > 0. {
+            1. / self.frame_time
+        } else {
+            0.
+        }
+    }
+
+    /// Records the time since the previous frame and adjusts the scale. Returns whether the
+    /// scale changed.
+    pub fn record_frame(&mut self, frame_time: f32) -> bool {
+        self.frame_time = if self.frame_time > 0. {
+            self.frame_time * 0.9 + frame_time * 0.1
+        } else {
+            frame_time
+        };
+        self.settled += 1;
+        if !self.enabled {
+            return self.reset();
+        }
+        if self.settled < Self::SETTLE_FRAMES {
+            return false;
+        }
+        let budget = 1. / self.target_fps;
+        let old_scale = self.scale;
+        if self.frame_time > budget * Self::SLACK {
+            if self.scale > self.min_scale {
+                // Pixel count goes with the square of the scale.
+                let wanted = self.scale * (budget / self.frame_time).sqrt();
+                self.scale =
+                    Self::quantize(wanted.min(self.scale - Self::STEP)).max(self.min_scale);
+            } else {
+                self.full_effects = false;
+            }
+            self.settled = 0;
+        } else if self.settled >= Self::UPSCALE_FRAMES {
+            if !self.full_effects {
+                self.full_effects = true;
+            } else {
+                self.scale = (self.scale + Self::STEP).min(1.);
+            }
+            self.settled = 0;
+        }
+        self.scale != old_scale
+    }
+
+    /// Goes back to the render scale and full effects. Returns whether the scale changed.
+    fn reset(&mut self) -> bool {
+        let changed = self.scale != 1.;
+        self.scale = 1.;
+        self.full_effects = true;
+        changed
+    }
+
+    fn quantize(scale: f32) -> f32 {
+        (scale / Self::STEP).floor() * Self::STEP
+    }
+
+    /// The render size for a window of the given size, at most `max_size` on either side.
+    pub fn scaled_size(&self, width: u32, height: u32, max_size: u32) -> (u32, u32) {
+        let scale = |size: u32| ((size as f32 * self.scale()).round() as u32).clamp(1, max_size);
+        (scale(width), scale(height))
+    }
+}
+
+/// Offscreen color target the scene is rendered into at a different size than the window,
+/// stretched over the window's frame afterwards. Also holds whole frames for surfaces that don't
+/// encode sRGB, encoding them in the copy.
+#[derive(Debug)]
+pub struct ScaledTarget {
+    pub size: (u32, u32),
+    /// Can be copied from, e.g. to capture the frame, see `State::capture_frame`.
+    pub texture: Tracked<wgpu::Texture>,
+    pub view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ScaledTarget {
+    /// A target of `format` copied onto views of `target_format`.
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        target_format: wgpu::TextureFormat,
+        size: (u32, u32),
+    ) -> Self {
+        let texture = device.create_tracked_texture(GpuCategory::Targets, &wgpu::TextureDescriptor {
+            label: Some("Scaled Render Target"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Scaled Render Target Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("blit_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("blit_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!("blit.vert.spv"));
+        let fs_module = if surface::needs_encoding(target_format) {
+            device.create_shader_module(&wgpu::include_spirv!("blit_srgb.frag.spv"))
+        } else {
+            device.create_shader_module(&wgpu::include_spirv!("blit.frag.spv"))
+        };
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blit Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Blit Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &vs_module,
+                entry_point: "main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_module,
+                entry_point: "main",
+                targets: &[wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+        });
+
+        Self {
+            size,
+            texture,
+            view,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    /// Stretches the rendered image over the frame.
+    pub fn blit(&self, encoder: &mut wgpu::CommandEncoder, frame_view: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: frame_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
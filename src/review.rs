@@ -0,0 +1,205 @@
+//! Review notes pinned to points of the scene, each remembering the view it was written from
+//! and the models it is about. They are exported as a folder of screenshots through their
+//! views, with numbered markers at the pinned points, and a JSON and an HTML report, so a
+//! reviewer can send feedback the modeler can act on.
+
+use std::{fs, path::Path};
+
+use anyhow::*;
+use cgmath::{InnerSpace, Point3, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::{camera::Camera, headless::CameraDesc, picking, scene::Scene};
+
+/// A model a note refers to, by index and by the name of its node when the note was made.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectRef {
+    pub model: usize,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Note {
+    pub text: String,
+    /// Where the note is pinned, the 3D cursor when it was made.
+    pub position: [f32; 3],
+    /// The view the note was made from, its screenshot is rendered through it.
+    pub view: CameraDesc,
+    /// The models selected when the note was made.
+    pub models: Vec<ObjectRef>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Review {
+    pub notes: Vec<Note>,
+}
+
+impl Review {
+    pub const COLOR: [f32; 3] = [1.0, 0.85, 0.1];
+    /// Radius in pixels of the rings marking notes on the screenshots.
+    const MARKER_RADIUS: f32 = 12.;
+}
+
+/// A note as written to the report, with its screenshot.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportedNote {
+    pub number: usize,
+    #[serde(flatten)]
+    pub note: Note,
+    /// The screenshot's file name, next to the report.
+    pub screenshot: String,
+    /// Where the note's marker is on the screenshot in pixels, if it is in view.
+    pub marker: Option<[u32; 2]>,
+}
+
+/// Where the point shows on an image of the camera's view, in pixels from the top left.
+pub fn pixel(camera: &Camera, point: Point3<f32>, width: u32, height: u32) -> Option<[u32; 2]> {
+    let [x, y] = picking::to_screen(camera, point)?;
+    if x.abs() > 1. || y.abs() > 1. {
+        return None;
+    }
+    let x = (x + 1.) / 2. * width as f32;
+    let y = (1. - y) / 2. * height as f32;
+    Some([x as u32, y as u32])
+}
+
+/// Draws a ring with a dot in its middle around the pixel.
+pub fn draw_marker(image: &mut image::RgbaImage, [x, y]: [u32; 2]) {
+    let [r, g, b] = Review::COLOR.map(|c| (c * 255.) as u8);
+    let radius = Review::MARKER_RADIUS;
+    let reach = radius as i64 + 2;
+    for dy in -reach..=reach {
+        for dx in -reach..=reach {
+            let (px, py) = (x as i64 + dx, y as i64 + dy);
+            if px < 0 || py < 0 || px >= image.width() as i64 || py >= image.height() as i64 {
+                continue;
+            }
+            let distance = ((dx * dx + dy * dy) as f32).sqrt();
+            if (distance - radius).abs() <= 1.5 || distance <= 2.5 {
+                image.put_pixel(px as u32, py as u32, image::Rgba([r, g, b, 0xff]));
+            }
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A page listing the notes with their screenshots, a numbered badge over each marker.
+fn html_report(notes: &[ExportedNote], size: (u32, u32)) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Review</title>\n\
+         <style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         .shot { position: relative; display: inline-block; }\n\
+         .shot img { max-width: 100%; }\n\
+         .badge { position: absolute; transform: translate(-50%, -150%); background: #fd1; \
+         border-radius: 1em; padding: 0 0.5em; font-weight: bold; }\n\
+         </style>\n</head>\n<body>\n<h1>Review</h1>\n",
+    );
+    for exported in notes {
+        let note = &exported.note;
+        html += &format!(
+            "<section>\n<h2>Note {}</h2>\n<p>{}</p>\n",
+            exported.number,
+            escape_html(&note.text).replace('\n', "<br>")
+        );
+        if !note.models.is_empty() {
+            let models = note
+                .models
+                .iter()
+                .map(|object| format!("{} (model {})", escape_html(&object.name), object.model))
+                .collect::<Vec<_>>();
+            html += &format!("<p>Models: {}</p>\n", models.join(", "));
+        }
+        let [x, y, z] = note.position;
+        let [ex, ey, ez] = note.view.eye;
+        html += &format!(
+            "<p>At ({:.3}, {:.3}, {:.3}), seen from ({:.3}, {:.3}, {:.3})</p>\n",
+            x, y, z, ex, ey, ez
+        );
+        html += &format!("<div class=\"shot\"><img src=\"{}\">", exported.screenshot);
+        if let Some([px, py]) = exported.marker {
+            let left = px as f32 / size.0 as f32 * 100.;
+            let top = py as f32 / size.1 as f32 * 100.;
+            html += &format!(
+                "<span class=\"badge\" style=\"left: {:.2}%; top: {:.2}%\">{}</span>",
+                left, top, exported.number
+            );
+        }
+        html += "</div>\n</section>\n";
+    }
+    html += "</body>\n</html>\n";
+    html
+}
+
+/// Writes review.json and review.html into the folder the screenshots were saved to.
+pub fn write_report(dir: &Path, notes: &[ExportedNote], size: (u32, u32)) -> Result<()> {
+    let json = serde_json::to_string_pretty(notes)?;
+    fs::write(dir.join("review.json"), json)
+        .with_context(|| format!("Can't write the review into {:?}", dir))?;
+    fs::write(dir.join("review.html"), html_report(notes, size))
+        .with_context(|| format!("Can't write the review into {:?}", dir))?;
+    Ok(())
+}
+
+impl Scene {
+    /// Pins a note at the 3D cursor, seen from the viewport and about the selected models.
+    pub fn add_note(&mut self, text: impl Into<String>) -> usize {
+        let models = self
+            .outliner
+            .selected
+            .iter()
+            .map(|&model| ObjectRef {
+                model,
+                name: self
+                    .graph
+                    .model_root(model)
+                    .map(|root| self.graph.nodes[root].name.clone())
+                    .unwrap_or_else(|| format!("Model {}", model)),
+            })
+            .collect();
+        self.review.notes.push(Note {
+            text: text.into(),
+            position: self.cursor.position.into(),
+            view: CameraDesc::from_camera(&self.camera),
+            models,
+        });
+        self.review.notes.len() - 1
+    }
+
+    /// Points the renderer through the view of the note for a frame, returning where the
+    /// note's marker goes on an image of the given size and the view to restore after it.
+    pub fn view_note(
+        &mut self,
+        queue: &wgpu::Queue,
+        index: usize,
+        width: u32,
+        height: u32,
+    ) -> Option<(Option<[u32; 2]>, CameraDesc)> {
+        let note = self.review.notes.get(index)?.clone();
+        let previous = self.view_from(queue, &note.view);
+        let marker = pixel(&self.camera, note.position.into(), width, height);
+        Some((marker, previous))
+    }
+
+    /// Draws a pin at every note, standing on the point it is pinned to.
+    pub(crate) fn draw_notes(&mut self) {
+        let lines = &mut self.renderer.debug_lines;
+        for note in self.review.notes.iter() {
+            let position = Point3::from(note.position);
+            let size = (self.camera.eye - position).magnitude() * 0.03;
+            let head = position + Vector3::unit_y() * size;
+            lines.line(position, head, Review::COLOR);
+            let (x, z) = (Vector3::unit_x() * size * 0.3, Vector3::unit_z() * size * 0.3);
+            let corners = [head + x, head + z, head - x, head - z];
+            for i in 0..4 {
+                lines.line(corners[i], corners[(i + 1) % 4], Review::COLOR);
+            }
+        }
+    }
+}
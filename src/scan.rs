@@ -0,0 +1,540 @@
+//! One-click cleanup of photogrammetry scans: the meshes are welded, decimated to a triangle
+//! budget, remeshed, given smooth normals and ambient occlusion baked into their vertex colors,
+//! one step after the other on a worker thread. Scans rarely have useful texture coordinates
+//! once remeshed, so the occlusion goes to the vertices rather than a map.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+};
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3, Zero};
+
+use crate::{
+    bake::{hammersley, orthonormal_basis},
+    bounds::Aabb,
+    bvh::Bvh,
+    model::{compute_normals, Model, ModelVertex},
+    picking::Ray,
+    remesh::{voxel_remesh, Progress, RemeshSettings},
+    scene::Scene,
+    vertex_layout::{VertexAttribute, VertexData, VertexLayout},
+    weld::Welded,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CleanupStep {
+    Weld,
+    Decimate,
+    Remesh,
+    Normals,
+    Occlusion,
+}
+
+impl CleanupStep {
+    pub const ALL: [CleanupStep; 5] = [
+        CleanupStep::Weld,
+        CleanupStep::Decimate,
+        CleanupStep::Remesh,
+        CleanupStep::Normals,
+        CleanupStep::Occlusion,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CleanupStep::Weld => "Weld",
+            CleanupStep::Decimate => "Decimate",
+            CleanupStep::Remesh => "Remesh",
+            CleanupStep::Normals => "Recompute normals",
+            CleanupStep::Occlusion => "Bake AO",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CleanupSettings {
+    pub weld: bool,
+    pub decimate: bool,
+    /// Triangles each mesh is decimated to at most.
+    pub target_triangles: usize,
+    pub remesh: bool,
+    pub remesh_settings: RemeshSettings,
+    pub normals: bool,
+    pub occlusion: bool,
+    /// Rays per vertex for the ambient occlusion.
+    pub ao_samples: u32,
+    pub ao_distance: f32,
+}
+
+impl Default for CleanupSettings {
+    fn default() -> Self {
+        Self {
+            weld: true,
+            decimate: true,
+            target_triangles: 50_000,
+            remesh: true,
+            remesh_settings: RemeshSettings::default(),
+            normals: true,
+            occlusion: true,
+            ao_samples: 16,
+            ao_distance: 1.0,
+        }
+    }
+}
+
+impl CleanupSettings {
+    /// The steps to run, in order.
+    pub fn steps(&self) -> Vec<CleanupStep> {
+        CleanupStep::ALL
+            .iter()
+            .copied()
+            .filter(|step| match step {
+                CleanupStep::Weld => self.weld,
+                CleanupStep::Decimate => self.decimate,
+                CleanupStep::Remesh => self.remesh,
+                CleanupStep::Normals => self.normals,
+                CleanupStep::Occlusion => self.occlusion,
+            })
+            .collect()
+    }
+}
+
+/// Merges the vertices sharing a position, dropping the triangles it collapses.
+pub fn weld(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let welded = Welded::new(vertices, indices);
+    let merged = welded
+        .group_vertices
+        .iter()
+        .map(|group| vertices[group[0] as usize])
+        .collect();
+    let indices = welded
+        .triangles
+        .iter()
+        .filter(|[a, b, c]| a != b && b != c && c != a)
+        .flatten()
+        .copied()
+        .collect();
+    (merged, indices)
+}
+
+/// Merges the vertices in each cell of a grid into their average, dropping the triangles it
+/// collapses and the ones left twice.
+fn cluster(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    origin: Point3<f32>,
+    cell: f32,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut cells = HashMap::new();
+    // Position and normal sums, the vertex count and the first vertex of each cell.
+    let mut sums: Vec<(Vector3<f32>, Vector3<f32>, u32, usize)> = Vec::new();
+    let cell_of = vertices
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let p = Point3::from(v.position);
+            let key = [0, 1, 2].map(|axis| ((p[axis] - origin[axis]) / cell).floor() as i32);
+            let c = *cells.entry(key).or_insert_with(|| {
+                sums.push((Vector3::zero(), Vector3::zero(), 0, i));
+                sums.len() as u32 - 1
+            });
+            let sum = &mut sums[c as usize];
+            sum.0 += p.to_vec();
+            sum.1 += Vector3::from(v.normal);
+            sum.2 += 1;
+            c
+        })
+        .collect::<Vec<_>>();
+    let merged = sums
+        .iter()
+        .map(|&(position, normal, count, first)| {
+            let mut vertex = vertices[first];
+            vertex.position = (position / count as f32).into();
+            if normal.magnitude2() > 0. {
+                vertex.normal = normal.normalize().into();
+            }
+            vertex
+        })
+        .collect();
+    let mut seen = HashSet::new();
+    let indices = indices
+        .chunks_exact(3)
+        .map(|t| [0, 1, 2].map(|i| cell_of[t[i] as usize]))
+        .filter(|&[a, b, c]| a != b && b != c && c != a)
+        .filter(|triangle| {
+            let mut key = *triangle;
+            key.sort_unstable();
+            seen.insert(key)
+        })
+        .flatten()
+        .collect();
+    (merged, indices)
+}
+
+/// Clusters the vertices on a grid made coarser until at most `target` triangles are left.
+/// `report` is called with the progress and returns whether to go on, `None` when it stopped.
+pub fn decimate(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    target: usize,
+    report: &dyn Fn(f32) -> bool,
+) -> Option<(Vec<ModelVertex>, Vec<u32>)> {
+    let bounds = Aabb::from_points(vertices.iter().map(|v| v.position.into()));
+    if indices.len() / 3 <= target || bounds.is_empty() {
+        return Some((vertices.to_vec(), indices.to_vec()));
+    }
+    let size = bounds.size();
+    let longest = size.x.max(size.y).max(size.z).max(1e-6);
+    // A surface across a grid of n cells keeps about n² triangles.
+    let mut resolution = (target as f32).sqrt() * 2.;
+    let mut pass = 1;
+    loop {
+        let (merged, kept) = cluster(vertices, indices, bounds.min, longest / resolution);
+        if kept.len() / 3 <= target || resolution <= 1. {
+            return Some((merged, kept));
+        }
+        resolution *= 0.8;
+        // Most targets are met within a dozen passes.
+        if !report((pass as f32 / 12.).min(0.99)) {
+            return None;
+        }
+        pass += 1;
+    }
+}
+
+/// Area weighted normals, shared by the vertices at a position. The tangents are any frame
+/// around the normals, as cleaned up scans have no normal maps.
+pub fn recompute_normals(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let welded = Welded::new(vertices, indices);
+    // The first vertex at each position gathers the faces of all of them.
+    let first = welded
+        .group_vertices
+        .iter()
+        .map(|group| group[0])
+        .collect::<Vec<_>>();
+    let shared = welded
+        .triangles
+        .iter()
+        .flatten()
+        .map(|&group| first[group as usize])
+        .collect::<Vec<_>>();
+    compute_normals(vertices, &shared);
+    for group in welded.group_vertices.iter() {
+        let normal = vertices[group[0] as usize].normal;
+        for &vertex in group[1..].iter() {
+            vertices[vertex as usize].normal = normal;
+        }
+    }
+    for vertex in vertices.iter_mut() {
+        let (tangent, bitangent) = orthonormal_basis(vertex.normal.into());
+        vertex.tangent = tangent.into();
+        vertex.bitangent = bitangent.into();
+    }
+}
+
+/// How much of the hemisphere around each vertex's normal is open, from 0 to 1, sampled with
+/// rays reaching `distance`. `None` when `report` stopped it.
+pub fn vertex_occlusion(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    samples: u32,
+    distance: f32,
+    report: &dyn Fn(f32) -> bool,
+) -> Option<Vec<f32>> {
+    let positions = vertices
+        .iter()
+        .map(|v| Point3::from(v.position))
+        .collect::<Vec<_>>();
+    let bvh = Bvh::from_indexed(&positions, indices);
+    let size = bvh.bounds().size();
+    let offset = size.x.max(size.y).max(size.z) * 1e-4;
+    let samples = samples.max(1);
+    let mut open = Vec::with_capacity(vertices.len());
+    for (i, vertex) in vertices.iter().enumerate() {
+        let normal = Vector3::from(vertex.normal);
+        let (tangent, bitangent) = orthonormal_basis(normal);
+        let origin = positions[i] + normal * offset;
+        let occluded = (0..samples)
+            .filter(|&s| {
+                // Cosine weighted, as for the baked maps.
+                let (u1, u2) = hammersley(s, samples);
+                let r = u1.sqrt();
+                let phi = 2. * std::f32::consts::PI * u2;
+                let direction = tangent * (r * phi.cos())
+                    + bitangent * (r * phi.sin())
+                    + normal * (1. - u1).max(0.).sqrt();
+                bvh.occluded(&Ray { origin, direction }, distance)
+            })
+            .count();
+        open.push(1. - occluded as f32 / samples as f32);
+        if i % 1024 == 0 && !report(i as f32 / vertices.len() as f32) {
+            return None;
+        }
+    }
+    Some(open)
+}
+
+#[derive(Debug, Default)]
+pub struct Cleaned {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    /// Ambient occlusion of each vertex, if baked.
+    pub occlusion: Option<Vec<f32>>,
+}
+
+/// Runs the steps on one mesh, reporting the progress through them to `report`.
+fn clean_mesh(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    settings: &CleanupSettings,
+    steps: &[CleanupStep],
+    report: &dyn Fn(usize, f32) -> bool,
+) -> Option<Cleaned> {
+    let mut cleaned = Cleaned {
+        vertices: vertices.to_vec(),
+        indices: indices.to_vec(),
+        occlusion: None,
+    };
+    for (i, step) in steps.iter().enumerate() {
+        let report = |fraction| report(i, fraction);
+        if !report(0.) {
+            return None;
+        }
+        match step {
+            CleanupStep::Weld => {
+                let (vertices, indices) = weld(&cleaned.vertices, &cleaned.indices);
+                cleaned.vertices = vertices;
+                cleaned.indices = indices;
+            }
+            CleanupStep::Decimate => {
+                let target = settings.target_triangles;
+                let (vertices, indices) =
+                    decimate(&cleaned.vertices, &cleaned.indices, target, &report)?;
+                cleaned.vertices = vertices;
+                cleaned.indices = indices;
+            }
+            CleanupStep::Remesh => {
+                let remeshed = voxel_remesh(
+                    &cleaned.vertices,
+                    &cleaned.indices,
+                    &settings.remesh_settings,
+                    &report,
+                )?;
+                // Open scans can voxelize to nothing, keeping what they were is better.
+                if !remeshed.indices.is_empty() {
+                    cleaned.vertices = remeshed.vertices;
+                    cleaned.indices = remeshed.indices;
+                }
+            }
+            CleanupStep::Normals => recompute_normals(&mut cleaned.vertices, &cleaned.indices),
+            CleanupStep::Occlusion => {
+                cleaned.occlusion = Some(vertex_occlusion(
+                    &cleaned.vertices,
+                    &cleaned.indices,
+                    settings.ao_samples,
+                    settings.ao_distance,
+                    &report,
+                )?);
+            }
+        }
+    }
+    Some(cleaned)
+}
+
+/// Scan cleanup of a whole model running on a worker thread.
+#[derive(Debug)]
+pub struct CleanupJob {
+    pub model: usize,
+    pub steps: Vec<CleanupStep>,
+    /// Index into `steps` of the one running.
+    step: Arc<AtomicUsize>,
+    progress: Arc<Progress>,
+    receiver: mpsc::Receiver<Vec<Cleaned>>,
+}
+
+impl CleanupJob {
+    /// Starts cleaning up a copy of the model's current geometry.
+    pub fn spawn(index: usize, model: &Model, settings: CleanupSettings) -> Self {
+        let meshes = model
+            .meshes()
+            .iter()
+            .map(|m| (m.vertices.clone(), m.indices.clone()))
+            .collect::<Vec<_>>();
+        let steps = settings.steps();
+        let step = Arc::new(AtomicUsize::new(0));
+        let progress = Arc::new(Progress::default());
+        let (sender, receiver) = mpsc::channel();
+        let (worker_steps, worker_step, worker_progress) =
+            (steps.clone(), step.clone(), progress.clone());
+        let work = move || {
+            let count = meshes.len().max(1) as f32;
+            let per_mesh = worker_steps.len().max(1) as f32;
+            let mut cleaned = Vec::new();
+            for (i, (vertices, indices)) in meshes.iter().enumerate() {
+                let report = |step: usize, fraction: f32| {
+                    worker_step.store(step, Ordering::Relaxed);
+                    let done = (step as f32 + fraction) / per_mesh;
+                    worker_progress.report((i as f32 + done) / count)
+                };
+                match clean_mesh(vertices, indices, &settings, &worker_steps, &report) {
+                    Some(mesh) => cleaned.push(mesh),
+                    None => return,
+                }
+            }
+            // Fails if the job was dropped in the meantime, when nobody wants the result.
+            let _ = sender.send(cleaned);
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(work);
+        // There are no threads on the web, so it blocks there instead.
+        #[cfg(target_arch = "wasm32")]
+        work();
+
+        Self {
+            model: index,
+            steps,
+            step,
+            progress,
+            receiver,
+        }
+    }
+
+    /// The step running, of the mesh being cleaned up.
+    pub fn step(&self) -> Option<CleanupStep> {
+        self.steps.get(self.step.load(Ordering::Relaxed)).copied()
+    }
+
+    /// From 0 to 1, over all steps and meshes.
+    pub fn progress(&self) -> f32 {
+        self.progress.fraction()
+    }
+
+    /// Asks the worker to stop at the next opportunity.
+    pub fn cancel(&self) {
+        self.progress.cancel();
+    }
+}
+
+impl Scene {
+    /// Starts cleaning up every mesh of the model in the background, replacing any cleanup of
+    /// it still running.
+    pub fn start_cleanup(&mut self, model: usize, settings: CleanupSettings) {
+        self.cancel_cleanup(model);
+        let job = CleanupJob::spawn(model, &self.models[model], settings);
+        self.cleanup_jobs.push(job);
+    }
+
+    pub fn cancel_cleanup(&mut self, model: usize) {
+        for job in self.cleanup_jobs.iter().filter(|job| job.model == model) {
+            job.cancel();
+        }
+        self.cleanup_jobs.retain(|job| job.model != model);
+    }
+
+    /// Swaps in the meshes of finished jobs, the baked occlusion as their vertex colors.
+    pub fn poll_cleanup(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        profile_function!();
+        let mut finished = Vec::new();
+        self.cleanup_jobs
+            .retain(|job| match job.receiver.try_recv() {
+                Ok(meshes) => {
+                    finished.push((job.model, meshes));
+                    false
+                }
+                Err(mpsc::TryRecvError::Empty) => true,
+                Err(mpsc::TryRecvError::Disconnected) => false,
+            });
+
+        let layout = VertexLayout::builder().color().build().ok();
+        for (model, meshes) in finished {
+            self.edit.release_target();
+            // The permutations reading vertex colors, made before the meshes are borrowed.
+            let shaders = match &layout {
+                Some(layout) if meshes.iter().any(|mesh| mesh.occlusion.is_some()) => {
+                    let current = self.models[model].meshes();
+                    current
+                        .iter()
+                        .map(|mesh| {
+                            let features = mesh.material.features.with_layout(layout);
+                            self.model_shader(device, config, features, Some(layout))
+                        })
+                        .collect()
+                }
+                _ => Vec::new(),
+            };
+            let meshes = self.models[model].meshes_mut().iter_mut().zip(meshes);
+            for (i, (mesh, cleaned)) in meshes.enumerate() {
+                if cleaned.indices.is_empty() {
+                    continue;
+                }
+                mesh.replace_geometry(device, cleaned.vertices, cleaned.indices);
+                if let (Some(occlusion), Some(layout), Some(shader)) =
+                    (cleaned.occlusion, &layout, shaders.get(i))
+                {
+                    let mut data = VertexData::new(layout.clone(), occlusion.len());
+                    for (v, open) in occlusion.into_iter().enumerate() {
+                        data.set(v, &VertexAttribute::Color, &[open, open, open, 1.]);
+                    }
+                    mesh.set_extra(device, data, shader.clone());
+                }
+            }
+            self.topology_changed(model);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitive::Primitive;
+
+    #[test]
+    fn weld_cube() {
+        let cube = Primitive::Cube.mesh(2.);
+        let (vertices, indices) = weld(&cube.vertices, &cube.indices);
+        assert_eq!(vertices.len(), 8);
+        assert_eq!(indices.len() / 3, 12);
+        assert!(indices.iter().all(|&i| (i as usize) < vertices.len()));
+    }
+
+    #[test]
+    fn weld_drops_collapsed_triangles() {
+        let mut cube = Primitive::Cube.mesh(2.);
+        // Moves a corner of the first face onto its neighbour, collapsing both its triangles.
+        let [a, b] = [cube.indices[0], cube.indices[1]].map(|i| i as usize);
+        cube.vertices[a].position = cube.vertices[b].position;
+        let (_, indices) = weld(&cube.vertices, &cube.indices);
+        assert!(indices.len() / 3 < 12);
+        assert!(indices.chunks_exact(3).all(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0]));
+    }
+
+    #[test]
+    fn decimate_sphere() {
+        let sphere = Primitive::Sphere.mesh(2.);
+        let triangles = sphere.indices.len() / 3;
+        assert!(triangles > 200);
+        let (vertices, indices) = decimate(&sphere.vertices, &sphere.indices, 100, &|_| true)
+            .expect("nothing stops it");
+        assert!(indices.len() / 3 <= 100);
+        assert!(indices.len() / 3 >= 20);
+        assert!(vertices.len() < sphere.vertices.len());
+        assert!(indices.iter().all(|&i| (i as usize) < vertices.len()));
+    }
+
+    #[test]
+    fn decimate_keeps_small_meshes() {
+        let cube = Primitive::Cube.mesh(2.);
+        let (vertices, indices) = decimate(&cube.vertices, &cube.indices, 12, &|_| true).unwrap();
+        assert_eq!(vertices.len(), cube.vertices.len());
+        assert_eq!(indices, cube.indices);
+    }
+
+    #[test]
+    fn decimate_stops_when_cancelled() {
+        let sphere = Primitive::Sphere.mesh(2.);
+        assert!(decimate(&sphere.vertices, &sphere.indices, 1, &|_| false).is_none());
+    }
+}
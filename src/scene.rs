@@ -1,61 +1,770 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     sync::{Arc, RwLock},
 };
 
+use cgmath::{InnerSpace, SquareMatrix};
+use serde::Serialize;
 use wgpu::CommandEncoder;
 use winit::dpi::PhysicalSize;
 
-use crate::{camera::{Camera, CameraController}, light::{Light, LightObject, LightRaw, Lights}, model::{Material, Model}, renderer::{Renderer, RendererExt}, shader::Shader, texture};
+use crate::{
+    bounds::Aabb,
+    camera::Camera,
+    cameras::SceneCameras,
+    cloth::Cloth,
+    compare::{Compare, ComparePass},
+    compression::Compression,
+    construction::ConstructionPlanes,
+    cursor::Cursor3D,
+    display::Display,
+    edit::EditMode,
+    events::{EventQueue, SceneEvent},
+    gizmos::Gizmos,
+    gpu_memory::{self, LeakCheck},
+    ground::GroundOcclusion,
+    impostor::Impostors,
+    lens::{Guides, Lens},
+    light::{Light, LightKind, Lights},
+    loading::LoadJob,
+    model::{transform_vertices, Material, Mesh, Model},
+    modifier::ModifierStack,
+    outliner::{GroupTransform, Outliner},
+    permutation::ShaderStrategy,
+    placeholder::Placeholders,
+    poses::PoseLibrary,
+    project::Project,
+    registry::{MeshData, NodeId, Registry},
+    remap::PathRemap,
+    remesh::RemeshJob,
+    render_config::RenderConfig,
+    renderer::{Renderer, RendererExt},
+    resolution::{DynamicResolution, ScaledTarget},
+    review::Review,
+    scan::CleanupJob,
+    scene_graph::SceneGraph,
+    sculpt::Sculpt,
+    section::SectionBox,
+    selection::ObjectSelection,
+    shader::Shader,
+    shadow::ObjectFlags,
+    shape_keys::ShapeKeys,
+    stereo::{AnaglyphTarget, Eye, Stereo, StereoMode},
+    streaming::TextureStream,
+    sun::SunStudy,
+    texture,
+    wireframe::Wireframe,
+    xray::XRay,
+};
 
-type Materials = Arc<RwLock<HashMap<String, Arc<Material>>>>;
+type Materials = Arc<RwLock<Registry<Material>>>;
 type Shaders = Arc<RwLock<HashMap<String, Arc<Shader>>>>;
 
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct SceneStats {
+    pub draw_calls: u32,
+    pub triangles: u64,
+    pub vertices: u64,
+    pub vram_bytes: u64,
+}
+
+impl SceneStats {
+    /// Counts drawing the mesh.
+    fn add_mesh(&mut self, mesh: &Mesh) {
+        self.draw_calls += 1;
+        self.triangles += mesh.num_elements as u64 / 3;
+        self.vertices += mesh.num_vertices as u64;
+    }
+}
+
+/// What a frame may draw to stay real-time, e.g. the limits of a game's asset budget.
+#[derive(Debug, Clone)]
+pub struct Budget {
+    pub enabled: bool,
+    pub triangles: u64,
+    pub vertices: u64,
+    pub draw_calls: u32,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            triangles: 1_000_000,
+            vertices: 1_000_000,
+            draw_calls: 1000,
+        }
+    }
+}
+
+impl Budget {
+    /// The limits `stats` goes over, as `(what, count, limit)`.
+    pub fn exceeded(&self, stats: &SceneStats) -> Vec<(&'static str, u64, u64)> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let counts = [
+            ("triangles", stats.triangles, self.triangles),
+            ("vertices", stats.vertices, self.vertices),
+            ("draw calls", stats.draw_calls as u64, self.draw_calls as u64),
+        ];
+        counts.iter().copied().filter(|(_, count, limit)| count > limit).collect()
+    }
+}
+
 #[derive(Debug)]
 pub struct Scene {
     pub models: Vec<Model>,
     pub lights: Lights,
     pub camera: Camera,
+    pub cameras: SceneCameras,
+    pub gizmos: Gizmos,
+    pub graph: SceneGraph,
+    /// Animations of the loaded models and poses stored of them.
+    pub poses: PoseLibrary,
+    /// Buffers of replaced models, checked to be freed.
+    pub memory: LeakCheck,
     pub renderer: Renderer,
     pub materials: Materials,
+    /// Geometry `spawn` makes models of.
+    pub meshes: Registry<MeshData>,
+    pub textures: Registry<texture::Texture>,
+    /// Textures standing in for missing ones, see placeholder.rs.
+    pub placeholders: Placeholders,
     pub shaders: Shaders,
+    /// Whether models get shader permutations or share the uber shader.
+    pub shader_strategy: ShaderStrategy,
+    pub cloths: Vec<Cloth>,
+    pub sculpt: Sculpt,
+    pub edit: EditMode,
+    pub modifiers: Vec<ModifierStack>,
+    pub shape_keys: Vec<ShapeKeys>,
+    pub outliner: Outliner,
+    pub resolution: DynamicResolution,
+    pub stereo: Stereo,
+    pub lens: Lens,
+    pub guides: Guides,
+    pub sun: SunStudy,
+    pub section: SectionBox,
+    pub selection: ObjectSelection,
+    pub cursor: Cursor3D,
+    pub construction: ConstructionPlanes,
+    pub display: Display,
+    /// The A/B comparison of two looks side by side.
+    pub compare: Compare,
+    pub xray: XRay,
+    /// The edges of the triangles, drawn alone or over the surfaces.
+    pub wireframe: Wireframe,
+    /// Far models drawn as a picture of them.
+    pub impostors: Impostors,
+    /// The top-down capture of the scene darkening the ground under it.
+    pub ground: GroundOcclusion,
+    pub budget: Budget,
+    /// Stop redrawing while nothing moves, until the next input.
+    pub reduced_power: bool,
+    /// What happened since the subscribers were last told.
+    pub events: EventQueue,
+    /// Notes for review, pinned to points of the scene.
+    pub review: Review,
+    /// Where the scene is rendered while the resolution is scaled.
+    scaled_target: Option<ScaledTarget>,
+    /// Where the eyes are rendered in anaglyph stereo.
+    anaglyph_target: Option<AnaglyphTarget>,
+    pub remesh_jobs: Vec<RemeshJob>,
+    pub cleanup_jobs: Vec<CleanupJob>,
+    pub load_jobs: Vec<LoadJob>,
+    /// A project waiting for its models to load, see `load_project`.
+    pub pending_project: Option<Project>,
+    /// A project waiting in the remap dialog for its missing files to be found.
+    pub remap: Option<PathRemap>,
+    pub texture_stream: TextureStream,
+    /// How saved projects and the mesh cache are compressed.
+    pub compression: Compression,
+    /// Where the models loaded with `start_load` came from, by index.
+    pub sources: BTreeMap<usize, String>,
+    /// Origins set with the origin tools, by model. The others are at the center of their
+    /// bounds.
+    pub origins: BTreeMap<usize, cgmath::Point3<f32>>,
+    #[cfg(feature = "physics")]
+    pub physics: crate::physics::Physics,
 }
 
 impl Scene {
-    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
-        let light = Light::new(
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        Self::with_render_config(device, queue, config, RenderConfig::default())
+    }
+
+    pub fn with_render_config(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        render_config: RenderConfig,
+    ) -> Self {
+        let mut light = Light::new(
             cgmath::Point3::new(200.0, 200.0, 2.0),
             cgmath::Vector3::new(1., 1., 1.),
             cgmath::Deg(45.),
             1.0..20.0,
         );
-        let lights = Lights::new(device, vec!(LightObject::new(&device, light)));
+        light.kind = LightKind::Directional;
+        let mut lights = Lights::new(device, light);
+        lights.lights[0].shadow.size = render_config.shadow_map_size;
 
         let size = PhysicalSize::<u32>::new(config.width, config.height);
         let camera = Camera::new(size);
         Self {
             models: Vec::new(),
-            renderer: Renderer::new(device, config, &camera, &lights.lights[0]),
+            renderer: Renderer::new(device, config, &camera, &lights.lights[0], render_config),
             lights,
             camera,
-            materials: Arc::new(RwLock::new(HashMap::new())),
+            cameras: SceneCameras::default(),
+            gizmos: Gizmos::default(),
+            graph: SceneGraph::default(),
+            poses: PoseLibrary::default(),
+            memory: LeakCheck::default(),
+            materials: Arc::new(RwLock::new(Registry::default())),
+            meshes: Registry::default(),
+            textures: Registry::default(),
+            placeholders: Placeholders::new(device, queue),
             shaders: Arc::new(RwLock::new(HashMap::new())),
+            shader_strategy: ShaderStrategy::default(),
+            cloths: Vec::new(),
+            sculpt: Sculpt::default(),
+            edit: EditMode::default(),
+            modifiers: Vec::new(),
+            shape_keys: Vec::new(),
+            outliner: Outliner::default(),
+            resolution: DynamicResolution::default(),
+            stereo: Stereo::default(),
+            lens: Lens::default(),
+            guides: Guides::default(),
+            sun: SunStudy::default(),
+            section: SectionBox::default(),
+            selection: ObjectSelection::default(),
+            cursor: Cursor3D::default(),
+            construction: ConstructionPlanes::default(),
+            display: Display::default(),
+            compare: Compare::default(),
+            xray: XRay::default(),
+            wireframe: Wireframe::default(),
+            impostors: Impostors::default(),
+            ground: GroundOcclusion::default(),
+            budget: Budget::default(),
+            reduced_power: true,
+            events: EventQueue::default(),
+            review: Review::default(),
+            scaled_target: None,
+            anaglyph_target: None,
+            remesh_jobs: Vec::new(),
+            cleanup_jobs: Vec::new(),
+            load_jobs: Vec::new(),
+            pending_project: None,
+            remap: None,
+            texture_stream: TextureStream::default(),
+            compression: Compression::default(),
+            sources: BTreeMap::new(),
+            origins: BTreeMap::new(),
+            #[cfg(feature = "physics")]
+            physics: crate::physics::Physics::new(),
         }
     }
     pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, frame_view: &wgpu::TextureView) {
-        self.renderer
-            .draw(encoder, frame_view, &self.models, &self.lights);
+        profile_function!();
+        let models = self
+            .models
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.outliner.is_visible(*i))
+            .filter(|(i, _)| !self.renderer.impostors.shows(*i))
+            .collect::<Vec<_>>();
+        let casters = if self.resolution.full_effects() {
+            self.shadow_casters()
+        } else {
+            Vec::new()
+        };
+        let view = match &self.scaled_target {
+            Some(target) => &target.view,
+            None => frame_view,
+        };
+        if let Some(anaglyph) = &self.anaglyph_target {
+            let (left, right) = (&anaglyph.left, &anaglyph.right);
+            self.renderer
+                .draw_eyes(encoder, left, right, &models, &casters, &self.lights);
+            anaglyph.composite(encoder, view);
+        } else if self.stereo.enabled {
+            self.renderer
+                .draw_stereo(encoder, view, &models, &casters, &self.lights);
+        } else if self.compare.enabled {
+            let size = self.renderer.depth_texture.size;
+            let x = self.compare.divider(size.width);
+            let swap = self.compare_swap();
+            let left = ComparePass {
+                scissor: [0, 0, x, size.height],
+                compared: false,
+                swap: None,
+            };
+            let right = ComparePass {
+                scissor: [x, 0, size.width - x, size.height],
+                compared: true,
+                swap: swap.as_ref().map(|(from, to)| (from, to)),
+            };
+            self.renderer
+                .draw_compare(encoder, view, &models, &casters, &self.lights, &left, &right);
+        } else {
+            self.renderer
+                .draw(encoder, view, &models, &casters, &self.lights);
+        }
+        if let Some(target) = &self.scaled_target {
+            target.blit(encoder, frame_view);
+        }
+    }
+
+    /// The models on layers casting shadows with their indices, for the shadow pass.
+    pub fn shadow_casters(&self) -> Vec<(usize, &Model)> {
+        self.models
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.outliner.casts_shadows(*i))
+            .collect()
+    }
+
+    /// Bounds of the models shown in the outliner.
+    pub fn visible_bounds(&self) -> Aabb {
+        self.models
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.outliner.is_visible(*i))
+            .fold(Aabb::EMPTY, |bounds, (_, model)| bounds.union(&model.bounds()))
+    }
+
+    /// Fits the shadow map around the visible models and uploads which of them receive
+    /// shadows, their display colors, model matrices and joint matrices. Call after `update`,
+    /// once the models are in place for the frame.
+    pub fn update_shadows(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        profile_function!();
+        let casters = self.shadow_casters();
+        let bounds = if casters.is_empty() || !self.resolution.full_effects() {
+            Aabb::EMPTY
+        } else {
+            self.visible_bounds()
+        };
+        let matrices = self.mesh_matrices();
+        let joints = self.joint_palette();
+        let objects = |display: &Display| {
+            self.models
+                .iter()
+                .enumerate()
+                .map(|(i, model)| {
+                    let receives_shadows = self.outliner.receives_shadows(i);
+                    let lit = self.outliner.is_lit(i);
+                    let meshes = model.meshes().iter().enumerate();
+                    meshes
+                        .map(|(j, mesh)| ObjectFlags {
+                            receives_shadows,
+                            lit,
+                            display_color: display.color(i, j, &mesh.material.name),
+                            model_matrix: matrices[i][j],
+                            first_joint: joints.first.get(&(i, j)).copied(),
+                        })
+                        .collect()
+                })
+                .collect::<Vec<_>>()
+        };
+        let compared = if self.compare.enabled {
+            Some(objects(&self.compare.display(&self.display)))
+        } else {
+            None
+        };
+        let objects = objects(&self.display);
+        // The first light casts the shadows, a directional one along its direction from
+        // wherever it is.
+        let light = &self.lights.lights[0];
+        let from = match light.light.kind {
+            LightKind::Directional => bounds.center() - light.light.direction(),
+            LightKind::Point | LightKind::Spot => light.light.position,
+        };
+        let size = light.shadow.size.min(device.limits().max_texture_dimension_2d);
+        self.renderer.shadows.resize(device, size);
+        let models = &self.models;
+        let skinned = self.poses.skins.iter().filter_map(|skin| {
+            let mesh = models.get(skin.model)?.meshes().get(skin.mesh)?;
+            mesh.extra.as_ref().map(|extra| &extra.data.layout)
+        });
+        self.renderer.shadows.add_skinned_layouts(device, skinned);
+        self.renderer.shadows.update(
+            device,
+            queue,
+            from,
+            &light.shadow,
+            &bounds,
+            &objects,
+            compared.as_deref(),
+            &joints.matrices,
+        );
     }
 
     pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
         use crate::camera::PerspectiveFovExt;
         self.camera.projection.resize(config.width, config.height);
         self.renderer.depth_texture =
-            texture::Texture::create_depth_texture(device, config, "depth_texture");
+            texture::Texture::create_depth_texture(
+                device,
+                config,
+                self.renderer.config.depth_format.format(),
+                "depth_texture",
+            );
+        self.scaled_target = None;
+    }
+
+    /// Sizes the render target for the current resolution scale. At full scale the scene is
+    /// rendered straight into the frame.
+    pub fn prepare_render_target(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) {
+        let max_size = device.limits().max_texture_dimension_2d;
+        let size = self
+            .resolution
+            .scaled_size(config.width, config.height, max_size);
+        let current = self.scaled_target.as_ref().map(|target| target.size);
+        if size == (config.width, config.height) {
+            if current.is_some() {
+                self.resize(device, config);
+            }
+        } else if current != Some(size) {
+            self.scaled_target = Some(ScaledTarget::new(
+                device,
+                config.format,
+                config.format,
+                size,
+            ));
+            let scaled = wgpu::SurfaceConfiguration {
+                width: size.0,
+                height: size.1,
+                ..config.clone()
+            };
+            self.renderer.depth_texture =
+                texture::Texture::create_depth_texture(
+                    device,
+                    &scaled,
+                    self.renderer.config.depth_format.format(),
+                    "depth_texture",
+                );
+        }
+
+        let anaglyph = self.stereo.enabled && self.stereo.mode == StereoMode::Anaglyph;
+        let current = self.anaglyph_target.as_ref().map(|target| target.size);
+        if !anaglyph {
+            self.anaglyph_target = None;
+        } else if current != Some(size) {
+            self.anaglyph_target = Some(AnaglyphTarget::new(device, config.format, size));
+        }
+    }
+
+    /// Whether the scene changes without input, e.g. a simulation is running or the light moves.
+    pub fn is_animating(&self) -> bool {
+        #[cfg(feature = "physics")]
+        if self.physics.is_active() {
+            return true;
+        }
+        self.lights.lights.iter().any(|light| light.orbit)
+            || self.sun.is_playing()
+            || !self.cloths.is_empty()
+            || !self.remesh_jobs.is_empty()
+            || !self.cleanup_jobs.is_empty()
+            || self.is_loading()
+            || self.is_streaming()
+            || self.lights.environment.is_loading()
+    }
+
+    /// Counts what a draw submits. VRAM is everything in the GPU memory registry.
+    pub fn stats(&self) -> SceneStats {
+        let mut stats = SceneStats::default();
+        for mesh in self.models.iter().flat_map(|model| model.meshes()) {
+            stats.add_mesh(mesh);
+        }
+        stats.vram_bytes = gpu_memory::total_bytes();
+        stats
+    }
+
+    /// Meshes passing the outliner's filter, as `(model, mesh)`.
+    pub fn matching_meshes(&self) -> Vec<(usize, usize)> {
+        let filter = &self.outliner.filter;
+        let mut matching = Vec::new();
+        for (i, model) in self.models.iter().enumerate() {
+            for (j, mesh) in model.meshes().iter().enumerate() {
+                let triangles = mesh.num_elements as u64 / 3;
+                if filter.matches(&mesh.name, &mesh.material.name, triangles) {
+                    matching.push((i, j));
+                }
+            }
+        }
+        matching
+    }
+
+    /// Selects the models with meshes passing the outliner's filter, adding them to the
+    /// selection with `extend`.
+    pub fn select_matching(&mut self, extend: bool) {
+        let matching = self.matching_meshes();
+        let selected = &mut self.outliner.selected;
+        if !extend {
+            selected.clear();
+        }
+        selected.extend(matching.into_iter().map(|(model, _)| model));
+    }
+
+    /// Counts for one model, without textures as materials are shared between models.
+    pub fn model_stats(&self, model: usize) -> SceneStats {
+        let mut stats = SceneStats::default();
+        for mesh in self.models.get(model).into_iter().flat_map(|model| model.meshes()) {
+            stats.add_mesh(mesh);
+        }
+        stats
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue) {
-        self.lights.lights[0].update(queue);
-        self.renderer.update(queue, &self.camera);
+    /// Turns every mesh of the model into cloth hanging from its top edge.
+    pub fn attach_cloth(&mut self, model: usize) {
+        self.cloths.retain(|cloth| cloth.model != model);
+        if let Some(m) = self.models.get(model) {
+            for (i, mesh) in m.meshes().iter().enumerate() {
+                let mut cloth = Cloth::new(model, i, mesh);
+                cloth.pin_top(mesh.bounds.size().y * 0.01);
+                self.cloths.push(cloth);
+            }
+        }
+    }
+
+    /// Removes the cloth from the model, putting its meshes back into their rest shape.
+    /// Takes effect on the next update.
+    pub fn detach_cloth(&mut self, model: usize) {
+        for cloth in self.cloths.iter_mut().filter(|cloth| cloth.model == model) {
+            cloth.detach();
+        }
+    }
+
+    /// The modifiers of the model, starting an empty stack from its current shape if needed.
+    pub fn modifier_stack_mut(&mut self, model: usize) -> &mut ModifierStack {
+        match self.modifiers.iter().position(|stack| stack.model == model) {
+            Some(i) => &mut self.modifiers[i],
+            None => {
+                self.modifiers
+                    .push(ModifierStack::new(model, &self.models[model]));
+                self.modifiers.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// The shape keys of the model, starting them from its current shape as the basis if needed.
+    pub fn shape_keys_mut(&mut self, model: usize) -> &mut ShapeKeys {
+        match self.shape_keys.iter().position(|keys| keys.model == model) {
+            Some(i) => &mut self.shape_keys[i],
+            None => {
+                self.shape_keys
+                    .push(ShapeKeys::new(model, &self.models[model]));
+                self.shape_keys.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Blends the models whose shape key weights changed. Edit and sculpt state of a blended
+    /// model no longer matches it and is let go.
+    pub fn apply_shape_keys(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        profile_function!();
+        let mut blended = Vec::new();
+        for keys in self.shape_keys.iter_mut() {
+            if let Some(model) = self.models.get_mut(keys.model) {
+                if keys.apply(model, device, queue) {
+                    blended.push(keys.model);
+                }
+            }
+        }
+        for model in blended {
+            if self.edit.target_model() == Some(model) {
+                self.edit.release_target();
+            }
+            self.shape_changed(model);
+        }
+    }
+
+    /// Lets go of everything holding on to the old vertices after the model's meshes were
+    /// replaced by ones with a different topology. Cloth and modifiers stay baked into the
+    /// new meshes, shape keys no longer match them.
+    pub fn topology_changed(&mut self, model: usize) {
+        self.shape_keys.retain(|keys| keys.model != model);
+        self.shape_changed(model);
+    }
+
+    /// Lets go of everything holding on to the old shape after vertices of the model's meshes
+    /// were moved in place. Cloth and modifiers stay baked into the new shape.
+    pub fn shape_changed(&mut self, model: usize) {
+        self.sculpt.release_target();
+        self.cloths.retain(|cloth| cloth.model != model);
+        self.modifiers.retain(|stack| stack.model != model);
+        #[cfg(feature = "physics")]
+        if let Some(kind) = self.physics.body_kind(model) {
+            self.physics.set_body(model, &self.models[model], Some(kind));
+        }
+    }
+
+    /// Groups the selected models around their common center. Returns the new group's index.
+    pub fn group_selected(&mut self, name: String) -> Option<usize> {
+        let models = self.outliner.selected.iter().copied().collect::<Vec<_>>();
+        if models.is_empty() {
+            return None;
+        }
+        let pivot = models
+            .iter()
+            .fold(Aabb::EMPTY, |bounds, &m| bounds.union(&self.models[m].bounds()))
+            .center();
+        Some(self.outliner.add_group(name, models, pivot))
+    }
+
+    /// Replaces the groups, e.g. with those of a loaded project. Models are put back where they
+    /// were before the old groups moved them, then moved by the new groups on the next update.
+    pub fn set_outliner(&mut self, mut outliner: Outliner) {
+        for group in self.outliner.groups.iter_mut() {
+            group.transform = GroupTransform::default();
+        }
+        self.apply_group_transforms();
+        outliner.retain_models(self.models.len());
+        self.outliner = outliner;
+    }
+
+    /// Bakes the groups' transforms that changed since the last call into their models'
+    /// vertices. Edit and sculpt state of a moved model no longer matches it and is let go.
+    fn apply_group_transforms(&mut self) {
+        let mut moved = Vec::new();
+        let count = self.models.len();
+        for group in self.outliner.groups.iter_mut() {
+            if group.transform == group.applied {
+                continue;
+            }
+            let pivot = group.pivot.into();
+            let delta = match group.applied.matrix(pivot).invert() {
+                Some(inverse) => group.transform.matrix(pivot) * inverse,
+                None => continue,
+            };
+            group.applied = group.transform;
+            let models = group.models.iter().filter(|&&m| m < count);
+            moved.extend(models.map(|&m| (m, delta)));
+        }
+        if !moved.is_empty() {
+            let models = moved.iter().map(|&(model, _)| NodeId(model)).collect();
+            self.events.push(SceneEvent::TransformEdited(models));
+        }
+        for (model, delta) in moved {
+            self.bake_transform(model, delta);
+        }
+    }
+
+    /// Moves the vertices of the model by `matrix`. Edit and sculpt state of the model no
+    /// longer matches it and is let go.
+    pub(crate) fn bake_transform(&mut self, model: usize, matrix: cgmath::Matrix4<f32>) {
+        for mesh in self.models[model].meshes_mut().iter_mut() {
+            transform_vertices(&mut mesh.vertices, matrix);
+            mesh.bounds = Aabb::from_points(mesh.vertices.iter().map(|v| v.position.into()));
+            let count = mesh.vertices.len();
+            mesh.mark_vertices_dirty(0..count);
+        }
+        if let Some(origin) = self.origins.get_mut(&model) {
+            *origin = cgmath::Transform::transform_point(&matrix, *origin);
+        }
+        for keys in self.shape_keys.iter_mut().filter(|keys| keys.model == model) {
+            keys.transform(matrix);
+        }
+        if self.edit.target_model() == Some(model) {
+            self.edit.release_target();
+        }
+        self.shape_changed(model);
+    }
+
+    /// The events since the last call, for the subscribers.
+    pub fn take_events(&mut self) -> Vec<SceneEvent> {
+        self.events.take(&self.outliner.selected)
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32) {
+        profile_function!();
+        self.memory.check();
+        self.lights.lights[0].update(queue, dt);
+        self.sun.update(&mut self.lights.lights[0], queue, dt);
+        self.write_moved_lights(queue);
+        self.renderer.debug_lines.clear();
+        self.apply_group_transforms();
+        self.evaluate_constraints(dt);
+        self.apply_node_transforms();
+        for &m in self.outliner.selected.iter() {
+            if !self.outliner.is_visible(m) {
+                continue;
+            }
+            if let Some(model) = self.models.get(m) {
+                self.renderer
+                    .debug_lines
+                    .aabb(&model.bounds(), [1.0, 0.6, 0.1]);
+            }
+        }
+        for mesh in self.models.iter_mut().flat_map(|model| model.meshes_mut()) {
+            mesh.flush_vertices(queue);
+        }
+        for cloth in self.cloths.iter_mut() {
+            cloth.step(dt);
+            let mesh = self
+                .models
+                .get_mut(cloth.model)
+                .and_then(|model| model.meshes_mut().get_mut(cloth.mesh));
+            if let Some(mesh) = mesh {
+                cloth.sync(mesh, queue);
+                let size = mesh.bounds.size().magnitude() * 0.005;
+                cloth.draw_pins(&mut self.renderer.debug_lines, size);
+            }
+        }
+        self.cloths.retain(|cloth| !cloth.is_detached());
+        for stack in self.modifiers.iter_mut() {
+            if let Some(model) = self.models.get_mut(stack.model) {
+                stack.apply(model, queue);
+                stack.draw(&mut self.renderer.debug_lines);
+            }
+        }
+        self.edit.draw(&mut self.renderer.debug_lines);
+        self.section.draw(&mut self.renderer.debug_lines);
+        self.draw_selection();
+        self.draw_construction_planes();
+        self.draw_cursor();
+        self.draw_cameras();
+        self.draw_notes();
+        self.draw_light_gizmos();
+        self.renderer.set_section(self.section.clip_bounds().as_ref());
+        let xrayed = (0..self.models.len())
+            .filter(|&i| self.outliner.is_visible(i))
+            .filter(|i| self.xray.shows(self.outliner.selected.contains(i)))
+            .collect();
+        self.renderer.xray.update(queue, &self.xray, xrayed);
+        // An empty stack has just put its model back into the original shape.
+        self.modifiers.retain(|stack| !stack.modifiers.is_empty());
+        #[cfg(feature = "physics")]
+        {
+            self.physics.step(dt);
+            self.physics.sync(&mut self.models, queue);
+            if self.physics.show_colliders {
+                self.physics.draw_colliders(&mut self.renderer.debug_lines);
+            }
+        }
+        if self.lens.enabled {
+            self.camera.projection.fovy = self.lens.vertical_fov(self.camera.projection.aspect);
+        }
+        self.follow_viewport();
+        self.lights.update_clusters(queue, &self.camera);
+        if self.stereo.enabled {
+            let left = self.stereo.eye_camera(&self.camera, Eye::Left);
+            let right = self.stereo.eye_camera(&self.camera, Eye::Right);
+            self.renderer.update_stereo(queue, &left, &right);
+        } else {
+            self.renderer.update(queue, &self.camera);
+        }
     }
 }
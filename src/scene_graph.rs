@@ -0,0 +1,326 @@
+//! The hierarchy placing the models, mirroring the node tree of imported glTF files. Every
+//! model gets a root node when added, and moving a node moves its meshes and everything below
+//! it. Like group transforms, a node's place is baked into its meshes' vertices, so nodes only
+//! keep what they baked last to move the meshes by the difference. While a node keeps moving,
+//! the difference is drawn through the meshes' model matrices instead, and only baked once it
+//! stays put for an update.
+
+use cgmath::{Matrix4, One, Quaternion, SquareMatrix, Vector3};
+
+use crate::{
+    bounds::Aabb, constraints::Constraint, events::SceneEvent, model::transform_vertices,
+    registry::NodeId, scene::Scene,
+};
+
+/// Translation, rotation and scale relative to the parent, applied in the reverse order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for NodeTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::new(0., 0., 0.),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1., 1., 1.),
+        }
+    }
+}
+
+impl NodeTransform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// The model whose meshes the node places.
+    pub model: Option<usize>,
+    /// The meshes of the model the node places. Meshes of child nodes move with them instead.
+    pub meshes: Vec<usize>,
+    pub local: NodeTransform,
+    /// Driving the local transform at every update.
+    pub constraints: Vec<Constraint>,
+    /// The world matrix last baked into the meshes.
+    applied: Matrix4<f32>,
+    /// The world matrix at the last update.
+    shown: Matrix4<f32>,
+}
+
+impl Node {
+    pub fn new(name: String, model: Option<usize>, meshes: Vec<usize>) -> Self {
+        Self {
+            name,
+            parent: None,
+            children: Vec::new(),
+            model,
+            meshes,
+            local: NodeTransform::default(),
+            constraints: Vec::new(),
+            applied: Matrix4::identity(),
+            shown: Matrix4::identity(),
+        }
+    }
+}
+
+/// A node of a model being loaded, with its parent and meshes as indices into the model's.
+/// Parents come before their children.
+#[derive(Debug, Clone)]
+pub struct LoadedNode {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub local: NodeTransform,
+    pub meshes: Vec<usize>,
+}
+
+/// Nodes by index, which changes when nodes before them are removed.
+#[derive(Debug, Clone, Default)]
+pub struct SceneGraph {
+    pub nodes: Vec<Node>,
+}
+
+impl SceneGraph {
+    /// Nodes without a parent.
+    pub fn roots(&self) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|&i| self.nodes[i].parent.is_none())
+            .collect()
+    }
+
+    /// Adds the node under `parent`, returning its index.
+    pub fn add(&mut self, mut node: Node, parent: Option<usize>) -> usize {
+        let index = self.nodes.len();
+        node.parent = parent;
+        if let Some(parent) = parent {
+            self.nodes[parent].children.push(index);
+        }
+        self.nodes.push(node);
+        index
+    }
+
+    /// The matrix from the node's space to the scene's.
+    pub fn world(&self, node: usize) -> Matrix4<f32> {
+        let mut matrix = self.nodes[node].local.matrix();
+        let mut parent = self.nodes[node].parent;
+        while let Some(p) = parent {
+            matrix = self.nodes[p].local.matrix() * matrix;
+            parent = self.nodes[p].parent;
+        }
+        matrix
+    }
+
+    /// Whether `ancestor` is `node` or above it.
+    pub fn is_ancestor(&self, ancestor: usize, node: usize) -> bool {
+        let mut current = Some(node);
+        while let Some(c) = current {
+            if c == ancestor {
+                return true;
+            }
+            current = self.nodes[c].parent;
+        }
+        false
+    }
+
+    /// Moves the node under `parent` keeping its local transform, so it moves along with the
+    /// new parent. Nothing happens for a parent below the node.
+    pub fn set_parent(&mut self, node: usize, parent: Option<usize>) {
+        if parent.map_or(false, |p| self.is_ancestor(node, p)) {
+            return;
+        }
+        if let Some(old) = self.nodes[node].parent {
+            self.nodes[old].children.retain(|&c| c != node);
+        }
+        if let Some(parent) = parent {
+            self.nodes[parent].children.push(node);
+        }
+        self.nodes[node].parent = parent;
+    }
+
+    /// The top node placing the model, whose transform places the whole model.
+    pub fn model_root(&self, model: usize) -> Option<usize> {
+        (0..self.nodes.len()).find(|&i| {
+            let node = &self.nodes[i];
+            let parent_model = node.parent.and_then(|p| self.nodes[p].model);
+            node.model == Some(model) && parent_model != Some(model)
+        })
+    }
+
+    /// Takes out the nodes placing the model, their children going to their parent, and the
+    /// constraints on them.
+    pub fn remove_model(&mut self, model: usize) {
+        let removed = (0..self.nodes.len())
+            .filter(|&i| self.nodes[i].model == Some(model))
+            .collect::<Vec<_>>();
+        for &node in removed.iter() {
+            let parent = self.nodes[node].parent;
+            for child in std::mem::take(&mut self.nodes[node].children) {
+                self.set_parent(child, parent);
+            }
+            self.set_parent(node, None);
+        }
+        // The index every kept node ends up at.
+        let mut kept = Vec::with_capacity(self.nodes.len());
+        let mut next = 0;
+        for i in 0..self.nodes.len() {
+            kept.push(next);
+            if !removed.contains(&i) {
+                next += 1;
+            }
+        }
+        let mut i = 0;
+        self.nodes.retain(|_| {
+            i += 1;
+            !removed.contains(&(i - 1))
+        });
+        let index = |i: usize| Some(kept[i]).filter(|_| !removed.contains(&i));
+        for node in self.nodes.iter_mut() {
+            node.parent = node.parent.map(|p| kept[p]);
+            for child in node.children.iter_mut() {
+                *child = kept[*child];
+            }
+            let constraints = std::mem::take(&mut node.constraints);
+            node.constraints = constraints
+                .iter()
+                .filter_map(|constraint| constraint.renumber(index))
+                .collect();
+        }
+    }
+}
+
+impl Scene {
+    /// Where the model is placed relative to its parent node, if it is in the scene.
+    pub fn model_transform(&self, id: NodeId) -> Option<NodeTransform> {
+        let root = self.graph.model_root(id.0)?;
+        Some(self.graph.nodes[root].local)
+    }
+
+    /// Translates, rotates and scales the model. It is drawn there from the next update on,
+    /// and its vertices follow once it has stayed there for an update.
+    pub fn set_model_transform(&mut self, id: NodeId, transform: NodeTransform) {
+        if let Some(root) = self.graph.model_root(id.0) {
+            self.graph.nodes[root].local = transform;
+        }
+    }
+
+    /// Adds the root node of a newly added model, placing all its meshes.
+    pub(crate) fn add_model_node(&mut self, model: usize, name: String) -> usize {
+        let meshes = (0..self.models[model].meshes().len()).collect();
+        self.graph.add(Node::new(name, Some(model), meshes), None)
+    }
+
+    /// Puts the nodes of a loaded model under its root, which keeps the meshes no node
+    /// places. The meshes were loaded where their nodes put them.
+    pub(crate) fn add_loaded_nodes(&mut self, model: usize, name: String, loaded: Vec<LoadedNode>) {
+        self.graph.remove_model(model);
+        let root = self.add_model_node(model, name);
+        if loaded.is_empty() {
+            return;
+        }
+        let placed = loaded.iter().flat_map(|node| node.meshes.iter().copied());
+        let placed = placed.collect::<Vec<_>>();
+        self.graph.nodes[root]
+            .meshes
+            .retain(|mesh| !placed.contains(mesh));
+        let first = self.graph.nodes.len();
+        for node in loaded {
+            let parent = Some(node.parent.map_or(root, |p| first + p));
+            let mut added = Node::new(node.name, Some(model), node.meshes);
+            added.local = node.local;
+            let index = self.graph.add(added, parent);
+            let world = self.graph.world(index);
+            let node = &mut self.graph.nodes[index];
+            node.applied = world;
+            node.shown = world;
+        }
+    }
+
+    /// Bakes the change of the world matrix of every node that stopped moving into its meshes.
+    /// Nodes still moving are left to the model matrices.
+    pub(crate) fn apply_node_transforms(&mut self) {
+        let mut moved = Vec::new();
+        let count = self.models.len();
+        for i in 0..self.graph.nodes.len() {
+            let world = self.graph.world(i);
+            let node = &mut self.graph.nodes[i];
+            let shown = std::mem::replace(&mut node.shown, world);
+            if world == node.applied || world != shown {
+                continue;
+            }
+            let delta = match node.applied.invert() {
+                Some(inverse) => world * inverse,
+                None => continue,
+            };
+            node.applied = world;
+            if let Some(model) = node.model.filter(|&m| m < count) {
+                moved.push((model, node.meshes.clone(), delta));
+            }
+        }
+        if !moved.is_empty() {
+            let models = moved.iter().map(|(model, _, _)| NodeId(*model)).collect();
+            self.events.push(SceneEvent::TransformEdited(models));
+        }
+        for (model, meshes, delta) in moved {
+            self.bake_mesh_transforms(model, &meshes, delta);
+        }
+    }
+
+    /// The model matrix of every mesh by model, moving it from where its vertices were baked
+    /// to where its node was at the last update.
+    pub(crate) fn mesh_matrices(&self) -> Vec<Vec<Matrix4<f32>>> {
+        let mut matrices = self
+            .models
+            .iter()
+            .map(|model| vec![Matrix4::identity(); model.meshes().len()])
+            .collect::<Vec<_>>();
+        for node in self.graph.nodes.iter().filter(|node| node.shown != node.applied) {
+            let meshes = match node.model.and_then(|model| matrices.get_mut(model)) {
+                Some(meshes) => meshes,
+                None => continue,
+            };
+            let matrix = match node.applied.invert() {
+                Some(inverse) => node.shown * inverse,
+                None => continue,
+            };
+            for &mesh in node.meshes.iter() {
+                if let Some(slot) = meshes.get_mut(mesh) {
+                    *slot = matrix;
+                }
+            }
+        }
+        matrices
+    }
+
+    /// Moves the vertices of some meshes of the model by `matrix`, like `bake_transform` does
+    /// for all of them.
+    fn bake_mesh_transforms(&mut self, model: usize, meshes: &[usize], matrix: Matrix4<f32>) {
+        if meshes.len() == self.models[model].meshes().len() {
+            self.bake_transform(model, matrix);
+            return;
+        }
+        if meshes.is_empty() {
+            return;
+        }
+        let all = self.models[model].meshes_mut();
+        for &i in meshes {
+            if let Some(mesh) = all.get_mut(i) {
+                transform_vertices(&mut mesh.vertices, matrix);
+                mesh.bounds = Aabb::from_points(mesh.vertices.iter().map(|v| v.position.into()));
+                let count = mesh.vertices.len();
+                mesh.mark_vertices_dirty(0..count);
+            }
+        }
+        if self.edit.target_model() == Some(model) {
+            self.edit.release_target();
+        }
+        self.shape_changed(model);
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3, Zero};
+
+use crate::{
+    model::{Mesh, Model},
+    picking::{Hit, Ray},
+    scene::Scene,
+    smoothing,
+    weld::Welded,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Brush {
+    /// Raises the surface along the average normal under the brush.
+    Draw,
+    /// Relaxes vertices toward their neighbours.
+    Smooth,
+    /// Drags the vertices under the brush with the cursor.
+    Grab,
+    /// Pushes every vertex along its own normal.
+    Inflate,
+}
+
+impl Brush {
+    pub const ALL: [Brush; 4] = [Brush::Draw, Brush::Smooth, Brush::Grab, Brush::Inflate];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Brush::Draw => "Draw",
+            Brush::Smooth => "Smooth",
+            Brush::Grab => "Grab",
+            Brush::Inflate => "Inflate",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BrushSettings {
+    pub brush: Brush,
+    pub radius: f32,
+    /// From 0 to 1.
+    pub strength: f32,
+    /// Digs in instead of raising for draw and deflates for inflate.
+    pub invert: bool,
+    /// Mirrors strokes across the planes through the mesh center, per axis.
+    pub mirror: [bool; 3],
+    /// Keeps the volume when smoothing by following each step with an inflating one.
+    pub taubin: bool,
+}
+
+impl Default for BrushSettings {
+    fn default() -> Self {
+        Self {
+            brush: Brush::Draw,
+            radius: 0.5,
+            strength: 0.5,
+            invert: false,
+            mirror: [false; 3],
+            taubin: false,
+        }
+    }
+}
+
+/// Uniform grid bucketing points so the vertices under the brush are found without scanning the mesh.
+#[derive(Debug)]
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<[i32; 3], Vec<u32>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn key(&self, p: Vector3<f32>) -> [i32; 3] {
+        [
+            (p.x / self.cell_size).floor() as i32,
+            (p.y / self.cell_size).floor() as i32,
+            (p.z / self.cell_size).floor() as i32,
+        ]
+    }
+
+    pub fn insert(&mut self, index: u32, p: Vector3<f32>) {
+        let key = self.key(p);
+        self.cells.entry(key).or_default().push(index);
+    }
+
+    pub fn remove(&mut self, index: u32, p: Vector3<f32>) {
+        let key = self.key(p);
+        if let Some(cell) = self.cells.get_mut(&key) {
+            if let Some(i) = cell.iter().position(|&j| j == index) {
+                cell.swap_remove(i);
+            }
+        }
+    }
+
+    /// Moves a point to the cell of its new position.
+    pub fn relocate(&mut self, index: u32, from: Vector3<f32>, to: Vector3<f32>) {
+        if self.key(from) != self.key(to) {
+            self.remove(index, from);
+            self.insert(index, to);
+        }
+    }
+
+    /// Points within `radius` of `center` with their distance.
+    pub fn query(
+        &self,
+        center: Vector3<f32>,
+        radius: f32,
+        positions: &[Vector3<f32>],
+    ) -> Vec<(u32, f32)> {
+        let min = self.key(center - Vector3::new(radius, radius, radius));
+        let max = self.key(center + Vector3::new(radius, radius, radius));
+        let mut found = Vec::new();
+        for x in min[0]..=max[0] {
+            for y in min[1]..=max[1] {
+                for z in min[2]..=max[2] {
+                    for &i in self.cells.get(&[x, y, z]).into_iter().flatten() {
+                        let distance = (positions[i as usize] - center).magnitude();
+                        if distance <= radius {
+                            found.push((i, distance));
+                        }
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Editing state of the mesh being sculpted.
+#[derive(Debug)]
+struct SculptTarget {
+    model: usize,
+    mesh: usize,
+    welded: Welded,
+    grid: SpatialGrid,
+    center: Point3<f32>,
+}
+
+impl SculptTarget {
+    fn new(model: usize, mesh_index: usize, mesh: &Mesh) -> Self {
+        let welded = Welded::new(&mesh.vertices, &mesh.indices);
+        let cell_size = (mesh.bounds.size().magnitude() / 128.).max(1e-4);
+        let mut grid = SpatialGrid::new(cell_size);
+        for (i, p) in welded.positions.iter().enumerate() {
+            grid.insert(i as u32, *p);
+        }
+
+        Self {
+            model,
+            mesh: mesh_index,
+            welded,
+            grid,
+            center: mesh.bounds.center(),
+        }
+    }
+
+    fn move_group(&mut self, group: u32, to: Vector3<f32>) {
+        let from = self.welded.positions[group as usize];
+        self.grid.relocate(group, from, to);
+        self.welded.positions[group as usize] = to;
+    }
+
+    /// Recomputes the normals around the moved groups and uploads the vertices that changed.
+    fn commit(&mut self, mesh: &mut Mesh, queue: &wgpu::Queue, moved: &HashSet<u32>) {
+        if moved.is_empty() {
+            return;
+        }
+        let mut affected = moved.clone();
+        for &g in moved.iter() {
+            affected.extend(self.welded.neighbours[g as usize].iter().copied());
+        }
+        for &g in affected.iter() {
+            self.welded.recompute_normal(g);
+        }
+        let range = self.welded.write_groups(&mut mesh.vertices, affected);
+        mesh.upload_vertex_range(queue, range);
+    }
+}
+
+#[derive(Debug)]
+struct Grab {
+    plane_point: Point3<f32>,
+    plane_normal: Vector3<f32>,
+    last: Point3<f32>,
+    /// Grabbed groups and their falloff, for every mirrored copy of the brush.
+    weights: Vec<(Vector3<f32>, Vec<(u32, f32)>)>,
+}
+
+/// Brush based sculpting of the mesh under the cursor.
+#[derive(Debug, Default)]
+pub struct Sculpt {
+    pub enabled: bool,
+    pub settings: BrushSettings,
+    target: Option<SculptTarget>,
+    last_point: Option<Point3<f32>>,
+    grab: Option<Grab>,
+}
+
+/// Inflating step of the Taubin smooth brush relative to its shrinking one.
+const TAUBIN_INFLATE: f32 = -1.06;
+
+/// Smooth falloff from 1 at the brush center to 0 at its rim.
+fn falloff(distance: f32, radius: f32) -> f32 {
+    let x = (1. - distance / radius).max(0.).min(1.);
+    x * x * (3. - 2. * x)
+}
+
+fn mirror(v: Vector3<f32>, signs: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(v.x * signs.x, v.y * signs.y, v.z * signs.z)
+}
+
+impl Sculpt {
+    pub fn is_stroking(&self) -> bool {
+        self.last_point.is_some()
+    }
+
+    /// Drops the cached editing state, freeing its memory.
+    pub fn release_target(&mut self) {
+        self.end_stroke();
+        self.target = None;
+    }
+
+    /// Every combination of the enabled mirror axes, as per axis signs.
+    fn mirror_signs(&self) -> Vec<Vector3<f32>> {
+        let mut signs = vec![Vector3::new(1., 1., 1.)];
+        for axis in 0..3 {
+            if self.settings.mirror[axis] {
+                let flipped = signs
+                    .iter()
+                    .map(|s| {
+                        let mut s = *s;
+                        s[axis] = -1.;
+                        s
+                    })
+                    .collect::<Vec<_>>();
+                signs.extend(flipped);
+            }
+        }
+        signs
+    }
+
+    fn begin_stroke(&mut self, models: &mut [Model], queue: &wgpu::Queue, ray: &Ray, hit: &Hit) {
+        let is_target = self
+            .target
+            .as_ref()
+            .map(|t| t.model == hit.model && t.mesh == hit.mesh)
+            .unwrap_or(false);
+        if !is_target {
+            let mesh = &models[hit.model].meshes()[hit.mesh];
+            self.target = Some(SculptTarget::new(hit.model, hit.mesh, mesh));
+        }
+
+        if self.settings.brush == Brush::Grab {
+            let target = self.target.as_ref().unwrap();
+            let weights = self
+                .mirror_signs()
+                .into_iter()
+                .map(|signs| {
+                    let center = target.center.to_vec() + mirror(hit.point - target.center, signs);
+                    let groups = target
+                        .grid
+                        .query(center, self.settings.radius, &target.welded.positions)
+                        .into_iter()
+                        .map(|(g, d)| (g, falloff(d, self.settings.radius)))
+                        .collect();
+                    (signs, groups)
+                })
+                .collect();
+            self.grab = Some(Grab {
+                plane_point: hit.point,
+                plane_normal: -ray.direction,
+                last: hit.point,
+                weights,
+            });
+        } else {
+            self.dab(models, queue, hit.point);
+        }
+        self.last_point = Some(hit.point);
+    }
+
+    pub fn end_stroke(&mut self) {
+        self.last_point = None;
+        self.grab = None;
+    }
+
+    /// Moves the grabbed vertices with the cursor, on a plane facing the camera.
+    fn drag_grab(&mut self, models: &mut [Model], queue: &wgpu::Queue, ray: &Ray) {
+        let (grab, target) = match (&mut self.grab, &mut self.target) {
+            (Some(grab), Some(target)) => (grab, target),
+            _ => return,
+        };
+        let denominator = ray.direction.dot(grab.plane_normal);
+        if denominator.abs() < 1e-6 {
+            return;
+        }
+        let t = (grab.plane_point - ray.origin).dot(grab.plane_normal) / denominator;
+        let point = ray.at(t);
+        let delta = point - grab.last;
+        grab.last = point;
+
+        let mut moved = HashSet::new();
+        for (signs, groups) in grab.weights.iter() {
+            let delta = mirror(delta, *signs);
+            for &(g, w) in groups.iter() {
+                let to = target.welded.positions[g as usize] + delta * w;
+                target.move_group(g, to);
+                moved.insert(g);
+            }
+        }
+        let mesh = &mut models[target.model].meshes_mut()[target.mesh];
+        target.commit(mesh, queue, &moved);
+        self.last_point = Some(point);
+    }
+
+    /// Applies the brush once at `point` and at its mirrored copies.
+    fn dab(&mut self, models: &mut [Model], queue: &wgpu::Queue, point: Point3<f32>) {
+        let settings = self.settings;
+        let signs = self.mirror_signs();
+        let target = match &mut self.target {
+            Some(target) => target,
+            None => return,
+        };
+        let direction = if settings.invert { -1. } else { 1. };
+        let step = settings.radius * settings.strength * 0.1;
+
+        let mut moved = HashSet::new();
+        for signs in signs {
+            let center = target.center.to_vec() + mirror(point - target.center, signs);
+            let under_brush = target
+                .grid
+                .query(center, settings.radius, &target.welded.positions);
+            let updates = match settings.brush {
+                Brush::Draw => {
+                    let area_normal = under_brush
+                        .iter()
+                        .map(|&(g, d)| target.welded.normals[g as usize] * falloff(d, settings.radius))
+                        .fold(Vector3::zero(), |sum, n| sum + n);
+                    if area_normal.magnitude2() == 0. {
+                        continue;
+                    }
+                    let area_normal = area_normal.normalize();
+                    under_brush
+                        .iter()
+                        .map(|&(g, d)| {
+                            let offset =
+                                area_normal * step * direction * falloff(d, settings.radius);
+                            (g, target.welded.positions[g as usize] + offset)
+                        })
+                        .collect::<Vec<_>>()
+                }
+                Brush::Inflate => under_brush
+                    .iter()
+                    .map(|&(g, d)| {
+                        let offset = target.welded.normals[g as usize]
+                            * step
+                            * direction
+                            * falloff(d, settings.radius);
+                        (g, target.welded.positions[g as usize] + offset)
+                    })
+                    .collect(),
+                Brush::Smooth => {
+                    let weights = under_brush
+                        .iter()
+                        .map(|&(g, d)| (g, settings.strength * falloff(d, settings.radius)))
+                        .collect::<Vec<_>>();
+                    if !settings.taubin {
+                        smoothing::relax(&target.welded, &weights, 1.)
+                    } else {
+                        // Shrink then inflate so repeated dabs don't flatten the surface away.
+                        for (g, to) in smoothing::relax(&target.welded, &weights, 1.) {
+                            target.move_group(g, to);
+                            moved.insert(g);
+                        }
+                        smoothing::relax(&target.welded, &weights, TAUBIN_INFLATE)
+                    }
+                }
+                Brush::Grab => Vec::new(),
+            };
+            for (g, to) in updates {
+                target.move_group(g, to);
+                moved.insert(g);
+            }
+        }
+        let mesh = &mut models[target.model].meshes_mut()[target.mesh];
+        target.commit(mesh, queue, &moved);
+    }
+}
+
+impl Scene {
+    /// Starts a sculpt stroke on the mesh under the ray. Returns whether a mesh was hit.
+    pub fn begin_sculpt_stroke(&mut self, queue: &wgpu::Queue, ray: &Ray) -> bool {
+        match self.raycast(ray) {
+            Some(hit) => {
+                self.sculpt.begin_stroke(&mut self.models, queue, ray, &hit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Continues the current stroke to where the ray hits the sculpted mesh, spacing dabs
+    /// evenly along the way.
+    pub fn continue_sculpt_stroke(&mut self, queue: &wgpu::Queue, ray: &Ray) {
+        if self.sculpt.settings.brush == Brush::Grab {
+            self.sculpt.drag_grab(&mut self.models, queue, ray);
+            return;
+        }
+        let last = match self.sculpt.last_point {
+            Some(last) => last,
+            None => return,
+        };
+        let hit = match self.raycast(ray) {
+            Some(hit) => hit,
+            None => return,
+        };
+        let on_target = self
+            .sculpt
+            .target
+            .as_ref()
+            .map(|t| t.model == hit.model && t.mesh == hit.mesh)
+            .unwrap_or(false);
+        if !on_target {
+            return;
+        }
+        let spacing = (self.sculpt.settings.radius * 0.25).max(1e-4);
+        let distance = (hit.point - last).magnitude();
+        if distance < spacing {
+            return;
+        }
+        let steps = (distance / spacing).floor() as u32;
+        for i in 1..=steps {
+            let point = last + (hit.point - last) * (i as f32 * spacing / distance);
+            self.sculpt.dab(&mut self.models, queue, point);
+            self.sculpt.last_point = Some(point);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use cgmath::{InnerSpace, MetricSpace, Point3, Vector3};
+
+use crate::{bounds::Aabb, debug_lines::DebugLines, picking::Ray};
+
+/// One of the six faces of the section box, dragged along its axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Handle {
+    pub axis: usize,
+    /// The face on the maximum side of the axis.
+    pub max: bool,
+}
+
+impl Handle {
+    fn all() -> impl Iterator<Item = Handle> {
+        (0..6).map(|i| Handle {
+            axis: i / 2,
+            max: i % 2 == 1,
+        })
+    }
+}
+
+/// A box cutting away everything outside of it, to look into buildings and assemblies.
+///
+/// Each face is a clip plane that only cuts within the box's extent, so the box shrink-wraps
+/// the part of the scene under review. Faces are moved by dragging the handles in their
+/// middle. Shadows are still cast by the clipped away parts.
+#[derive(Debug, Clone)]
+pub struct SectionBox {
+    pub enabled: bool,
+    pub bounds: Aabb,
+    hovered: Option<Handle>,
+    dragging: Option<Handle>,
+}
+
+impl Default for SectionBox {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bounds: Aabb {
+                min: Point3::new(-1., -1., -1.),
+                max: Point3::new(1., 1., 1.),
+            },
+            hovered: None,
+            dragging: None,
+        }
+    }
+}
+
+impl SectionBox {
+    /// Faces keep at least this fraction of the box's size apart.
+    const MIN_THICKNESS: f32 = 1e-3;
+
+    pub fn is_dragging(&self) -> bool {
+        self.dragging.is_some()
+    }
+
+    /// Whether `point` survives the clipping.
+    pub fn contains(&self, point: Point3<f32>) -> bool {
+        !self.enabled
+            || (0..3).all(|axis| {
+                self.bounds.min[axis] <= point[axis] && point[axis] <= self.bounds.max[axis]
+            })
+    }
+
+    /// The box the shader clips to, if any.
+    pub fn clip_bounds(&self) -> Option<Aabb> {
+        Some(self.bounds).filter(|_| self.enabled)
+    }
+
+    fn handle_center(&self, handle: Handle) -> Point3<f32> {
+        let mut center = self.bounds.center();
+        center[handle.axis] = if handle.max {
+            self.bounds.max[handle.axis]
+        } else {
+            self.bounds.min[handle.axis]
+        };
+        center
+    }
+
+    fn handle_radius(&self) -> f32 {
+        self.bounds.size().magnitude() * 0.04
+    }
+
+    /// The handle the ray passes closest to, within reach.
+    fn pick(&self, ray: &Ray) -> Option<Handle> {
+        let radius = self.handle_radius();
+        Handle::all()
+            .filter_map(|handle| {
+                let center = self.handle_center(handle);
+                let along = (center - ray.origin).dot(ray.direction);
+                let miss = ray.at(along.max(0.)).distance(center);
+                Some((handle, along)).filter(|_| miss <= radius)
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(handle, _)| handle)
+    }
+
+    pub fn hover(&mut self, ray: &Ray) {
+        if self.enabled && self.dragging.is_none() {
+            self.hovered = self.pick(ray);
+        }
+    }
+
+    /// Grabs the handle under the ray. Returns whether there was one.
+    pub fn begin_drag(&mut self, ray: &Ray) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        self.dragging = self.pick(ray);
+        self.hovered = self.dragging;
+        self.dragging.is_some()
+    }
+
+    /// Moves the dragged face to where its axis passes closest to the ray.
+    pub fn drag(&mut self, ray: &Ray) {
+        let handle = match self.dragging {
+            Some(handle) => handle,
+            None => return,
+        };
+        let center = self.handle_center(handle);
+        let mut axis = Vector3::new(0., 0., 0.);
+        axis[handle.axis] = 1.;
+        // Closest points of two lines, the axis has unit length.
+        let offset = center - ray.origin;
+        let cos = axis.dot(ray.direction);
+        let denominator = 1. - cos * cos;
+        if denominator < 1e-6 {
+            // Looking straight along the axis, where it can't be dragged.
+            return;
+        }
+        let along = (cos * offset.dot(ray.direction) - offset.dot(axis)) / denominator;
+        let value = center[handle.axis] + along;
+        let gap = self.bounds.size().magnitude() * Self::MIN_THICKNESS;
+        let (min, max) = (&mut self.bounds.min, &mut self.bounds.max);
+        if handle.max {
+            max[handle.axis] = value.max(min[handle.axis] + gap);
+        } else {
+            min[handle.axis] = value.min(max[handle.axis] - gap);
+        }
+    }
+
+    /// Lets go of the handle. Returns whether one was dragged.
+    pub fn end_drag(&mut self) -> bool {
+        self.dragging.take().is_some()
+    }
+
+    /// Fits the box around `bounds`, e.g. the visible models.
+    pub fn fit(&mut self, bounds: &Aabb) {
+        if !bounds.is_empty() {
+            self.bounds = *bounds;
+        }
+    }
+
+    pub fn draw(&self, lines: &mut DebugLines) {
+        if !self.enabled {
+            return;
+        }
+        lines.aabb(&self.bounds, [0.2, 0.8, 1.0]);
+        let radius = self.handle_radius();
+        for handle in Handle::all() {
+            let color = if Some(handle) == self.dragging || Some(handle) == self.hovered {
+                [1.0, 0.9, 0.2]
+            } else {
+                [0.2, 0.8, 1.0]
+            };
+            // A square lying in the face.
+            let center = self.handle_center(handle);
+            let (u, v) = ((handle.axis + 1) % 3, (handle.axis + 2) % 3);
+            let mut corners = [center; 4];
+            for (i, corner) in corners.iter_mut().enumerate() {
+                corner[u] += if i & 1 == 0 { -radius } else { radius };
+                corner[v] += if i & 2 == 0 { -radius } else { radius };
+            }
+            lines.line(corners[0], corners[1], color);
+            lines.line(corners[1], corners[3], color);
+            lines.line(corners[3], corners[2], color);
+            lines.line(corners[2], corners[0], color);
+        }
+    }
+}
@@ -0,0 +1,484 @@
+//! Selecting models in the viewport, by clicking them or dragging a box around them, and
+//! moving, rotating and scaling all selected models at once, typed in or with the handles of
+//! the transform gizmo. The gizmos of lights and scene cameras are selected and moved along
+//! with them.
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, Vector3};
+
+use crate::{
+    bounds::Aabb,
+    events::SceneEvent,
+    outliner::GroupTransform,
+    picking::{to_screen, Ray},
+    registry::NodeId,
+    scene::Scene,
+};
+
+/// What the selected models rotate and scale around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PivotMode {
+    /// The average of their centers, turning them as one.
+    Median,
+    /// Each its own center, turning each in place.
+    Individual,
+    /// The 3D cursor.
+    Cursor,
+}
+
+impl PivotMode {
+    pub const ALL: [PivotMode; 3] = [PivotMode::Median, PivotMode::Individual, PivotMode::Cursor];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PivotMode::Median => "Median point",
+            PivotMode::Individual => "Individual origins",
+            PivotMode::Cursor => "3D cursor",
+        }
+    }
+}
+
+/// What dragging a handle of the transform gizmo does to the selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoMode {
+    /// Arrows moving it along their axis.
+    Translate,
+    /// Rings turning it around their axis.
+    Rotate,
+    /// Arrows ending in a box stretching it along their axis.
+    Scale,
+}
+
+impl GizmoMode {
+    pub const ALL: [GizmoMode; 3] = [GizmoMode::Translate, GizmoMode::Rotate, GizmoMode::Scale];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GizmoMode::Translate => "Move",
+            GizmoMode::Rotate => "Rotate",
+            GizmoMode::Scale => "Scale",
+        }
+    }
+}
+
+/// A handle of the gizmo being dragged.
+#[derive(Debug, Clone, Copy)]
+struct AxisDrag {
+    axis: usize,
+    /// Where the gizmo was when the drag began; rotating and scaling keep it in place.
+    pivot: Point3<f32>,
+    /// The point of the handle under the cursor when last moved: on the axis of an arrow, or
+    /// on the plane of a ring.
+    point: Point3<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectSelection {
+    pub pivot: PivotMode,
+    /// Shows the transform gizmo at the pivot, moving, turning or scaling the selection when
+    /// one of its handles is dragged.
+    pub gizmo: bool,
+    pub gizmo_mode: GizmoMode,
+    /// Typed into the Selection panel and applied to every selected model at once.
+    pub transform: GroupTransform,
+    /// The next click puts the origins of the selected models at the surface under it.
+    pub picking_origin: bool,
+    /// Corners of the box being dragged, on the screen from -1 to 1.
+    region: Option<[[f32; 2]; 2]>,
+    /// Whether the box or click adds to the selection instead of replacing it.
+    extend: bool,
+    hovered_axis: Option<usize>,
+    dragging: Option<AxisDrag>,
+}
+
+impl Default for ObjectSelection {
+    fn default() -> Self {
+        Self {
+            pivot: PivotMode::Median,
+            gizmo: true,
+            gizmo_mode: GizmoMode::Translate,
+            transform: GroupTransform::default(),
+            picking_origin: false,
+            region: None,
+            extend: false,
+            hovered_axis: None,
+            dragging: None,
+        }
+    }
+}
+
+impl ObjectSelection {
+    /// Boxes smaller than this, in screen units, are clicks.
+    const CLICK_SIZE: f32 = 0.01;
+    const AXIS_COLORS: [[f32; 3]; 3] = [[0.9, 0.2, 0.2], [0.2, 0.9, 0.2], [0.2, 0.4, 1.0]];
+    const HOVER_COLOR: [f32; 3] = [1.0, 0.9, 0.2];
+    const BOX_COLOR: [f32; 3] = [1.0, 0.6, 0.1];
+    const ORIGIN_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+    const RING_SEGMENTS: u32 = 48;
+    /// Scaling a model to nothing would lose its shape for good.
+    const MIN_SCALE: f32 = 1e-3;
+
+    pub fn is_dragging(&self) -> bool {
+        self.dragging.is_some() || self.region.is_some()
+    }
+}
+
+/// The point of the line through `origin` along the unit `axis` closest to the ray, as the
+/// distance from `origin`. `None` looking straight along the axis.
+fn closest_on_axis(origin: Point3<f32>, axis: Vector3<f32>, ray: &Ray) -> Option<f32> {
+    let offset = origin - ray.origin;
+    let cos = axis.dot(ray.direction);
+    let denominator = 1. - cos * cos;
+    if denominator < 1e-6 {
+        return None;
+    }
+    Some((cos * offset.dot(ray.direction) - offset.dot(axis)) / denominator)
+}
+
+/// Where the ray meets the plane through `origin` square to the unit `normal`, `None` when it
+/// runs along the plane or away from it.
+fn hit_plane(origin: Point3<f32>, normal: Vector3<f32>, ray: &Ray) -> Option<Point3<f32>> {
+    let facing = normal.dot(ray.direction);
+    if facing.abs() < 1e-4 {
+        return None;
+    }
+    let t = (origin - ray.origin).dot(normal) / facing;
+    Some(ray.at(t)).filter(|_| t > 0.)
+}
+
+fn unit(axis: usize) -> Vector3<f32> {
+    let mut v = Vector3::new(0., 0., 0.);
+    v[axis] = 1.;
+    v
+}
+
+impl Scene {
+    /// The selected models that still exist.
+    pub(crate) fn selected_models(&self) -> Vec<usize> {
+        self.outliner
+            .selected
+            .iter()
+            .copied()
+            .filter(|&m| m < self.models.len())
+            .collect()
+    }
+
+    /// Whether any model or gizmo is selected.
+    pub fn has_selection(&self) -> bool {
+        !self.selected_models().is_empty() || !self.selected_gizmos().is_empty()
+    }
+
+    /// The average of the origins of the selected models and the positions of the selected
+    /// gizmos.
+    pub fn selection_pivot(&self) -> Option<Point3<f32>> {
+        let gizmos = self.selected_gizmos().into_iter();
+        let origins = self
+            .selected_models()
+            .into_iter()
+            .filter(|&m| !self.models[m].bounds().is_empty())
+            .map(|m| self.origin(m))
+            .chain(gizmos.filter_map(|object| self.gizmo_position(object)))
+            .collect::<Vec<_>>();
+        Some(Point3::centroid(&origins)).filter(|_| !origins.is_empty())
+    }
+
+    /// Moves the selected models by `transform` around the pivot, e.g. the one typed into the
+    /// Selection panel.
+    pub fn transform_selected(&mut self, transform: &GroupTransform) {
+        self.transform_selected_around(|pivot| transform.matrix(pivot));
+        self.events.push(SceneEvent::TransformEdited(
+            self.selected_models().into_iter().map(NodeId).collect(),
+        ));
+    }
+
+    /// Moves each selected model and gizmo by the matrix made for its pivot, as the pivot mode
+    /// has it, with `median` the pivot of the whole selection.
+    fn transform_selected_around(&mut self, matrix: impl Fn(Point3<f32>) -> Matrix4<f32>) {
+        let median = match self.selection_pivot() {
+            Some(pivot) => pivot,
+            None => return,
+        };
+        for model in self.selected_models() {
+            let pivot = match self.selection.pivot {
+                PivotMode::Median => median,
+                PivotMode::Individual => self.origin(model),
+                PivotMode::Cursor => self.cursor.position,
+            };
+            self.bake_transform(model, matrix(pivot));
+        }
+        for object in self.selected_gizmos() {
+            let pivot = match self.selection.pivot {
+                PivotMode::Median => median,
+                PivotMode::Individual => self.gizmo_position(object).unwrap_or(median),
+                PivotMode::Cursor => self.cursor.position,
+            };
+            self.transform_gizmo(object, matrix(pivot));
+        }
+    }
+
+    fn gizmo_length(&self, pivot: Point3<f32>) -> f32 {
+        (self.camera.eye - pivot).magnitude() * 0.2
+    }
+
+    /// The gizmo arrow or ring the ray passes closest to, within reach.
+    fn pick_axis(&self, ray: &Ray) -> Option<usize> {
+        if !self.selection.gizmo {
+            return None;
+        }
+        let pivot = self.selection_pivot()?;
+        let length = self.gizmo_length(pivot);
+        let rings = self.selection.gizmo_mode == GizmoMode::Rotate;
+        (0..3)
+            .filter_map(|axis| {
+                let miss = if rings {
+                    let point = hit_plane(pivot, unit(axis), ray)?;
+                    ((point - pivot).magnitude() - length).abs()
+                } else {
+                    let along = closest_on_axis(pivot, unit(axis), ray)?.max(0.).min(length);
+                    let point = pivot + unit(axis) * along;
+                    let depth = (point - ray.origin).dot(ray.direction);
+                    (ray.at(depth) - point).magnitude()
+                };
+                Some((axis, miss)).filter(|_| miss <= length * 0.08)
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(axis, _)| axis)
+    }
+
+    /// The point of the handle of the axis under the ray, see `AxisDrag::point`.
+    fn handle_point(&self, pivot: Point3<f32>, axis: usize, ray: &Ray) -> Option<Point3<f32>> {
+        match self.selection.gizmo_mode {
+            GizmoMode::Rotate => hit_plane(pivot, unit(axis), ray),
+            GizmoMode::Translate | GizmoMode::Scale => {
+                Some(pivot + unit(axis) * closest_on_axis(pivot, unit(axis), ray)?)
+            }
+        }
+    }
+
+    /// Picks the model or gizmo under the ray, `extend` adding it to the selection or taking it
+    /// away.
+    pub fn click_select(&mut self, ray: &Ray, extend: bool) {
+        let hit = self.raycast(ray);
+        let gizmo = self
+            .pick_gizmo(ray)
+            .filter(|&(_, depth)| hit.as_ref().map_or(true, |hit| depth < hit.distance))
+            .map(|(object, _)| object);
+        if !extend {
+            self.outliner.selected.clear();
+            self.gizmos.selected.clear();
+        }
+        if let Some(object) = gizmo {
+            let selected = &mut self.gizmos.selected;
+            if !selected.remove(&object) {
+                selected.insert(object);
+            }
+        } else if let Some(hit) = hit {
+            self.outliner.toggle_selected(hit.model);
+        }
+    }
+
+    /// Grabs the gizmo arrow under the ray, or else starts a box at `screen`. While picking an
+    /// origin or construction plane, picks the surface under the ray for it instead. Returns
+    /// whether there was something to do.
+    pub fn begin_select(&mut self, ray: &Ray, screen: [f32; 2], extend: bool) -> bool {
+        if self.pick_construction_plane(ray) {
+            return true;
+        }
+        if std::mem::take(&mut self.selection.picking_origin) {
+            if let Some(hit) = self.raycast(ray) {
+                self.set_selected_origins(hit.point);
+            }
+            return true;
+        }
+        if let Some(axis) = self.pick_axis(ray) {
+            let pivot = match self.selection_pivot() {
+                Some(pivot) => pivot,
+                None => return false,
+            };
+            let point = self.handle_point(pivot, axis, ray).unwrap_or(pivot);
+            self.selection.dragging = Some(AxisDrag { axis, pivot, point });
+            return true;
+        }
+        self.selection.region = Some([screen, screen]);
+        self.selection.extend = extend;
+        true
+    }
+
+    /// Follows the cursor with the box or the dragged handle, or highlights the handle under it.
+    pub fn drag_select(&mut self, ray: &Ray, screen: [f32; 2]) {
+        if let Some(region) = &mut self.selection.region {
+            region[1] = screen;
+            return;
+        }
+        let drag = match self.selection.dragging {
+            Some(drag) => drag,
+            None => {
+                self.selection.hovered_axis = self.pick_axis(ray);
+                return;
+            }
+        };
+        let axis = unit(drag.axis);
+        let point = match self.handle_point(drag.pivot, drag.axis, ray) {
+            Some(point) => point,
+            None => return,
+        };
+        match self.selection.gizmo_mode {
+            GizmoMode::Translate => {
+                let offset = axis * (point - drag.point).dot(axis);
+                self.transform_selected_around(|_| Matrix4::from_translation(offset));
+                if let Some(drag) = &mut self.selection.dragging {
+                    drag.point += offset;
+                    drag.pivot += offset;
+                }
+                return;
+            }
+            GizmoMode::Rotate => {
+                let (from, to) = (drag.point - drag.pivot, point - drag.pivot);
+                let angle = Rad(axis.dot(from.cross(to)).atan2(from.dot(to)));
+                self.transform_selected_around(|pivot| {
+                    let pivot = pivot.to_vec();
+                    Matrix4::from_translation(pivot)
+                        * Matrix4::from_axis_angle(axis, angle)
+                        * Matrix4::from_translation(-pivot)
+                });
+            }
+            GizmoMode::Scale => {
+                let from = (drag.point - drag.pivot).dot(axis);
+                let to = (point - drag.pivot).dot(axis);
+                let factor = to / from;
+                if from.abs() < 1e-6 || factor < ObjectSelection::MIN_SCALE {
+                    return;
+                }
+                let mut scale = [1.; 3];
+                scale[drag.axis] = factor;
+                self.transform_selected_around(|pivot| {
+                    let pivot = pivot.to_vec();
+                    Matrix4::from_translation(pivot)
+                        * Matrix4::from_nonuniform_scale(scale[0], scale[1], scale[2])
+                        * Matrix4::from_translation(-pivot)
+                });
+            }
+        }
+        if let Some(drag) = &mut self.selection.dragging {
+            drag.point = point;
+        }
+    }
+
+    /// Finishes the box, selecting the models whose center is inside it and the gizmos too, or
+    /// the one under the cursor for a click, or lets go of the arrow. Returns whether anything
+    /// was dragged.
+    pub fn end_select(&mut self, ray: &Ray) -> bool {
+        if self.selection.dragging.take().is_some() {
+            let models = self.selected_models().into_iter().map(NodeId).collect();
+            self.events.push(SceneEvent::TransformEdited(models));
+            return true;
+        }
+        let [start, end] = match self.selection.region.take() {
+            Some(region) => region,
+            None => return false,
+        };
+        let extend = self.selection.extend;
+        if (end[0] - start[0]).abs().max((end[1] - start[1]).abs()) < ObjectSelection::CLICK_SIZE {
+            self.click_select(ray, extend);
+            return true;
+        }
+        let (min, max) = (
+            [start[0].min(end[0]), start[1].min(end[1])],
+            [start[0].max(end[0]), start[1].max(end[1])],
+        );
+        let in_box = |point| {
+            to_screen(&self.camera, point).map_or(false, |p| {
+                (min[0]..=max[0]).contains(&p[0]) && (min[1]..=max[1]).contains(&p[1])
+            })
+        };
+        let inside = (0..self.models.len())
+            .filter(|&m| self.outliner.is_pickable(m))
+            .filter(|&m| {
+                let bounds = self.models[m].bounds();
+                !bounds.is_empty() && in_box(bounds.center())
+            })
+            .collect::<Vec<_>>();
+        let gizmos = self
+            .shown_gizmos()
+            .into_iter()
+            .filter(|&object| self.gizmo_position(object).map_or(false, in_box))
+            .collect::<Vec<_>>();
+        if !extend {
+            self.outliner.selected.clear();
+            self.gizmos.selected.clear();
+        }
+        self.outliner.selected.extend(inside);
+        self.gizmos.selected.extend(gizmos);
+        true
+    }
+
+    /// Draws the box being dragged, the origins of the selected models and the gizmo.
+    pub(crate) fn draw_selection(&mut self) {
+        for model in self.selected_models() {
+            let origin = self.origin(model);
+            let size = self.gizmo_length(origin) * 0.04;
+            for axis in 0..3 {
+                let (a, b) = (origin - unit(axis) * size, origin + unit(axis) * size);
+                self.renderer
+                    .debug_lines
+                    .line(a, b, ObjectSelection::ORIGIN_COLOR);
+            }
+        }
+        if let Some([start, end]) = self.selection.region {
+            // Just behind the near plane, in front of everything.
+            let depth = self.camera.projection.near * 2.;
+            let corners = [start, [end[0], start[1]], end, [start[0], end[1]]]
+                .map(|corner| Ray::from_screen(&self.camera, corner).at(depth));
+            for i in 0..4 {
+                let (a, b) = (corners[i], corners[(i + 1) % 4]);
+                self.renderer
+                    .debug_lines
+                    .line(a, b, ObjectSelection::BOX_COLOR);
+            }
+        }
+        if !self.selection.gizmo {
+            return;
+        }
+        let pivot = match self.selection_pivot() {
+            Some(pivot) => pivot,
+            None => return,
+        };
+        // Kept where the drag began while turning or scaling, as the median moves with
+        // individual origins.
+        let pivot = self.selection.dragging.map_or(pivot, |drag| drag.pivot);
+        let length = self.gizmo_length(pivot);
+        let mode = self.selection.gizmo_mode;
+        let lines = &mut self.renderer.debug_lines;
+        let active = self
+            .selection
+            .dragging
+            .map(|drag| drag.axis)
+            .or(self.selection.hovered_axis);
+        for (axis, &color) in ObjectSelection::AXIS_COLORS.iter().enumerate() {
+            let color = if active == Some(axis) {
+                ObjectSelection::HOVER_COLOR
+            } else {
+                color
+            };
+            let tip = pivot + unit(axis) * length;
+            match mode {
+                GizmoMode::Translate => {
+                    lines.line(pivot, tip, color);
+                    // A small cross at the tip.
+                    for other in (0..3).filter(|&other| other != axis) {
+                        let side = unit(other) * length * 0.05;
+                        lines.line(tip - unit(axis) * length * 0.1 + side, tip, color);
+                        lines.line(tip - unit(axis) * length * 0.1 - side, tip, color);
+                    }
+                }
+                GizmoMode::Rotate => {
+                    lines.circle(pivot, unit(axis), length, ObjectSelection::RING_SEGMENTS, color);
+                }
+                GizmoMode::Scale => {
+                    lines.line(pivot, tip, color);
+                    let half = Vector3::new(1., 1., 1.) * length * 0.04;
+                    lines.aabb(&Aabb::from_points([tip - half, tip + half]), color);
+                }
+            }
+        }
+    }
+}
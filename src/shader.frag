@@ -1,5 +1,6 @@
 #version 450
 #extension GL_GOOGLE_include_directive : enable
+#extension GL_EXT_samplerless_texture_functions : require
 
 #include "test.glsl"
 
@@ -7,6 +8,12 @@ layout(location=0) in vec2 v_tex_coords;
 layout(location=1) in vec3 v_position;
 layout(location=2) in vec3 v_light_position;
 layout(location=3) in vec3 v_view_position;
+layout(location=4) in vec4 v_shadow_position;
+layout(location=5) in vec3 v_world_position;
+#ifdef HAS_VERTEX_COLOR
+layout(location=6) in vec4 v_color;
+#endif
+layout(location=7) in mat3 v_tangent_matrix;
 //layout(location=2) in mat3 v_tangent_matrix;
 layout(location=0) out vec4 f_color;
 
@@ -16,40 +23,319 @@ layout(set = 0, binding = 2) uniform texture2D t_normal;
 layout(set = 0, binding = 3) uniform sampler s_normal;
 layout(set = 0, binding = 4) uniform texture2D t_specular;
 layout(set = 0, binding = 5) uniform sampler s_specular;
+layout(set = 0, binding = 6) uniform texture2D t_occlusion;
+layout(set = 0, binding = 7) uniform sampler s_occlusion;
+layout(set = 0, binding = 8)
+uniform MaterialFeatures {
+    // Read by the uber shader only. x: whether there is a normal map, y: double sided,
+    // z: metallic-roughness
+    vec4 material_features;
+};
+layout(set = 0, binding = 9) uniform texture2D t_emissive;
+layout(set = 0, binding = 10) uniform sampler s_emissive;
+
+layout(set=1, binding=0)
+uniform Uniforms {
+    vec3 u_view_position;
+    mat4 u_view_proj;
+    // xyz: corners of the section box, w of the minimum: whether it clips
+    vec4 u_section_min;
+    vec4 u_section_max;
+};
 
 layout(set=2, binding=0)
 uniform Light {
     mat4 light_projection;
+    // w: range
     vec4 light_position;
     vec4 light_color;
+    // w: the kind, see LightKind::index
+    vec4 light_direction;
+    // x, y: cosines of the inner and outer cone angles
+    vec4 light_spot;
+};
+// The lights after the first and the clusters listing them, see clusters.rs. The uber shader
+// made for WebGL2, which has no storage buffers, reads the lights from a copy in a texture.
+#ifdef UBER
+layout(set=2, binding=1) uniform texture2D t_point_lights;
+#else
+// LightRaw in light.rs.
+struct PointLight {
+    mat4 projection;
+    // w: range
+    vec4 position;
+    vec4 color;
+    // w: the kind, see LightKind::index
+    vec4 direction;
+    vec4 spot;
+};
+
+layout(std430, set=2, binding=9) readonly buffer PointLights {
+    PointLight point_light_data[];
+};
+#endif
+layout(set=2, binding=2) uniform utexture2D t_clusters;
+layout(set=2, binding=3)
+uniform Clusters {
+    mat4 cluster_view;
+    mat4 cluster_view_proj;
+    mat4 cluster_inverse_projection;
+    // xyz: clusters along each axis, w: number of lights
+    uvec4 cluster_grid;
+    // x: near, y: far
+    vec4 cluster_depth;
+};
+// The environment map baked for image based lighting, see environment.rs.
+layout(set=2, binding=4) uniform textureCube t_irradiance;
+layout(set=2, binding=5) uniform textureCube t_prefiltered;
+layout(set=2, binding=6) uniform texture2D t_brdf_lut;
+layout(set=2, binding=7) uniform sampler s_environment;
+layout(set=2, binding=8)
+uniform Environment {
+    // x: whether the environment lights, y: its intensity, z: last mip level of t_prefiltered
+    vec4 environment;
+};
+
+layout(set=3, binding=0)
+uniform Shadow {
+    mat4 shadow_view_proj;
+    // x: whether anything casts shadows, y: depth bias, z: normal offset in scene units,
+    // w: texels sampled on each side of the fragment
+    vec4 shadow_params;
+};
+layout(set=3, binding=1) uniform texture2D t_shadow;
+layout(set=3, binding=2) uniform samplerShadow s_shadow;
+layout(set=3, binding=3)
+uniform Object {
+    // x: whether the mesh receives shadows, y: whether it is lit
+    vec4 object_flags;
+    // rgb: flat color replacing the textures while a is 1
+    vec4 display_color;
+    mat4 model_matrix;
+};
+
+// 1 where the light reaches the fragment, 0 in shadow.
+float lit() {
+    if (shadow_params.x == 0.0 || object_flags.x == 0.0) {
+        return 1.0;
+    }
+    vec3 ndc = v_shadow_position.xyz / v_shadow_position.w;
+    vec2 uv = vec2(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || ndc.z > 1.0) {
+        return 1.0;
+    }
+    // Percentage closer filtering over a square of texels, softening the edges.
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DShadow(t_shadow, s_shadow), 0));
+    int radius = int(shadow_params.w);
+    float sum = 0.0;
+    for (int x = -radius; x <= radius; x++) {
+        for (int y = -radius; y <= radius; y++) {
+            vec3 coords = vec3(uv + vec2(x, y) * texel, ndc.z - shadow_params.y);
+            sum += texture(sampler2DShadow(t_shadow, s_shadow), coords);
+        }
+    }
+    float side = float(radius * 2 + 1);
+    return sum / (side * side);
+}
+
+// The cluster the fragment is in, its row in t_clusters.
+int cluster() {
+    vec4 clip = cluster_view_proj * vec4(v_world_position, 1.0);
+    vec2 cells = vec2(cluster_grid.xy);
+    uvec2 tile = uvec2(clamp((clip.xy / clip.w * 0.5 + 0.5) * cells, vec2(0.0), cells - 1.0));
+    float depth = max(-(cluster_view * vec4(v_world_position, 1.0)).z, cluster_depth.x);
+    float slice = log(depth / cluster_depth.x) / log(cluster_depth.y / cluster_depth.x);
+    uint z = uint(clamp(slice * float(cluster_grid.z), 0.0, float(cluster_grid.z) - 1.0));
+    return int(tile.x + tile.y * cluster_grid.x + z * cluster_grid.x * cluster_grid.y);
+}
+
+const float DIRECTIONAL = 0.0;
+const float SPOT = 2.0;
+const float PI = 3.14159265359;
+
+// What the lights shade, read from the material's textures.
+struct Surface {
+    vec3 color;
+    // The reflectance head-on, the specular color of Phong materials.
+    vec3 f0;
+    float metallic;
+    float roughness;
+    // Lit as metallic-roughness, or with Phong's highlights.
+    bool pbr;
 };
 
+// Cook-Torrance with the GGX distribution, Smith's geometry and Schlick's Fresnel. Scaled by
+// pi, so a white diffuse surface facing a light is as bright as with Phong.
+vec3 cook_torrance(Surface surface, vec3 normal, vec3 view_dir, vec3 light_dir) {
+    vec3 half_dir = normalize(view_dir + light_dir);
+    float n_dot_l = max(dot(normal, light_dir), 0.0);
+    float n_dot_v = max(dot(normal, view_dir), 0.0001);
+    float n_dot_h = max(dot(normal, half_dir), 0.0);
+    float a = max(surface.roughness, 0.04);
+    a *= a;
+    float d = n_dot_h * n_dot_h * (a * a - 1.0) + 1.0;
+    float distribution = a * a / (PI * d * d);
+    float k = (surface.roughness + 1.0) * (surface.roughness + 1.0) / 8.0;
+    float geometry = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+    vec3 fresnel = surface.f0
+        + (1.0 - surface.f0) * pow(1.0 - max(dot(half_dir, view_dir), 0.0), 5.0);
+    vec3 specular = distribution * geometry * fresnel / max(4.0 * n_dot_v * n_dot_l, 0.0001);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - surface.metallic) * surface.color;
+    return (diffuse + PI * specular) * n_dot_l;
+}
+
+// The light of a light's color from `light_dir` reflected towards the eye.
+vec3 shade(Surface surface, vec3 normal, vec3 view_dir, vec3 light_dir, vec3 light_color) {
+    if (surface.pbr) {
+        return cook_torrance(surface, normal, view_dir, light_dir) * light_color;
+    }
+    float diffuse_strength = diffuse_s(normal, light_dir);
+    vec3 half_dir = normalize(view_dir + light_dir);
+    float specular_strength = pow(max(dot(normal, half_dir), 0.0), 32)
+        * smoothstep(-.01, .01, diffuse_strength);
+    return (surface.color * diffuse_strength + surface.f0 * specular_strength) * light_color;
+}
+
+// How much of the light reaches the fragment `to_light` away from it in scene space: all of
+// a directional light, fading out to nothing at the range otherwise, and for a spot light also
+// between its inner and outer cone.
+float attenuation(vec3 to_light, float range, vec4 direction, vec4 spot) {
+    if (direction.w == DIRECTIONAL) {
+        return 1.0;
+    }
+    float falloff = clamp(1.0 - pow(length(to_light) / range, 2.0), 0.0, 1.0);
+    falloff *= falloff;
+    if (direction.w == SPOT) {
+        falloff *= smoothstep(spot.y, spot.x, dot(normalize(-to_light), direction.xyz));
+    }
+    return falloff;
+}
+
+// Diffuse and specular light of the lights of the fragment's cluster, in tangent space.
+vec3 point_lights(vec3 normal, vec3 view_dir, Surface surface) {
+    vec3 color = vec3(0.0);
+    if (cluster_grid.w == 0) {
+        return color;
+    }
+    int row = cluster();
+    uint count = texelFetch(t_clusters, ivec2(0, row), 0).r;
+    for (uint i = 0; i < count; i++) {
+        int light = int(texelFetch(t_clusters, ivec2(i + 1, row), 0).r);
+#ifdef UBER
+        vec4 position_range = texelFetch(t_point_lights, ivec2(light * 4, 0), 0);
+        vec3 point_color = texelFetch(t_point_lights, ivec2(light * 4 + 1, 0), 0).rgb;
+        vec4 direction = texelFetch(t_point_lights, ivec2(light * 4 + 2, 0), 0);
+        vec4 spot = texelFetch(t_point_lights, ivec2(light * 4 + 3, 0), 0);
+#else
+        vec4 position_range = point_light_data[light].position;
+        vec3 point_color = point_light_data[light].color.rgb;
+        vec4 direction = point_light_data[light].direction;
+        vec4 spot = point_light_data[light].spot;
+#endif
+        vec3 to_light = position_range.xyz - v_world_position;
+        float falloff = attenuation(to_light, position_range.w, direction, spot);
+        if (direction.w == DIRECTIONAL) {
+            to_light = -direction.xyz;
+        }
+        vec3 light_dir = normalize(v_tangent_matrix * to_light);
+        color += shade(surface, normal, view_dir, light_dir, point_color) * falloff;
+    }
+    return color;
+}
+
+// Diffuse and specular light of the environment, in scene space.
+vec3 environment_light(vec3 normal, vec3 view_dir, Surface surface) {
+    float roughness = surface.roughness;
+    float n_dot_v = max(dot(normal, view_dir), 0.0);
+    vec3 f0 = surface.f0;
+    // Schlick's Fresnel, rough surfaces reflecting less at grazing angles.
+    vec3 fresnel = f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+    vec3 irradiance = texture(samplerCube(t_irradiance, s_environment), normal).rgb;
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - surface.metallic) * irradiance * surface.color;
+    float lod = roughness * environment.z;
+    vec3 reflected = textureLod(
+        samplerCube(t_prefiltered, s_environment), reflect(-view_dir, normal), lod).rgb;
+    vec2 brdf = texture(sampler2D(t_brdf_lut, s_environment), vec2(n_dot_v, roughness)).rg;
+    return (diffuse + reflected * (fresnel * brdf.x + brdf.y)) * environment.y;
+}
+
 void main() {
+    if (u_section_min.w != 0.0 && (any(lessThan(v_world_position, u_section_min.xyz))
+            || any(greaterThan(v_world_position, u_section_max.xyz)))) {
+        discard;
+    }
     vec4 object_color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords);
+#ifdef UBER
+    if (material_features.y == 0.0 && !gl_FrontFacing) {
+        discard;
+    }
     vec4 object_normal = texture(sampler2D(t_normal, s_normal), v_tex_coords);
+    if (material_features.x == 0.0) {
+        object_normal = vec4(0.5, 0.5, 1.0, 1.0);
+    }
+#elif defined(HAS_NORMAL_MAP)
+    vec4 object_normal = texture(sampler2D(t_normal, s_normal), v_tex_coords);
+#else
+    vec4 object_normal = vec4(0.5, 0.5, 1.0, 1.0);
+#endif
     vec4 object_specular = texture(sampler2D(t_specular, s_specular), v_tex_coords);
+    float occlusion = texture(sampler2D(t_occlusion, s_occlusion), v_tex_coords).r;
+    vec3 emissive = texture(sampler2D(t_emissive, s_emissive), v_tex_coords).rgb;
+#ifdef HAS_VERTEX_COLOR
+    object_color *= v_color;
+#endif
+#ifdef UBER
+    bool pbr = material_features.z != 0.0;
+#elif defined(PBR)
+    bool pbr = true;
+#else
+    bool pbr = false;
+#endif
+    if (display_color.a != 0.0) {
+        object_color = vec4(display_color.rgb, 1.0);
+        object_normal = vec4(0.5, 0.5, 1.0, 1.0);
+        object_specular = vec4(0.2);
+        occlusion = 1.0;
+        pbr = false;
+    }
+
+    Surface surface;
+    if (pbr) {
+        // Roughness in green, metalness in blue, as glTF has them.
+        float metallic = object_specular.b;
+        vec3 f0 = mix(vec3(0.04), object_color.rgb, metallic);
+        surface = Surface(object_color.rgb, f0, metallic, object_specular.g, true);
+    } else {
+        // Phong materials reflect the environment as if fully rough, and don't glow.
+        surface = Surface(object_color.rgb, object_specular.rgb, 0.0, 1.0, false);
+        emissive = vec3(0.0);
+    }
 
     float ambient_strength = 0.1;
-    vec3 ambient_color = light_color.rgb * ambient_strength;
+    vec3 ambient_color = light_color.rgb * ambient_strength * occlusion;
 
     vec3 normal = normalize(object_normal.rgb * 2.0 - 1.0);
     vec3 light_dir = normalize(v_light_position - v_position);
-
-    //float diffuse_strength = max(dot(normal, light_dir), 0.);
-    float diffuse_strength = diffuse_s(normal, light_dir);
-    vec3 diffuse_color = light_color.rgb * diffuse_strength;
-
+    if (light_direction.w == DIRECTIONAL) {
+        light_dir = normalize(v_tangent_matrix * -light_direction.xyz);
+    }
+    float light_falloff = attenuation(
+        light_position.xyz - v_world_position, light_position.w, light_direction, light_spot);
     vec3 view_dir = normalize(v_view_position - v_position);
-    vec3 half_dir = normalize(view_dir + light_dir);
-    float specular_strength = pow(max(dot(normal, half_dir), 0.0), 32) * smoothstep(-.01,.01, diffuse_strength);
-    vec3 specular_color = object_specular.rgb * specular_strength * light_color.rgb;
-
-    vec3 color = (ambient_color + diffuse_color) * object_color.xyz + specular_color;
-    // vec3 color = ambient_color * object_color.xyz;
-    // vec3 color = diffuse_color * object_color.xyz;
-    // vec3 color = specular_color;
-    // vec3 color = normal;
-    // vec3 color =  vec3(v_tex_coords, 0.0);
+
+    float light = lit() * light_falloff;
+    vec3 ambient = ambient_color * object_color.xyz;
+    if (environment.x != 0.0) {
+        vec3 world_normal = normalize(transpose(v_tangent_matrix) * normal);
+        vec3 world_view_dir = normalize(u_view_position - v_world_position);
+        ambient = environment_light(world_normal, world_view_dir, surface) * occlusion;
+    }
+    vec3 color = ambient + shade(surface, normal, view_dir, light_dir, light_color.rgb) * light;
+    color += point_lights(normal, view_dir, surface);
+    color += emissive;
+    if (object_flags.y == 0.0) {
+        color = object_color.rgb;
+    }
 
     f_color = vec4(color, object_color.a);
 }
\ No newline at end of file
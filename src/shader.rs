@@ -1,8 +1,12 @@
 use std::{fs::File, io::Read, path::Path, path::PathBuf};
 
 use crate::{
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    instancing::InstanceRaw,
     model::{self, ModelVertex, Vertex},
+    permutation::ShaderFeatures,
     texture,
+    vertex_layout::VertexLayout,
 };
 
 #[derive(Debug)]
@@ -11,6 +15,7 @@ pub struct Shader {
     filename: PathBuf,
     modules: Vec<wgpu::ShaderModule>,
     pub render_pipeline: wgpu::RenderPipeline,
+    pub features: ShaderFeatures,
 }
 
 pub trait Pass {
@@ -22,7 +27,7 @@ pub trait Pass {
 pub struct ForwardPass {
     pipeline: wgpu::RenderPipeline,
     bind_group: wgpu::BindGroup,
-    uniform_buf: wgpu::Buffer,
+    uniform_buf: Tracked<wgpu::Buffer>,
 }
 
 impl Pass for ForwardPass {
@@ -64,12 +69,15 @@ impl ForwardPass {
             push_constant_ranges: &[],
         });
 
-        let uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: uniform_size,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        let uniform_buf = device.create_tracked_buffer(
+            GpuCategory::Uniforms,
+            &wgpu::BufferDescriptor {
+                label: None,
+                size: uniform_size,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
 
         // Create bind group
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -158,125 +166,6 @@ impl ForwardPass {
     }
 }
 
-pub struct ShadowPass {
-    pipeline: wgpu::RenderPipeline,
-    bind_group: wgpu::BindGroup,
-    uniform_buf: wgpu::Buffer,
-}
-
-impl Pass for ShadowPass {
-    fn pipeline(&self) -> &wgpu::RenderPipeline {
-        &self.pipeline
-    }
-
-    fn bind_group(&self) -> &wgpu::BindGroup {
-        &self.bind_group
-    }
-
-    fn uniform_buf(&self) -> &wgpu::Buffer {
-        &self.uniform_buf
-    }
-}
-
-//impl ShadowPass {
-//    const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
-//
-//    fn render_pass<'a>(
-//        encoder: &'a wgpu::CommandEncoder,
-//        target_view: &'a wgpu::TextureView,
-//    ) -> wgpu::RenderPass<'a> {
-//        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-//            label: None,
-//            color_attachments: &[],
-//            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-//                view: target_view,
-//                depth_ops: Some(wgpu::Operations {
-//                    load: wgpu::LoadOp::Clear(1.0),
-//                    store: true,
-//                }),
-//                stencil_ops: None,
-//            }),
-//        })
-//    }
-//
-//    pub fn new(device: &wgpu::Device, shader: &Shader) -> Self {
-//        let uniform_size = std::mem::size_of_val(shader) as wgpu::BufferAddress;
-//        // Create pipeline layout
-//        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-//            label: None,
-//            entries: &[wgpu::BindGroupLayoutEntry {
-//                binding: 0, // global
-//                visibility: wgpu::ShaderStages::VERTEX,
-//                ty: wgpu::BindingType::Buffer {
-//                    ty: wgpu::BufferBindingType::Uniform,
-//                    has_dynamic_offset: false,
-//                    min_binding_size: wgpu::BufferSize::new(uniform_size),
-//                },
-//                count: None,
-//            }],
-//        });
-//        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-//            label: Some("shadow"),
-//            bind_group_layouts: &[&bind_group_layout, &local_bind_group_layout],
-//            push_constant_ranges: &[],
-//        });
-//
-//        let uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
-//            label: None,
-//            size: uniform_size,
-//            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-//            mapped_at_creation: false,
-//        });
-//
-//        // Create bind group
-//        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-//            layout: &bind_group_layout,
-//            entries: &[wgpu::BindGroupEntry {
-//                binding: 0,
-//                resource: uniform_buf.as_entire_binding(),
-//            }],
-//            label: None,
-//        });
-//
-//        // Create the render pipeline
-//        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-//            label: Some("shadow"),
-//            layout: Some(&pipeline_layout),
-//            vertex: wgpu::VertexState {
-//                module: &shader,
-//                entry_point: "vs_bake",
-//                buffers: &[ModelVertex::desc()],
-//            },
-//            fragment: None,
-//            primitive: wgpu::PrimitiveState {
-//                topology: wgpu::PrimitiveTopology::TriangleList,
-//                front_face: wgpu::FrontFace::Ccw,
-//                cull_mode: Some(wgpu::Face::Back),
-//                clamp_depth: device.features().contains(wgpu::Features::DEPTH_CLAMPING),
-//                ..Default::default()
-//            },
-//            depth_stencil: Some(wgpu::DepthStencilState {
-//                format: Self::SHADOW_FORMAT,
-//                depth_write_enabled: true,
-//                depth_compare: wgpu::CompareFunction::LessEqual,
-//                stencil: wgpu::StencilState::default(),
-//                bias: wgpu::DepthBiasState {
-//                    constant: 2, // corresponds to bilinear filtering
-//                    slope_scale: 2.0,
-//                    clamp: 0.0,
-//                },
-//            }),
-//            multisample: wgpu::MultisampleState::default(),
-//        });
-//
-//        Self {
-//            pipeline,
-//            bind_group,
-//            uniform_buf,
-//        }
-//    }
-//}
-
 impl Shader {
     pub fn new(
         label: impl Into<String>,
@@ -285,16 +174,51 @@ impl Shader {
         texture_bind_group_layout: &wgpu::BindGroupLayout,
         light_bind_group_layout: &wgpu::BindGroupLayout,
         uniforms_bind_group_layout: &wgpu::BindGroupLayout,
+        shadow_bind_group_layout: &wgpu::BindGroupLayout,
         texture_format: &wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+    ) -> Self {
+        Self::with_features(
+            label,
+            filename,
+            device,
+            texture_bind_group_layout,
+            light_bind_group_layout,
+            uniforms_bind_group_layout,
+            shadow_bind_group_layout,
+            texture_format,
+            depth_format,
+            ShaderFeatures::default(),
+            None,
+        )
+    }
+
+    /// The permutation of the shader at `filename` compiled for `features`, for meshes with
+    /// the extra attributes of `vertex_layout` in a second vertex buffer if given.
+    pub fn with_features(
+        label: impl Into<String>,
+        filename: impl Into<PathBuf>,
+        device: &wgpu::Device,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        uniforms_bind_group_layout: &wgpu::BindGroupLayout,
+        shadow_bind_group_layout: &wgpu::BindGroupLayout,
+        texture_format: &wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        features: ShaderFeatures,
+        vertex_layout: Option<&VertexLayout>,
     ) -> Self {
         let label = label.into();
-        let filename = filename.into();
+        let filename: PathBuf = filename.into();
+        let filename = features.shader_path(&filename);
         let mut vert_name = filename.clone();
         vert_name.set_extension("vert.spv");
         let mut frag_name = filename.clone();
         frag_name.set_extension("frag.spv");
         let vs_module = Self::compile_shader(&label, &vert_name, device);
         let fs_module = Self::compile_shader(&label, &frag_name, device);
+        let mut buffers = vec![model::ModelVertex::desc(), InstanceRaw::desc()];
+        buffers.extend(vertex_layout.map(|layout| layout.desc()));
         let render_pipeline = {
             let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
@@ -302,6 +226,7 @@ impl Shader {
                     texture_bind_group_layout,
                     light_bind_group_layout,
                     uniforms_bind_group_layout,
+                    shadow_bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
@@ -309,10 +234,11 @@ impl Shader {
                 &device,
                 &layout,
                 *texture_format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
+                Some(depth_format),
+                &buffers,
                 &vs_module,
                 &fs_module,
+                features.cull_mode(),
             )
         };
 
@@ -323,6 +249,7 @@ impl Shader {
             filename,
             modules,
             render_pipeline,
+            features,
         }
     }
 
@@ -333,14 +260,17 @@ impl Shader {
         texture_bind_group_layout: &wgpu::BindGroupLayout,
         light_bind_group_layout: &wgpu::BindGroupLayout,
         uniforms_bind_group_layout: &wgpu::BindGroupLayout,
+        shadow_bind_group_layout: &wgpu::BindGroupLayout,
         texture_format: &wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
     ) -> Self {
         let label = label.into();
         let filename = filename.into();
 
-        let shader = wgpu::include_spirv!("shader.vert.spv");
+        let features = ShaderFeatures::default();
+        let shader = wgpu::include_spirv!("shader_normal_map.vert.spv");
         let vs_module = device.create_shader_module(&shader);
-        let shader = wgpu::include_spirv!("shader.frag.spv");
+        let shader = wgpu::include_spirv!("shader_normal_map.frag.spv");
         let fs_module = device.create_shader_module(&shader);
         let render_pipeline = {
             let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -349,6 +279,7 @@ impl Shader {
                     texture_bind_group_layout,
                     light_bind_group_layout,
                     uniforms_bind_group_layout,
+                    shadow_bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
@@ -356,10 +287,11 @@ impl Shader {
                 &device,
                 &layout,
                 *texture_format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
+                Some(depth_format),
+                &[model::ModelVertex::desc(), InstanceRaw::desc()],
                 &vs_module,
                 &fs_module,
+                features.cull_mode(),
             )
         };
 
@@ -370,12 +302,71 @@ impl Shader {
             filename,
             modules,
             render_pipeline,
+            features,
         }
     }
+    /// Shaders loaded by path, built in for platforms where the build output isn't around at run
+    /// time.
+    const EMBEDDED: &'static [(&'static str, &'static [u8])] = &[
+        ("shader.vert.spv", include_bytes!("shader.vert.spv")),
+        ("shader.frag.spv", include_bytes!("shader.frag.spv")),
+        ("shader_normal_map.vert.spv", include_bytes!("shader_normal_map.vert.spv")),
+        ("shader_normal_map.frag.spv", include_bytes!("shader_normal_map.frag.spv")),
+        ("shader_vertex_color.vert.spv", include_bytes!("shader_vertex_color.vert.spv")),
+        ("shader_vertex_color.frag.spv", include_bytes!("shader_vertex_color.frag.spv")),
+        ("shader_normal_map_vertex_color.vert.spv", include_bytes!("shader_normal_map_vertex_color.vert.spv")),
+        ("shader_normal_map_vertex_color.frag.spv", include_bytes!("shader_normal_map_vertex_color.frag.spv")),
+        ("shader_skinned.vert.spv", include_bytes!("shader_skinned.vert.spv")),
+        ("shader_skinned.frag.spv", include_bytes!("shader_skinned.frag.spv")),
+        ("shader_normal_map_skinned.vert.spv", include_bytes!("shader_normal_map_skinned.vert.spv")),
+        ("shader_normal_map_skinned.frag.spv", include_bytes!("shader_normal_map_skinned.frag.spv")),
+        ("shader_vertex_color_skinned.vert.spv", include_bytes!("shader_vertex_color_skinned.vert.spv")),
+        ("shader_vertex_color_skinned.frag.spv", include_bytes!("shader_vertex_color_skinned.frag.spv")),
+        ("shader_normal_map_vertex_color_skinned.vert.spv", include_bytes!("shader_normal_map_vertex_color_skinned.vert.spv")),
+        ("shader_normal_map_vertex_color_skinned.frag.spv", include_bytes!("shader_normal_map_vertex_color_skinned.frag.spv")),
+        ("shader_pbr.vert.spv", include_bytes!("shader_pbr.vert.spv")),
+        ("shader_pbr.frag.spv", include_bytes!("shader_pbr.frag.spv")),
+        ("shader_normal_map_pbr.vert.spv", include_bytes!("shader_normal_map_pbr.vert.spv")),
+        ("shader_normal_map_pbr.frag.spv", include_bytes!("shader_normal_map_pbr.frag.spv")),
+        ("shader_vertex_color_pbr.vert.spv", include_bytes!("shader_vertex_color_pbr.vert.spv")),
+        ("shader_vertex_color_pbr.frag.spv", include_bytes!("shader_vertex_color_pbr.frag.spv")),
+        ("shader_normal_map_vertex_color_pbr.vert.spv", include_bytes!("shader_normal_map_vertex_color_pbr.vert.spv")),
+        ("shader_normal_map_vertex_color_pbr.frag.spv", include_bytes!("shader_normal_map_vertex_color_pbr.frag.spv")),
+        ("shader_skinned_pbr.vert.spv", include_bytes!("shader_skinned_pbr.vert.spv")),
+        ("shader_skinned_pbr.frag.spv", include_bytes!("shader_skinned_pbr.frag.spv")),
+        ("shader_normal_map_skinned_pbr.vert.spv", include_bytes!("shader_normal_map_skinned_pbr.vert.spv")),
+        ("shader_normal_map_skinned_pbr.frag.spv", include_bytes!("shader_normal_map_skinned_pbr.frag.spv")),
+        ("shader_vertex_color_skinned_pbr.vert.spv", include_bytes!("shader_vertex_color_skinned_pbr.vert.spv")),
+        ("shader_vertex_color_skinned_pbr.frag.spv", include_bytes!("shader_vertex_color_skinned_pbr.frag.spv")),
+        ("shader_normal_map_vertex_color_skinned_pbr.vert.spv", include_bytes!("shader_normal_map_vertex_color_skinned_pbr.vert.spv")),
+        ("shader_normal_map_vertex_color_skinned_pbr.frag.spv", include_bytes!("shader_normal_map_vertex_color_skinned_pbr.frag.spv")),
+        ("shader_uber.vert.spv", include_bytes!("shader_uber.vert.spv")),
+        ("shader_uber.frag.spv", include_bytes!("shader_uber.frag.spv")),
+        ("shader_vertex_color_uber.vert.spv", include_bytes!("shader_vertex_color_uber.vert.spv")),
+        ("shader_vertex_color_uber.frag.spv", include_bytes!("shader_vertex_color_uber.frag.spv")),
+        ("shader_skinned_uber.vert.spv", include_bytes!("shader_skinned_uber.vert.spv")),
+        ("shader_skinned_uber.frag.spv", include_bytes!("shader_skinned_uber.frag.spv")),
+        ("shader_vertex_color_skinned_uber.vert.spv", include_bytes!("shader_vertex_color_skinned_uber.vert.spv")),
+        ("shader_vertex_color_skinned_uber.frag.spv", include_bytes!("shader_vertex_color_skinned_uber.frag.spv")),
+    ];
+
     pub fn compile_shader(label: &str, path: &Path, device: &wgpu::Device) -> wgpu::ShaderModule {
-        let mut f = File::open(path).unwrap();
-        let mut buffer = Vec::new();
-        f.read_to_end(&mut buffer);
+        let buffer = match File::open(path) {
+            Ok(mut f) => {
+                let mut buffer = Vec::new();
+                f.read_to_end(&mut buffer).unwrap();
+                buffer
+            }
+            Err(e) => {
+                let name = path.file_name().and_then(|name| name.to_str());
+                Self::EMBEDDED
+                    .iter()
+                    .find(|(embedded, _)| Some(*embedded) == name)
+                    .unwrap_or_else(|| panic!("Failed to open shader {:?}: {:?}", path, e))
+                    .1
+                    .to_vec()
+            }
+        };
 
         let shader = wgpu::ShaderModuleDescriptor {
             label: Some(label),
@@ -453,6 +444,7 @@ impl Shader {
         //shader: &wgpu::ShaderModule,
         vs_module: &wgpu::ShaderModule,
         fs_module: &wgpu::ShaderModule,
+        cull_mode: Option<wgpu::Face>,
     ) -> wgpu::RenderPipeline {
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline"),
@@ -476,6 +468,7 @@ impl Shader {
             primitive: wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 front_face: wgpu::FrontFace::Ccw,
+                cull_mode,
                 ..Default::default()
                 //strip_index_format: None,
                 //cull_mode: Some(wgpu::Face::Back),
@@ -523,6 +516,7 @@ impl Shader {
             //&shader_module,
             &vs_module,
             &fs_module,
+            None,
         )
     }
 }
@@ -5,20 +5,39 @@ layout(location=1) in vec2 a_tex_coords;
 layout(location=2) in vec3 a_normal;
 layout(location=3) in vec3 a_tangent;
 layout(location=4) in vec3 a_bitangent;
-// layout(location=5) in vec4 model_matrix1;
-// layout(location=6) in vec4 model_matrix2;
-// layout(location=7) in vec4 model_matrix3;
-// layout(location=8) in vec4 model_matrix4;
+// Permutations, see permutation.rs. Extra attributes come from the second vertex buffer at the
+// locations of vertex_layout.rs.
+#ifdef HAS_VERTEX_COLOR
+layout(location=6) in vec4 a_color;
+#endif
+#ifdef SKINNED
+layout(location=7) in vec4 a_joints;
+layout(location=8) in vec4 a_weights;
+#endif
+// Where the instance is drawn, see instancing.rs. The rotation is a quaternion with w last.
+layout(location=13) in vec3 i_position;
+layout(location=14) in vec4 i_rotation;
+layout(location=15) in vec3 i_scale;
 
 layout(location=0) out vec2 v_tex_coords;
 layout(location=1) out vec3 v_position;
 layout(location=2) out vec3 v_light_position;
 layout(location=3) out vec3 v_view_position;
+layout(location=4) out vec4 v_shadow_position;
+layout(location=5) out vec3 v_world_position;
+#ifdef HAS_VERTEX_COLOR
+layout(location=6) out vec4 v_color;
+#endif
+// From world to tangent space, for the point lights.
+layout(location=7) out mat3 v_tangent_matrix;
 
 layout(set=1, binding=0)
 uniform Uniforms {
     vec3 u_view_position;
     mat4 u_view_proj;
+    // xyz: corners of the section box, w of the minimum: whether it clips
+    vec4 u_section_min;
+    vec4 u_section_max;
 };
 
 layout(set=2, binding=0)
@@ -28,21 +47,74 @@ uniform Light {
     vec4 light_color;
 };
 
+layout(set=3, binding=0)
+uniform Shadow {
+    mat4 shadow_view_proj;
+    vec4 shadow_params;
+};
+layout(set=3, binding=3)
+uniform Object {
+    // z: the mesh's first joint matrix, w: whether it has any
+    vec4 object_flags;
+    vec4 display_color;
+    // Where the mesh is drawn, see scene_graph.rs.
+    mat4 model_matrix;
+};
+#ifdef SKINNED
+// The joint matrices of every skinned mesh, see skin.rs.
+layout(set=3, binding=4)
+uniform Joints {
+    mat4 joint_matrices[256];
+};
+#endif
+
+// The vector turned by the instance's rotation.
+vec3 rotate(vec3 v) {
+    vec3 u = i_rotation.xyz;
+    return v + 2.0 * cross(u, cross(u, v) + i_rotation.w * v);
+}
+
+mat4 instance_matrix() {
+    return mat4(
+        vec4(rotate(vec3(i_scale.x, 0.0, 0.0)), 0.0),
+        vec4(rotate(vec3(0.0, i_scale.y, 0.0)), 0.0),
+        vec4(rotate(vec3(0.0, 0.0, i_scale.z)), 0.0),
+        vec4(i_position, 1.0)
+    );
+}
+
 void main() {
-    //mat4 model_matrix = mat4(model_matrix1,model_matrix2,model_matrix3,model_matrix4);
-    mat4 model_matrix = mat4(1,0,0,0, 0,1,0,0, 0,0,1,0 ,0,0,0,1);
     v_tex_coords = a_tex_coords;
 
-    mat3 normal_matrix = mat3(transpose(inverse(model_matrix)));
+    // The instance is placed in the model's space, before the model's own transform.
+    mat4 world_matrix = model_matrix * instance_matrix();
+#ifdef SKINNED
+    // Moved by its joints from where it was bound, before the instance and model matrices.
+    if (object_flags.w != 0.0) {
+        int first = int(object_flags.z);
+        world_matrix *= a_weights.x * joint_matrices[first + int(a_joints.x)]
+            + a_weights.y * joint_matrices[first + int(a_joints.y)]
+            + a_weights.z * joint_matrices[first + int(a_joints.z)]
+            + a_weights.w * joint_matrices[first + int(a_joints.w)];
+    }
+#endif
+    mat3 normal_matrix = mat3(transpose(inverse(world_matrix)));
     vec3 normal = normalize(normal_matrix * a_normal);
     vec3 tangent = normalize(normal_matrix * a_tangent);
     vec3 bitangent = normalize(normal_matrix * a_bitangent);
 
     mat3 tangent_matrix = transpose(mat3(tangent, bitangent, normal));
-    vec4 model_space = model_matrix * vec4(a_position, 1.);
+    vec4 model_space = world_matrix * vec4(a_position, 1.);
 
     v_position = tangent_matrix * model_space.xyz;
     v_light_position = tangent_matrix * light_position.xyz;
     v_view_position = tangent_matrix * u_view_position;
+    // Pushed off the surface along its normal, so it doesn't shadow itself.
+    v_shadow_position = shadow_view_proj * (model_space + vec4(normal * shadow_params.z, 0.0));
+    v_world_position = model_space.xyz;
+    v_tangent_matrix = tangent_matrix;
+#ifdef HAS_VERTEX_COLOR
+    v_color = a_color;
+#endif
     gl_Position = u_view_proj * model_space;
 }
\ No newline at end of file
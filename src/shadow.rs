@@ -0,0 +1,828 @@
+use std::collections::HashMap;
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3};
+
+use crate::{
+    bounds::Aabb,
+    camera::OPENGL_TO_WGPU_MATRIX,
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    model::{Model, ModelVertex, Vertex},
+    skin::MAX_JOINTS,
+    vertex_layout::VertexLayout,
+};
+
+/// Light view projection, then whether anything casts shadows, the depth bias, the normal
+/// offset and the filter radius.
+type ShadowUniform = [[f32; 4]; 5];
+/// Whether the mesh receives shadows, whether it is lit, its first joint matrix and whether it
+/// has any, then its display color with w set while it replaces the textures, then its model
+/// matrix.
+type ObjectUniform = [[f32; 4]; 6];
+
+/// What the forward and shadow passes need to know about one mesh.
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectFlags {
+    pub receives_shadows: bool,
+    /// Whether the lights shade the mesh, or its colors are drawn unchanged.
+    pub lit: bool,
+    /// Flat color drawn instead of the textures.
+    pub display_color: Option<[f32; 3]>,
+    /// Moves the vertices to where the mesh is drawn.
+    pub model_matrix: Matrix4<f32>,
+    /// Index of the mesh's first joint matrix, for skinned meshes.
+    pub first_joint: Option<u32>,
+}
+
+impl Default for ObjectFlags {
+    fn default() -> Self {
+        Self {
+            receives_shadows: false,
+            lit: true,
+            display_color: None,
+            model_matrix: Matrix4::identity(),
+            first_joint: None,
+        }
+    }
+}
+
+/// How a light's shadows are drawn, tuned to get rid of acne without detaching the shadows
+/// from their casters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    /// Width and height of the depth map.
+    pub size: u32,
+    /// Depth subtracted from a fragment before comparing it to the map.
+    pub bias: f32,
+    /// How far fragments are pushed along their normal before looking them up, in texels.
+    pub normal_bias: f32,
+    /// Texels filtered on each side of a fragment, 0 for a single lookup.
+    pub pcf_radius: u32,
+    /// Whether the map keeps its extent while the scene grows and shrinks within it and
+    /// moves by whole texels, so shadow edges stay put instead of shimmering.
+    pub stabilize: bool,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            size: ShadowMap::SIZE,
+            bias: ShadowMap::DEPTH_BIAS,
+            normal_bias: 0.,
+            pcf_radius: 0,
+            stabilize: true,
+        }
+    }
+}
+
+/// Copies the depth map to a color texture the GUI can show.
+#[derive(Debug)]
+struct ShadowDebug {
+    texture: Tracked<wgpu::Texture>,
+    view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowDebug {
+    /// Width and height of the copy, whatever the size of the map.
+    const SIZE: u32 = 256;
+    /// The format egui takes textures in.
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    fn new(device: &wgpu::Device, shadow_view: &wgpu::TextureView) -> Self {
+        let texture = device.create_tracked_texture(GpuCategory::Targets, &wgpu::TextureDescriptor {
+            label: Some("Shadow Debug Texture"),
+            size: wgpu::Extent3d {
+                width: Self::SIZE,
+                height: Self::SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Debug Sampler"),
+            ..Default::default()
+        });
+        // Depth maps can be read as unfilterable floats, which gives the raw depth instead of
+        // a comparison.
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shadow_debug_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, shadow_view, &sampler);
+
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!("blit.vert.spv"));
+        let fs_module =
+            device.create_shader_module(&wgpu::include_spirv!("shadow_debug.frag.spv"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Debug Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Debug Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &vs_module,
+                entry_point: "main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_module,
+                entry_point: "main",
+                targets: &[Self::FORMAT.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+        });
+        Self {
+            texture,
+            view,
+            sampler,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        shadow_view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow_debug_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(shadow_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    fn draw(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Debug Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: &self.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+/// Depth map of the shadow casters seen from the light, fitted around the scene every update.
+/// Meshes read it through the bind group at set 3, with a dynamic offset selecting their
+/// object flags, and skinned meshes their joint matrices.
+#[derive(Debug)]
+pub struct ShadowMap {
+    /// Width and height of the depth map.
+    size: u32,
+    view: Tracked<wgpu::TextureView>,
+    sampler: wgpu::Sampler,
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    object_buffer: Tracked<wgpu::Buffer>,
+    /// The joint matrices of all skinned meshes, see skin.rs.
+    joint_buffer: Tracked<wgpu::Buffer>,
+    /// Number of meshes the object buffer has room for.
+    capacity: usize,
+    /// Index of the first mesh of each model in the object buffer.
+    first_objects: Vec<usize>,
+    /// Index of the flags of the comparison view's other side, after the ones of every mesh.
+    compared: Option<usize>,
+    pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    skinned_module: wgpu::ShaderModule,
+    /// Pipelines of the skinned meshes, also reading their joints and weights from the second
+    /// vertex buffer, by the key of its layout.
+    skinned_pipelines: HashMap<String, wgpu::RenderPipeline>,
+    pass_bind_group_layout: wgpu::BindGroupLayout,
+    pass_bind_group: wgpu::BindGroup,
+    /// Whether every shadow pass also copies the map for the GUI to show.
+    pub debug_view: bool,
+    debug: Option<ShadowDebug>,
+    /// The copy once the GUI knows it.
+    pub debug_texture_id: Option<egui::TextureId>,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMap {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    /// Size of the map unless the render config says otherwise.
+    pub const SIZE: u32 = 2048;
+    /// Distance between the object flags of consecutive meshes, the minimum uniform buffer
+    /// offset alignment.
+    const OBJECT_STRIDE: usize = 256;
+    pub const DEPTH_BIAS: f32 = 0.0005;
+
+    pub fn new(device: &wgpu::Device, size: u32) -> Self {
+        let size = size.max(1);
+        let view = Self::create_view(device, size);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_tracked_buffer(
+            GpuCategory::Uniforms,
+            &wgpu::BufferDescriptor {
+                label: Some("Shadow Uniform Buffer"),
+                size: std::mem::size_of::<ShadowUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+
+        let uniform_entry = |binding, visibility, has_dynamic_offset| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shadow_bind_group_layout"),
+            entries: &[
+                uniform_entry(
+                    0,
+                    wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    false,
+                ),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: true,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+                uniform_entry(
+                    3,
+                    wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    true,
+                ),
+                uniform_entry(4, wgpu::ShaderStages::VERTEX, false),
+            ],
+        });
+        let pass_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("shadow_pass_bind_group_layout"),
+                entries: &[
+                    uniform_entry(0, wgpu::ShaderStages::VERTEX, false),
+                    uniform_entry(1, wgpu::ShaderStages::VERTEX, true),
+                    uniform_entry(2, wgpu::ShaderStages::VERTEX, false),
+                ],
+            });
+        let capacity = 16;
+        let object_buffer = Self::create_object_buffer(device, capacity);
+        let joint_buffer = device.create_tracked_buffer(
+            GpuCategory::Uniforms,
+            &wgpu::BufferDescriptor {
+                label: Some("Joint Buffer"),
+                size: (MAX_JOINTS * std::mem::size_of::<[[f32; 4]; 4]>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+        let pass_bind_group = Self::create_pass_bind_group(
+            device,
+            &pass_bind_group_layout,
+            &uniform_buffer,
+            &object_buffer,
+            &joint_buffer,
+        );
+
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!("shadow.vert.spv"));
+        let skinned_module =
+            device.create_shader_module(&wgpu::include_spirv!("shadow_skinned.vert.spv"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&pass_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &vs_module,
+            "Shadow Pipeline",
+            &[ModelVertex::desc()],
+        );
+
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &uniform_buffer,
+            &view,
+            &sampler,
+            &object_buffer,
+            &joint_buffer,
+        );
+        Self {
+            size,
+            view,
+            sampler,
+            uniform_buffer,
+            object_buffer,
+            joint_buffer,
+            capacity,
+            first_objects: Vec::new(),
+            compared: None,
+            pipeline,
+            pipeline_layout,
+            skinned_module,
+            skinned_pipelines: HashMap::new(),
+            pass_bind_group_layout,
+            pass_bind_group,
+            debug_view: false,
+            debug: None,
+            debug_texture_id: None,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        module: &wgpu::ShaderModule,
+        label: &str,
+        buffers: &[wgpu::VertexBufferLayout],
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module,
+                entry_point: "main",
+                buffers,
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                // Edited and cut meshes aren't always closed.
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Self::FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+        })
+    }
+
+    /// Makes the pipelines for the layouts of skinned meshes there are none for yet.
+    pub fn add_skinned_layouts<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: impl IntoIterator<Item = &'a VertexLayout>,
+    ) {
+        for layout in layouts {
+            if self.skinned_pipelines.contains_key(&layout.key()) {
+                continue;
+            }
+            let pipeline = Self::create_pipeline(
+                device,
+                &self.pipeline_layout,
+                &self.skinned_module,
+                "Skinned Shadow Pipeline",
+                &[ModelVertex::desc(), layout.desc()],
+            );
+            self.skinned_pipelines.insert(layout.key(), pipeline);
+        }
+    }
+
+    fn create_view(device: &wgpu::Device, size: u32) -> Tracked<wgpu::TextureView> {
+        let texture = device.create_tracked_texture(GpuCategory::Targets, &wgpu::TextureDescriptor {
+            label: Some("Shadow Map"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        texture.into_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Makes a new depth map if the size changed. The bind group layout stays, so the model
+    /// pipelines keep working.
+    pub fn resize(&mut self, device: &wgpu::Device, size: u32) {
+        let size = size.max(1);
+        if size == self.size {
+            return;
+        }
+        self.size = size;
+        self.view = Self::create_view(device, size);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            &self.view,
+            &self.sampler,
+            &self.object_buffer,
+            &self.joint_buffer,
+        );
+        if let Some(debug) = &mut self.debug {
+            debug.bind_group = ShadowDebug::create_bind_group(
+                device,
+                &debug.bind_group_layout,
+                &self.view,
+                &debug.sampler,
+            );
+        }
+    }
+
+    /// The copy of the map shown by the debug view, made once the view is turned on.
+    pub fn debug_texture(&self) -> Option<&wgpu::Texture> {
+        self.debug.as_ref().map(|debug| &*debug.texture)
+    }
+
+    fn create_object_buffer(device: &wgpu::Device, capacity: usize) -> Tracked<wgpu::Buffer> {
+        device.create_tracked_buffer(GpuCategory::Uniforms, &wgpu::BufferDescriptor {
+            label: Some("Shadow Object Buffer"),
+            size: (capacity * Self::OBJECT_STRIDE) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        object_buffer: &wgpu::Buffer,
+        joint_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: Self::object_binding(object_buffer),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: joint_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// The shadow pass reads the same uniform, the model matrices of the object buffer and the
+    /// joint matrices.
+    fn create_pass_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        object_buffer: &wgpu::Buffer,
+        joint_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow_pass_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: Self::object_binding(object_buffer),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: joint_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// One mesh's entry of the object buffer, picked by the dynamic offset.
+    fn object_binding(object_buffer: &wgpu::Buffer) -> wgpu::BindingResource<'_> {
+        let size = std::mem::size_of::<ObjectUniform>() as wgpu::BufferAddress;
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: object_buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new(size),
+        })
+    }
+
+    /// Half the width of the area the map covers around the bounds. Stabilized, it is
+    /// rounded up to a power of two so it only changes when the scene outgrows it.
+    fn radius(bounds: &Aabb, stabilize: bool) -> f32 {
+        let radius = (bounds.size().magnitude() * 0.5).max(0.001);
+        match stabilize {
+            true => 2f32.powi(radius.log2().ceil() as i32),
+            false => radius,
+        }
+    }
+
+    /// Orthographic projection from the light's direction covering the bounds.
+    fn light_view_proj(
+        &self,
+        light: Point3<f32>,
+        settings: &ShadowSettings,
+        bounds: &Aabb,
+    ) -> Matrix4<f32> {
+        let center = bounds.center();
+        let radius = Self::radius(bounds, settings.stabilize);
+        let direction = (light - center).normalize();
+        let direction = if direction.x.is_finite() {
+            direction
+        } else {
+            Vector3::unit_y()
+        };
+        let up = if direction.y.abs() > 0.99 {
+            Vector3::unit_z()
+        } else {
+            Vector3::unit_y()
+        };
+        if !settings.stabilize {
+            let view = Matrix4::look_at_rh(center + direction * radius * 2., center, up);
+            let projection =
+                cgmath::ortho(-radius, radius, -radius, radius, radius * 0.5, radius * 3.5);
+            return OPENGL_TO_WGPU_MATRIX * projection * view;
+        }
+        // Looking from the scene origin rather than the center, the center can be snapped to
+        // whole texels of the light's view.
+        let view = Matrix4::look_at_rh(Point3::origin() + direction, Point3::origin(), up);
+        let texel = radius * 2. / self.size as f32;
+        let snap = |x: f32| (x / texel).round() * texel;
+        let center = view.transform_point(center);
+        let (x, y, z) = (snap(center.x), snap(center.y), snap(center.z));
+        let projection = cgmath::ortho(
+            x - radius,
+            x + radius,
+            y - radius,
+            y + radius,
+            -z - radius * 1.5,
+            -z + radius * 1.5,
+        );
+        OPENGL_TO_WGPU_MATRIX * projection * view
+    }
+
+    /// Fits the shadow map around `bounds` as seen from the light and uploads the flags of
+    /// each model's meshes, by index, and the joint matrices of the skinned ones. Empty bounds
+    /// turn shadows off. `compared` are the flags the other side of the comparison view draws
+    /// the meshes with, if it is on.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        light: Point3<f32>,
+        settings: &ShadowSettings,
+        bounds: &Aabb,
+        objects: &[Vec<ObjectFlags>],
+        compared: Option<&[Vec<ObjectFlags>]>,
+        joints: &[Matrix4<f32>],
+    ) {
+        if self.debug_view && self.debug.is_none() {
+            self.debug = Some(ShadowDebug::new(device, &self.view));
+        }
+        let view_proj: [[f32; 4]; 4] = if bounds.is_empty() {
+            Matrix4::identity().into()
+        } else {
+            self.light_view_proj(light, settings, bounds).into()
+        };
+        let mut uniform: ShadowUniform = [[0.; 4]; 5];
+        uniform[..4].copy_from_slice(&view_proj);
+        let texel = Self::radius(bounds, settings.stabilize) * 2. / self.size as f32;
+        uniform[4] = [
+            if bounds.is_empty() { 0. } else { 1. },
+            settings.bias,
+            settings.normal_bias * texel,
+            settings.pcf_radius as f32,
+        ];
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&uniform));
+
+        self.first_objects.clear();
+        let mut count = 0;
+        for meshes in objects {
+            self.first_objects.push(count);
+            count += meshes.len();
+        }
+        self.compared = compared.map(|_| count);
+        let compared = compared.unwrap_or(&[]);
+        let count = count + compared.iter().map(Vec::len).sum::<usize>();
+        if count > self.capacity {
+            self.capacity = count.next_power_of_two();
+            self.object_buffer = Self::create_object_buffer(device, self.capacity);
+            self.bind_group = Self::create_bind_group(
+                device,
+                &self.bind_group_layout,
+                &self.uniform_buffer,
+                &self.view,
+                &self.sampler,
+                &self.object_buffer,
+                &self.joint_buffer,
+            );
+            self.pass_bind_group = Self::create_pass_bind_group(
+                device,
+                &self.pass_bind_group_layout,
+                &self.uniform_buffer,
+                &self.object_buffer,
+                &self.joint_buffer,
+            );
+        }
+        let mut data = vec![0u8; count * Self::OBJECT_STRIDE];
+        for (i, flags) in objects.iter().chain(compared).flatten().enumerate() {
+            let ([r, g, b], replace) = match flags.display_color {
+                Some(color) => (color, 1.),
+                None => ([0.; 3], 0.),
+            };
+            let matrix: [[f32; 4]; 4] = flags.model_matrix.into();
+            let (first_joint, skinned) = match flags.first_joint {
+                Some(first) => (first as f32, 1.),
+                None => (0., 0.),
+            };
+            let object: ObjectUniform = [
+                [
+                    flags.receives_shadows as u32 as f32,
+                    flags.lit as u32 as f32,
+                    first_joint,
+                    skinned,
+                ],
+                [r, g, b, replace],
+                matrix[0],
+                matrix[1],
+                matrix[2],
+                matrix[3],
+            ];
+            let start = i * Self::OBJECT_STRIDE;
+            data[start..start + std::mem::size_of::<ObjectUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&object));
+        }
+        if !data.is_empty() {
+            queue.write_buffer(&self.object_buffer, 0, &data);
+        }
+        let joints = joints
+            .iter()
+            .take(MAX_JOINTS)
+            .map(|&matrix| matrix.into())
+            .collect::<Vec<[[f32; 4]; 4]>>();
+        if !joints.is_empty() {
+            queue.write_buffer(&self.joint_buffer, 0, bytemuck::cast_slice(&joints));
+        }
+    }
+
+    /// Dynamic offset of the flags of a model's mesh in the bind group.
+    pub fn object_offset(&self, model: usize, mesh: usize) -> wgpu::DynamicOffset {
+        let object = self
+            .first_objects
+            .get(model)
+            .map_or(0, |first| first + mesh);
+        (object.min(self.capacity - 1) * Self::OBJECT_STRIDE) as wgpu::DynamicOffset
+    }
+
+    /// Like `object_offset`, for the flags of the comparison view's other side.
+    pub fn compared_offset(&self, model: usize, mesh: usize) -> wgpu::DynamicOffset {
+        let (first, compared) = match (self.first_objects.get(model), self.compared) {
+            (Some(first), Some(compared)) => (first, compared),
+            _ => return self.object_offset(model, mesh),
+        };
+        let object = compared + first + mesh;
+        (object.min(self.capacity - 1) * Self::OBJECT_STRIDE) as wgpu::DynamicOffset
+    }
+
+    /// Renders the casters, by index, into the shadow map, then copies it for the debug view if
+    /// it is on.
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, casters: &[(usize, &Model)]) {
+        profile_function!();
+        self.draw_casters(encoder, casters);
+        if let Some(debug) = self.debug.as_ref().filter(|_| self.debug_view) {
+            debug.draw(encoder);
+        }
+    }
+
+    fn draw_casters(&self, encoder: &mut wgpu::CommandEncoder, casters: &[(usize, &Model)]) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        for &(index, model) in casters {
+            for (i, mesh) in model.meshes().iter().enumerate() {
+                let offset = self.object_offset(index, i);
+                pass.set_bind_group(0, &self.pass_bind_group, &[offset]);
+                let skinned = mesh.extra.as_ref().and_then(|extra| {
+                    let pipeline = self.skinned_pipelines.get(&extra.data.layout.key())?;
+                    Some((pipeline, extra))
+                });
+                match skinned {
+                    Some((pipeline, extra)) => {
+                        pass.set_pipeline(pipeline);
+                        pass.set_vertex_buffer(1, extra.buffer.slice(..));
+                    }
+                    None => pass.set_pipeline(&self.pipeline),
+                }
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+#version 450
+
+// Depth only, the rest of the vertex is ignored.
+layout(location=0) in vec3 a_position;
+#ifdef SKINNED
+layout(location=7) in vec4 a_joints;
+layout(location=8) in vec4 a_weights;
+#endif
+
+layout(set=0, binding=0)
+uniform Shadow {
+    mat4 shadow_view_proj;
+    vec4 shadow_params;
+};
+layout(set=0, binding=1)
+uniform Object {
+    vec4 object_flags;
+    vec4 display_color;
+    mat4 model_matrix;
+};
+#ifdef SKINNED
+// The joint matrices of every skinned mesh, see skin.rs.
+layout(set=0, binding=2)
+uniform Joints {
+    mat4 joint_matrices[256];
+};
+#endif
+
+void main() {
+    mat4 world_matrix = model_matrix;
+#ifdef SKINNED
+    // Moved by its joints like in shader.vert, so the shadow follows the pose.
+    if (object_flags.w != 0.0) {
+        int first = int(object_flags.z);
+        world_matrix *= a_weights.x * joint_matrices[first + int(a_joints.x)]
+            + a_weights.y * joint_matrices[first + int(a_joints.y)]
+            + a_weights.z * joint_matrices[first + int(a_joints.z)]
+            + a_weights.w * joint_matrices[first + int(a_joints.w)];
+    }
+#endif
+    gl_Position = shadow_view_proj * world_matrix * vec4(a_position, 1.0);
+}
@@ -0,0 +1,13 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0) uniform texture2D t_shadow;
+layout(set=0, binding=1) uniform sampler s_shadow;
+
+// The raw depth as gray, nearer casters darker.
+void main() {
+    float depth = texture(sampler2D(t_shadow, s_shadow), v_tex_coords).r;
+    f_color = vec4(vec3(depth), 1.0);
+}
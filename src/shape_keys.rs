@@ -0,0 +1,523 @@
+use std::{fs::File, io::Write, path::Path};
+
+use anyhow::*;
+use cgmath::{InnerSpace, Matrix4, Vector3};
+
+use crate::{
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    model::{transform_vertices, Mesh, Model, ModelVertex},
+};
+
+/// A stored variation of a model, as offsets from its basis.
+#[derive(Debug, Clone)]
+pub struct ShapeKey {
+    pub name: String,
+    /// From 0 to 1.
+    pub weight: f32,
+    /// Position and normal offset of every vertex, per mesh.
+    offsets: Vec<Vec<[Vector3<f32>; 2]>>,
+}
+
+/// Compute pipeline blending the keys of one mesh into a copy of its vertices, which is then
+/// copied into the vertex buffer.
+#[derive(Debug)]
+struct GpuBlend {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    weights: Tracked<wgpu::Buffer>,
+    blended: Tracked<wgpu::Buffer>,
+    /// Number of keys the offsets were uploaded for.
+    keys: usize,
+}
+
+/// Shape keys of one model, blended onto the shape it had when they were added. Keys only
+/// match meshes with the vertices of the basis, so they go when the topology changes.
+#[derive(Debug)]
+pub struct ShapeKeys {
+    pub model: usize,
+    pub keys: Vec<ShapeKey>,
+    /// Blends on the GPU instead of the CPU. Only the vertex buffers are blended then, the CPU
+    /// copies used for picking and editing keep the shape of the last CPU blend.
+    pub gpu: bool,
+    basis: Vec<Vec<ModelVertex>>,
+    blends: Vec<Option<GpuBlend>>,
+    dirty: bool,
+}
+
+impl ShapeKeys {
+    const STRIDE: usize = std::mem::size_of::<ModelVertex>() / std::mem::size_of::<f32>();
+    const WORKGROUP_SIZE: u32 = 64;
+
+    pub fn new(index: usize, model: &Model) -> Self {
+        Self {
+            model: index,
+            keys: Vec::new(),
+            gpu: false,
+            basis: model.meshes().iter().map(|m| m.vertices.clone()).collect(),
+            blends: model.meshes().iter().map(|_| None).collect(),
+            dirty: false,
+        }
+    }
+
+    /// Whether the model still has the vertices of the basis.
+    pub fn matches(&self, model: &Model) -> bool {
+        let meshes = model.meshes();
+        meshes.len() == self.basis.len()
+            && meshes
+                .iter()
+                .zip(self.basis.iter())
+                .all(|(mesh, basis)| mesh.vertices.len() == basis.len())
+    }
+
+    /// Stores the current shape of the model as a key, showing only it.
+    pub fn store(&mut self, name: String, model: &Model) -> Result<()> {
+        if !self.matches(model) {
+            bail!("The model's vertices changed since its shape keys were added");
+        }
+        let offsets = model
+            .meshes()
+            .iter()
+            .zip(self.basis.iter())
+            .map(|(mesh, basis)| {
+                mesh.vertices
+                    .iter()
+                    .zip(basis.iter())
+                    .map(|(v, b)| {
+                        [
+                            Vector3::from(v.position) - Vector3::from(b.position),
+                            Vector3::from(v.normal) - Vector3::from(b.normal),
+                        ]
+                    })
+                    .collect()
+            })
+            .collect();
+        for key in self.keys.iter_mut() {
+            key.weight = 0.;
+        }
+        self.keys.push(ShapeKey {
+            name,
+            weight: 1.,
+            offsets,
+        });
+        self.blends = self.blends.iter().map(|_| None).collect();
+        Ok(())
+    }
+
+    /// Moves the basis and every key by the matrix, after the model itself was moved by it.
+    pub fn transform(&mut self, matrix: Matrix4<f32>) {
+        for (mesh, basis) in self.basis.iter_mut().enumerate() {
+            let mut moved = basis.clone();
+            transform_vertices(&mut moved, matrix);
+            for key in self.keys.iter_mut() {
+                let mut shape = basis
+                    .iter()
+                    .zip(key.offsets[mesh].iter())
+                    .map(|(b, [dp, dn])| ModelVertex {
+                        position: (Vector3::from(b.position) + dp).into(),
+                        normal: (Vector3::from(b.normal) + dn).into(),
+                        ..*b
+                    })
+                    .collect::<Vec<_>>();
+                transform_vertices(&mut shape, matrix);
+                for ((offset, v), b) in key.offsets[mesh].iter_mut().zip(shape).zip(moved.iter()) {
+                    *offset = [
+                        Vector3::from(v.position) - Vector3::from(b.position),
+                        Vector3::from(v.normal) - Vector3::from(b.normal),
+                    ];
+                }
+            }
+            *basis = moved;
+        }
+        self.blends = self.blends.iter().map(|_| None).collect();
+        self.dirty = true;
+    }
+
+    /// Gives vertices of the basis new normals, which every key keeps, e.g. after they were
+    /// edited by hand.
+    pub fn set_normals(&mut self, mesh: usize, normals: &[(usize, [f32; 3])]) {
+        let basis = match self.basis.get_mut(mesh) {
+            Some(basis) => basis,
+            None => return,
+        };
+        for &(i, normal) in normals {
+            if let Some(vertex) = basis.get_mut(i) {
+                vertex.normal = normal;
+            }
+            for key in self.keys.iter_mut() {
+                if let Some(offset) = key.offsets[mesh].get_mut(i) {
+                    offset[1] = Vector3::new(0., 0., 0.);
+                }
+            }
+        }
+        self.blends = self.blends.iter().map(|_| None).collect();
+        self.dirty = true;
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        self.keys.remove(index);
+        self.blends = self.blends.iter().map(|_| None).collect();
+        self.dirty = true;
+    }
+
+    /// Call after changing weights so the model is blended again on the next update.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Sets every weight to 0, showing the basis, e.g. to edit the next key from it.
+    pub fn reset(&mut self) {
+        for key in self.keys.iter_mut() {
+            key.weight = 0.;
+        }
+        self.dirty = true;
+    }
+
+    /// The basis of the mesh with the weighted offsets of every key added.
+    pub fn blend(&self, mesh: usize) -> Vec<ModelVertex> {
+        self.basis[mesh]
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let (position, normal) = self.keys.iter().filter(|key| key.weight != 0.).fold(
+                    (Vector3::from(v.position), Vector3::from(v.normal)),
+                    |(p, n), key| {
+                        let [dp, dn] = key.offsets[mesh][i];
+                        (p + dp * key.weight, n + dn * key.weight)
+                    },
+                );
+                let normal = if normal.magnitude2() > 0. {
+                    normal.normalize()
+                } else {
+                    normal
+                };
+                ModelVertex {
+                    position: position.into(),
+                    normal: normal.into(),
+                    ..*v
+                }
+            })
+            .collect()
+    }
+
+    /// Blends the model's meshes if the weights changed since the last call. Returns whether
+    /// they were.
+    pub fn apply(&mut self, model: &mut Model, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        if !self.dirty || !self.matches(model) {
+            return false;
+        }
+        self.dirty = false;
+        if !self.gpu || self.keys.is_empty() {
+            for (i, mesh) in model.meshes_mut().iter_mut().enumerate() {
+                let vertices = self.blend(i);
+                mesh.upload_vertices(queue, &vertices);
+            }
+            return true;
+        }
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Shape Key Encoder"),
+        });
+        for (i, mesh) in model.meshes().iter().enumerate() {
+            if mesh.vertices.is_empty() {
+                continue;
+            }
+            if self.blends[i].as_ref().map(|b| b.keys) != Some(self.keys.len()) {
+                self.blends[i] = Some(self.create_blend(device, i));
+            }
+            let blend = self.blends[i].as_ref().unwrap();
+            let mut header = vec![mesh.vertices.len() as u32, self.keys.len() as u32];
+            header.extend(self.keys.iter().map(|key| key.weight.to_bits()));
+            queue.write_buffer(&blend.weights, 0, bytemuck::cast_slice(&header));
+            {
+                let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("Shape Key Pass"),
+                });
+                pass.set_pipeline(&blend.pipeline);
+                pass.set_bind_group(0, &blend.bind_group, &[]);
+                let count = mesh.vertices.len() as u32;
+                pass.dispatch(
+                    (count + Self::WORKGROUP_SIZE - 1) / Self::WORKGROUP_SIZE,
+                    1,
+                    1,
+                );
+            }
+            let size = (mesh.vertices.len() * std::mem::size_of::<ModelVertex>()) as u64;
+            encoder.copy_buffer_to_buffer(&blend.blended, 0, &mesh.vertex_buffer, 0, size);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        true
+    }
+
+    fn create_blend(&self, device: &wgpu::Device, mesh: usize) -> GpuBlend {
+        let basis = &self.basis[mesh];
+        let offsets = self
+            .keys
+            .iter()
+            .flat_map(|key| key.offsets[mesh].iter())
+            .flat_map(|[dp, dn]| [dp.x, dp.y, dp.z, dn.x, dn.y, dn.z])
+            .collect::<Vec<_>>();
+        let storage = |label, contents: &[u8], usage| {
+            device.create_tracked_buffer_init(
+                GpuCategory::Meshes,
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(label),
+                    contents,
+                    usage: wgpu::BufferUsages::STORAGE | usage,
+                },
+            )
+        };
+        let basis_buffer = storage(
+            "Shape Key Basis",
+            bytemuck::cast_slice(basis),
+            wgpu::BufferUsages::empty(),
+        );
+        let offset_buffer = storage(
+            "Shape Key Offsets",
+            bytemuck::cast_slice(&offsets),
+            wgpu::BufferUsages::empty(),
+        );
+        let weights = storage(
+            "Shape Key Weights",
+            bytemuck::cast_slice(&vec![0u32; 2 + self.keys.len()]),
+            wgpu::BufferUsages::COPY_DST,
+        );
+        let blended = device.create_tracked_buffer(GpuCategory::Meshes, &wgpu::BufferDescriptor {
+            label: Some("Shape Key Blended"),
+            size: (basis.len() * Self::STRIDE * std::mem::size_of::<f32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shape Key Bind Group Layout"),
+            entries: &[
+                entry(0, true),
+                entry(1, true),
+                entry(2, true),
+                entry(3, false),
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shape Key Bind Group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: basis_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: offset_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: weights.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: blended.as_entire_binding(),
+                },
+            ],
+        });
+        let module = device.create_shader_module(&wgpu::include_spirv!("shape_keys.comp.spv"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shape Key Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Shape Key Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "main",
+        });
+        GpuBlend {
+            pipeline,
+            bind_group,
+            weights,
+            blended,
+            keys: self.keys.len(),
+        }
+    }
+
+    /// Writes the basis as a binary glTF file, with the keys as morph targets of every mesh.
+    pub fn export_gltf<P: AsRef<Path>>(&self, model: &Model, path: P) -> Result<()> {
+        if !self.matches(model) {
+            bail!("The model's vertices changed since its shape keys were added");
+        }
+        let mut gltf = GltfWriter::default();
+        let primitives = model
+            .meshes()
+            .iter()
+            .zip(self.basis.iter())
+            .enumerate()
+            .map(|(i, (mesh, basis))| self.write_primitive(&mut gltf, i, mesh, basis))
+            .collect::<Vec<_>>();
+        let names = self.keys.iter().map(|key| &key.name).collect::<Vec<_>>();
+        let weights = self.keys.iter().map(|key| key.weight).collect::<Vec<_>>();
+        let json = serde_json::json!({
+            "asset": { "version": "2.0", "generator": "modeling" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{
+                "primitives": primitives,
+                "weights": weights,
+                "extras": { "targetNames": names },
+            }],
+            "buffers": [{ "byteLength": gltf.binary.len() }],
+            "bufferViews": gltf.views,
+            "accessors": gltf.accessors,
+        });
+        let file = File::create(path.as_ref())
+            .with_context(|| format!("Failed to create {}", path.as_ref().display()))?;
+        write_glb(file, &serde_json::to_vec(&json)?, &gltf.binary)
+    }
+
+    fn write_primitive(
+        &self,
+        gltf: &mut GltfWriter,
+        mesh: usize,
+        source: &Mesh,
+        basis: &[ModelVertex],
+    ) -> serde_json::Value {
+        let positions = basis.iter().map(|v| v.position).collect::<Vec<_>>();
+        let normals = basis.iter().map(|v| v.normal).collect::<Vec<_>>();
+        let tex_coords = basis.iter().map(|v| v.tex_coords).collect::<Vec<_>>();
+        let position = gltf.vec3s(&positions, true);
+        let normal = gltf.vec3s(&normals, false);
+        let tex_coord = gltf.floats(bytemuck::cast_slice(&tex_coords), "VEC2");
+        let indices = gltf.indices(&source.indices);
+        let targets = self
+            .keys
+            .iter()
+            .map(|key| {
+                let offsets = &key.offsets[mesh];
+                let positions = offsets.iter().map(|[p, _]| (*p).into()).collect::<Vec<_>>();
+                let normals = offsets.iter().map(|[_, n]| (*n).into()).collect::<Vec<_>>();
+                serde_json::json!({
+                    "POSITION": gltf.vec3s(&positions, true),
+                    "NORMAL": gltf.vec3s(&normals, false),
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut primitive = serde_json::json!({
+            "attributes": {
+                "POSITION": position,
+                "NORMAL": normal,
+                "TEXCOORD_0": tex_coord,
+            },
+            "indices": indices,
+            "mode": 4,
+        });
+        if !targets.is_empty() {
+            primitive["targets"] = targets.into();
+        }
+        primitive
+    }
+}
+
+/// Binary buffer, buffer views and accessors of a glTF file being written, one view per
+/// accessor.
+#[derive(Debug, Default)]
+struct GltfWriter {
+    binary: Vec<u8>,
+    views: Vec<serde_json::Value>,
+    accessors: Vec<serde_json::Value>,
+}
+
+impl GltfWriter {
+    const FLOAT: u32 = 5126;
+    const UNSIGNED_INT: u32 = 5125;
+    const ARRAY_BUFFER: u32 = 34962;
+    const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+    /// Appends the data as a buffer view, returning its index.
+    fn view(&mut self, data: &[u8], target: u32) -> usize {
+        while self.binary.len() % 4 != 0 {
+            self.binary.push(0);
+        }
+        self.views.push(serde_json::json!({
+            "buffer": 0,
+            "byteOffset": self.binary.len(),
+            "byteLength": data.len(),
+            "target": target,
+        }));
+        self.binary.extend_from_slice(data);
+        self.views.len() - 1
+    }
+
+    fn accessor(&mut self, accessor: serde_json::Value) -> usize {
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn floats(&mut self, data: &[f32], kind: &str) -> usize {
+        let components = if kind == "VEC2" { 2 } else { 3 };
+        let view = self.view(bytemuck::cast_slice(data), Self::ARRAY_BUFFER);
+        self.accessor(serde_json::json!({
+            "bufferView": view,
+            "componentType": Self::FLOAT,
+            "count": data.len() / components,
+            "type": kind,
+        }))
+    }
+
+    /// Positions need their bounds in the accessor.
+    fn vec3s(&mut self, data: &[[f32; 3]], bounds: bool) -> usize {
+        let accessor = self.floats(bytemuck::cast_slice(data), "VEC3");
+        if bounds && !data.is_empty() {
+            let (mut min, mut max) = (data[0], data[0]);
+            for p in data {
+                for i in 0..3 {
+                    min[i] = min[i].min(p[i]);
+                    max[i] = max[i].max(p[i]);
+                }
+            }
+            self.accessors[accessor]["min"] = min.to_vec().into();
+            self.accessors[accessor]["max"] = max.to_vec().into();
+        }
+        accessor
+    }
+
+    fn indices(&mut self, indices: &[u32]) -> usize {
+        let view = self.view(bytemuck::cast_slice(indices), Self::ELEMENT_ARRAY_BUFFER);
+        self.accessor(serde_json::json!({
+            "bufferView": view,
+            "componentType": Self::UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }))
+    }
+}
+
+/// Writes a GLB container: the header, then the JSON and binary chunks, each padded to four
+/// bytes.
+fn write_glb<W: Write>(mut writer: W, json: &[u8], binary: &[u8]) -> Result<()> {
+    const MAGIC: u32 = 0x4654_6c67;
+    const JSON: u32 = 0x4e4f_534a;
+    const BIN: u32 = 0x004e_4942;
+    let padded = |len: usize| (len + 3) / 4 * 4;
+    let length = 12 + 8 + padded(json.len()) + 8 + padded(binary.len());
+    let mut bytes = Vec::with_capacity(length);
+    for word in [MAGIC, 2, length as u32] {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    for (kind, data, pad) in [(JSON, json, b' '), (BIN, binary, 0)] {
+        bytes.extend_from_slice(&(padded(data.len()) as u32).to_le_bytes());
+        bytes.extend_from_slice(&kind.to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes.resize(bytes.len() + padded(data.len()) - data.len(), pad);
+    }
+    writer.write_all(&bytes)?;
+    Ok(())
+}
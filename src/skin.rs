@@ -0,0 +1,152 @@
+//! Skinned meshes of glTF characters, bent by their joints. The joints are nodes of the scene
+//! graph, so posing or scrubbing the nodes moves them. At every update each joint gets a
+//! matrix from the mesh's bind pose to where the joint is now, and the skinned permutation of
+//! the model shader blends the matrices of a vertex's joints by its weights.
+//!
+//! The vertices of a skinned mesh stay in its bind pose. The shadow pass blends the same
+//! matrices in the skinned permutation of the shadow shader, and picking poses the vertices on
+//! the CPU, while the bounds still see the mesh as it was bound.
+
+use std::collections::HashMap;
+
+use cgmath::{Matrix4, Point3, SquareMatrix, Transform, Zero};
+
+use crate::{model::Mesh, scene::Scene, scene_graph::SceneGraph, vertex_layout::VertexAttribute};
+
+/// Joint matrices of all skinned meshes together, the length of `joint_matrices` in
+/// shader.vert. Skins past it keep their bind pose.
+pub const MAX_JOINTS: usize = 256;
+
+/// The joints bending one mesh of a model.
+#[derive(Debug, Clone)]
+pub struct Skin {
+    /// Set once the model is in the scene.
+    pub model: usize,
+    /// The mesh among the model's.
+    pub mesh: usize,
+    /// The joints by their place among the model's nodes, the model's root node coming first.
+    pub joints: Vec<usize>,
+    /// From the mesh's space to each joint's when the mesh was bound to them.
+    pub inverse_binds: Vec<Matrix4<f32>>,
+}
+
+impl Skin {
+    /// Where each joint moves the vertices bound to it. `nodes` are the model's nodes in the
+    /// scene graph, in order.
+    pub fn joint_matrices(&self, graph: &SceneGraph, nodes: &[usize]) -> Vec<Matrix4<f32>> {
+        self.joints
+            .iter()
+            .zip(self.inverse_binds.iter())
+            .map(|(&joint, inverse_bind)| match nodes.get(joint) {
+                Some(&node) => graph.world(node) * inverse_bind,
+                None => Matrix4::identity(),
+            })
+            .collect()
+    }
+}
+
+/// The joint matrices of every skinned mesh, one mesh after the other.
+#[derive(Debug, Clone, Default)]
+pub struct JointPalette {
+    pub matrices: Vec<Matrix4<f32>>,
+    /// Index of the first matrix of each skinned mesh, by model and mesh.
+    pub first: HashMap<(usize, usize), u32>,
+}
+
+impl JointPalette {
+    /// Where the joints move the vertices of a model's mesh, blended like the skinned shader
+    /// does. `None` for meshes that aren't skinned.
+    pub fn posed_positions(
+        &self,
+        model: usize,
+        index: usize,
+        mesh: &Mesh,
+    ) -> Option<Vec<Point3<f32>>> {
+        let first = *self.first.get(&(model, index))? as usize;
+        let data = &mesh.extra.as_ref()?.data;
+        let posed = mesh.vertices.iter().enumerate().map(|(v, vertex)| {
+            let position = Point3::from(vertex.position);
+            let joints = data.get(v, &VertexAttribute::Joints);
+            let weights = data.get(v, &VertexAttribute::Weights);
+            let (joints, weights) = match (joints, weights) {
+                (Some(joints), Some(weights)) => (joints, weights),
+                _ => return position,
+            };
+            let matrix = joints.iter().zip(weights).fold(
+                Matrix4::zero(),
+                |sum, (&joint, &weight)| match self.matrices.get(first + joint as usize) {
+                    Some(&matrix) => sum + matrix * weight,
+                    None => sum,
+                },
+            );
+            matrix.transform_point(position)
+        });
+        Some(posed.collect())
+    }
+}
+
+impl Scene {
+    /// Puts in the skins of a model just loaded, in place of those loaded for it before. Their
+    /// meshes are taken out of the nodes' meshes, as the joints move them instead.
+    pub(crate) fn set_skins(&mut self, model: usize, skins: Vec<Skin>) {
+        self.poses.skins.retain(|skin| skin.model != model);
+        for node in self.graph.model_nodes(model) {
+            self.graph.nodes[node]
+                .meshes
+                .retain(|mesh| skins.iter().all(|skin| skin.mesh != *mesh));
+        }
+        self.poses
+            .skins
+            .extend(skins.into_iter().map(|skin| Skin { model, ..skin }));
+    }
+
+    /// The joint matrices of the skins for where the nodes are now.
+    pub fn joint_palette(&self) -> JointPalette {
+        let mut palette = JointPalette::default();
+        for skin in self.poses.skins.iter() {
+            if palette.matrices.len() + skin.joints.len() > MAX_JOINTS {
+                continue;
+            }
+            let nodes = self.graph.model_nodes(skin.model);
+            let first = palette.matrices.len() as u32;
+            palette.first.insert((skin.model, skin.mesh), first);
+            palette
+                .matrices
+                .extend(skin.joint_matrices(&self.graph, &nodes));
+        }
+        palette
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::*;
+    use crate::scene_graph::Node;
+
+    #[test]
+    fn joint_matrices_move_from_the_bind_pose() {
+        let mut graph = SceneGraph::default();
+        let root = graph.add(Node::new("root".into(), Some(0), Vec::new()), None);
+        let joint = graph.add(Node::new("joint".into(), Some(0), Vec::new()), Some(root));
+        graph.nodes[joint].local.translation = Vector3::new(0., 1., 0.);
+        let skin = Skin {
+            model: 0,
+            mesh: 0,
+            joints: vec![1],
+            inverse_binds: vec![Matrix4::from_translation(Vector3::new(0., -1., 0.))],
+        };
+        let nodes = [root, joint];
+        assert_eq!(
+            skin.joint_matrices(&graph, &nodes),
+            vec![Matrix4::identity()]
+        );
+
+        graph.nodes[root].local.translation = Vector3::new(2., 0., 0.);
+        assert_eq!(
+            skin.joint_matrices(&graph, &nodes),
+            vec![Matrix4::from_translation(Vector3::new(2., 0., 0.))]
+        );
+    }
+}
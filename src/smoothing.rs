@@ -0,0 +1,81 @@
+use cgmath::{Vector3, Zero};
+
+use crate::{bounds::Aabb, scene::Scene, weld::Welded};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SmoothSettings {
+    pub iterations: u32,
+    /// How far each vertex moves toward the average of its neighbours per iteration.
+    pub lambda: f32,
+    /// Follows every step with an inflating one so the mesh doesn't shrink.
+    pub taubin: bool,
+    /// Factor of the inflating step, negative and slightly larger than `lambda`.
+    pub mu: f32,
+}
+
+impl Default for SmoothSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 10,
+            lambda: 0.5,
+            taubin: true,
+            mu: -0.53,
+        }
+    }
+}
+
+/// One Laplacian step: every group moves toward the average of its neighbours by `factor`
+/// times its weight. All targets are computed before anything moves.
+pub fn relax(welded: &Welded, groups: &[(u32, f32)], factor: f32) -> Vec<(u32, Vector3<f32>)> {
+    groups
+        .iter()
+        .filter(|(g, _)| !welded.neighbours[*g as usize].is_empty())
+        .map(|&(g, weight)| {
+            let neighbours = &welded.neighbours[g as usize];
+            let average = neighbours
+                .iter()
+                .map(|&n| welded.positions[n as usize])
+                .fold(Vector3::zero(), |sum, p| sum + p)
+                / neighbours.len() as f32;
+            let p = welded.positions[g as usize];
+            (g, p + (average - p) * factor * weight)
+        })
+        .collect()
+}
+
+/// Smooths the whole mesh and recomputes its normals.
+pub fn smooth(welded: &mut Welded, settings: &SmoothSettings) {
+    let groups = (0..welded.len() as u32)
+        .map(|g| (g, 1.))
+        .collect::<Vec<_>>();
+    let step = |welded: &mut Welded, factor: f32| {
+        for (g, p) in relax(welded, &groups, factor) {
+            welded.positions[g as usize] = p;
+        }
+    };
+    for _ in 0..settings.iterations {
+        step(welded, settings.lambda);
+        if settings.taubin {
+            step(welded, settings.mu);
+        }
+    }
+    for g in 0..welded.len() as u32 {
+        welded.recompute_normal(g);
+    }
+}
+
+impl Scene {
+    /// Smooths every mesh of the model. The result is uploaded on the next update.
+    pub fn smooth_model(&mut self, model: usize, settings: &SmoothSettings) {
+        // The sculpt state caches positions which are about to change.
+        self.sculpt.release_target();
+        self.edit.release_target();
+        for mesh in self.models[model].meshes_mut() {
+            let mut welded = Welded::new(&mesh.vertices, &mesh.indices);
+            smooth(&mut welded, settings);
+            let range = welded.write_groups(&mut mesh.vertices, 0..welded.len() as u32);
+            mesh.bounds = Aabb::from_points(mesh.vertices.iter().map(|v| v.position.into()));
+            mesh.mark_vertices_dirty(range);
+        }
+    }
+}
@@ -17,41 +17,119 @@ use instant::Instant;
 use cgmath::prelude::*;
 
 use crate::{
+    assets, bench,
     camera::{self, CameraController},
-    collection, gui, light,
+    capture,
+    clipboard::Clipboard,
+    collection,
+    events::{SceneEvent, Subscribers},
+    gui, light,
     model::{self, Vertex},
+    outliner,
+    permutation::ShaderStrategy,
+    picking, project,
+    recording::{self, Session},
+    render_config::RenderConfig,
     renderer::RendererExt,
-    scene, texture,
+    resolution::ScaledTarget,
+    review,
+    scene,
+    surface::SurfaceFormats,
+    texture,
 };
 
 pub struct State {
+    /// Kept to create the surface again when Android hands over a new window.
+    #[cfg_attr(not(target_os = "android"), allow(dead_code))]
+    instance: wgpu::Instance,
     surface: wgpu::Surface,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    /// Surface configuration with the render format, see `formats`.
     config: wgpu::SurfaceConfiguration,
+    formats: SurfaceFormats,
+    /// Frames are rendered here when the surface doesn't encode sRGB.
+    present_target: Option<ScaledTarget>,
+    /// Where the next frame is saved, see `capture_frame`.
+    frame_capture: Option<std::path::PathBuf>,
     size: winit::dpi::PhysicalSize<u32>,
     scene: Arc<RwLock<scene::Scene>>,
     camera_controller: camera::CameraController,
+    modifiers: winit::event::ModifiersState,
+    frame: u64,
+    /// Simulation step in seconds. `None` updates once per frame with the measured frame time.
+    pub timestep: Option<f32>,
+    accumulator: f32,
+    last_update: Option<Instant>,
+    /// When the last frame was rendered, for the dynamic resolution.
+    last_frame: Option<Instant>,
+    /// Set between `Suspended` and `Resumed`, while the surface mustn't be drawn to.
+    suspended: bool,
+    /// Whether the camera moved in the last update, e.g. still easing after input.
+    camera_moving: bool,
+    /// An event arrived while idle, so one frame has to be drawn for it.
+    wake: bool,
 
     pub gui: gui::Gui,
+    pub session: Option<recording::Session>,
+    bench: Option<bench::Benchmark>,
+    subscribers: Subscribers,
+    /// Functions of the page embedding the viewer called when the selection changes.
+    #[cfg(target_arch = "wasm32")]
+    selection_listeners: crate::web::SelectionListeners,
 }
 
 impl State {
-    pub fn handle_event<T>(
+    pub fn handle_event(
         &mut self,
-        event: &winit::event::Event<T>,
+        event: &winit::event::Event<gui::Event>,
         control_flow: &mut ControlFlow,
         window: &Window,
         start_time: Instant,
         last_update_inst: &mut Instant,
         previous_frame_time: &mut Option<f32>,
     ) {
+        let is_replaying = matches!(self.session, Some(Session::Replay(_)));
         match event {
+            RedrawRequested(_) if !self.is_visible() => {}
+            RedrawRequested(_) if is_replaying => {
+                self.replay_frame(control_flow, start_time, previous_frame_time, &window);
+            }
             RedrawRequested(_) => {
+                if self.bench.is_none() {
+                    self.record_frame_time();
+                }
                 self.render(start_time, previous_frame_time, &window);
+                if let Some(bench) = &mut self.bench {
+                    bench.record_frame(self.scene.read().unwrap().stats());
+                    if bench.is_finished() {
+                        if let Err(e) = bench.write_report() {
+                            log::error!("Failed to write benchmark report: {:?}", e);
+                        }
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+            }
+            RedrawEventsCleared if !self.is_visible() => {
+                // Nothing is drawn, only the simulation keeps going at a slow pace.
+                self.last_frame = None;
+                *control_flow = ControlFlow::WaitUntil(Instant::now() + Self::HIDDEN_FRAMETIME);
+            }
+            RedrawEventsCleared if self.is_idle() => {
+                // Waits for the next event instead of redrawing the same frame.
+                if std::mem::take(&mut self.wake) {
+                    window.request_redraw();
+                }
+                self.last_frame = None;
+                *control_flow = ControlFlow::Wait;
+            }
+            RedrawEventsCleared if self.bench.is_some() => {
+                // Benchmarks run uncapped.
+                window.request_redraw();
             }
             RedrawEventsCleared => {
-                let target_frametime = Duration::from_secs_f64(1.0 / 60.0);
+                let target_frametime = self.scene.read().unwrap().renderer.config.frame_time();
+                let target_frametime = target_frametime.unwrap_or_default();
                 let time_since_last_frame = last_update_inst.elapsed();
                 if time_since_last_frame >= target_frametime {
                     window.request_redraw();
@@ -62,14 +140,134 @@ impl State {
                     );
                 }
             }
-            MainEventsCleared => {
-                self.update();
+            MainEventsCleared if !is_replaying => {
+                self.advance();
+            }
+            Suspended => {
+                self.suspended = true;
+            }
+            UserEvent(gui::Event::RequestRedraw) => {
+                self.wake = true;
+            }
+            UserEvent(gui::Event::ToggleFullscreen) => {
+                Self::toggle_fullscreen(window);
+            }
+            UserEvent(gui::Event::CanvasResized(size)) => {
+                // winit follows up with a `Resized` event.
+                window.set_inner_size(*size);
+            }
+            UserEvent(gui::Event::LoadModel(source)) => {
+                self.scene.write().unwrap().start_load(source.clone());
+                self.wake = true;
+            }
+            UserEvent(gui::Event::ReloadModel(source)) => {
+                self.scene.write().unwrap().start_reload(source.clone());
+                self.wake = true;
+            }
+            UserEvent(gui::Event::PasteModels(text)) => {
+                let pasted = Clipboard::from_text(text).and_then(|clipboard| {
+                    let mut scene = self.scene.write().unwrap();
+                    scene.paste(&self.device, &self.queue, &self.config, clipboard)
+                });
+                if let Err(e) = pasted {
+                    log::warn!("Pasting failed: {:?}", e);
+                }
+                self.wake = true;
+            }
+            UserEvent(gui::Event::AddPrimitive(primitive)) => {
+                let mut scene = self.scene.write().unwrap();
+                let (device, queue, config) = (&self.device, &self.queue, &self.config);
+                if let Err(e) = scene.add_primitive(device, queue, config, *primitive) {
+                    log::warn!("Adding a {} failed: {:?}", primitive.name(), e);
+                }
+                self.wake = true;
+            }
+            UserEvent(gui::Event::AddLight) => {
+                let mut scene = self.scene.write().unwrap();
+                if scene.add_light(&self.device).is_none() {
+                    log::warn!("No more lights can be added");
+                }
+                self.wake = true;
+            }
+            UserEvent(gui::Event::SetCamera { eye, target }) => {
+                let camera = &mut self.scene.write().unwrap().camera;
+                camera.eye = *eye;
+                camera.target = *target;
+                self.camera_controller.reset();
+                self.wake = true;
+            }
+            UserEvent(gui::Event::SetCameraScheme(scheme)) => {
+                self.camera_controller.set_scheme(*scheme);
+            }
+            UserEvent(gui::Event::LookThrough(camera)) => {
+                self.scene.write().unwrap().look_through(*camera);
+                self.camera_controller.reset();
+                self.wake = true;
+            }
+            UserEvent(gui::Event::RenderCamera { camera, path }) => {
+                if let Err(e) = self.render_camera(*camera, path) {
+                    log::warn!("Rendering to {:?} failed: {:?}", path, e);
+                }
+            }
+            UserEvent(gui::Event::ExportReview(dir)) => {
+                if let Err(e) = self.export_review(dir) {
+                    log::warn!("Exporting the review to {:?} failed: {:?}", dir, e);
+                }
+            }
+            UserEvent(gui::Event::LoadEnvironment(source)) => {
+                self.scene.write().unwrap().lights.environment.load(source.clone());
+                self.wake = true;
+            }
+            UserEvent(gui::Event::AddGround) => {
+                let mut scene = self.scene.write().unwrap();
+                if let Err(e) = scene.add_ground(&self.device, &self.queue, &self.config) {
+                    log::warn!("Adding the ground failed: {:?}", e);
+                }
+                self.wake = true;
+            }
+            UserEvent(gui::Event::CaptureFrame) => {
+                self.capture_frame(capture::screenshot_name());
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            UserEvent(gui::Event::OpenBundle(path)) => {
+                if let Err(e) = self.scene.write().unwrap().open_bundle(path) {
+                    log::warn!("Opening the bundle failed: {:?}", e);
+                }
+                self.wake = true;
+            }
+            UserEvent(gui::Event::Subscribe(subscriber)) => {
+                self.subscribers.add(subscriber.clone());
+            }
+            #[cfg(target_arch = "wasm32")]
+            UserEvent(gui::Event::OnSelect(callback)) => {
+                self.selection_listeners.add(callback.clone());
+            }
+            Resumed if self.suspended => {
+                self.suspended = false;
+                // Android destroys the native window while suspended.
+                #[cfg(target_os = "android")]
+                {
+                    self.surface = unsafe { self.instance.create_surface(window) };
+                }
+                self.resize(self.size);
+            }
+            LoopDestroyed => {
+                if let Some(Session::Record(recorder)) = &mut self.session {
+                    if let Err(e) = recorder.finish(self.frame) {
+                        log::error!("Failed to save recording: {:?}", e);
+                    }
+                }
             }
             WindowEvent {
                 ref event,
                 window_id,
             } if *window_id == window.id() => {
-                if !self.input(event) {
+                if let Some(Session::Record(recorder)) = &mut self.session {
+                    recorder.record(self.frame, event);
+                }
+                self.wake = true;
+                // Live input is ignored while a recording drives the camera.
+                if is_replaying || !self.input(event) {
                     match event {
                         winit::event::WindowEvent::CloseRequested => {
                             *control_flow = ControlFlow::Exit
@@ -80,6 +278,16 @@ impl State {
                                 virtual_keycode: Some(winit::event::VirtualKeyCode::Escape),
                                 ..
                             } => *control_flow = ControlFlow::Exit,
+                            winit::event::KeyboardInput {
+                                state: winit::event::ElementState::Pressed,
+                                virtual_keycode: Some(winit::event::VirtualKeyCode::F11),
+                                ..
+                            } => Self::toggle_fullscreen(window),
+                            winit::event::KeyboardInput {
+                                state: winit::event::ElementState::Pressed,
+                                virtual_keycode: Some(winit::event::VirtualKeyCode::F12),
+                                ..
+                            } => self.capture_frame(capture::screenshot_name()),
                             _ => {}
                         },
                         winit::event::WindowEvent::Resized(physical_size) => {
@@ -100,9 +308,16 @@ impl State {
 }
 
 impl State {
+    pub const TIMESTEP: f32 = 1.0 / 60.0;
+    /// Upper bound of fixed steps per frame so a long stall doesn't snowball.
+    const MAX_STEPS: u32 = 8;
+    /// How often the event loop wakes up while the window isn't visible.
+    const HIDDEN_FRAMETIME: Duration = Duration::from_millis(250);
+
     pub async fn new(
         window: &Window,
-        texture_format: wgpu::TextureFormat,
+        surface_format: Option<wgpu::TextureFormat>,
+        render_config: RenderConfig,
         event_loop: &EventLoop<gui::Event>,
     ) -> Self {
         let backend = wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all);
@@ -121,39 +336,66 @@ impl State {
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    features: wgpu::Features::empty(),
+                    // Line mode for the wireframe views, which draw edge lists without it.
+                    features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
                     limits: wgpu::Limits::default(),
                 },
                 None,
             )
             .await
             .expect("Unable to find a suitable GPU adapter!");
+        // The WebGL2 build has to stay within what WebGL2 offers, WebGPU has the full limits.
+        #[cfg(target_arch = "wasm32")]
+        let limits = if cfg!(feature = "webgl") {
+            wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits())
+        } else {
+            wgpu::Limits::default()
+        };
         #[cfg(target_arch = "wasm32")]
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     features: wgpu::Features::default(),
-                    limits: wgpu::Limits::downlevel_webgl2_defaults()
-                        .using_resolution(adapter.limits()),
+                    limits,
                     label: None,
                 },
                 None,
             )
             .await
             .expect("Unable to find a suitable GPU adapter!");
+        let formats =
+            SurfaceFormats::negotiate(surface.get_preferred_format(&adapter), surface_format);
+        log::info!(
+            "Surface format {:?}, rendering to {:?}",
+            formats.surface,
+            formats.render
+        );
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            //format: texture_format,
-            format: surface.get_preferred_format(&adapter).unwrap(),
+            format: formats.render,
             width: size.width,
             height: size.height,
             present_mode: wgpu::PresentMode::Fifo,
         };
-        surface.configure(&device, &config);
+        surface.configure(
+            &device,
+            &wgpu::SurfaceConfiguration {
+                format: formats.surface,
+                ..config.clone()
+            },
+        );
+        let present_target = Self::present_target(&device, &config, formats);
 
-        let res_dir = std::path::Path::new(env!("OUT_DIR")).join("res");
+        let res_dir = assets::res_dir();
         //let model = model::Model::GLTF(model.await.unwrap());
-        let mut scene = Arc::new(RwLock::new(scene::Scene::new(&device, &config)));
+        let mut scene = Arc::new(RwLock::new(scene::Scene::with_render_config(
+            &device,
+            &queue,
+            &config,
+            render_config,
+        )));
+        scene.write().unwrap().shader_strategy =
+            ShaderStrategy::for_backend(adapter.get_info().backend);
         let mut collection = Arc::new(RwLock::new(collection::Collection::new()));
         collection.write().unwrap().add_model(
             Arc::new(collection::Model::RUNGHOLT(
@@ -196,43 +438,603 @@ impl State {
         //    .await
         //    .unwrap(),
         //);
-        scene.write().unwrap().models.push(model);
+        scene.write().unwrap().add_model(model);
         // scene.write().unwrap().models.push(light_model);
 
-        let camera_controller = CameraController::new(0.2, size);
+        let camera_controller = CameraController::new(0.2, size, window.scale_factor());
 
         Self {
+            instance,
             surface,
             device,
             queue,
             config,
+            formats,
+            present_target,
+            frame_capture: None,
             size,
             scene,
             camera_controller,
+            modifiers: winit::event::ModifiersState::empty(),
+            frame: 0,
+            timestep: Some(Self::TIMESTEP),
+            accumulator: 0.,
+            last_update: None,
+            last_frame: None,
+            suspended: false,
+            camera_moving: false,
+            wake: true,
             gui,
+            session: None,
+            bench: None,
+            subscribers: Subscribers::default(),
+            #[cfg(target_arch = "wasm32")]
+            selection_listeners: Default::default(),
+        }
+    }
+
+    /// Hands the camera over to the benchmark and disables vsync until it finishes.
+    pub fn start_benchmark(&mut self, benchmark: bench::Benchmark) {
+        self.config.present_mode = wgpu::PresentMode::Immediate;
+        self.configure_surface();
+        self.bench = Some(benchmark);
+    }
+
+    /// Groups the scene's models like the saved project, loading the ones it names.
+    pub fn open_project(&mut self, project: project::Project) {
+        self.scene.write().unwrap().load_project(project);
+    }
+
+    /// Configures the surface like `config` but with the surface format.
+    fn configure_surface(&self) {
+        self.surface.configure(
+            &self.device,
+            &wgpu::SurfaceConfiguration {
+                format: self.formats.surface,
+                ..self.config.clone()
+            },
+        );
+    }
+
+    /// The target frames are rendered into before being encoded onto the surface, if needed.
+    fn present_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        formats: SurfaceFormats,
+    ) -> Option<ScaledTarget> {
+        if formats.needs_encoding() {
+            Some(ScaledTarget::new(
+                device,
+                formats.render,
+                formats.surface,
+                (config.width, config.height),
+            ))
+        } else {
+            None
         }
     }
 
+    /// Whether the window can be drawn to, i.e. the app isn't suspended and the window isn't
+    /// minimized.
+    pub fn is_visible(&self) -> bool {
+        !self.suspended && self.size.width > 0 && self.size.height > 0
+    }
+
+    /// Switches between a borderless fullscreen window, the whole screen on the web, and a normal
+    /// one.
+    fn toggle_fullscreen(window: &Window) {
+        if window.fullscreen().is_some() {
+            window.set_fullscreen(None);
+        } else {
+            window.set_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
+        }
+    }
+
+    /// Whether nothing changes from frame to frame, so the event loop can wait for events instead
+    /// of redrawing. Benchmarks and replays always run continuously.
+    fn is_idle(&self) -> bool {
+        let scene = self.scene.read().unwrap();
+        scene.reduced_power
+            && self.bench.is_none()
+            && self.session.is_none()
+            && !self.camera_moving
+            && !self.gui.needs_repaint()
+            && !scene.is_animating()
+    }
+
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.size = new_size;
+        // A minimized window has no size, the surface is configured again once it is restored.
+        if !self.is_visible() {
+            return;
+        }
         self.config.width = new_size.width;
         self.config.height = new_size.height;
-        self.surface.configure(&self.device, &self.config);
+        self.configure_surface();
+        self.present_target = Self::present_target(&self.device, &self.config, self.formats);
         self.scene
             .write()
             .unwrap()
             .resize(&self.device, &self.config);
-        self.camera_controller.size = self.size;
+        self.camera_controller.input.size = self.size;
     }
 
     fn input(&mut self, event: &winit::event::WindowEvent) -> bool {
+        if let winit::event::WindowEvent::ModifiersChanged(modifiers) = event {
+            self.modifiers = *modifiers;
+        }
+        if self.section_input(event)
+            || self.sculpt_input(event)
+            || self.edit_input(event)
+            || self.layer_input(event)
+            || self.cursor_input(event)
+            || self.select_input(event)
+        {
+            return true;
+        }
+        if let winit::event::WindowEvent::Touch(touch) = event {
+            // Touches starting on a GUI window operate it instead.
+            if touch.phase == winit::event::TouchPhase::Started && self.gui.wants_pointer_input() {
+                return false;
+            }
+        }
+        if let winit::event::WindowEvent::MouseWheel { .. } = event {
+            if self.camera_controller.zoom_to_cursor {
+                self.camera_controller.input.zoom_anchor = Some(self.zoom_anchor());
+            }
+        }
         self.camera_controller.process_events(event, self.size)
     }
 
-    fn update(&mut self) {
-        self.camera_controller
-            .update_camera(&mut self.scene.write().unwrap().camera);
-        self.scene.write().unwrap().update(&self.queue);
+    /// Drags the faces of the section box by their handles with the left mouse button.
+    fn section_input(&mut self, event: &winit::event::WindowEvent) -> bool {
+        use winit::event::{ElementState, MouseButton, WindowEvent};
+
+        let mut scene = self.scene.write().unwrap();
+        if !scene.section.enabled {
+            return false;
+        }
+        match event {
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !self.gui.wants_pointer_input() => {
+                let ray = scene.stereo.ray_from_cursor(
+                    &scene.camera,
+                    self.camera_controller.cursor_position(),
+                    self.size,
+                );
+                scene.section.begin_drag(&ray)
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } => scene.section.end_drag(),
+            WindowEvent::CursorMoved { position, .. } => {
+                let ray = scene.stereo.ray_from_cursor(
+                    &scene.camera,
+                    (position.x, position.y),
+                    self.size,
+                );
+                if scene.section.is_dragging() {
+                    scene.section.drag(&ray);
+                } else {
+                    scene.section.hover(&ray);
+                }
+                // The camera controller still has to track the cursor.
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Drives sculpt strokes with the left mouse button while sculpt mode is on.
+    fn sculpt_input(&mut self, event: &winit::event::WindowEvent) -> bool {
+        use winit::event::{ElementState, MouseButton, WindowEvent};
+
+        let mut scene = self.scene.write().unwrap();
+        if !scene.sculpt.enabled {
+            return false;
+        }
+        match event {
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !self.gui.wants_pointer_input() => {
+                let ray = scene.stereo.ray_from_cursor(
+                    &scene.camera,
+                    self.camera_controller.cursor_position(),
+                    self.size,
+                );
+                scene.begin_sculpt_stroke(&self.queue, &ray)
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let was_stroking = scene.sculpt.is_stroking();
+                scene.sculpt.end_stroke();
+                was_stroking
+            }
+            WindowEvent::CursorMoved { position, .. } if scene.sculpt.is_stroking() => {
+                let ray = scene.stereo.ray_from_cursor(
+                    &scene.camera,
+                    (position.x, position.y),
+                    self.size,
+                );
+                scene.continue_sculpt_stroke(&self.queue, &ray);
+                // The camera controller still has to track the cursor.
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Drives the edit mode tools with the left mouse button while edit mode is on. Scrolling
+    /// while moving the selection changes the proportional editing radius.
+    fn edit_input(&mut self, event: &winit::event::WindowEvent) -> bool {
+        use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+
+        let mut scene = self.scene.write().unwrap();
+        if !scene.edit.enabled {
+            return false;
+        }
+        match event {
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !self.gui.wants_pointer_input() => {
+                let ray = scene.stereo.ray_from_cursor(
+                    &scene.camera,
+                    self.camera_controller.cursor_position(),
+                    self.size,
+                );
+                scene.begin_edit_action(&ray, self.modifiers.shift())
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } => scene.end_edit_action(&self.device),
+            WindowEvent::MouseWheel {
+                delta: MouseScrollDelta::LineDelta(_, lines),
+                ..
+            } if scene.edit.is_moving() => {
+                scene.edit.scale_proportional_radius(*lines);
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let ray = scene.stereo.ray_from_cursor(
+                    &scene.camera,
+                    (position.x, position.y),
+                    self.size,
+                );
+                scene.hover_edit(&ray);
+                // The camera controller still has to track the cursor.
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Selects models with the left mouse button: a click picks the one under the cursor and a
+    /// drag selects those inside the box, with shift adding to the selection. Dragging an arrow
+    /// of the gizmo moves the selection. Stereo mode only picks by clicking into either eye.
+    fn select_input(&mut self, event: &winit::event::WindowEvent) -> bool {
+        use winit::event::{ElementState, MouseButton, WindowEvent};
+
+        let mut scene = self.scene.write().unwrap();
+        match event {
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !self.gui.wants_pointer_input() => {
+                let cursor = self.camera_controller.cursor_position();
+                let ray = scene.stereo.ray_from_cursor(&scene.camera, cursor, self.size);
+                if scene.stereo.enabled {
+                    scene.click_select(&ray, self.modifiers.shift());
+                    return true;
+                }
+                let screen = picking::cursor_to_screen(cursor, self.size);
+                scene.begin_select(&ray, screen, self.modifiers.shift())
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let cursor = self.camera_controller.cursor_position();
+                let ray = scene.stereo.ray_from_cursor(&scene.camera, cursor, self.size);
+                scene.end_select(&ray)
+            }
+            WindowEvent::CursorMoved { position, .. } if !scene.stereo.enabled => {
+                let cursor = (position.x, position.y);
+                let ray = scene.stereo.ray_from_cursor(&scene.camera, cursor, self.size);
+                scene.drag_select(&ray, picking::cursor_to_screen(cursor, self.size));
+                // The camera controller still has to track the cursor.
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Places the 3D cursor with shift and the right mouse button.
+    fn cursor_input(&mut self, event: &winit::event::WindowEvent) -> bool {
+        use winit::event::{ElementState, MouseButton, WindowEvent};
+
+        match event {
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Right,
+                ..
+            } if self.modifiers.shift() && !self.gui.wants_pointer_input() => {
+                let mut scene = self.scene.write().unwrap();
+                let cursor = self.camera_controller.cursor_position();
+                let ray = scene.stereo.ray_from_cursor(&scene.camera, cursor, self.size);
+                scene.place_cursor(&ray)
+            }
+            _ => false,
+        }
+    }
+
+    /// Number keys show or hide the layers, with shift only the pressed one is shown.
+    fn layer_input(&mut self, event: &winit::event::WindowEvent) -> bool {
+        use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+        const KEYS: [VirtualKeyCode; outliner::Outliner::LAYER_COUNT] = [
+            VirtualKeyCode::Key1,
+            VirtualKeyCode::Key2,
+            VirtualKeyCode::Key3,
+            VirtualKeyCode::Key4,
+            VirtualKeyCode::Key5,
+            VirtualKeyCode::Key6,
+            VirtualKeyCode::Key7,
+            VirtualKeyCode::Key8,
+        ];
+        let keycode = match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } if !self.gui.wants_keyboard_input() => keycode,
+            _ => return false,
+        };
+        match KEYS.iter().position(|key| key == keycode) {
+            Some(layer) => {
+                let mut scene = self.scene.write().unwrap();
+                scene.outliner.toggle_layer(layer, self.modifiers.shift());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The surface point under the cursor, or the point at the target's depth when there is none.
+    fn zoom_anchor(&self) -> cgmath::Point3<f32> {
+        let scene = self.scene.read().unwrap();
+        let ray = scene.stereo.ray_from_cursor(
+            &scene.camera,
+            self.camera_controller.cursor_position(),
+            self.size,
+        );
+        match scene.raycast(&ray) {
+            Some(hit) => hit.point,
+            None => ray.at((scene.camera.target - scene.camera.eye).magnitude()),
+        }
+    }
+
+    /// Runs as many updates as the time since the last call requires.
+    fn advance(&mut self) {
+        let now = Instant::now();
+        let elapsed = self
+            .last_update
+            .map(|last| (now - last).as_secs_f32())
+            .unwrap_or(0.);
+        self.last_update = Some(now);
+
+        match self.timestep {
+            Some(step) => {
+                self.accumulator = (self.accumulator + elapsed).min(step * Self::MAX_STEPS as f32);
+                while self.accumulator >= step {
+                    self.update(step);
+                    self.frame += 1;
+                    self.accumulator -= step;
+                }
+            }
+            None => {
+                self.update(elapsed);
+                self.frame += 1;
+            }
+        }
+    }
+
+    fn update(&mut self, dt: f32) {
+        profile_function!();
+        let (eye, target) = {
+            let camera = &self.scene.read().unwrap().camera;
+            (camera.eye, camera.target)
+        };
+        match &mut self.bench {
+            Some(bench) => bench.update_camera(&mut self.scene.write().unwrap().camera),
+            None => self
+                .camera_controller
+                .update_camera(&mut self.scene.write().unwrap().camera, dt),
+        }
+        let mut scene = {
+            profile_scope!("scene lock");
+            self.scene.write().unwrap()
+        };
+        self.camera_moving = scene.camera.eye != eye || scene.camera.target != target;
+        scene.poll_remesh(&self.device);
+        scene.poll_cleanup(&self.device, &self.config);
+        scene.poll_loads(&self.device, &self.queue, &self.config);
+        scene.poll_textures(&self.device, &self.queue);
+        scene.poll_environment(&self.device, &self.queue);
+        scene.apply_edit_operation(&self.device);
+        scene.apply_shape_keys(&self.device, &self.queue);
+        scene.update(&self.queue, dt);
+        scene.update_shadows(&self.device, &self.queue);
+        scene.update_impostors(&self.device, &self.queue);
+        scene.update_wireframe(&self.device, &self.queue);
+        #[cfg(target_arch = "wasm32")]
+        self.selection_listeners.notify(&scene.outliner.selected);
+        let events = scene.take_events();
+        drop(scene);
+        self.subscribers.notify(&events);
+    }
+
+    /// Lets the dynamic resolution react to the time since the last frame.
+    fn record_frame_time(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last_frame {
+            let frame_time = (now - last).as_secs_f32();
+            self.scene
+                .write()
+                .unwrap()
+                .resolution
+                .record_frame(frame_time);
+        }
+        self.last_frame = Some(now);
+    }
+
+    /// Advances a replayed session by exactly one update and renders it.
+    fn replay_frame(
+        &mut self,
+        control_flow: &mut ControlFlow,
+        start_time: Instant,
+        previous_frame_time: &mut Option<f32>,
+        window: &Window,
+    ) {
+        let player = match &mut self.session {
+            Some(Session::Replay(player)) => player,
+            _ => return,
+        };
+        if player.is_finished(self.frame) {
+            log::info!("Replay finished after {} frames", self.frame);
+            *control_flow = ControlFlow::Exit;
+            return;
+        }
+        let events = player.events_for_frame(self.frame);
+        let frame_path = player.frame_path(self.frame);
+        let timestep = player.recording.timestep;
+        for event in events.iter() {
+            self.input(event);
+        }
+        self.update(timestep);
+        self.render(start_time, previous_frame_time, window);
+
+        if let Some(path) = frame_path {
+            if let Err(e) = self.dump_frame(&path) {
+                log::error!("Failed to dump frame {:?}: {:?}", path, e);
+            }
+        }
+        self.frame += 1;
+    }
+
+    /// Saves a frame rendered through the scene camera as an image, the viewport keeping its
+    /// view.
+    fn render_camera(&self, camera: usize, path: &std::path::Path) -> anyhow::Result<()> {
+        let view = self.scene.write().unwrap().view_through(&self.queue, camera);
+        let view = view.ok_or_else(|| anyhow::anyhow!("No camera {}", camera))?;
+        let result = self.dump_frame(path);
+        self.scene.write().unwrap().restore_view(&self.queue, view);
+        result
+    }
+
+    /// Saves a screenshot through the view of every review note, its marker drawn on it,
+    /// and the report on them into the folder, the viewport keeping its view.
+    fn export_review(&self, dir: &std::path::Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        let (width, height) = (self.config.width, self.config.height);
+        let count = self.scene.read().unwrap().review.notes.len();
+        let mut exported = Vec::with_capacity(count);
+        for i in 0..count {
+            let mut scene = self.scene.write().unwrap();
+            let (marker, view) = match scene.view_note(&self.queue, i, width, height) {
+                Some(viewed) => viewed,
+                None => break,
+            };
+            let note = scene.review.notes[i].clone();
+            drop(scene);
+            let image = self.render_offscreen();
+            self.scene.write().unwrap().restore_view(&self.queue, view);
+            let mut image = image?;
+            if let Some(marker) = marker {
+                review::draw_marker(&mut image, marker);
+            }
+            let screenshot = format!("note-{}.png", i + 1);
+            image.save(dir.join(&screenshot))?;
+            exported.push(review::ExportedNote {
+                number: i + 1,
+                note,
+                screenshot,
+                marker,
+            });
+        }
+        review::write_report(dir, &exported, (width, height))
+    }
+
+    /// Renders the scene without the GUI into an offscreen target and saves it as an image.
+    fn dump_frame(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        self.render_offscreen()?.save(path)?;
+        Ok(())
+    }
+
+    /// Renders the scene without the GUI into an offscreen target.
+    fn render_offscreen(&self) -> anyhow::Result<image::RgbaImage> {
+        let capture =
+            capture::Capture::new(&self.device, self.config.width, self.config.height, self.config.format);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Capture Encoder"),
+            });
+        self.scene.read().unwrap().draw(&mut encoder, &capture.view);
+        capture.copy_to_buffer(&mut encoder);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        futures::executor::block_on(capture.read(&self.device))
+    }
+
+    /// Saves the next frame as it's presented, with the GUI, as a PNG at `path`. On the web it's
+    /// downloaded under the path's file name.
+    pub fn capture_frame(&mut self, path: std::path::PathBuf) {
+        self.frame_capture = Some(path);
+        self.wake = true;
+    }
+
+    /// Reads back the frame copied into `readback` and saves it, see `capture_frame`.
+    fn save_frame(&self, readback: capture::Readback, path: std::path::PathBuf) {
+        #[cfg(not(target_arch = "wasm32"))]
+        match futures::executor::block_on(readback.read(&self.device))
+            .and_then(|image| Ok(image.save(&path)?))
+        {
+            Ok(()) => log::info!("Saved the frame to {:?}", path),
+            Err(e) => log::warn!("Saving the frame failed: {:?}", e),
+        }
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(async move {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            let saved = readback.into_image().await.and_then(|image| {
+                let mut png = Vec::new();
+                image::DynamicImage::ImageRgba8(image)
+                    .write_to(&mut png, image::ImageOutputFormat::Png)?;
+                crate::web::download(&name, &png, "image/png")
+            });
+            if let Err(e) = saved {
+                log::warn!("Saving the frame failed: {:?}", e);
+            }
+        });
     }
 
     fn render(
@@ -241,40 +1043,100 @@ impl State {
         previous_frame_time: &mut Option<f32>,
         window: &Window,
     ) {
-        let frame = match self.surface.get_current_texture() {
+        crate::profiling::new_frame();
+        profile_function!();
+        let acquired = {
+            profile_scope!("acquire frame");
+            self.surface.get_current_texture()
+        };
+        let frame = match acquired {
             Ok(frame) => frame,
-            Err(_) => {
-                self.surface.configure(&self.device, &self.config);
-                self.surface
-                    .get_current_texture()
-                    .expect("Failed to acquire next surface texture!")
+            Err(wgpu::SurfaceError::Lost) | Err(wgpu::SurfaceError::Outdated) => {
+                // Drawn next frame, with the surface matching the window again.
+                self.configure_surface();
+                return;
+            }
+            Err(e) => {
+                log::warn!("Skipping frame: {:?}", e);
+                return;
             }
         };
-        let view = frame
+        let frame_view = frame
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
+        let frame_capture = self.frame_capture.take().filter(|_| {
+            let readable = capture::Readback::supports(self.formats.render);
+            if !readable {
+                log::warn!("Frames of {:?} can't be captured", self.formats.render);
+            }
+            readable
+        });
+        // The surface texture can't be copied from everywhere, so a captured frame is rendered
+        // offscreen first like for surfaces that don't encode sRGB.
+        let capture_target = match (&frame_capture, &self.present_target) {
+            (Some(_), None) => Some(ScaledTarget::new(
+                &self.device,
+                self.formats.render,
+                self.formats.surface,
+                (self.config.width, self.config.height),
+            )),
+            _ => None,
+        };
+        let target = self.present_target.as_ref().or_else(|| capture_target.as_ref());
+        let view = match target {
+            Some(target) => &target.view,
+            None => &frame_view,
+        };
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
 
-        self.scene.read().unwrap().draw(&mut encoder, &view);
-
+        let mut scene = {
+            profile_scope!("scene lock");
+            self.scene.write().unwrap()
+        };
+        scene.prepare_render_target(&self.device, &self.config);
+        scene.draw(&mut encoder, view);
+        self.gui.register_shadow_debug(&self.device, &mut scene.renderer.shadows);
+        drop(scene);
         self.gui.draw(
             &self.device,
             &self.queue,
             &mut encoder,
-            &view,
+            view,
             start_time,
             previous_frame_time,
             window,
             self.config.width,
             self.config.height,
         );
+        let readback = target.zip(frame_capture).map(|(target, path)| {
+            let (width, height) = target.size;
+            let readback = capture::Readback::new(&self.device, width, height, self.formats.render);
+            readback.copy_from(&mut encoder, &target.texture);
+            (readback, path)
+        });
+        if let Some(target) = target {
+            target.blit(&mut encoder, &frame_view);
+        }
 
-        // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
-        frame.present();
+        {
+            profile_scope!("submit and present");
+            // submit will accept anything that implements IntoIter
+            self.queue.submit(std::iter::once(encoder.finish()));
+            frame.present();
+        }
+        if let Some((readback, path)) = readback {
+            self.save_frame(readback, path);
+        }
+        if !self.subscribers.is_empty() {
+            let stats = self.scene.read().unwrap().stats();
+            self.subscribers.notify(&[SceneEvent::FrameRendered {
+                frame: self.frame,
+                stats,
+            }]);
+        }
     }
 }
@@ -0,0 +1,230 @@
+use cgmath::InnerSpace;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera::Camera,
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    picking::Ray,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+/// How the two eyes are put on the screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoMode {
+    /// The left eye in the left half of the window and the right eye in the right half, for
+    /// viewing through a headset holding the screen or a stereoscope.
+    SideBySide,
+    /// Both eyes over the whole window in different colors, for red-cyan glasses.
+    Anaglyph,
+}
+
+/// Stereo rendering of the scene from two eyes next to the camera.
+///
+/// Both eyes look parallel to the camera, so the stereo depth doesn't depend on the distance
+/// to the target.
+#[derive(Debug, Clone)]
+pub struct Stereo {
+    pub enabled: bool,
+    pub mode: StereoMode,
+    /// Distance between the eyes in scene units, 0.064 for a scene in meters.
+    pub eye_separation: f32,
+}
+
+impl Default for Stereo {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: StereoMode::SideBySide,
+            eye_separation: 0.064,
+        }
+    }
+}
+
+impl Stereo {
+    /// The camera of one eye of a viewer standing at `camera`.
+    pub fn eye_camera(&self, camera: &Camera, eye: Eye) -> Camera {
+        let forward = (camera.target - camera.eye).normalize();
+        let right = forward.cross(camera.up).normalize();
+        let offset = match eye {
+            Eye::Left => -0.5,
+            Eye::Right => 0.5,
+        } * self.eye_separation;
+        let mut projection = camera.projection;
+        if self.mode == StereoMode::SideBySide {
+            projection.aspect /= 2.;
+        }
+        Camera {
+            eye: camera.eye + right * offset,
+            target: camera.target + right * offset,
+            up: camera.up,
+            projection,
+            orthographic: camera.orthographic,
+        }
+    }
+
+    /// The ray through the cursor. Side by side it picks in the half of the window it is over.
+    pub fn ray_from_cursor(
+        &self,
+        camera: &Camera,
+        cursor: (f64, f64),
+        size: PhysicalSize<u32>,
+    ) -> Ray {
+        if !self.enabled || self.mode != StereoMode::SideBySide {
+            return Ray::from_cursor(camera, cursor, size);
+        }
+        let half = (size.width / 2).max(1);
+        let (eye, x) = if cursor.0 < half as f64 {
+            (Eye::Left, cursor.0)
+        } else {
+            (Eye::Right, cursor.0 - half as f64)
+        };
+        let eye_size = PhysicalSize::new(half, size.height);
+        Ray::from_cursor(&self.eye_camera(camera, eye), (x, cursor.1), eye_size)
+    }
+}
+
+/// The two eyes rendered separately and combined into an anaglyph.
+#[derive(Debug)]
+pub struct AnaglyphTarget {
+    pub size: (u32, u32),
+    pub left: Tracked<wgpu::TextureView>,
+    pub right: Tracked<wgpu::TextureView>,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl AnaglyphTarget {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, size: (u32, u32)) -> Self {
+        let eye_view = |label| {
+            device
+                .create_tracked_texture(GpuCategory::Targets, &wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: size.0,
+                        height: size.1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                })
+                .into_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let left = eye_view("Left Eye Target");
+        let right = eye_view("Right Eye Target");
+        // Both eyes are as large as the target, so nothing is filtered.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Anaglyph Sampler"),
+            ..Default::default()
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("anaglyph_bind_group_layout"),
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("anaglyph_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&left),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&right),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!("blit.vert.spv"));
+        let fs_module = device.create_shader_module(&wgpu::include_spirv!("anaglyph.frag.spv"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Anaglyph Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Anaglyph Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &vs_module,
+                entry_point: "main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_module,
+                entry_point: "main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+        });
+
+        Self {
+            size,
+            left,
+            right,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    /// Combines the eyes into `view`, which has the size of the target.
+    pub fn composite(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Anaglyph Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,211 @@
+//! Textures streamed in after their model: a load leaves the OBJ material textures out, the
+//! model is added with one pixel textures in the materials' colors, and the textures are
+//! fetched and decoded afterwards on the load's worker, each swapped into its material as it
+//! is uploaded. glTF textures are still decoded with their model, as they are combined with the
+//! material's factors first.
+
+use std::sync::{mpsc, Arc};
+
+use crate::{
+    loading::{Fetcher, LoadedModel, Pixels},
+    model::Material,
+    scene::Scene,
+    texture::Texture,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureSlot {
+    Diffuse,
+    Normal,
+    Specular,
+    Occlusion,
+}
+
+impl TextureSlot {
+    pub const ALL: [TextureSlot; 4] = [
+        TextureSlot::Diffuse,
+        TextureSlot::Normal,
+        TextureSlot::Specular,
+        TextureSlot::Occlusion,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TextureSlot::Diffuse => "diffuse",
+            TextureSlot::Normal => "normal",
+            TextureSlot::Specular => "specular",
+            TextureSlot::Occlusion => "occlusion",
+        }
+    }
+}
+
+/// A texture decoded for the material of that registry name.
+#[derive(Debug)]
+pub struct StreamedTexture {
+    pub material: String,
+    pub slot: TextureSlot,
+    /// The image, or missing when it couldn't be loaded.
+    pub pixels: Pixels,
+}
+
+#[derive(Debug)]
+pub struct TextureStream {
+    /// Whether loads stream their textures, or decode them before adding the model.
+    pub enabled: bool,
+    /// Textures of added models not swapped in yet.
+    pub pending: usize,
+    sender: mpsc::Sender<StreamedTexture>,
+    receiver: mpsc::Receiver<StreamedTexture>,
+}
+
+impl Default for TextureStream {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            enabled: true,
+            pending: 0,
+            sender,
+            receiver,
+        }
+    }
+}
+
+impl TextureStream {
+    /// Textures uploaded per frame at most, so a model with many doesn't stall one.
+    const UPLOADS_PER_FRAME: usize = 4;
+
+    /// Where a load sends its textures, `None` while streaming is off.
+    pub fn sender(&self) -> Option<mpsc::Sender<StreamedTexture>> {
+        self.enabled.then(|| self.sender.clone())
+    }
+}
+
+fn pixels(material: &crate::loading::LoadedMaterial, slot: TextureSlot) -> &Pixels {
+    match slot {
+        TextureSlot::Diffuse => &material.diffuse,
+        TextureSlot::Normal => &material.normal,
+        TextureSlot::Specular => &material.specular,
+        TextureSlot::Occlusion => &material.occlusion,
+    }
+}
+
+/// The textures of the model left to stream: the registry name of their material, their slot
+/// and where they are.
+pub(crate) fn streamed_textures(model: &LoadedModel) -> Vec<(String, TextureSlot, String)> {
+    let mut textures = Vec::new();
+    for material in model.materials.iter() {
+        for slot in TextureSlot::ALL {
+            if let Pixels::Streamed { url, .. } = pixels(material, slot) {
+                let name = format!("{}:{}", model.source, material.name);
+                textures.push((name, slot, url.clone()));
+            }
+        }
+    }
+    textures
+}
+
+/// Fetches and decodes the textures one after the other, sending each when it's done. Stops
+/// when the scene is gone.
+pub(crate) async fn stream(
+    fetcher: &Fetcher<'_>,
+    textures: Vec<(String, TextureSlot, String)>,
+    sender: mpsc::Sender<StreamedTexture>,
+) {
+    for (material, slot, url) in textures {
+        let pixels = match fetcher.image(&url).await {
+            Ok(image) => Pixels::Image(image),
+            Err(e) => {
+                log::warn!("Texture {} not loaded: {:?}", url, e);
+                Pixels::Missing
+            }
+        };
+        let streamed = StreamedTexture {
+            material,
+            slot,
+            pixels,
+        };
+        if sender.send(streamed).is_err() {
+            return;
+        }
+        crate::loading::yield_now().await;
+    }
+}
+
+impl Material {
+    /// The material with the texture in the slot instead, its bind group made again.
+    pub fn with_texture(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        slot: TextureSlot,
+        texture: Arc<Texture>,
+    ) -> Self {
+        let mut textures = [
+            self.diffuse_texture.clone(),
+            self.normal_texture.clone(),
+            self.specular_texture.clone(),
+            self.occlusion_texture.clone(),
+        ];
+        textures[slot as usize] = texture;
+        let [diffuse, normal, specular, occlusion] = textures;
+        Material::new(
+            device,
+            &self.name,
+            diffuse,
+            normal,
+            specular,
+            occlusion,
+            self.emissive_texture.clone(),
+            self.features,
+            self.id,
+            layout,
+            self.shader.clone(),
+        )
+    }
+}
+
+impl Scene {
+    /// Whether textures of added models are still on their way.
+    pub fn is_streaming(&self) -> bool {
+        self.texture_stream.pending > 0
+    }
+
+    /// Uploads the textures streamed in since the last frame and swaps them into their
+    /// materials, in the registry and in the meshes drawn with them.
+    pub fn poll_textures(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        profile_function!();
+        for _ in 0..TextureStream::UPLOADS_PER_FRAME {
+            let streamed = match self.texture_stream.receiver.try_recv() {
+                Ok(streamed) => streamed,
+                Err(_) => break,
+            };
+            self.texture_stream.pending = self.texture_stream.pending.saturating_sub(1);
+            // Gone when the model was removed and its materials purged in the meantime.
+            let previous = match self.materials.read().unwrap().by_name(&streamed.material) {
+                Some(material) => material.clone(),
+                None => continue,
+            };
+            let label = format!("{} texture", streamed.slot.name());
+            let is_normal_map = streamed.slot == TextureSlot::Normal;
+            let texture = match &streamed.pixels {
+                Pixels::Image(image) => {
+                    Texture::from_image(device, queue, image, Some(&label), is_normal_map)
+                        .map(Arc::new)
+                        .unwrap_or_else(|_| self.placeholders.missing.clone())
+                }
+                _ => self.placeholders.missing.clone(),
+            };
+            let layout = &self.renderer.texture_bind_group_layout;
+            let material = Arc::new(previous.with_texture(device, layout, streamed.slot, texture));
+            self.materials
+                .write()
+                .unwrap()
+                .insert(streamed.material, material.clone());
+            for mesh in self.models.iter_mut().flat_map(|model| model.meshes_mut()) {
+                if Arc::ptr_eq(&mesh.material, &previous) {
+                    mesh.material = material.clone();
+                }
+            }
+        }
+    }
+}
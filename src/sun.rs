@@ -0,0 +1,128 @@
+use std::f32::consts::PI;
+
+use cgmath::{Deg, InnerSpace, Rad, Vector3};
+use chrono::Datelike;
+
+use crate::light::LightObject;
+
+/// Moves the scene light along the path of the sun over a day, at a place and date on Earth.
+///
+/// The scene is taken to be Y up with north along -Z and east along +X, unless turned by
+/// `north`. The sun position follows the NOAA solar equations, which are accurate to about a
+/// degree, plenty for judging shadows on a building.
+#[derive(Debug, Clone)]
+pub struct SunStudy {
+    pub enabled: bool,
+    /// Degrees, north positive.
+    pub latitude: f32,
+    /// Degrees, east positive.
+    pub longitude: f32,
+    pub date: chrono::NaiveDate,
+    /// Hours the local clock is ahead of UTC.
+    pub utc_offset: f32,
+    /// Local clock time in hours, from 0 to 24.
+    pub time: f32,
+    /// Whether the time runs.
+    pub playing: bool,
+    /// Hours passing per second while playing.
+    pub speed: f32,
+    /// Degrees the scene's north is turned from -Z toward +X.
+    pub north: f32,
+    /// Color of the light in full daylight. It fades out as the sun sets.
+    pub color: Vector3<f32>,
+    /// Whether the light was last set by the study, to give it back its color when disabled.
+    applied: bool,
+}
+
+impl Default for SunStudy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            // Greenwich at the June solstice.
+            latitude: 51.48,
+            longitude: 0.,
+            date: chrono::NaiveDate::from_ymd(2021, 6, 21),
+            utc_offset: 1.,
+            time: 12.,
+            playing: false,
+            speed: 1.,
+            north: 0.,
+            color: Vector3::new(1., 1., 1.),
+            applied: false,
+        }
+    }
+}
+
+impl SunStudy {
+    pub fn is_playing(&self) -> bool {
+        self.enabled && self.playing
+    }
+
+    /// Unit vector from the scene toward the sun.
+    pub fn direction(&self) -> Vector3<f32> {
+        let utc = self.time - self.utc_offset;
+        // Fractional year in radians.
+        let year = 2. * PI / 365. * (self.date.ordinal0() as f32 + (utc - 12.) / 24.);
+        let (sin1, cos1) = year.sin_cos();
+        let (sin2, cos2) = (2. * year).sin_cos();
+        let (sin3, cos3) = (3. * year).sin_cos();
+        // Minutes the sun is ahead of the mean sun.
+        let equation_of_time = 229.18
+            * (0.000075 + 0.001868 * cos1 - 0.032077 * sin1 - 0.014615 * cos2 - 0.040849 * sin2);
+        let declination = 0.006918 - 0.399912 * cos1 + 0.070257 * sin1 - 0.006758 * cos2
+            + 0.000907 * sin2
+            - 0.002697 * cos3
+            + 0.00148 * sin3;
+        let solar_minutes = utc * 60. + equation_of_time + 4. * self.longitude;
+        let hour_angle = Rad::from(Deg(solar_minutes / 4. - 180.)).0;
+
+        let (sin_lat, cos_lat) = Rad::from(Deg(self.latitude)).0.sin_cos();
+        let (sin_dec, cos_dec) = declination.sin_cos();
+        let (sin_ha, cos_ha) = hour_angle.sin_cos();
+        let east = -cos_dec * sin_ha;
+        let north = cos_lat * sin_dec - sin_lat * cos_dec * cos_ha;
+        let up = sin_lat * sin_dec + cos_lat * cos_dec * cos_ha;
+
+        let (sin_n, cos_n) = Rad::from(Deg(self.north)).0.sin_cos();
+        Vector3::new(
+            east * cos_n + north * sin_n,
+            up,
+            east * sin_n - north * cos_n,
+        )
+        .normalize()
+    }
+
+    /// Angle of the sun above the horizon.
+    pub fn elevation(&self) -> Deg<f32> {
+        Deg::from(Rad(self.direction().y.asin()))
+    }
+
+    /// How bright the light is, fading out between 6 degrees above the horizon and 1 below.
+    pub fn daylight(&self) -> f32 {
+        let t = ((self.elevation().0 + 1.) / 7.).max(0.).min(1.);
+        t * t * (3. - 2. * t)
+    }
+
+    /// Advances the time and places `light` at the sun, as far from the origin as it was.
+    pub fn update(&mut self, light: &mut LightObject, queue: &wgpu::Queue, dt: f32) {
+        use cgmath::EuclideanSpace;
+
+        if !self.enabled {
+            if self.applied {
+                light.light.color = self.color;
+                light.write(queue);
+                self.applied = false;
+            }
+            return;
+        }
+        if self.playing {
+            self.time = (self.time + self.speed * dt).rem_euclid(24.);
+        }
+        let distance = light.light.position.to_vec().magnitude();
+        light.light.position = cgmath::Point3::from_vec(self.direction() * distance);
+        light.light.color = self.color * self.daylight();
+        light.orbit = false;
+        light.write(queue);
+        self.applied = true;
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::*;
+
+/// Formats the window is drawn with.
+///
+/// The scene and egui shaders both write linear colors and leave the encoding to an sRGB
+/// target. When the surface can only be configured with a format that doesn't encode, frames are
+/// rendered into an sRGB target first and encoded while copying them to the surface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceFormats {
+    /// Format the surface is configured with.
+    pub surface: wgpu::TextureFormat,
+    /// Format the scene and GUI pipelines render to.
+    pub render: wgpu::TextureFormat,
+}
+
+impl SurfaceFormats {
+    /// Picks the formats for a surface preferring `preferred`, or `requested` when overridden.
+    pub fn negotiate(
+        preferred: Option<wgpu::TextureFormat>,
+        requested: Option<wgpu::TextureFormat>,
+    ) -> Self {
+        let surface = requested
+            .or(preferred)
+            .unwrap_or(wgpu::TextureFormat::Bgra8UnormSrgb);
+        let render = if needs_encoding(surface) {
+            srgb_variant(surface)
+        } else {
+            surface
+        };
+        Self { surface, render }
+    }
+
+    /// Whether frames have to be rendered offscreen and encoded on the way to the surface.
+    pub fn needs_encoding(&self) -> bool {
+        self.surface != self.render
+    }
+}
+
+/// Whether the format stores sRGB colors without encoding them on write, so linear colors written
+/// to it come out too dark.
+pub fn needs_encoding(format: wgpu::TextureFormat) -> bool {
+    matches!(
+        format,
+        wgpu::TextureFormat::Bgra8Unorm
+            | wgpu::TextureFormat::Rgba8Unorm
+            | wgpu::TextureFormat::Rgb10a2Unorm
+    )
+}
+
+/// The encoding counterpart of an 8 bit format, Rgba8UnormSrgb for the others.
+pub fn srgb_variant(format: wgpu::TextureFormat) -> wgpu::TextureFormat {
+    match format {
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => {
+            wgpu::TextureFormat::Bgra8UnormSrgb
+        }
+        _ => wgpu::TextureFormat::Rgba8UnormSrgb,
+    }
+}
+
+/// Parses a surface format name as accepted by `--surface-format`.
+pub fn parse_format(name: &str) -> Result<wgpu::TextureFormat> {
+    let format = match name.to_ascii_lowercase().replace('_', "-").as_str() {
+        "bgra8unorm" => wgpu::TextureFormat::Bgra8Unorm,
+        "bgra8unorm-srgb" => wgpu::TextureFormat::Bgra8UnormSrgb,
+        "rgba8unorm" => wgpu::TextureFormat::Rgba8Unorm,
+        "rgba8unorm-srgb" => wgpu::TextureFormat::Rgba8UnormSrgb,
+        "rgb10a2unorm" => wgpu::TextureFormat::Rgb10a2Unorm,
+        "rgba16float" => wgpu::TextureFormat::Rgba16Float,
+        _ => bail!(
+            "Unknown surface format {:?}, expected one of bgra8unorm, bgra8unorm-srgb, \
+             rgba8unorm, rgba8unorm-srgb, rgb10a2unorm or rgba16float",
+            name
+        ),
+    };
+    Ok(format)
+}
@@ -0,0 +1,293 @@
+//! Vertex attributes beyond the ones every mesh has, kept in a second vertex buffer next to the
+//! `ModelVertex` one so importers can hold on to whatever the file had.
+
+use std::convert::TryFrom;
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+/// Shader location of the first extra attribute, after the ones of `ModelVertex`.
+const FIRST_LOCATION: u32 = 5;
+
+/// Locations left for extra attributes, the ones of the instance buffer following.
+const MAX_LOCATIONS: u32 = crate::instancing::FIRST_LOCATION;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum VertexAttribute {
+    /// A second set of texture coordinates, e.g. for lightmaps.
+    Uv2,
+    /// RGBA, multiplied with the diffuse color when drawn.
+    Color,
+    /// Indices of the four joints moving the vertex, as floats.
+    Joints,
+    /// How much each of the four joints moves the vertex.
+    Weights,
+    /// Anything else, kept for exports but not drawn.
+    Custom { name: String, components: u32 },
+}
+
+impl VertexAttribute {
+    pub fn components(&self) -> u32 {
+        match self {
+            VertexAttribute::Uv2 => 2,
+            VertexAttribute::Color | VertexAttribute::Joints | VertexAttribute::Weights => 4,
+            VertexAttribute::Custom { components, .. } => *components,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            VertexAttribute::Uv2 => "uv2",
+            VertexAttribute::Color => "color",
+            VertexAttribute::Joints => "joints",
+            VertexAttribute::Weights => "weights",
+            VertexAttribute::Custom { name, .. } => name,
+        }
+    }
+
+    /// The value of vertices nothing was read for.
+    fn default_value(&self) -> f32 {
+        match self {
+            VertexAttribute::Color => 1.,
+            _ => 0.,
+        }
+    }
+
+    fn format(&self) -> wgpu::VertexFormat {
+        match self.components() {
+            1 => wgpu::VertexFormat::Float32,
+            2 => wgpu::VertexFormat::Float32x2,
+            3 => wgpu::VertexFormat::Float32x3,
+            _ => wgpu::VertexFormat::Float32x4,
+        }
+    }
+
+    /// The built-in attributes have fixed locations so the shaders reading them work with any
+    /// layout, custom ones follow in order.
+    fn fixed_location(&self) -> Option<u32> {
+        match self {
+            VertexAttribute::Uv2 => Some(FIRST_LOCATION),
+            VertexAttribute::Color => Some(FIRST_LOCATION + 1),
+            VertexAttribute::Joints => Some(FIRST_LOCATION + 2),
+            VertexAttribute::Weights => Some(FIRST_LOCATION + 3),
+            VertexAttribute::Custom { .. } => None,
+        }
+    }
+}
+
+/// Which extra attributes a mesh's vertices have and where they are in its second buffer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<VertexAttribute>", into = "Vec<VertexAttribute>")]
+pub struct VertexLayout {
+    attributes: Vec<VertexAttribute>,
+    wgpu_attributes: Vec<wgpu::VertexAttribute>,
+}
+
+#[derive(Debug, Default)]
+pub struct VertexLayoutBuilder {
+    attributes: Vec<VertexAttribute>,
+}
+
+impl VertexLayoutBuilder {
+    pub fn uv2(mut self) -> Self {
+        self.attributes.push(VertexAttribute::Uv2);
+        self
+    }
+
+    pub fn color(mut self) -> Self {
+        self.attributes.push(VertexAttribute::Color);
+        self
+    }
+
+    /// Joint indices and weights.
+    pub fn skin(mut self) -> Self {
+        self.attributes.push(VertexAttribute::Joints);
+        self.attributes.push(VertexAttribute::Weights);
+        self
+    }
+
+    /// An attribute of one to four floats.
+    pub fn custom(mut self, name: impl Into<String>, components: u32) -> Self {
+        self.attributes.push(VertexAttribute::Custom {
+            name: name.into(),
+            components,
+        });
+        self
+    }
+
+    pub fn attribute(mut self, attribute: VertexAttribute) -> Self {
+        self.attributes.push(attribute);
+        self
+    }
+
+    /// Fails on attributes given twice, custom attributes of no or more than four components,
+    /// or more attributes than a vertex buffer can have.
+    pub fn build(self) -> Result<VertexLayout> {
+        let mut wgpu_attributes = Vec::new();
+        let mut offset = 0;
+        let mut next_custom = FIRST_LOCATION + 4;
+        for (i, attribute) in self.attributes.iter().enumerate() {
+            if self.attributes[..i]
+                .iter()
+                .any(|a| a.name() == attribute.name())
+            {
+                bail!("Vertex attribute {} is given twice", attribute.name());
+            }
+            if !(1..=4).contains(&attribute.components()) {
+                bail!(
+                    "Vertex attribute {} has {} components, not one to four",
+                    attribute.name(),
+                    attribute.components()
+                );
+            }
+            let shader_location = match attribute.fixed_location() {
+                Some(location) => location,
+                None => {
+                    next_custom += 1;
+                    next_custom - 1
+                }
+            };
+            if shader_location >= MAX_LOCATIONS {
+                bail!("Too many custom vertex attributes");
+            }
+            wgpu_attributes.push(wgpu::VertexAttribute {
+                offset,
+                shader_location,
+                format: attribute.format(),
+            });
+            offset += attribute.format().size();
+        }
+        Ok(VertexLayout {
+            attributes: self.attributes,
+            wgpu_attributes,
+        })
+    }
+}
+
+impl TryFrom<Vec<VertexAttribute>> for VertexLayout {
+    type Error = Error;
+
+    fn try_from(attributes: Vec<VertexAttribute>) -> Result<Self> {
+        VertexLayoutBuilder { attributes }.build()
+    }
+}
+
+impl From<VertexLayout> for Vec<VertexAttribute> {
+    fn from(layout: VertexLayout) -> Self {
+        layout.attributes
+    }
+}
+
+impl VertexLayout {
+    pub fn builder() -> VertexLayoutBuilder {
+        VertexLayoutBuilder::default()
+    }
+
+    pub fn attributes(&self) -> &[VertexAttribute] {
+        &self.attributes
+    }
+
+    pub fn has(&self, attribute: &VertexAttribute) -> bool {
+        self.attributes.contains(attribute)
+    }
+
+    /// Floats per vertex.
+    pub fn stride(&self) -> usize {
+        self.attributes
+            .iter()
+            .map(|a| a.components() as usize)
+            .sum()
+    }
+
+    /// Position of the attribute within a vertex, in floats.
+    pub fn offset_of(&self, attribute: &VertexAttribute) -> Option<usize> {
+        let i = self.attributes.iter().position(|a| a == attribute)?;
+        Some(
+            self.attributes[..i]
+                .iter()
+                .map(|a| a.components() as usize)
+                .sum(),
+        )
+    }
+
+    pub fn desc(&self) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: (self.stride() * std::mem::size_of::<f32>()) as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &self.wgpu_attributes,
+        }
+    }
+
+    /// Tells layouts apart, to share a pipeline between meshes of the same one.
+    pub fn key(&self) -> String {
+        self.attributes
+            .iter()
+            .map(|a| format!("{}{}", a.name(), a.components()))
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+}
+
+/// The extra attributes of a mesh's vertices, interleaved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VertexData {
+    pub layout: VertexLayout,
+    values: Vec<f32>,
+}
+
+impl VertexData {
+    /// `count` vertices of default values, white for colors and zero otherwise.
+    pub fn new(layout: VertexLayout, count: usize) -> Self {
+        let vertex = layout
+            .attributes
+            .iter()
+            .flat_map(|a| std::iter::repeat(a.default_value()).take(a.components() as usize))
+            .collect::<Vec<_>>();
+        let values = vertex.repeat(count);
+        Self { layout, values }
+    }
+
+    /// Takes interleaved values, failing if they aren't whole vertices.
+    pub fn from_values(layout: VertexLayout, values: Vec<f32>) -> Result<Self> {
+        let stride = layout.stride();
+        if stride == 0 || values.len() % stride != 0 {
+            bail!("Vertex attribute values don't make whole vertices");
+        }
+        Ok(Self { layout, values })
+    }
+
+    pub fn len(&self) -> usize {
+        match self.layout.stride() {
+            0 => 0,
+            stride => self.values.len() / stride,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    pub fn get(&self, vertex: usize, attribute: &VertexAttribute) -> Option<&[f32]> {
+        let start = vertex * self.layout.stride() + self.layout.offset_of(attribute)?;
+        self.values
+            .get(start..start + attribute.components() as usize)
+    }
+
+    /// Sets the attribute of the vertex from as many values as it has components, missing
+    /// ones keep their value.
+    pub fn set(&mut self, vertex: usize, attribute: &VertexAttribute, values: &[f32]) {
+        if let Some(start) = self.layout.offset_of(attribute) {
+            let start = vertex * self.layout.stride() + start;
+            for (value, new) in self.values[start..start + attribute.components() as usize]
+                .iter_mut()
+                .zip(values)
+            {
+                *value = *new;
+            }
+        }
+    }
+}
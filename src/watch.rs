@@ -0,0 +1,96 @@
+//! `--watch DIR`, loading the models exported into a folder as they appear or change.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use winit::event_loop::EventLoopProxy;
+
+use crate::gui::Event;
+
+/// When a file was last changed and how long it was then.
+type Stamp = (SystemTime, u64);
+
+/// Polls a folder for OBJ and glTF files, as exporters save them. A file is only loaded once it stayed
+/// the same for a whole poll, so it isn't read while still being written.
+pub struct FolderWatch {
+    dir: PathBuf,
+    /// The version of each file that was loaded.
+    loaded: HashMap<PathBuf, Stamp>,
+    /// Changed files, waiting to stay the same.
+    changed: HashMap<PathBuf, Stamp>,
+}
+
+impl FolderWatch {
+    const INTERVAL: Duration = Duration::from_millis(500);
+
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            loaded: HashMap::new(),
+            changed: HashMap::new(),
+        }
+    }
+
+    /// Watches on a thread of its own until the event loop closes. The models in the folder
+    /// already are loaded right away.
+    pub fn spawn(mut self, proxy: EventLoopProxy<Event>) {
+        std::thread::spawn(move || loop {
+            for path in self.poll() {
+                let source = path.to_string_lossy().into_owned();
+                log::info!("Loading {}", source);
+                if proxy.send_event(Event::ReloadModel(source)).is_err() {
+                    return;
+                }
+            }
+            std::thread::sleep(Self::INTERVAL);
+        });
+    }
+
+    fn is_model(path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .map_or(false, |extension| {
+                ["obj", "gltf", "glb"]
+                    .iter()
+                    .any(|model| extension.eq_ignore_ascii_case(model))
+            })
+    }
+
+    /// The files that are ready to be loaded.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::warn!("Can't watch {:?}: {:?}", self.dir, e);
+                return Vec::new();
+            }
+        };
+        let mut ready = Vec::new();
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let metadata = match entry.metadata() {
+                Ok(metadata) if metadata.is_file() && Self::is_model(&path) => metadata,
+                _ => continue,
+            };
+            let stamp = match metadata.modified() {
+                Ok(modified) => (modified, metadata.len()),
+                Err(_) => continue,
+            };
+            if self.loaded.get(&path) == Some(&stamp) {
+                continue;
+            }
+            if self.changed.get(&path) == Some(&stamp) {
+                self.changed.remove(&path);
+                self.loaded.insert(path.clone(), stamp);
+                ready.push(path);
+            } else {
+                self.changed.insert(path, stamp);
+            }
+        }
+        ready.sort();
+        ready
+    }
+}
@@ -0,0 +1,219 @@
+//! Browser specifics of the wasm build.
+
+use std::collections::BTreeSet;
+
+use anyhow::*;
+use js_sys::Reflect;
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use winit::{dpi::LogicalSize, event_loop::EventLoopProxy};
+
+use crate::gui;
+
+// Not in web-sys yet.
+#[wasm_bindgen]
+extern "C" {
+    type ResizeObserver;
+    #[wasm_bindgen(constructor)]
+    fn new(callback: &js_sys::Function) -> ResizeObserver;
+    #[wasm_bindgen(method)]
+    fn observe(this: &ResizeObserver, target: &web_sys::Element);
+    #[wasm_bindgen(method)]
+    fn disconnect(this: &ResizeObserver);
+
+    type ResizeObserverEntry;
+    #[wasm_bindgen(method, getter, js_name = contentRect)]
+    fn content_rect(this: &ResizeObserverEntry) -> web_sys::DomRectReadOnly;
+}
+
+/// Whether the browser exposes WebGPU as `navigator.gpu`.
+pub fn webgpu_available() -> bool {
+    web_sys::window().map_or(false, |window| {
+        js_sys::Reflect::has(&window.navigator(), &"gpu".into()).unwrap_or(false)
+    })
+}
+
+/// Keeps the canvas as large as the element containing it, the page body for the standalone
+/// viewer.
+///
+/// winit follows devicePixelRatio changes by itself but not the page layout, so the parent is
+/// watched with a ResizeObserver and the window resized to it through the event loop.
+pub struct CanvasFit {
+    observer: ResizeObserver,
+    _callback: Closure<dyn FnMut(js_sys::Array)>,
+}
+
+impl CanvasFit {
+    pub fn new(
+        proxy: EventLoopProxy<gui::Event>,
+        canvas: &web_sys::HtmlCanvasElement,
+    ) -> Option<Self> {
+        let parent = canvas.parent_element()?;
+        let callback = Closure::wrap(Box::new(move |entries: js_sys::Array| {
+            let entry: ResizeObserverEntry = entries.get(0).unchecked_into();
+            let rect = entry.content_rect();
+            let size = LogicalSize::new(rect.width(), rect.height());
+            proxy.send_event(gui::Event::CanvasResized(size)).ok();
+        }) as Box<dyn FnMut(js_sys::Array)>);
+        let observer = ResizeObserver::new(callback.as_ref().unchecked_ref());
+        observer.observe(&parent);
+        Some(Self {
+            observer,
+            _callback: callback,
+        })
+    }
+}
+
+impl Drop for CanvasFit {
+    fn drop(&mut self) {
+        self.observer.disconnect();
+    }
+}
+
+/// Functions the embedding page registered for selection changes.
+#[derive(Default)]
+pub struct SelectionListeners {
+    callbacks: Vec<js_sys::Function>,
+    /// The selection they were last called with.
+    selected: BTreeSet<usize>,
+}
+
+impl SelectionListeners {
+    pub fn add(&mut self, callback: js_sys::Function) {
+        self.callbacks.push(callback);
+    }
+
+    /// Calls the listeners with an array of the selected model indices if they changed.
+    pub fn notify(&mut self, selected: &BTreeSet<usize>) {
+        if *selected == self.selected {
+            return;
+        }
+        self.selected = selected.clone();
+        let indices = selected
+            .iter()
+            .map(|&model| JsValue::from(model as u32))
+            .collect::<js_sys::Array>();
+        for callback in &self.callbacks {
+            if let Err(e) = callback.call1(&JsValue::NULL, &indices) {
+                log::warn!("Selection listener failed: {:?}", e);
+            }
+        }
+    }
+}
+
+pub(crate) fn js_error(error: JsValue) -> Error {
+    anyhow!("{:?}", error)
+}
+
+pub enum Fetched {
+    /// The server confirmed the copy with the given ETag is still current.
+    NotModified,
+    Downloaded {
+        data: Vec<u8>,
+        etag: Option<String>,
+    },
+}
+
+/// Downloads `url`, calling `progress` with the bytes received so far and the size the server
+/// announced. The announced size is the compressed one for compressed responses, so the bytes
+/// received can end up larger.
+///
+/// Lets the browser save `bytes` as a file called `name`, through a link to them clicked
+/// right away.
+pub fn download(name: &str, bytes: &[u8], mime: &str) -> Result<()> {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let mut options = web_sys::BlobPropertyBag::new();
+    options.type_(mime);
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+        .map_err(js_error)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(js_error)?;
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .context("No document")?;
+    let link = document
+        .create_element("a")
+        .map_err(js_error)?
+        .dyn_into::<web_sys::HtmlAnchorElement>()
+        .map_err(|_| anyhow!("Not a link"))?;
+    link.set_href(&url);
+    link.set_download(name);
+    // The URL is kept, some browsers cancel the download when it's revoked right away.
+    link.click();
+    Ok(())
+}
+
+/// With an `etag` the download is skipped if the file didn't change since.
+pub async fn fetch_with_progress(
+    url: &str,
+    etag: Option<&str>,
+    progress: &dyn Fn(u64, Option<u64>),
+) -> Result<Fetched> {
+    let window = web_sys::window().context("No window")?;
+    let mut init = web_sys::RequestInit::new();
+    if let Some(etag) = etag {
+        let headers = js_sys::Object::new();
+        Reflect::set(&headers, &"If-None-Match".into(), &etag.into()).map_err(js_error)?;
+        init.headers(&headers);
+    }
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str_and_init(url, &init))
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    if etag.is_some() && response.status() == 304 {
+        return Ok(Fetched::NotModified);
+    }
+    if !response.ok() {
+        bail!("Fetching {} failed with status {}", url, response.status());
+    }
+    let etag = response.headers().get("ETag").ok().flatten();
+    let total = response
+        .headers()
+        .get("Content-Length")
+        .ok()
+        .flatten()
+        .and_then(|length| length.parse().ok());
+    let body = response.body().context("Response without a body")?;
+    // Read chunk by chunk, as the whole body at once tells nothing about the progress.
+    let reader = Reflect::get(&body, &"getReader".into())
+        .and_then(|get_reader| get_reader.unchecked_into::<js_sys::Function>().call0(&body))
+        .map_err(js_error)?;
+    let read: js_sys::Function = Reflect::get(&reader, &"read".into())
+        .map_err(js_error)?
+        .unchecked_into();
+    let mut data = Vec::with_capacity(total.unwrap_or(0) as usize);
+    loop {
+        let chunk = read.call0(&reader).map_err(js_error)?;
+        let chunk = JsFuture::from(js_sys::Promise::from(chunk))
+            .await
+            .map_err(js_error)?;
+        let done = Reflect::get(&chunk, &"done".into()).map_err(js_error)?;
+        if done.as_bool().unwrap_or(true) {
+            break;
+        }
+        let value: js_sys::Uint8Array = Reflect::get(&chunk, &"value".into())
+            .map_err(js_error)?
+            .dyn_into()
+            .map_err(js_error)?;
+        let start = data.len();
+        data.resize(start + value.length() as usize, 0);
+        value.copy_to(&mut data[start..]);
+        progress(data.len() as u64, total);
+    }
+    Ok(Fetched::Downloaded { data, etag })
+}
+
+/// Resolves on the next turn of the page's event loop, letting it handle input and draw.
+pub async fn yield_now() {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let scheduled = web_sys::window().map_or(false, |window| {
+            window
+                .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, 0)
+                .is_ok()
+        });
+        if !scheduled {
+            resolve.call0(&JsValue::NULL).ok();
+        }
+    });
+    JsFuture::from(promise).await.ok();
+}
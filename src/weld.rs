@@ -0,0 +1,117 @@
+use std::{collections::HashMap, ops::Range};
+
+use cgmath::{InnerSpace, Vector3, Zero};
+
+use crate::model::ModelVertex;
+
+/// Connectivity of a mesh with vertices sharing a position, e.g. along UV seams, welded into
+/// one group, so edits move the surface without tearing it.
+#[derive(Debug, Clone)]
+pub struct Welded {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    /// Mesh vertices belonging to each group.
+    pub group_vertices: Vec<Vec<u32>>,
+    /// Groups connected to each group by an edge, sorted.
+    pub neighbours: Vec<Vec<u32>>,
+    /// Triangles around each group as indices into `triangles`.
+    pub group_triangles: Vec<Vec<u32>>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl Welded {
+    pub fn new(vertices: &[ModelVertex], indices: &[u32]) -> Self {
+        let mut welded = HashMap::new();
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut group_vertices: Vec<Vec<u32>> = Vec::new();
+        let vertex_group = vertices
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let key = v.position.map(f32::to_bits);
+                let group = *welded.entry(key).or_insert_with(|| {
+                    positions.push(Vector3::from(v.position));
+                    normals.push(Vector3::from(v.normal));
+                    group_vertices.push(Vec::new());
+                    positions.len() as u32 - 1
+                });
+                group_vertices[group as usize].push(i as u32);
+                group
+            })
+            .collect::<Vec<_>>();
+
+        let triangles = indices
+            .chunks_exact(3)
+            .map(|c| [0, 1, 2].map(|i| vertex_group[c[i] as usize]))
+            .collect::<Vec<_>>();
+        let mut neighbours = vec![Vec::new(); positions.len()];
+        let mut group_triangles = vec![Vec::new(); positions.len()];
+        for (t, triangle) in triangles.iter().enumerate() {
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                group_triangles[a as usize].push(t as u32);
+                if a != b {
+                    neighbours[a as usize].push(b);
+                    neighbours[b as usize].push(a);
+                }
+            }
+        }
+        for n in neighbours.iter_mut() {
+            n.sort_unstable();
+            n.dedup();
+        }
+
+        Self {
+            positions,
+            normals,
+            group_vertices,
+            neighbours,
+            group_triangles,
+            triangles,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Area weighted normal from the triangles around the group.
+    pub fn recompute_normal(&mut self, group: u32) {
+        let normal = self.group_triangles[group as usize]
+            .iter()
+            .map(|&t| {
+                let [a, b, c] = self.triangles[t as usize].map(|i| self.positions[i as usize]);
+                (b - a).cross(c - a)
+            })
+            .fold(Vector3::zero(), |sum, n| sum + n);
+        if normal.magnitude2() > 0. {
+            self.normals[group as usize] = normal.normalize();
+        }
+    }
+
+    /// Copies position and normal of the groups to their vertices, returning the range of
+    /// vertices written. Texture coordinates are left untouched.
+    pub fn write_groups<I: IntoIterator<Item = u32>>(
+        &self,
+        vertices: &mut [ModelVertex],
+        groups: I,
+    ) -> Range<usize> {
+        let mut first = usize::MAX;
+        let mut last = 0;
+        for g in groups {
+            for &v in self.group_vertices[g as usize].iter() {
+                let vertex = &mut vertices[v as usize];
+                vertex.position = self.positions[g as usize].into();
+                vertex.normal = self.normals[g as usize].into();
+                first = first.min(v as usize);
+                last = last.max(v as usize + 1);
+            }
+        }
+        first.min(last)..last
+    }
+}
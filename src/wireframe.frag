@@ -0,0 +1,26 @@
+#version 450
+
+layout(location=0) in vec3 v_world_position;
+
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0)
+uniform Uniforms {
+    vec3 u_view_position;
+    mat4 u_view_proj;
+    vec4 u_section_min;
+    vec4 u_section_max;
+};
+
+layout(set=1, binding=0)
+uniform Wire {
+    vec4 u_color;
+};
+
+void main() {
+    if (u_section_min.w != 0.0 && (any(lessThan(v_world_position, u_section_min.xyz))
+            || any(greaterThan(v_world_position, u_section_max.xyz)))) {
+        discard;
+    }
+    f_color = u_color;
+}
@@ -0,0 +1,283 @@
+//! Wireframe view modes for looking at the topology of the meshes: the edges of the triangles
+//! drawn alone, or over the shaded surfaces. Where the device can rasterize polygons as lines
+//! the meshes are drawn again with their own index buffers in line mode; elsewhere, e.g. on the
+//! web, with an index buffer per mesh listing each edge once, drawn as a line list. Both read
+//! the mesh's vertex buffer, so edges follow sculpting, cloth and shape keys as they happen.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    model::{self, Mesh, Model, Vertex},
+    scene::Scene,
+    shadow::ShadowMap,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WireframeMode {
+    Off,
+    /// The edges alone, the surfaces left out.
+    Wireframe,
+    /// The edges over the shaded surfaces, the hidden ones left out.
+    Shaded,
+}
+
+impl WireframeMode {
+    pub const ALL: [WireframeMode; 3] = [
+        WireframeMode::Off,
+        WireframeMode::Wireframe,
+        WireframeMode::Shaded,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WireframeMode::Off => "Off",
+            WireframeMode::Wireframe => "Wireframe",
+            WireframeMode::Shaded => "Shaded wireframe",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Wireframe {
+    pub mode: WireframeMode,
+    pub color: [f32; 3],
+}
+
+impl Default for Wireframe {
+    fn default() -> Self {
+        Self {
+            mode: WireframeMode::Off,
+            color: [0.1, 0.1, 0.1],
+        }
+    }
+}
+
+/// The edges of a mesh's triangles, each once, as pairs of vertex indices.
+#[derive(Debug)]
+struct EdgeList {
+    buffer: Tracked<wgpu::Buffer>,
+    count: u32,
+}
+
+impl EdgeList {
+    fn new(device: &wgpu::Device, mesh: &Mesh) -> Self {
+        let mut seen = HashSet::new();
+        let mut indices: Vec<u32> = Vec::new();
+        for triangle in mesh.indices.chunks_exact(3) {
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                if seen.insert((a.min(b), a.max(b))) {
+                    indices.extend([a, b].iter());
+                }
+            }
+        }
+        let buffer = device.create_tracked_buffer_init(
+            GpuCategory::Meshes,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Edge Buffer", mesh.name)),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            },
+        );
+        Self {
+            buffer,
+            count: indices.len() as u32,
+        }
+    }
+}
+
+/// Draws the edges of the models after the scene, tested against its depth without writing it.
+#[derive(Debug)]
+pub struct WireframePass {
+    mode: WireframeMode,
+    /// Whether the pipeline draws the triangles in line mode, or edge lists as lines.
+    polygon_lines: bool,
+    pipeline: wgpu::RenderPipeline,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    /// Edge lists of the meshes by the id of their index buffer, without line mode.
+    edges: HashMap<u64, EdgeList>,
+}
+
+impl WireframePass {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        uniforms_bind_group_layout: &wgpu::BindGroupLayout,
+        objects_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let polygon_lines = device.features().contains(wgpu::Features::POLYGON_MODE_LINE);
+        let buffer = device.create_tracked_buffer_init(
+            GpuCategory::Uniforms,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Wireframe Color Buffer"),
+                contents: bytemuck::cast_slice(&[[0f32; 4]]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("wireframe_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("wireframe_bind_group"),
+        });
+
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!("wireframe.vert.spv"));
+        let fs_module = device.create_shader_module(&wgpu::include_spirv!("wireframe.frag.spv"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Wireframe Pipeline Layout"),
+            bind_group_layouts: &[
+                uniforms_bind_group_layout,
+                &bind_group_layout,
+                objects_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let primitive = if polygon_lines {
+            wgpu::PrimitiveState {
+                polygon_mode: wgpu::PolygonMode::Line,
+                ..Default::default()
+            }
+        } else {
+            wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            }
+        };
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Wireframe Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &vs_module,
+                entry_point: "main",
+                buffers: &[model::ModelVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_module,
+                entry_point: "main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive,
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+        });
+
+        Self {
+            mode: WireframeMode::Off,
+            polygon_lines,
+            pipeline,
+            buffer,
+            bind_group,
+            edges: HashMap::new(),
+        }
+    }
+
+    /// Whether the forward pass leaves the surfaces out.
+    pub fn hides_surfaces(&self) -> bool {
+        self.mode == WireframeMode::Wireframe
+    }
+
+    /// Sets the mode and color, and without line mode makes the edge lists of the models'
+    /// meshes missing one, dropping those of meshes gone.
+    pub fn update<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        wireframe: &Wireframe,
+        models: impl Iterator<Item = &'a Model>,
+    ) {
+        self.mode = wireframe.mode;
+        let [r, g, b] = wireframe.color;
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[[r, g, b, 1.]]));
+        if self.polygon_lines || self.mode == WireframeMode::Off {
+            self.edges.clear();
+            return;
+        }
+        let mut used = HashSet::new();
+        for mesh in models.flat_map(|model| model.meshes()) {
+            let id = mesh.index_buffer.id();
+            used.insert(id);
+            self.edges
+                .entry(id)
+                .or_insert_with(|| EdgeList::new(device, mesh));
+        }
+        self.edges.retain(|id, _| used.contains(id));
+    }
+
+    /// Draws the edges of `models` into a pass whose depth holds the scene.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        models: &[(usize, &'a Model)],
+        uniforms: &'a wgpu::BindGroup,
+        objects: &'a ShadowMap,
+    ) {
+        if self.mode == WireframeMode::Off {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, uniforms, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        for &(index, model) in models {
+            for (i, mesh) in model.meshes().iter().enumerate() {
+                let (indices, count) = if self.polygon_lines {
+                    (&mesh.index_buffer, mesh.num_elements)
+                } else {
+                    match self.edges.get(&mesh.index_buffer.id()) {
+                        Some(edges) => (&edges.buffer, edges.count),
+                        None => continue,
+                    }
+                };
+                let offset = objects.object_offset(index, i);
+                render_pass.set_bind_group(2, &objects.bind_group, &[offset]);
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(indices.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..count, 0, 0..1);
+            }
+        }
+    }
+}
+
+impl Scene {
+    /// Hands the wireframe mode to the renderer, with the edge lists of the visible models
+    /// where it needs them. Call before drawing, after the models changed for the frame.
+    pub fn update_wireframe(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let outliner = &self.outliner;
+        let models = self
+            .models
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| outliner.is_visible(*i))
+            .map(|(_, model)| model);
+        self.renderer
+            .wireframe
+            .update(device, queue, &self.wireframe, models);
+    }
+}
@@ -0,0 +1,30 @@
+#version 450
+
+layout(location=0) in vec3 a_position;
+
+layout(location=0) out vec3 v_world_position;
+
+layout(set=0, binding=0)
+uniform Uniforms {
+    vec3 u_view_position;
+    mat4 u_view_proj;
+    // xyz: corners of the section box, w of the minimum: whether it clips
+    vec4 u_section_min;
+    vec4 u_section_max;
+};
+
+// The object flags of the mesh in the shadow map's bind group, see shadow.rs.
+layout(set=2, binding=3)
+uniform Object {
+    vec4 object_flags;
+    vec4 display_color;
+    mat4 model_matrix;
+};
+
+void main() {
+    vec4 world_position = model_matrix * vec4(a_position, 1.0);
+    v_world_position = world_position.xyz;
+    gl_Position = u_view_proj * world_position;
+    // A little towards the eye, so the edges win the depth test against their own faces.
+    gl_Position.z -= 0.0002 * gl_Position.w;
+}
@@ -0,0 +1,31 @@
+#version 450
+
+layout(location=0) in vec3 v_world_position;
+layout(location=1) in vec3 v_normal;
+
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0)
+uniform Uniforms {
+    vec3 u_view_position;
+    mat4 u_view_proj;
+    vec4 u_section_min;
+    vec4 u_section_max;
+};
+
+layout(set=1, binding=0)
+uniform Tint {
+    // rgb: color, a: opacity
+    vec4 u_tint;
+};
+
+void main() {
+    if (u_section_min.w != 0.0 && (any(lessThan(v_world_position, u_section_min.xyz))
+            || any(greaterThan(v_world_position, u_section_max.xyz)))) {
+        discard;
+    }
+    // Surfaces facing the camera brighter than grazing ones, so the hidden shapes read.
+    vec3 view_dir = normalize(u_view_position - v_world_position);
+    float facing = abs(dot(normalize(v_normal), view_dir));
+    f_color = vec4(u_tint.rgb * (0.4 + 0.6 * facing), u_tint.a);
+}
@@ -0,0 +1,194 @@
+use crate::{
+    gpu_memory::{DeviceMemoryExt, GpuCategory, Tracked},
+    model::{self, Model, Vertex},
+    shadow::ShadowMap,
+};
+
+/// Which models show their hidden parts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum XRayMode {
+    Off,
+    Selected,
+    All,
+}
+
+impl XRayMode {
+    pub const ALL: [XRayMode; 3] = [XRayMode::Off, XRayMode::Selected, XRayMode::All];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            XRayMode::Off => "Off",
+            XRayMode::Selected => "Selected",
+            XRayMode::All => "All",
+        }
+    }
+}
+
+/// Shows the parts of models hidden behind others as a translucent tint over the view, to find
+/// and select things behind walls.
+#[derive(Debug, Clone)]
+pub struct XRay {
+    pub mode: XRayMode,
+    pub color: [f32; 3],
+    pub opacity: f32,
+}
+
+impl Default for XRay {
+    fn default() -> Self {
+        Self {
+            mode: XRayMode::Off,
+            color: [1.0, 0.6, 0.1],
+            opacity: 0.35,
+        }
+    }
+}
+
+impl XRay {
+    /// Whether the hidden parts of the model are drawn.
+    pub fn shows(&self, selected: bool) -> bool {
+        match self.mode {
+            XRayMode::Off => false,
+            XRayMode::Selected => selected,
+            XRayMode::All => true,
+        }
+    }
+}
+
+/// Draws the x-rayed models a second time after the scene, only where they are behind what
+/// was drawn already and without writing depth.
+#[derive(Debug)]
+pub struct XRayPass {
+    pipeline: wgpu::RenderPipeline,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    /// Indices of the models drawn, in the order of the scene.
+    models: Vec<usize>,
+}
+
+impl XRayPass {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        uniforms_bind_group_layout: &wgpu::BindGroupLayout,
+        objects_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let buffer = device.create_tracked_buffer_init(
+            GpuCategory::Uniforms,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("X-Ray Tint Buffer"),
+                contents: bytemuck::cast_slice(&[[0f32; 4]]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            },
+        );
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("xray_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("xray_bind_group"),
+        });
+
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!("xray.vert.spv"));
+        let fs_module = device.create_shader_module(&wgpu::include_spirv!("xray.frag.spv"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("X-Ray Pipeline Layout"),
+            bind_group_layouts: &[
+                uniforms_bind_group_layout,
+                &bind_group_layout,
+                objects_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("X-Ray Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &vs_module,
+                entry_point: "main",
+                buffers: &[model::ModelVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_module,
+                entry_point: "main",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            // Back faces too, the insides of hidden rooms are what's looked for.
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Greater,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+        });
+
+        Self {
+            pipeline,
+            buffer,
+            bind_group,
+            models: Vec::new(),
+        }
+    }
+
+    /// Sets the tint and which models are drawn.
+    pub fn update(&mut self, queue: &wgpu::Queue, xray: &XRay, models: Vec<usize>) {
+        let [r, g, b] = xray.color;
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[[r, g, b, xray.opacity]]),
+        );
+        self.models = models;
+    }
+
+    /// Draws the hidden parts of the models among `models` that are x-rayed, into a pass whose
+    /// depth holds the scene.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        models: &[(usize, &'a Model)],
+        uniforms: &'a wgpu::BindGroup,
+        objects: &'a ShadowMap,
+    ) {
+        if self.models.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, uniforms, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        for &(index, model) in models {
+            if self.models.binary_search(&index).is_err() {
+                continue;
+            }
+            for (i, mesh) in model.meshes().iter().enumerate() {
+                let offset = objects.object_offset(index, i);
+                render_pass.set_bind_group(2, &objects.bind_group, &[offset]);
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+            }
+        }
+    }
+}
@@ -0,0 +1,32 @@
+#version 450
+
+layout(location=0) in vec3 a_position;
+layout(location=1) in vec2 a_tex_coords;
+layout(location=2) in vec3 a_normal;
+
+layout(location=0) out vec3 v_world_position;
+layout(location=1) out vec3 v_normal;
+
+layout(set=0, binding=0)
+uniform Uniforms {
+    vec3 u_view_position;
+    mat4 u_view_proj;
+    // xyz: corners of the section box, w of the minimum: whether it clips
+    vec4 u_section_min;
+    vec4 u_section_max;
+};
+
+// The object flags of the mesh in the shadow map's bind group, see shadow.rs.
+layout(set=2, binding=3)
+uniform Object {
+    vec4 object_flags;
+    vec4 display_color;
+    mat4 model_matrix;
+};
+
+void main() {
+    vec4 world_position = model_matrix * vec4(a_position, 1.0);
+    v_world_position = world_position.xyz;
+    v_normal = mat3(transpose(inverse(model_matrix))) * a_normal;
+    gl_Position = u_view_proj * world_position;
+}
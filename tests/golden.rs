@@ -0,0 +1,85 @@
+//! Golden image tests of the renderer: the scenes in `tests/scenes` are rendered offscreen with
+//! their fixed camera and compared with `tests/golden/<scene>.png`, so changes to the renderer
+//! can't break shading, depth testing or culling unnoticed. Pixels may differ by a little between
+//! GPUs and drivers, and a few may differ more along edges.
+//!
+//! Golden images are only recorded with `UPDATE_GOLDEN=1 cargo test --test golden`, for a new
+//! scene or after a change that is meant to alter the images; look at them before committing.
+//! A scene without a golden image fails, writing its render to `target/golden`, and so does a
+//! failing comparison, along with a diff marking the pixels that differ. Without a GPU adapter
+//! the tests fail, as they would check nothing.
+//!
+//! No golden image is recorded yet, so the tests are ignored until they are: record them on a
+//! machine with a GPU with `UPDATE_GOLDEN=1 cargo test --test golden -- --ignored`.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::path::{Path, PathBuf};
+
+use modeling::headless::{self, Headless, SceneDesc};
+
+/// How much a channel may differ before the pixel counts as changed.
+const TOLERANCE: u8 = 8;
+/// How many of the pixels may change, about the outline of a small model.
+const MAX_MISMATCHED_RATIO: f32 = 0.005;
+
+fn root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn save(image: &image::RgbaImage, path: &Path) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    image.save(path).unwrap();
+}
+
+/// Renders `tests/scenes/<name>.ron` and compares it with its golden image.
+fn check(name: &str) {
+    let desc = SceneDesc::load(root().join("tests/scenes").join(format!("{}.ron", name))).unwrap();
+    let headless = futures::executor::block_on(Headless::new())
+        .unwrap_or_else(|e| panic!("{}: can't render without a GPU adapter: {}", name, e));
+    let actual = futures::executor::block_on(headless.render(&desc)).unwrap();
+
+    let golden = root().join("tests/golden").join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        save(&actual, &golden);
+        eprintln!("Recorded {:?}", golden);
+        return;
+    }
+    let out = root().join("target/golden");
+    if !golden.exists() {
+        save(&actual, &out.join(format!("{}.png", name)));
+        panic!(
+            "{}: {:?} is missing, see {:?} and record it with UPDATE_GOLDEN=1",
+            name, golden, out
+        );
+    }
+    let expected = image::open(&golden).unwrap().to_rgba8();
+    let diff = headless::compare_images(&actual, &expected, TOLERANCE).unwrap();
+    if !diff.is_within(MAX_MISMATCHED_RATIO) {
+        save(&actual, &out.join(format!("{}.png", name)));
+        let marked = headless::diff_image(&actual, &expected, TOLERANCE);
+        save(&marked, &out.join(format!("{}_diff.png", name)));
+        panic!(
+            "{}: {} of {} pixels differ from {:?} (by up to {}), see {:?}",
+            name, diff.mismatched, diff.total, golden, diff.max_difference, out
+        );
+    }
+}
+
+#[test]
+#[ignore = "no golden images recorded yet"]
+fn shading() {
+    check("shading");
+}
+
+#[test]
+#[ignore = "no golden images recorded yet"]
+fn depth() {
+    check("depth");
+}
+
+#[test]
+#[ignore = "no golden images recorded yet"]
+fn culling() {
+    check("culling");
+}
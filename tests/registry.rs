@@ -0,0 +1,55 @@
+//! Assets of removed models: the textures of a model's materials are dropped with it when no
+//! other model uses them. It needs a GPU adapter to make the materials and fails without one.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use modeling::{
+    headless::Headless,
+    model::ModelVertex,
+    registry::{MaterialTextures, MeshData, Transform},
+    render_config::RenderConfig,
+    scene::Scene,
+    texture::Texture,
+};
+
+fn vertex(position: [f32; 3]) -> ModelVertex {
+    ModelVertex {
+        position,
+        tex_coords: [0., 0.],
+        normal: [0., 0., 1.],
+        tangent: [1., 0., 0.],
+        bitangent: [0., 1., 0.],
+    }
+}
+
+#[test]
+fn removing_a_model_drops_its_emissive_texture() {
+    let headless = futures::executor::block_on(Headless::new())
+        .unwrap_or_else(|e| panic!("can't make materials without a GPU adapter: {}", e));
+    let (device, queue) = (&headless.device, &headless.queue);
+    let config = Headless::config(64, 64);
+    let mut scene = Scene::with_render_config(device, queue, &config, RenderConfig::default());
+
+    let glow = Texture::one_pixel(device, queue, &[0xff, 0x80, 0, 0xff], Some("glow"), false);
+    let emissive = scene.add_texture("glow", glow);
+    let textures = MaterialTextures {
+        emissive: Some(emissive),
+        ..Default::default()
+    };
+    let material = scene
+        .create_material(device, queue, &config, "glowing", textures)
+        .unwrap();
+    let vertices = vec![
+        vertex([0., 0., 0.]),
+        vertex([1., 0., 0.]),
+        vertex([0., 1., 0.]),
+    ];
+    let mesh = scene.add_mesh("triangle", MeshData::new(vertices, vec![0, 1, 2]).unwrap());
+    let model = scene
+        .spawn(device, mesh, material, Transform::default())
+        .unwrap();
+
+    let purged = scene.remove_model(model);
+    assert!(scene.textures.get(emissive).is_none());
+    assert_eq!(purged.textures, 1);
+}